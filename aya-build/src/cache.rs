@@ -0,0 +1,367 @@
+//! Content-hash based caching of eBPF build outputs.
+//!
+//! A build is identified by the digest of its inputs: the sources of every local package the eBPF
+//! package depends on, the lockfile and the configuration the package is built with. Outputs are
+//! recorded in a [`Manifest`] alongside that digest so that later builds with the same inputs can
+//! reuse them without invoking cargo.
+
+use std::{
+    collections::{BTreeSet, HashSet},
+    fmt, fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::{Context as _, Result, anyhow};
+use cargo_metadata::{CargoOpt, MetadataCommand, PackageId};
+use sha2::{Digest as _, Sha256};
+use walkdir::WalkDir;
+
+/// A SHA-256 digest.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Digest([u8; 32]);
+
+impl Digest {
+    /// Returns the digest of the file at `path`.
+    pub(crate) fn of_file(path: &Path) -> Result<Self> {
+        let data = fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+        Ok(Self(Sha256::digest(data).into()))
+    }
+}
+
+impl AsRef<[u8]> for Digest {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl fmt::Display for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|b| write!(f, "{b:02x}"))
+    }
+}
+
+impl fmt::Debug for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl FromStr for Digest {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut digest = [0; 32];
+        if s.len() != digest.len() * 2 || !s.is_ascii() {
+            return Err(anyhow!("invalid digest {s:?}"));
+        }
+        for (b, chunk) in digest.iter_mut().zip(s.as_bytes().chunks(2)) {
+            let chunk = std::str::from_utf8(chunk)?;
+            *b = u8::from_str_radix(chunk, 16).with_context(|| format!("invalid digest {s:?}"))?;
+        }
+        Ok(Self(digest))
+    }
+}
+
+/// Incrementally computes a [`Digest`] over a sequence of fields.
+///
+/// Fields are length-prefixed so that the boundaries between them are part of the digest.
+pub(crate) struct Hasher(Sha256);
+
+impl Hasher {
+    pub(crate) fn new(domain: &str) -> Self {
+        Self(Sha256::new()).field(domain)
+    }
+
+    pub(crate) fn field(self, data: impl AsRef<[u8]>) -> Self {
+        let Self(mut sha) = self;
+        let data = data.as_ref();
+        sha.update(data.len().to_string());
+        sha.update(b":");
+        sha.update(data);
+        Self(sha)
+    }
+
+    pub(crate) fn finish(self) -> Digest {
+        let Self(sha) = self;
+        Digest(sha.finalize().into())
+    }
+}
+
+/// The local sources a package is built from.
+pub(crate) struct Sources {
+    /// The root of the workspace the package belongs to.
+    pub(crate) workspace_root: PathBuf,
+    /// The files of the package and of its local (path) dependencies.
+    pub(crate) files: Vec<PathBuf>,
+    /// The digest of the contents of `files` and of the workspace lockfile.
+    pub(crate) digest: Digest,
+}
+
+impl Sources {
+    /// Collects the sources of the package `name` whose manifest is in `root_dir`.
+    ///
+    /// Registry and git dependencies are covered by hashing the lockfile, which pins them.
+    pub(crate) fn collect(root_dir: &Path, name: &str) -> Result<Self> {
+        let manifest_path = root_dir.join("Cargo.toml");
+        let metadata = MetadataCommand::new()
+            .manifest_path(&manifest_path)
+            .features(CargoOpt::AllFeatures)
+            .exec()
+            .with_context(|| format!("failed to get metadata for {}", manifest_path.display()))?;
+        let resolve = metadata
+            .resolve
+            .as_ref()
+            .ok_or_else(|| anyhow!("no dependency graph for {}", manifest_path.display()))?;
+        let root = metadata
+            .workspace_packages()
+            .into_iter()
+            .find(|package| package.name == name)
+            .ok_or_else(|| anyhow!("package {name} not found in {}", manifest_path.display()))?;
+
+        let mut seen = HashSet::new();
+        let mut pending = vec![&root.id];
+        let mut package_dirs = BTreeSet::new();
+        while let Some(id) = pending.pop() {
+            if !seen.insert(id) {
+                continue;
+            }
+            let package = &metadata[id];
+            if package.source.is_none()
+                && let Some(dir) = package.manifest_path.parent()
+            {
+                let _: bool = package_dirs.insert(dir.as_std_path().to_path_buf());
+            }
+            if let Some(node) = resolve.nodes.iter().find(|node| &node.id == id) {
+                pending.extend(node.dependencies.iter().map(|id: &PackageId| id));
+            }
+        }
+
+        let workspace_root = metadata.workspace_root.into_std_path_buf();
+        let mut hasher = Hasher::new("aya-build sources v1");
+        let mut files = Vec::new();
+        for dir in &package_dirs {
+            hasher = hash_dir(hasher, &workspace_root, dir, &mut files)?;
+        }
+        let lockfile = workspace_root.join("Cargo.lock");
+        if lockfile.exists() {
+            let data = fs::read(&lockfile)
+                .with_context(|| format!("failed to read {}", lockfile.display()))?;
+            hasher = hasher.field("Cargo.lock").field(data);
+        }
+
+        Ok(Self {
+            workspace_root,
+            files,
+            digest: hasher.finish(),
+        })
+    }
+}
+
+/// Hashes the files of the package in `dir`, appending their paths to `files`.
+///
+/// Build outputs, hidden entries and nested packages are skipped; paths are hashed relative to
+/// `base` so that the digest does not depend on where the sources are checked out.
+fn hash_dir(
+    mut hasher: Hasher,
+    base: &Path,
+    dir: &Path,
+    files: &mut Vec<PathBuf>,
+) -> Result<Hasher> {
+    let walk = WalkDir::new(dir)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|entry| {
+            if entry.depth() == 0 {
+                return true;
+            }
+            let name = entry.file_name().as_encoded_bytes();
+            let is_dir = entry.file_type().is_dir();
+            !(name.starts_with(b".")
+                || is_dir && (name == b"target" || entry.path().join("Cargo.toml").exists()))
+        });
+    for entry in walk {
+        let entry = entry.with_context(|| format!("failed to walk {}", dir.display()))?;
+        if entry.file_type().is_dir() {
+            continue;
+        }
+        let path = entry.path();
+        let relative = path.strip_prefix(base).unwrap_or(path);
+        let data = fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+        hasher = hasher
+            .field(relative.as_os_str().as_encoded_bytes())
+            .field(data);
+        files.push(path.to_path_buf());
+    }
+    Ok(hasher)
+}
+
+/// A record of the objects produced by a build and the inputs they were produced from.
+///
+/// Manifests are stored as text, one record per line:
+///
+/// ```text
+/// inputs <digest>
+/// object <digest> <name>
+/// ```
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Manifest {
+    pub(crate) inputs: Digest,
+    pub(crate) objects: Vec<(String, Digest)>,
+}
+
+impl Manifest {
+    /// Reads the manifest at `path`, returning `None` if it doesn't exist.
+    pub(crate) fn read(path: &Path) -> Result<Option<Self>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        text.parse()
+            .map(Some)
+            .with_context(|| format!("failed to parse {}", path.display()))
+    }
+
+    pub(crate) fn write(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("failed to create {}", parent.display()))?;
+        }
+        fs::write(path, self.to_string())
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    /// Returns true if every object in the manifest exists in `dir` with the recorded digest.
+    pub(crate) fn verify(&self, dir: &Path) -> bool {
+        self.objects.iter().all(|(name, digest)| {
+            Digest::of_file(&dir.join(name)).is_ok_and(|actual| actual == *digest)
+        })
+    }
+}
+
+impl fmt::Display for Manifest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self { inputs, objects } = self;
+        writeln!(f, "inputs {inputs}")?;
+        for (name, digest) in objects {
+            writeln!(f, "object {digest} {name}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Manifest {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut inputs = None;
+        let mut objects = Vec::new();
+        for line in s.lines() {
+            match line.split_once(' ') {
+                Some(("inputs", digest)) => inputs = Some(digest.parse()?),
+                Some(("object", rest)) => {
+                    let (digest, name) = rest
+                        .split_once(' ')
+                        .ok_or_else(|| anyhow!("invalid object record {line:?}"))?;
+                    objects.push((name.to_owned(), digest.parse()?));
+                }
+                _ => return Err(anyhow!("invalid record {line:?}")),
+            }
+        }
+        let inputs = inputs.ok_or_else(|| anyhow!("missing inputs record"))?;
+        Ok(Self { inputs, objects })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn package(dir: &Path) {
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::write(
+            dir.join("Cargo.toml"),
+            "[package]\nname = \"probe\"\nversion = \"0.1.0\"\nedition = \"2024\"\n\n[workspace]\n",
+        )
+        .unwrap();
+        fs::write(dir.join("src/main.rs"), "fn main() {}\n").unwrap();
+    }
+
+    #[test]
+    fn test_sources_digest() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        package(dir);
+
+        let digest = |dir: &Path| Sources::collect(dir, "probe").unwrap().digest;
+        let original = digest(dir);
+        assert_eq!(digest(dir), original);
+        assert_eq!(
+            Sources::collect(dir, "probe").unwrap().files,
+            [
+                dir.join("Cargo.lock"),
+                dir.join("Cargo.toml"),
+                dir.join("src/main.rs")
+            ]
+        );
+
+        // Build outputs and hidden files don't affect the digest.
+        fs::create_dir_all(dir.join("target/release")).unwrap();
+        fs::write(dir.join("target/release/probe"), "binary").unwrap();
+        fs::write(dir.join(".editorconfig"), "root = true\n").unwrap();
+        assert_eq!(digest(dir), original);
+        assert!(
+            !Sources::collect(dir, "probe")
+                .unwrap()
+                .files
+                .iter()
+                .any(|file| file.starts_with(dir.join("target")))
+        );
+
+        // The digest doesn't depend on where the sources live.
+        let other = tempfile::tempdir().unwrap();
+        let other = other.path();
+        package(other);
+        fs::copy(dir.join("Cargo.lock"), other.join("Cargo.lock")).unwrap();
+        assert_eq!(digest(other), original);
+
+        fs::write(dir.join("src/main.rs"), "fn main() { loop {} }\n").unwrap();
+        assert_ne!(digest(dir), original);
+    }
+
+    #[test]
+    fn test_manifest() {
+        let inputs = Hasher::new("inputs").field("a").finish();
+        let manifest = Manifest {
+            inputs,
+            objects: vec![("xdp".into(), Hasher::new("xdp").finish())],
+        };
+        let text = manifest.to_string();
+        assert_eq!(text.lines().count(), 2);
+        assert!(text.starts_with(&format!("inputs {inputs}\n")));
+        assert_eq!(text.parse::<Manifest>().unwrap(), manifest);
+        assert_ne!(inputs, Hasher::new("inputs").field("").field("a").finish());
+
+        let err = "object 00 xdp\n".parse::<Manifest>().unwrap_err();
+        assert_eq!(err.to_string(), "invalid digest \"00\"");
+        let err = "bogus\n".parse::<Manifest>().unwrap_err();
+        assert_eq!(err.to_string(), "invalid record \"bogus\"");
+
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        let path = dir.join("cache/xdp.manifest");
+        assert_eq!(Manifest::read(&path).unwrap(), None);
+        manifest.write(&path).unwrap();
+        let manifest = Manifest::read(&path).unwrap().unwrap();
+        assert!(!manifest.verify(dir));
+        fs::write(dir.join("xdp"), "object").unwrap();
+        let manifest = Manifest {
+            objects: vec![("xdp".into(), Digest::of_file(&dir.join("xdp")).unwrap())],
+            ..manifest
+        };
+        assert!(manifest.verify(dir));
+        fs::write(dir.join("xdp"), "tampered").unwrap();
+        assert!(!manifest.verify(dir));
+    }
+}
@@ -2,24 +2,106 @@ use std::{
     borrow::Cow,
     env,
     ffi::{OsStr, OsString},
-    fs,
+    fmt, fs,
     io::{BufRead as _, BufReader},
-    path::PathBuf,
+    path::{Path, PathBuf},
     process::{Child, Command, Stdio},
 };
 
 pub use anyhow::Result;
 use anyhow::{Context as _, anyhow};
-use cargo_metadata::{Artifact, CompilerMessage, Message, Target};
+use aya_obj::lint::{CpuVersion, LintOptions};
+use cargo_metadata::{CompilerMessage, Message, Target};
 use rustc_version::Channel;
 use which::which;
 
+use crate::{
+    cache::{Digest, Hasher, Manifest, Sources},
+    linker::{BpfLinker, undefined_functions},
+};
+
+mod cache;
+mod linker;
+
 #[derive(Default)]
 pub struct Package<'a> {
     pub name: &'a str,
     pub root_dir: &'a str,
     pub no_default_features: bool,
     pub features: &'a [&'a str],
+    /// A directory of prebuilt objects for the package, usually shipped with the crate.
+    ///
+    /// Objects are stored per target in `<prebuilt_dir>/<target>/<bpf_target_arch>` along with a
+    /// manifest recording the inputs they were built from. When the manifest matches the current
+    /// sources, or when the sources are not available at all, the prebuilt objects are used and
+    /// neither the nightly toolchain nor bpf-linker is needed.
+    ///
+    /// Set `AYA_BUILD_UPDATE_PREBUILT=1` to rebuild the objects and refresh this directory.
+    pub prebuilt_dir: Option<&'a str>,
+}
+
+/// Where an artifact produced by [`build_ebpf`] came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArtifactOrigin {
+    /// The artifact was compiled by this build.
+    Built,
+    /// The artifact was reused from a previous build with identical inputs.
+    Cached,
+    /// The artifact was copied from the package's [`Package::prebuilt_dir`].
+    Prebuilt,
+}
+
+/// An eBPF object produced by [`build_ebpf`].
+#[derive(Clone, Debug)]
+pub struct Artifact {
+    /// The package the artifact belongs to.
+    pub package: String,
+    /// The name of the binary target.
+    pub name: String,
+    /// The location of the object, in `OUT_DIR`.
+    pub path: PathBuf,
+    /// The hex-encoded SHA-256 digest of the object.
+    pub sha256: String,
+    /// Where the artifact came from.
+    pub origin: ArtifactOrigin,
+}
+
+/// The artifacts produced by [`build_ebpf_with_report`] and [`EbpfBuilder::build`].
+///
+/// The report is also written to `OUT_DIR/aya-build/report`.
+#[derive(Clone, Debug, Default)]
+pub struct BuildReport {
+    pub artifacts: Vec<Artifact>,
+}
+
+impl BuildReport {
+    /// Returns the artifacts that were compiled by this build.
+    pub fn rebuilt(&self) -> impl Iterator<Item = &Artifact> {
+        self.artifacts
+            .iter()
+            .filter(|artifact| artifact.origin == ArtifactOrigin::Built)
+    }
+}
+
+impl fmt::Display for BuildReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for Artifact {
+            package,
+            name,
+            path: _,
+            sha256,
+            origin,
+        } in &self.artifacts
+        {
+            let origin = match origin {
+                ArtifactOrigin::Built => "built",
+                ArtifactOrigin::Cached => "cached",
+                ArtifactOrigin::Prebuilt => "prebuilt",
+            };
+            writeln!(f, "{origin:8} {sha256} {package}/{name}")?;
+        }
+        Ok(())
+    }
 }
 
 fn target_arch_fixup(target_arch: Cow<'_, str>) -> Cow<'_, str> {
@@ -30,250 +112,885 @@ fn target_arch_fixup(target_arch: Cow<'_, str>) -> Cow<'_, str> {
     }
 }
 
-/// Build binary artifacts produced by `packages`.
+/// Build binary artifacts produced by `packages` with the default configuration.
 ///
-/// This would be better expressed as one or more [artifact-dependencies][bindeps] but issues such
-/// as:
-///
-/// * <https://github.com/rust-lang/cargo/issues/12374>
-/// * <https://github.com/rust-lang/cargo/issues/12375>
-/// * <https://github.com/rust-lang/cargo/issues/12385>
-///
-/// prevent their use for the time being.
-///
-/// [bindeps]: https://doc.rust-lang.org/nightly/cargo/reference/unstable.html?highlight=feature#artifact-dependencies
-#[expect(clippy::print_stdout, reason = "println! is used for cargo:warning")]
+/// See [`build_ebpf_with_report`] to find out which artifacts were rebuilt.
 pub fn build_ebpf<'a>(
     packages: impl IntoIterator<Item = Package<'a>>,
     toolchain: Toolchain<'a>,
 ) -> Result<()> {
-    const AYA_BUILD_SKIP: &str = "AYA_BUILD_SKIP";
-    println!("cargo:rerun-if-env-changed={AYA_BUILD_SKIP}");
-    if let Some(aya_build_skip) = env::var_os(AYA_BUILD_SKIP)
-        && (aya_build_skip.eq("1") || aya_build_skip.eq_ignore_ascii_case("true"))
-    {
-        println!(
-            "cargo:warning={AYA_BUILD_SKIP}={}; skipping eBPF build",
-            aya_build_skip.display()
-        );
-        return Ok(());
-    }
+    build_ebpf_with_report(packages, toolchain).map(drop)
+}
 
-    const OUT_DIR: &str = "OUT_DIR";
-    let out_dir = env::var_os(OUT_DIR).ok_or_else(|| anyhow!("{OUT_DIR} not set"))?;
-    let out_dir = PathBuf::from(out_dir);
+/// Build binary artifacts produced by `packages` with the default configuration, returning the
+/// artifacts.
+///
+/// This is equivalent to `EbpfBuilder::new().toolchain(toolchain).build(packages)`, see
+/// [`EbpfBuilder::build`].
+pub fn build_ebpf_with_report<'a>(
+    packages: impl IntoIterator<Item = Package<'a>>,
+    toolchain: Toolchain<'a>,
+) -> Result<BuildReport> {
+    EbpfBuilder::new().toolchain(toolchain).build(packages)
+}
 
-    const CARGO_CFG_TARGET_ENDIAN: &str = "CARGO_CFG_TARGET_ENDIAN";
-    let endian = env::var_os(CARGO_CFG_TARGET_ENDIAN)
-        .ok_or_else(|| anyhow!("{CARGO_CFG_TARGET_ENDIAN} not set"))?;
-    let target = if endian == "big" {
-        "bpfeb"
-    } else if endian == "little" {
-        "bpfel"
-    } else {
-        return Err(anyhow!("unsupported endian={}", endian.display()));
-    };
-
-    const TARGET_ARCH: &str = "CARGO_CFG_TARGET_ARCH";
-    let bpf_target_arch =
-        env::var_os(TARGET_ARCH).ok_or_else(|| anyhow!("{TARGET_ARCH} not set"))?;
-    let bpf_target_arch = bpf_target_arch.into_string().map_err(|bpf_target_arch| {
-        anyhow!(
-            "OsString::into_string({TARGET_ARCH}={})",
-            bpf_target_arch.display()
-        )
-    })?;
-    let bpf_target_arch = target_arch_fixup(bpf_target_arch.into());
-    let target = format!("{target}-unknown-none");
-
-    const RUSTUP: &str = "rustup";
-    let rustup = which(RUSTUP);
-    let prefix: &[_] = match rustup.as_ref() {
-        Ok(rustup) => &[
-            rustup.as_os_str(),
-            OsStr::new("run"),
-            OsStr::new(toolchain.as_str()),
-        ],
-        Err(err) => {
-            println!("cargo:warning=which({RUSTUP})={err}; proceeding with current toolchain");
-            &[]
+/// The BPF target to build for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BpfTarget {
+    /// Little endian BPF, i.e. `bpfel-unknown-none`.
+    Bpfel,
+    /// Big endian BPF, i.e. `bpfeb-unknown-none`.
+    Bpfeb,
+}
+
+impl BpfTarget {
+    const fn as_str(self) -> &'static str {
+        match self {
+            Self::Bpfel => "bpfel-unknown-none",
+            Self::Bpfeb => "bpfeb-unknown-none",
         }
-    };
-
-    let cmd = |program| match prefix {
-        [] => Command::new(program),
-        [wrapper, args @ ..] => {
-            let mut cmd = Command::new(wrapper);
-            cmd.args(args).arg(program);
-            cmd
+    }
+}
+
+/// The version of the BPF instruction set to generate code for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BpfCpu {
+    /// The original instruction set.
+    V1,
+    /// Adds the `jlt`, `jle`, `jslt` and `jsle` jumps. Requires kernel 4.14.
+    V2,
+    /// Adds 32-bit jumps and ALU32 code generation. Requires kernel 5.1.
+    V3,
+    /// Adds sign extension, unconditional byte swaps and 32-bit jumps with 32-bit offsets.
+    /// Requires kernel 6.6.
+    V4,
+}
+
+impl BpfCpu {
+    const fn as_str(self) -> &'static str {
+        match self {
+            Self::V1 => "v1",
+            Self::V2 => "v2",
+            Self::V3 => "v3",
+            Self::V4 => "v4",
         }
-    };
-
-    let rustc_version::VersionMeta {
-        semver: _,
-        commit_hash: _,
-        commit_date: _,
-        build_date: _,
-        channel,
-        host: _,
-        short_version_string: _,
-        llvm_version: _,
-    } = rustc_version::VersionMeta::for_command(cmd("rustc"))
-        .context("failed to get rustc version meta")?;
-
-    let rustc_bootstrap = env::var_os("RUSTC_BOOTSTRAP");
-
-    for Package {
-        name,
-        root_dir,
-        no_default_features,
-        features,
-    } in packages
-    {
-        // We have a build-dependency on `name`, so cargo will automatically rebuild us if `name`'s
-        // *library* target or any of its dependencies change. Since we depend on `name`'s *binary*
-        // targets, that only gets us half of the way. This stanza ensures cargo will rebuild us on
-        // changes to the binaries too, which gets us the rest of the way.
-        println!("cargo:rerun-if-changed={root_dir}");
-
-        let mut cmd = cmd("cargo");
-        cmd.args([
-            "build",
-            "--package",
-            name,
-            "--bins",
-            "--message-format=json",
-            "--release",
-            "--target",
-            &target,
-        ]);
-
-        // RUSTC_BOOTSTRAP controls whether unstable features (-Z flags) are available:
-        //   "1"           – enable for all crates
-        //   "<crate>"     – enable only for the named crate
-        //   "-1"          – force stable behavior even on nightly (explicit opt-out)
-        //   (unset/other) – follow the actual compiler channel
-        // See: https://doc.rust-lang.org/beta/unstable-book/compiler-environment-variables/RUSTC_BOOTSTRAP.html
-        let use_build_std = match rustc_bootstrap.as_ref() {
-            Some(rustc_bootstrap) => {
-                if rustc_bootstrap == "1" || rustc_bootstrap == name {
-                    true
-                } else if rustc_bootstrap == "-1" {
-                    false
-                } else {
-                    channel == Channel::Nightly
-                }
-            }
-            None => channel == Channel::Nightly,
-        };
+    }
 
-        if use_build_std {
-            cmd.args(["-Z", "build-std=core"]);
+    const fn version(self) -> CpuVersion {
+        match self {
+            Self::V1 => CpuVersion::V1,
+            Self::V2 => CpuVersion::V2,
+            Self::V3 => CpuVersion::V3,
+            Self::V4 => CpuVersion::V4,
         }
+    }
+}
 
-        if no_default_features {
-            cmd.arg("--no-default-features");
+/// What to do with the problems found by the static lint pass, see [`aya_obj::Object::lint`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LintLevel {
+    /// Don't lint the objects.
+    #[default]
+    Allow,
+    /// Report the problems as cargo warnings.
+    Warn,
+    /// Fail the build if any problem is found.
+    Deny,
+}
+
+/// Builder for eBPF builds.
+///
+/// # Examples
+///
+/// ```no_run
+/// use aya_build::{BpfCpu, EbpfBuilder, Package};
+///
+/// let report = EbpfBuilder::new()
+///     .cpu(BpfCpu::V3)
+///     .codegen_option("opt-level=3")
+///     .min_bpf_linker_version("0.9.14")
+///     .build([Package {
+///         name: "myapp-ebpf",
+///         root_dir: "../myapp-ebpf",
+///         ..Default::default()
+///     }])?;
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub struct EbpfBuilder<'a> {
+    toolchain: Toolchain<'a>,
+    target: Option<BpfTarget>,
+    cpu: Option<BpfCpu>,
+    btf: bool,
+    debug_info: bool,
+    codegen_options: Vec<&'a str>,
+    allowed_unsupported_helpers: Vec<&'a str>,
+    bpf_linker: Option<&'a str>,
+    min_bpf_linker_version: Option<&'a str>,
+    rustc_bootstrap: bool,
+    lints: LintLevel,
+}
+
+impl Default for EbpfBuilder<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> EbpfBuilder<'a> {
+    /// Creates a new builder with the default configuration.
+    ///
+    /// By default objects are built with debug info and BTF, for the endianness of the target
+    /// the build script runs for, with bpf-linker found in `PATH`.
+    pub const fn new() -> Self {
+        Self {
+            toolchain: Toolchain::Nightly,
+            target: None,
+            cpu: None,
+            btf: true,
+            debug_info: true,
+            codegen_options: Vec::new(),
+            allowed_unsupported_helpers: Vec::new(),
+            bpf_linker: None,
+            min_bpf_linker_version: None,
+            rustc_bootstrap: false,
+            lints: LintLevel::Allow,
         }
+    }
 
-        cmd.args(["--features", &features.join(",")]);
+    /// Sets the toolchain used to build the eBPF packages.
+    pub const fn toolchain(&mut self, toolchain: Toolchain<'a>) -> &mut Self {
+        self.toolchain = toolchain;
+        self
+    }
 
-        {
-            const SEPARATOR: &str = "\x1f";
+    /// Sets the BPF target.
+    ///
+    /// Defaults to the target matching the endianness of the target the build script runs for.
+    pub const fn target(&mut self, target: BpfTarget) -> &mut Self {
+        self.target = Some(target);
+        self
+    }
 
-            let mut rustflags = OsString::new();
+    /// Sets the version of the BPF instruction set to generate.
+    ///
+    /// Defaults to the version picked by bpf-linker.
+    pub const fn cpu(&mut self, cpu: BpfCpu) -> &mut Self {
+        self.cpu = Some(cpu);
+        self
+    }
 
-            for s in [
-                "--cfg=bpf_target_arch=\"",
-                &bpf_target_arch,
-                "\"",
-                SEPARATOR,
-                "-Cdebuginfo=2",
-                SEPARATOR,
-                "-Clink-arg=--btf",
-            ] {
-                rustflags.push(s);
+    /// Sets whether BTF is emitted for the objects. Enabled by default.
+    ///
+    /// BTF is generated from debug info, see [`EbpfBuilder::debug_info`].
+    pub const fn btf(&mut self, btf: bool) -> &mut Self {
+        self.btf = btf;
+        self
+    }
+
+    /// Sets whether debug info is emitted for the objects. Enabled by default.
+    pub const fn debug_info(&mut self, debug_info: bool) -> &mut Self {
+        self.debug_info = debug_info;
+        self
+    }
+
+    /// Adds a codegen option, passed to rustc as `-C<option>`, e.g. `opt-level=2`.
+    pub fn codegen_option(&mut self, option: &'a str) -> &mut Self {
+        self.codegen_options.push(option);
+        self
+    }
+
+    /// Allows the objects to call the undefined function `name`.
+    ///
+    /// Calls to functions that are neither defined in the object nor BPF helpers, such as
+    /// `memcpy` or the helpers of 128-bit arithmetic, can't be resolved by the kernel and are
+    /// rejected by default. Functions that are resolved at load time, such as kfuncs, must be
+    /// allowed explicitly.
+    pub fn allow_unsupported_helper(&mut self, name: &'a str) -> &mut Self {
+        self.allowed_unsupported_helpers.push(name);
+        self
+    }
+
+    /// Sets the path of bpf-linker. Defaults to the `bpf-linker` found in `PATH`.
+    pub const fn bpf_linker(&mut self, path: &'a str) -> &mut Self {
+        self.bpf_linker = Some(path);
+        self
+    }
+
+    /// Sets the minimum version of bpf-linker required by the build, e.g. `0.9.14`.
+    pub const fn min_bpf_linker_version(&mut self, version: &'a str) -> &mut Self {
+        self.min_bpf_linker_version = Some(version);
+        self
+    }
+
+    /// Enables unstable features for the eBPF build by setting `RUSTC_BOOTSTRAP=1`.
+    ///
+    /// This allows building `core` for the BPF targets with a stable toolchain.
+    pub const fn rustc_bootstrap(&mut self, rustc_bootstrap: bool) -> &mut Self {
+        self.rustc_bootstrap = rustc_bootstrap;
+        self
+    }
+
+    /// Sets what to do with the problems found by linting the objects. Defaults to
+    /// [`LintLevel::Allow`].
+    ///
+    /// The lint pass catches programs the verifier would predictably reject, such as helpers that
+    /// aren't available to the program type, oversized stacks or instructions the selected
+    /// [`BpfCpu`] doesn't support, without loading them into a kernel.
+    pub const fn lints(&mut self, lints: LintLevel) -> &mut Self {
+        self.lints = lints;
+        self
+    }
+
+    /// Returns the flags passed to rustc for the configuration.
+    fn rustflags(&self) -> Result<Vec<String>> {
+        let Self {
+            toolchain: _,
+            target: _,
+            cpu,
+            btf,
+            debug_info,
+            codegen_options,
+            allowed_unsupported_helpers: _,
+            bpf_linker: _,
+            min_bpf_linker_version: _,
+            rustc_bootstrap: _,
+            lints: _,
+        } = self;
+        let mut rustflags = Vec::new();
+        if *debug_info {
+            rustflags.push("-Cdebuginfo=2".to_owned());
+        }
+        if *btf {
+            if !debug_info {
+                return Err(anyhow!(
+                    "BTF is generated from debug info; enable `EbpfBuilder::debug_info` or \
+                     disable `EbpfBuilder::btf`"
+                ));
             }
+            rustflags.push("-Clink-arg=--btf".to_owned());
+        }
+        if let Some(cpu) = cpu {
+            let cpu = cpu.as_str();
+            rustflags.push(format!("-Ctarget-cpu={cpu}"));
+            rustflags.push(format!("-Clink-arg=--cpu={cpu}"));
+        }
+        for option in codegen_options {
+            rustflags.push(format!("-C{option}"));
+        }
+        Ok(rustflags)
+    }
 
-            cmd.env("CARGO_ENCODED_RUSTFLAGS", rustflags);
+    /// Build binary artifacts produced by `packages`.
+    ///
+    /// This would be better expressed as one or more [artifact-dependencies][bindeps] but issues
+    /// such as:
+    ///
+    /// * <https://github.com/rust-lang/cargo/issues/12374>
+    /// * <https://github.com/rust-lang/cargo/issues/12375>
+    /// * <https://github.com/rust-lang/cargo/issues/12385>
+    ///
+    /// prevent their use for the time being.
+    ///
+    /// Builds are cached by content: the objects of each package are recorded together with a
+    /// digest of its local sources, its lockfile and the build configuration, and cargo is only
+    /// invoked when that digest changes. Packages with a [`Package::prebuilt_dir`] can be built
+    /// without the eBPF toolchain. The returned [`BuildReport`] tells which artifacts were
+    /// rebuilt.
+    ///
+    /// [bindeps]: https://doc.rust-lang.org/nightly/cargo/reference/unstable.html?highlight=feature#artifact-dependencies
+    #[expect(clippy::print_stdout, reason = "println! is used for cargo:warning")]
+    pub fn build(&self, packages: impl IntoIterator<Item = Package<'a>>) -> Result<BuildReport> {
+        const AYA_BUILD_SKIP: &str = "AYA_BUILD_SKIP";
+        println!("cargo:rerun-if-env-changed={AYA_BUILD_SKIP}");
+        if let Some(aya_build_skip) = env::var_os(AYA_BUILD_SKIP)
+            && (aya_build_skip.eq("1") || aya_build_skip.eq_ignore_ascii_case("true"))
+        {
+            println!(
+                "cargo:warning={AYA_BUILD_SKIP}={}; skipping eBPF build",
+                aya_build_skip.display()
+            );
+            return Ok(BuildReport::default());
         }
 
-        // Workaround to make sure that the correct toolchain is used.
-        for key in ["RUSTC", "RUSTC_WORKSPACE_WRAPPER"] {
-            cmd.env_remove(key);
+        const AYA_BUILD_UPDATE_PREBUILT: &str = "AYA_BUILD_UPDATE_PREBUILT";
+        println!("cargo:rerun-if-env-changed={AYA_BUILD_UPDATE_PREBUILT}");
+        let update_prebuilt =
+            env::var_os(AYA_BUILD_UPDATE_PREBUILT).is_some_and(|update_prebuilt| {
+                update_prebuilt.eq("1") || update_prebuilt.eq_ignore_ascii_case("true")
+            });
+
+        let Self {
+            toolchain,
+            target,
+            cpu,
+            btf: _,
+            debug_info: _,
+            codegen_options: _,
+            allowed_unsupported_helpers,
+            bpf_linker,
+            min_bpf_linker_version,
+            rustc_bootstrap: force_rustc_bootstrap,
+            lints,
+        } = self;
+        let rustflags = self.rustflags()?;
+        let mut lint_options = LintOptions::default();
+        if let Some(cpu) = cpu {
+            lint_options.cpu = cpu.version();
         }
 
-        // Workaround for https://github.com/rust-lang/cargo/issues/6412 where cargo flocks itself.
-        //
-        // Keep the cargo `--target-dir` separate from `OUT_DIR`'s output artifacts. Otherwise, if
-        // the package name matches a bin target name, `target_dir` would collide with the file we
-        // later copy to `OUT_DIR/<bin-name>`, causing `fs::copy` to fail with EISDIR.
-        let target_dir = out_dir.join("aya-build").join("target").join(name);
-        cmd.arg("--target-dir").arg(&target_dir);
-
-        let mut child = cmd
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()
-            .with_context(|| format!("failed to spawn {cmd:?}"))?;
-        let Child { stdout, stderr, .. } = &mut child;
-
-        // Trampoline stdout to cargo warnings.
-        let stderr = stderr.take().expect("stderr");
-        let stderr = BufReader::new(stderr);
-        let stderr = std::thread::spawn(move || {
-            for line in stderr.lines() {
-                let line = line.expect("read line");
-                println!("cargo:warning={line}");
+        const OUT_DIR: &str = "OUT_DIR";
+        let out_dir = env::var_os(OUT_DIR).ok_or_else(|| anyhow!("{OUT_DIR} not set"))?;
+        let out_dir = PathBuf::from(out_dir);
+
+        let target = if let Some(target) = target {
+            *target
+        } else {
+            const CARGO_CFG_TARGET_ENDIAN: &str = "CARGO_CFG_TARGET_ENDIAN";
+            let endian = env::var_os(CARGO_CFG_TARGET_ENDIAN)
+                .ok_or_else(|| anyhow!("{CARGO_CFG_TARGET_ENDIAN} not set"))?;
+            if endian == "big" {
+                BpfTarget::Bpfeb
+            } else if endian == "little" {
+                BpfTarget::Bpfel
+            } else {
+                return Err(anyhow!("unsupported endian={}", endian.display()));
             }
-        });
+        };
+        let target = target.as_str();
+
+        const TARGET_ARCH: &str = "CARGO_CFG_TARGET_ARCH";
+        let bpf_target_arch =
+            env::var_os(TARGET_ARCH).ok_or_else(|| anyhow!("{TARGET_ARCH} not set"))?;
+        let bpf_target_arch = bpf_target_arch.into_string().map_err(|bpf_target_arch| {
+            anyhow!(
+                "OsString::into_string({TARGET_ARCH}={})",
+                bpf_target_arch.display()
+            )
+        })?;
+        let bpf_target_arch = target_arch_fixup(bpf_target_arch.into());
+
+        const RUSTUP: &str = "rustup";
+        let rustup = which(RUSTUP);
+        let prefix: &[_] = match rustup.as_ref() {
+            Ok(rustup) => &[
+                rustup.as_os_str(),
+                OsStr::new("run"),
+                OsStr::new(toolchain.as_str()),
+            ],
+            Err(err) => {
+                println!("cargo:warning=which({RUSTUP})={err}; proceeding with current toolchain");
+                &[]
+            }
+        };
+
+        let cmd = |program| match prefix {
+            [] => Command::new(program),
+            [wrapper, args @ ..] => {
+                let mut cmd = Command::new(wrapper);
+                cmd.args(args).arg(program);
+                cmd
+            }
+        };
+
+        // The toolchain is only queried once a package actually needs to be built, so that
+        // packages served from prebuilt objects don't require it to be installed.
+        let mut rustc = None;
+        let mut linker = None;
+
+        let rustc_bootstrap = if *force_rustc_bootstrap {
+            Some(OsString::from("1"))
+        } else {
+            env::var_os("RUSTC_BOOTSTRAP")
+        };
+
+        const PREBUILT_MANIFEST: &str = "aya-build.manifest";
 
-        let stdout = stdout.take().expect("stdout");
-        let stdout = BufReader::new(stdout);
-        let mut executables = Vec::new();
-        for message in Message::parse_stream(stdout) {
-            #[expect(clippy::collapsible_match, reason = "better captures intent")]
-            match message.with_context(|| anyhow!("cargo stdout stream contains invalid JSON"))? {
-                Message::CompilerArtifact(Artifact {
-                    executable,
-                    target: Target { name, .. },
-                    ..
-                }) => {
-                    if let Some(executable) = executable {
-                        executables.push((name, executable.into_std_path_buf()));
+        let mut report = BuildReport::default();
+
+        for Package {
+            name,
+            root_dir,
+            no_default_features,
+            features,
+            prebuilt_dir,
+        } in packages
+        {
+            let root_dir = Path::new(root_dir);
+
+            let prebuilt_dir = prebuilt_dir.map(|prebuilt_dir| {
+                let prebuilt_dir = Path::new(prebuilt_dir).join(target).join(&*bpf_target_arch);
+                println!("cargo:rerun-if-changed={}", prebuilt_dir.display());
+                prebuilt_dir
+            });
+
+            // Crates shipping prebuilt objects don't necessarily ship the sources they were built
+            // from.
+            let sources = (prebuilt_dir.is_none() || root_dir.join("Cargo.toml").exists())
+                .then(|| Sources::collect(root_dir, name))
+                .transpose()?;
+            let inputs = sources.as_ref().map(
+                |Sources {
+                     workspace_root,
+                     files,
+                     digest,
+                 }| {
+                    // We have a build-dependency on `name`, so cargo will automatically rebuild
+                    // us if `name`'s *library* target or any of its dependencies change. Since we
+                    // depend on `name`'s *binary* targets, that only gets us half of the way. This
+                    // stanza ensures cargo will rebuild us on changes to the binaries too, which
+                    // gets us the rest of the way. Files are listed individually because cargo
+                    // scans directories recursively, which would include build outputs.
+                    println!(
+                        "cargo:rerun-if-changed={}",
+                        workspace_root.join("Cargo.lock").display()
+                    );
+                    for file in files {
+                        println!("cargo:rerun-if-changed={}", file.display());
+                    }
+
+                    let hasher = Hasher::new("aya-build inputs v1")
+                        .field(digest)
+                        .field(name)
+                        .field(target)
+                        .field(&*bpf_target_arch)
+                        .field(no_default_features.to_string())
+                        .field(features.len().to_string());
+                    let hasher = features.iter().fold(hasher, Hasher::field);
+                    let hasher = hasher.field(rustflags.len().to_string());
+                    let hasher = rustflags.iter().fold(hasher, Hasher::field);
+                    allowed_unsupported_helpers
+                        .iter()
+                        .fold(hasher, Hasher::field)
+                        .finish()
+                },
+            );
+
+            if let Some(prebuilt_dir) = prebuilt_dir.as_ref()
+                && !update_prebuilt
+            {
+                let manifest = Manifest::read(&prebuilt_dir.join(PREBUILT_MANIFEST))?;
+                match manifest {
+                    Some(manifest) if inputs.is_none_or(|inputs| inputs == manifest.inputs) => {
+                        if !manifest.verify(prebuilt_dir) {
+                            return Err(anyhow!(
+                                "prebuilt objects in {} don't match their manifest",
+                                prebuilt_dir.display()
+                            ));
+                        }
+                        copy_objects(
+                            prebuilt_dir,
+                            &out_dir,
+                            &manifest.objects,
+                            name,
+                            ArtifactOrigin::Prebuilt,
+                            &mut report,
+                        )?;
+                        lint_objects(&out_dir, &manifest.objects, &lint_options, *lints)?;
+                        continue;
+                    }
+                    Some(_) => {
+                        println!(
+                            "cargo:warning=prebuilt objects in {} are out of date; rebuilding \
+                             {name}",
+                            prebuilt_dir.display()
+                        );
                     }
+                    None => {}
                 }
-                Message::CompilerMessage(CompilerMessage { message, .. }) => {
-                    for line in message.rendered.unwrap_or_default().split('\n') {
-                        println!("cargo:warning={line}");
+            }
+
+            let (Some(sources), Some(inputs)) = (sources, inputs) else {
+                return Err(anyhow!(
+                    "{} not found and no prebuilt objects available for {name}",
+                    root_dir.join("Cargo.toml").display()
+                ));
+            };
+
+            let (rustc_version, channel) = if let Some(rustc) = &rustc {
+                rustc
+            } else {
+                let rustc_version::VersionMeta {
+                    semver: _,
+                    commit_hash: _,
+                    commit_date: _,
+                    build_date: _,
+                    channel,
+                    host: _,
+                    short_version_string,
+                    llvm_version: _,
+                } = rustc_version::VersionMeta::for_command(cmd("rustc"))
+                    .context("failed to get rustc version meta")?;
+                rustc.insert((short_version_string, channel))
+            };
+            let BpfLinker {
+                path: linker_path,
+                version: linker_version,
+            } = if let Some(linker) = &linker {
+                linker
+            } else {
+                linker.insert(BpfLinker::find(*bpf_linker, *min_bpf_linker_version)?)
+            };
+
+            // RUSTC_BOOTSTRAP controls whether unstable features (-Z flags) are available:
+            //   "1"           – enable for all crates
+            //   "<crate>"     – enable only for the named crate
+            //   "-1"          – force stable behavior even on nightly (explicit opt-out)
+            //   (unset/other) – follow the actual compiler channel
+            // See: https://doc.rust-lang.org/beta/unstable-book/compiler-environment-variables/RUSTC_BOOTSTRAP.html
+            let use_build_std = match rustc_bootstrap.as_ref() {
+                Some(rustc_bootstrap) => {
+                    if rustc_bootstrap == "1" || rustc_bootstrap == name {
+                        true
+                    } else if rustc_bootstrap == "-1" {
+                        false
+                    } else {
+                        *channel == Channel::Nightly
                     }
                 }
-                Message::TextLine(line) => {
-                    println!("cargo:warning={line}");
+                None => *channel == Channel::Nightly,
+            };
+
+            // Unlike `inputs`, the cache key depends on the toolchain: objects built by one
+            // compiler or linker are not reused when another one is selected.
+            let key = Hasher::new("aya-build cache v1")
+                .field(inputs)
+                .field(toolchain.as_str())
+                .field(rustc_version)
+                .field(linker_version.to_string())
+                .field(use_build_std.to_string())
+                .finish();
+
+            let cache_manifest = out_dir
+                .join("aya-build")
+                .join("cache")
+                .join(format!("{name}.manifest"));
+            let objects = match Manifest::read(&cache_manifest)? {
+                Some(manifest) if manifest.inputs == key && manifest.verify(&out_dir) => {
+                    copy_objects(
+                        &out_dir,
+                        &out_dir,
+                        &manifest.objects,
+                        name,
+                        ArtifactOrigin::Cached,
+                        &mut report,
+                    )?;
+                    manifest.objects
+                }
+                _ => {
+                    let mut cmd = cmd("cargo");
+                    if *force_rustc_bootstrap {
+                        cmd.env("RUSTC_BOOTSTRAP", "1");
+                    }
+                    let objects = cargo_build(
+                        cmd,
+                        name,
+                        target,
+                        &bpf_target_arch,
+                        use_build_std,
+                        no_default_features,
+                        features,
+                        &rustflags,
+                        linker_path,
+                        &sources,
+                        &out_dir,
+                    )?;
+                    for (object, _digest) in &objects {
+                        let path = out_dir.join(object);
+                        let unsupported = undefined_functions(&path)?
+                            .into_iter()
+                            .filter(|name| !allowed_unsupported_helpers.contains(&name.as_str()))
+                            .collect::<Vec<_>>();
+                        if !unsupported.is_empty() {
+                            return Err(anyhow!(
+                                "{} calls undefined functions the kernel can't resolve: {}; \
+                                 avoid the code that requires them or, if they are resolved at \
+                                 load time, allow them with \
+                                 `EbpfBuilder::allow_unsupported_helper`",
+                                path.display(),
+                                unsupported.join(", ")
+                            ));
+                        }
+                    }
+                    for (object, digest) in &objects {
+                        report.artifacts.push(Artifact {
+                            package: name.to_owned(),
+                            name: object.clone(),
+                            path: out_dir.join(object),
+                            sha256: digest.to_string(),
+                            origin: ArtifactOrigin::Built,
+                        });
+                    }
+                    let manifest = Manifest {
+                        inputs: key,
+                        objects,
+                    };
+                    manifest.write(&cache_manifest)?;
+                    manifest.objects
+                }
+            };
+            lint_objects(&out_dir, &objects, &lint_options, *lints)?;
+
+            if let Some(prebuilt_dir) = prebuilt_dir
+                && update_prebuilt
+            {
+                fs::create_dir_all(&prebuilt_dir)
+                    .with_context(|| format!("failed to create {}", prebuilt_dir.display()))?;
+                for (object, _digest) in &objects {
+                    let src = out_dir.join(object);
+                    let dst = prebuilt_dir.join(object);
+                    let _: u64 = fs::copy(&src, &dst).with_context(|| {
+                        format!("failed to copy {} to {}", src.display(), dst.display())
+                    })?;
                 }
-                _ => {}
+                Manifest { inputs, objects }.write(&prebuilt_dir.join(PREBUILT_MANIFEST))?;
             }
         }
 
-        let status = child
-            .wait()
-            .with_context(|| format!("failed to wait for {cmd:?}"))?;
-        if !status.success() {
-            return Err(anyhow!("{cmd:?} failed: {status:?}"));
+        let report_path = out_dir.join("aya-build").join("report");
+        fs::create_dir_all(out_dir.join("aya-build"))
+            .and_then(|()| fs::write(&report_path, report.to_string()))
+            .with_context(|| format!("failed to write {}", report_path.display()))?;
+
+        Ok(report)
+    }
+}
+
+/// Copies `objects` from `src_dir` to `dst_dir`, recording them in `report`.
+fn copy_objects(
+    src_dir: &Path,
+    dst_dir: &Path,
+    objects: &[(String, Digest)],
+    package: &str,
+    origin: ArtifactOrigin,
+    report: &mut BuildReport,
+) -> Result<()> {
+    for (object, digest) in objects {
+        let src = src_dir.join(object);
+        let dst = dst_dir.join(object);
+        if src != dst {
+            let _: u64 = fs::copy(&src, &dst).with_context(|| {
+                format!("failed to copy {} to {}", src.display(), dst.display())
+            })?;
         }
+        report.artifacts.push(Artifact {
+            package: package.to_owned(),
+            name: object.clone(),
+            path: dst,
+            sha256: digest.to_string(),
+            origin,
+        });
+    }
+    Ok(())
+}
 
-        match stderr.join().map_err(std::panic::resume_unwind) {
-            Ok(()) => {}
-            Err(err) => match err {},
+/// Lints `objects` in `dir`, reporting the problems according to `level`.
+#[expect(clippy::print_stdout, reason = "println! is used for cargo:warning")]
+fn lint_objects(
+    dir: &Path,
+    objects: &[(String, Digest)],
+    options: &LintOptions,
+    level: LintLevel,
+) -> Result<()> {
+    if level == LintLevel::Allow {
+        return Ok(());
+    }
+    let mut count = 0;
+    for (object, _digest) in objects {
+        let path = dir.join(object);
+        let data = fs::read(&path).with_context(|| format!("failed to read {}", path.display()))?;
+        let diagnostics = aya_obj::Object::parse(&data)
+            .with_context(|| format!("failed to parse {}", path.display()))?
+            .lint(options)
+            .with_context(|| format!("failed to lint {}", path.display()))?;
+        for diagnostic in &diagnostics {
+            // Diagnostics span several lines, and each warning must fit on one.
+            let diagnostic = diagnostic.to_string();
+            let mut lines = diagnostic.lines();
+            if let Some(line) = lines.next() {
+                println!("cargo:warning={}: {line}", path.display());
+            }
+            for line in lines {
+                println!("cargo:warning={line}");
+            }
         }
+        count += diagnostics.len();
+    }
+    if level == LintLevel::Deny && count != 0 {
+        return Err(anyhow!(
+            "lint found {count} problems in the eBPF objects; fix them or lower the level with \
+             `EbpfBuilder::lints`"
+        ));
+    }
+    Ok(())
+}
+
+/// Runs `cargo build` for the binaries of `name` and copies them to `out_dir`.
+///
+/// Returns the names and digests of the copied objects.
+#[expect(clippy::print_stdout, reason = "println! is used for cargo:warning")]
+#[expect(
+    clippy::too_many_arguments,
+    reason = "these are all the build parameters"
+)]
+fn cargo_build(
+    mut cmd: Command,
+    name: &str,
+    target: &str,
+    bpf_target_arch: &str,
+    use_build_std: bool,
+    no_default_features: bool,
+    features: &[&str],
+    rustflags: &[String],
+    linker: &Path,
+    sources: &Sources,
+    out_dir: &Path,
+) -> Result<Vec<(String, Digest)>> {
+    cmd.args([
+        "build",
+        "--package",
+        name,
+        "--bins",
+        "--message-format=json",
+        "--release",
+        "--target",
+        target,
+    ]);
+
+    if use_build_std {
+        cmd.args(["-Z", "build-std=core"]);
+    }
+
+    if no_default_features {
+        cmd.arg("--no-default-features");
+    }
+
+    cmd.args(["--features", &features.join(",")]);
+
+    {
+        const SEPARATOR: &str = "\x1f";
+
+        let mut encoded = OsString::new();
+
+        for s in ["--cfg=bpf_target_arch=\"", bpf_target_arch, "\""] {
+            encoded.push(s);
+        }
+        for flag in rustflags {
+            encoded.push(SEPARATOR);
+            encoded.push(flag);
+        }
+
+        // Keep the location of the sources out of the debug info so that objects built from
+        // identical sources are identical.
+        encoded.push(SEPARATOR);
+        encoded.push("-Clinker=");
+        encoded.push(linker);
+
+        encoded.push(SEPARATOR);
+        encoded.push("--remap-path-prefix=");
+        encoded.push(&sources.workspace_root);
+        encoded.push("=.");
+
+        cmd.env("CARGO_ENCODED_RUSTFLAGS", encoded);
+    }
+
+    // Workaround to make sure that the correct toolchain is used.
+    for key in ["RUSTC", "RUSTC_WORKSPACE_WRAPPER"] {
+        cmd.env_remove(key);
+    }
+
+    // Workaround for https://github.com/rust-lang/cargo/issues/6412 where cargo flocks itself.
+    //
+    // Keep the cargo `--target-dir` separate from `OUT_DIR`'s output artifacts. Otherwise, if
+    // the package name matches a bin target name, `target_dir` would collide with the file we
+    // later copy to `OUT_DIR/<bin-name>`, causing `fs::copy` to fail with EISDIR.
+    let target_dir = out_dir.join("aya-build").join("target").join(name);
+    cmd.arg("--target-dir").arg(&target_dir);
 
-        for (name, binary) in executables {
-            let dst = out_dir.join(name);
+    let mut child = cmd
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("failed to spawn {cmd:?}"))?;
+    let Child { stdout, stderr, .. } = &mut child;
+
+    // Trampoline stdout to cargo warnings.
+    let stderr = stderr.take().expect("stderr");
+    let stderr = BufReader::new(stderr);
+    let stderr = std::thread::spawn(move || {
+        for line in stderr.lines() {
+            let line = line.expect("read line");
+            println!("cargo:warning={line}");
+        }
+    });
+
+    let stdout = stdout.take().expect("stdout");
+    let stdout = BufReader::new(stdout);
+    let mut executables = Vec::new();
+    for message in Message::parse_stream(stdout) {
+        #[expect(clippy::collapsible_match, reason = "better captures intent")]
+        match message.with_context(|| anyhow!("cargo stdout stream contains invalid JSON"))? {
+            Message::CompilerArtifact(cargo_metadata::Artifact {
+                executable,
+                target: Target { name, .. },
+                ..
+            }) => {
+                if let Some(executable) = executable {
+                    executables.push((name, executable.into_std_path_buf()));
+                }
+            }
+            Message::CompilerMessage(CompilerMessage { message, .. }) => {
+                for line in message.rendered.unwrap_or_default().split('\n') {
+                    println!("cargo:warning={line}");
+                }
+            }
+            Message::TextLine(line) => {
+                println!("cargo:warning={line}");
+            }
+            _ => {}
+        }
+    }
+
+    let status = child
+        .wait()
+        .with_context(|| format!("failed to wait for {cmd:?}"))?;
+    if !status.success() {
+        return Err(anyhow!("{cmd:?} failed: {status:?}"));
+    }
+
+    match stderr.join().map_err(std::panic::resume_unwind) {
+        Ok(()) => {}
+        Err(err) => match err {},
+    }
+
+    executables
+        .into_iter()
+        .map(|(name, binary)| {
+            let dst = out_dir.join(&name);
             let _: u64 = fs::copy(&binary, &dst).with_context(|| {
                 format!("failed to copy {} to {}", binary.display(), dst.display())
             })?;
-        }
-    }
-    Ok(())
+            Ok((name, Digest::of_file(&dst)?))
+        })
+        .collect()
+}
+
+/// Writes the skeleton of the eBPF object at `object` to `dst`.
+///
+/// The skeleton is Rust source code giving typed access to the programs, maps and global
+/// variables of the object, see [`aya_obj::skeleton`]. `name` is the name of the generated
+/// struct, converted to upper camel case. The skeleton is usually written to `OUT_DIR` after
+/// [`build_ebpf`] and included with `include!`.
+#[expect(
+    clippy::print_stdout,
+    reason = "println! is used for cargo:rerun-if-changed"
+)]
+pub fn write_skeleton(object: &Path, name: &str, dst: &Path) -> Result<()> {
+    println!("cargo:rerun-if-changed={}", object.display());
+    let data = fs::read(object).with_context(|| format!("failed to read {}", object.display()))?;
+    let object = aya_obj::Object::parse(&data)
+        .with_context(|| format!("failed to parse {}", object.display()))?;
+    let skeleton = aya_obj::skeleton::generate(&object, name)?;
+    fs::write(dst, skeleton).with_context(|| format!("failed to write {}", dst.display()))
 }
 
 /// The toolchain to use for building eBPF programs.
@@ -362,3 +1079,44 @@ pub fn emit_bpf_target_arch_cfg() -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rustflags() {
+        assert_eq!(
+            EbpfBuilder::new().rustflags().unwrap(),
+            ["-Cdebuginfo=2", "-Clink-arg=--btf"]
+        );
+        assert_eq!(
+            EbpfBuilder::new()
+                .btf(false)
+                .cpu(BpfCpu::V3)
+                .codegen_option("opt-level=2")
+                .rustflags()
+                .unwrap(),
+            [
+                "-Cdebuginfo=2",
+                "-Ctarget-cpu=v3",
+                "-Clink-arg=--cpu=v3",
+                "-Copt-level=2"
+            ]
+        );
+
+        let err = EbpfBuilder::new()
+            .debug_info(false)
+            .rustflags()
+            .unwrap_err();
+        assert!(err.to_string().contains("EbpfBuilder::debug_info"), "{err}");
+        assert!(
+            EbpfBuilder::new()
+                .debug_info(false)
+                .btf(false)
+                .rustflags()
+                .unwrap()
+                .is_empty()
+        );
+    }
+}
@@ -0,0 +1,130 @@
+//! Discovery of bpf-linker and validation of the objects it produces.
+
+use std::{
+    collections::BTreeSet,
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::{Context as _, Result, anyhow};
+use object::{Object as _, ObjectSection as _, ObjectSymbol as _, RelocationTarget, SectionKind};
+use rustc_version::Version;
+use which::which;
+
+const BPF_LINKER: &str = "bpf-linker";
+
+/// A bpf-linker installation.
+pub(crate) struct BpfLinker {
+    pub(crate) path: PathBuf,
+    pub(crate) version: Version,
+}
+
+impl BpfLinker {
+    /// Locates bpf-linker at `path`, or in `PATH` if `path` is `None`, and checks that it is at
+    /// least `min_version`.
+    pub(crate) fn find(path: Option<&str>, min_version: Option<&str>) -> Result<Self> {
+        let path = match path {
+            Some(path) => PathBuf::from(path),
+            None => which(BPF_LINKER).map_err(|err| {
+                anyhow!(
+                    "{BPF_LINKER} not found in PATH ({err}); install it with `cargo install \
+                     {BPF_LINKER}` or set its location with `EbpfBuilder::bpf_linker`"
+                )
+            })?,
+        };
+        let output = Command::new(&path)
+            .arg("--version")
+            .output()
+            .with_context(|| {
+                format!(
+                    "failed to run {}; check that it is an executable {BPF_LINKER}",
+                    path.display()
+                )
+            })?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "`{} --version` failed: {:?}",
+                path.display(),
+                output.status
+            ));
+        }
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let version = parse_version(&stdout).with_context(|| {
+            format!(
+                "failed to parse the version of {} from {stdout:?}",
+                path.display()
+            )
+        })?;
+
+        if let Some(min_version) = min_version {
+            let min_version = Version::parse(min_version)
+                .with_context(|| format!("invalid minimum {BPF_LINKER} version {min_version:?}"))?;
+            if version < min_version {
+                return Err(anyhow!(
+                    "{BPF_LINKER} {version} at {} is older than the required {min_version}; \
+                     update it with `cargo install {BPF_LINKER} --force`",
+                    path.display()
+                ));
+            }
+        }
+
+        Ok(Self { path, version })
+    }
+}
+
+/// Parses the output of `bpf-linker --version`, e.g. `bpf-linker 0.9.14`.
+fn parse_version(output: &str) -> Result<Version> {
+    let version = output
+        .split_whitespace()
+        .nth(1)
+        .ok_or_else(|| anyhow!("missing version"))?;
+    Ok(Version::parse(version)?)
+}
+
+/// Returns the names of the undefined functions called by the object at `path`.
+///
+/// The kernel cannot resolve calls to functions that are neither BPF helpers nor kfuncs. They
+/// typically come from `core` intrinsics, such as `memcpy` or 128-bit arithmetic, that the BPF
+/// backend has no lowering for.
+pub(crate) fn undefined_functions(path: &Path) -> Result<BTreeSet<String>> {
+    let data = fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let file = object::File::parse(&*data)
+        .with_context(|| format!("failed to parse {}", path.display()))?;
+    let mut names = BTreeSet::new();
+    for section in file.sections() {
+        if section.kind() != SectionKind::Text {
+            continue;
+        }
+        for (_offset, relocation) in section.relocations() {
+            let RelocationTarget::Symbol(index) = relocation.target() else {
+                continue;
+            };
+            let symbol = file
+                .symbol_by_index(index)
+                .with_context(|| format!("invalid symbol index in {}", path.display()))?;
+            if symbol.is_undefined()
+                && let Ok(name) = symbol.name()
+                && !name.is_empty()
+            {
+                let _: bool = names.insert(name.to_owned());
+            }
+        }
+    }
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(
+            parse_version("bpf-linker 0.9.14\n").unwrap(),
+            Version::new(0, 9, 14)
+        );
+        let err = parse_version("bpf-linker\n").unwrap_err();
+        assert_eq!(err.to_string(), "missing version");
+    }
+}
@@ -0,0 +1,154 @@
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{
+    Data, DeriveInput, Index, Member, Result, Type, WhereClause, parse_quote, spanned::Spanned as _,
+};
+
+pub(crate) struct Aggregate {
+    item: DeriveInput,
+    fields: Vec<(Member, Type)>,
+}
+
+impl Aggregate {
+    pub(crate) fn parse(item: TokenStream) -> Result<Self> {
+        let item: DeriveInput = syn::parse2(item)?;
+        let fields = match &item.data {
+            Data::Struct(data) => data
+                .fields
+                .iter()
+                .enumerate()
+                .map(|(i, field)| {
+                    let member = field
+                        .ident
+                        .clone()
+                        .map_or_else(|| Member::Unnamed(Index::from(i)), Member::Named);
+                    (member, field.ty.clone())
+                })
+                .collect(),
+            Data::Enum(data) => {
+                return Err(syn::Error::new(
+                    data.enum_token.span(),
+                    "`Aggregate` can't be derived for enums",
+                ));
+            }
+            Data::Union(data) => {
+                return Err(syn::Error::new(
+                    data.union_token.span(),
+                    "`Aggregate` can't be derived for unions",
+                ));
+            }
+        };
+        Ok(Self { item, fields })
+    }
+
+    pub(crate) fn expand(&self) -> TokenStream {
+        let Self { item, fields } = self;
+        let ident = &item.ident;
+        let (impl_generics, ty_generics, where_clause) = item.generics.split_for_impl();
+        let mut where_clause = where_clause.cloned().unwrap_or_else(|| WhereClause {
+            where_token: Default::default(),
+            predicates: Default::default(),
+        });
+        where_clause.predicates.extend(
+            fields.iter().map(|(_, ty)| -> syn::WherePredicate {
+                parse_quote!(#ty: ::aya::maps::Aggregate)
+            }),
+        );
+        let members: Vec<_> = fields.iter().map(|(member, _)| member).collect();
+        let methods = [quote!(sum_with), quote!(min_with), quote!(max_with)]
+            .into_iter()
+            .map(|method| {
+                quote! {
+                    fn #method(&mut self, other: &Self) {
+                        #(::aya::maps::Aggregate::#method(&mut self.#members, &other.#members);)*
+                    }
+                }
+            });
+        quote! {
+            impl #impl_generics ::aya::maps::Aggregate for #ident #ty_generics #where_clause {
+                #(#methods)*
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use syn::parse_quote;
+
+    use super::*;
+
+    #[test]
+    fn test_aggregate() {
+        let aggregate = Aggregate::parse(parse_quote! {
+            #[repr(C)]
+            struct Counters {
+                packets: u64,
+                bytes: [u32; 2],
+            }
+        })
+        .unwrap();
+        let expanded = aggregate.expand();
+        let expected = quote! {
+            impl ::aya::maps::Aggregate for Counters
+            where
+                u64: ::aya::maps::Aggregate,
+                [u32; 2]: ::aya::maps::Aggregate
+            {
+                fn sum_with(&mut self, other: &Self) {
+                    ::aya::maps::Aggregate::sum_with(&mut self.packets, &other.packets);
+                    ::aya::maps::Aggregate::sum_with(&mut self.bytes, &other.bytes);
+                }
+                fn min_with(&mut self, other: &Self) {
+                    ::aya::maps::Aggregate::min_with(&mut self.packets, &other.packets);
+                    ::aya::maps::Aggregate::min_with(&mut self.bytes, &other.bytes);
+                }
+                fn max_with(&mut self, other: &Self) {
+                    ::aya::maps::Aggregate::max_with(&mut self.packets, &other.packets);
+                    ::aya::maps::Aggregate::max_with(&mut self.bytes, &other.bytes);
+                }
+            }
+        };
+        assert_eq!(expected.to_string(), expanded.to_string());
+    }
+
+    #[test]
+    fn test_aggregate_tuple_struct() {
+        let aggregate = Aggregate::parse(parse_quote! {
+            #[repr(transparent)]
+            struct Count<T>(T);
+        })
+        .unwrap();
+        let expanded = aggregate.expand();
+        let expected = quote! {
+            impl<T> ::aya::maps::Aggregate for Count<T>
+            where
+                T: ::aya::maps::Aggregate
+            {
+                fn sum_with(&mut self, other: &Self) {
+                    ::aya::maps::Aggregate::sum_with(&mut self.0, &other.0);
+                }
+                fn min_with(&mut self, other: &Self) {
+                    ::aya::maps::Aggregate::min_with(&mut self.0, &other.0);
+                }
+                fn max_with(&mut self, other: &Self) {
+                    ::aya::maps::Aggregate::max_with(&mut self.0, &other.0);
+                }
+            }
+        };
+        assert_eq!(expected.to_string(), expanded.to_string());
+    }
+
+    #[test]
+    fn test_aggregate_union() {
+        let err = Aggregate::parse(parse_quote! {
+            #[repr(C)]
+            union Value {
+                a: u32,
+            }
+        })
+        .err()
+        .unwrap();
+        assert_eq!(err.to_string(), "`Aggregate` can't be derived for unions");
+    }
+}
@@ -0,0 +1,100 @@
+#![cfg_attr(test, expect(unused_crate_dependencies, reason = "used in doctests"))]
+
+mod aggregate;
+mod pod;
+
+use aggregate::Aggregate;
+use pod::Pod;
+use proc_macro::TokenStream;
+
+/// Implements `aya::Pod` for a `#[repr(C)]` or `#[repr(transparent)]` struct.
+///
+/// All the fields must implement `Pod`. Structs with implicit padding are rejected at compile
+/// time: padding bytes are not initialized, so equal keys could have different bytes and miss in
+/// hash maps. Add explicit padding fields instead.
+///
+/// The struct can be defined in a crate shared with the eBPF side, deriving `Pod` only when
+/// building for user space:
+///
+/// ```
+/// #[derive(Clone, Copy)]
+/// #[cfg_attr(feature = "user", derive(aya::Pod))]
+/// #[repr(C)]
+/// pub struct FlowKey {
+///     pub src_ip: u32,
+///     pub dst_ip: u32,
+///     pub src_port: u16,
+///     pub dst_port: u16,
+///     pub protocol: u8,
+///     pub _pad: [u8; 3],
+/// }
+/// ```
+///
+/// The padding check is evaluated by the typed maps, such as `HashMap` and `Array`, when they are
+/// created from a `Map` or with `create`. Other APIs taking a `Pod`, like
+/// `EbpfLoader::override_global`, don't check it. Structs with const generic parameters are only
+/// checked for the instantiations used with a typed map:
+///
+/// ```
+/// #[derive(Clone, Copy, aya::Pod)]
+/// #[repr(C)]
+/// pub struct Path<const N: usize> {
+///     pub len: u32,
+///     pub bytes: [u8; N],
+/// }
+/// ```
+///
+/// A struct with implicit padding doesn't compile:
+///
+/// ```compile_fail
+/// #[derive(Clone, Copy, aya::Pod)]
+/// #[repr(C)]
+/// pub struct FlowKey {
+///     pub ip: u32,
+///     pub port: u16,
+/// }
+/// ```
+///
+/// When `aya` is renamed or re-exported by another crate, set its path with `#[pod(crate = ...)]`:
+///
+/// ```
+/// use aya as user;
+///
+/// #[derive(Clone, Copy, user::Pod)]
+/// #[pod(crate = user)]
+/// #[repr(C)]
+/// pub struct Id {
+///     pub id: u64,
+/// }
+/// ```
+#[proc_macro_derive(Pod, attributes(pod))]
+pub fn pod(item: TokenStream) -> TokenStream {
+    match Pod::parse(item.into()) {
+        Ok(pod) => pod.expand(),
+        Err(err) => err.into_compile_error(),
+    }
+    .into()
+}
+
+/// Implements `aya::maps::Aggregate` for a struct by aggregating each field.
+///
+/// All the fields must implement `Aggregate`, and the struct must implement `aya::Pod`:
+///
+/// ```
+/// use aya::{Pod, maps::Aggregate};
+///
+/// #[derive(Clone, Copy, Pod, Aggregate)]
+/// #[repr(C)]
+/// pub struct Counters {
+///     pub packets: u64,
+///     pub bytes: u64,
+/// }
+/// ```
+#[proc_macro_derive(Aggregate)]
+pub fn aggregate(item: TokenStream) -> TokenStream {
+    match Aggregate::parse(item.into()) {
+        Ok(aggregate) => aggregate.expand(),
+        Err(err) => err.into_compile_error(),
+    }
+    .into()
+}
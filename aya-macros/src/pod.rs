@@ -0,0 +1,277 @@
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{Data, DeriveInput, Path, Result, Type, WhereClause, parse_quote, spanned::Spanned as _};
+
+pub(crate) struct Pod {
+    item: DeriveInput,
+    fields: Vec<Type>,
+    krate: Path,
+}
+
+impl Pod {
+    pub(crate) fn parse(item: TokenStream) -> Result<Self> {
+        let item: DeriveInput = syn::parse2(item)?;
+        let fields = match &item.data {
+            Data::Struct(data) => data.fields.iter().map(|field| field.ty.clone()).collect(),
+            Data::Enum(data) => {
+                return Err(syn::Error::new(
+                    data.enum_token.span(),
+                    "`Pod` can't be derived for enums",
+                ));
+            }
+            Data::Union(data) => {
+                return Err(syn::Error::new(
+                    data.union_token.span(),
+                    "`Pod` can't be derived for unions",
+                ));
+            }
+        };
+        check_repr(&item)?;
+        let krate = parse_crate(&item)?;
+        Ok(Self {
+            item,
+            fields,
+            krate,
+        })
+    }
+
+    pub(crate) fn expand(&self) -> TokenStream {
+        let Self {
+            item,
+            fields,
+            krate,
+        } = self;
+        let ident = &item.ident;
+        let (impl_generics, ty_generics, where_clause) = item.generics.split_for_impl();
+        let mut where_clause = where_clause.cloned().unwrap_or_else(|| WhereClause {
+            where_token: Default::default(),
+            predicates: Default::default(),
+        });
+        where_clause.predicates.extend(
+            fields
+                .iter()
+                .map(|ty| -> syn::WherePredicate { parse_quote!(#ty: #krate::Pod) }),
+        );
+        let message = format!("`{ident}` has implicit padding, add explicit padding fields");
+        // Generic structs are checked when `ASSERT_NO_PADDING` is used for a given instantiation.
+        let check = item.generics.params.is_empty().then(|| {
+            quote! {
+                const _: () = <#ident as #krate::Pod>::ASSERT_NO_PADDING;
+            }
+        });
+        quote! {
+            unsafe impl #impl_generics #krate::Pod for #ident #ty_generics #where_clause {
+                const ASSERT_NO_PADDING: () = ::core::assert!(
+                    ::core::mem::size_of::<Self>() == 0 #(+ ::core::mem::size_of::<#fields>())*,
+                    #message
+                );
+            }
+
+            #check
+        }
+    }
+}
+
+/// Returns the path of the `aya` crate, which can be set with `#[pod(crate = path)]`.
+fn parse_crate(item: &DeriveInput) -> Result<Path> {
+    let mut krate = parse_quote!(::aya);
+    for attr in item.attrs.iter().filter(|attr| attr.path().is_ident("pod")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("crate") {
+                krate = meta.value()?.parse()?;
+                Ok(())
+            } else {
+                Err(meta.error("unsupported `pod` attribute, expected `crate`"))
+            }
+        })?;
+    }
+    Ok(krate)
+}
+
+/// Checks that the layout of the struct is defined, so that it matches the eBPF side.
+fn check_repr(item: &DeriveInput) -> Result<()> {
+    let mut defined = false;
+    for attr in item
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("repr"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("C") || meta.path.is_ident("transparent") {
+                defined = true;
+            } else if meta.input.peek(syn::token::Paren) {
+                // `packed(N)` and `align(N)`.
+                let _args: proc_macro2::Group = meta.input.parse()?;
+            }
+            Ok(())
+        })?;
+    }
+    if defined {
+        Ok(())
+    } else {
+        Err(syn::Error::new(
+            item.ident.span(),
+            "`Pod` requires `#[repr(C)]` or `#[repr(transparent)]`",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use syn::parse_quote;
+
+    use super::*;
+
+    #[test]
+    fn test_pod() {
+        let pod = Pod::parse(parse_quote! {
+            #[derive(Clone, Copy)]
+            #[repr(C)]
+            struct Key {
+                ip: u32,
+                port: u16,
+                _pad: [u8; 2],
+            }
+        })
+        .unwrap();
+        let expanded = pod.expand();
+        let expected = quote! {
+            unsafe impl ::aya::Pod for Key
+            where
+                u32: ::aya::Pod,
+                u16: ::aya::Pod,
+                [u8; 2]: ::aya::Pod
+            {
+                const ASSERT_NO_PADDING: () = ::core::assert!(
+                    ::core::mem::size_of::<Self>() == 0
+                        + ::core::mem::size_of::<u32>()
+                        + ::core::mem::size_of::<u16>()
+                        + ::core::mem::size_of::<[u8; 2]>(),
+                    "`Key` has implicit padding, add explicit padding fields"
+                );
+            }
+
+            const _: () = <Key as ::aya::Pod>::ASSERT_NO_PADDING;
+        };
+        assert_eq!(expected.to_string(), expanded.to_string());
+    }
+
+    #[test]
+    fn test_pod_const_generic() {
+        let pod = Pod::parse(parse_quote! {
+            #[repr(C, align(8))]
+            struct Path<const N: usize> where [u8; N]: Copy {
+                bytes: [u8; N],
+            }
+        })
+        .unwrap();
+        let expanded = pod.expand();
+        let expected = quote! {
+            unsafe impl<const N: usize> ::aya::Pod for Path<N>
+            where
+                [u8; N]: Copy,
+                [u8; N]: ::aya::Pod
+            {
+                const ASSERT_NO_PADDING: () = ::core::assert!(
+                    ::core::mem::size_of::<Self>() == 0 + ::core::mem::size_of::<[u8; N]>(),
+                    "`Path` has implicit padding, add explicit padding fields"
+                );
+            }
+        };
+        assert_eq!(expected.to_string(), expanded.to_string());
+    }
+
+    #[test]
+    fn test_pod_tuple_struct() {
+        let pod = Pod::parse(parse_quote! {
+            #[repr(transparent)]
+            struct Id(u64);
+        })
+        .unwrap();
+        let expanded = pod.expand();
+        let expected = quote! {
+            unsafe impl ::aya::Pod for Id
+            where
+                u64: ::aya::Pod
+            {
+                const ASSERT_NO_PADDING: () = ::core::assert!(
+                    ::core::mem::size_of::<Self>() == 0 + ::core::mem::size_of::<u64>(),
+                    "`Id` has implicit padding, add explicit padding fields"
+                );
+            }
+
+            const _: () = <Id as ::aya::Pod>::ASSERT_NO_PADDING;
+        };
+        assert_eq!(expected.to_string(), expanded.to_string());
+    }
+
+    #[test]
+    fn test_pod_crate() {
+        let pod = Pod::parse(parse_quote! {
+            #[repr(transparent)]
+            #[pod(crate = common::aya)]
+            struct Id(u64);
+        })
+        .unwrap();
+        let expanded = pod.expand();
+        let expected = quote! {
+            unsafe impl common::aya::Pod for Id
+            where
+                u64: common::aya::Pod
+            {
+                const ASSERT_NO_PADDING: () = ::core::assert!(
+                    ::core::mem::size_of::<Self>() == 0 + ::core::mem::size_of::<u64>(),
+                    "`Id` has implicit padding, add explicit padding fields"
+                );
+            }
+
+            const _: () = <Id as common::aya::Pod>::ASSERT_NO_PADDING;
+        };
+        assert_eq!(expected.to_string(), expanded.to_string());
+    }
+
+    #[test]
+    fn test_pod_unknown_attribute() {
+        let err = Pod::parse(parse_quote! {
+            #[repr(C)]
+            #[pod(skip)]
+            struct Key {
+                ip: u32,
+            }
+        })
+        .err()
+        .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "unsupported `pod` attribute, expected `crate`"
+        );
+    }
+
+    #[test]
+    fn test_pod_no_repr() {
+        let err = Pod::parse(parse_quote! {
+            struct Key {
+                ip: u32,
+            }
+        })
+        .err()
+        .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "`Pod` requires `#[repr(C)]` or `#[repr(transparent)]`"
+        );
+    }
+
+    #[test]
+    fn test_pod_enum() {
+        let err = Pod::parse(parse_quote! {
+            #[repr(C)]
+            enum Key {
+                A,
+            }
+        })
+        .err()
+        .unwrap();
+        assert_eq!(err.to_string(), "`Pod` can't be derived for enums");
+    }
+}
@@ -14,7 +14,7 @@ use crate::{
     btf::{
         Array, BtfEnum, BtfEnum64, BtfKind, BtfMember, BtfType, Const, DataSec, DataSecEntry, Enum,
         Enum64, Enum64Fallback, Enum64VariantFallback, FuncInfo, FuncLinkage, Int, IntEncoding,
-        LineInfo, Struct, Typedef, Union, Var, VarLinkage,
+        LineInfo, Ptr, Restrict, Struct, TypeTag, Typedef, Union, Var, VarLinkage, Volatile,
         info::{FuncSecInfo, LineSecInfo},
         relocation::Relocation,
     },
@@ -174,6 +174,45 @@ pub enum BtfError {
         /// The name of the map with the invalid definition.
         name: String,
     },
+
+    /// The data is too short to be decoded as the given type.
+    #[error("{len} bytes are not enough to decode a value of BTF type `{type_id}`")]
+    ValueTooShort {
+        /// type id
+        type_id: u32,
+        /// length of the data
+        len: usize,
+    },
+
+    /// The value can't be encoded as the given type.
+    #[error("value can't be encoded as BTF type `{type_id}`")]
+    ValueTypeMismatch {
+        /// type id
+        type_id: u32,
+    },
+
+    /// The value doesn't fit in the given type.
+    #[error("value is out of range for BTF type `{type_id}`")]
+    ValueOutOfRange {
+        /// type id
+        type_id: u32,
+    },
+
+    /// The type has no member with the given name.
+    #[error("BTF type `{type_id}` has no member named `{name}`")]
+    UnknownField {
+        /// type id
+        type_id: u32,
+        /// name of the member
+        name: String,
+    },
+
+    /// The types form a cycle that can't be expressed in C.
+    #[error("unsatisfiable type cycle involving BTF type `{type_id}`")]
+    TypeCycle {
+        /// type id
+        type_id: u32,
+    },
 }
 
 /// Available BTF features
@@ -335,6 +374,19 @@ impl Btf {
         add_type(&mut self.header, &mut self.types, btf_type)
     }
 
+    /// Replaces the type with id `type_id`.
+    pub(crate) fn replace_type(&mut self, type_id: u32, btf_type: BtfType) {
+        let old_size = self.types.types[type_id as usize].type_info_size() as u32;
+        let size = btf_type.type_info_size() as u32;
+        self.types.types[type_id as usize] = btf_type;
+        self.header.type_len = self.header.type_len - old_size + size;
+        self.header.str_off = self.header.str_off - old_size + size;
+    }
+
+    pub(crate) fn strings(&self) -> &[u8] {
+        &self.strings
+    }
+
     /// Loads BTF metadata from `/sys/kernel/btf/vmlinux`.
     pub fn from_sys_fs() -> Result<Self, BtfError> {
         Self::parse_file("/sys/kernel/btf/vmlinux", Endianness::default())
@@ -463,6 +515,62 @@ impl Btf {
         })
     }
 
+    /// Returns a C-like spelling of the type with the given id, for example `struct flow_key`,
+    /// `__u32[4]` or `const char *`.
+    pub fn type_c_name(&self, type_id: u32) -> Result<String, BtfError> {
+        self.type_c_name_inner(type_id, MAX_RESOLVE_DEPTH)
+    }
+
+    fn type_c_name_inner(&self, type_id: u32, depth: usize) -> Result<String, BtfError> {
+        let Some(depth) = depth.checked_sub(1) else {
+            return Err(BtfError::MaximumTypeDepthReached { type_id });
+        };
+        let ty = self.type_by_id(type_id)?;
+        let named = |prefix: &str| -> Result<String, BtfError> {
+            let name = self.type_name(ty)?;
+            Ok(if name.is_empty() {
+                format!("{prefix} <anon>")
+            } else {
+                format!("{prefix} {name}")
+            })
+        };
+        Ok(match ty {
+            BtfType::Unknown => "void".to_owned(),
+            BtfType::Struct(_) => named("struct")?,
+            BtfType::Union(_) => named("union")?,
+            BtfType::Enum(_) | BtfType::Enum64(_) => named("enum")?,
+            BtfType::Ptr(Ptr { btf_type, .. }) => {
+                format!("{} *", self.type_c_name_inner(*btf_type, depth)?)
+            }
+            BtfType::Const(Const { btf_type, .. }) => {
+                format!("const {}", self.type_c_name_inner(*btf_type, depth)?)
+            }
+            BtfType::Volatile(Volatile { btf_type, .. }) => {
+                format!("volatile {}", self.type_c_name_inner(*btf_type, depth)?)
+            }
+            BtfType::Restrict(Restrict { btf_type, .. }) => {
+                format!("{} restrict", self.type_c_name_inner(*btf_type, depth)?)
+            }
+            BtfType::TypeTag(TypeTag { btf_type, .. }) => {
+                self.type_c_name_inner(*btf_type, depth)?
+            }
+            BtfType::Array(Array { array, .. }) => format!(
+                "{}[{}]",
+                self.type_c_name_inner(array.element_type, depth)?,
+                array.len
+            ),
+            BtfType::FuncProto(_) => "fn".to_owned(),
+            BtfType::Fwd(_)
+            | BtfType::Typedef(_)
+            | BtfType::Func(_)
+            | BtfType::Int(_)
+            | BtfType::Float(_)
+            | BtfType::Var(_)
+            | BtfType::DataSec(_)
+            | BtfType::DeclTag(_) => self.type_name(ty)?.into_owned(),
+        })
+    }
+
     pub(crate) fn type_size(&self, root_type_id: u32) -> Result<usize, BtfError> {
         let mut type_id = root_type_id;
         let mut n_elems = 1;
@@ -1187,6 +1295,10 @@ impl BtfExt {
         self.relocations.as_slice()
     }
 
+    pub(crate) const fn relocations_mut(&mut self) -> &mut Vec<(u32, Vec<Relocation>)> {
+        &mut self.relocations
+    }
+
     pub(crate) const fn func_info_rec_size(&self) -> usize {
         self.func_info_rec_size
     }
@@ -1326,7 +1438,7 @@ mod tests {
     use assert_matches::assert_matches;
 
     use super::*;
-    use crate::btf::{BtfParam, DeclTag, Float, Func, FuncProto, Ptr, TypeTag};
+    use crate::btf::{BtfParam, DeclTag, Float, Func, FuncProto};
 
     #[test]
     fn test_parse_header() {
@@ -1550,6 +1662,30 @@ mod tests {
         assert_eq!(btf.string_at(5).unwrap(), "widget");
     }
 
+    #[test]
+    fn test_type_c_name() {
+        let mut btf = Btf::new();
+        let name_offset = btf.add_string("char");
+        let char_type = btf.add_type(BtfType::Int(Int::new(
+            name_offset,
+            1,
+            IntEncoding::Signed,
+            0,
+        )));
+        let const_char = btf.add_type(BtfType::Const(Const::new(char_type)));
+        let ptr = btf.add_type(BtfType::Ptr(Ptr::new(0, const_char)));
+        let array = btf.add_type(BtfType::Array(Array::new(0, char_type, char_type, 16)));
+        let name_offset = btf.add_string("flow_key");
+        let named = btf.add_type(BtfType::Struct(Struct::new(name_offset, Vec::new(), 0)));
+        let anon = btf.add_type(BtfType::Union(Union::new(0, 0, Vec::new(), None)));
+
+        assert_eq!(btf.type_c_name(0).unwrap(), "void");
+        assert_eq!(btf.type_c_name(ptr).unwrap(), "const char *");
+        assert_eq!(btf.type_c_name(array).unwrap(), "char[16]");
+        assert_eq!(btf.type_c_name(named).unwrap(), "struct flow_key");
+        assert_eq!(btf.type_c_name(anon).unwrap(), "union <anon>");
+    }
+
     #[test]
     fn test_fixup_ptr() {
         let mut btf = Btf::new();
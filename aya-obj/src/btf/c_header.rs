@@ -0,0 +1,1241 @@
+//! Emission of C headers, such as `vmlinux.h`, from BTF.
+//!
+//! The algorithm follows libbpf's `btf_dump`: types are first put in an order where every type
+//! embedded by value is defined before its users, then emitted, adding forward declarations where
+//! a type is only referenced through pointers before its definition.
+
+use std::{
+    borrow::{Cow, ToOwned as _},
+    collections::HashMap,
+    fmt::Write as _,
+    mem,
+};
+
+use crate::btf::{Btf, BtfError, BtfMember, BtfType, FuncProto, Typedef};
+
+const PTR_SIZE: u32 = 8;
+
+/// Nesting limit of declarators and inline definitions, guarding against malformed BTF.
+const MAX_DECL_DEPTH: usize = 256;
+
+const PREAMBLE: &str = "\
+#ifndef __VMLINUX_H__
+#define __VMLINUX_H__
+
+#ifndef BPF_NO_PRESERVE_ACCESS_INDEX
+#pragma clang attribute push (__attribute__((preserve_access_index)), apply_to = record)
+#endif
+
+";
+
+const POSTAMBLE: &str = "\
+#ifndef BPF_NO_PRESERVE_ACCESS_INDEX
+#pragma clang attribute pop
+#endif
+
+#endif /* __VMLINUX_H__ */
+";
+
+impl Btf {
+    /// Renders every type as a C header, like `bpftool btf dump file <path> format c`.
+    ///
+    /// Named types are defined in dependency order and forward declared where they are only
+    /// referenced through pointers. Anonymous structs, unions and enums are defined inline.
+    /// Bitfields and explicit padding are emitted so that every struct has the layout described
+    /// by the BTF, and structs that can't be laid out naturally are marked packed. Types whose
+    /// names clash get a `___N` suffix.
+    ///
+    /// The header is wrapped in `__VMLINUX_H__` include guards and applies
+    /// `preserve_access_index` to every record, so that field accesses are CO-RE relocatable.
+    pub fn to_c_header(&self) -> Result<String, BtfError> {
+        let mut dumper = CDumper::new(self);
+        for type_id in 1..dumper.states.len() as u32 {
+            let _strong: bool = dumper.order(type_id, false)?;
+            for type_id in mem::take(&mut dumper.queue) {
+                dumper.emit(type_id, 0)?;
+            }
+        }
+        Ok(format!("{PREAMBLE}{}{POSTAMBLE}", dumper.out))
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum State {
+    #[default]
+    NotStarted,
+    InProgress,
+    Done,
+}
+
+#[derive(Debug, Default)]
+struct TypeState {
+    order: State,
+    emit: State,
+    /// A forward declaration, or for typedefs the definition, has been emitted.
+    fwd_emitted: bool,
+    /// Another type refers to this one.
+    referenced: bool,
+    name: Option<String>,
+    align: Option<u32>,
+}
+
+struct CDumper<'a> {
+    btf: &'a Btf,
+    states: Vec<TypeState>,
+    /// Named types ready to be emitted, in dependency order.
+    queue: Vec<u32>,
+    /// Occurrences of struct, union and enum tags.
+    type_names: HashMap<String, usize>,
+    /// Occurrences of typedef and enumerator names.
+    ident_names: HashMap<String, usize>,
+    out: String,
+}
+
+impl<'a> CDumper<'a> {
+    fn new(btf: &'a Btf) -> Self {
+        let mut states = btf
+            .types()
+            .map(|_| TypeState::default())
+            .collect::<Vec<_>>();
+        let mut mark = |type_id: u32| {
+            if let Some(state) = states.get_mut(type_id as usize) {
+                state.referenced = true;
+            }
+        };
+        for ty in btf.types() {
+            match ty {
+                BtfType::Array(array) => {
+                    mark(array.array.element_type);
+                    mark(array.array.index_type);
+                }
+                BtfType::Struct(_) | BtfType::Union(_) => {
+                    ty.members()
+                        .into_iter()
+                        .flatten()
+                        .for_each(|member| mark(member.btf_type));
+                }
+                BtfType::FuncProto(proto) => {
+                    mark(proto.return_type);
+                    proto.params.iter().for_each(|param| mark(param.btf_type));
+                }
+                BtfType::DataSec(datasec) => {
+                    datasec
+                        .entries
+                        .iter()
+                        .for_each(|entry| mark(entry.btf_type));
+                }
+                ty => {
+                    if let Some(type_id) = ty.btf_type() {
+                        mark(type_id);
+                    }
+                }
+            }
+        }
+        Self {
+            btf,
+            states,
+            queue: Vec::new(),
+            type_names: HashMap::new(),
+            ident_names: HashMap::new(),
+            out: String::new(),
+        }
+    }
+
+    fn state(&mut self, type_id: u32) -> Result<&mut TypeState, BtfError> {
+        self.states
+            .get_mut(type_id as usize)
+            .ok_or(BtfError::UnknownBtfType { type_id })
+    }
+
+    /// Queues the named types `type_id` depends on, then `type_id` itself, for emission.
+    ///
+    /// Returns whether the type is a definition that must be emitted before it is used, as
+    /// opposed to one that can be forward declared because it's only reached through a pointer.
+    fn order(&mut self, type_id: u32, through_ptr: bool) -> Result<bool, BtfError> {
+        let btf = self.btf;
+        let ty = btf.type_by_id(type_id)?;
+        let named = ty.name_offset() != 0;
+        match self.state(type_id)?.order {
+            State::Done => return Ok(true),
+            // A cycle through a pointer can be broken with a forward declaration.
+            State::InProgress if ty.is_composite() && through_ptr && named => return Ok(false),
+            State::InProgress => return Err(BtfError::TypeCycle { type_id }),
+            State::NotStarted => {}
+        }
+        let strong = match ty {
+            BtfType::Unknown => false,
+            BtfType::Int(_) | BtfType::Float(_) => {
+                self.state(type_id)?.order = State::Done;
+                false
+            }
+            BtfType::Ptr(ptr) => {
+                let strong = self.order(ptr.btf_type, true)?;
+                self.state(type_id)?.order = State::Done;
+                strong
+            }
+            BtfType::Array(array) => self.order(array.array.element_type, false)?,
+            BtfType::Struct(_) | BtfType::Union(_) => {
+                // Only embedded or anonymous structs need their members defined first.
+                if through_ptr && named {
+                    return Ok(false);
+                }
+                self.state(type_id)?.order = State::InProgress;
+                for member in ty.members().into_iter().flatten() {
+                    let _strong: bool = self.order(member.btf_type, false)?;
+                }
+                if named {
+                    self.queue.push(type_id);
+                }
+                self.state(type_id)?.order = State::Done;
+                true
+            }
+            BtfType::Enum(_) | BtfType::Enum64(_) | BtfType::Fwd(_) => {
+                // Anonymous enums that aren't referenced only exist to define constants.
+                if named || !self.state(type_id)?.referenced {
+                    self.queue.push(type_id);
+                }
+                self.state(type_id)?.order = State::Done;
+                true
+            }
+            BtfType::Typedef(typedef) => {
+                let strong = self.order(typedef.btf_type, through_ptr)?;
+                if through_ptr && !strong {
+                    return Ok(false);
+                }
+                self.queue.push(type_id);
+                self.state(type_id)?.order = State::Done;
+                true
+            }
+            BtfType::Const(_)
+            | BtfType::Volatile(_)
+            | BtfType::Restrict(_)
+            | BtfType::TypeTag(_) => match ty.btf_type() {
+                Some(target) => self.order(target, through_ptr)?,
+                None => false,
+            },
+            BtfType::FuncProto(proto) => {
+                let mut strong = self.order(proto.return_type, through_ptr)?;
+                for param in &proto.params {
+                    strong |= self.order(param.btf_type, through_ptr)?;
+                }
+                strong
+            }
+            BtfType::Func(_) | BtfType::Var(_) | BtfType::DataSec(_) | BtfType::DeclTag(_) => {
+                self.state(type_id)?.order = State::Done;
+                false
+            }
+        };
+        Ok(strong)
+    }
+
+    /// Emits the definition of `type_id`, preceded by the forward declarations it needs.
+    ///
+    /// `cont_id` is the named struct or union being defined when `type_id` is reached through
+    /// one of its members, or 0 for top-level definitions.
+    fn emit(&mut self, type_id: u32, cont_id: u32) -> Result<(), BtfError> {
+        let btf = self.btf;
+        let ty = btf.type_by_id(type_id)?;
+        let top_level = cont_id == 0;
+        let state = self.state(type_id)?;
+        match state.emit {
+            State::Done => return Ok(()),
+            State::InProgress => {
+                if state.fwd_emitted {
+                    return Ok(());
+                }
+                match ty {
+                    // Members referring to their own container need no declaration.
+                    BtfType::Struct(_) | BtfType::Union(_) if type_id == cont_id => {}
+                    BtfType::Struct(_) | BtfType::Union(_) => {
+                        if ty.name_offset() == 0 {
+                            return Err(BtfError::TypeCycle { type_id });
+                        }
+                        let fwd = self.composite_fwd(type_id)?;
+                        self.push_def(&fwd);
+                        self.state(type_id)?.fwd_emitted = true;
+                    }
+                    // A typedef can be used as its own forward declaration, as long as it's
+                    // only referenced through pointers.
+                    BtfType::Typedef(typedef) => {
+                        self.emit_typedef(type_id, typedef)?;
+                        self.state(type_id)?.fwd_emitted = true;
+                    }
+                    _ => {}
+                }
+                return Ok(());
+            }
+            State::NotStarted => {}
+        }
+        match ty {
+            BtfType::Int(_) | BtfType::Float(_) => self.state(type_id)?.emit = State::Done,
+            BtfType::Enum(_) | BtfType::Enum64(_) => {
+                if top_level {
+                    let def = self.enum_def(type_id, 0)?;
+                    self.push_def(&def);
+                }
+                self.state(type_id)?.emit = State::Done;
+            }
+            BtfType::Ptr(_)
+            | BtfType::Const(_)
+            | BtfType::Volatile(_)
+            | BtfType::Restrict(_)
+            | BtfType::TypeTag(_) => {
+                if let Some(target) = ty.btf_type() {
+                    self.emit(target, cont_id)?;
+                }
+            }
+            BtfType::Array(array) => self.emit(array.array.element_type, cont_id)?,
+            BtfType::Fwd(_) => {
+                let fwd = self.composite_fwd(type_id)?;
+                self.push_def(&fwd);
+                self.state(type_id)?.emit = State::Done;
+            }
+            BtfType::Typedef(typedef) => {
+                self.state(type_id)?.emit = State::InProgress;
+                self.emit(typedef.btf_type, type_id)?;
+                if !self.state(type_id)?.fwd_emitted {
+                    self.emit_typedef(type_id, typedef)?;
+                }
+                self.state(type_id)?.emit = State::Done;
+            }
+            BtfType::Struct(_) | BtfType::Union(_) => {
+                self.state(type_id)?.emit = State::InProgress;
+                let anonymous = ty.name_offset() == 0;
+                // Definitions spell out every member, which may need forward declarations.
+                if top_level || anonymous {
+                    let cont_id = if anonymous { cont_id } else { type_id };
+                    for member in ty.members().into_iter().flatten() {
+                        self.emit(member.btf_type, cont_id)?;
+                    }
+                } else if !self.state(type_id)?.fwd_emitted && type_id != cont_id {
+                    let fwd = self.composite_fwd(type_id)?;
+                    self.push_def(&fwd);
+                    self.state(type_id)?.fwd_emitted = true;
+                }
+                if top_level {
+                    let def = self.composite_def(type_id, 0, MAX_DECL_DEPTH)?;
+                    self.push_def(&def);
+                    self.state(type_id)?.emit = State::Done;
+                } else {
+                    self.state(type_id)?.emit = State::NotStarted;
+                }
+            }
+            BtfType::FuncProto(proto) => {
+                self.emit(proto.return_type, cont_id)?;
+                for param in &proto.params {
+                    self.emit(param.btf_type, cont_id)?;
+                }
+            }
+            BtfType::Unknown
+            | BtfType::Func(_)
+            | BtfType::Var(_)
+            | BtfType::DataSec(_)
+            | BtfType::DeclTag(_) => {}
+        }
+        Ok(())
+    }
+
+    fn push_def(&mut self, def: &str) {
+        self.out.push_str(def);
+        self.out.push_str(";\n\n");
+    }
+
+    fn emit_typedef(&mut self, type_id: u32, typedef: &Typedef) -> Result<(), BtfError> {
+        let name = self.name(type_id)?;
+        // Compiler builtins can't be redefined.
+        if name == "__builtin_va_list" {
+            return Ok(());
+        }
+        let decl = self.decl(typedef.btf_type, name, 0, MAX_DECL_DEPTH)?;
+        self.push_def(&format!("typedef {decl}"));
+        Ok(())
+    }
+
+    /// Returns the name of `type_id`, suffixed if another type of the same namespace already
+    /// uses it.
+    fn name(&mut self, type_id: u32) -> Result<String, BtfError> {
+        if let Some(name) = &self.state(type_id)?.name {
+            return Ok(name.clone());
+        }
+        let btf = self.btf;
+        let ty = btf.type_by_id(type_id)?;
+        let name = btf.type_name(ty)?;
+        let name = match ty {
+            _ if name.is_empty() => String::new(),
+            // Forward declarations and enum declarations refer to a definition of the same name.
+            BtfType::Fwd(_) => name.into_owned(),
+            BtfType::Enum(e) if e.variants.is_empty() => name.into_owned(),
+            BtfType::Enum64(e) if e.variants.is_empty() => name.into_owned(),
+            BtfType::Typedef(_) => dedup(&mut self.ident_names, &name),
+            _ => dedup(&mut self.type_names, &name),
+        };
+        self.state(type_id)?.name = Some(name.clone());
+        Ok(name)
+    }
+
+    /// Returns the C declaration of `declarator` with type `type_id`.
+    ///
+    /// Anonymous structs, unions and enums are defined inline, indented at `level`.
+    fn decl(
+        &mut self,
+        type_id: u32,
+        declarator: String,
+        level: usize,
+        depth: usize,
+    ) -> Result<String, BtfError> {
+        let Some(depth) = depth.checked_sub(1) else {
+            return Err(BtfError::MaximumTypeDepthReached { type_id });
+        };
+        let btf = self.btf;
+        let ty = btf.type_by_id(type_id)?;
+        match ty {
+            BtfType::Ptr(ptr) => self.decl(ptr.btf_type, format!("*{declarator}"), level, depth),
+            BtfType::Const(_) => self.qualified("const", ty, declarator, level, depth),
+            BtfType::Volatile(_) => self.qualified("volatile", ty, declarator, level, depth),
+            BtfType::Restrict(_) => self.qualified("restrict", ty, declarator, level, depth),
+            BtfType::TypeTag(tag) => self.decl(tag.btf_type, declarator, level, depth),
+            BtfType::Array(array) => {
+                let declarator = format!("{}[{}]", parenthesize(declarator), array.array.len);
+                self.decl(array.array.element_type, declarator, level, depth)
+            }
+            BtfType::FuncProto(proto) => {
+                let params = self.params(proto, level, depth)?;
+                let declarator = format!("{}({params})", parenthesize(declarator));
+                self.decl(proto.return_type, declarator, level, depth)
+            }
+            BtfType::Unknown => Ok(join("void", &declarator)),
+            BtfType::Int(_) | BtfType::Float(_) => Ok(join(&btf.type_name(ty)?, &declarator)),
+            BtfType::Typedef(_) => Ok(join(&self.name(type_id)?, &declarator)),
+            BtfType::Fwd(_) => Ok(join(&self.composite_fwd(type_id)?, &declarator)),
+            BtfType::Struct(_) | BtfType::Union(_) => {
+                let base = if ty.name_offset() == 0 {
+                    self.composite_def(type_id, level, depth)?
+                } else {
+                    self.composite_fwd(type_id)?
+                };
+                Ok(join(&base, &declarator))
+            }
+            BtfType::Enum(_) | BtfType::Enum64(_) => {
+                let base = if ty.name_offset() == 0 {
+                    self.enum_def(type_id, level)?
+                } else {
+                    format!("enum {}", self.name(type_id)?)
+                };
+                Ok(join(&base, &declarator))
+            }
+            BtfType::Func(_) | BtfType::Var(_) | BtfType::DataSec(_) | BtfType::DeclTag(_) => {
+                Err(BtfError::UnexpectedBtfType { type_id })
+            }
+        }
+    }
+
+    /// Declares `declarator` with the qualified type `ty`.
+    fn qualified(
+        &mut self,
+        qualifier: &str,
+        ty: &BtfType,
+        declarator: String,
+        level: usize,
+        depth: usize,
+    ) -> Result<String, BtfError> {
+        let Some(target) = ty.btf_type() else {
+            return Ok(join(qualifier, &declarator));
+        };
+        // Qualifiers of pointers go after the `*`, other qualifiers go before the type.
+        let mut next = target;
+        let qualifies_ptr = loop {
+            match self.btf.type_by_id(next)? {
+                BtfType::Ptr(_) => break true,
+                ty @ (BtfType::Const(_)
+                | BtfType::Volatile(_)
+                | BtfType::Restrict(_)
+                | BtfType::TypeTag(_)) => match ty.btf_type() {
+                    Some(btf_type) => next = btf_type,
+                    None => break false,
+                },
+                _ => break false,
+            }
+        };
+        if qualifies_ptr {
+            self.decl(target, join(qualifier, &declarator), level, depth)
+        } else {
+            let decl = self.decl(target, declarator, level, depth)?;
+            Ok(format!("{qualifier} {decl}"))
+        }
+    }
+
+    fn params(
+        &mut self,
+        proto: &FuncProto,
+        level: usize,
+        depth: usize,
+    ) -> Result<String, BtfError> {
+        if proto.params.is_empty() {
+            return Ok("void".to_owned());
+        }
+        let mut params = Vec::with_capacity(proto.params.len());
+        for (i, param) in proto.params.iter().enumerate() {
+            // A trailing `void` parameter marks a variadic function.
+            if param.btf_type == 0 && i == proto.params.len() - 1 {
+                params.push("...".to_owned());
+                continue;
+            }
+            let name = self.btf.string_at(param.name_offset)?.into_owned();
+            params.push(self.decl(param.btf_type, name, level, depth)?);
+        }
+        Ok(params.join(", "))
+    }
+
+    /// Returns the forward declaration of a struct or union.
+    fn composite_fwd(&mut self, type_id: u32) -> Result<String, BtfError> {
+        let keyword = match self.btf.type_by_id(type_id)? {
+            BtfType::Struct(_) => "struct",
+            BtfType::Fwd(fwd) if !fwd.is_union() => "struct",
+            BtfType::Union(_) | BtfType::Fwd(_) => "union",
+            _ => return Err(BtfError::UnexpectedBtfType { type_id }),
+        };
+        Ok(format!("{keyword} {}", self.name(type_id)?))
+    }
+
+    /// Returns the definition of a struct or union, indented at `level`.
+    fn composite_def(
+        &mut self,
+        type_id: u32,
+        level: usize,
+        depth: usize,
+    ) -> Result<String, BtfError> {
+        let btf = self.btf;
+        let ty = btf.type_by_id(type_id)?;
+        let (keyword, size) = match ty {
+            BtfType::Struct(s) => ("struct", s.size),
+            BtfType::Union(u) => ("union", u.size),
+            _ => return Err(BtfError::UnexpectedBtfType { type_id }),
+        };
+        let is_struct = keyword == "struct";
+        let align = self.align_of(type_id)?;
+        let packed = is_struct && self.is_packed(ty)?;
+        let name = self.name(type_id)?;
+        let mut def = join(keyword, &name);
+        def.push_str(" {");
+
+        let indent = "\t".repeat(level + 1);
+        let mut offset = 0;
+        let mut prev_bitfield = false;
+        for (member, bit_offset, bit_size) in members(ty) {
+            let member_align = if packed {
+                1
+            } else {
+                self.align_of(member.btf_type)?
+            };
+            let in_bitfield = prev_bitfield && bit_size != 0;
+            emit_padding(
+                &mut def,
+                offset,
+                bit_offset,
+                member_align,
+                in_bitfield,
+                &indent,
+            );
+            let name = btf.string_at(member.name_offset)?.into_owned();
+            let decl = self.decl(member.btf_type, name, level + 1, depth)?;
+            def.push('\n');
+            def.push_str(&indent);
+            def.push_str(&decl);
+            if bit_size == 0 {
+                offset = bit_offset + btf.type_size(member.btf_type)? as u32 * 8;
+                prev_bitfield = false;
+            } else {
+                write!(def, ": {bit_size}").unwrap();
+                offset = bit_offset + bit_size;
+                prev_bitfield = true;
+            }
+            def.push(';');
+        }
+        if is_struct {
+            emit_padding(&mut def, offset, size * 8, align, false, &indent);
+        }
+
+        // Keep `struct empty {}` on a single line.
+        if !def.ends_with('{') {
+            def.push('\n');
+            def.push_str(&"\t".repeat(level));
+        }
+        def.push('}');
+        if packed {
+            def.push_str(" __attribute__((packed))");
+        }
+        Ok(def)
+    }
+
+    /// Returns the definition of an enum, indented at `level`.
+    fn enum_def(&mut self, type_id: u32, level: usize) -> Result<String, BtfError> {
+        let btf = self.btf;
+        let ty = btf.type_by_id(type_id)?;
+        let (size, variants): (_, Vec<(u32, Cow<'_, str>)>) = match ty {
+            BtfType::Enum(e) => (
+                e.size,
+                e.variants
+                    .iter()
+                    .map(|v| {
+                        let value = if e.is_signed() {
+                            (v.value as i32).to_string()
+                        } else {
+                            v.value.to_string()
+                        };
+                        (v.name_offset, value.into())
+                    })
+                    .collect(),
+            ),
+            BtfType::Enum64(e) => (
+                e.size,
+                e.variants
+                    .iter()
+                    .map(|v| {
+                        let value = (u64::from(v.value_high) << 32) | u64::from(v.value_low);
+                        let value = if e.is_signed() {
+                            format!("{}LL", value as i64)
+                        } else {
+                            format!("{value}ULL")
+                        };
+                        (v.name_offset, value.into())
+                    })
+                    .collect(),
+            ),
+            _ => return Err(BtfError::UnexpectedBtfType { type_id }),
+        };
+        let name = self.name(type_id)?;
+        let mut def = join("enum", &name);
+        if variants.is_empty() {
+            return Ok(def);
+        }
+        def.push_str(" {");
+        let indent = "\t".repeat(level + 1);
+        for (name_offset, value) in variants {
+            let name = dedup(&mut self.ident_names, &btf.string_at(name_offset)?);
+            write!(def, "\n{indent}{name} = {value},").unwrap();
+        }
+        def.push('\n');
+        def.push_str(&"\t".repeat(level));
+        def.push('}');
+
+        // Enums are int sized unless their values don't fit, so other sizes need attributes.
+        let needs_word_mode = match ty {
+            BtfType::Enum(_) => true,
+            BtfType::Enum64(e) => e.variants.iter().all(|v| v.value_high == 0),
+            _ => false,
+        };
+        if size == 1 {
+            def.push_str(" __attribute__((mode(byte)))");
+        } else if size == 8 && needs_word_mode {
+            def.push_str(" __attribute__((mode(word)))");
+        }
+        Ok(def)
+    }
+
+    /// Returns the natural alignment of `type_id`, in bytes.
+    fn align_of(&mut self, type_id: u32) -> Result<u32, BtfError> {
+        if let Some(align) = self.state(type_id)?.align {
+            return Ok(align);
+        }
+        let btf = self.btf;
+        let ty = btf.type_by_id(type_id)?;
+        let align = match ty {
+            BtfType::Int(_) | BtfType::Float(_) | BtfType::Enum(_) | BtfType::Enum64(_) => {
+                ty.size().unwrap_or(1).clamp(1, PTR_SIZE)
+            }
+            BtfType::Ptr(_) => PTR_SIZE,
+            BtfType::Array(array) => self.align_of(array.array.element_type)?,
+            BtfType::Typedef(_)
+            | BtfType::Const(_)
+            | BtfType::Volatile(_)
+            | BtfType::Restrict(_)
+            | BtfType::TypeTag(_) => match ty.btf_type() {
+                Some(target) => self.align_of(target)?,
+                None => return Err(BtfError::UnexpectedBtfType { type_id }),
+            },
+            BtfType::Struct(_) | BtfType::Union(_) => {
+                if self.is_packed(ty)? {
+                    1
+                } else {
+                    let mut max_align = 1;
+                    for (member, _, _) in members(ty) {
+                        max_align = max_align.max(self.align_of(member.btf_type)?);
+                    }
+                    max_align
+                }
+            }
+            BtfType::Unknown
+            | BtfType::Fwd(_)
+            | BtfType::Func(_)
+            | BtfType::FuncProto(_)
+            | BtfType::Var(_)
+            | BtfType::DataSec(_)
+            | BtfType::DeclTag(_) => return Err(BtfError::UnexpectedBtfType { type_id }),
+        };
+        self.state(type_id)?.align = Some(align);
+        Ok(align)
+    }
+
+    /// Returns true if the members of `ty` aren't naturally aligned, or its size isn't a
+    /// multiple of its alignment.
+    fn is_packed(&mut self, ty: &BtfType) -> Result<bool, BtfError> {
+        let mut max_align = 1;
+        for (member, bit_offset, bit_size) in members(ty) {
+            let align = self.align_of(member.btf_type)?;
+            if bit_size == 0 && !bit_offset.is_multiple_of(align * 8) {
+                return Ok(true);
+            }
+            max_align = max_align.max(align);
+        }
+        Ok(!ty.size().unwrap_or(0).is_multiple_of(max_align))
+    }
+}
+
+/// Returns the members of a struct or union with their bit offsets and bitfield sizes.
+fn members(ty: &BtfType) -> impl Iterator<Item = (&BtfMember, u32, u32)> {
+    ty.members().into_iter().flatten().map(move |member| {
+        (
+            member,
+            ty.member_bit_offset(member).unwrap_or(0) as u32,
+            ty.member_bit_field_size(member).unwrap_or(0) as u32,
+        )
+    })
+}
+
+/// Appends anonymous bitfields to `def`, moving from bit `offset` to `next_offset`.
+///
+/// The compiler aligns the next member on its own, so explicit padding is only needed where
+/// natural alignment wouldn't place the member at `next_offset`.
+fn emit_padding(
+    def: &mut String,
+    offset: u32,
+    next_offset: u32,
+    next_align: u32,
+    in_bitfield: bool,
+    indent: &str,
+) {
+    const PADS: [(&str, u32); 4] = [("long", 64), ("int", 32), ("short", 16), ("char", 8)];
+
+    if offset >= next_offset {
+        return;
+    }
+    // Find the largest type whose alignment falls within the gap.
+    let mut offset = offset;
+    let (mut pad_type, mut pad_bits) = PADS[3];
+    let mut aligned = offset;
+    for (name, bits) in PADS {
+        (pad_type, pad_bits) = (name, bits);
+        aligned = offset.next_multiple_of(bits);
+        if aligned <= next_offset {
+            break;
+        }
+    }
+    if aligned > offset && aligned <= next_offset {
+        // A `<type>: 0` marker is needed when the next member wouldn't be aligned to this
+        // boundary on its own, or when the remaining gap would fit in this hole, making the
+        // compiler ignore the padding. Bitfields always need explicit sizes.
+        if in_bitfield
+            || (aligned == next_offset && offset.next_multiple_of(next_align * 8) != aligned)
+            || (aligned != next_offset && next_offset - aligned <= aligned - offset)
+        {
+            let bits = if in_bitfield { aligned - offset } else { 0 };
+            write!(def, "\n{indent}{pad_type}: {bits};").unwrap();
+        }
+        offset = aligned;
+    }
+    // Fill the rest of the gap, using the smallest type that fits the remainder.
+    while offset != next_offset {
+        let bits = (next_offset - offset).min(pad_bits);
+        let pad_type = if bits == pad_bits {
+            pad_type
+        } else {
+            PADS.iter()
+                .rev()
+                .find(|(_, pad_bits)| *pad_bits >= bits)
+                .map_or("long", |(name, _)| name)
+        };
+        write!(def, "\n{indent}{pad_type}: {bits};").unwrap();
+        offset += bits;
+    }
+}
+
+/// Parenthesizes pointer declarators so that array and function suffixes bind to them.
+fn parenthesize(declarator: String) -> String {
+    if declarator.starts_with('*') {
+        format!("({declarator})")
+    } else {
+        declarator
+    }
+}
+
+fn join(base: &str, declarator: &str) -> String {
+    if declarator.is_empty() {
+        base.to_owned()
+    } else {
+        format!("{base} {declarator}")
+    }
+}
+
+fn dedup(names: &mut HashMap<String, usize>, name: &str) -> String {
+    let count = names.entry(name.to_owned()).or_default();
+    *count += 1;
+    if *count > 1 {
+        format!("{name}___{count}")
+    } else {
+        name.to_owned()
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::btf::{
+        Array, BtfEnum, BtfEnum64, BtfParam, Const, Enum, Enum64, Int, IntEncoding, Ptr, Struct,
+        Union,
+    };
+
+    fn member(btf: &mut Btf, name: &str, btf_type: u32, offset: u32) -> BtfMember {
+        BtfMember {
+            name_offset: btf.add_string(name),
+            btf_type,
+            offset,
+        }
+    }
+
+    fn int(btf: &mut Btf, name: &str, size: u32, encoding: IntEncoding) -> u32 {
+        let name_offset = btf.add_string(name);
+        btf.add_type(BtfType::Int(Int::new(name_offset, size, encoding, 0)))
+    }
+
+    fn param(btf: &mut Btf, name: &str, btf_type: u32) -> BtfParam {
+        BtfParam {
+            name_offset: btf.add_string(name),
+            btf_type,
+        }
+    }
+
+    fn body(btf: &Btf) -> String {
+        let header = btf.to_c_header().unwrap();
+        header
+            .strip_prefix(PREAMBLE)
+            .and_then(|header| header.strip_suffix(POSTAMBLE))
+            .unwrap()
+            .to_owned()
+    }
+
+    #[test]
+    fn test_bitfields_and_anonymous_types() {
+        let mut btf = Btf::new();
+        let name_offset = btf.add_string("unsigned int");
+        let uint = btf.add_type(BtfType::Int(Int::new(name_offset, 4, IntEncoding::None, 0)));
+        let name_offset = btf.add_string("u32");
+        let u32_type = btf.add_type(BtfType::Typedef(Typedef::new(name_offset, uint)));
+        let name_offset = btf.add_string("char");
+        let char_type = btf.add_type(BtfType::Int(Int::new(
+            name_offset,
+            1,
+            IntEncoding::Signed,
+            0,
+        )));
+        let variants = vec![
+            BtfEnum::new(btf.add_string("MODE_A"), 0),
+            BtfEnum::new(btf.add_string("MODE_B"), 1),
+        ];
+        let mode = btf.add_type(BtfType::Enum(Enum::new(0, false, variants)));
+        let members = vec![
+            member(&mut btf, "raw", u32_type, 0),
+            member(&mut btf, "mode", mode, 0),
+        ];
+        let anon_union = btf.add_type(BtfType::Union(Union::new(0, 4, members, None)));
+        let members = vec![
+            member(&mut btf, "flags", u32_type, 3 << 24),
+            member(&mut btf, "kind", uint, (5 << 24) | 3),
+            member(&mut btf, "tag", char_type, 16),
+            member(&mut btf, "", anon_union, 32),
+            member(&mut btf, "last", char_type, 96),
+        ];
+        let name_offset = btf.add_string("packet");
+        let mut packet = Struct::new(name_offset, members, 16);
+        packet.info |= 1 << 31;
+        btf.add_type(BtfType::Struct(packet));
+
+        assert_eq!(
+            body(&btf),
+            "typedef unsigned int u32;
+
+struct packet {
+\tu32 flags: 3;
+\tunsigned int kind: 5;
+\tshort: 0;
+\tchar tag;
+\tunion {
+\t\tu32 raw;
+\t\tenum {
+\t\t\tMODE_A = 0,
+\t\t\tMODE_B = 1,
+\t\t} mode;
+\t};
+\tint: 32;
+\tchar last;
+};
+
+"
+        );
+    }
+
+    #[test]
+    fn test_forward_declarations() {
+        // struct a { struct b *b; int (*cb)(struct a *, int); };
+        // struct b { struct a a; struct b *next; const char *names[2]; };
+        const A: u32 = 10;
+        const B: u32 = 11;
+        let mut btf = Btf::new();
+        let name_offset = btf.add_string("int");
+        let int = btf.add_type(BtfType::Int(Int::new(
+            name_offset,
+            4,
+            IntEncoding::Signed,
+            0,
+        )));
+        let name_offset = btf.add_string("char");
+        let char_type = btf.add_type(BtfType::Int(Int::new(
+            name_offset,
+            1,
+            IntEncoding::Signed,
+            0,
+        )));
+        let const_char = btf.add_type(BtfType::Const(Const::new(char_type)));
+        let str_type = btf.add_type(BtfType::Ptr(Ptr::new(0, const_char)));
+        let names = btf.add_type(BtfType::Array(Array::new(0, str_type, int, 2)));
+        let b_ptr = btf.add_type(BtfType::Ptr(Ptr::new(0, B)));
+        let a_ptr = btf.add_type(BtfType::Ptr(Ptr::new(0, A)));
+        let params = vec![
+            BtfParam {
+                name_offset: 0,
+                btf_type: a_ptr,
+            },
+            BtfParam {
+                name_offset: btf.add_string("arg"),
+                btf_type: int,
+            },
+        ];
+        let proto = btf.add_type(BtfType::FuncProto(FuncProto::new(params, int)));
+        let cb = btf.add_type(BtfType::Ptr(Ptr::new(0, proto)));
+        let members = vec![
+            member(&mut btf, "b", b_ptr, 0),
+            member(&mut btf, "cb", cb, 64),
+        ];
+        let name_offset = btf.add_string("a");
+        let a = btf.add_type(BtfType::Struct(Struct::new(name_offset, members, 16)));
+        let members = vec![
+            member(&mut btf, "a", a, 0),
+            member(&mut btf, "next", b_ptr, 128),
+            member(&mut btf, "names", names, 192),
+        ];
+        let name_offset = btf.add_string("b");
+        let b = btf.add_type(BtfType::Struct(Struct::new(name_offset, members, 40)));
+        assert_eq!((a, b), (A, B));
+
+        assert_eq!(
+            body(&btf),
+            "struct b;
+
+struct a {
+\tstruct b *b;
+\tint (*cb)(struct a *, int arg);
+};
+
+struct b {
+\tstruct a a;
+\tstruct b *next;
+\tconst char *names[2];
+};
+
+"
+        );
+    }
+
+    #[test]
+    fn test_name_clashes() {
+        let mut btf = Btf::new();
+        let int = int(&mut btf, "int", 4, IntEncoding::Signed);
+        let members = vec![member(&mut btf, "a", int, 0)];
+        let name_offset = btf.add_string("dup");
+        let first = btf.add_type(BtfType::Struct(Struct::new(name_offset, members, 4)));
+        let members = vec![
+            member(&mut btf, "a", int, 0),
+            member(&mut btf, "b", int, 32),
+        ];
+        let second = btf.add_type(BtfType::Struct(Struct::new(name_offset, members, 8)));
+        // Typedefs live in a different namespace than struct tags.
+        let dup_t = btf.add_type(BtfType::Typedef(Typedef::new(name_offset, second)));
+        for name in ["first", "second"] {
+            let variants = vec![BtfEnum::new(btf.add_string("VAL"), 0)];
+            let name_offset = btf.add_string(name);
+            btf.add_type(BtfType::Enum(Enum::new(name_offset, false, variants)));
+        }
+        let members = vec![
+            member(&mut btf, "x", first, 0),
+            member(&mut btf, "y", second, 32),
+            member(&mut btf, "z", dup_t, 96),
+        ];
+        let name_offset = btf.add_string("user");
+        btf.add_type(BtfType::Struct(Struct::new(name_offset, members, 20)));
+
+        assert_eq!(
+            body(&btf),
+            "struct dup {
+\tint a;
+};
+
+struct dup___2 {
+\tint a;
+\tint b;
+};
+
+typedef struct dup___2 dup;
+
+enum first {
+\tVAL = 0,
+};
+
+enum second {
+\tVAL___2 = 0,
+};
+
+struct user {
+\tstruct dup x;
+\tstruct dup___2 y;
+\tdup z;
+};
+
+"
+        );
+    }
+
+    #[test]
+    fn test_packed_structs() {
+        let mut btf = Btf::new();
+        let char_type = int(&mut btf, "char", 1, IntEncoding::Signed);
+        let int = int(&mut btf, "int", 4, IntEncoding::Signed);
+        // A member that isn't naturally aligned.
+        let members = vec![
+            member(&mut btf, "c", char_type, 0),
+            member(&mut btf, "i", int, 8),
+        ];
+        let name_offset = btf.add_string("unaligned");
+        let unaligned = btf.add_type(BtfType::Struct(Struct::new(name_offset, members, 5)));
+        // A size that isn't a multiple of the alignment.
+        let members = vec![
+            member(&mut btf, "i", int, 0),
+            member(&mut btf, "c", char_type, 32),
+        ];
+        let name_offset = btf.add_string("trailing");
+        btf.add_type(BtfType::Struct(Struct::new(name_offset, members, 5)));
+        // Packed structs are byte aligned, so embedding them doesn't require packing.
+        let members = vec![
+            member(&mut btf, "c", char_type, 0),
+            member(&mut btf, "u", unaligned, 8),
+        ];
+        let name_offset = btf.add_string("outer");
+        btf.add_type(BtfType::Struct(Struct::new(name_offset, members, 6)));
+
+        assert_eq!(
+            body(&btf),
+            "struct unaligned {
+\tchar c;
+\tint i;
+} __attribute__((packed));
+
+struct trailing {
+\tint i;
+\tchar c;
+} __attribute__((packed));
+
+struct outer {
+\tchar c;
+\tstruct unaligned u;
+};
+
+"
+        );
+    }
+
+    #[test]
+    fn test_enum64() {
+        let mut btf = Btf::new();
+        let variants = vec![
+            BtfEnum64::new(btf.add_string("BIG_ONE"), 1),
+            BtfEnum64::new(btf.add_string("BIG_MAX"), u64::MAX),
+        ];
+        let name_offset = btf.add_string("big");
+        btf.add_type(BtfType::Enum64(Enum64::new(name_offset, false, variants)));
+        let variants = vec![
+            BtfEnum64::new(btf.add_string("NEG_ONE"), -1i64 as u64),
+            BtfEnum64::new(btf.add_string("NEG_HIGH"), 1 << 40),
+        ];
+        let name_offset = btf.add_string("neg");
+        btf.add_type(BtfType::Enum64(Enum64::new(name_offset, true, variants)));
+        // 8 byte enums whose values fit in 32 bits need an explicit mode.
+        let variants = vec![BtfEnum64::new(btf.add_string("SMALL"), 1)];
+        let name_offset = btf.add_string("small");
+        btf.add_type(BtfType::Enum64(Enum64::new(name_offset, false, variants)));
+
+        assert_eq!(
+            body(&btf),
+            "enum big {
+\tBIG_ONE = 1ULL,
+\tBIG_MAX = 18446744073709551615ULL,
+};
+
+enum neg {
+\tNEG_ONE = -1LL,
+\tNEG_HIGH = 1099511627776LL,
+};
+
+enum small {
+\tSMALL = 1ULL,
+} __attribute__((mode(word)));
+
+"
+        );
+    }
+
+    #[test]
+    fn test_func_protos() {
+        let mut btf = Btf::new();
+        let char_type = int(&mut btf, "char", 1, IntEncoding::Signed);
+        let int = int(&mut btf, "int", 4, IntEncoding::Signed);
+        let const_char = btf.add_type(BtfType::Const(Const::new(char_type)));
+        let str_type = btf.add_type(BtfType::Ptr(Ptr::new(0, const_char)));
+        // int (*printf_fn)(const char *fmt, ...)
+        let params = vec![param(&mut btf, "fmt", str_type), param(&mut btf, "", 0)];
+        let proto = btf.add_type(BtfType::FuncProto(FuncProto::new(params, int)));
+        let ptr = btf.add_type(BtfType::Ptr(Ptr::new(0, proto)));
+        let name_offset = btf.add_string("printf_fn");
+        btf.add_type(BtfType::Typedef(Typedef::new(name_offset, ptr)));
+        // void (*cb_fn)(void)
+        let proto = btf.add_type(BtfType::FuncProto(FuncProto::new(Vec::new(), 0)));
+        let ptr = btf.add_type(BtfType::Ptr(Ptr::new(0, proto)));
+        let name_offset = btf.add_string("cb_fn");
+        btf.add_type(BtfType::Typedef(Typedef::new(name_offset, ptr)));
+        // A function returning a pointer to an array of function pointers.
+        let array = btf.add_type(BtfType::Array(Array::new(0, ptr, int, 4)));
+        let array_ptr = btf.add_type(BtfType::Ptr(Ptr::new(0, array)));
+        let params = vec![param(&mut btf, "n", int)];
+        let proto = btf.add_type(BtfType::FuncProto(FuncProto::new(params, array_ptr)));
+        let name_offset = btf.add_string("table_fn");
+        btf.add_type(BtfType::Typedef(Typedef::new(name_offset, proto)));
+
+        assert_eq!(
+            body(&btf),
+            "typedef int (*printf_fn)(const char *fmt, ...);
+
+typedef void (*cb_fn)(void);
+
+typedef void (*(*table_fn(int n))[4])(void);
+
+"
+        );
+    }
+
+    #[test]
+    fn test_typedef_cycles() {
+        // typedef struct node node_t; struct node { node_t *next; int value; };
+        // typedef struct a a_t; typedef struct b b_t;
+        // struct a { b_t *b; }; struct b { a_t *a; struct a embedded; };
+        const NODE: u32 = 4;
+        const A: u32 = 8;
+        const B: u32 = 10;
+        let mut btf = Btf::new();
+        let int = int(&mut btf, "int", 4, IntEncoding::Signed);
+        let name_offset = btf.add_string("node_t");
+        let node_t = btf.add_type(BtfType::Typedef(Typedef::new(name_offset, NODE)));
+        let node_ptr = btf.add_type(BtfType::Ptr(Ptr::new(0, node_t)));
+        let members = vec![
+            member(&mut btf, "next", node_ptr, 0),
+            member(&mut btf, "value", int, 64),
+        ];
+        let name_offset = btf.add_string("node");
+        let node = btf.add_type(BtfType::Struct(Struct::new(name_offset, members, 16)));
+        let name_offset = btf.add_string("a_t");
+        let a_t = btf.add_type(BtfType::Typedef(Typedef::new(name_offset, A)));
+        let name_offset = btf.add_string("b_t");
+        let b_t = btf.add_type(BtfType::Typedef(Typedef::new(name_offset, B)));
+        let b_ptr = btf.add_type(BtfType::Ptr(Ptr::new(0, b_t)));
+        let members = vec![member(&mut btf, "b", b_ptr, 0)];
+        let name_offset = btf.add_string("a");
+        let a = btf.add_type(BtfType::Struct(Struct::new(name_offset, members, 8)));
+        let a_ptr = btf.add_type(BtfType::Ptr(Ptr::new(0, a_t)));
+        let members = vec![
+            member(&mut btf, "a", a_ptr, 0),
+            member(&mut btf, "embedded", a, 64),
+        ];
+        let name_offset = btf.add_string("b");
+        let b = btf.add_type(BtfType::Struct(Struct::new(name_offset, members, 16)));
+        assert_eq!((node, a, b), (NODE, A, B));
+
+        assert_eq!(
+            body(&btf),
+            "struct node;
+
+typedef struct node node_t;
+
+struct node {
+\tnode_t *next;
+\tint value;
+};
+
+struct b;
+
+typedef struct b b_t;
+
+struct a {
+\tb_t *b;
+};
+
+typedef struct a a_t;
+
+struct b {
+\ta_t *a;
+\tstruct a embedded;
+};
+
+"
+        );
+    }
+
+    #[test]
+    fn test_bitfield_padding() {
+        let mut btf = Btf::new();
+        let uchar = int(&mut btf, "unsigned char", 1, IntEncoding::None);
+        let ulong = int(&mut btf, "unsigned long", 8, IntEncoding::None);
+        let uint = int(&mut btf, "unsigned int", 4, IntEncoding::None);
+        let members = vec![
+            member(&mut btf, "a", uchar, 1 << 24),
+            member(&mut btf, "b", uchar, (2 << 24) | 4),
+            member(&mut btf, "c", ulong, (33 << 24) | 64),
+            member(&mut btf, "d", uint, 128),
+            member(&mut btf, "e", uint, (3 << 24) + 168),
+        ];
+        let name_offset = btf.add_string("bits");
+        let mut bits = Struct::new(name_offset, members, 24);
+        bits.info |= 1 << 31;
+        btf.add_type(BtfType::Struct(bits));
+
+        assert_eq!(
+            body(&btf),
+            "struct bits {
+\tunsigned char a: 1;
+\tchar: 3;
+\tunsigned char b: 2;
+\tlong: 58;
+\tunsigned long c: 33;
+\tunsigned int d;
+\tchar: 8;
+\tunsigned int e: 3;
+};
+
+"
+        );
+    }
+}
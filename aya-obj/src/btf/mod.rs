@@ -2,12 +2,15 @@
 
 #[expect(clippy::module_inception, reason = "TODO")]
 mod btf;
+mod c_header;
 mod extern_types;
 mod info;
 mod relocation;
 mod types;
+mod value;
 
 pub use btf::*;
 pub use info::*;
 pub use relocation::BtfRelocationError;
 pub use types::*;
+pub use value::*;
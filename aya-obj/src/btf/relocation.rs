@@ -213,6 +213,17 @@ impl Relocation {
             number,
         })
     }
+
+    /// Rewrites the type id and access string offset, e.g. when moving the relocation to another
+    /// [`Btf`].
+    pub(crate) fn remap(
+        &mut self,
+        types: &mut impl FnMut(u32) -> u32,
+        strings: &mut impl FnMut(u32) -> u32,
+    ) {
+        self.type_id = types(self.type_id);
+        self.access_str_offset = strings(self.access_str_offset);
+    }
 }
 
 impl Object {
@@ -51,6 +51,24 @@ impl Fwd {
     pub(crate) const fn type_info_size(&self) -> usize {
         size_of::<Self>()
     }
+
+    /// Returns true if this declares a union rather than a struct.
+    pub(crate) const fn is_union(&self) -> bool {
+        self.info >> 31 == 1
+    }
+
+    #[cfg(test)]
+    pub(crate) const fn new(name_offset: u32, union: bool) -> Self {
+        let mut info = (BtfKind::Fwd as u32) << 24;
+        if union {
+            info |= 1 << 31;
+        }
+        Self {
+            name_offset,
+            info,
+            _unused: 0,
+        }
+    }
 }
 
 #[repr(C)]
@@ -576,7 +594,7 @@ pub(crate) struct BtfMember {
 #[derive(Clone, Debug)]
 pub struct Struct {
     pub(crate) name_offset: u32,
-    info: u32,
+    pub(crate) info: u32,
     pub(crate) size: u32,
     pub(crate) members: Vec<BtfMember>,
 }
@@ -1393,6 +1411,123 @@ impl BtfType {
         }
     }
 
+    /// Rewrites the type ids and string offsets referenced by this type, e.g. when moving it to
+    /// another [`Btf`].
+    pub(crate) fn remap(
+        &mut self,
+        types: &mut impl FnMut(u32) -> u32,
+        strings: &mut impl FnMut(u32) -> u32,
+    ) {
+        match self {
+            Self::Unknown => {}
+            Self::Fwd(Fwd { name_offset, .. })
+            | Self::Int(Int { name_offset, .. })
+            | Self::Float(Float { name_offset, .. }) => *name_offset = strings(*name_offset),
+            Self::Const(Const {
+                name_offset,
+                btf_type,
+                ..
+            })
+            | Self::Volatile(Volatile {
+                name_offset,
+                btf_type,
+                ..
+            })
+            | Self::Restrict(Restrict {
+                name_offset,
+                btf_type,
+                ..
+            })
+            | Self::Ptr(Ptr {
+                name_offset,
+                btf_type,
+                ..
+            })
+            | Self::Typedef(Typedef {
+                name_offset,
+                btf_type,
+                ..
+            })
+            | Self::Func(Func {
+                name_offset,
+                btf_type,
+                ..
+            })
+            | Self::Var(Var {
+                name_offset,
+                btf_type,
+                ..
+            })
+            | Self::DeclTag(DeclTag {
+                name_offset,
+                btf_type,
+                ..
+            })
+            | Self::TypeTag(TypeTag {
+                name_offset,
+                btf_type,
+                ..
+            }) => {
+                *name_offset = strings(*name_offset);
+                *btf_type = types(*btf_type);
+            }
+            Self::Enum(t) => {
+                t.name_offset = strings(t.name_offset);
+                for variant in &mut t.variants {
+                    variant.name_offset = strings(variant.name_offset);
+                }
+            }
+            Self::Enum64(t) => {
+                t.name_offset = strings(t.name_offset);
+                for variant in &mut t.variants {
+                    variant.name_offset = strings(variant.name_offset);
+                }
+            }
+            Self::Array(t) => {
+                t.name_offset = strings(t.name_offset);
+                t.array.element_type = types(t.array.element_type);
+                t.array.index_type = types(t.array.index_type);
+            }
+            Self::Struct(Struct {
+                name_offset,
+                members,
+                ..
+            }) => {
+                *name_offset = strings(*name_offset);
+                for member in members {
+                    member.name_offset = strings(member.name_offset);
+                    member.btf_type = types(member.btf_type);
+                }
+            }
+            Self::Union(t) => {
+                t.name_offset = strings(t.name_offset);
+                for member in &mut t.members {
+                    member.name_offset = strings(member.name_offset);
+                    member.btf_type = types(member.btf_type);
+                }
+                if let Some(fallback) = &mut t.enum64_fallback {
+                    for variant in &mut fallback.variants {
+                        variant.name_offset = strings(variant.name_offset);
+                    }
+                }
+            }
+            Self::FuncProto(t) => {
+                t.name_offset = strings(t.name_offset);
+                t.return_type = types(t.return_type);
+                for param in &mut t.params {
+                    param.name_offset = strings(param.name_offset);
+                    param.btf_type = types(param.btf_type);
+                }
+            }
+            Self::DataSec(t) => {
+                t.name_offset = strings(t.name_offset);
+                for entry in &mut t.entries {
+                    entry.btf_type = types(entry.btf_type);
+                }
+            }
+        }
+    }
+
     pub(crate) const fn kind(&self) -> BtfKind {
         match self {
             Self::Unknown => BtfKind::Unknown,
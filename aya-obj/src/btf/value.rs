@@ -0,0 +1,943 @@
+//! Decoding and encoding of raw bytes as typed values using BTF.
+
+use std::{
+    borrow::{Cow, ToOwned as _},
+    fmt,
+    num::TryFromIntError,
+};
+
+use crate::btf::{
+    Array, Btf, BtfError, BtfMember, BtfType, Const, DataSec, Enum, Enum64, Float, Int,
+    IntEncoding, Ptr, Restrict, Struct, TypeTag, Typedef, Union, Var, Volatile,
+    btf::MAX_RESOLVE_DEPTH,
+};
+
+/// A value decoded from raw bytes according to its BTF type.
+///
+/// Values are produced by [`Btf::decode_value`] and turned back into bytes by
+/// [`Btf::encode_value`]. The [`Display`](fmt::Display) implementation renders them with C
+/// initializer syntax, e.g. `{.pid = 42, .comm = "bash"}`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BtfValue {
+    /// A boolean.
+    Bool(bool),
+    /// A signed integer.
+    Int(i128),
+    /// An unsigned integer.
+    Uint(u128),
+    /// A floating point number.
+    Float(f64),
+    /// An enumeration value.
+    Enum {
+        /// The name of the enumerator matching `value`, if any.
+        name: Option<String>,
+        /// The numeric value.
+        value: i128,
+    },
+    /// A pointer, represented by its address.
+    Pointer(u64),
+    /// A NUL-terminated `char` array.
+    String(String),
+    /// An array.
+    Array(Vec<Self>),
+    /// A struct or a data section.
+    Struct(Vec<BtfField>),
+    /// A union, with every member decoded.
+    Union(Vec<BtfField>),
+    /// Bytes whose type is unknown or has no value representation, such as `void`.
+    Bytes(Vec<u8>),
+}
+
+impl BtfValue {
+    /// Returns the member `name` of a struct or union value.
+    pub fn field(&self, name: &str) -> Option<&Self> {
+        match self {
+            Self::Struct(fields) | Self::Union(fields) => fields
+                .iter()
+                .find(|field| field.name == name)
+                .map(|field| &field.value),
+            _ => None,
+        }
+    }
+
+    /// Returns a mutable reference to the member `name` of a struct or union value.
+    pub fn field_mut(&mut self, name: &str) -> Option<&mut Self> {
+        match self {
+            Self::Struct(fields) | Self::Union(fields) => fields
+                .iter_mut()
+                .find(|field| field.name == name)
+                .map(|field| &mut field.value),
+            _ => None,
+        }
+    }
+}
+
+/// A named member of a [`BtfValue::Struct`] or [`BtfValue::Union`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct BtfField {
+    /// The name of the member.
+    pub name: String,
+    /// The value of the member.
+    pub value: BtfValue,
+}
+
+impl Btf {
+    /// Decodes `data` as a value of the type `type_id`.
+    ///
+    /// `data` must be in host byte order, which is how the kernel returns map keys and values.
+    /// Bytes past the end of the type are ignored.
+    pub fn decode_value(&self, type_id: u32, data: &[u8]) -> Result<BtfValue, BtfError> {
+        self.decode(type_id, data, MAX_RESOLVE_DEPTH)
+    }
+
+    /// Encodes `value` as the type `type_id`, the inverse of [`Btf::decode_value`].
+    ///
+    /// The result is in host byte order and as long as the type. Struct and union members are
+    /// matched by name; members missing from `value` as well as padding are zeroed. Enum values
+    /// are looked up by name when one is given. [`BtfValue::Bytes`] is accepted for any type
+    /// and is copied verbatim, zero-extended to the size of the type.
+    pub fn encode_value(&self, type_id: u32, value: &BtfValue) -> Result<Vec<u8>, BtfError> {
+        let mut data = vec![0; self.type_size(type_id)?];
+        self.encode(type_id, value, &mut data, MAX_RESOLVE_DEPTH)?;
+        Ok(data)
+    }
+
+    fn decode(&self, type_id: u32, data: &[u8], depth: usize) -> Result<BtfValue, BtfError> {
+        let Some(depth) = depth.checked_sub(1) else {
+            return Err(BtfError::MaximumTypeDepthReached { type_id });
+        };
+        let bytes = |size: usize| {
+            data.get(..size).ok_or(BtfError::ValueTooShort {
+                type_id,
+                len: data.len(),
+            })
+        };
+        Ok(match self.type_by_id(type_id)? {
+            BtfType::Int(int) => {
+                let size = int.size as usize;
+                int_value(int, read_uint(bytes(size)?), size * 8)
+            }
+            BtfType::Float(Float { size, .. }) => {
+                let data = bytes(*size as usize)?;
+                if let Ok(data) = <[u8; 4]>::try_from(data) {
+                    BtfValue::Float(f32::from_ne_bytes(data).into())
+                } else if let Ok(data) = <[u8; 8]>::try_from(data) {
+                    BtfValue::Float(f64::from_ne_bytes(data))
+                } else {
+                    BtfValue::Bytes(data.to_vec())
+                }
+            }
+            BtfType::Enum(Enum { size, .. }) | BtfType::Enum64(Enum64 { size, .. }) => {
+                let size = *size as usize;
+                self.enum_value(type_id, read_uint(bytes(size)?), size * 8)?
+            }
+            BtfType::Ptr(Ptr { .. }) => {
+                BtfValue::Pointer(read_uint(bytes(size_of::<u64>())?) as u64)
+            }
+            BtfType::Array(Array { array, .. }) => {
+                let element_size = self.type_size(array.element_type)?;
+                let data = bytes(element_size * array.len as usize)?;
+                if let Some(s) = self.is_char(array.element_type)?.then(|| c_string(data)) {
+                    s
+                } else if element_size == 0 {
+                    BtfValue::Array(Vec::new())
+                } else {
+                    BtfValue::Array(
+                        data.chunks_exact(element_size)
+                            .map(|element| self.decode(array.element_type, element, depth))
+                            .collect::<Result<_, _>>()?,
+                    )
+                }
+            }
+            BtfType::Struct(s) => {
+                let data = bytes(s.size as usize)?;
+                BtfValue::Struct(self.decode_members(
+                    &s.members,
+                    |m| (s.member_bit_offset(m), s.member_bit_field_size(m)),
+                    data,
+                    depth,
+                )?)
+            }
+            BtfType::Union(u) => {
+                let data = bytes(u.size as usize)?;
+                BtfValue::Union(self.decode_members(
+                    &u.members,
+                    |m| (u.member_bit_offset(m), u.member_bit_field_size(m)),
+                    data,
+                    depth,
+                )?)
+            }
+            BtfType::DataSec(DataSec { entries, .. }) => BtfValue::Struct(
+                entries
+                    .iter()
+                    .map(|entry| {
+                        let ty = self.type_by_id(entry.btf_type)?;
+                        let BtfType::Var(Var { btf_type, .. }) = ty else {
+                            return Err(BtfError::InvalidDatasec);
+                        };
+                        let start = entry.offset as usize;
+                        let data = data.get(start..).ok_or(BtfError::ValueTooShort {
+                            type_id,
+                            len: data.len(),
+                        })?;
+                        Ok(BtfField {
+                            name: self.type_name(ty)?.into_owned(),
+                            value: self.decode(*btf_type, data, depth)?,
+                        })
+                    })
+                    .collect::<Result<_, _>>()?,
+            ),
+            BtfType::Const(Const { btf_type, .. })
+            | BtfType::Volatile(Volatile { btf_type, .. })
+            | BtfType::Restrict(Restrict { btf_type, .. })
+            | BtfType::Typedef(Typedef { btf_type, .. })
+            | BtfType::TypeTag(TypeTag { btf_type, .. })
+            | BtfType::Var(Var { btf_type, .. }) => self.decode(*btf_type, data, depth)?,
+            BtfType::DeclTag(tag) => self.decode(tag.btf_type, data, depth)?,
+            BtfType::Unknown | BtfType::Fwd(_) | BtfType::Func(_) | BtfType::FuncProto(_) => {
+                BtfValue::Bytes(data.to_vec())
+            }
+        })
+    }
+
+    fn decode_members(
+        &self,
+        members: &[BtfMember],
+        layout: impl Fn(&BtfMember) -> (usize, usize),
+        data: &[u8],
+        depth: usize,
+    ) -> Result<Vec<BtfField>, BtfError> {
+        let mut fields = Vec::with_capacity(members.len());
+        for member in members {
+            let name = self.string_at(member.name_offset)?.into_owned();
+            let (bit_offset, bit_size) = layout(member);
+            let value = if bit_size == 0 && bit_offset % 8 == 0 {
+                let data = data.get(bit_offset / 8..).ok_or(BtfError::ValueTooShort {
+                    type_id: member.btf_type,
+                    len: data.len(),
+                })?;
+                self.decode(member.btf_type, data, depth)?
+            } else {
+                let bit_size = match bit_size {
+                    0 => self.type_size(member.btf_type)? * 8,
+                    bit_size => bit_size,
+                };
+                self.decode_bitfield(member.btf_type, data, bit_offset, bit_size)?
+            };
+            match value {
+                // Members of anonymous structs and unions are accessed as if they were members
+                // of the enclosing type.
+                BtfValue::Struct(inner) | BtfValue::Union(inner) if name.is_empty() => {
+                    fields.extend(inner);
+                }
+                value => fields.push(BtfField { name, value }),
+            }
+        }
+        Ok(fields)
+    }
+
+    fn decode_bitfield(
+        &self,
+        type_id: u32,
+        data: &[u8],
+        bit_offset: usize,
+        bit_size: usize,
+    ) -> Result<BtfValue, BtfError> {
+        let start = bit_offset / 8;
+        let end = (bit_offset + bit_size).div_ceil(8);
+        let bytes = data.get(start..end).ok_or(BtfError::ValueTooShort {
+            type_id,
+            len: data.len(),
+        })?;
+        let shift = if cfg!(target_endian = "little") {
+            bit_offset % 8
+        } else {
+            bytes.len() * 8 - bit_offset % 8 - bit_size
+        };
+        let mut raw = read_uint(bytes) >> shift;
+        if bit_size < 128 {
+            raw &= (1 << bit_size) - 1;
+        }
+
+        let type_id = self.resolve_type(type_id)?;
+        Ok(match self.type_by_id(type_id)? {
+            BtfType::Int(int) => int_value(int, raw, bit_size),
+            BtfType::Enum(_) | BtfType::Enum64(_) => self.enum_value(type_id, raw, bit_size)?,
+            _ => BtfValue::Uint(raw),
+        })
+    }
+
+    fn enum_value(&self, type_id: u32, raw: u128, bits: usize) -> Result<BtfValue, BtfError> {
+        let (signed, variants) = self.enum_variants(type_id)?;
+        let value = if signed {
+            sign_extend(raw, bits)
+        } else {
+            raw as i128
+        };
+        let name = variants
+            .iter()
+            .find(|(_, v)| *v == value)
+            .map(|(name_offset, _)| self.string_at(*name_offset).map(Cow::into_owned))
+            .transpose()?;
+        Ok(BtfValue::Enum { name, value })
+    }
+
+    /// Returns the signedness of an enum and the name offsets and values of its variants.
+    fn enum_variants(&self, type_id: u32) -> Result<(bool, Vec<(u32, i128)>), BtfError> {
+        Ok(match self.type_by_id(type_id)? {
+            BtfType::Enum(e) => (
+                e.is_signed(),
+                e.variants
+                    .iter()
+                    .map(|v| {
+                        let value = if e.is_signed() {
+                            i128::from(v.value as i32)
+                        } else {
+                            i128::from(v.value)
+                        };
+                        (v.name_offset, value)
+                    })
+                    .collect(),
+            ),
+            BtfType::Enum64(e) => (
+                e.is_signed(),
+                e.variants
+                    .iter()
+                    .map(|v| {
+                        let value = (u64::from(v.value_high) << 32) | u64::from(v.value_low);
+                        let value = if e.is_signed() {
+                            i128::from(value as i64)
+                        } else {
+                            i128::from(value)
+                        };
+                        (v.name_offset, value)
+                    })
+                    .collect(),
+            ),
+            _ => return Err(BtfError::UnexpectedBtfType { type_id }),
+        })
+    }
+
+    /// Returns whether `type_id` is a (possibly qualified) `char`.
+    ///
+    /// Typedefs are deliberately not followed so that arrays of `__u8` are rendered as numbers.
+    fn is_char(&self, mut type_id: u32) -> Result<bool, BtfError> {
+        for () in core::iter::repeat_n((), MAX_RESOLVE_DEPTH) {
+            match self.type_by_id(type_id)? {
+                BtfType::Const(Const { btf_type, .. })
+                | BtfType::Volatile(Volatile { btf_type, .. })
+                | BtfType::TypeTag(TypeTag { btf_type, .. }) => type_id = *btf_type,
+                ty @ BtfType::Int(int) => {
+                    return Ok(int.size == 1
+                        && (int.encoding() == IntEncoding::Char || self.type_name(ty)? == "char"));
+                }
+                _ => return Ok(false),
+            }
+        }
+        Err(BtfError::MaximumTypeDepthReached { type_id })
+    }
+
+    fn encode(
+        &self,
+        type_id: u32,
+        value: &BtfValue,
+        data: &mut [u8],
+        depth: usize,
+    ) -> Result<(), BtfError> {
+        let Some(depth) = depth.checked_sub(1) else {
+            return Err(BtfError::MaximumTypeDepthReached { type_id });
+        };
+        let mismatch = || BtfError::ValueTypeMismatch { type_id };
+        if let BtfValue::Bytes(value) = value {
+            let size = match self.type_size(type_id) {
+                Ok(size) => size,
+                // Types without a size, such as `void`, take whatever space is left.
+                Err(BtfError::UnexpectedBtfType { .. }) => data.len(),
+                Err(e) => return Err(e),
+            };
+            prefix_mut(type_id, data, size)?
+                .get_mut(..value.len())
+                .ok_or(BtfError::ValueOutOfRange { type_id })?
+                .copy_from_slice(value);
+            return Ok(());
+        }
+        match self.type_by_id(type_id)? {
+            BtfType::Int(int) => {
+                let size = int.size as usize;
+                let raw = int_raw(
+                    type_id,
+                    int.encoding() == IntEncoding::Signed,
+                    value,
+                    size * 8,
+                )?;
+                write_uint(prefix_mut(type_id, data, size)?, raw);
+            }
+            BtfType::Float(Float { size, .. }) => {
+                let &BtfValue::Float(x) = value else {
+                    return Err(mismatch());
+                };
+                match size {
+                    4 => prefix_mut(type_id, data, 4)?.copy_from_slice(&(x as f32).to_ne_bytes()),
+                    8 => prefix_mut(type_id, data, 8)?.copy_from_slice(&x.to_ne_bytes()),
+                    _ => return Err(mismatch()),
+                }
+            }
+            BtfType::Enum(Enum { size, .. }) | BtfType::Enum64(Enum64 { size, .. }) => {
+                let size = *size as usize;
+                let raw = self.enum_raw(type_id, value, size * 8)?;
+                write_uint(prefix_mut(type_id, data, size)?, raw);
+            }
+            BtfType::Ptr(Ptr { .. }) => {
+                let address = match *value {
+                    BtfValue::Pointer(address) => address,
+                    BtfValue::Uint(address) => u64::try_from(address)
+                        .map_err(|TryFromIntError { .. }| BtfError::ValueOutOfRange { type_id })?,
+                    _ => return Err(mismatch()),
+                };
+                write_uint(prefix_mut(type_id, data, size_of::<u64>())?, address.into());
+            }
+            BtfType::Array(Array { array, .. }) => {
+                let element_size = self.type_size(array.element_type)?;
+                let len = array.len as usize;
+                let data = prefix_mut(type_id, data, element_size * len)?;
+                match value {
+                    BtfValue::String(s) if self.is_char(array.element_type)? => data
+                        .get_mut(..s.len())
+                        .ok_or(BtfError::ValueOutOfRange { type_id })?
+                        .copy_from_slice(s.as_bytes()),
+                    BtfValue::Array(items) => {
+                        if items.len() > len {
+                            return Err(BtfError::ValueOutOfRange { type_id });
+                        }
+                        if element_size != 0 {
+                            for (item, data) in
+                                items.iter().zip(data.chunks_exact_mut(element_size))
+                            {
+                                self.encode(array.element_type, item, data, depth)?;
+                            }
+                        }
+                    }
+                    _ => return Err(mismatch()),
+                }
+            }
+            BtfType::Struct(Struct { size, .. }) | BtfType::Union(Union { size, .. }) => {
+                let (BtfValue::Struct(fields) | BtfValue::Union(fields)) = value else {
+                    return Err(mismatch());
+                };
+                let data = prefix_mut(type_id, data, *size as usize)?;
+                for BtfField { name, value } in fields {
+                    let (member_type, bit_offset, bit_size) = self
+                        .find_member(type_id, name, depth)?
+                        .ok_or_else(|| BtfError::UnknownField {
+                            type_id,
+                            name: name.clone(),
+                        })?;
+                    if bit_size == 0 && bit_offset % 8 == 0 {
+                        let len = data.len();
+                        let data =
+                            data.get_mut(bit_offset / 8..)
+                                .ok_or(BtfError::ValueTooShort {
+                                    type_id: member_type,
+                                    len,
+                                })?;
+                        self.encode(member_type, value, data, depth)?;
+                    } else {
+                        let bit_size = match bit_size {
+                            0 => self.type_size(member_type)? * 8,
+                            bit_size => bit_size,
+                        };
+                        self.encode_bitfield(member_type, value, data, bit_offset, bit_size)?;
+                    }
+                }
+            }
+            BtfType::DataSec(DataSec { entries, size, .. }) => {
+                let BtfValue::Struct(fields) = value else {
+                    return Err(mismatch());
+                };
+                let data = prefix_mut(type_id, data, *size as usize)?;
+                for BtfField { name, value } in fields {
+                    let mut found = None;
+                    for entry in entries {
+                        let ty = self.type_by_id(entry.btf_type)?;
+                        let BtfType::Var(Var { btf_type, .. }) = ty else {
+                            return Err(BtfError::InvalidDatasec);
+                        };
+                        if self.type_name(ty)? == name.as_str() {
+                            found = Some((*btf_type, entry.offset as usize));
+                            break;
+                        }
+                    }
+                    let (btf_type, offset) = found.ok_or_else(|| BtfError::UnknownField {
+                        type_id,
+                        name: name.clone(),
+                    })?;
+                    let len = data.len();
+                    let data = data
+                        .get_mut(offset..)
+                        .ok_or(BtfError::ValueTooShort { type_id, len })?;
+                    self.encode(btf_type, value, data, depth)?;
+                }
+            }
+            BtfType::Const(Const { btf_type, .. })
+            | BtfType::Volatile(Volatile { btf_type, .. })
+            | BtfType::Restrict(Restrict { btf_type, .. })
+            | BtfType::Typedef(Typedef { btf_type, .. })
+            | BtfType::TypeTag(TypeTag { btf_type, .. })
+            | BtfType::Var(Var { btf_type, .. }) => self.encode(*btf_type, value, data, depth)?,
+            BtfType::DeclTag(tag) => self.encode(tag.btf_type, value, data, depth)?,
+            BtfType::Unknown | BtfType::Fwd(_) | BtfType::Func(_) | BtfType::FuncProto(_) => {
+                return Err(mismatch());
+            }
+        }
+        Ok(())
+    }
+
+    /// Finds the member `name` of a struct or union, looking into anonymous members the same way
+    /// [`Btf::decode_value`] flattens them.
+    ///
+    /// Returns the type, bit offset and bitfield size of the member.
+    fn find_member(
+        &self,
+        type_id: u32,
+        name: &str,
+        depth: usize,
+    ) -> Result<Option<(u32, usize, usize)>, BtfError> {
+        let Some(depth) = depth.checked_sub(1) else {
+            return Err(BtfError::MaximumTypeDepthReached { type_id });
+        };
+        let ty = self.type_by_id(type_id)?;
+        let (BtfType::Struct(Struct { members, .. }) | BtfType::Union(Union { members, .. })) = ty
+        else {
+            return Ok(None);
+        };
+        for member in members {
+            let (Some(bit_offset), Some(bit_size)) = (
+                ty.member_bit_offset(member),
+                ty.member_bit_field_size(member),
+            ) else {
+                return Ok(None);
+            };
+            let member_name = self.string_at(member.name_offset)?;
+            if member_name == name {
+                return Ok(Some((member.btf_type, bit_offset, bit_size)));
+            }
+            if member_name.is_empty() {
+                let inner = self.resolve_type(member.btf_type)?;
+                if let Some((btf_type, offset, bit_size)) = self.find_member(inner, name, depth)? {
+                    return Ok(Some((btf_type, bit_offset + offset, bit_size)));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    fn encode_bitfield(
+        &self,
+        type_id: u32,
+        value: &BtfValue,
+        data: &mut [u8],
+        bit_offset: usize,
+        bit_size: usize,
+    ) -> Result<(), BtfError> {
+        let start = bit_offset / 8;
+        let end = (bit_offset + bit_size).div_ceil(8);
+        let len = data.len();
+        let bytes = data
+            .get_mut(start..end)
+            .ok_or(BtfError::ValueTooShort { type_id, len })?;
+        let shift = if cfg!(target_endian = "little") {
+            bit_offset % 8
+        } else {
+            bytes.len() * 8 - bit_offset % 8 - bit_size
+        };
+
+        let type_id = self.resolve_type(type_id)?;
+        let raw = match self.type_by_id(type_id)? {
+            BtfType::Int(int) => int_raw(
+                type_id,
+                int.encoding() == IntEncoding::Signed,
+                value,
+                bit_size,
+            )?,
+            BtfType::Enum(_) | BtfType::Enum64(_) => self.enum_raw(type_id, value, bit_size)?,
+            _ => int_raw(type_id, false, value, bit_size)?,
+        };
+        let mask = if bit_size < 128 {
+            (1 << bit_size) - 1
+        } else {
+            u128::MAX
+        };
+        let word = (read_uint(bytes) & !(mask << shift)) | (raw << shift);
+        write_uint(bytes, word);
+        Ok(())
+    }
+
+    fn enum_raw(&self, type_id: u32, value: &BtfValue, bits: usize) -> Result<u128, BtfError> {
+        let (signed, variants) = self.enum_variants(type_id)?;
+        let value = match value {
+            BtfValue::Enum {
+                name: Some(name), ..
+            } => {
+                let mut found = None;
+                for (name_offset, value) in variants {
+                    if self.string_at(name_offset)? == name.as_str() {
+                        found = Some(value);
+                        break;
+                    }
+                }
+                found.ok_or_else(|| BtfError::UnknownField {
+                    type_id,
+                    name: name.clone(),
+                })?
+            }
+            BtfValue::Enum { name: None, value } => *value,
+            value => return int_raw(type_id, signed, value, bits),
+        };
+        int_raw(type_id, signed, &BtfValue::Int(value), bits)
+    }
+}
+
+/// Returns the first `size` bytes of `data`, which holds a value of the type `type_id`.
+fn prefix_mut(type_id: u32, data: &mut [u8], size: usize) -> Result<&mut [u8], BtfError> {
+    let len = data.len();
+    data.get_mut(..size)
+        .ok_or(BtfError::ValueTooShort { type_id, len })
+}
+
+/// Converts an integer value to its raw representation in `bits` bits.
+///
+/// Negative values are accepted for unsigned types as long as they fit in two's complement, which
+/// is how [`Btf::decode_value`] represents `char` arrays that aren't valid UTF-8.
+fn int_raw(type_id: u32, signed: bool, value: &BtfValue, bits: usize) -> Result<u128, BtfError> {
+    // Whether `u` is below `2^bits`.
+    let fits = |u: u128, bits: usize| u.checked_shr(bits as u32).is_none_or(|high| high == 0);
+    let magnitude_bits = if signed { bits - 1 } else { bits };
+    let (raw, in_range) = match *value {
+        BtfValue::Bool(b) => (u128::from(b), true),
+        BtfValue::Uint(u) => (u, fits(u, magnitude_bits)),
+        BtfValue::Int(i) if i < 0 => (i as u128, fits(!(i as u128), bits - 1)),
+        BtfValue::Int(i) => (i as u128, fits(i as u128, magnitude_bits)),
+        _ => return Err(BtfError::ValueTypeMismatch { type_id }),
+    };
+    if !in_range {
+        return Err(BtfError::ValueOutOfRange { type_id });
+    }
+    Ok(if bits < 128 {
+        raw & ((1 << bits) - 1)
+    } else {
+        raw
+    })
+}
+
+fn int_value(int: &Int, raw: u128, bits: usize) -> BtfValue {
+    match int.encoding() {
+        IntEncoding::Bool => BtfValue::Bool(raw != 0),
+        IntEncoding::Signed => BtfValue::Int(sign_extend(raw, bits)),
+        IntEncoding::None | IntEncoding::Char | IntEncoding::Unknown => BtfValue::Uint(raw),
+    }
+}
+
+/// Reads up to 16 bytes as an unsigned integer in host byte order.
+fn read_uint(bytes: &[u8]) -> u128 {
+    let mut buf = [0u8; size_of::<u128>()];
+    let len = bytes.len().min(buf.len());
+    if cfg!(target_endian = "little") {
+        buf[..len].copy_from_slice(&bytes[..len]);
+    } else {
+        buf[size_of::<u128>() - len..].copy_from_slice(&bytes[..len]);
+    }
+    u128::from_ne_bytes(buf)
+}
+
+/// Writes the low bytes of `raw` in host byte order, the inverse of [`read_uint`].
+fn write_uint(bytes: &mut [u8], raw: u128) {
+    let buf = raw.to_ne_bytes();
+    let len = bytes.len().min(buf.len());
+    if cfg!(target_endian = "little") {
+        bytes[..len].copy_from_slice(&buf[..len]);
+    } else {
+        bytes[..len].copy_from_slice(&buf[size_of::<u128>() - len..]);
+    }
+}
+
+const fn sign_extend(raw: u128, bits: usize) -> i128 {
+    if bits == 0 || bits >= 128 {
+        return raw as i128;
+    }
+    let shift = 128 - bits as u32;
+    ((raw << shift) as i128) >> shift
+}
+
+/// Decodes a `char` array as a string, falling back to numbers if it isn't valid UTF-8.
+fn c_string(data: &[u8]) -> BtfValue {
+    let len = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+    match std::str::from_utf8(&data[..len]) {
+        Ok(s) => BtfValue::String(s.to_owned()),
+        Err(std::str::Utf8Error { .. }) => BtfValue::Array(
+            data.iter()
+                .map(|&b| BtfValue::Int(i128::from(b as i8)))
+                .collect(),
+        ),
+    }
+}
+
+impl fmt::Display for BtfValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn list<T>(
+            f: &mut fmt::Formatter<'_>,
+            (open, close): (&str, &str),
+            items: &[T],
+            mut item: impl FnMut(&mut fmt::Formatter<'_>, &T) -> fmt::Result,
+        ) -> fmt::Result {
+            f.write_str(open)?;
+            for (i, it) in items.iter().enumerate() {
+                if i > 0 {
+                    f.write_str(", ")?;
+                }
+                item(f, it)?;
+            }
+            f.write_str(close)
+        }
+
+        let field = |f: &mut fmt::Formatter<'_>, BtfField { name, value }: &BtfField| {
+            write!(f, ".{name} = {value}")
+        };
+        match self {
+            Self::Bool(b) => write!(f, "{b}"),
+            Self::Int(i) => write!(f, "{i}"),
+            Self::Uint(u) => write!(f, "{u}"),
+            Self::Float(x) => write!(f, "{x}"),
+            Self::Enum { name, value } => match name {
+                Some(name) => f.write_str(name),
+                None => write!(f, "{value}"),
+            },
+            Self::Pointer(p) => write!(f, "{p:#x}"),
+            Self::String(s) => write!(f, "\"{}\"", s.escape_default()),
+            Self::Array(items) => list(f, ("[", "]"), items, |f, v| write!(f, "{v}")),
+            Self::Struct(fields) | Self::Union(fields) => list(f, ("{", "}"), fields, field),
+            Self::Bytes(bytes) => list(f, ("[", "]"), bytes, |f, b| write!(f, "{b:#04x}")),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for BtfValue {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap as _;
+
+        match self {
+            Self::Bool(b) => serializer.serialize_bool(*b),
+            Self::Int(i) => serializer.serialize_i128(*i),
+            Self::Uint(u) => serializer.serialize_u128(*u),
+            Self::Float(x) => serializer.serialize_f64(*x),
+            Self::Enum { name, value } => match name {
+                Some(name) => serializer.serialize_str(name),
+                None => serializer.serialize_i128(*value),
+            },
+            Self::Pointer(p) => serializer.collect_str(&format_args!("{p:#x}")),
+            Self::String(s) => serializer.serialize_str(s),
+            Self::Array(items) => serializer.collect_seq(items),
+            Self::Struct(fields) | Self::Union(fields) => {
+                let mut map = serializer.serialize_map(Some(fields.len()))?;
+                for BtfField { name, value } in fields {
+                    map.serialize_entry(name.as_str(), value)?;
+                }
+                map.end()
+            }
+            Self::Bytes(bytes) => serializer.serialize_bytes(bytes),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_matches::assert_matches;
+
+    use super::*;
+    use crate::btf::BtfEnum;
+
+    fn member(btf: &mut Btf, name: &str, btf_type: u32, offset: u32) -> BtfMember {
+        BtfMember {
+            name_offset: btf.add_string(name),
+            btf_type,
+            offset,
+        }
+    }
+
+    #[test]
+    fn test_decode_struct() {
+        let mut btf = Btf::new();
+        let name_offset = btf.add_string("int");
+        let int = btf.add_type(BtfType::Int(Int::new(
+            name_offset,
+            4,
+            IntEncoding::Signed,
+            0,
+        )));
+        let name_offset = btf.add_string("char");
+        let char_type = btf.add_type(BtfType::Int(Int::new(
+            name_offset,
+            1,
+            IntEncoding::Signed,
+            0,
+        )));
+        let comm = btf.add_type(BtfType::Array(Array::new(0, char_type, int, 8)));
+        let ptr = btf.add_type(BtfType::Ptr(Ptr::new(0, int)));
+        let name_offset = btf.add_string("state");
+        let variants = vec![
+            BtfEnum::new(btf.add_string("RUNNING"), 0),
+            BtfEnum::new(btf.add_string("SLEEPING"), 1),
+        ];
+        let state = btf.add_type(BtfType::Enum(Enum::new(name_offset, false, variants)));
+        let members = vec![
+            member(&mut btf, "pid", int, 0),
+            member(&mut btf, "comm", comm, 32),
+            member(&mut btf, "ptr", ptr, 96),
+            member(&mut btf, "state", state, 160),
+        ];
+        let name_offset = btf.add_string("task");
+        let task = btf.add_type(BtfType::Struct(Struct::new(name_offset, members, 24)));
+
+        let mut data = Vec::new();
+        data.extend((-42i32).to_ne_bytes());
+        data.extend(b"bash\0\0\0\0");
+        data.extend(0xdead_beefu64.to_ne_bytes());
+        data.extend(1u32.to_ne_bytes());
+
+        let value = btf.decode_value(task, &data).unwrap();
+        assert_eq!(
+            value.to_string(),
+            r#"{.pid = -42, .comm = "bash", .ptr = 0xdeadbeef, .state = SLEEPING}"#
+        );
+        assert_matches!(
+            btf.decode_value(task, &data[..8]),
+            Err(BtfError::ValueTooShort { len: 8, .. })
+        );
+        assert_eq!(btf.encode_value(task, &value).unwrap(), data);
+
+        let mut value = value;
+        *value.field_mut("pid").unwrap() = BtfValue::Int(7);
+        *value.field_mut("state").unwrap() = BtfValue::Enum {
+            name: Some("RUNNING".into()),
+            value: 1,
+        };
+        let data = btf.encode_value(task, &value).unwrap();
+        assert_eq!(
+            btf.decode_value(task, &data).unwrap().to_string(),
+            r#"{.pid = 7, .comm = "bash", .ptr = 0xdeadbeef, .state = RUNNING}"#
+        );
+        assert_eq!(value.field("comm"), Some(&BtfValue::String("bash".into())));
+        assert_eq!(value.field("nope"), None);
+
+        // Missing members are zeroed.
+        let value = BtfValue::Struct(vec![BtfField {
+            name: "comm".into(),
+            value: BtfValue::String("sh".into()),
+        }]);
+        let mut expected = vec![0; 24];
+        expected[4..6].copy_from_slice(b"sh");
+        assert_eq!(btf.encode_value(task, &value).unwrap(), expected);
+
+        let field = |name: &str, value| {
+            BtfValue::Struct(vec![BtfField {
+                name: name.into(),
+                value,
+            }])
+        };
+        assert_matches!(
+            btf.encode_value(task, &field("nope", BtfValue::Int(0))),
+            Err(BtfError::UnknownField { name, .. }) if name == "nope"
+        );
+        assert_matches!(
+            btf.encode_value(task, &field("pid", BtfValue::Uint(1 << 31))),
+            Err(BtfError::ValueOutOfRange { type_id }) if type_id == int
+        );
+        assert_matches!(
+            btf.encode_value(task, &field("comm", BtfValue::String("too long!".into()))),
+            Err(BtfError::ValueOutOfRange { type_id }) if type_id == comm
+        );
+        assert_matches!(
+            btf.encode_value(task, &field("pid", BtfValue::Float(1.0))),
+            Err(BtfError::ValueTypeMismatch { type_id }) if type_id == int
+        );
+    }
+
+    #[test]
+    fn test_decode_bitfields() {
+        let mut btf = Btf::new();
+        let name_offset = btf.add_string("unsigned int");
+        let uint = btf.add_type(BtfType::Int(Int::new(name_offset, 4, IntEncoding::None, 0)));
+        let name_offset = btf.add_string("int");
+        let int = btf.add_type(BtfType::Int(Int::new(
+            name_offset,
+            4,
+            IntEncoding::Signed,
+            0,
+        )));
+        let members = vec![
+            member(&mut btf, "a", uint, 3 << 24),
+            member(&mut btf, "b", int, (5 << 24) | 3),
+            member(&mut btf, "c", uint, (24 << 24) | 8),
+        ];
+        let mut s = Struct::new(0, members, 4);
+        // Set kind_flag so member offsets carry bitfield sizes.
+        s.info |= 1 << 31;
+        let s = btf.add_type(BtfType::Struct(s));
+
+        // a = 5, b = -3, c = 0x123456
+        let raw: u32 = 5 | ((-3i32 as u32 & 0x1f) << 3) | (0x12_3456 << 8);
+        let value = btf.decode_value(s, &raw.to_ne_bytes()).unwrap();
+        assert_eq!(
+            value,
+            BtfValue::Struct(vec![
+                BtfField {
+                    name: "a".into(),
+                    value: BtfValue::Uint(5)
+                },
+                BtfField {
+                    name: "b".into(),
+                    value: BtfValue::Int(-3)
+                },
+                BtfField {
+                    name: "c".into(),
+                    value: BtfValue::Uint(0x12_3456)
+                },
+            ])
+        );
+        assert_eq!(btf.encode_value(s, &value).unwrap(), raw.to_ne_bytes());
+        assert_matches!(
+            btf.encode_value(
+                s,
+                &BtfValue::Struct(vec![BtfField {
+                    name: "b".into(),
+                    value: BtfValue::Int(-17),
+                }])
+            ),
+            Err(BtfError::ValueOutOfRange { .. })
+        );
+    }
+
+    #[test]
+    fn test_decode_anonymous_union_and_bytes() {
+        let mut btf = Btf::new();
+        let name_offset = btf.add_string("__u8");
+        let u8_type = btf.add_type(BtfType::Int(Int::new(name_offset, 1, IntEncoding::None, 0)));
+        let bytes = btf.add_type(BtfType::Array(Array::new(0, u8_type, u8_type, 2)));
+        let members = vec![member(&mut btf, "x", u8_type, 0)];
+        let union = btf.add_type(BtfType::Union(Union::new(0, 1, members, None)));
+        let members = vec![
+            member(&mut btf, "", union, 0),
+            member(&mut btf, "y", bytes, 8),
+        ];
+        let s = btf.add_type(BtfType::Struct(Struct::new(0, members, 3)));
+
+        let value = btf.decode_value(s, &[1, 2, 3]).unwrap();
+        assert_eq!(value.to_string(), "{.x = 1, .y = [2, 3]}");
+        assert_eq!(btf.encode_value(s, &value).unwrap(), [1, 2, 3]);
+        assert_eq!(
+            btf.encode_value(s, &BtfValue::Bytes(vec![4, 5])).unwrap(),
+            [4, 5, 0]
+        );
+        assert_eq!(btf.decode_value(0, &[0xab]).unwrap().to_string(), "[0xab]");
+    }
+}
@@ -0,0 +1,257 @@
+//! In-memory maps.
+
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::{EbpfSectionKind, Map, generated::bpf_map_type, harness::HarnessError};
+
+pub(super) const EFAULT: i64 = 14;
+pub(super) const EINVAL: i64 = 22;
+const ENOENT: i64 = 2;
+const E2BIG: i64 = 7;
+const EEXIST: i64 = 17;
+
+const BPF_NOEXIST: u64 = 1;
+const BPF_EXIST: u64 = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Kind {
+    Array,
+    Hash,
+    Queue,
+    Stack,
+    RingBuf,
+    PerfEventArray,
+    Unsupported,
+}
+
+/// An in-memory map backing a map of the object run by a [`Harness`](super::Harness).
+///
+/// Arrays, hash maps, queues, stacks, ring buffers and perf event arrays are supported. Per-CPU
+/// maps have a single value per key, as programs always run on the CPU set in the
+/// [`Environment`](super::Environment). Other map types can't be accessed by programs.
+///
+/// Keys and values are the raw bytes of the eBPF types.
+#[derive(Debug)]
+pub struct FakeMap {
+    kind: Kind,
+    map_type: u32,
+    key_size: u32,
+    value_size: u32,
+    max_entries: u32,
+    readonly: bool,
+    /// The values of the map, one after the other. Programs get pointers into it, and hash maps
+    /// reuse the slots of deleted entries.
+    pub(super) values: Vec<u8>,
+    slots: BTreeMap<Vec<u8>, usize>,
+    free_slots: Vec<usize>,
+    queue: VecDeque<Vec<u8>>,
+    records: Vec<Vec<u8>>,
+}
+
+impl FakeMap {
+    pub(super) fn new(map: &Map) -> Self {
+        let map_type = map.map_type();
+        let kind = match bpf_map_type::try_from(map_type) {
+            Ok(bpf_map_type::BPF_MAP_TYPE_ARRAY | bpf_map_type::BPF_MAP_TYPE_PERCPU_ARRAY) => {
+                Kind::Array
+            }
+            Ok(
+                bpf_map_type::BPF_MAP_TYPE_HASH
+                | bpf_map_type::BPF_MAP_TYPE_LRU_HASH
+                | bpf_map_type::BPF_MAP_TYPE_PERCPU_HASH
+                | bpf_map_type::BPF_MAP_TYPE_LRU_PERCPU_HASH,
+            ) => Kind::Hash,
+            Ok(bpf_map_type::BPF_MAP_TYPE_QUEUE) => Kind::Queue,
+            Ok(bpf_map_type::BPF_MAP_TYPE_STACK) => Kind::Stack,
+            Ok(bpf_map_type::BPF_MAP_TYPE_RINGBUF) => Kind::RingBuf,
+            Ok(bpf_map_type::BPF_MAP_TYPE_PERF_EVENT_ARRAY) => Kind::PerfEventArray,
+            _ => Kind::Unsupported,
+        };
+        let mut values = Vec::new();
+        if kind == Kind::Array {
+            values = map.data().to_vec();
+            values.resize(map.max_entries() as usize * map.value_size() as usize, 0);
+        }
+        Self {
+            kind,
+            map_type,
+            key_size: map.key_size(),
+            value_size: map.value_size(),
+            max_entries: map.max_entries(),
+            readonly: map.section_kind() == EbpfSectionKind::Rodata,
+            values,
+            slots: BTreeMap::new(),
+            free_slots: Vec::new(),
+            queue: VecDeque::new(),
+            records: Vec::new(),
+        }
+    }
+
+    /// Returns the type of the map.
+    pub const fn map_type(&self) -> u32 {
+        self.map_type
+    }
+
+    /// Returns the value of `key`, or `None` if there is none.
+    ///
+    /// The key of an array is its index as a `u32`.
+    pub fn get(&self, key: &[u8]) -> Option<&[u8]> {
+        let offset = self.offset(key)?;
+        self.values.get(offset..offset + self.value_size as usize)
+    }
+
+    /// Sets the value of `key`, inserting it if it isn't in the map.
+    pub fn insert(&mut self, key: &[u8], value: &[u8]) -> Result<(), HarnessError> {
+        self.update(key, value, 0)
+            .map_err(|errno| HarnessError::MapUpdate { errno: -errno })
+    }
+
+    /// Removes `key` from a hash map, returning whether it was in the map.
+    pub fn remove(&mut self, key: &[u8]) -> bool {
+        self.delete(key).is_ok()
+    }
+
+    /// Returns the keys of a hash map, in byte order.
+    pub fn keys(&self) -> impl Iterator<Item = &[u8]> {
+        self.slots.keys().map(Vec::as_slice)
+    }
+
+    /// Pushes `value` to a queue or a stack.
+    pub fn push(&mut self, value: &[u8]) -> Result<(), HarnessError> {
+        self.push_elem(value, 0)
+            .map_err(|errno| HarnessError::MapUpdate { errno: -errno })
+    }
+
+    /// Pops the next value of a queue or a stack.
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        self.pop_elem(false).ok()
+    }
+
+    /// Returns the records written to a ring buffer or a perf event array, oldest first.
+    pub fn records(&self) -> &[Vec<u8>] {
+        &self.records
+    }
+
+    /// Removes and returns the records written to a ring buffer or a perf event array.
+    pub fn take_records(&mut self) -> Vec<Vec<u8>> {
+        std::mem::take(&mut self.records)
+    }
+
+    pub(super) const fn key_size(&self) -> usize {
+        self.key_size as usize
+    }
+
+    pub(super) const fn value_size(&self) -> usize {
+        self.value_size as usize
+    }
+
+    pub(super) const fn readonly(&self) -> bool {
+        self.readonly
+    }
+
+    pub(super) const fn is_ringbuf(&self) -> bool {
+        matches!(self.kind, Kind::RingBuf)
+    }
+
+    /// Returns the offset of the value of `key` in `values`.
+    pub(super) fn offset(&self, key: &[u8]) -> Option<usize> {
+        if key.len() != self.key_size() {
+            return None;
+        }
+        match self.kind {
+            Kind::Array => {
+                let index = u32::from_ne_bytes(key.try_into().ok()?);
+                (index < self.max_entries).then(|| index as usize * self.value_size())
+            }
+            Kind::Hash => self.slots.get(key).map(|slot| slot * self.value_size()),
+            Kind::Queue
+            | Kind::Stack
+            | Kind::RingBuf
+            | Kind::PerfEventArray
+            | Kind::Unsupported => None,
+        }
+    }
+
+    pub(super) fn update(&mut self, key: &[u8], value: &[u8], flags: u64) -> Result<(), i64> {
+        if key.len() != self.key_size() || value.len() != self.value_size() || flags > BPF_EXIST {
+            return Err(-EINVAL);
+        }
+        let offset = match self.kind {
+            Kind::Array => {
+                if flags == BPF_NOEXIST {
+                    return Err(-EEXIST);
+                }
+                self.offset(key).ok_or(-E2BIG)?
+            }
+            Kind::Hash => match (self.slots.get(key), flags) {
+                (Some(_), BPF_NOEXIST) => return Err(-EEXIST),
+                (None, BPF_EXIST) => return Err(-ENOENT),
+                (Some(slot), _) => slot * self.value_size(),
+                (None, _) => {
+                    if self.slots.len() >= self.max_entries as usize {
+                        return Err(-E2BIG);
+                    }
+                    let slot = self.free_slots.pop().unwrap_or_else(|| {
+                        let slot = self.values.len() / self.value_size().max(1);
+                        self.values.resize(self.values.len() + self.value_size(), 0);
+                        slot
+                    });
+                    let _: Option<usize> = self.slots.insert(key.to_vec(), slot);
+                    slot * self.value_size()
+                }
+            },
+            Kind::Queue
+            | Kind::Stack
+            | Kind::RingBuf
+            | Kind::PerfEventArray
+            | Kind::Unsupported => {
+                return Err(-EINVAL);
+            }
+        };
+        self.values[offset..offset + value.len()].copy_from_slice(value);
+        Ok(())
+    }
+
+    pub(super) fn delete(&mut self, key: &[u8]) -> Result<(), i64> {
+        if self.kind != Kind::Hash {
+            return Err(-EINVAL);
+        }
+        let slot = self.slots.remove(key).ok_or(-ENOENT)?;
+        self.free_slots.push(slot);
+        Ok(())
+    }
+
+    pub(super) fn push_elem(&mut self, value: &[u8], flags: u64) -> Result<(), i64> {
+        if !matches!(self.kind, Kind::Queue | Kind::Stack) || value.len() != self.value_size() {
+            return Err(-EINVAL);
+        }
+        if self.queue.len() >= self.max_entries as usize {
+            if flags != BPF_EXIST {
+                return Err(-E2BIG);
+            }
+            // BPF_EXIST makes room by dropping the oldest value.
+            let _unused: Option<Vec<u8>> = self.queue.pop_front();
+        }
+        self.queue.push_back(value.to_vec());
+        Ok(())
+    }
+
+    pub(super) fn pop_elem(&mut self, peek: bool) -> Result<Vec<u8>, i64> {
+        let value = match (self.kind, peek) {
+            (Kind::Queue, false) => self.queue.pop_front(),
+            (Kind::Queue, true) => self.queue.front().cloned(),
+            (Kind::Stack, false) => self.queue.pop_back(),
+            (Kind::Stack, true) => self.queue.back().cloned(),
+            _ => return Err(-EINVAL),
+        };
+        value.ok_or(-ENOENT)
+    }
+
+    pub(super) fn output(&mut self, data: Vec<u8>) -> Result<(), i64> {
+        if !matches!(self.kind, Kind::RingBuf | Kind::PerfEventArray) {
+            return Err(-EINVAL);
+        }
+        self.records.push(data);
+        Ok(())
+    }
+}
@@ -0,0 +1,613 @@
+//! In-process execution of eBPF programs.
+//!
+//! [`Harness`] runs the programs of an [`Object`] with an interpreter instead of loading them in
+//! the kernel, so that their logic can be unit tested without root or a VM. Maps are relocated to
+//! in-memory [`FakeMap`]s that tests can fill before running a program and inspect after, and
+//! the common helpers are emulated:
+//!
+//! * map lookup, update and delete, and queue and stack push, pop and peek.
+//! * ring buffer output, reserve, submit and discard, and perf event output.
+//! * `ktime_get_*_ns`, the current pid, tgid, uid, gid and comm, the CPU and random numbers,
+//!   with values taken from the [`Environment`].
+//! * `probe_read*` on memory supplied with [`Harness::add_memory`].
+//! * `trace_printk`, whose output is available from [`Harness::trace_log`].
+//! * `skb_load_bytes`, `xdp_adjust_head` and `xdp_adjust_tail`.
+//!
+//! Other helpers can be provided with [`Harness::set_helper`].
+//!
+//! The harness doesn't verify programs: an access the verifier would reject may succeed, and
+//! out of bounds accesses are reported as [`HarnessError::InvalidMemoryAccess`] instead.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use aya_obj::{Object, harness::Harness};
+//!
+//! let bytes = std::fs::read("program.o").unwrap();
+//! let mut harness = Harness::new(Object::parse(&bytes).unwrap()).unwrap();
+//!
+//! let mut packet = vec![0; 64];
+//! const XDP_PASS: u32 = 2;
+//! assert_eq!(harness.run_xdp("xdp_pass", &mut packet).unwrap(), XDP_PASS);
+//!
+//! let packets = harness.map("PACKETS").unwrap().get(&0u32.to_ne_bytes()).unwrap();
+//! assert_eq!(packets, 1u64.to_ne_bytes());
+//! ```
+
+mod maps;
+mod vm;
+
+use std::{
+    borrow::ToOwned as _,
+    collections::{HashMap, HashSet},
+};
+
+pub use maps::FakeMap;
+use object::Endianness;
+use thiserror::Error;
+
+use crate::{
+    Object, ProgramSection, generated::bpf_insn, harness::vm::Vm, relocation::EbpfRelocationError,
+};
+
+/// The error type returned by [`Harness`].
+#[derive(Debug, Error)]
+pub enum HarnessError {
+    /// The object has a different endianness than the host.
+    #[error("the object has a different endianness than the host")]
+    Endianness,
+
+    /// Relocating the object failed.
+    #[error(transparent)]
+    Relocation(#[from] EbpfRelocationError),
+
+    /// There is no program with this name.
+    #[error("unknown program `{name}`")]
+    UnknownProgram {
+        /// The program name.
+        name: String,
+    },
+
+    /// The program has a different type than the one being run.
+    #[error("program `{name}` is not a {expected} program")]
+    UnexpectedProgramType {
+        /// The program name.
+        name: String,
+        /// The expected program type.
+        expected: &'static str,
+    },
+
+    /// The program contains an invalid or unsupported instruction.
+    #[error("invalid instruction {code:#04x} at {pc}")]
+    InvalidInstruction {
+        /// The index of the instruction.
+        pc: usize,
+        /// The opcode of the instruction.
+        code: u8,
+    },
+
+    /// The program jumped outside of its instructions.
+    #[error("jump out of bounds to {pc}")]
+    InvalidJump {
+        /// The index the program jumped to.
+        pc: usize,
+    },
+
+    /// The program accessed memory it doesn't own.
+    #[error("invalid access of {size} bytes at {address:#x} at instruction {pc}")]
+    InvalidMemoryAccess {
+        /// The index of the instruction.
+        pc: usize,
+        /// The accessed address.
+        address: u64,
+        /// The size of the access.
+        size: usize,
+    },
+
+    /// The program called a helper that isn't emulated.
+    #[error("unsupported helper {id} called at {pc}")]
+    UnsupportedHelper {
+        /// The index of the call instruction.
+        pc: usize,
+        /// The helper id.
+        id: i32,
+    },
+
+    /// The program passed something that isn't a supported map to a helper.
+    #[error("invalid map {handle:#x} passed to helper at {pc}")]
+    InvalidMap {
+        /// The index of the call instruction.
+        pc: usize,
+        /// The value passed as map.
+        handle: u64,
+    },
+
+    /// The program nested too many function calls.
+    #[error("call stack too deep at {pc}")]
+    CallDepth {
+        /// The index of the call instruction.
+        pc: usize,
+    },
+
+    /// The program executed more than [`Harness::INSTRUCTION_LIMIT`] instructions.
+    #[error("instruction limit reached")]
+    InstructionLimit,
+
+    /// Updating a [`FakeMap`] failed.
+    #[error("map update failed with errno {errno}")]
+    MapUpdate {
+        /// The errno the kernel would have returned.
+        errno: i64,
+    },
+}
+
+/// The state of the machine programs run on, as seen through helpers and contexts.
+#[derive(Clone, Debug, Default)]
+pub struct Environment {
+    /// The value returned by the `ktime_get_*_ns` helpers.
+    pub ktime_ns: u64,
+    /// The CPU programs run on.
+    pub cpu: u32,
+    /// The pid (thread id) of the current task.
+    pub pid: u32,
+    /// The tgid (process id) of the current task.
+    pub tgid: u32,
+    /// The uid of the current task.
+    pub uid: u32,
+    /// The gid of the current task.
+    pub gid: u32,
+    /// The comm of the current task, padded with NUL bytes.
+    pub comm: [u8; 16],
+    /// The index of the interface packets are received on.
+    pub ifindex: u32,
+    /// The index of the receive queue packets are received on.
+    pub rx_queue_index: u32,
+}
+
+type Helper = Box<dyn FnMut([u64; 5]) -> u64>;
+
+/// The state shared by the runs of a [`Harness`].
+struct State {
+    maps: Vec<FakeMap>,
+    map_names: HashMap<String, usize>,
+    environment: Environment,
+    memory: Vec<(u64, Vec<u8>)>,
+    helpers: HashMap<i32, Helper>,
+    trace_log: Vec<String>,
+    random: u64,
+}
+
+/// Runs the programs of an [`Object`] in process.
+///
+/// See the [module documentation](self) for what is emulated.
+pub struct Harness {
+    programs: HashMap<String, (ProgramSection, Vec<bpf_insn>)>,
+    state: State,
+}
+
+impl Harness {
+    /// The number of instructions after which a run is aborted.
+    pub const INSTRUCTION_LIMIT: u64 = 1_000_000;
+
+    /// Creates a harness running the programs of `object`.
+    ///
+    /// The maps of the object are replaced with empty [`FakeMap`]s, except for the `.data`,
+    /// `.rodata` and `.bss` maps which hold the initial values of the globals.
+    pub fn new(mut object: Object) -> Result<Self, HarnessError> {
+        if object.endianness != Endianness::default() {
+            return Err(HarnessError::Endianness);
+        }
+
+        let mut names = object.maps.keys().cloned().collect::<Vec<_>>();
+        names.sort_unstable();
+        let object_maps = object.maps.clone();
+        let maps = names
+            .iter()
+            .map(|name| FakeMap::new(&object_maps[name]))
+            .collect();
+        let text_sections = object
+            .functions
+            .keys()
+            .map(|(section_index, _)| *section_index)
+            .collect::<HashSet<_>>();
+        // Maps are referenced by their index, see `Vm`.
+        object.relocate_maps(
+            names
+                .iter()
+                .enumerate()
+                .map(|(index, name)| (name.as_str(), index as i32, &object_maps[name])),
+            &text_sections,
+        )?;
+        object.relocate_calls(&text_sections)?;
+
+        let programs = object
+            .programs
+            .iter()
+            .filter_map(|(name, program)| {
+                let function = object.functions.get(&program.function_key())?;
+                Some((
+                    name.clone(),
+                    (program.section.clone(), function.instructions.clone()),
+                ))
+            })
+            .collect();
+        let map_names = names
+            .into_iter()
+            .enumerate()
+            .map(|(index, name)| (name, index))
+            .collect();
+        Ok(Self {
+            programs,
+            state: State {
+                maps,
+                map_names,
+                environment: Environment::default(),
+                memory: Vec::new(),
+                helpers: HashMap::new(),
+                trace_log: Vec::new(),
+                random: 0x2545_f491_4f6c_dd1d,
+            },
+        })
+    }
+
+    /// Returns the map called `name`.
+    pub fn map(&self, name: &str) -> Option<&FakeMap> {
+        let State {
+            maps, map_names, ..
+        } = &self.state;
+        maps.get(*map_names.get(name)?)
+    }
+
+    /// Returns the map called `name` mutably, e.g. to fill it before running a program.
+    pub fn map_mut(&mut self, name: &str) -> Option<&mut FakeMap> {
+        let State {
+            maps, map_names, ..
+        } = &mut self.state;
+        maps.get_mut(*map_names.get(name)?)
+    }
+
+    /// Returns the environment programs run in.
+    pub const fn environment(&self) -> &Environment {
+        &self.state.environment
+    }
+
+    /// Returns the environment programs run in mutably, e.g. to advance the time.
+    pub const fn environment_mut(&mut self) -> &mut Environment {
+        &mut self.state.environment
+    }
+
+    /// Makes `data` readable by the `probe_read*` helpers at `address`.
+    ///
+    /// This is typically used to provide the kernel or user memory that pointers in the context
+    /// of tracing programs point to.
+    pub fn add_memory(&mut self, address: u64, data: Vec<u8>) {
+        self.state.memory.push((address, data));
+    }
+
+    /// Emulates the helper `id` with `helper`, which is called with the arguments of the helper
+    /// and returns its result.
+    ///
+    /// This overrides the built-in emulation of the helper, if any.
+    pub fn set_helper(&mut self, id: u32, helper: impl FnMut([u64; 5]) -> u64 + 'static) {
+        let _unused: Option<Helper> = self.state.helpers.insert(id as i32, Box::new(helper));
+    }
+
+    /// Returns the messages written with `bpf_trace_printk`, oldest first.
+    pub fn trace_log(&self) -> &[String] {
+        &self.state.trace_log
+    }
+
+    /// Runs `program` with `ctx` as context and returns its result.
+    ///
+    /// Changes made by the program to the context are written back to `ctx`.
+    pub fn run(&mut self, program: &str, ctx: &mut [u8]) -> Result<u64, HarnessError> {
+        let instructions = program_instructions(&self.programs, program, "", |_| true)?;
+        let mut vm = Vm::new(instructions, &mut self.state);
+        let ctx_address = vm.add_context(ctx.to_vec(), &[]);
+        let ret = vm.run(ctx_address)?;
+        ctx.copy_from_slice(vm.region(ctx_address));
+        Ok(ret)
+    }
+
+    /// Runs the XDP program `program` on `packet` and returns its action.
+    ///
+    /// Changes made by the program to the packet, including with `xdp_adjust_head` and
+    /// `xdp_adjust_tail`, are written back to `packet`.
+    pub fn run_xdp(&mut self, program: &str, packet: &mut Vec<u8>) -> Result<u32, HarnessError> {
+        let instructions = program_instructions(&self.programs, program, "XDP", |section| {
+            matches!(section, ProgramSection::Xdp { .. })
+        })?;
+        let Environment {
+            ifindex,
+            rx_queue_index,
+            ..
+        } = self.state.environment;
+        let mut vm = Vm::new(instructions, &mut self.state);
+        vm.set_packet(packet, vm::XDP_PACKET_HEADROOM, vm::XDP_PACKET_TAILROOM);
+        // struct xdp_md
+        let mut ctx = vec![0; 24];
+        write_u32(&mut ctx, 12, ifindex);
+        write_u32(&mut ctx, 16, rx_queue_index);
+        let ctx = vm.add_context(ctx, vm::XDP_MD_POINTERS);
+        let ret = vm.run(ctx)?;
+        *packet = vm.packet().to_vec();
+        Ok(ret as u32)
+    }
+
+    /// Runs the TC classifier `program` on `packet` and returns its action.
+    ///
+    /// Changes made by the program to the packet are written back to `packet`.
+    pub fn run_tc(&mut self, program: &str, packet: &mut Vec<u8>) -> Result<i32, HarnessError> {
+        let instructions =
+            program_instructions(&self.programs, program, "TC classifier", |section| {
+                matches!(section, ProgramSection::SchedClassifier)
+            })?;
+        let ctx = sk_buff(packet, &self.state.environment);
+        let mut vm = Vm::new(instructions, &mut self.state);
+        vm.set_packet(packet, 0, 0);
+        let ctx = vm.add_context(ctx, vm::SK_BUFF_POINTERS);
+        let ret = vm.run(ctx)?;
+        *packet = vm.packet().to_vec();
+        Ok(ret as i32)
+    }
+
+    /// Runs the socket filter `program` on `packet` and returns the number of bytes of the
+    /// packet to keep.
+    pub fn run_socket_filter(&mut self, program: &str, packet: &[u8]) -> Result<u32, HarnessError> {
+        let instructions =
+            program_instructions(&self.programs, program, "socket filter", |section| {
+                matches!(section, ProgramSection::SocketFilter)
+            })?;
+        let ctx = sk_buff(packet, &self.state.environment);
+        let mut vm = Vm::new(instructions, &mut self.state);
+        vm.set_packet(packet, 0, 0);
+        let ctx = vm.add_context(ctx, vm::SK_BUFF_POINTERS);
+        Ok(vm.run(ctx)? as u32)
+    }
+}
+
+/// Returns the instructions of the program called `name`, checking that it is of the
+/// `expected` type.
+fn program_instructions<'a>(
+    programs: &'a HashMap<String, (ProgramSection, Vec<bpf_insn>)>,
+    name: &str,
+    expected: &'static str,
+    is_expected: impl FnOnce(&ProgramSection) -> bool,
+) -> Result<&'a [bpf_insn], HarnessError> {
+    let (section, instructions) =
+        programs
+            .get(name)
+            .ok_or_else(|| HarnessError::UnknownProgram {
+                name: name.to_owned(),
+            })?;
+    if !is_expected(section) {
+        return Err(HarnessError::UnexpectedProgramType {
+            name: name.to_owned(),
+            expected,
+        });
+    }
+    Ok(instructions)
+}
+
+/// Returns the `struct __sk_buff` of `packet`.
+fn sk_buff(packet: &[u8], environment: &Environment) -> Vec<u8> {
+    let mut ctx = vec![0; vm::SK_BUFF_SIZE];
+    write_u32(&mut ctx, 0, packet.len() as u32);
+    // The protocol is the ethertype, in network byte order.
+    if let Some(&[a, b]) = packet.get(12..14) {
+        write_u32(&mut ctx, 16, u16::from_ne_bytes([a, b]).into());
+    }
+    write_u32(&mut ctx, 36, environment.ifindex);
+    write_u32(&mut ctx, 40, environment.ifindex);
+    ctx
+}
+
+fn write_u32(buf: &mut [u8], offset: usize, value: u32) {
+    buf[offset..offset + 4].copy_from_slice(&value.to_ne_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        EbpfSectionKind, Map,
+        generated::{
+            BPF_ABS, BPF_ADD, BPF_ALU64, BPF_ATOMIC, BPF_B, BPF_CALL, BPF_DW, BPF_H, BPF_IMM,
+            BPF_JEQ, BPF_JGT, BPF_JMP, BPF_K, BPF_LD, BPF_LDX, BPF_MEM, BPF_MOV, BPF_MUL,
+            BPF_PSEUDO_CALL, BPF_PSEUDO_MAP_FD, BPF_ST, BPF_STX, BPF_W, BPF_X, bpf_func_id,
+            bpf_map_type,
+        },
+        maps::{LegacyMap, bpf_map_def},
+        programs::XdpAttachType,
+        test_utils::{self, call, exit, insn},
+    };
+
+    fn mov(dst: u8, imm: i32) -> bpf_insn {
+        insn(BPF_ALU64 | BPF_MOV | BPF_K, dst, 0, 0, imm)
+    }
+
+    fn ld_map(dst: u8, index: i32) -> [bpf_insn; 2] {
+        [
+            insn(
+                BPF_LD | BPF_IMM | BPF_DW,
+                dst,
+                BPF_PSEUDO_MAP_FD as u8,
+                0,
+                index,
+            ),
+            insn(0, 0, 0, 0, 0),
+        ]
+    }
+
+    fn map(map_type: bpf_map_type, key_size: u32, value_size: u32) -> Map {
+        Map::Legacy(LegacyMap {
+            def: bpf_map_def {
+                map_type: map_type as u32,
+                key_size,
+                value_size,
+                max_entries: 16,
+                ..Default::default()
+            },
+            inner_def: None,
+            section_index: 0,
+            section_kind: EbpfSectionKind::Maps,
+            symbol_index: None,
+            data: Vec::new(),
+        })
+    }
+
+    /// Returns [`test_utils::object`] with `maps`.
+    fn object(
+        section: ProgramSection,
+        functions: &[&[bpf_insn]],
+        maps: impl IntoIterator<Item = (&'static str, Map)>,
+    ) -> Object {
+        let mut object = test_utils::object(section, functions);
+        object
+            .maps
+            .extend(maps.into_iter().map(|(name, map)| (name.to_owned(), map)));
+        object
+    }
+
+    #[test]
+    fn test_xdp() {
+        let program = [
+            insn(BPF_ALU64 | BPF_MOV | BPF_X, 6, 1, 0, 0),
+            // r2 = data, r3 = data_end
+            insn(BPF_LDX | BPF_MEM | BPF_W, 2, 6, 0, 0),
+            insn(BPF_LDX | BPF_MEM | BPF_W, 3, 6, 4, 0),
+            insn(BPF_ALU64 | BPF_MOV | BPF_X, 4, 2, 0, 0),
+            insn(BPF_ALU64 | BPF_ADD | BPF_K, 4, 0, 0, 14),
+            // Drop packets shorter than an ethernet header.
+            insn(BPF_JMP | BPF_JGT | BPF_X, 4, 3, 12, 0),
+            // Rewrite the first byte.
+            insn(BPF_ST | BPF_MEM | BPF_B, 2, 0, 0, 0xff),
+            // Count packets in COUNTS[0].
+            insn(BPF_ST | BPF_MEM | BPF_W, 10, 0, -4, 0),
+            ld_map(1, 0)[0],
+            ld_map(1, 0)[1],
+            insn(BPF_ALU64 | BPF_MOV | BPF_X, 2, 10, 0, 0),
+            insn(BPF_ALU64 | BPF_ADD | BPF_K, 2, 0, 0, -4),
+            call(bpf_func_id::BPF_FUNC_map_lookup_elem),
+            insn(BPF_JMP | BPF_JEQ | BPF_K, 0, 0, 2, 0),
+            mov(1, 1),
+            insn(BPF_STX | BPF_ATOMIC | BPF_DW, 0, 1, 0, BPF_ADD as i32),
+            // XDP_PASS
+            mov(0, 2),
+            exit(),
+            // XDP_DROP
+            mov(0, 1),
+            exit(),
+        ];
+        let object = object(
+            ProgramSection::Xdp {
+                frags: false,
+                attach_type: XdpAttachType::Interface,
+            },
+            &[&program],
+            [("COUNTS", map(bpf_map_type::BPF_MAP_TYPE_ARRAY, 4, 8))],
+        );
+        let mut harness = Harness::new(object).unwrap();
+
+        for _ in 0..2 {
+            let mut packet = vec![1; 64];
+            assert_eq!(harness.run_xdp("prog", &mut packet).unwrap(), 2);
+            assert_eq!(packet[..2], [0xff, 1]);
+            assert_eq!(packet.len(), 64);
+        }
+        let mut packet = vec![1; 10];
+        assert_eq!(harness.run_xdp("prog", &mut packet).unwrap(), 1);
+        assert_eq!(packet, [1; 10]);
+
+        let counts = harness.map("COUNTS").unwrap();
+        assert_eq!(
+            counts.get(&0u32.to_ne_bytes()),
+            Some(&2u64.to_ne_bytes()[..])
+        );
+        assert_matches::assert_matches!(
+            harness.run_tc("prog", &mut packet),
+            Err(HarnessError::UnexpectedProgramType { .. })
+        );
+    }
+
+    #[test]
+    fn test_helpers_and_calls() {
+        let program = [
+            call(bpf_func_id::BPF_FUNC_get_current_pid_tgid),
+            insn(BPF_STX | BPF_MEM | BPF_DW, 10, 0, -8, 0),
+            // Send the pid and tgid to EVENTS.
+            ld_map(1, 0)[0],
+            ld_map(1, 0)[1],
+            insn(BPF_ALU64 | BPF_MOV | BPF_X, 2, 10, 0, 0),
+            insn(BPF_ALU64 | BPF_ADD | BPF_K, 2, 0, 0, -8),
+            mov(3, 8),
+            mov(4, 0),
+            call(bpf_func_id::BPF_FUNC_ringbuf_output),
+            // trace_printk("%d", double(21))
+            mov(1, 21),
+            insn(BPF_JMP | BPF_CALL, 0, BPF_PSEUDO_CALL as u8, 0, 9),
+            insn(BPF_ALU64 | BPF_MOV | BPF_X, 3, 0, 0, 0),
+            insn(
+                BPF_ST | BPF_MEM | BPF_H,
+                10,
+                0,
+                -12,
+                i32::from(u16::from_ne_bytes(*b"%d")),
+            ),
+            insn(BPF_ST | BPF_MEM | BPF_B, 10, 0, -10, 0),
+            insn(BPF_ALU64 | BPF_MOV | BPF_X, 1, 10, 0, 0),
+            insn(BPF_ALU64 | BPF_ADD | BPF_K, 1, 0, 0, -12),
+            mov(2, 3),
+            call(bpf_func_id::BPF_FUNC_trace_printk),
+            mov(0, 0),
+            exit(),
+        ];
+        let double = [
+            insn(BPF_ALU64 | BPF_MOV | BPF_X, 0, 1, 0, 0),
+            insn(BPF_ALU64 | BPF_MUL | BPF_K, 0, 0, 0, 2),
+            exit(),
+        ];
+        let object = object(
+            ProgramSection::KProbe,
+            &[&program, &double],
+            [("EVENTS", map(bpf_map_type::BPF_MAP_TYPE_RINGBUF, 0, 0))],
+        );
+        let mut harness = Harness::new(object).unwrap();
+        harness.environment_mut().pid = 7;
+        harness.environment_mut().tgid = 3;
+
+        assert_eq!(harness.run("prog", &mut [0; 8]).unwrap(), 0);
+        let pid_tgid = (3u64 << 32) | 7;
+        assert_eq!(
+            harness.map("EVENTS").unwrap().records(),
+            [pid_tgid.to_ne_bytes().to_vec()]
+        );
+        assert_eq!(harness.trace_log(), ["42"]);
+    }
+
+    #[test]
+    fn test_socket_filter() {
+        let program = [
+            // Keep IPv4 packets.
+            insn(BPF_LD | BPF_ABS | BPF_H, 0, 0, 0, 12),
+            insn(BPF_JMP | BPF_JEQ | BPF_K, 0, 0, 2, 0x0800),
+            mov(0, 0),
+            exit(),
+            mov(0, -1),
+            exit(),
+        ];
+        let object = object(ProgramSection::SocketFilter, &[&program], []);
+        let mut harness = Harness::new(object).unwrap();
+
+        let mut packet = vec![0; 20];
+        packet[12..14].copy_from_slice(&[0x08, 0x00]);
+        assert_eq!(
+            harness.run_socket_filter("prog", &packet).unwrap(),
+            u32::MAX
+        );
+        packet[13] = 0x06;
+        assert_eq!(harness.run_socket_filter("prog", &packet).unwrap(), 0);
+        // Loads past the end of the packet end the program.
+        assert_eq!(harness.run_socket_filter("prog", &packet[..10]).unwrap(), 0);
+    }
+}
@@ -0,0 +1,898 @@
+//! The interpreter.
+//!
+//! Programs address memory through 64-bit pointers made of a region id in the upper half and an
+//! offset in the lower half. The values of map `i` are region `i`, and the regions created for a
+//! run (stack frames, the context, the packet and ring buffer reservations) follow. Region ids
+//! are offset by one so that no region overlaps NULL.
+//!
+//! Maps are relocated to their index. Loading a map yields `MAP_HANDLE | index`, which only
+//! helpers accept.
+
+use crate::{
+    generated::{
+        BPF_ABS, BPF_ADD, BPF_ALU, BPF_ALU64, BPF_AND, BPF_ARSH, BPF_ATOMIC, BPF_B, BPF_CALL,
+        BPF_CMPXCHG, BPF_DIV, BPF_DW, BPF_END, BPF_EXIT, BPF_FETCH, BPF_H, BPF_IMM, BPF_IND,
+        BPF_JA, BPF_JCOND, BPF_JEQ, BPF_JGE, BPF_JGT, BPF_JLE, BPF_JLT, BPF_JMP, BPF_JMP32,
+        BPF_JNE, BPF_JSET, BPF_JSGE, BPF_JSGT, BPF_JSLE, BPF_JSLT, BPF_LD, BPF_LDX, BPF_LSH,
+        BPF_MEM, BPF_MEMSX, BPF_MOD, BPF_MOV, BPF_MUL, BPF_NEG, BPF_OR, BPF_PSEUDO_CALL,
+        BPF_PSEUDO_MAP_FD, BPF_PSEUDO_MAP_VALUE, BPF_RSH, BPF_ST, BPF_STX, BPF_SUB, BPF_W, BPF_X,
+        BPF_XCHG, BPF_XOR, bpf_func_id, bpf_insn,
+    },
+    harness::{
+        Harness, HarnessError, State,
+        maps::{EFAULT, EINVAL},
+    },
+};
+
+pub(super) const XDP_PACKET_HEADROOM: usize = 256;
+/// The room `xdp_adjust_tail` can grow packets into.
+pub(super) const XDP_PACKET_TAILROOM: usize = 256;
+/// The size of `struct __sk_buff`.
+pub(super) const SK_BUFF_SIZE: usize = 192;
+
+/// The fields of `struct xdp_md` the kernel rewrites into packet pointers.
+pub(super) const XDP_MD_POINTERS: &[(usize, Pointer)] = &[
+    (0, Pointer::Data),
+    (4, Pointer::DataEnd),
+    (8, Pointer::DataMeta),
+];
+/// The fields of `struct __sk_buff` the kernel rewrites into packet pointers.
+pub(super) const SK_BUFF_POINTERS: &[(usize, Pointer)] = &[
+    (76, Pointer::Data),
+    (80, Pointer::DataEnd),
+    (140, Pointer::DataMeta),
+];
+
+const STACK_SIZE: usize = 512;
+const MAX_CALL_DEPTH: usize = 8;
+const MAP_HANDLE: u64 = 0xffff_0000_0000_0000;
+const ETH_HLEN: usize = 14;
+
+const MAP_LOOKUP_ELEM: i32 = bpf_func_id::BPF_FUNC_map_lookup_elem as i32;
+const MAP_UPDATE_ELEM: i32 = bpf_func_id::BPF_FUNC_map_update_elem as i32;
+const MAP_DELETE_ELEM: i32 = bpf_func_id::BPF_FUNC_map_delete_elem as i32;
+const MAP_PUSH_ELEM: i32 = bpf_func_id::BPF_FUNC_map_push_elem as i32;
+const MAP_POP_ELEM: i32 = bpf_func_id::BPF_FUNC_map_pop_elem as i32;
+const MAP_PEEK_ELEM: i32 = bpf_func_id::BPF_FUNC_map_peek_elem as i32;
+const PROBE_READ: i32 = bpf_func_id::BPF_FUNC_probe_read as i32;
+const PROBE_READ_USER: i32 = bpf_func_id::BPF_FUNC_probe_read_user as i32;
+const PROBE_READ_KERNEL: i32 = bpf_func_id::BPF_FUNC_probe_read_kernel as i32;
+const PROBE_READ_STR: i32 = bpf_func_id::BPF_FUNC_probe_read_str as i32;
+const PROBE_READ_USER_STR: i32 = bpf_func_id::BPF_FUNC_probe_read_user_str as i32;
+const PROBE_READ_KERNEL_STR: i32 = bpf_func_id::BPF_FUNC_probe_read_kernel_str as i32;
+const KTIME_GET_NS: i32 = bpf_func_id::BPF_FUNC_ktime_get_ns as i32;
+const KTIME_GET_BOOT_NS: i32 = bpf_func_id::BPF_FUNC_ktime_get_boot_ns as i32;
+const KTIME_GET_COARSE_NS: i32 = bpf_func_id::BPF_FUNC_ktime_get_coarse_ns as i32;
+const KTIME_GET_TAI_NS: i32 = bpf_func_id::BPF_FUNC_ktime_get_tai_ns as i32;
+const GET_PRANDOM_U32: i32 = bpf_func_id::BPF_FUNC_get_prandom_u32 as i32;
+const GET_SMP_PROCESSOR_ID: i32 = bpf_func_id::BPF_FUNC_get_smp_processor_id as i32;
+const GET_CURRENT_PID_TGID: i32 = bpf_func_id::BPF_FUNC_get_current_pid_tgid as i32;
+const GET_CURRENT_UID_GID: i32 = bpf_func_id::BPF_FUNC_get_current_uid_gid as i32;
+const GET_CURRENT_COMM: i32 = bpf_func_id::BPF_FUNC_get_current_comm as i32;
+const TRACE_PRINTK: i32 = bpf_func_id::BPF_FUNC_trace_printk as i32;
+const PERF_EVENT_OUTPUT: i32 = bpf_func_id::BPF_FUNC_perf_event_output as i32;
+const RINGBUF_OUTPUT: i32 = bpf_func_id::BPF_FUNC_ringbuf_output as i32;
+const RINGBUF_RESERVE: i32 = bpf_func_id::BPF_FUNC_ringbuf_reserve as i32;
+const RINGBUF_SUBMIT: i32 = bpf_func_id::BPF_FUNC_ringbuf_submit as i32;
+const RINGBUF_DISCARD: i32 = bpf_func_id::BPF_FUNC_ringbuf_discard as i32;
+const SKB_LOAD_BYTES: i32 = bpf_func_id::BPF_FUNC_skb_load_bytes as i32;
+const XDP_ADJUST_HEAD: i32 = bpf_func_id::BPF_FUNC_xdp_adjust_head as i32;
+const XDP_ADJUST_TAIL: i32 = bpf_func_id::BPF_FUNC_xdp_adjust_tail as i32;
+
+/// A context field holding a packet pointer.
+#[derive(Clone, Copy)]
+pub(super) enum Pointer {
+    Data,
+    DataEnd,
+    DataMeta,
+}
+
+enum RegionKind {
+    Memory,
+    Context(&'static [(usize, Pointer)]),
+    Reservation { map: usize },
+    Released,
+}
+
+struct Region {
+    data: Vec<u8>,
+    kind: RegionKind,
+}
+
+/// The packet being processed, as a range of a region.
+struct Packet {
+    region: usize,
+    start: usize,
+    end: usize,
+    meta: usize,
+}
+
+struct Frame {
+    return_pc: usize,
+    /// r6 to r10.
+    registers: [u64; 5],
+}
+
+/// A single run of a program.
+pub(super) struct Vm<'a> {
+    instructions: &'a [bpf_insn],
+    state: &'a mut State,
+    regions: Vec<Region>,
+    packet: Option<Packet>,
+}
+
+impl<'a> Vm<'a> {
+    pub(super) const fn new(instructions: &'a [bpf_insn], state: &'a mut State) -> Self {
+        Self {
+            instructions,
+            state,
+            regions: Vec::new(),
+            packet: None,
+        }
+    }
+
+    /// Adds a context and returns its address.
+    pub(super) fn add_context(
+        &mut self,
+        data: Vec<u8>,
+        pointers: &'static [(usize, Pointer)],
+    ) -> u64 {
+        self.add_region(data, RegionKind::Context(pointers))
+    }
+
+    /// Sets the packet, with `headroom` and `tailroom` bytes of room around it.
+    pub(super) fn set_packet(&mut self, packet: &[u8], headroom: usize, tailroom: usize) {
+        let mut data = vec![0; headroom];
+        data.extend_from_slice(packet);
+        data.resize(data.len() + tailroom, 0);
+        let _: u64 = self.add_region(data, RegionKind::Memory);
+        self.packet = Some(Packet {
+            region: self.regions.len() - 1,
+            start: headroom,
+            end: headroom + packet.len(),
+            meta: headroom,
+        });
+    }
+
+    /// Returns the packet.
+    pub(super) fn packet(&self) -> &[u8] {
+        self.packet
+            .as_ref()
+            .and_then(
+                |Packet {
+                     region, start, end, ..
+                 }| { self.regions.get(*region)?.data.get(*start..*end) },
+            )
+            .unwrap_or_default()
+    }
+
+    /// Returns the region starting at `address`.
+    pub(super) fn region(&self, address: u64) -> &[u8] {
+        split(address)
+            .and_then(|(id, _)| self.region_data(id))
+            .unwrap_or_default()
+    }
+
+    /// Runs the program with `ctx` in r1 and returns r0.
+    pub(super) fn run(&mut self, ctx: u64) -> Result<u64, HarnessError> {
+        let mut regs = [0u64; 11];
+        regs[1] = ctx;
+        regs[10] = self.add_stack();
+        let mut frames = Vec::new();
+        let mut pc = 0;
+        for _ in 0..Harness::INSTRUCTION_LIMIT {
+            let insn = self
+                .instructions
+                .get(pc)
+                .ok_or(HarnessError::InvalidJump { pc })?;
+            let invalid = HarnessError::InvalidInstruction {
+                pc,
+                code: insn.code,
+            };
+            let code = u32::from(insn.code);
+            let (dst, src) = (usize::from(insn.dst_reg()), usize::from(insn.src_reg()));
+            if dst > 10 || src > 10 {
+                return Err(invalid);
+            }
+            let imm = i64::from(insn.imm) as u64;
+            let off = i64::from(insn.off);
+            let mut next = pc + 1;
+            match code & 0x07 {
+                class @ (BPF_ALU | BPF_ALU64) => {
+                    let op = code & 0xf0;
+                    regs[dst] = if op == BPF_END {
+                        swap(class == BPF_ALU64, code & BPF_X != 0, insn.imm, regs[dst])
+                    } else {
+                        let operand = if code & BPF_X == 0 { imm } else { regs[src] };
+                        if class == BPF_ALU64 {
+                            alu64(op, insn.off, regs[dst], operand)
+                        } else {
+                            alu32(op, insn.off, regs[dst] as u32, operand as u32).map(u64::from)
+                        }
+                    }
+                    .ok_or(invalid)?;
+                }
+                class @ (BPF_JMP | BPF_JMP32) => match (code & 0xf0, class) {
+                    (BPF_CALL, BPF_JMP) => match src as u32 {
+                        BPF_PSEUDO_CALL => {
+                            if frames.len() >= MAX_CALL_DEPTH {
+                                return Err(HarnessError::CallDepth { pc });
+                            }
+                            let mut registers = [0; 5];
+                            registers.copy_from_slice(&regs[6..]);
+                            frames.push(Frame {
+                                return_pc: next,
+                                registers,
+                            });
+                            regs[10] = self.add_stack();
+                            next = jump(next, i64::from(insn.imm))?;
+                        }
+                        0 => {
+                            let args = [regs[1], regs[2], regs[3], regs[4], regs[5]];
+                            regs[0] = self.call_helper(pc, insn.imm, args)?;
+                        }
+                        _ => return Err(HarnessError::UnsupportedHelper { pc, id: insn.imm }),
+                    },
+                    (BPF_EXIT, BPF_JMP) => match frames.pop() {
+                        Some(Frame {
+                            return_pc,
+                            registers,
+                        }) => {
+                            regs[6..].copy_from_slice(&registers);
+                            next = return_pc;
+                        }
+                        None => return Ok(regs[0]),
+                    },
+                    (BPF_JA, BPF_JMP) => next = jump(next, off)?,
+                    (BPF_JA, _) => next = jump(next, i64::from(insn.imm))?,
+                    // `may_goto` only jumps once a loop has run too long.
+                    (BPF_JCOND, _) => {}
+                    (op, _) => {
+                        let operand = if code & BPF_X == 0 { imm } else { regs[src] };
+                        let taken = if class == BPF_JMP {
+                            condition(op, regs[dst], operand, regs[dst] as i64, operand as i64)
+                        } else {
+                            condition(
+                                op,
+                                u64::from(regs[dst] as u32),
+                                u64::from(operand as u32),
+                                i64::from(regs[dst] as i32),
+                                i64::from(operand as i32),
+                            )
+                        }
+                        .ok_or(invalid)?;
+                        if taken {
+                            next = jump(next, off)?;
+                        }
+                    }
+                },
+                BPF_LD => match code & 0xe0 {
+                    BPF_IMM if code & 0x18 == BPF_DW => {
+                        let high = self
+                            .instructions
+                            .get(next)
+                            .ok_or(HarnessError::InvalidJump { pc: next })?
+                            .imm;
+                        regs[dst] = match src as u32 {
+                            0 => u64::from(insn.imm as u32) | (u64::from(high as u32) << 32),
+                            BPF_PSEUDO_MAP_FD => {
+                                MAP_HANDLE | self.map(pc, u64::from(insn.imm as u32))? as u64
+                            }
+                            BPF_PSEUDO_MAP_VALUE => {
+                                let map = self.map(pc, u64::from(insn.imm as u32))?;
+                                region_address(map, high as u32 as usize)
+                            }
+                            _ => return Err(invalid),
+                        };
+                        next += 1;
+                    }
+                    mode @ (BPF_ABS | BPF_IND) => {
+                        let mut offset = i64::from(insn.imm);
+                        if mode == BPF_IND {
+                            offset += i64::from(regs[src] as i32);
+                        }
+                        // Loads out of the packet end the program.
+                        let Some(bytes) = usize::try_from(offset)
+                            .ok()
+                            .and_then(|offset| self.packet_bytes(offset, access_size(code)))
+                        else {
+                            return Ok(0);
+                        };
+                        // Packet data is in network byte order.
+                        regs[0] = bytes
+                            .iter()
+                            .fold(0, |value, byte| (value << 8) | u64::from(*byte));
+                    }
+                    _ => return Err(invalid),
+                },
+                BPF_LDX => {
+                    let size = access_size(code);
+                    let value = self.load(pc, regs[src].wrapping_add(off as u64), size)?;
+                    regs[dst] = match code & 0xe0 {
+                        BPF_MEM => value,
+                        BPF_MEMSX => sign_extend(value, size),
+                        _ => return Err(invalid),
+                    };
+                }
+                BPF_ST if code & 0xe0 == BPF_MEM => {
+                    let address = regs[dst].wrapping_add(off as u64);
+                    self.store(pc, address, access_size(code), imm)?;
+                }
+                BPF_STX => {
+                    let address = regs[dst].wrapping_add(off as u64);
+                    let size = access_size(code);
+                    match code & 0xe0 {
+                        BPF_MEM => self.store(pc, address, size, regs[src])?,
+                        BPF_ATOMIC if size >= 4 => {
+                            let old = self.load(pc, address, size)?;
+                            let value = truncate(regs[src], size);
+                            match insn.imm as u32 {
+                                BPF_XCHG => {
+                                    self.store(pc, address, size, value)?;
+                                    regs[src] = old;
+                                }
+                                BPF_CMPXCHG => {
+                                    if truncate(regs[0], size) == old {
+                                        self.store(pc, address, size, value)?;
+                                    }
+                                    regs[0] = old;
+                                }
+                                op => {
+                                    let new = match op & !BPF_FETCH {
+                                        BPF_ADD => old.wrapping_add(value),
+                                        BPF_OR => old | value,
+                                        BPF_AND => old & value,
+                                        BPF_XOR => old ^ value,
+                                        _ => return Err(invalid),
+                                    };
+                                    self.store(pc, address, size, new)?;
+                                    if op & BPF_FETCH != 0 {
+                                        regs[src] = old;
+                                    }
+                                }
+                            }
+                        }
+                        _ => return Err(invalid),
+                    }
+                }
+                _ => return Err(invalid),
+            }
+            pc = next;
+        }
+        Err(HarnessError::InstructionLimit)
+    }
+
+    fn add_region(&mut self, data: Vec<u8>, kind: RegionKind) -> u64 {
+        self.regions.push(Region { data, kind });
+        region_address(self.state.maps.len() + self.regions.len() - 1, 0)
+    }
+
+    /// Adds a stack frame and returns its frame pointer.
+    fn add_stack(&mut self) -> u64 {
+        self.add_region(vec![0; STACK_SIZE], RegionKind::Memory) + STACK_SIZE as u64
+    }
+
+    /// Returns the data of region `id`.
+    fn region_data(&self, id: usize) -> Option<&[u8]> {
+        if let Some(map) = self.state.maps.get(id) {
+            return Some(&map.values);
+        }
+        let region = self.regions.get(id - self.state.maps.len())?;
+        match region.kind {
+            RegionKind::Released => None,
+            RegionKind::Memory | RegionKind::Context(_) | RegionKind::Reservation { .. } => {
+                Some(&region.data)
+            }
+        }
+    }
+
+    fn bytes(&self, address: u64, len: usize) -> Option<&[u8]> {
+        let (id, offset) = split(address)?;
+        self.region_data(id)?.get(offset..offset.checked_add(len)?)
+    }
+
+    /// Returns the writable bytes at `address`. Values of `.rodata` maps aren't writable.
+    fn bytes_mut(&mut self, address: u64, len: usize) -> Option<&mut [u8]> {
+        let (id, offset) = split(address)?;
+        let maps = self.state.maps.len();
+        let data = if let Some(map) = self.state.maps.get_mut(id) {
+            if map.readonly() {
+                return None;
+            }
+            &mut map.values
+        } else {
+            let region = self.regions.get_mut(id - maps)?;
+            if matches!(region.kind, RegionKind::Released) {
+                return None;
+            }
+            &mut region.data
+        };
+        data.get_mut(offset..offset.checked_add(len)?)
+    }
+
+    /// Returns the packet pointer a load of `address` yields, if it's a pointer field of the
+    /// context.
+    fn context_pointer(&self, address: u64) -> Option<u64> {
+        let (id, offset) = split(address)?;
+        let region = self.regions.get(id.checked_sub(self.state.maps.len())?)?;
+        let RegionKind::Context(pointers) = region.kind else {
+            return None;
+        };
+        let (_, pointer) = pointers.iter().find(|(field, _)| *field == offset)?;
+        let Packet {
+            region,
+            start,
+            end,
+            meta,
+        } = self.packet.as_ref()?;
+        let offset = match pointer {
+            Pointer::Data => start,
+            Pointer::DataEnd => end,
+            Pointer::DataMeta => meta,
+        };
+        Some(region_address(self.state.maps.len() + region, *offset))
+    }
+
+    fn packet_bytes(&self, offset: usize, len: usize) -> Option<&[u8]> {
+        let Packet {
+            region, start, end, ..
+        } = self.packet.as_ref()?;
+        let packet = self.regions.get(*region)?.data.get(*start..*end)?;
+        packet.get(offset..offset.checked_add(len)?)
+    }
+
+    fn load(&self, pc: usize, address: u64, size: usize) -> Result<u64, HarnessError> {
+        if size >= 4
+            && let Some(pointer) = self.context_pointer(address)
+        {
+            return Ok(pointer);
+        }
+        self.bytes(address, size)
+            .map(read_uint)
+            .ok_or(HarnessError::InvalidMemoryAccess { pc, address, size })
+    }
+
+    fn store(
+        &mut self,
+        pc: usize,
+        address: u64,
+        size: usize,
+        value: u64,
+    ) -> Result<(), HarnessError> {
+        let bytes = self
+            .bytes_mut(address, size)
+            .ok_or(HarnessError::InvalidMemoryAccess { pc, address, size })?;
+        write_uint(bytes, value);
+        Ok(())
+    }
+
+    fn read(&self, pc: usize, address: u64, size: usize) -> Result<Vec<u8>, HarnessError> {
+        self.bytes(address, size)
+            .map(<[u8]>::to_vec)
+            .ok_or(HarnessError::InvalidMemoryAccess { pc, address, size })
+    }
+
+    fn write(&mut self, pc: usize, address: u64, data: &[u8]) -> Result<(), HarnessError> {
+        let size = data.len();
+        self.bytes_mut(address, size)
+            .ok_or(HarnessError::InvalidMemoryAccess { pc, address, size })?
+            .copy_from_slice(data);
+        Ok(())
+    }
+
+    /// Reads at most `len` bytes at `address` on behalf of `probe_read*`, which may also read
+    /// the memory added with `Harness::add_memory`.
+    fn read_unsafe(&self, address: u64, len: usize) -> &[u8] {
+        let memory = self.state.memory.iter().find_map(|(base, data)| {
+            let offset = usize::try_from(address.checked_sub(*base)?).ok()?;
+            data.get(offset..)
+        });
+        let memory = memory.or_else(|| {
+            let (id, offset) = split(address)?;
+            self.region_data(id)?.get(offset..)
+        });
+        memory.map_or(&[], |memory| &memory[..len.min(memory.len())])
+    }
+
+    /// Returns the index of the map `handle` refers to.
+    const fn map(&self, pc: usize, handle: u64) -> Result<usize, HarnessError> {
+        let index = (handle & !MAP_HANDLE) as usize;
+        if index < self.state.maps.len() {
+            Ok(index)
+        } else {
+            Err(HarnessError::InvalidMap { pc, handle })
+        }
+    }
+
+    fn call_helper(&mut self, pc: usize, id: i32, args: [u64; 5]) -> Result<u64, HarnessError> {
+        if let Some(helper) = self.state.helpers.get_mut(&id) {
+            return Ok(helper(args));
+        }
+        let [a1, a2, a3, a4, a5] = args;
+        let environment = &self.state.environment;
+        let ret = match id {
+            MAP_LOOKUP_ELEM => {
+                let map = self.map(pc, a1)?;
+                let key = self.read(pc, a2, self.state.maps[map].key_size())?;
+                Ok(self.state.maps[map]
+                    .offset(&key)
+                    .map_or(0, |offset| region_address(map, offset)))
+            }
+            MAP_UPDATE_ELEM => {
+                let map = self.map(pc, a1)?;
+                let key = self.read(pc, a2, self.state.maps[map].key_size())?;
+                let value = self.read(pc, a3, self.state.maps[map].value_size())?;
+                self.state.maps[map].update(&key, &value, a4).map(|()| 0)
+            }
+            MAP_DELETE_ELEM => {
+                let map = self.map(pc, a1)?;
+                let key = self.read(pc, a2, self.state.maps[map].key_size())?;
+                self.state.maps[map].delete(&key).map(|()| 0)
+            }
+            MAP_PUSH_ELEM => {
+                let map = self.map(pc, a1)?;
+                let value = self.read(pc, a2, self.state.maps[map].value_size())?;
+                self.state.maps[map].push_elem(&value, a3).map(|()| 0)
+            }
+            MAP_POP_ELEM | MAP_PEEK_ELEM => {
+                let map = self.map(pc, a1)?;
+                match self.state.maps[map].pop_elem(id == MAP_PEEK_ELEM) {
+                    Ok(value) => self.write(pc, a2, &value).map(|()| Ok(0))?,
+                    Err(errno) => Err(errno),
+                }
+            }
+            PROBE_READ | PROBE_READ_USER | PROBE_READ_KERNEL => {
+                let size = a2 as u32 as usize;
+                let data = self.read_unsafe(a3, size).to_vec();
+                if data.len() == size {
+                    self.write(pc, a1, &data)?;
+                    Ok(0)
+                } else {
+                    self.write(pc, a1, &vec![0; size])?;
+                    Err(-EFAULT)
+                }
+            }
+            PROBE_READ_STR | PROBE_READ_USER_STR | PROBE_READ_KERNEL_STR => {
+                let size = a2 as u32 as usize;
+                let data = self.read_unsafe(a3, size.saturating_sub(1));
+                if data.is_empty() && size > 1 {
+                    Err(-EFAULT)
+                } else {
+                    let mut data = data
+                        .split(|byte| *byte == 0)
+                        .next()
+                        .unwrap_or_default()
+                        .to_vec();
+                    data.push(0);
+                    self.write(pc, a1, &data)?;
+                    Ok(data.len() as u64)
+                }
+            }
+            KTIME_GET_NS | KTIME_GET_BOOT_NS | KTIME_GET_COARSE_NS | KTIME_GET_TAI_NS => {
+                Ok(environment.ktime_ns)
+            }
+            GET_PRANDOM_U32 => Ok(self.random()),
+            GET_SMP_PROCESSOR_ID => Ok(environment.cpu.into()),
+            GET_CURRENT_PID_TGID => {
+                Ok((u64::from(environment.tgid) << 32) | u64::from(environment.pid))
+            }
+            GET_CURRENT_UID_GID => {
+                Ok((u64::from(environment.gid) << 32) | u64::from(environment.uid))
+            }
+            GET_CURRENT_COMM => {
+                let mut comm = environment.comm.to_vec();
+                comm.resize(a2 as u32 as usize, 0);
+                if let Some(last) = comm.last_mut() {
+                    *last = 0;
+                }
+                self.write(pc, a1, &comm)?;
+                Ok(0)
+            }
+            TRACE_PRINTK => {
+                let fmt = self.read(pc, a1, a2 as u32 as usize)?;
+                let message = self.format(&fmt, [a3, a4, a5]);
+                let len = message.len() as u64;
+                self.state.trace_log.push(message);
+                Ok(len)
+            }
+            PERF_EVENT_OUTPUT => {
+                let map = self.map(pc, a2)?;
+                let data = self.read(pc, a4, a5 as usize)?;
+                self.state.maps[map].output(data).map(|()| 0)
+            }
+            RINGBUF_OUTPUT => {
+                let map = self.map(pc, a1)?;
+                let data = self.read(pc, a2, a3 as usize)?;
+                self.state.maps[map].output(data).map(|()| 0)
+            }
+            RINGBUF_RESERVE => {
+                let map = self.map(pc, a1)?;
+                if !self.state.maps[map].is_ringbuf() {
+                    return Err(HarnessError::InvalidMap { pc, handle: a1 });
+                }
+                Ok(self.add_region(vec![0; a2 as usize], RegionKind::Reservation { map }))
+            }
+            RINGBUF_SUBMIT | RINGBUF_DISCARD => {
+                let region = split(a1)
+                    .filter(|(_, offset)| *offset == 0)
+                    .and_then(|(id, _)| {
+                        self.regions.get_mut(id.checked_sub(self.state.maps.len())?)
+                    })
+                    .filter(|region| matches!(region.kind, RegionKind::Reservation { .. }))
+                    .ok_or(HarnessError::InvalidMemoryAccess {
+                        pc,
+                        address: a1,
+                        size: 0,
+                    })?;
+                let RegionKind::Reservation { map } =
+                    std::mem::replace(&mut region.kind, RegionKind::Released)
+                else {
+                    return Err(HarnessError::InvalidMap { pc, handle: a1 });
+                };
+                let data = std::mem::take(&mut region.data);
+                if id == RINGBUF_SUBMIT {
+                    self.state.maps[map].output(data).map(|()| 0)
+                } else {
+                    Ok(0)
+                }
+            }
+            SKB_LOAD_BYTES => {
+                match self
+                    .packet_bytes(a2 as u32 as usize, a4 as u32 as usize)
+                    .map(<[u8]>::to_vec)
+                {
+                    Some(data) => {
+                        self.write(pc, a3, &data)?;
+                        Ok(0)
+                    }
+                    None => Err(-EFAULT),
+                }
+            }
+            XDP_ADJUST_HEAD => self.adjust_packet(i64::from(a2 as i32), 0),
+            XDP_ADJUST_TAIL => self.adjust_packet(0, i64::from(a2 as i32)),
+            _ => return Err(HarnessError::UnsupportedHelper { pc, id }),
+        };
+        Ok(ret.unwrap_or_else(|errno| errno as u64))
+    }
+
+    /// Moves the start and the end of the packet, as `xdp_adjust_head` and `xdp_adjust_tail` do.
+    fn adjust_packet(&mut self, head: i64, tail: i64) -> Result<u64, i64> {
+        let Some(Packet {
+            region,
+            start,
+            end,
+            meta,
+        }) = &mut self.packet
+        else {
+            return Err(-EINVAL);
+        };
+        let len = self.regions[*region].data.len();
+        let new_start = start.checked_add_signed(head as isize).ok_or(-EINVAL)?;
+        let new_end = end.checked_add_signed(tail as isize).ok_or(-EINVAL)?;
+        if new_start + ETH_HLEN > new_end || new_end > len {
+            return Err(-EINVAL);
+        }
+        if new_end > *end {
+            self.regions[*region].data[*end..new_end].fill(0);
+        }
+        *start = new_start;
+        *end = new_end;
+        *meta = new_start;
+        Ok(0)
+    }
+
+    /// Returns a pseudo random number, with xorshift64*.
+    const fn random(&mut self) -> u64 {
+        let mut x = self.state.random;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.state.random = x;
+        x.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 32
+    }
+
+    /// Formats a `trace_printk` message.
+    fn format(&self, fmt: &[u8], args: [u64; 3]) -> String {
+        let fmt = fmt.split(|byte| *byte == 0).next().unwrap_or_default();
+        let fmt = String::from_utf8_lossy(fmt);
+        let mut args = args.into_iter();
+        let mut message = String::new();
+        let mut chars = fmt.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                message.push(c);
+                continue;
+            }
+            if chars.next_if_eq(&'%').is_some() {
+                message.push('%');
+                continue;
+            }
+            let zero = chars.next_if_eq(&'0').is_some();
+            let mut width = 0;
+            while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+                width = width * 10 + digit.to_digit(10).unwrap_or_default() as usize;
+            }
+            let mut long = false;
+            while let Some(modifier) = chars.next_if(|c| matches!(c, 'l' | 'h')) {
+                long |= modifier == 'l';
+            }
+            let Some(conversion) = chars.next() else {
+                break;
+            };
+            let arg = args.next().unwrap_or_default();
+            let arg32 = arg as u32;
+            let formatted = match (conversion, long) {
+                ('d' | 'i', true) => (arg as i64).to_string(),
+                ('d' | 'i', false) => (arg32 as i32).to_string(),
+                ('u', true) => arg.to_string(),
+                ('u', false) => arg32.to_string(),
+                ('x', true) => format!("{arg:x}"),
+                ('x', false) => format!("{arg32:x}"),
+                ('X', true) => format!("{arg:X}"),
+                ('X', false) => format!("{arg32:X}"),
+                ('p', _) => format!("0x{arg:x}"),
+                ('c', _) => char::from(arg as u8).to_string(),
+                ('s', _) => {
+                    let s = self.read_unsafe(arg, 256);
+                    let s = s.split(|byte| *byte == 0).next().unwrap_or_default();
+                    String::from_utf8_lossy(s).into_owned()
+                }
+                (conversion, _) => format!("%{conversion}"),
+            };
+            let pad = if zero { '0' } else { ' ' };
+            message.extend(std::iter::repeat_n(
+                pad,
+                width.saturating_sub(formatted.len()),
+            ));
+            message.push_str(&formatted);
+        }
+        message
+    }
+}
+
+const fn region_address(id: usize, offset: usize) -> u64 {
+    ((id as u64 + 1) << 32) + offset as u64
+}
+
+/// Splits `address` into a region id and an offset.
+fn split(address: u64) -> Option<(usize, usize)> {
+    let id = (address >> 32).checked_sub(1)?;
+    Some((id as usize, address as u32 as usize))
+}
+
+fn jump(next: usize, offset: i64) -> Result<usize, HarnessError> {
+    next.checked_add_signed(offset as isize)
+        .ok_or(HarnessError::InvalidJump { pc: next })
+}
+
+const fn access_size(code: u32) -> usize {
+    match code & 0x18 {
+        BPF_W => 4,
+        BPF_H => 2,
+        BPF_B => 1,
+        _ => 8,
+    }
+}
+
+const fn truncate(value: u64, size: usize) -> u64 {
+    if size == 4 {
+        value as u32 as u64
+    } else {
+        value
+    }
+}
+
+const fn sign_extend(value: u64, size: usize) -> u64 {
+    match size {
+        1 => value as i8 as u64,
+        2 => value as i16 as u64,
+        4 => value as i32 as u64,
+        _ => value,
+    }
+}
+
+fn read_uint(bytes: &[u8]) -> u64 {
+    match bytes.len() {
+        1 => bytes[0].into(),
+        2 => u16::from_ne_bytes(bytes.try_into().unwrap()).into(),
+        4 => u32::from_ne_bytes(bytes.try_into().unwrap()).into(),
+        _ => u64::from_ne_bytes(bytes.try_into().unwrap()),
+    }
+}
+
+const fn write_uint(bytes: &mut [u8], value: u64) {
+    match bytes.len() {
+        1 => bytes.copy_from_slice(&[value as u8]),
+        2 => bytes.copy_from_slice(&(value as u16).to_ne_bytes()),
+        4 => bytes.copy_from_slice(&(value as u32).to_ne_bytes()),
+        _ => bytes.copy_from_slice(&value.to_ne_bytes()),
+    }
+}
+
+/// Implements `BPF_END`: byte swaps for `BPF_ALU64`, conversions from little or big endian for
+/// `BPF_ALU`.
+fn swap(alu64: bool, to_be: bool, width: i32, value: u64) -> Option<u64> {
+    Some(match (alu64, to_be, width) {
+        (true, _, 16) => (value as u16).swap_bytes().into(),
+        (true, _, 32) => (value as u32).swap_bytes().into(),
+        (true, _, 64) => value.swap_bytes(),
+        (false, false, 16) => (value as u16).to_le().into(),
+        (false, false, 32) => (value as u32).to_le().into(),
+        (false, false, 64) => value.to_le(),
+        (false, true, 16) => (value as u16).to_be().into(),
+        (false, true, 32) => (value as u32).to_be().into(),
+        (false, true, 64) => value.to_be(),
+        _ => return None,
+    })
+}
+
+const fn alu64(op: u32, off: i16, dst: u64, src: u64) -> Option<u64> {
+    Some(match op {
+        BPF_ADD => dst.wrapping_add(src),
+        BPF_SUB => dst.wrapping_sub(src),
+        BPF_MUL => dst.wrapping_mul(src),
+        BPF_DIV if src == 0 => 0,
+        BPF_DIV if off == 1 => (dst as i64).wrapping_div(src as i64) as u64,
+        BPF_DIV => dst / src,
+        BPF_MOD if src == 0 => dst,
+        BPF_MOD if off == 1 => (dst as i64).wrapping_rem(src as i64) as u64,
+        BPF_MOD => dst.wrapping_rem(src),
+        BPF_OR => dst | src,
+        BPF_AND => dst & src,
+        BPF_XOR => dst ^ src,
+        BPF_LSH => dst.wrapping_shl(src as u32),
+        BPF_RSH => dst.wrapping_shr(src as u32),
+        BPF_ARSH => (dst as i64).wrapping_shr(src as u32) as u64,
+        BPF_NEG => dst.wrapping_neg(),
+        BPF_MOV => match off {
+            0 => src,
+            8 | 16 | 32 => sign_extend(src, off as usize / 8),
+            _ => return None,
+        },
+        _ => return None,
+    })
+}
+
+const fn alu32(op: u32, off: i16, dst: u32, src: u32) -> Option<u32> {
+    Some(match op {
+        BPF_ADD => dst.wrapping_add(src),
+        BPF_SUB => dst.wrapping_sub(src),
+        BPF_MUL => dst.wrapping_mul(src),
+        BPF_DIV if src == 0 => 0,
+        BPF_DIV if off == 1 => (dst as i32).wrapping_div(src as i32) as u32,
+        BPF_DIV => dst / src,
+        BPF_MOD if src == 0 => dst,
+        BPF_MOD if off == 1 => (dst as i32).wrapping_rem(src as i32) as u32,
+        BPF_MOD => dst.wrapping_rem(src),
+        BPF_OR => dst | src,
+        BPF_AND => dst & src,
+        BPF_XOR => dst ^ src,
+        BPF_LSH => dst.wrapping_shl(src),
+        BPF_RSH => dst.wrapping_shr(src),
+        BPF_ARSH => (dst as i32).wrapping_shr(src) as u32,
+        BPF_NEG => dst.wrapping_neg(),
+        BPF_MOV => match off {
+            0 => src,
+            8 | 16 => sign_extend(src as u64, off as usize / 8) as u32,
+            _ => return None,
+        },
+        _ => return None,
+    })
+}
+
+const fn condition(op: u32, a: u64, b: u64, signed_a: i64, signed_b: i64) -> Option<bool> {
+    Some(match op {
+        BPF_JEQ => a == b,
+        BPF_JNE => a != b,
+        BPF_JGT => a > b,
+        BPF_JGE => a >= b,
+        BPF_JLT => a < b,
+        BPF_JLE => a <= b,
+        BPF_JSET => a & b != 0,
+        BPF_JSGT => signed_a > signed_b,
+        BPF_JSGE => signed_a >= signed_b,
+        BPF_JSLT => signed_a < signed_b,
+        BPF_JSLE => signed_a <= signed_b,
+        _ => return None,
+    })
+}
@@ -90,12 +90,19 @@ mod extern_types;
     reason = "generated code"
 )]
 pub mod generated;
+pub mod harness;
+pub mod linker;
 pub mod links;
+pub mod lint;
 pub mod maps;
 pub mod obj;
 pub mod programs;
 pub mod relocation;
+pub mod skeleton;
+#[cfg(test)]
+mod test_utils;
 mod util;
+pub mod verifier;
 
 pub use extern_types::KsymsError;
 pub use maps::Map;
@@ -0,0 +1,1213 @@
+//! Static linking of objects.
+//!
+//! Programs split across several crates are compiled to several objects. [`Object::link`] merges
+//! them into a single [`Object`], as if they had been compiled together:
+//!
+//! - maps defined with the same name and definition in several objects are shared,
+//! - data sections with the same name, such as `.rodata`, are concatenated,
+//! - `extern` functions, variables and maps are resolved to their definition in another object,
+//!   and strong definitions override weak ones,
+//! - the BTF of the objects is merged, deduplicating equivalent types, including recursive types
+//!   and forward declarations, and the `.BTF.ext` function, line and CO-RE relocation records are
+//!   rebased on the merged BTF.
+//!
+//! Conflicting definitions, such as two programs or functions with the same name or two maps with
+//! the same name and different definitions, are reported as [`LinkError`]s.
+//!
+//! The result is an in-memory [`Object`], ready to be loaded. Linking doesn't produce an ELF file:
+//! use `bpftool gen object` to write linked objects to disk.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, hash_map::Entry},
+    mem,
+};
+
+use object::{SectionIndex, SymbolKind};
+
+use crate::{
+    EbpfSectionKind, Object,
+    btf::{Btf, BtfError, BtfType, DataSec, DataSecEntry, Struct, Union},
+    extern_types::ExternCollection,
+    generated::{BPF_CALL, BPF_JMP, BPF_PSEUDO_CALL},
+    maps::Map,
+    relocation::{INS_SIZE, Symbol},
+    util::HashMap,
+};
+
+/// The error type returned by [`Object::link`].
+#[derive(thiserror::Error, Debug)]
+pub enum LinkError {
+    /// No objects were given.
+    #[error("no objects to link")]
+    NoObjects,
+
+    /// The objects don't have the same endianness.
+    #[error("the objects don't have the same endianness")]
+    Endianness,
+
+    /// A program is defined in several objects.
+    #[error("program `{name}` is defined in several objects")]
+    DuplicateProgram {
+        /// The program name
+        name: String,
+    },
+
+    /// A symbol has several strong definitions.
+    #[error("symbol `{name}` is defined in several objects")]
+    DuplicateSymbol {
+        /// The symbol name
+        name: String,
+    },
+
+    /// A map is defined in several objects with different definitions.
+    #[error("map `{name}` is defined in several objects with different definitions")]
+    MapConflict {
+        /// The map name
+        name: String,
+    },
+
+    /// Some `extern` symbols are not defined in any object and are not kernel symbols.
+    #[error("undefined symbols: {}", .names.join(", "))]
+    UndefinedSymbols {
+        /// The symbol names
+        names: Vec<String>,
+    },
+
+    /// Error merging BTF.
+    #[error(transparent)]
+    Btf(#[from] BtfError),
+}
+
+impl Object {
+    /// Links `objects` into a single object.
+    ///
+    /// See the [module documentation](self) for how definitions are merged.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use aya_obj::Object;
+    ///
+    /// let objects = ["probes.o", "filters.o"]
+    ///     .into_iter()
+    ///     .map(|path| Ok(Object::parse(&std::fs::read(path)?)?))
+    ///     .collect::<Result<Vec<_>, Box<dyn std::error::Error>>>()?;
+    /// let object = Object::link(objects)?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn link<I: IntoIterator<Item = Self>>(objects: I) -> Result<Self, LinkError> {
+        let mut objects = objects.into_iter();
+        let mut linked = objects.next().ok_or(LinkError::NoObjects)?;
+        let mut merged = false;
+        for object in objects {
+            linked.merge(object)?;
+            merged = true;
+        }
+        if merged && let Some(btf) = &mut linked.btf {
+            // The `.ksyms` sections of the objects have been merged, collect their externs again.
+            btf.externs = ExternCollection::new();
+            linked.collect_ksyms_from_btf()?;
+        }
+        linked.resolve_symbols()?;
+        Ok(linked)
+    }
+
+    /// Returns the first section index not used by the object.
+    fn next_section_index(&self) -> usize {
+        self.section_infos
+            .values()
+            .map(|(index, _)| index.0)
+            .chain(self.functions.keys().map(|(index, _)| *index))
+            .chain(self.maps.values().map(Map::section_index))
+            .chain(
+                self.symbol_table
+                    .values()
+                    .filter_map(|symbol| symbol.section_index),
+            )
+            .chain(self.relocations.keys().map(|index| index.0))
+            .max()
+            .map_or(0, |index| index + 1)
+    }
+
+    /// Merges `other` into `self`.
+    ///
+    /// Sections of `other` are renumbered after the sections of `self`, except for data and map
+    /// sections that exist in both objects, which are appended to the sections of `self`.
+    /// Symbols are renumbered after the symbols of `self`.
+    fn merge(&mut self, other: Self) -> Result<(), LinkError> {
+        let Self {
+            endianness,
+            license: _,
+            kernel_version,
+            btf,
+            btf_ext,
+            maps,
+            programs,
+            functions,
+            relocations,
+            symbol_table,
+            symbols_by_section,
+            section_infos,
+            symbol_offset_by_name,
+        } = other;
+        if endianness != self.endianness {
+            return Err(LinkError::Endianness);
+        }
+        if let Some(name) = programs
+            .keys()
+            .find(|name| self.programs.contains_key(*name))
+        {
+            return Err(LinkError::DuplicateProgram { name: name.clone() });
+        }
+        self.kernel_version = self.kernel_version.or(kernel_version);
+
+        let section_offset = self.next_section_index();
+        let symbol_offset = self.symbol_table.keys().max().map_or(0, |index| index + 1);
+
+        // Maps the section indices of `other` to the section index and address shift in `self`.
+        let mut sections = HashMap::new();
+        let mut shifts = HashMap::new();
+        let mut renamed = HashMap::new();
+        let mut section_infos = section_infos.into_iter().collect::<Vec<_>>();
+        section_infos.sort_by_key(|(_, (index, _))| index.0);
+        for (name, (SectionIndex(index), size)) in section_infos {
+            let mergeable = matches!(
+                EbpfSectionKind::from_name(&name),
+                EbpfSectionKind::Bss
+                    | EbpfSectionKind::Data
+                    | EbpfSectionKind::Rodata
+                    | EbpfSectionKind::Maps
+                    | EbpfSectionKind::BtfMaps
+            );
+            if mergeable
+                && let Some((SectionIndex(base_index), base_size)) =
+                    self.section_infos.get_mut(&name)
+            {
+                let shift = base_size.next_multiple_of(8);
+                *base_size = shift + size;
+                let _unused: Option<(usize, u64)> = sections.insert(index, (*base_index, shift));
+                let _unused: Option<u64> = shifts.insert(name, shift);
+            } else {
+                let new_index = index + section_offset;
+                let _unused: Option<(usize, u64)> = sections.insert(index, (new_index, 0));
+                // Section names are how CO-RE relocations find their section, keep them unique.
+                let mut new_name = name.clone();
+                let mut n = 1;
+                while self.section_infos.contains_key(&new_name) {
+                    new_name = format!("{name}.{n}");
+                    n += 1;
+                }
+                let _unused: Option<(SectionIndex, u64)> = self
+                    .section_infos
+                    .insert(new_name.clone(), (SectionIndex(new_index), size));
+                if new_name != name {
+                    let _unused: Option<String> = renamed.insert(name, new_name);
+                }
+            }
+        }
+        let section = |index: usize| {
+            sections
+                .get(&index)
+                .copied()
+                .unwrap_or((index + section_offset, 0))
+        };
+
+        let mut type_ids = Vec::new();
+        let strings = if let Some(other_btf) = &btf {
+            let base_btf = self.btf.get_or_insert_with(Btf::new);
+            let string_map = StringMap::new(base_btf, other_btf);
+            type_ids = merge_types(base_btf, other_btf, &string_map, &shifts)?;
+            if let Some(mut other_ext) = btf_ext {
+                let mut relocations = mem::take(other_ext.relocations_mut());
+                for (section_name, relocations) in &mut relocations {
+                    let name = other_btf.string_at(*section_name)?;
+                    *section_name = match renamed.get(name.as_ref()) {
+                        Some(name) => base_btf.add_string(name),
+                        None => string_map.get(*section_name),
+                    };
+                    for relocation in relocations {
+                        relocation.remap(
+                            &mut |id| type_ids.get(id as usize).copied().unwrap_or(id),
+                            &mut |offset| string_map.get(offset),
+                        );
+                    }
+                }
+                if let Some(base_ext) = &mut self.btf_ext {
+                    base_ext.relocations_mut().extend(relocations);
+                } else {
+                    *other_ext.relocations_mut() = relocations;
+                    self.btf_ext = Some(other_ext);
+                }
+            }
+            Some(string_map)
+        } else {
+            None
+        };
+        let type_id = |id: u32| type_ids.get(id as usize).copied().unwrap_or(id);
+        let string = |offset: u32| {
+            strings
+                .as_ref()
+                .map_or(offset, |strings| strings.get(offset))
+        };
+
+        let mut symbol_shifts = HashMap::new();
+        for (index, mut symbol) in symbol_table {
+            symbol.index = index + symbol_offset;
+            if let Some(section_index) = symbol.section_index {
+                let (section_index, shift) = section(section_index);
+                symbol.section_index = Some(section_index);
+                symbol.address += shift;
+                if let Some(name) = &symbol.name {
+                    let _unused: Option<u64> = symbol_shifts.insert(name.clone(), shift);
+                }
+            }
+            let _unused: Option<Symbol> = self.symbol_table.insert(symbol.index, symbol);
+        }
+        for (name, offset) in symbol_offset_by_name {
+            let shift = symbol_shifts.get(&name).copied().unwrap_or(0);
+            let _unused: &mut u64 = self
+                .symbol_offset_by_name
+                .entry(name)
+                .or_insert(offset + shift);
+        }
+        for (SectionIndex(index), symbols) in symbols_by_section {
+            self.symbols_by_section
+                .entry(SectionIndex(section(index).0))
+                .or_default()
+                .extend(symbols.into_iter().map(|index| index + symbol_offset));
+        }
+
+        // Maps the symbols of maps shared with `self` to the symbols of `self`.
+        let mut aliases = HashMap::new();
+        for (name, mut map) in maps {
+            let (section_index, shift) = section(map.section_index());
+            match (self.maps.get_mut(&name), map.symbol_index()) {
+                // A data section present in both objects.
+                (Some(base), None) if base.symbol_index().is_none() => {
+                    let data = base.data_mut();
+                    data.resize(shift as usize, 0);
+                    data.extend_from_slice(map.data());
+                    base.set_value_size(shift as u32 + map.value_size());
+                }
+                (Some(base), Some(symbol_index)) if same_definition(base, &map) => {
+                    if let Some(base_symbol_index) = base.symbol_index() {
+                        let _unused: Option<usize> =
+                            aliases.insert(symbol_index + symbol_offset, base_symbol_index);
+                    }
+                }
+                (Some(_), _) => return Err(LinkError::MapConflict { name }),
+                (None, _) => {
+                    match &mut map {
+                        Map::Legacy(map) => {
+                            map.section_index = section_index;
+                            map.symbol_index = map.symbol_index.map(|index| index + symbol_offset);
+                        }
+                        Map::Btf(map) => {
+                            map.section_index = section_index;
+                            map.symbol_index += symbol_offset;
+                            for def in std::iter::once(&mut map.def).chain(&mut map.inner_def) {
+                                def.btf_key_type_id = type_id(def.btf_key_type_id);
+                                def.btf_value_type_id = type_id(def.btf_value_type_id);
+                            }
+                        }
+                    }
+                    let _unused: Option<Map> = self.maps.insert(name, map);
+                }
+            }
+        }
+
+        for (SectionIndex(index), relocations) in relocations {
+            let (index, shift) = section(index);
+            let base = self.relocations.entry(SectionIndex(index)).or_default();
+            for (offset, mut relocation) in relocations {
+                relocation.offset = offset + shift;
+                relocation.symbol_index += symbol_offset;
+                if let Some(alias) = aliases.get(&relocation.symbol_index) {
+                    relocation.symbol_index = *alias;
+                }
+                let _unused: Option<crate::relocation::Relocation> =
+                    base.insert(relocation.offset, relocation);
+            }
+        }
+
+        for ((index, address), mut function) in functions {
+            let (index, shift) = section(index);
+            function.section_index = SectionIndex(index);
+            function.address += shift;
+            function.section_offset += shift as usize;
+            for info in &mut function.func_info.func_info {
+                info.type_id = type_id(info.type_id);
+            }
+            for info in &mut function.line_info.line_info {
+                info.file_name_off = string(info.file_name_off);
+                info.line_off = string(info.line_off);
+            }
+            let _unused: Option<crate::Function> =
+                self.functions.insert((index, address + shift), function);
+        }
+
+        for (name, mut program) in programs {
+            program.section_index = section(program.section_index).0;
+            let _unused: Option<crate::Program> = self.programs.insert(name, program);
+        }
+
+        Ok(())
+    }
+
+    /// Resolves `extern` and weak symbols to their definitions.
+    fn resolve_symbols(&mut self) -> Result<(), LinkError> {
+        let section_kinds: HashMap<usize, EbpfSectionKind> = self
+            .section_infos
+            .iter()
+            .map(|(name, (index, _))| (index.0, EbpfSectionKind::from_name(name)))
+            .collect();
+
+        let mut symbols = self.symbol_table.values().collect::<Vec<_>>();
+        symbols.sort_by_key(|symbol| symbol.index);
+
+        let mut definitions: HashMap<&str, &Symbol> = HashMap::new();
+        for symbol in &symbols {
+            let (Some(name), Some(section_index)) = (symbol.name.as_deref(), symbol.section_index)
+            else {
+                continue;
+            };
+            if name.is_empty()
+                || !symbol.is_global
+                || !symbol.is_definition
+                || !matches!(symbol.kind, SymbolKind::Text | SymbolKind::Data)
+                // Maps are shared by name, see `merge`.
+                || (symbol.kind == SymbolKind::Data && self.maps.contains_key(name))
+                || matches!(
+                    section_kinds.get(&section_index),
+                    Some(
+                        EbpfSectionKind::License
+                            | EbpfSectionKind::Version
+                            | EbpfSectionKind::Btf
+                            | EbpfSectionKind::BtfExt
+                    )
+                )
+            {
+                continue;
+            }
+            match definitions.entry(name) {
+                Entry::Vacant(entry) => {
+                    let _unused: &mut &Symbol = entry.insert(symbol);
+                }
+                Entry::Occupied(mut entry) => match (entry.get().is_weak, symbol.is_weak) {
+                    (false, false) => {
+                        return Err(LinkError::DuplicateSymbol {
+                            name: name.to_owned(),
+                        });
+                    }
+                    (true, false) => {
+                        let _unused: &Symbol = entry.insert(symbol);
+                    }
+                    (_, true) => {}
+                },
+            }
+        }
+
+        let mut redirects = HashMap::new();
+        for symbol in &symbols {
+            let Some(definition) = symbol
+                .name
+                .as_deref()
+                .and_then(|name| definitions.get(name))
+            else {
+                continue;
+            };
+            let overridden = symbol.is_global && symbol.is_definition && symbol.is_weak;
+            if definition.index != symbol.index && (symbol.is_extern() || overridden) {
+                let _unused: Option<(usize, SymbolKind)> =
+                    redirects.insert(symbol.index, (definition.index, definition.kind));
+            }
+        }
+
+        let mut undefined = BTreeSet::new();
+        for (section_index, relocations) in &mut self.relocations {
+            for relocation in relocations.values_mut() {
+                if let Some((index, kind)) = redirects.get(&relocation.symbol_index) {
+                    relocation.symbol_index = *index;
+                    // Calls to `extern` functions are emitted as helper calls, turn them into
+                    // calls to the function.
+                    if *kind == SymbolKind::Text
+                        && let Some(function) = self
+                            .functions
+                            .range_mut((section_index.0, 0)..=(section_index.0, u64::MAX))
+                            .map(|(_, function)| function)
+                            .find(|function| {
+                                (function.section_offset
+                                    ..function.section_offset
+                                        + function.instructions.len() * INS_SIZE)
+                                    .contains(&(relocation.offset as usize))
+                            })
+                    {
+                        let ins = &mut function.instructions
+                            [(relocation.offset as usize - function.section_offset) / INS_SIZE];
+                        if ins.code == (BPF_JMP | BPF_CALL) as u8 {
+                            ins.set_src_reg(BPF_PSEUDO_CALL as u8);
+                        }
+                    }
+                }
+                if let Some(symbol) = self.symbol_table.get(&relocation.symbol_index)
+                    && symbol.is_extern()
+                    && let Some(name) = &symbol.name
+                    && !self
+                        .btf
+                        .as_ref()
+                        .is_some_and(|btf| btf.externs.externs.contains_key(name))
+                {
+                    let _unused: bool = undefined.insert(name.clone());
+                }
+            }
+        }
+        if !undefined.is_empty() {
+            return Err(LinkError::UndefinedSymbols {
+                names: undefined.into_iter().collect(),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns whether `a` and `b` define the same map.
+fn same_definition(a: &Map, b: &Map) -> bool {
+    a.map_type() == b.map_type()
+        && a.key_size() == b.key_size()
+        && a.value_size() == b.value_size()
+        && a.max_entries() == b.max_entries()
+        && a.map_flags() == b.map_flags()
+        && a.map_extra() == b.map_extra()
+        && a.pinning() == b.pinning()
+        && match (a.inner(), b.inner()) {
+            (Some(a), Some(b)) => same_definition(&a, &b),
+            (None, None) => true,
+            (Some(_), None) | (None, Some(_)) => false,
+        }
+}
+
+/// Iterates over the NUL terminated strings of a string section and their offsets.
+fn strings(data: &[u8]) -> impl Iterator<Item = (u32, &[u8])> {
+    let mut offset = 0;
+    data.split_inclusive(|b| *b == 0).map(move |s| {
+        let start = offset;
+        offset += s.len();
+        (start as u32, s.strip_suffix(&[0]).unwrap_or(s))
+    })
+}
+
+/// Maps the string offsets of a [`Btf`] to the offsets of the same strings in another.
+struct StringMap(BTreeMap<u32, u32>);
+
+impl StringMap {
+    /// Adds the strings of `other` that `btf` doesn't have to `btf`.
+    fn new(btf: &mut Btf, other: &Btf) -> Self {
+        let mut existing = HashMap::new();
+        for (offset, s) in strings(btf.strings()) {
+            let _unused: &mut u32 = existing.entry(s.to_vec()).or_insert(offset);
+        }
+        let mut map = BTreeMap::new();
+        for (offset, s) in strings(other.strings()) {
+            let new_offset = match existing.entry(s.to_vec()) {
+                Entry::Occupied(entry) => *entry.get(),
+                Entry::Vacant(entry) => *entry.insert(btf.add_string(&String::from_utf8_lossy(s))),
+            };
+            let _unused: Option<u32> = map.insert(offset, new_offset);
+        }
+        Self(map)
+    }
+
+    /// Returns the offset of the string at `offset` in the other [`Btf`].
+    ///
+    /// Offsets can point into a string to reference its suffix.
+    fn get(&self, offset: u32) -> u32 {
+        let Self(map) = self;
+        map.range(..=offset)
+            .next_back()
+            .map_or(0, |(start, new_start)| new_start + (offset - start))
+    }
+}
+
+/// Returns the type ids referenced by `ty`, in order.
+fn referenced_types(ty: &BtfType) -> Vec<u32> {
+    let mut ids = Vec::new();
+    let mut ty = ty.clone();
+    ty.remap(
+        &mut |id| {
+            ids.push(id);
+            id
+        },
+        &mut |offset| offset,
+    );
+    ids
+}
+
+/// Returns the encoding of `ty` without the type ids it references, with its string offsets
+/// mapped by `strings`.
+///
+/// Two types are identical if their shallow encodings are and the types they reference are.
+fn shallow_bytes(ty: &BtfType, strings: &mut impl FnMut(u32) -> u32) -> Vec<u8> {
+    let mut ty = ty.clone();
+    ty.remap(&mut |_id| 0, strings);
+    ty.to_bytes()
+}
+
+/// Deduplicates the types of one [`Btf`] against the types of another.
+///
+/// As in libbpf's `btf__dedup`, types are compared as graphs: two types are equivalent if they
+/// have the same shallow encoding and the types they reference are equivalent. Cycles, e.g.
+/// through a pointer to the struct being compared, are handled by assuming that the types being
+/// compared are equivalent until proven otherwise. A forward declaration is equivalent to the
+/// struct or union with the same name. Variables and data sections are never deduplicated.
+struct Dedup<'a> {
+    base: Vec<&'a BtfType>,
+    other: &'a [&'a BtfType],
+    strings: &'a StringMap,
+    // The type ids of `other` known to be equivalent to a type of `base`.
+    ids: Vec<Option<u32>>,
+}
+
+impl Dedup<'_> {
+    /// Returns whether the type `id` of `other` is equivalent to the type `base_id` of `base`,
+    /// recording the pairs of types assumed to be equivalent in `assumed`.
+    fn equivalent(&self, id: u32, base_id: u32, assumed: &mut HashMap<u32, u32>) -> bool {
+        let Self {
+            base,
+            other,
+            strings,
+            ids,
+        } = self;
+        if let Some(known) = ids.get(id as usize).copied().flatten() {
+            return known == base_id;
+        }
+        match assumed.entry(id) {
+            Entry::Occupied(entry) => return *entry.get() == base_id,
+            Entry::Vacant(entry) => {
+                let _unused: &mut u32 = entry.insert(base_id);
+            }
+        }
+        let (Some(ty), Some(base_ty)) = (other.get(id as usize), base.get(base_id as usize)) else {
+            return false;
+        };
+        match (ty, base_ty) {
+            (BtfType::Fwd(fwd), BtfType::Struct(Struct { name_offset, .. })) => {
+                return !fwd.is_union() && strings.get(fwd.name_offset) == *name_offset;
+            }
+            (BtfType::Fwd(fwd), BtfType::Union(Union { name_offset, .. })) => {
+                return fwd.is_union() && strings.get(fwd.name_offset) == *name_offset;
+            }
+            _ => {}
+        }
+        if shallow_bytes(ty, &mut |offset| strings.get(offset))
+            != shallow_bytes(base_ty, &mut |offset| offset)
+        {
+            return false;
+        }
+        referenced_types(ty)
+            .into_iter()
+            .zip(referenced_types(base_ty))
+            .all(|(id, base_id)| self.equivalent(id, base_id, assumed))
+    }
+}
+
+/// Merges the types of `other` into `btf` and returns the new ids of the types of `other`.
+///
+/// Types equivalent to a type of `btf` are deduplicated, see [`Dedup`]. Data sections with the
+/// same name are merged, shifting the offsets of the entries of `other` by `shifts[name]`.
+fn merge_types(
+    btf: &mut Btf,
+    other: &Btf,
+    strings: &StringMap,
+    shifts: &HashMap<String, u64>,
+) -> Result<Vec<u32>, BtfError> {
+    let mut candidates: HashMap<Vec<u8>, Vec<u32>> = HashMap::new();
+    let mut composites: HashMap<u32, Vec<u32>> = HashMap::new();
+    let mut datasecs = HashMap::new();
+    for (id, ty) in btf.types().enumerate().skip(1) {
+        let id = id as u32;
+        match ty {
+            BtfType::DataSec(_) => {
+                let _unused: Option<u32> = datasecs.insert(btf.type_name(ty)?.into_owned(), id);
+                continue;
+            }
+            // Each variable has its own entry in its data section.
+            BtfType::Var(_) => continue,
+            BtfType::Struct(Struct { name_offset, .. })
+            | BtfType::Union(Union { name_offset, .. }) => {
+                composites.entry(*name_offset).or_default().push(id);
+            }
+            _ => {}
+        }
+        candidates
+            .entry(shallow_bytes(ty, &mut |offset| offset))
+            .or_default()
+            .push(id);
+    }
+
+    let other_types = other.types().collect::<Vec<_>>();
+    let mut dedup = Dedup {
+        base: btf.types().collect(),
+        other: &other_types,
+        strings,
+        ids: vec![None; other_types.len()],
+    };
+    if let Some(void) = dedup.ids.first_mut() {
+        *void = Some(0);
+    }
+    let mut merged_datasecs = Vec::new();
+    for (id, ty) in other_types.iter().enumerate().skip(1) {
+        if let BtfType::DataSec(_) = ty {
+            let name = other.type_name(ty)?;
+            if let Some(base_id) = datasecs.get(name.as_ref()) {
+                dedup.ids[id] = Some(*base_id);
+                merged_datasecs.push((*base_id, id, name.into_owned()));
+            }
+            continue;
+        }
+        if dedup.ids[id].is_some() {
+            continue;
+        }
+        let candidates = match ty {
+            BtfType::Fwd(fwd) => composites.get(&strings.get(fwd.name_offset)),
+            ty => candidates.get(&shallow_bytes(ty, &mut |offset| strings.get(offset))),
+        };
+        for base_id in candidates.into_iter().flatten() {
+            let mut assumed = HashMap::new();
+            if dedup.equivalent(id as u32, *base_id, &mut assumed) {
+                for (id, base_id) in assumed {
+                    let _unused: &mut u32 = dedup.ids[id as usize].get_or_insert(base_id);
+                }
+                break;
+            }
+        }
+    }
+    let Dedup { ids, .. } = dedup;
+
+    let base_len = btf.types().count() as u32;
+    let mut next_id = base_len;
+    let ids = ids
+        .into_iter()
+        .map(|id| {
+            id.unwrap_or_else(|| {
+                let id = next_id;
+                next_id += 1;
+                id
+            })
+        })
+        .collect::<Vec<_>>();
+    let mut type_id = |id: u32| ids.get(id as usize).copied().unwrap_or(0);
+
+    for (id, ty) in other_types.iter().enumerate().skip(1) {
+        if ids[id] < base_len {
+            continue;
+        }
+        let mut ty = (*ty).clone();
+        ty.remap(&mut type_id, &mut |offset| strings.get(offset));
+        let new_id = btf.add_type(ty);
+        debug_assert_eq!(new_id, ids[id]);
+    }
+
+    for (base_id, id, name) in merged_datasecs {
+        let (BtfType::DataSec(base), BtfType::DataSec(datasec)) =
+            (btf.type_by_id(base_id)?, other_types[id])
+        else {
+            continue;
+        };
+        let shift = shifts.get(&name).copied().unwrap_or(0) as u32;
+        let mut entries = base.entries.clone();
+        for entry in &datasec.entries {
+            let btf_type = type_id(entry.btf_type);
+            if entries.iter().all(|entry| entry.btf_type != btf_type) {
+                entries.push(DataSecEntry {
+                    btf_type,
+                    offset: entry.offset + shift,
+                    size: entry.size,
+                });
+            }
+        }
+        // A zero size is fixed up from the section size when loading.
+        let size = if base.size == 0 || datasec.size == 0 {
+            0
+        } else {
+            shift + datasec.size
+        };
+        let datasec = DataSec::new(base.name_offset, entries, size);
+        btf.replace_type(base_id, BtfType::DataSec(datasec));
+    }
+
+    Ok(ids)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CString;
+
+    use assert_matches::assert_matches;
+
+    use super::*;
+    use crate::{
+        Function, Program, ProgramSection,
+        btf::{BtfMember, Fwd, Int, IntEncoding, Ptr, Var, VarLinkage},
+        generated::{BPF_DW, BPF_IMM, BPF_LD, bpf_insn},
+        maps::{BtfMap, BtfMapDef, LegacyMap},
+        relocation::Relocation,
+        test_utils::{empty_object, exit, insn},
+        util::HashSet,
+    };
+
+    fn symbol(
+        index: usize,
+        section_index: Option<usize>,
+        address: u64,
+        name: &str,
+        kind: SymbolKind,
+    ) -> Symbol {
+        Symbol {
+            index,
+            section_index,
+            name: Some(name.to_owned()),
+            address,
+            size: 0,
+            is_definition: section_index.is_some(),
+            kind,
+            is_weak: false,
+            is_global: true,
+        }
+    }
+
+    fn add_symbol(object: &mut Object, symbol: Symbol) {
+        if let Some(section_index) = symbol.section_index {
+            object
+                .symbols_by_section
+                .entry(SectionIndex(section_index))
+                .or_default()
+                .push(symbol.index);
+        }
+        let _unused: Option<Symbol> = object.symbol_table.insert(symbol.index, symbol);
+    }
+
+    fn add_relocation(object: &mut Object, section_index: usize, offset: u64, symbol_index: usize) {
+        let _unused: Option<Relocation> = object
+            .relocations
+            .entry(SectionIndex(section_index))
+            .or_default()
+            .insert(
+                offset,
+                Relocation {
+                    offset,
+                    symbol_index,
+                    size: 64,
+                },
+            );
+    }
+
+    /// Adds a function at the start of the section `section_index`, with a global symbol at
+    /// `symbol_index`. Programs are in their own section.
+    fn add_function(
+        object: &mut Object,
+        section_index: usize,
+        symbol_index: usize,
+        name: &str,
+        instructions: Vec<bpf_insn>,
+        program: bool,
+    ) {
+        let section_name = if program {
+            format!("xdp/{name}")
+        } else {
+            ".text".to_owned()
+        };
+        let size = (instructions.len() * INS_SIZE) as u64;
+        let _unused: Option<(SectionIndex, u64)> = object
+            .section_infos
+            .insert(section_name, (SectionIndex(section_index), size));
+        add_symbol(
+            object,
+            symbol(symbol_index, Some(section_index), 0, name, SymbolKind::Text),
+        );
+        let _unused: Option<Function> = object.functions.insert(
+            (section_index, 0),
+            Function {
+                address: 0,
+                name: name.to_owned(),
+                section_index: SectionIndex(section_index),
+                section_offset: 0,
+                instructions,
+                func_info: Default::default(),
+                line_info: Default::default(),
+                func_info_rec_size: 0,
+                line_info_rec_size: 0,
+            },
+        );
+        if program {
+            let _unused: Option<Program> = object.programs.insert(
+                name.to_owned(),
+                Program {
+                    license: CString::new("GPL").unwrap(),
+                    kernel_version: None,
+                    section: ProgramSection::Xdp {
+                        frags: false,
+                        attach_type: crate::programs::XdpAttachType::Interface,
+                    },
+                    section_index,
+                    address: 0,
+                },
+            );
+        }
+    }
+
+    fn add_map(object: &mut Object, section_index: usize, symbol_index: usize, max_entries: u32) {
+        let _unused: Option<(SectionIndex, u64)> = object
+            .section_infos
+            .insert(".maps".to_owned(), (SectionIndex(section_index), 32));
+        add_symbol(
+            object,
+            symbol(
+                symbol_index,
+                Some(section_index),
+                0,
+                "shared",
+                SymbolKind::Data,
+            ),
+        );
+        let _unused: Option<Map> = object.maps.insert(
+            "shared".to_owned(),
+            Map::Btf(BtfMap {
+                def: BtfMapDef {
+                    map_type: 1,
+                    key_size: 4,
+                    value_size: 4,
+                    max_entries,
+                    ..Default::default()
+                },
+                inner_def: None,
+                section_index,
+                symbol_index,
+                data: Vec::new(),
+            }),
+        );
+    }
+
+    #[test]
+    fn test_link_extern_function() {
+        // The program calls `helper`, which is defined in the other object. `extern` calls are
+        // emitted as helper calls with an undefined symbol.
+        let mut a = empty_object();
+        add_function(
+            &mut a,
+            1,
+            1,
+            "prog",
+            vec![insn(BPF_JMP | BPF_CALL, 0, 0, 0, -1), exit()],
+            true,
+        );
+        add_symbol(&mut a, symbol(2, None, 0, "helper", SymbolKind::Unknown));
+        add_relocation(&mut a, 1, 0, 2);
+
+        let mut b = empty_object();
+        add_function(&mut b, 1, 1, "helper", vec![exit()], false);
+
+        let mut linked = Object::link([a, b]).unwrap();
+        let helper = linked
+            .symbol_table
+            .values()
+            .find(|symbol| symbol.name.as_deref() == Some("helper") && symbol.is_definition)
+            .unwrap();
+        assert_ne!(helper.section_index, Some(1));
+        let relocation = &linked.relocations[&SectionIndex(1)][&0];
+        assert_eq!(relocation.symbol_index, helper.index);
+
+        let text_sections = HashSet::from([helper.section_index.unwrap()]);
+        linked.relocate_calls(&text_sections).unwrap();
+        let program = &linked.functions[&(1, 0)];
+        assert_eq!(program.instructions.len(), 3);
+        assert_eq!(program.instructions[0].src_reg(), BPF_PSEUDO_CALL as u8);
+        assert_eq!(program.instructions[0].imm, 1);
+    }
+
+    #[test]
+    fn test_link_undefined_symbol() {
+        let mut a = empty_object();
+        add_function(
+            &mut a,
+            1,
+            1,
+            "prog",
+            vec![insn(BPF_JMP | BPF_CALL, 0, 0, 0, -1), exit()],
+            true,
+        );
+        add_symbol(&mut a, symbol(2, None, 0, "helper", SymbolKind::Unknown));
+        add_relocation(&mut a, 1, 0, 2);
+
+        let err = Object::link([a, empty_object()]).unwrap_err();
+        assert_eq!(err.to_string(), "undefined symbols: helper");
+    }
+
+    #[test]
+    fn test_link_duplicates() {
+        let prog = |index| {
+            let mut object = empty_object();
+            add_function(&mut object, index, 1, "prog", vec![exit()], true);
+            object
+        };
+        assert_matches!(
+            Object::link([prog(1), prog(1)]),
+            Err(LinkError::DuplicateProgram { name }) if name == "prog"
+        );
+
+        let function = || {
+            let mut object = empty_object();
+            add_function(&mut object, 1, 1, "helper", vec![exit()], false);
+            object
+        };
+        assert_matches!(
+            Object::link([function(), function()]),
+            Err(LinkError::DuplicateSymbol { name }) if name == "helper"
+        );
+
+        // A weak definition is overridden by a strong one.
+        let mut weak = function();
+        weak.symbol_table.get_mut(&1).unwrap().is_weak = true;
+        let linked = Object::link([weak, function()]).unwrap();
+        assert_eq!(linked.functions.len(), 2);
+    }
+
+    #[test]
+    fn test_link_shared_maps() {
+        let ld_map = || {
+            vec![
+                insn(BPF_LD | BPF_DW | BPF_IMM, 0, 0, 0, 0),
+                insn(0, 0, 0, 0, 0),
+                exit(),
+            ]
+        };
+        let mut a = empty_object();
+        add_function(&mut a, 1, 1, "prog_a", ld_map(), true);
+        add_map(&mut a, 2, 2, 16);
+        add_relocation(&mut a, 1, 0, 2);
+
+        let mut b = empty_object();
+        add_function(&mut b, 1, 1, "prog_b", ld_map(), true);
+        add_map(&mut b, 2, 2, 16);
+        add_relocation(&mut b, 1, 0, 2);
+
+        let linked = Object::link([a, b]).unwrap();
+        assert_eq!(linked.maps.len(), 1);
+        assert_eq!(linked.maps["shared"].symbol_index(), Some(2));
+        let prog_b = &linked.programs["prog_b"];
+        let relocation = &linked.relocations[&SectionIndex(prog_b.section_index)][&0];
+        assert_eq!(relocation.symbol_index, 2);
+
+        let mut b = empty_object();
+        add_map(&mut b, 2, 1, 32);
+        assert_matches!(
+            Object::link([empty_object(), b]),
+            Ok(linked) if linked.maps["shared"].max_entries() == 32
+        );
+        let mut a = empty_object();
+        add_map(&mut a, 2, 1, 16);
+        let mut b = empty_object();
+        add_map(&mut b, 2, 1, 32);
+        assert_matches!(
+            Object::link([a, b]),
+            Err(LinkError::MapConflict { name }) if name == "shared"
+        );
+    }
+
+    #[test]
+    fn test_link_data_sections() {
+        let rodata = |data: &[u8], name: &str| {
+            let mut object = empty_object();
+            let _unused: Option<(SectionIndex, u64)> = object
+                .section_infos
+                .insert(".rodata".to_owned(), (SectionIndex(3), data.len() as u64));
+            add_symbol(&mut object, symbol(1, Some(3), 0, name, SymbolKind::Data));
+            let _unused: Option<u64> = object.symbol_offset_by_name.insert(name.to_owned(), 0);
+            let _unused: Option<Map> = object.maps.insert(
+                ".rodata".to_owned(),
+                Map::Legacy(LegacyMap {
+                    def: crate::maps::bpf_map_def {
+                        value_size: data.len() as u32,
+                        max_entries: 1,
+                        ..Default::default()
+                    },
+                    inner_def: None,
+                    section_index: 3,
+                    section_kind: EbpfSectionKind::Rodata,
+                    symbol_index: None,
+                    data: data.to_vec(),
+                }),
+            );
+            object
+        };
+
+        let linked = Object::link([rodata(&[1, 2, 3], "a"), rodata(&[4, 5, 6, 7], "b")]).unwrap();
+        let map = &linked.maps[".rodata"];
+        assert_eq!(map.data(), &[1, 2, 3, 0, 0, 0, 0, 0, 4, 5, 6, 7]);
+        assert_eq!(map.value_size(), 12);
+        assert_eq!(linked.section_infos[".rodata"], (SectionIndex(3), 12));
+        assert_eq!(linked.symbol_offset_by_name["b"], 8);
+        let b = linked
+            .symbol_table
+            .values()
+            .find(|symbol| symbol.name.as_deref() == Some("b"))
+            .unwrap();
+        assert_eq!((b.section_index, b.address), (Some(3), 8));
+    }
+
+    #[test]
+    fn test_link_btf() {
+        let btf = |var: &str| {
+            let mut btf = Btf::new();
+            let name = btf.add_string("int");
+            let int = btf.add_type(BtfType::Int(Int::new(name, 4, IntEncoding::Signed, 0)));
+            let ptr = btf.add_type(BtfType::Ptr(Ptr::new(0, int)));
+            let name = btf.add_string(var);
+            let var = btf.add_type(BtfType::Var(Var::new(name, ptr, VarLinkage::Global)));
+            let name = btf.add_string(".rodata");
+            let entries = vec![DataSecEntry {
+                btf_type: var,
+                offset: 0,
+                size: 8,
+            }];
+            btf.add_type(BtfType::DataSec(DataSec::new(name, entries, 8)));
+            let mut object = empty_object();
+            object.btf = Some(btf);
+            let _unused: Option<(SectionIndex, u64)> = object
+                .section_infos
+                .insert(".rodata".to_owned(), (SectionIndex(3), 8));
+            object
+        };
+
+        let linked = Object::link([btf("a"), btf("b")]).unwrap();
+        let btf = linked.btf.as_ref().unwrap();
+        // The int and pointer types are shared, only the variable is added.
+        assert_eq!(btf.types().count(), 6);
+        let BtfType::Var(var) = btf.type_by_id(5).unwrap() else {
+            panic!("expected a variable");
+        };
+        assert_eq!(btf.string_at(var.name_offset).unwrap(), "b");
+        assert_eq!(var.btf_type, 2);
+        let BtfType::DataSec(datasec) = btf.type_by_id(4).unwrap() else {
+            panic!("expected a data section");
+        };
+        assert_eq!(datasec.size, 16);
+        assert_matches!(
+            datasec.entries.as_slice(),
+            [
+                DataSecEntry {
+                    btf_type: 3,
+                    offset: 0,
+                    size: 8
+                },
+                DataSecEntry {
+                    btf_type: 5,
+                    offset: 8,
+                    size: 8
+                }
+            ]
+        );
+    }
+
+    /// Adds `struct list { struct list *next; <value> value; }`, with the struct at `id` and the
+    /// pointer to it at `id + 1`.
+    fn add_list(btf: &mut Btf, id: u32, value: u32) -> u32 {
+        let name = btf.add_string("list");
+        let next = btf.add_string("next");
+        let value_name = btf.add_string("value");
+        let members = vec![
+            BtfMember {
+                name_offset: next,
+                btf_type: id + 1,
+                offset: 0,
+            },
+            BtfMember {
+                name_offset: value_name,
+                btf_type: value,
+                offset: 64,
+            },
+        ];
+        let list = btf.add_type(BtfType::Struct(Struct::new(name, members, 16)));
+        assert_eq!(list, id);
+        let ptr = btf.add_type(BtfType::Ptr(Ptr::new(0, list)));
+        assert_eq!(ptr, id + 1);
+        list
+    }
+
+    fn add_int(btf: &mut Btf, name: &str, size: u32) -> u32 {
+        let name = btf.add_string(name);
+        btf.add_type(BtfType::Int(Int::new(name, size, IntEncoding::Signed, 0)))
+    }
+
+    fn btf_object(btf: Btf) -> Object {
+        let mut object = empty_object();
+        object.btf = Some(btf);
+        object
+    }
+
+    #[test]
+    fn test_link_btf_recursive_types() {
+        let mut a = Btf::new();
+        let int = add_int(&mut a, "int", 4);
+        let list = add_list(&mut a, 2, int);
+
+        // The same types in another order, with the int after the struct that references it.
+        let mut b = Btf::new();
+        let _list = add_list(&mut b, 1, 3);
+        let _int = add_int(&mut b, "int", 4);
+        // A forward declaration of the struct and a pointer to it.
+        let name = b.add_string("list");
+        let fwd = b.add_type(BtfType::Fwd(Fwd::new(name, false)));
+        let _ptr = b.add_type(BtfType::Ptr(Ptr::new(0, fwd)));
+        // A union forward declaration doesn't resolve to the struct.
+        let _union = b.add_type(BtfType::Fwd(Fwd::new(name, true)));
+
+        let linked = Object::link([btf_object(a), btf_object(b)]).unwrap();
+        let btf = linked.btf.as_ref().unwrap();
+        assert_eq!(btf.types().count(), 5);
+        let BtfType::Fwd(fwd) = btf.type_by_id(4).unwrap() else {
+            panic!("expected a forward declaration");
+        };
+        assert!(fwd.is_union());
+        assert_matches!(btf.type_by_id(3).unwrap(), BtfType::Ptr(ptr) if ptr.btf_type == list);
+    }
+
+    #[test]
+    fn test_link_btf_different_recursive_types() {
+        let mut a = Btf::new();
+        let int = add_int(&mut a, "int", 4);
+        let _list = add_list(&mut a, 2, int);
+
+        // `struct list` has a different member type, so does the pointer to it.
+        let mut b = Btf::new();
+        let long = add_int(&mut b, "long", 8);
+        let _list = add_list(&mut b, 2, long);
+
+        let linked = Object::link([btf_object(a), btf_object(b)]).unwrap();
+        let btf = linked.btf.as_ref().unwrap();
+        assert_eq!(btf.types().count(), 7);
+        let BtfType::Struct(list) = btf.type_by_id(5).unwrap() else {
+            panic!("expected a struct");
+        };
+        assert_eq!(list.members[0].btf_type, 6);
+        assert_eq!(list.members[1].btf_type, 4);
+        assert_matches!(btf.type_by_id(6).unwrap(), BtfType::Ptr(ptr) if ptr.btf_type == 5);
+    }
+
+    #[test]
+    fn test_link_btf_variables() {
+        let btf = || {
+            let mut btf = Btf::new();
+            let int = add_int(&mut btf, "int", 4);
+            let name = btf.add_string("counter");
+            let _var = btf.add_type(BtfType::Var(Var::new(name, int, VarLinkage::Static)));
+            btf
+        };
+
+        // Variables with the same name and type in several objects are distinct.
+        let linked = Object::link([btf_object(btf()), btf_object(btf())]).unwrap();
+        let btf = linked.btf.as_ref().unwrap();
+        assert_eq!(btf.types().count(), 4);
+        assert_matches!(btf.type_by_id(3).unwrap(), BtfType::Var(var) if var.btf_type == 1);
+    }
+}
@@ -0,0 +1,1015 @@
+//! Static checks of eBPF programs.
+//!
+//! Some programs are accepted by [`Object::parse`] but predictably rejected by the verifier at load
+//! time: they call helpers that are not available to their program type, use more stack than the
+//! kernel allows, contain loops that can't terminate or use instructions that the targeted CPU
+//! version doesn't have. [`Object::lint`] finds these problems without a kernel and reports them
+//! with the function and source location they come from.
+//!
+//! The checks are conservative: a program without diagnostics can still be rejected by the
+//! verifier, but a diagnostic points at a problem the verifier would report.
+
+use std::{
+    borrow::Cow,
+    cmp,
+    collections::{BTreeSet, HashMap, HashSet},
+    fmt::{self, Display},
+    ops::Range,
+};
+
+use crate::{
+    Function, Object, ProgramSection,
+    btf::{Btf, BtfType},
+    generated::{
+        BPF_ABS, BPF_ADD, BPF_ALU, BPF_ALU64, BPF_AND, BPF_ATOMIC, BPF_CALL, BPF_CMPXCHG, BPF_DIV,
+        BPF_DW, BPF_END, BPF_EXIT, BPF_FETCH, BPF_IMM, BPF_IND, BPF_JA, BPF_JCOND, BPF_JLT,
+        BPF_JMP, BPF_JMP32, BPF_JSLE, BPF_K, BPF_LD, BPF_LDX, BPF_MEM, BPF_MEMSX, BPF_MOD, BPF_MOV,
+        BPF_OR, BPF_PSEUDO_CALL, BPF_PSEUDO_FUNC, BPF_ST, BPF_STX, BPF_XCHG, BPF_XOR, bpf_func_id,
+        bpf_insn,
+    },
+    relocation::EbpfRelocationError,
+    verifier::{SourceLocation, source_location},
+};
+
+/// A version of the BPF instruction set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum CpuVersion {
+    /// The original instruction set.
+    V1,
+    /// Adds the `jlt`, `jle`, `jslt` and `jsle` jumps.
+    V2,
+    /// Adds 32-bit jumps and atomic operations other than `add`.
+    V3,
+    /// Adds sign extending loads and moves, signed division, byte swaps and long jumps.
+    V4,
+}
+
+impl Display for CpuVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::V1 => "v1",
+            Self::V2 => "v2",
+            Self::V3 => "v3",
+            Self::V4 => "v4",
+        })
+    }
+}
+
+/// Options for [`Object::lint`].
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct LintOptions {
+    /// The CPU version the programs are loaded with. Defaults to [`CpuVersion::V3`].
+    pub cpu: CpuVersion,
+    /// Whether the target kernel supports bounded loops, which it does since 5.3. Defaults to
+    /// `true`.
+    pub bounded_loops: bool,
+    /// The maximum combined stack size of a call chain. Defaults to 512 bytes.
+    pub stack_limit: u32,
+}
+
+impl Default for LintOptions {
+    fn default() -> Self {
+        Self {
+            cpu: CpuVersion::V3,
+            bounded_loops: true,
+            stack_limit: 512,
+        }
+    }
+}
+
+/// A problem found by [`Object::lint`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LintDiagnostic {
+    /// The name of the program.
+    pub program: String,
+    /// The name of the function containing the instruction, if known.
+    pub function: Option<String>,
+    /// The index of the instruction in the program, after call linking.
+    pub instruction: usize,
+    /// The source location of the instruction, if known.
+    pub source: Option<SourceLocation>,
+    /// The problem.
+    pub kind: LintKind,
+}
+
+impl Display for LintDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self {
+            program,
+            function,
+            instruction,
+            source,
+            kind,
+        } = self;
+        write!(f, "{program}")?;
+        if let Some(function) = function
+            && function != program
+        {
+            write!(f, " ({function})")?;
+        }
+        write!(f, ": insn {instruction}: {kind}")?;
+        if let Some(source) = source {
+            write!(f, "\n  --> {source}")?;
+            if let Some(text) = &source.text {
+                write!(f, "\n   | {text}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The kinds of problems found by [`Object::lint`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LintKind {
+    /// A helper is called that isn't available to the program type.
+    UnsupportedHelper {
+        /// The helper id.
+        helper: u32,
+        /// The name of the helper.
+        name: &'static str,
+    },
+    /// A helper is called that doesn't exist.
+    UnknownHelper {
+        /// The helper id.
+        helper: u32,
+    },
+    /// A call chain uses more stack than the kernel allows.
+    StackTooLarge {
+        /// The combined stack size of the call chain, in bytes.
+        size: u32,
+        /// The maximum stack size.
+        limit: u32,
+    },
+    /// A loop has no way out.
+    InfiniteLoop,
+    /// A loop is used but the target kernel doesn't support bounded loops.
+    Loop,
+    /// An instruction requires a more recent CPU version than the one targeted.
+    UnsupportedInstruction {
+        /// The opcode of the instruction.
+        opcode: u8,
+        /// The CPU version the instruction requires.
+        required: CpuVersion,
+    },
+    /// An instruction is not a valid eBPF instruction.
+    InvalidInstruction {
+        /// The opcode of the instruction.
+        opcode: u8,
+    },
+}
+
+impl Display for LintKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnsupportedHelper { helper, name } => write!(
+                f,
+                "helper bpf_{name}#{helper} is not available to this program type"
+            ),
+            Self::UnknownHelper { helper } => write!(f, "unknown helper #{helper}"),
+            Self::StackTooLarge { size, limit } => write!(
+                f,
+                "combined stack size of {size} bytes exceeds the limit of {limit} bytes"
+            ),
+            Self::InfiniteLoop => write!(f, "infinite loop"),
+            Self::Loop => write!(f, "loops are not supported by the target kernel"),
+            Self::UnsupportedInstruction { opcode, required } => {
+                write!(f, "instruction ({opcode:02x}) requires cpu {required}")
+            }
+            Self::InvalidInstruction { opcode } => write!(f, "invalid instruction ({opcode:02x})"),
+        }
+    }
+}
+
+impl Object {
+    /// Statically checks the programs of the object for problems that the verifier would reject.
+    ///
+    /// Calls are linked on a copy of the object, so that the instruction indices of the
+    /// diagnostics match those reported by the verifier.
+    pub fn lint(&self, options: &LintOptions) -> Result<Vec<LintDiagnostic>, EbpfRelocationError> {
+        let mut object = self.clone();
+        let text_sections = object
+            .functions
+            .keys()
+            .map(|(section_index, _)| *section_index)
+            .collect::<HashSet<_>>();
+        object.relocate_calls(&text_sections)?;
+
+        let mut names = object.programs.keys().collect::<Vec<_>>();
+        names.sort_unstable();
+        let mut diagnostics = Vec::new();
+        for name in names {
+            let program = &object.programs[name];
+            let Some(function) = object.functions.get(&program.function_key()) else {
+                continue;
+            };
+            let mut linter = Linter {
+                program: name,
+                function,
+                btf: object.btf.as_ref(),
+                diagnostics: &mut diagnostics,
+            };
+            linter.check(&program.section, options);
+        }
+        Ok(diagnostics)
+    }
+}
+
+/// The program types helpers are restricted to, see [`RESTRICTED_HELPERS`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    KProbe,
+    TracePoint,
+    PerfEvent,
+    RawTracePoint,
+    Tracing,
+    Lsm,
+    SocketFilter,
+    Xdp,
+    SchedClassifier,
+    CgroupSkb,
+    SkSkb,
+    SkMsg,
+    SockOps,
+    CgroupSockAddr,
+    CgroupSysctl,
+    LircMode2,
+    SkLookup,
+    SkReuseport,
+    Other,
+}
+
+impl Kind {
+    const fn of(section: &ProgramSection) -> Option<Self> {
+        Some(match section {
+            ProgramSection::KProbe
+            | ProgramSection::KRetProbe
+            | ProgramSection::UProbe { .. }
+            | ProgramSection::URetProbe { .. } => Self::KProbe,
+            ProgramSection::TracePoint => Self::TracePoint,
+            ProgramSection::PerfEvent => Self::PerfEvent,
+            ProgramSection::RawTracePoint => Self::RawTracePoint,
+            ProgramSection::BtfTracePoint
+            | ProgramSection::FEntry { .. }
+            | ProgramSection::FExit { .. }
+            | ProgramSection::Iter { .. } => Self::Tracing,
+            ProgramSection::Lsm { .. } | ProgramSection::LsmCgroup => Self::Lsm,
+            ProgramSection::SocketFilter => Self::SocketFilter,
+            ProgramSection::Xdp { .. } => Self::Xdp,
+            ProgramSection::SchedClassifier => Self::SchedClassifier,
+            ProgramSection::CgroupSkb { .. } => Self::CgroupSkb,
+            ProgramSection::SkSkbStream { .. } => Self::SkSkb,
+            ProgramSection::SkMsg => Self::SkMsg,
+            ProgramSection::SockOps => Self::SockOps,
+            ProgramSection::CgroupSockAddr { .. } => Self::CgroupSockAddr,
+            ProgramSection::CgroupSysctl => Self::CgroupSysctl,
+            ProgramSection::LircMode2 => Self::LircMode2,
+            ProgramSection::SkLookup => Self::SkLookup,
+            ProgramSection::SkReuseport { .. } => Self::SkReuseport,
+            ProgramSection::CgroupSockopt { .. }
+            | ProgramSection::CgroupSock { .. }
+            | ProgramSection::CgroupDevice
+            | ProgramSection::FlowDissector => Self::Other,
+            // Extensions take the type of the program they replace.
+            ProgramSection::Extension => return None,
+        })
+    }
+}
+
+const TRACING: &[Kind] = &[
+    Kind::KProbe,
+    Kind::TracePoint,
+    Kind::PerfEvent,
+    Kind::RawTracePoint,
+    Kind::Tracing,
+    Kind::Lsm,
+];
+
+macro_rules! restricted_helpers {
+    ($($helper:ident => $kinds:expr),* $(,)?) => {
+        &[$((bpf_func_id::$helper, stringify!($helper), $kinds)),*]
+    };
+}
+
+/// Helpers that are only available to some program types.
+///
+/// Helpers that aren't listed here are assumed to be available to every program type.
+const RESTRICTED_HELPERS: &[(bpf_func_id, &str, &[Kind])] = {
+    use self::Kind::{
+        CgroupSkb, CgroupSockAddr, CgroupSysctl, KProbe, LircMode2, Lsm, PerfEvent,
+        SchedClassifier, SkLookup, SkMsg, SkReuseport, SkSkb, SockOps, Tracing, Xdp,
+    };
+
+    restricted_helpers! {
+        BPF_FUNC_skb_store_bytes => &[SchedClassifier, SkSkb],
+        BPF_FUNC_l3_csum_replace => &[SchedClassifier],
+        BPF_FUNC_l4_csum_replace => &[SchedClassifier],
+        BPF_FUNC_clone_redirect => &[SchedClassifier],
+        BPF_FUNC_skb_vlan_push => &[SchedClassifier],
+        BPF_FUNC_skb_vlan_pop => &[SchedClassifier],
+        BPF_FUNC_redirect => &[SchedClassifier, Xdp],
+        BPF_FUNC_skb_change_proto => &[SchedClassifier],
+        BPF_FUNC_skb_change_type => &[SchedClassifier],
+        BPF_FUNC_skb_change_tail => &[SchedClassifier, SkSkb],
+        BPF_FUNC_skb_change_head => &[SchedClassifier, SkSkb],
+        BPF_FUNC_skb_adjust_room => &[SchedClassifier, SkSkb],
+        BPF_FUNC_set_hash => &[SchedClassifier],
+        BPF_FUNC_xdp_adjust_head => &[Xdp],
+        BPF_FUNC_xdp_adjust_meta => &[Xdp],
+        BPF_FUNC_xdp_adjust_tail => &[Xdp],
+        BPF_FUNC_xdp_load_bytes => &[Xdp],
+        BPF_FUNC_xdp_store_bytes => &[Xdp],
+        BPF_FUNC_redirect_map => &[Xdp],
+        BPF_FUNC_sk_redirect_map => &[SkSkb],
+        BPF_FUNC_sk_redirect_hash => &[SkSkb],
+        BPF_FUNC_sock_map_update => &[SockOps],
+        BPF_FUNC_sock_hash_update => &[SockOps],
+        BPF_FUNC_sock_ops_cb_flags_set => &[SockOps],
+        BPF_FUNC_load_hdr_opt => &[SockOps],
+        BPF_FUNC_store_hdr_opt => &[SockOps],
+        BPF_FUNC_reserve_hdr_opt => &[SockOps],
+        BPF_FUNC_msg_redirect_map => &[SkMsg],
+        BPF_FUNC_msg_redirect_hash => &[SkMsg],
+        BPF_FUNC_msg_apply_bytes => &[SkMsg],
+        BPF_FUNC_msg_cork_bytes => &[SkMsg],
+        BPF_FUNC_msg_pull_data => &[SkMsg],
+        BPF_FUNC_msg_push_data => &[SkMsg],
+        BPF_FUNC_msg_pop_data => &[SkMsg],
+        BPF_FUNC_bind => &[CgroupSockAddr],
+        BPF_FUNC_sk_assign => &[SchedClassifier, SkLookup],
+        BPF_FUNC_sk_select_reuseport => &[SkReuseport],
+        BPF_FUNC_check_mtu => &[SchedClassifier, Xdp],
+        BPF_FUNC_fib_lookup => &[SchedClassifier, Xdp],
+        BPF_FUNC_redirect_neigh => &[SchedClassifier],
+        BPF_FUNC_redirect_peer => &[SchedClassifier],
+        BPF_FUNC_skb_set_tstamp => &[SchedClassifier],
+        BPF_FUNC_csum_level => &[SchedClassifier],
+        BPF_FUNC_skb_ecn_set_ce => &[SchedClassifier, CgroupSkb],
+        BPF_FUNC_sysctl_get_name => &[CgroupSysctl],
+        BPF_FUNC_sysctl_get_current_value => &[CgroupSysctl],
+        BPF_FUNC_sysctl_get_new_value => &[CgroupSysctl],
+        BPF_FUNC_sysctl_set_new_value => &[CgroupSysctl],
+        BPF_FUNC_rc_repeat => &[LircMode2],
+        BPF_FUNC_rc_keydown => &[LircMode2],
+        BPF_FUNC_rc_pointer_rel => &[LircMode2],
+        BPF_FUNC_lwt_push_encap => &[],
+        BPF_FUNC_lwt_seg6_store_bytes => &[],
+        BPF_FUNC_lwt_seg6_adjust_srh => &[],
+        BPF_FUNC_lwt_seg6_action => &[],
+        BPF_FUNC_override_return => &[KProbe],
+        BPF_FUNC_get_func_ip => &[KProbe, Tracing],
+        BPF_FUNC_perf_prog_read_value => &[PerfEvent],
+        BPF_FUNC_read_branch_records => &[PerfEvent],
+        BPF_FUNC_probe_write_user => TRACING,
+        BPF_FUNC_get_stackid => TRACING,
+        BPF_FUNC_send_signal => TRACING,
+        BPF_FUNC_send_signal_thread => TRACING,
+        BPF_FUNC_d_path => &[Tracing, Lsm],
+        BPF_FUNC_seq_printf => &[Tracing],
+        BPF_FUNC_seq_write => &[Tracing],
+        BPF_FUNC_seq_printf_btf => &[Tracing],
+    }
+};
+
+/// Checks a single program.
+struct Linter<'a> {
+    program: &'a str,
+    function: &'a Function,
+    btf: Option<&'a Btf>,
+    diagnostics: &'a mut Vec<LintDiagnostic>,
+}
+
+impl Linter<'_> {
+    fn report(&mut self, instruction: usize, kind: LintKind) {
+        let function = self.function_name(instruction);
+        let source = self
+            .btf
+            .and_then(|btf| source_location(self.function, btf, instruction));
+        self.diagnostics.push(LintDiagnostic {
+            program: self.program.to_string(),
+            function,
+            instruction,
+            source,
+            kind,
+        });
+    }
+
+    /// Returns the name of the function containing `instruction` according to the `.BTF.ext`
+    /// function info.
+    fn function_name(&self, instruction: usize) -> Option<String> {
+        let btf = self.btf?;
+        let func_info = self
+            .function
+            .func_info
+            .func_info
+            .iter()
+            .filter(|func_info| func_info.insn_off as usize <= instruction)
+            .max_by_key(|func_info| func_info.insn_off)?;
+        match btf.type_by_id(func_info.type_id).ok()? {
+            ty @ BtfType::Func(_) => btf.type_name(ty).ok().map(Cow::into_owned),
+            _ => None,
+        }
+    }
+
+    fn check(&mut self, section: &ProgramSection, options: &LintOptions) {
+        let instructions = &self.function.instructions;
+        let kind = Kind::of(section);
+
+        // Subprograms start at the targets of calls and of callback references.
+        let mut starts = BTreeSet::from([0]);
+        let mut index = 0;
+        while let Some(insn) = instructions.get(index) {
+            let code = u32::from(insn.code);
+            let src = u32::from(insn.src_reg());
+            if (code == BPF_JMP | BPF_CALL && src == BPF_PSEUDO_CALL)
+                || (code == BPF_LD | BPF_IMM | BPF_DW && src == BPF_PSEUDO_FUNC)
+            {
+                let target = index as i64 + i64::from(insn.imm) + 1;
+                if let Ok(target) = usize::try_from(target)
+                    && target < instructions.len()
+                {
+                    let _: bool = starts.insert(target);
+                }
+            }
+            index += insn_len(*insn);
+        }
+        let starts = starts.into_iter().collect::<Vec<_>>();
+        let subprograms = starts
+            .iter()
+            .zip(starts.iter().skip(1).copied().chain([instructions.len()]))
+            .map(|(&start, end)| start..end)
+            .collect::<Vec<_>>();
+
+        let mut frames = Vec::with_capacity(subprograms.len());
+        for range in &subprograms {
+            let mut index = range.start;
+            while index < range.end {
+                let insn = &instructions[index];
+                self.check_instruction(index, *insn, kind, options.cpu);
+                index += insn_len(*insn);
+            }
+            self.check_loops(range.clone(), options);
+            frames.push(stack_depth(&instructions[range.clone()], range.start));
+        }
+
+        // The kernel limits the combined stack of every call chain.
+        let callees = subprograms
+            .iter()
+            .map(|range| {
+                range
+                    .clone()
+                    .filter_map(|index| {
+                        let insn = &instructions[index];
+                        (u32::from(insn.code) == BPF_JMP | BPF_CALL
+                            && u32::from(insn.src_reg()) == BPF_PSEUDO_CALL)
+                            .then(|| {
+                                let target = index as i64 + i64::from(insn.imm) + 1;
+                                starts.binary_search(&(target as usize)).ok()
+                            })
+                            .flatten()
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let mut chains = vec![None; subprograms.len()];
+        let mut on_stack = vec![false; subprograms.len()];
+        let (size, instruction) = deepest_chain(0, &frames, &callees, &mut chains, &mut on_stack);
+        if size > options.stack_limit {
+            self.report(
+                instruction,
+                LintKind::StackTooLarge {
+                    size,
+                    limit: options.stack_limit,
+                },
+            );
+        }
+    }
+
+    fn check_instruction(
+        &mut self,
+        index: usize,
+        insn: bpf_insn,
+        kind: Option<Kind>,
+        cpu: CpuVersion,
+    ) {
+        let opcode = insn.code;
+        let code = u32::from(opcode);
+        let class = code & 0x07;
+        let op = code & 0xf0;
+        let mode = code & 0xe0;
+
+        let required = match class {
+            BPF_LD => (code == BPF_LD | BPF_IMM | BPF_DW || matches!(mode, BPF_ABS | BPF_IND))
+                .then_some(CpuVersion::V1),
+            BPF_LDX => match mode {
+                BPF_MEM => Some(CpuVersion::V1),
+                BPF_MEMSX => Some(CpuVersion::V4),
+                _ => None,
+            },
+            BPF_ST => (mode == BPF_MEM).then_some(CpuVersion::V1),
+            BPF_STX => match mode {
+                BPF_MEM => Some(CpuVersion::V1),
+                BPF_ATOMIC => match insn.imm as u32 {
+                    BPF_ADD => Some(CpuVersion::V1),
+                    imm if imm == BPF_ADD | BPF_FETCH
+                        || matches!(imm & !BPF_FETCH, BPF_AND | BPF_OR | BPF_XOR)
+                        || matches!(imm, BPF_XCHG | BPF_CMPXCHG) =>
+                    {
+                        Some(CpuVersion::V3)
+                    }
+                    // Load-acquire and store-release.
+                    0x100 | 0x110 => Some(CpuVersion::V4),
+                    _ => None,
+                },
+                _ => None,
+            },
+            BPF_ALU | BPF_ALU64 => {
+                if op > BPF_END {
+                    None
+                } else if (matches!(op, BPF_DIV | BPF_MOD) && insn.off == 1)
+                    || (op == BPF_MOV && matches!(insn.off, 8 | 16 | 32))
+                    || (op == BPF_END && class == BPF_ALU64)
+                {
+                    Some(CpuVersion::V4)
+                } else {
+                    Some(CpuVersion::V1)
+                }
+            }
+            BPF_JMP => match op {
+                BPF_JCOND => Some(CpuVersion::V4),
+                op if op > BPF_JSLE => None,
+                op if op >= BPF_JLT => Some(CpuVersion::V2),
+                _ => Some(CpuVersion::V1),
+            },
+            BPF_JMP32 => match op {
+                BPF_JA => Some(CpuVersion::V4),
+                BPF_CALL | BPF_EXIT => None,
+                op if op > BPF_JSLE => None,
+                _ => Some(CpuVersion::V3),
+            },
+            _ => None,
+        };
+        match required {
+            None => self.report(index, LintKind::InvalidInstruction { opcode }),
+            Some(required) if required > cpu => {
+                self.report(index, LintKind::UnsupportedInstruction { opcode, required });
+            }
+            Some(_) => {}
+        }
+
+        if code == BPF_JMP | BPF_CALL && u32::from(insn.src_reg()) == 0 {
+            let helper = insn.imm as u32;
+            if helper == 0 || helper >= bpf_func_id::__BPF_FUNC_MAX_ID as u32 {
+                self.report(index, LintKind::UnknownHelper { helper });
+            } else if let Some(kind) = kind
+                && let Some((_, id, _)) = RESTRICTED_HELPERS
+                    .iter()
+                    .find(|(id, _, kinds)| *id as u32 == helper && !kinds.contains(&kind))
+            {
+                let name = id.strip_prefix("BPF_FUNC_").unwrap_or(id);
+                self.report(index, LintKind::UnsupportedHelper { helper, name });
+            }
+        }
+    }
+
+    /// Reports the loops of the subprogram at `range`.
+    fn check_loops(&mut self, range: Range<usize>, options: &LintOptions) {
+        let instructions = &self.function.instructions;
+        let mut next = range.start;
+        while next < range.end {
+            let index = next;
+            let insn = instructions[index];
+            next += insn_len(insn);
+            let Some(target) = jump_target(index, insn) else {
+                continue;
+            };
+            if target > index {
+                continue;
+            }
+            // A loop can only be left through an exit or a jump out of its body, unless the back
+            // edge itself is conditional.
+            let body = target..=index;
+            let unconditional = u32::from(insn.code) & 0xf0 == BPF_JA;
+            let escapes = (target..index).any(|index| {
+                let insn = instructions[index];
+                u32::from(insn.code) == BPF_JMP | BPF_EXIT
+                    || jump_target(index, insn).is_some_and(|target| !body.contains(&target))
+            });
+            if unconditional && !escapes {
+                self.report(index, LintKind::InfiniteLoop);
+            } else if !options.bounded_loops {
+                self.report(index, LintKind::Loop);
+            }
+        }
+    }
+}
+
+/// Returns the combined stack size of the deepest call chain starting at `subprogram`, along with
+/// the deepest stack access of the last subprogram of the chain.
+///
+/// `frames` and `callees` are indexed by subprogram. The result for each subprogram is memoized in
+/// `chains`. Recursive calls, which the verifier rejects, are skipped using `on_stack`.
+fn deepest_chain(
+    subprogram: usize,
+    frames: &[(u32, usize)],
+    callees: &[Vec<usize>],
+    chains: &mut [Option<(u32, usize)>],
+    on_stack: &mut [bool],
+) -> (u32, usize) {
+    if let Some(chain) = chains[subprogram] {
+        return chain;
+    }
+    on_stack[subprogram] = true;
+    let (frame, deepest) = frames[subprogram];
+    let mut chain = (0, deepest);
+    for &callee in &callees[subprogram] {
+        if on_stack[callee] {
+            continue;
+        }
+        let callee_chain = deepest_chain(callee, frames, callees, chains, on_stack);
+        if callee_chain.0 > chain.0 {
+            chain = callee_chain;
+        }
+    }
+    on_stack[subprogram] = false;
+    let (size, instruction) = chain;
+    let chain = (frame.next_multiple_of(16) + size, instruction);
+    chains[subprogram] = Some(chain);
+    chain
+}
+
+/// Returns the number of slots taken by `insn`.
+fn insn_len(insn: bpf_insn) -> usize {
+    if u32::from(insn.code) == BPF_LD | BPF_IMM | BPF_DW {
+        2
+    } else {
+        1
+    }
+}
+
+/// Returns the target of `insn` at `index` if it is a jump within the function.
+fn jump_target(index: usize, insn: bpf_insn) -> Option<usize> {
+    let code = u32::from(insn.code);
+    let class = code & 0x07;
+    let op = code & 0xf0;
+    if !matches!(class, BPF_JMP | BPF_JMP32) || matches!(op, BPF_CALL | BPF_EXIT) {
+        return None;
+    }
+    let off = if class == BPF_JMP32 && op == BPF_JA {
+        i64::from(insn.imm)
+    } else {
+        i64::from(insn.off)
+    };
+    usize::try_from(index as i64 + off + 1).ok()
+}
+
+/// Returns the stack depth of a subprogram along with the index of its deepest stack access.
+///
+/// Pointers derived from the frame pointer by adding a constant, e.g. to pass a stack buffer to a
+/// helper, count as accesses.
+fn stack_depth(instructions: &[bpf_insn], base: usize) -> (u32, usize) {
+    const FP: u8 = 10;
+
+    let mut frame_offsets = HashMap::new();
+    let mut depth = 0;
+    let mut deepest = base;
+    let mut access = |offset: i64, index: usize| {
+        let size = u32::try_from(cmp::max(-offset, 0)).unwrap_or(u32::MAX);
+        if size > depth {
+            depth = size;
+            deepest = base + index;
+        }
+    };
+    let mut index = 0;
+    while let Some(insn) = instructions.get(index) {
+        let code = u32::from(insn.code);
+        let class = code & 0x07;
+        let (dst, src) = (insn.dst_reg(), insn.src_reg());
+        match class {
+            BPF_LDX if src == FP => access(i64::from(insn.off), index),
+            BPF_LDX => {
+                let _: Option<i64> = frame_offsets.remove(&dst);
+            }
+            BPF_ST | BPF_STX if dst == FP => access(i64::from(insn.off), index),
+            BPF_ALU64 => {
+                let op = code & 0xf0;
+                let source = code & 0x08 == BPF_K;
+                match (op, source) {
+                    (BPF_MOV, false) if src == FP => {
+                        let _: Option<i64> = frame_offsets.insert(dst, 0);
+                    }
+                    (BPF_MOV, false) => match frame_offsets.get(&src).copied() {
+                        Some(offset) => {
+                            let _: Option<i64> = frame_offsets.insert(dst, offset);
+                        }
+                        None => {
+                            let _: Option<i64> = frame_offsets.remove(&dst);
+                        }
+                    },
+                    (BPF_ADD, true) => {
+                        if let Some(offset) = frame_offsets.get_mut(&dst) {
+                            *offset += i64::from(insn.imm);
+                            access(*offset, index);
+                        }
+                    }
+                    _ => {
+                        let _: Option<i64> = frame_offsets.remove(&dst);
+                    }
+                }
+            }
+            BPF_ALU => {
+                let _: Option<i64> = frame_offsets.remove(&dst);
+            }
+            BPF_LD => {
+                let _: Option<i64> = frame_offsets.remove(&dst);
+            }
+            BPF_JMP if code & 0xf0 == BPF_CALL => {
+                // Calls clobber the caller-saved registers.
+                frame_offsets.retain(|reg, _| *reg > 5);
+            }
+            _ => {}
+        }
+        index += insn_len(*insn);
+    }
+    (depth, deepest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        btf::{Func, FuncLinkage, FuncProto},
+        generated::{BPF_JEQ, BPF_W, BPF_X, bpf_func_info, bpf_line_info},
+        programs::XdpAttachType,
+        test_utils::{call, exit, insn, object},
+    };
+
+    fn lint(
+        section: ProgramSection,
+        instructions: Vec<bpf_insn>,
+        options: &LintOptions,
+    ) -> Vec<LintKind> {
+        object(section, &[&instructions])
+            .lint(options)
+            .unwrap()
+            .into_iter()
+            .map(|LintDiagnostic { kind, .. }| kind)
+            .collect()
+    }
+
+    #[test]
+    fn test_helpers() {
+        let instructions = vec![
+            call(bpf_func_id::BPF_FUNC_xdp_adjust_head),
+            call(bpf_func_id::BPF_FUNC_map_lookup_elem),
+            insn(BPF_JMP | BPF_CALL, 0, 0, 0, 10_000),
+            exit(),
+        ];
+        let options = LintOptions::default();
+        assert_eq!(
+            lint(
+                ProgramSection::Xdp {
+                    frags: false,
+                    attach_type: XdpAttachType::Interface
+                },
+                instructions.clone(),
+                &options
+            ),
+            [LintKind::UnknownHelper { helper: 10_000 }]
+        );
+        assert_eq!(
+            lint(ProgramSection::KProbe, instructions, &options),
+            [
+                LintKind::UnsupportedHelper {
+                    helper: bpf_func_id::BPF_FUNC_xdp_adjust_head as u32,
+                    name: "xdp_adjust_head",
+                },
+                LintKind::UnknownHelper { helper: 10_000 },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_stack() {
+        let main = [
+            // *(u64 *)(r10 - 8) = 0
+            insn(BPF_ST | BPF_MEM | BPF_DW, 10, 0, -8, 0),
+            // call +2
+            insn(BPF_JMP | BPF_CALL, 0, BPF_PSEUDO_CALL as u8, 0, 2),
+            exit(),
+            exit(),
+        ];
+        // The subprogram passes a 504 bytes stack buffer around.
+        let sub = [
+            insn(BPF_ALU64 | BPF_MOV | BPF_X, 1, 10, 0, 0),
+            insn(BPF_ALU64 | BPF_ADD | BPF_K, 1, 0, 0, -504),
+            exit(),
+        ];
+        let diagnostics = object(ProgramSection::SocketFilter, &[&main, &sub])
+            .lint(&LintOptions::default())
+            .unwrap();
+        // Frames are rounded up to 16 bytes, so the call chain needs 16 + 512 bytes.
+        assert_eq!(
+            diagnostics
+                .iter()
+                .map(|LintDiagnostic { kind, .. }| kind)
+                .collect::<Vec<_>>(),
+            [&LintKind::StackTooLarge {
+                size: 16 + 512,
+                limit: 512
+            }]
+        );
+        assert_eq!(diagnostics[0].instruction, 5);
+        assert!(
+            lint(
+                ProgramSection::SocketFilter,
+                vec![insn(BPF_STX | BPF_MEM | BPF_W, 10, 1, -512, 0), exit()],
+                &LintOptions::default()
+            )
+            .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_stack_shared_callees() {
+        // Every subprogram calls the next one twice, so there are 2^32 call chains.
+        const COUNT: usize = 33;
+        let call = |imm| insn(BPF_JMP | BPF_CALL, 0, BPF_PSEUDO_CALL as u8, 0, imm);
+        let store = insn(BPF_ST | BPF_MEM | BPF_DW, 10, 0, -8, 0);
+        let caller = [store, call(2), call(1), exit()];
+        let last = [store, exit()];
+        let functions = std::iter::repeat_n(&caller[..], COUNT - 1)
+            .chain([&last[..]])
+            .collect::<Vec<_>>();
+        let diagnostics = object(ProgramSection::SocketFilter, &functions)
+            .lint(&LintOptions::default())
+            .unwrap();
+        assert_eq!(
+            diagnostics
+                .into_iter()
+                .map(|LintDiagnostic { kind, .. }| kind)
+                .collect::<Vec<_>>(),
+            [LintKind::StackTooLarge {
+                size: COUNT as u32 * 16,
+                limit: 512
+            }]
+        );
+    }
+
+    #[test]
+    fn test_loops() {
+        let bounded = vec![
+            insn(BPF_ALU64 | BPF_MOV | BPF_K, 1, 0, 0, 10),
+            insn(BPF_ALU64 | BPF_ADD | BPF_K, 1, 0, 0, -1),
+            insn(BPF_JMP | BPF_JEQ | BPF_K, 1, 0, 1, 0),
+            insn(BPF_JMP | BPF_JA, 0, 0, -3, 0),
+            exit(),
+        ];
+        let options = LintOptions::default();
+        assert!(lint(ProgramSection::SocketFilter, bounded.clone(), &options).is_empty());
+        assert_eq!(
+            lint(
+                ProgramSection::SocketFilter,
+                bounded,
+                &LintOptions {
+                    bounded_loops: false,
+                    ..LintOptions::default()
+                }
+            ),
+            [LintKind::Loop]
+        );
+
+        let infinite = vec![
+            insn(BPF_ALU64 | BPF_ADD | BPF_K, 1, 0, 0, 1),
+            insn(BPF_JMP | BPF_JA, 0, 0, -2, 0),
+            exit(),
+        ];
+        assert_eq!(
+            lint(ProgramSection::SocketFilter, infinite, &options),
+            [LintKind::InfiniteLoop]
+        );
+    }
+
+    #[test]
+    fn test_cpu_version() {
+        let instructions = vec![
+            insn(BPF_JMP | BPF_JLT | BPF_K, 1, 0, 0, 0),
+            insn(BPF_JMP32 | BPF_JEQ | BPF_K, 1, 0, 0, 0),
+            insn(BPF_LDX | BPF_MEMSX | BPF_W, 0, 1, 0, 0),
+            insn(0xf7, 0, 0, 0, 0),
+            exit(),
+        ];
+        let lint = |cpu| {
+            lint(
+                ProgramSection::SocketFilter,
+                instructions.clone(),
+                &LintOptions {
+                    cpu,
+                    ..LintOptions::default()
+                },
+            )
+        };
+        assert_eq!(
+            lint(CpuVersion::V1),
+            [
+                LintKind::UnsupportedInstruction {
+                    opcode: (BPF_JMP | BPF_JLT) as u8,
+                    required: CpuVersion::V2,
+                },
+                LintKind::UnsupportedInstruction {
+                    opcode: (BPF_JMP32 | BPF_JEQ) as u8,
+                    required: CpuVersion::V3,
+                },
+                LintKind::UnsupportedInstruction {
+                    opcode: (BPF_LDX | BPF_MEMSX | BPF_W) as u8,
+                    required: CpuVersion::V4,
+                },
+                LintKind::InvalidInstruction { opcode: 0xf7 },
+            ]
+        );
+        assert_eq!(
+            lint(CpuVersion::V4),
+            [LintKind::InvalidInstruction { opcode: 0xf7 }]
+        );
+    }
+
+    #[test]
+    fn test_source_location() {
+        // `prog` calls `sub1`, which loops forever.
+        let main = [
+            insn(BPF_JMP | BPF_CALL, 0, BPF_PSEUDO_CALL as u8, 0, 1),
+            exit(),
+        ];
+        let sub = [
+            insn(BPF_ALU64 | BPF_ADD | BPF_K, 1, 0, 0, 1),
+            insn(BPF_JMP | BPF_JA, 0, 0, -2, 0),
+            exit(),
+        ];
+        let mut object = object(ProgramSection::SocketFilter, &[&main, &sub]);
+
+        let mut btf = Btf::new();
+        let proto = btf.add_type(BtfType::FuncProto(FuncProto::new(Vec::new(), 0)));
+        let file = btf.add_string("src/main.rs");
+        let func_info = |btf: &mut Btf, name| {
+            let name = btf.add_string(name);
+            let type_id = btf.add_type(BtfType::Func(Func::new(name, proto, FuncLinkage::Global)));
+            vec![bpf_func_info {
+                insn_off: 0,
+                type_id,
+            }]
+        };
+        let line_info = |btf: &mut Btf, insn_off, line: u32, column, text| bpf_line_info {
+            insn_off,
+            file_name_off: file,
+            line_off: btf.add_string(text),
+            line_col: (line << 10) | column,
+        };
+        let prog = object.functions.get_mut(&(0, 0)).unwrap();
+        prog.func_info.func_info = func_info(&mut btf, "prog");
+        prog.line_info.line_info = vec![line_info(&mut btf, 0, 2, 5, "sub1();")];
+        // The line info of `sub1` is rebased when it's linked after `prog`.
+        let sub = object.functions.get_mut(&(0, 16)).unwrap();
+        sub.func_info.func_info = func_info(&mut btf, "sub1");
+        sub.line_info.line_info = vec![
+            line_info(&mut btf, 0, 6, 9, "let mut i = 0;"),
+            line_info(&mut btf, 1, 7, 5, "    loop { i += 1 }"),
+        ];
+        object.btf = Some(btf);
+
+        assert_eq!(
+            object.lint(&LintOptions::default()).unwrap(),
+            [LintDiagnostic {
+                program: "prog".to_string(),
+                function: Some("sub1".to_string()),
+                instruction: 3,
+                source: Some(SourceLocation {
+                    file: Some("src/main.rs".to_string()),
+                    line: 7,
+                    column: 5,
+                    text: Some("loop { i += 1 }".to_string()),
+                }),
+                kind: LintKind::InfiniteLoop,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_display() {
+        let diagnostic = LintDiagnostic {
+            program: "prog".to_string(),
+            function: Some("helper".to_string()),
+            instruction: 3,
+            source: Some(SourceLocation {
+                file: Some("src/main.rs".to_string()),
+                line: 12,
+                column: 5,
+                text: Some("loop {}".to_string()),
+            }),
+            kind: LintKind::InfiniteLoop,
+        };
+        assert_eq!(
+            diagnostic.to_string(),
+            "prog (helper): insn 3: infinite loop\n  --> src/main.rs:12:5\n   | loop {}"
+        );
+    }
+}
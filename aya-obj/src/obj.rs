@@ -529,6 +529,7 @@ impl Object {
                     is_definition: symbol.is_definition(),
                     kind: symbol.kind(),
                     is_weak: symbol.is_weak(),
+                    is_global: symbol.is_global(),
                 };
                 bpf_obj.symbol_table.insert(symbol.index().0, sym);
                 if let Some(section_idx) = symbol.section().index() {
@@ -569,7 +570,11 @@ impl Object {
         Ok(bpf_obj)
     }
 
-    fn new(endianness: Endianness, license: CString, kernel_version: Option<u32>) -> Self {
+    pub(crate) fn new(
+        endianness: Endianness,
+        license: CString,
+        kernel_version: Option<u32>,
+    ) -> Self {
         Self {
             endianness,
             license,
@@ -1098,7 +1103,7 @@ pub enum EbpfSectionKind {
 }
 
 impl EbpfSectionKind {
-    fn from_name(name: &str) -> Self {
+    pub(crate) fn from_name(name: &str) -> Self {
         if name.starts_with("license") {
             Self::License
         } else if name.starts_with("version") {
@@ -1612,6 +1617,7 @@ mod tests {
                 is_definition: false,
                 kind: SymbolKind::Text,
                 is_weak: false,
+                is_global: false,
             },
         );
         obj.symbols_by_section
@@ -2829,6 +2835,7 @@ mod tests {
                 is_definition: true,
                 kind: SymbolKind::Data,
                 is_weak: false,
+                is_global: false,
             },
         );
 
@@ -117,6 +117,7 @@ pub(crate) struct Symbol {
     pub(crate) is_definition: bool,
     pub(crate) kind: SymbolKind,
     pub(crate) is_weak: bool,
+    pub(crate) is_global: bool,
 }
 
 impl Symbol {
@@ -460,7 +461,7 @@ fn relocate_maps<'a, I: Iterator<Item = &'a Relocation>>(
 
 struct FunctionLinker<'a> {
     functions: &'a BTreeMap<(usize, u64), Function>,
-    linked_functions: HashMap<u64, usize>,
+    linked_functions: HashMap<(usize, u64), usize>,
     relocations: &'a HashMap<SectionIndex, HashMap<u64, Relocation>>,
     symbol_table: &'a HashMap<usize, Symbol>,
     text_sections: &'a HashSet<usize>,
@@ -498,11 +499,12 @@ impl<'a> FunctionLinker<'a> {
         program: &mut Function,
         fun: &Function,
     ) -> Result<usize, RelocationError> {
-        if let Some(fun_ins_index) = self.linked_functions.get(&fun.address) {
+        let key = (fun.section_index.0, fun.address);
+        if let Some(fun_ins_index) = self.linked_functions.get(&key) {
             return Ok(*fun_ins_index);
         }
 
-        // append fun.instructions to the program and record that `fun.address` has been inserted
+        // append fun.instructions to the program and record that `fun` has been inserted
         // at `start_ins`. We'll use `start_ins` to do pc-relative calls.
         let start_ins = program.instructions.len();
         program.instructions.extend(&fun.instructions);
@@ -515,7 +517,7 @@ impl<'a> FunctionLinker<'a> {
         // the offset needs to be adjusted
         Self::link_func_and_line_info(program, fun, start_ins);
 
-        self.linked_functions.insert(fun.address, start_ins);
+        self.linked_functions.insert(key, start_ins);
 
         // relocate `fun`, recursively linking in all the callees
         self.relocate(program, fun)?;
@@ -698,6 +700,7 @@ mod test {
             is_definition: false,
             kind: SymbolKind::Data,
             is_weak: false,
+            is_global: false,
         }
     }
 
@@ -711,6 +714,7 @@ mod test {
             is_definition: false,
             kind: SymbolKind::Unknown,
             is_weak,
+            is_global: true,
         }
     }
 
@@ -0,0 +1,1227 @@
+//! Generation of typed Rust skeletons for eBPF objects.
+//!
+//! A skeleton is Rust source code with one struct field per program and per map of an object,
+//! typed after the program section and the map type, so that name and type mismatches between
+//! user space and the eBPF code are caught when loading the object rather than at each lookup.
+//! Global variables get typed setters on a builder.
+//!
+//! Skeletons are usually generated from `build.rs` and included in a module:
+//!
+//! ```ignore
+//! mod skel {
+//!     include!(concat!(env!("OUT_DIR"), "/probe_skel.rs"));
+//! }
+//!
+//! let mut skel = skel::ProbeBuilder::new().set_pid(&1234).load(&bytes)?;
+//! skel.xdp_pass.load()?;
+//! let total = skel.counters.get(&0, 0)?;
+//! ```
+
+use std::{
+    borrow::ToOwned as _,
+    collections::{HashMap, HashSet},
+    fmt::Write as _,
+    iter,
+};
+
+use object::SymbolKind;
+
+use crate::{
+    EbpfSectionKind, Map, Object, ProgramSection,
+    btf::{Btf, BtfError, BtfType, IntEncoding},
+    generated::bpf_map_type,
+};
+
+const KEYWORDS: &[&str] = &[
+    "as", "async", "await", "box", "break", "const", "continue", "dyn", "else", "enum", "extern",
+    "false", "fn", "for", "gen", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut",
+    "pub", "ref", "return", "static", "struct", "trait", "true", "try", "type", "unsafe", "use",
+    "where", "while", "yield",
+];
+
+/// Names which can't be used as raw identifiers, and get an underscore suffix instead.
+const RESERVED: &[&str] = &["_", "crate", "self", "Self", "super"];
+
+/// Generates the skeleton of `object`.
+///
+/// The skeleton defines:
+///
+/// * `<name>`, with a field per program and per map. Map keys and values are typed from BTF
+///   when the object has it, and are otherwise unsigned integers or byte arrays of the right
+///   size. The `ebpf` field keeps the maps and programs the skeleton can't type.
+/// * `<name>Builder`, with a `set_<global>` method per global variable and a `load` method.
+/// * A `#[repr(C)]` struct or union for every BTF composite type used by the maps and globals,
+///   with explicit padding so that it implements `aya::Pod`.
+///
+/// `name` is converted to upper camel case.
+pub fn generate(object: &Object, name: &str) -> Result<String, BtfError> {
+    let mut generator = Generator {
+        btf: object.btf.as_ref(),
+        types: String::new(),
+        type_names: HashMap::new(),
+        used_type_names: HashSet::new(),
+    };
+    let name = camel_case(name);
+    // Without the suffix of reserved names, e.g. `SelfBuilder` rather than `Self_Builder`.
+    let builder = format!("{}Builder", name.trim_end_matches('_'));
+    // The generated code also refers to types from the prelude.
+    for type_name in [&name, &builder, "Default", "Result"] {
+        let _: bool = generator.used_type_names.insert(type_name.to_owned());
+    }
+
+    let mut fields = Fields::new();
+    let mut programs = object.programs.iter().collect::<Vec<_>>();
+    programs.sort_by_key(|(name, _)| *name);
+    for (prog_name, program) in programs {
+        let ty = program_type(&program.section);
+        fields.push(
+            prog_name,
+            format!("::aya::programs::{ty}"),
+            "take_program",
+            "programs",
+        );
+    }
+    let mut maps = object.maps.iter().collect::<Vec<_>>();
+    maps.sort_by_key(|(name, _)| *name);
+    for (map_name, map) in maps {
+        if is_data_map(map) {
+            continue;
+        }
+        if let Some(ty) = generator.map_type(map)? {
+            fields.push(map_name, ty, "take_map", "maps");
+        }
+    }
+    let globals = generator.globals(object)?;
+
+    let mut out = String::new();
+    out.push_str("// Generated from an eBPF object by `aya_obj::skeleton`. Do not edit.\n\n");
+    out.push_str(&generator.types);
+
+    writeln!(out, "/// Typed programs and maps of the eBPF object.").unwrap();
+    writeln!(out, "pub struct {name} {{").unwrap();
+    for Field {
+        ident, ty, name, ..
+    } in &fields.fields
+    {
+        writeln!(out, "    /// The `{name}` {}.", field_kind(ty)).unwrap();
+        writeln!(out, "    pub {ident}: {ty},").unwrap();
+    }
+    writeln!(
+        out,
+        "    /// The loaded object, holding the programs and maps without a field."
+    )
+    .unwrap();
+    writeln!(out, "    pub ebpf: ::aya::Ebpf,").unwrap();
+    writeln!(out, "}}\n").unwrap();
+
+    writeln!(out, "impl {name} {{").unwrap();
+    writeln!(
+        out,
+        "    /// Loads the object from `data`, leaving global variables at their initial values."
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "    pub fn load(data: &[u8]) -> Result<Self, ::aya::EbpfError> {{"
+    )
+    .unwrap();
+    writeln!(out, "        {builder}::new().load(data)").unwrap();
+    writeln!(out, "    }}\n").unwrap();
+    writeln!(
+        out,
+        "    fn from_ebpf(mut ebpf: ::aya::Ebpf) -> Result<Self, ::aya::EbpfError> {{"
+    )
+    .unwrap();
+    writeln!(out, "        Ok(Self {{").unwrap();
+    for Field {
+        ident,
+        name,
+        take,
+        error,
+        ..
+    } in &fields.fields
+    {
+        writeln!(out, "            {ident}: ebpf").unwrap();
+        writeln!(
+            out,
+            "                .{take}(\"{}\")",
+            name.escape_default()
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "                .ok_or_else(|| ::aya::{error}::{}Error::InvalidName {{",
+            if *error == "maps" { "Map" } else { "Program" }
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "                    name: \"{}\".to_owned(),",
+            name.escape_default()
+        )
+        .unwrap();
+        writeln!(out, "                }})?").unwrap();
+        writeln!(out, "                .try_into()?,").unwrap();
+    }
+    writeln!(out, "            ebpf,").unwrap();
+    writeln!(out, "        }})").unwrap();
+    writeln!(out, "    }}").unwrap();
+    writeln!(out, "}}\n").unwrap();
+
+    writeln!(
+        out,
+        "/// Sets the global variables of [`{name}`] before loading it."
+    )
+    .unwrap();
+    writeln!(out, "pub struct {builder}<'a> {{").unwrap();
+    writeln!(out, "    loader: ::aya::EbpfLoader<'a>,").unwrap();
+    writeln!(out, "}}\n").unwrap();
+    writeln!(out, "impl Default for {builder}<'_> {{").unwrap();
+    writeln!(out, "    fn default() -> Self {{").unwrap();
+    writeln!(out, "        Self::new()").unwrap();
+    writeln!(out, "    }}").unwrap();
+    writeln!(out, "}}\n").unwrap();
+    writeln!(out, "impl<'a> {builder}<'a> {{").unwrap();
+    writeln!(
+        out,
+        "    /// Creates a builder with the default loader options."
+    )
+    .unwrap();
+    writeln!(out, "    pub fn new() -> Self {{").unwrap();
+    writeln!(out, "        Self {{").unwrap();
+    writeln!(out, "            loader: ::aya::EbpfLoader::new(),").unwrap();
+    writeln!(out, "        }}").unwrap();
+    writeln!(out, "    }}\n").unwrap();
+    writeln!(
+        out,
+        "    /// Returns the underlying loader, to set options other than global variables."
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "    pub fn loader(&mut self) -> &mut ::aya::EbpfLoader<'a> {{"
+    )
+    .unwrap();
+    writeln!(out, "        &mut self.loader").unwrap();
+    writeln!(out, "    }}\n").unwrap();
+    for Global { name, ident, ty } in &globals {
+        writeln!(
+            out,
+            "    /// Sets the initial value of the `{name}` global."
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "    pub fn set_{ident}(&mut self, value: &'a {ty}) -> &mut Self {{"
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "        self.loader.override_global(\"{}\", value, true);",
+            name.escape_default()
+        )
+        .unwrap();
+        writeln!(out, "        self").unwrap();
+        writeln!(out, "    }}\n").unwrap();
+    }
+    writeln!(out, "    /// Loads the object from `data`.").unwrap();
+    writeln!(
+        out,
+        "    pub fn load(&mut self, data: &[u8]) -> Result<{name}, ::aya::EbpfError> {{"
+    )
+    .unwrap();
+    writeln!(out, "        {name}::from_ebpf(self.loader.load(data)?)").unwrap();
+    writeln!(out, "    }}").unwrap();
+    writeln!(out, "}}").unwrap();
+    Ok(out)
+}
+
+struct Field {
+    name: String,
+    ident: String,
+    ty: String,
+    take: &'static str,
+    error: &'static str,
+}
+
+struct Fields {
+    fields: Vec<Field>,
+    idents: HashSet<String>,
+}
+
+impl Fields {
+    fn new() -> Self {
+        Self {
+            fields: Vec::new(),
+            // The field holding the object, and the local it's moved from.
+            idents: ["ebpf", "from_ebpf"].map(ToOwned::to_owned).into(),
+        }
+    }
+
+    fn push(&mut self, name: &str, ty: String, take: &'static str, error: &'static str) {
+        let ident = unique(&mut self.idents, snake_case(name));
+        self.fields.push(Field {
+            name: name.to_owned(),
+            ident: raw_ident(ident),
+            ty,
+            take,
+            error,
+        });
+    }
+}
+
+struct Global {
+    name: String,
+    ident: String,
+    ty: String,
+}
+
+/// A Rust type generated for a BTF type.
+#[derive(Clone)]
+struct RustType {
+    name: String,
+    size: u32,
+    align: u32,
+    /// Whether the type implements `Debug`, which generated unions don't.
+    debug: bool,
+}
+
+impl RustType {
+    fn int(size: u32, signed: bool) -> Self {
+        match (size, signed) {
+            (1 | 2 | 4 | 8 | 16, true) => Self::primitive(format!("i{}", size * 8), size),
+            (1 | 2 | 4 | 8 | 16, false) => Self::primitive(format!("u{}", size * 8), size),
+            (size, _) => Self::bytes(size),
+        }
+    }
+
+    const fn primitive(name: String, size: u32) -> Self {
+        Self {
+            name,
+            size,
+            align: size,
+            debug: true,
+        }
+    }
+
+    fn bytes(size: u32) -> Self {
+        Self {
+            name: format!("[u8; {size}]"),
+            size,
+            align: 1,
+            debug: true,
+        }
+    }
+}
+
+struct Generator<'a> {
+    btf: Option<&'a Btf>,
+    /// Definitions of the generated types.
+    types: String,
+    type_names: HashMap<u32, RustType>,
+    used_type_names: HashSet<String>,
+}
+
+impl Generator<'_> {
+    /// Returns the Rust type of `type_id`, or an integer or byte array of `size` bytes when the
+    /// BTF doesn't describe it.
+    fn rust_type(&mut self, type_id: u32, size: u32) -> Result<RustType, BtfError> {
+        let Some(btf) = self.btf.filter(|_| type_id != 0) else {
+            return Ok(RustType::int(size, false));
+        };
+        let type_id = btf.resolve_type(type_id)?;
+        let ty = match btf.type_by_id(type_id)? {
+            BtfType::Int(int) => match int.encoding() {
+                IntEncoding::Signed => RustType::int(int.size, true),
+                IntEncoding::None
+                | IntEncoding::Char
+                | IntEncoding::Bool
+                | IntEncoding::Unknown => RustType::int(int.size, false),
+            },
+            BtfType::Enum(e) => RustType::int(e.size, e.is_signed()),
+            BtfType::Enum64(e) => RustType::int(e.size, e.is_signed()),
+            BtfType::Ptr(_) => RustType::int(8, false),
+            BtfType::Array(array) => {
+                let len = array.array.len;
+                let element_size = btf.type_size(array.array.element_type)? as u32;
+                let element = self.rust_type(array.array.element_type, element_size)?;
+                RustType {
+                    name: format!("[{}; {len}]", element.name),
+                    size: element.size * len,
+                    ..element
+                }
+            }
+            BtfType::Struct(_) | BtfType::Union(_) => self.composite(type_id)?,
+            ty => RustType::bytes(ty.size().unwrap_or(size)),
+        };
+        // The BTF and the object disagree, trust the object.
+        Ok(if ty.size == size {
+            ty
+        } else {
+            RustType::int(size, false)
+        })
+    }
+
+    /// Defines a Rust struct or union with the layout of the BTF composite `type_id`.
+    fn composite(&mut self, type_id: u32) -> Result<RustType, BtfError> {
+        if let Some(ty) = self.type_names.get(&type_id) {
+            return Ok(ty.clone());
+        }
+        let Some(btf) = self.btf else {
+            return Err(BtfError::UnknownBtfType { type_id });
+        };
+        let ty = btf.type_by_id(type_id)?;
+        let (is_struct, size) = match ty {
+            BtfType::Struct(s) => (true, s.size),
+            BtfType::Union(u) => (false, u.size),
+            _ => return Err(BtfError::UnexpectedBtfType { type_id }),
+        };
+        let btf_name = btf.type_name(ty)?;
+        // Rust types are named after their path, e.g. `probe_common::FlowKey`.
+        let btf_name = btf_name.rsplit("::").next().unwrap_or_default();
+        let name = if btf_name.is_empty() {
+            format!("Anon{type_id}")
+        } else {
+            camel_case(btf_name)
+        };
+        let name = unique(&mut self.used_type_names, name);
+
+        let mut field_idents = HashSet::new();
+        // (offset, size, align, ident, type)
+        let mut fields: Vec<(u32, u32, u32, String, RustType)> = Vec::new();
+        let mut bitfield: Option<(u32, u32)> = None;
+        let members = ty.members().into_iter().flatten().collect::<Vec<_>>();
+        let mut bitfields = Vec::new();
+        for member in members {
+            let bit_offset = ty.member_bit_offset(member).unwrap_or(0) as u32;
+            let bit_size = ty.member_bit_field_size(member).unwrap_or(0) as u32;
+            if bit_size != 0 || !bit_offset.is_multiple_of(8) {
+                // Bitfields are stored as the bytes they span.
+                let bit_size = if bit_size == 0 {
+                    btf.type_size(member.btf_type)? as u32 * 8
+                } else {
+                    bit_size
+                };
+                let start = bit_offset / 8;
+                let end = (bit_offset + bit_size).div_ceil(8);
+                bitfield = match bitfield {
+                    Some((bf_start, bf_end)) if is_struct && start < bf_end => {
+                        Some((bf_start, bf_end.max(end)))
+                    }
+                    Some(prev) => {
+                        bitfields.push(prev);
+                        Some((start, end))
+                    }
+                    None => Some((start, end)),
+                };
+                continue;
+            }
+            if let Some(prev) = bitfield.take() {
+                bitfields.push(prev);
+            }
+            let member_size = btf.type_size(member.btf_type)? as u32;
+            let member_type = self.rust_type(member.btf_type, member_size)?;
+            let member_name = btf.string_at(member.name_offset)?;
+            let ident = if member_name.is_empty() {
+                format!("anon_{}", fields.len())
+            } else {
+                snake_case(&member_name)
+            };
+            let ident = raw_ident(unique(&mut field_idents, ident));
+            fields.push((
+                bit_offset / 8,
+                member_type.size,
+                member_type.align,
+                ident,
+                member_type,
+            ));
+        }
+        bitfields.extend(bitfield);
+        for (i, (start, end)) in bitfields.into_iter().enumerate() {
+            let ident = unique(&mut field_idents, format!("_bitfield_{}", i + 1));
+            fields.push((start, end - start, 1, ident, RustType::bytes(end - start)));
+        }
+        fields.sort_by_key(|(offset, ..)| *offset);
+
+        let mut body = String::new();
+        let mut offset = 0;
+        let mut align = 1;
+        let mut packed = false;
+        let mut debug = is_struct;
+        let mut pads = 0;
+        for (field_offset, field_size, field_align, ident, field_type) in fields {
+            if is_struct && field_offset > offset {
+                pads += 1;
+                let ident = unique(&mut field_idents, format!("_pad_{pads}"));
+                writeln!(body, "    pub {ident}: [u8; {}],", field_offset - offset).unwrap();
+            }
+            packed |= is_struct && !field_offset.is_multiple_of(field_align);
+            align = align.max(field_align);
+            debug &= field_type.debug;
+            writeln!(body, "    pub {ident}: {},", field_type.name).unwrap();
+            offset = offset.max(field_offset + field_size);
+        }
+        if offset < size {
+            // Unions are padded with a member spanning all of them.
+            let ident = if is_struct {
+                pads += 1;
+                format!("_pad_{pads}")
+            } else {
+                "_bytes".to_owned()
+            };
+            let ident = unique(&mut field_idents, ident);
+            let len = if is_struct { size - offset } else { size };
+            writeln!(body, "    pub {ident}: [u8; {len}],").unwrap();
+        }
+        packed |= !size.is_multiple_of(align);
+        if packed {
+            align = 1;
+        }
+
+        let keyword = if is_struct { "struct" } else { "union" };
+        let repr = if packed { "C, packed" } else { "C" };
+        let derives = if debug {
+            "Clone, Copy, Debug"
+        } else {
+            "Clone, Copy"
+        };
+        writeln!(self.types, "#[repr({repr})]").unwrap();
+        writeln!(self.types, "#[derive({derives})]").unwrap();
+        writeln!(self.types, "pub {keyword} {name} {{").unwrap();
+        self.types.push_str(&body);
+        writeln!(self.types, "}}\n").unwrap();
+        writeln!(self.types, "unsafe impl ::aya::Pod for {name} {{}}\n").unwrap();
+
+        let ty = RustType {
+            name,
+            size,
+            align,
+            debug,
+        };
+        let _unused: Option<RustType> = self.type_names.insert(type_id, ty.clone());
+        Ok(ty)
+    }
+
+    /// Returns the typed map wrapper for `map`, or `None` if aya has no typed map for it.
+    fn map_type(&mut self, map: &Map) -> Result<Option<String>, BtfError> {
+        let (key_type_id, value_type_id) = match map {
+            Map::Btf(map) => (map.def.btf_key_type_id, map.def.btf_value_type_id),
+            Map::Legacy(_) => (0, 0),
+        };
+        let key = self.rust_type(key_type_id, map.key_size())?.name;
+        let value = self.rust_type(value_type_id, map.value_size())?.name;
+        let Ok(map_type) = bpf_map_type::try_from(map.map_type()) else {
+            return Ok(None);
+        };
+        let (ty, params) = match map_type {
+            bpf_map_type::BPF_MAP_TYPE_ARRAY => ("Array", vec![value]),
+            bpf_map_type::BPF_MAP_TYPE_PERCPU_ARRAY => ("PerCpuArray", vec![value]),
+            bpf_map_type::BPF_MAP_TYPE_HASH | bpf_map_type::BPF_MAP_TYPE_LRU_HASH => {
+                ("HashMap", vec![key, value])
+            }
+            bpf_map_type::BPF_MAP_TYPE_PERCPU_HASH | bpf_map_type::BPF_MAP_TYPE_LRU_PERCPU_HASH => {
+                ("PerCpuHashMap", vec![key, value])
+            }
+            bpf_map_type::BPF_MAP_TYPE_LPM_TRIE => {
+                let data = self.lpm_data_type(key_type_id, map.key_size())?;
+                ("LpmTrie", vec![data, value])
+            }
+            bpf_map_type::BPF_MAP_TYPE_BLOOM_FILTER => ("BloomFilter", vec![value]),
+            bpf_map_type::BPF_MAP_TYPE_QUEUE => ("Queue", vec![value]),
+            bpf_map_type::BPF_MAP_TYPE_STACK => ("Stack", vec![value]),
+            bpf_map_type::BPF_MAP_TYPE_SK_STORAGE => ("SkStorage", vec![value]),
+            bpf_map_type::BPF_MAP_TYPE_INODE_STORAGE => ("InodeStorage", vec![value]),
+            bpf_map_type::BPF_MAP_TYPE_CGRP_STORAGE => ("CgrpStorage", vec![value]),
+            bpf_map_type::BPF_MAP_TYPE_SOCKHASH => ("SockHash", vec![key]),
+            bpf_map_type::BPF_MAP_TYPE_CGROUP_ARRAY => ("CgroupArray", vec![]),
+            bpf_map_type::BPF_MAP_TYPE_CPUMAP => ("CpuMap", vec![]),
+            bpf_map_type::BPF_MAP_TYPE_DEVMAP => ("DevMap", vec![]),
+            bpf_map_type::BPF_MAP_TYPE_DEVMAP_HASH => ("DevMapHash", vec![]),
+            bpf_map_type::BPF_MAP_TYPE_PERF_EVENT_ARRAY => ("PerfEventArray", vec![]),
+            bpf_map_type::BPF_MAP_TYPE_PROG_ARRAY => ("ProgramArray", vec![]),
+            bpf_map_type::BPF_MAP_TYPE_REUSEPORT_SOCKARRAY => ("ReusePortSockArray", vec![]),
+            bpf_map_type::BPF_MAP_TYPE_RINGBUF => ("RingBuf", vec![]),
+            bpf_map_type::BPF_MAP_TYPE_SOCKMAP => ("SockMap", vec![]),
+            bpf_map_type::BPF_MAP_TYPE_STACK_TRACE => ("StackTraceMap", vec![]),
+            bpf_map_type::BPF_MAP_TYPE_XSKMAP => ("XskMap", vec![]),
+            _ => return Ok(None),
+        };
+        let params = iter::once("::aya::maps::MapData".to_owned())
+            .chain(params)
+            .collect::<Vec<_>>()
+            .join(", ");
+        Ok(Some(format!("::aya::maps::{ty}<{params}>")))
+    }
+
+    /// Returns the type of the data following the prefix length in an LPM trie key.
+    fn lpm_data_type(&mut self, key_type_id: u32, key_size: u32) -> Result<String, BtfError> {
+        let data_size = key_size.saturating_sub(4);
+        if let Some(btf) = self.btf.filter(|_| key_type_id != 0) {
+            let key = btf.type_by_id(btf.resolve_type(key_type_id)?)?;
+            if let Some(data) = key
+                .members()
+                .into_iter()
+                .flatten()
+                .find(|member| key.member_bit_offset(member) == Some(32))
+            {
+                return Ok(self.rust_type(data.btf_type, data_size)?.name);
+            }
+        }
+        Ok(RustType::int(data_size, false).name)
+    }
+
+    /// Returns the global variables of `object` that can be set before loading it.
+    fn globals(&mut self, object: &Object) -> Result<Vec<Global>, BtfError> {
+        let data_sections = object
+            .maps
+            .values()
+            .filter(|map| is_data_map(map))
+            .map(Map::section_index)
+            .collect::<HashSet<_>>();
+        let mut var_types = HashMap::new();
+        if let Some(btf) = self.btf {
+            for ty in btf.types() {
+                if let BtfType::Var(var) = ty {
+                    let _: Option<u32> =
+                        var_types.insert(btf.type_name(ty)?.into_owned(), var.btf_type);
+                }
+            }
+        }
+        let mut symbols = object
+            .symbol_table
+            .values()
+            .filter(|symbol| {
+                symbol.kind == SymbolKind::Data
+                    && symbol
+                        .section_index
+                        .is_some_and(|index| data_sections.contains(&index))
+            })
+            .filter_map(|symbol| Some((symbol.name.as_deref()?, symbol.size as u32)))
+            // Skip compiler generated symbols such as string literals.
+            .filter(|(name, _)| is_c_ident(name))
+            .collect::<Vec<_>>();
+        symbols.sort_unstable();
+        symbols.dedup();
+
+        let mut idents = HashSet::new();
+        let mut globals = Vec::with_capacity(symbols.len());
+        for (name, size) in symbols {
+            let type_id = var_types.get(name).copied().unwrap_or(0);
+            let ty = self.rust_type(type_id, size)?.name;
+            globals.push(Global {
+                name: name.to_owned(),
+                ident: unique(&mut idents, snake_case(name)),
+                ty,
+            });
+        }
+        Ok(globals)
+    }
+}
+
+const fn is_data_map(map: &Map) -> bool {
+    matches!(
+        map.section_kind(),
+        EbpfSectionKind::Data | EbpfSectionKind::Rodata | EbpfSectionKind::Bss
+    )
+}
+
+/// Returns the name of the `aya::programs` type loaded from `section`.
+const fn program_type(section: &ProgramSection) -> &'static str {
+    match section {
+        ProgramSection::KProbe | ProgramSection::KRetProbe => "KProbe",
+        ProgramSection::UProbe { .. } | ProgramSection::URetProbe { .. } => "UProbe",
+        ProgramSection::TracePoint => "TracePoint",
+        ProgramSection::SocketFilter => "SocketFilter",
+        ProgramSection::Xdp { .. } => "Xdp",
+        ProgramSection::SkMsg => "SkMsg",
+        ProgramSection::SkSkbStream { .. } => "SkSkb",
+        ProgramSection::SockOps => "SockOps",
+        ProgramSection::SchedClassifier => "SchedClassifier",
+        ProgramSection::CgroupSkb { .. } => "CgroupSkb",
+        ProgramSection::CgroupSockAddr { .. } => "CgroupSockAddr",
+        ProgramSection::CgroupSysctl => "CgroupSysctl",
+        ProgramSection::CgroupSockopt { .. } => "CgroupSockopt",
+        ProgramSection::LircMode2 => "LircMode2",
+        ProgramSection::PerfEvent => "PerfEvent",
+        ProgramSection::RawTracePoint => "RawTracePoint",
+        ProgramSection::Lsm { .. } => "Lsm",
+        ProgramSection::LsmCgroup => "LsmCgroup",
+        ProgramSection::BtfTracePoint => "BtfTracePoint",
+        ProgramSection::FEntry { .. } => "FEntry",
+        ProgramSection::FExit { .. } => "FExit",
+        ProgramSection::FlowDissector => "FlowDissector",
+        ProgramSection::Extension => "Extension",
+        ProgramSection::SkLookup => "SkLookup",
+        ProgramSection::SkReuseport { .. } => "SkReuseport",
+        ProgramSection::CgroupSock { .. } => "CgroupSock",
+        ProgramSection::CgroupDevice => "CgroupDevice",
+        ProgramSection::Iter { .. } => "Iter",
+    }
+}
+
+fn field_kind(ty: &str) -> &'static str {
+    if ty.starts_with("::aya::maps::") {
+        "map"
+    } else {
+        "program"
+    }
+}
+
+fn is_c_ident(name: &str) -> bool {
+    name.chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Converts `name` to a snake case identifier, e.g. `RxPackets` to `rx_packets`.
+fn snake_case(name: &str) -> String {
+    let mut ident = String::with_capacity(name.len());
+    let mut prev_lower = false;
+    for c in name.chars() {
+        if c.is_ascii_uppercase() {
+            if prev_lower {
+                ident.push('_');
+            }
+            ident.push(c.to_ascii_lowercase());
+            prev_lower = false;
+        } else if c.is_ascii_alphanumeric() {
+            ident.push(c);
+            prev_lower = c.is_ascii_lowercase() || c.is_ascii_digit();
+        } else {
+            ident.push('_');
+            prev_lower = false;
+        }
+    }
+    if ident.is_empty() || ident.starts_with(|c: char| c.is_ascii_digit()) {
+        ident.insert(0, '_');
+    }
+    if RESERVED.contains(&ident.as_str()) {
+        ident.push('_');
+    }
+    ident
+}
+
+/// Converts `name` to an upper camel case identifier, e.g. `flow_key` to `FlowKey`.
+fn camel_case(name: &str) -> String {
+    let mut ident = String::with_capacity(name.len());
+    let mut upper = true;
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() {
+            ident.push(if upper { c.to_ascii_uppercase() } else { c });
+            upper = false;
+        } else {
+            upper = true;
+        }
+    }
+    if ident.is_empty() || ident.starts_with(|c: char| c.is_ascii_digit()) {
+        ident.insert(0, '_');
+    }
+    if RESERVED.contains(&ident.as_str()) {
+        ident.push('_');
+    }
+    ident
+}
+
+fn raw_ident(ident: String) -> String {
+    if KEYWORDS.contains(&ident.as_str()) {
+        format!("r#{ident}")
+    } else {
+        ident
+    }
+}
+
+/// Suffixes `name` with a number if it's already in `names`.
+fn unique(names: &mut HashSet<String>, name: String) -> String {
+    let mut unique = name.clone();
+    let mut n = 1;
+    while names.contains(&unique) {
+        n += 1;
+        unique = format!("{name}_{n}");
+    }
+    let _: bool = names.insert(unique.clone());
+    unique
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CString;
+
+    use object::Endianness;
+
+    use super::*;
+    use crate::{
+        Program,
+        btf::{Array, BtfMember, Int, Struct, Union, Var, VarLinkage},
+        maps::{BtfMap, BtfMapDef, LegacyMap, bpf_map_def},
+        programs::XdpAttachType,
+        relocation::Symbol,
+    };
+
+    #[test]
+    fn test_generate() {
+        let mut btf = Btf::new();
+        let name_offset = btf.add_string("unsigned int");
+        let uint = btf.add_type(BtfType::Int(Int::new(name_offset, 4, IntEncoding::None, 0)));
+        let name_offset = btf.add_string("unsigned char");
+        let uchar = btf.add_type(BtfType::Int(Int::new(name_offset, 1, IntEncoding::None, 0)));
+        let name_offset = btf.add_string("long long");
+        let long = btf.add_type(BtfType::Int(Int::new(
+            name_offset,
+            8,
+            IntEncoding::Signed,
+            0,
+        )));
+        let members = vec![
+            BtfMember {
+                name_offset: btf.add_string("proto"),
+                btf_type: uchar,
+                offset: 0,
+            },
+            BtfMember {
+                name_offset: btf.add_string("rxBytes"),
+                btf_type: long,
+                offset: 64,
+            },
+        ];
+        let name_offset = btf.add_string("flow_stats");
+        let stats = btf.add_type(BtfType::Struct(Struct::new(name_offset, members, 16)));
+        let name_offset = btf.add_string("PID");
+        btf.add_type(BtfType::Var(Var::new(
+            name_offset,
+            uint,
+            VarLinkage::Global,
+        )));
+
+        let mut object = Object::new(Endianness::Little, CString::new("GPL").unwrap(), None);
+        object.btf = Some(btf);
+        object.programs.extend([(
+            "xdp_pass".to_owned(),
+            Program {
+                license: CString::new("GPL").unwrap(),
+                kernel_version: None,
+                section: ProgramSection::Xdp {
+                    frags: false,
+                    attach_type: XdpAttachType::Interface,
+                },
+                section_index: 1,
+                address: 0,
+            },
+        )]);
+        object.maps.extend([(
+            "STATS".to_owned(),
+            Map::Btf(BtfMap {
+                def: BtfMapDef {
+                    map_type: bpf_map_type::BPF_MAP_TYPE_HASH as u32,
+                    key_size: 4,
+                    value_size: 16,
+                    max_entries: 1024,
+                    btf_key_type_id: uint,
+                    btf_value_type_id: stats,
+                    ..Default::default()
+                },
+                inner_def: None,
+                section_index: 2,
+                symbol_index: 0,
+                data: Vec::new(),
+            }),
+        )]);
+        let legacy = |map_type: bpf_map_type, section_index, section_kind| {
+            Map::Legacy(LegacyMap {
+                def: bpf_map_def {
+                    map_type: map_type as u32,
+                    key_size: 4,
+                    value_size: 2,
+                    max_entries: 1,
+                    ..Default::default()
+                },
+                inner_def: None,
+                section_index,
+                section_kind,
+                symbol_index: None,
+                data: Vec::new(),
+            })
+        };
+        object.maps.extend([
+            (
+                "COUNTS".to_owned(),
+                legacy(bpf_map_type::BPF_MAP_TYPE_ARRAY, 3, EbpfSectionKind::Maps),
+            ),
+            (
+                ".rodata".to_owned(),
+                legacy(bpf_map_type::BPF_MAP_TYPE_ARRAY, 4, EbpfSectionKind::Rodata),
+            ),
+        ]);
+        // String literals get symbols too, but aren't globals.
+        object.symbol_table.extend(
+            [(0, "PID", 4), (1, ".L.str", 6)].map(|(index, name, size)| {
+                (
+                    index,
+                    Symbol {
+                        index,
+                        section_index: Some(4),
+                        name: Some(name.to_owned()),
+                        address: 0,
+                        size,
+                        is_definition: true,
+                        kind: SymbolKind::Data,
+                        is_weak: false,
+                        is_global: true,
+                    },
+                )
+            }),
+        );
+
+        let skeleton = generate(&object, "probe").unwrap();
+        for expected in [
+            "#[repr(C)]\n#[derive(Clone, Copy, Debug)]\npub struct FlowStats {\n    pub proto: u8,\n    pub _pad_1: [u8; 7],\n    pub rx_bytes: i64,\n}",
+            "unsafe impl ::aya::Pod for FlowStats {}",
+            "pub struct Probe {",
+            "pub xdp_pass: ::aya::programs::Xdp,",
+            "pub stats: ::aya::maps::HashMap<::aya::maps::MapData, u32, FlowStats>,",
+            "pub counts: ::aya::maps::Array<::aya::maps::MapData, u16>,",
+            ".take_map(\"STATS\")",
+            "pub fn set_pid(&mut self, value: &'a u32) -> &mut Self {",
+            "override_global(\"PID\", value, true)",
+        ] {
+            assert!(skeleton.contains(expected), "{expected} not in {skeleton}");
+        }
+        assert!(!skeleton.contains("rodata"), "{skeleton}");
+        assert!(!skeleton.contains("L.str"), "{skeleton}");
+    }
+
+    #[test]
+    fn test_generate_reserved_names() {
+        let mut object = Object::new(Endianness::Little, CString::new("GPL").unwrap(), None);
+        object.programs.extend(["ebpf", "crate"].map(|name| {
+            (
+                name.to_owned(),
+                Program {
+                    license: CString::new("GPL").unwrap(),
+                    kernel_version: None,
+                    section: ProgramSection::Xdp {
+                        frags: false,
+                        attach_type: XdpAttachType::Interface,
+                    },
+                    section_index: 1,
+                    address: 0,
+                },
+            )
+        }));
+        object
+            .maps
+            .extend(["self", "type", "from_ebpf"].map(|name| {
+                (
+                    name.to_owned(),
+                    Map::Legacy(LegacyMap {
+                        def: bpf_map_def {
+                            map_type: bpf_map_type::BPF_MAP_TYPE_ARRAY as u32,
+                            key_size: 4,
+                            value_size: 4,
+                            max_entries: 1,
+                            ..Default::default()
+                        },
+                        inner_def: None,
+                        section_index: 2,
+                        section_kind: EbpfSectionKind::Maps,
+                        symbol_index: None,
+                        data: Vec::new(),
+                    }),
+                )
+            }));
+
+        let skeleton = generate(&object, "self").unwrap();
+        for expected in [
+            "pub struct Self_ {",
+            "pub struct SelfBuilder<'a> {",
+            "pub crate_: ::aya::programs::Xdp,",
+            // Fields don't clash with the field holding the object.
+            "pub ebpf_2: ::aya::programs::Xdp,",
+            "ebpf_2: ebpf\n                .take_program(\"ebpf\")",
+            "pub from_ebpf_2: ::aya::maps::Array<::aya::maps::MapData, u32>,",
+            "pub self_: ::aya::maps::Array<::aya::maps::MapData, u32>,",
+            "pub r#type: ::aya::maps::Array<::aya::maps::MapData, u32>,",
+            "    pub ebpf: ::aya::Ebpf,",
+        ] {
+            assert!(skeleton.contains(expected), "{expected} not in {skeleton}");
+        }
+    }
+
+    fn member(btf: &mut Btf, name: &str, btf_type: u32, offset: u32) -> BtfMember {
+        BtfMember {
+            name_offset: btf.add_string(name),
+            btf_type,
+            offset,
+        }
+    }
+
+    fn int(btf: &mut Btf, name: &str, size: u32) -> u32 {
+        let name_offset = btf.add_string(name);
+        btf.add_type(BtfType::Int(Int::new(
+            name_offset,
+            size,
+            IntEncoding::None,
+            0,
+        )))
+    }
+
+    /// Generates the composite `type_id`, returning the definitions along with the size and
+    /// alignment of the type.
+    fn composite(btf: &Btf, type_id: u32) -> (String, u32, u32) {
+        let mut generator = Generator {
+            btf: Some(btf),
+            types: String::new(),
+            type_names: HashMap::new(),
+            used_type_names: HashSet::new(),
+        };
+        let RustType { size, align, .. } = generator.composite(type_id).unwrap();
+        (generator.types, size, align)
+    }
+
+    #[test]
+    fn test_composite_padding() {
+        // struct padded { u8 a; u32 b; u8 c; };
+        let mut btf = Btf::new();
+        let u8_type = int(&mut btf, "u8", 1);
+        let u32_type = int(&mut btf, "u32", 4);
+        let members = vec![
+            member(&mut btf, "a", u8_type, 0),
+            member(&mut btf, "b", u32_type, 32),
+            member(&mut btf, "c", u8_type, 64),
+        ];
+        let name_offset = btf.add_string("padded");
+        let padded = btf.add_type(BtfType::Struct(Struct::new(name_offset, members, 12)));
+
+        assert_eq!(
+            composite(&btf, padded),
+            (
+                "#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct Padded {
+    pub a: u8,
+    pub _pad_1: [u8; 3],
+    pub b: u32,
+    pub c: u8,
+    pub _pad_2: [u8; 3],
+}
+
+unsafe impl ::aya::Pod for Padded {}
+
+"
+                .to_owned(),
+                12,
+                4
+            )
+        );
+    }
+
+    #[test]
+    fn test_composite_bitfields() {
+        // struct flags { u32 flags: 3; u32 kind: 13; u16 port; u32 last: 1; };
+        let mut btf = Btf::new();
+        let u16_type = int(&mut btf, "u16", 2);
+        let u32_type = int(&mut btf, "u32", 4);
+        let members = vec![
+            member(&mut btf, "flags", u32_type, 3 << 24),
+            member(&mut btf, "kind", u32_type, (13 << 24) | 3),
+            member(&mut btf, "port", u16_type, 16),
+            member(&mut btf, "last", u32_type, (1 << 24) | 32),
+        ];
+        let name_offset = btf.add_string("flags");
+        let mut flags = Struct::new(name_offset, members, 8);
+        flags.info |= 1 << 31;
+        let flags = btf.add_type(BtfType::Struct(flags));
+
+        // Adjacent bitfields share the bytes they span.
+        assert_eq!(
+            composite(&btf, flags),
+            (
+                "#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct Flags {
+    pub _bitfield_1: [u8; 2],
+    pub port: u16,
+    pub _bitfield_2: [u8; 1],
+    pub _pad_1: [u8; 3],
+}
+
+unsafe impl ::aya::Pod for Flags {}
+
+"
+                .to_owned(),
+                8,
+                2
+            )
+        );
+    }
+
+    #[test]
+    fn test_composite_union() {
+        // union __attribute__((aligned(8))) value { u32 raw; u16 port[2]; };
+        let mut btf = Btf::new();
+        let u16_type = int(&mut btf, "u16", 2);
+        let u32_type = int(&mut btf, "u32", 4);
+        let ports = btf.add_type(BtfType::Array(Array::new(0, u16_type, u32_type, 2)));
+        let members = vec![
+            member(&mut btf, "raw", u32_type, 0),
+            member(&mut btf, "port", ports, 0),
+        ];
+        let name_offset = btf.add_string("value");
+        let value = btf.add_type(BtfType::Union(Union::new(name_offset, 8, members, None)));
+
+        // The union is padded with a member spanning all of it, and can't derive `Debug`.
+        assert_eq!(
+            composite(&btf, value),
+            (
+                "#[repr(C)]
+#[derive(Clone, Copy)]
+pub union Value {
+    pub raw: u32,
+    pub port: [u16; 2],
+    pub _bytes: [u8; 8],
+}
+
+unsafe impl ::aya::Pod for Value {}
+
+"
+                .to_owned(),
+                8,
+                4
+            )
+        );
+    }
+
+    #[test]
+    fn test_composite_packed() {
+        // struct __attribute__((packed)) header { u8 version; u32 length; };
+        let mut btf = Btf::new();
+        let u8_type = int(&mut btf, "u8", 1);
+        let u32_type = int(&mut btf, "u32", 4);
+        let members = vec![
+            member(&mut btf, "version", u8_type, 0),
+            member(&mut btf, "length", u32_type, 8),
+        ];
+        let name_offset = btf.add_string("header");
+        let header = btf.add_type(BtfType::Struct(Struct::new(name_offset, members, 5)));
+
+        assert_eq!(
+            composite(&btf, header),
+            (
+                "#[repr(C, packed)]
+#[derive(Clone, Copy, Debug)]
+pub struct Header {
+    pub version: u8,
+    pub length: u32,
+}
+
+unsafe impl ::aya::Pod for Header {}
+
+"
+                .to_owned(),
+                5,
+                1
+            )
+        );
+    }
+
+    #[test]
+    fn test_composite_anonymous() {
+        // struct outer {
+        //     u32 id;
+        //     struct { u16 x; u16 y; };
+        //     union { u32 raw; u8 tag; };
+        // };
+        let mut btf = Btf::new();
+        let u8_type = int(&mut btf, "u8", 1);
+        let u16_type = int(&mut btf, "u16", 2);
+        let u32_type = int(&mut btf, "u32", 4);
+        let members = vec![
+            member(&mut btf, "x", u16_type, 0),
+            member(&mut btf, "y", u16_type, 16),
+        ];
+        let point = btf.add_type(BtfType::Struct(Struct::new(0, members, 4)));
+        let members = vec![
+            member(&mut btf, "raw", u32_type, 0),
+            member(&mut btf, "tag", u8_type, 0),
+        ];
+        let value = btf.add_type(BtfType::Union(Union::new(0, 4, members, None)));
+        let members = vec![
+            member(&mut btf, "id", u32_type, 0),
+            member(&mut btf, "", point, 32),
+            member(&mut btf, "", value, 64),
+        ];
+        let name_offset = btf.add_string("outer");
+        let outer = btf.add_type(BtfType::Struct(Struct::new(name_offset, members, 12)));
+
+        // Anonymous types are named after their type ID, anonymous members after their index.
+        assert_eq!(
+            composite(&btf, outer),
+            (
+                format!(
+                    "#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct Anon{point} {{
+    pub x: u16,
+    pub y: u16,
+}}
+
+unsafe impl ::aya::Pod for Anon{point} {{}}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub union Anon{value} {{
+    pub raw: u32,
+    pub tag: u8,
+}}
+
+unsafe impl ::aya::Pod for Anon{value} {{}}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Outer {{
+    pub id: u32,
+    pub anon_1: Anon{point},
+    pub anon_2: Anon{value},
+}}
+
+unsafe impl ::aya::Pod for Outer {{}}
+
+"
+                ),
+                12,
+                4
+            )
+        );
+    }
+
+    #[test]
+    fn test_composite_reserved_names() {
+        let mut btf = Btf::new();
+        let u32_type = int(&mut btf, "u32", 4);
+        let members = vec![
+            member(&mut btf, "super", u32_type, 0),
+            member(&mut btf, "type", u32_type, 32),
+        ];
+        let name_offset = btf.add_string("self");
+        let value = btf.add_type(BtfType::Struct(Struct::new(name_offset, members, 8)));
+
+        assert_eq!(
+            composite(&btf, value),
+            (
+                "#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct Self_ {
+    pub super_: u32,
+    pub r#type: u32,
+}
+
+unsafe impl ::aya::Pod for Self_ {}
+
+"
+                .to_owned(),
+                8,
+                4
+            )
+        );
+    }
+}
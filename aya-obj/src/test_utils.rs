@@ -0,0 +1,74 @@
+//! Fixtures shared by the unit tests.
+
+use std::ffi::CString;
+
+use object::{Endianness, SectionIndex};
+
+use crate::{
+    Function, Object, Program, ProgramSection,
+    generated::{BPF_CALL, BPF_EXIT, BPF_JMP, bpf_func_id, bpf_insn},
+    relocation::INS_SIZE,
+};
+
+pub(crate) fn insn(code: u32, dst: u8, src: u8, off: i16, imm: i32) -> bpf_insn {
+    bpf_insn {
+        code: code as u8,
+        _bitfield_align_1: [],
+        _bitfield_1: bpf_insn::new_bitfield_1(dst, src),
+        off,
+        imm,
+    }
+}
+
+pub(crate) fn call(helper: bpf_func_id) -> bpf_insn {
+    insn(BPF_JMP | BPF_CALL, 0, 0, 0, helper as i32)
+}
+
+pub(crate) fn exit() -> bpf_insn {
+    insn(BPF_JMP | BPF_EXIT, 0, 0, 0, 0)
+}
+
+/// Returns an object without any program, map or symbol.
+pub(crate) fn empty_object() -> Object {
+    Object::new(Endianness::default(), CString::new("GPL").unwrap(), None)
+}
+
+/// Returns an object with a single program `prog` made of `functions`, laid out one after the
+/// other in section 0. The first function is the program's entry point, the others are named
+/// `sub1`, `sub2`, and so on.
+pub(crate) fn object(section: ProgramSection, functions: &[&[bpf_insn]]) -> Object {
+    let mut object = empty_object();
+    let _unused: Option<Program> = object.programs.insert(
+        "prog".to_owned(),
+        Program {
+            license: CString::new("GPL").unwrap(),
+            kernel_version: None,
+            section,
+            section_index: 0,
+            address: 0,
+        },
+    );
+    let mut address = 0;
+    for (i, instructions) in functions.iter().enumerate() {
+        let _unused: Option<Function> = object.functions.insert(
+            (0, address),
+            Function {
+                address,
+                name: if i == 0 {
+                    "prog".to_owned()
+                } else {
+                    format!("sub{i}")
+                },
+                section_index: SectionIndex(0),
+                section_offset: address as usize,
+                instructions: instructions.to_vec(),
+                func_info: Default::default(),
+                line_info: Default::default(),
+                func_info_rec_size: 0,
+                line_info_rec_size: 0,
+            },
+        );
+        address += (instructions.len() * INS_SIZE) as u64;
+    }
+    object
+}
@@ -0,0 +1,567 @@
+//! Verifier log diagnostics.
+//!
+//! The kernel verifier log can easily span thousands of lines. [`VerifierDiagnostic`] extracts the
+//! parts that usually matter when a program is rejected: the error message, the failing
+//! instruction, the register state right before it and the corresponding source location.
+
+use std::{
+    fmt::{self, Display},
+    string::ToString as _,
+};
+
+use crate::{Function, VerifierLog, btf::Btf};
+
+/// A location in the source code of an eBPF program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLocation {
+    /// The source file, if known.
+    pub file: Option<String>,
+    /// The line number, or `0` if unknown.
+    pub line: u32,
+    /// The column number, or `0` if unknown.
+    pub column: u32,
+    /// The text of the source line, if known.
+    pub text: Option<String>,
+}
+
+impl Display for SourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self {
+            file,
+            line,
+            column,
+            text: _,
+        } = self;
+        f.write_str(file.as_deref().unwrap_or("<unknown>"))?;
+        if *line != 0 {
+            write!(f, ":{line}")?;
+            if *column != 0 {
+                write!(f, ":{column}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The state of a register or stack slot as reported by the verifier.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegisterState {
+    /// The register (`R0` to `R10`) or stack slot (e.g. `fp-8`).
+    ///
+    /// Liveness suffixes such as `_w` are stripped.
+    pub name: String,
+    /// The state of the register, e.g. `ctx()` or `scalar(umax=255)`.
+    pub state: String,
+}
+
+/// An instruction as printed by the verifier.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifierInstruction {
+    /// The index of the instruction in the program.
+    pub index: usize,
+    /// The opcode of the instruction.
+    pub opcode: u8,
+    /// The disassembled instruction, e.g. `r2 = *(u32 *)(r1 +100)`.
+    pub text: String,
+}
+
+/// A concise summary of a verifier failure, extracted from a [`VerifierLog`].
+///
+/// The [`Display`] implementation renders a short, source annotated error report.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VerifierDiagnostic {
+    /// The error message printed by the verifier.
+    pub message: String,
+    /// The instruction the verifier rejected, if any.
+    pub instruction: Option<VerifierInstruction>,
+    /// The state of registers and stack slots before the rejected instruction.
+    ///
+    /// This is the last full state printed by the verifier, updated with the changes it printed
+    /// after each instruction since. Registers come first, by number, followed by stack slots.
+    pub registers: Vec<RegisterState>,
+    /// The source location of the rejected instruction, if known.
+    pub source: Option<SourceLocation>,
+    /// The number of instructions processed by the verifier, if reported.
+    pub processed_instructions: Option<u64>,
+}
+
+impl VerifierLog {
+    /// Extracts a [`VerifierDiagnostic`] from the log.
+    pub fn diagnose(&self) -> VerifierDiagnostic {
+        let Self(log) = self;
+        VerifierDiagnostic::parse(log)
+    }
+}
+
+// Lines which are neither part of the error message nor program state.
+const NOISE_PREFIXES: &[&str] = &[
+    "-- ",
+    "frame",
+    "func#",
+    "last_idx ",
+    "mark_precise:",
+    "parent ",
+    "processed ",
+    "propagating ",
+    "regs=",
+    "stack depth ",
+    "validating ",
+    "verification time ",
+];
+
+impl VerifierDiagnostic {
+    /// Parses the output of the verifier.
+    pub fn parse(log: &str) -> Self {
+        let mut instruction = None;
+        let mut message_start = 0;
+        let mut registers = Vec::new();
+        let mut source = None;
+        let mut registers_at_instruction = Vec::new();
+        let mut source_at_instruction = None;
+        let mut processed_instructions = None;
+
+        let lines: Vec<_> = log.lines().map(str::trim_end).collect();
+        for (i, line) in lines.iter().enumerate() {
+            if let Some(rest) = line.strip_prefix("processed ") {
+                processed_instructions = rest
+                    .split_ascii_whitespace()
+                    .next()
+                    .and_then(|n| n.parse().ok());
+            } else if let Some(text) = line.strip_prefix("; ") {
+                source = Some(text);
+            } else if let Some((insn, trailing_state)) = parse_instruction(line) {
+                registers_at_instruction.clone_from(&registers);
+                source_at_instruction = source;
+                instruction = Some(insn);
+                message_start = i + 1;
+                // Newer kernels only print the registers changed by each instruction after it,
+                // and the full state at branches and at the start of the program.
+                if let Some(changed) = trailing_state {
+                    update_registers(&mut registers, parse_registers(changed));
+                }
+            } else if let Some(state) = strip_state_prefix(line) {
+                registers = parse_registers(state);
+            }
+        }
+
+        let is_message = |line: &&&str| {
+            !line.is_empty()
+                && !line.starts_with("; ")
+                && strip_state_prefix(line).is_none()
+                && !NOISE_PREFIXES.iter().any(|prefix| line.starts_with(prefix))
+        };
+        let mut message: Vec<_> = lines[message_start..]
+            .iter()
+            .filter(is_message)
+            .copied()
+            .collect();
+        if message.is_empty() {
+            message.extend(lines.iter().rev().find(is_message));
+        }
+
+        Self {
+            message: message.join("\n"),
+            instruction,
+            registers: registers_at_instruction,
+            source: source_at_instruction.map(parse_source),
+            processed_instructions,
+        }
+    }
+
+    /// Resolves the source location of the rejected instruction using the `.BTF.ext` line info of
+    /// `function`.
+    ///
+    /// `function` must be the function that was passed to the kernel, i.e. after relocation and
+    /// call linking, so that instruction indices match those reported by the verifier. The source
+    /// text found in the verifier log, if any, is kept when the line info doesn't provide one.
+    pub fn annotate(&mut self, function: &Function, btf: &Btf) {
+        let Some(VerifierInstruction { index, .. }) = self.instruction else {
+            return;
+        };
+        let Some(mut source) = source_location(function, btf, index) else {
+            return;
+        };
+        if source.text.is_none() {
+            source.text = self.source.as_mut().and_then(|source| source.text.take());
+        }
+        self.source = Some(source);
+    }
+}
+
+/// Returns the source location of the instruction at `index` in `function` according to its
+/// `.BTF.ext` line info.
+pub(crate) fn source_location(
+    function: &Function,
+    btf: &Btf,
+    index: usize,
+) -> Option<SourceLocation> {
+    let line_info = function
+        .line_info
+        .line_info
+        .iter()
+        .filter(|line_info| line_info.insn_off as usize <= index)
+        .max_by_key(|line_info| line_info.insn_off)?;
+    let string_at = |offset| {
+        btf.string_at(offset)
+            .ok()
+            .filter(|s| !s.is_empty())
+            .map(|s| s.trim().to_string())
+    };
+    Some(SourceLocation {
+        file: string_at(line_info.file_name_off),
+        line: line_info.line_col >> 10,
+        column: line_info.line_col & 0x3ff,
+        text: string_at(line_info.line_off),
+    })
+}
+
+impl Display for VerifierDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self {
+            message,
+            instruction,
+            registers,
+            source,
+            processed_instructions,
+        } = self;
+        if message.is_empty() {
+            f.write_str("verifier rejected the program")?;
+        } else {
+            f.write_str(message)?;
+        }
+        if let Some(VerifierInstruction {
+            index,
+            opcode,
+            text,
+        }) = instruction
+        {
+            write!(f, "\n  at insn {index}: ({opcode:02x}) {text}")?;
+        }
+        if let Some(source) = source {
+            write!(f, "\n  --> {source}")?;
+            if let Some(text) = &source.text {
+                write!(f, "\n   | {text}")?;
+            }
+        }
+        if !registers.is_empty() {
+            f.write_str("\n  registers:")?;
+            for RegisterState { name, state } in registers {
+                write!(f, " {name}={state}")?;
+            }
+        }
+        if let Some(processed) = processed_instructions {
+            write!(f, "\n  ({processed} instructions processed)")?;
+        }
+        Ok(())
+    }
+}
+
+// Parses a line such as `12: (61) r2 = *(u32 *)(r1 +100)` into the instruction and the register
+// state printed after it by newer kernels (`... ; R2_w=scalar()`).
+fn parse_instruction(line: &str) -> Option<(VerifierInstruction, Option<&str>)> {
+    let (index, rest) = line.split_once(": (")?;
+    let index = index.parse().ok()?;
+    let (opcode, text) = rest.split_once(") ")?;
+    if opcode.len() != 2 {
+        return None;
+    }
+    let opcode = u8::from_str_radix(opcode, 16).ok()?;
+    let (text, state) = match text.split_once(" ; ") {
+        Some((text, state)) => (text, Some(state.trim())),
+        None => (text, None),
+    };
+    Some((
+        VerifierInstruction {
+            index,
+            opcode,
+            text: text.trim().to_string(),
+        },
+        state,
+    ))
+}
+
+// Returns the register state in lines such as `0: R1=ctx() R10=fp0`,
+// `from 4 to 6: R0=map_value(...) R10=fp0` or `R1_w=ctx()`.
+fn strip_state_prefix(line: &str) -> Option<&str> {
+    let mut line = line;
+    if let Some(rest) = line.strip_prefix("from ") {
+        line = rest.split_once(": ")?.1;
+    } else if let Some((index, rest)) = line.split_once(": ") {
+        if index.bytes().all(|b| b.is_ascii_digit()) {
+            line = rest;
+        }
+    }
+    let line = line.trim_start();
+    let (register, _state) = line.split_once('=')?;
+    let register = register
+        .split_once('_')
+        .map_or(register, |(register, _liveness)| register);
+    register
+        .strip_prefix('R')
+        .filter(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+        .map(|_| line)
+}
+
+// Splits `R1=ctx() R2_w=scalar(var_off=(0x0; 0xff)) fp-8=mmmmmmmm` into registers, honouring
+// parentheses since states may contain spaces.
+fn parse_registers(state: &str) -> Vec<RegisterState> {
+    let mut registers = Vec::new();
+    let mut depth = 0usize;
+    let mut start = 0;
+    let mut push = |token: &str| {
+        if let Some((name, state)) = token.split_once('=') {
+            let name = name.split_once('_').map_or(name, |(name, _liveness)| name);
+            registers.push(RegisterState {
+                name: name.to_string(),
+                state: state.to_string(),
+            });
+        }
+    };
+    for (i, c) in state.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth = depth.saturating_sub(1),
+            ' ' if depth == 0 => {
+                push(&state[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    push(&state[start..]);
+    registers
+}
+
+// Applies the states of the `changed` registers and stack slots to `registers`, keeping the
+// registers ordered by number and followed by the stack slots.
+fn update_registers(registers: &mut Vec<RegisterState>, changed: Vec<RegisterState>) {
+    for register in changed {
+        match registers.iter_mut().find(|r| r.name == register.name) {
+            Some(r) => r.state = register.state,
+            None => registers.push(register),
+        }
+    }
+    registers.sort_by_key(|RegisterState { name, .. }| {
+        match name.strip_prefix('R').map(str::parse::<u8>) {
+            Some(Ok(n)) => (0, n),
+            Some(Err(std::num::ParseIntError { .. })) | None => (1, 0),
+        }
+    });
+}
+
+// Parses a source line annotation, e.g. `int x = foo(); @ prog.c:12`.
+fn parse_source(source: &str) -> SourceLocation {
+    let (text, location) = match source.rsplit_once(" @ ") {
+        Some((text, location)) => (text, Some(location)),
+        None => (source, None),
+    };
+    let (file, line) = match location.and_then(|location| location.rsplit_once(':')) {
+        Some((file, line)) => match line.parse() {
+            Ok(line) => (Some(file.to_string()), line),
+            Err(std::num::ParseIntError { .. }) => (location.map(ToString::to_string), 0),
+        },
+        None => (location.map(ToString::to_string), 0),
+    };
+    let text = text.trim();
+    SourceLocation {
+        file,
+        line,
+        column: 0,
+        text: (!text.is_empty()).then(|| text.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        btf::{BtfType, Func, FuncLinkage, FuncProto, LineSecInfo},
+        generated::bpf_line_info,
+    };
+
+    const LOG: &str = "\
+func#0 @0
+0: R1=ctx() R10=fp0
+; int x = *(int *)(ctx->data + 100); @ prog.c:12
+0: (61) r2 = *(u32 *)(r1 +100)
+invalid bpf_context access off=100 size=4
+processed 1 insns (limit 1000000) max_states_per_insn 0 total_states 0 peak_states 0 mark_read 0
+";
+
+    #[test]
+    fn test_parse() {
+        let diagnostic = VerifierLog::new(LOG.to_string()).diagnose();
+        assert_eq!(
+            diagnostic,
+            VerifierDiagnostic {
+                message: "invalid bpf_context access off=100 size=4".to_string(),
+                instruction: Some(VerifierInstruction {
+                    index: 0,
+                    opcode: 0x61,
+                    text: "r2 = *(u32 *)(r1 +100)".to_string(),
+                }),
+                registers: vec![
+                    RegisterState {
+                        name: "R1".to_string(),
+                        state: "ctx()".to_string(),
+                    },
+                    RegisterState {
+                        name: "R10".to_string(),
+                        state: "fp0".to_string(),
+                    },
+                ],
+                source: Some(SourceLocation {
+                    file: Some("prog.c".to_string()),
+                    line: 12,
+                    column: 0,
+                    text: Some("int x = *(int *)(ctx->data + 100);".to_string()),
+                }),
+                processed_instructions: Some(1),
+            }
+        );
+        assert_eq!(
+            diagnostic.to_string(),
+            "invalid bpf_context access off=100 size=4
+  at insn 0: (61) r2 = *(u32 *)(r1 +100)
+  --> prog.c:12
+   | int x = *(int *)(ctx->data + 100);
+  registers: R1=ctx() R10=fp0
+  (1 instructions processed)"
+        );
+    }
+
+    #[test]
+    fn test_parse_trailing_state() {
+        let log = "\
+0: R1=ctx() R10=fp0
+0: (b7) r0 = 0                        ; R0_w=0
+1: (bf) r2 = r10                      ; R2_w=fp0 R10=fp0
+2: (07) r2 += -8                      ; R2_w=fp-8 fp-8=mmmmmmmm
+3: (85) call bpf_map_lookup_elem#1
+R1 type=fp expected=map_ptr
+processed 4 insns (limit 1000000) max_states_per_insn 0 total_states 0 peak_states 0 mark_read 0
+";
+        let diagnostic = VerifierDiagnostic::parse(log);
+        assert_eq!(diagnostic.message, "R1 type=fp expected=map_ptr");
+        assert_eq!(
+            diagnostic.instruction,
+            Some(VerifierInstruction {
+                index: 3,
+                opcode: 0x85,
+                text: "call bpf_map_lookup_elem#1".to_string(),
+            })
+        );
+        assert_eq!(
+            diagnostic
+                .registers
+                .iter()
+                .map(|RegisterState { name, state }| (name.as_str(), state.as_str()))
+                .collect::<Vec<_>>(),
+            [
+                ("R0", "0"),
+                ("R1", "ctx()"),
+                ("R2", "fp-8"),
+                ("R10", "fp0"),
+                ("fp-8", "mmmmmmmm")
+            ]
+        );
+        assert_eq!(diagnostic.source, None);
+        assert_eq!(diagnostic.processed_instructions, Some(4));
+    }
+
+    #[test]
+    fn test_parse_nested_parentheses() {
+        assert_eq!(
+            parse_registers("R0_w=scalar(umax=255,var_off=(0x0; 0xff)) R10=fp0"),
+            [
+                RegisterState {
+                    name: "R0".to_string(),
+                    state: "scalar(umax=255,var_off=(0x0; 0xff))".to_string(),
+                },
+                RegisterState {
+                    name: "R10".to_string(),
+                    state: "fp0".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_without_instruction() {
+        let diagnostic = VerifierDiagnostic::parse("last insn is not an exit or jmp\n");
+        assert_eq!(diagnostic.message, "last insn is not an exit or jmp");
+        assert_eq!(diagnostic.instruction, None);
+        assert_eq!(
+            diagnostic.to_string(),
+            "last insn is not an exit or jmp".to_string()
+        );
+    }
+
+    #[test]
+    fn test_annotate() {
+        let mut btf = Btf::new();
+        let file_name_off = btf.add_string("src/main.rs");
+        let line_off = btf.add_string("let x = unsafe { *ptr };");
+        let proto_type_id = btf.add_type(BtfType::FuncProto(FuncProto::new(vec![], 0)));
+        let name_off = btf.add_string("prog");
+        btf.add_type(BtfType::Func(Func::new(
+            name_off,
+            proto_type_id,
+            FuncLinkage::Global,
+        )));
+
+        let function = Function {
+            address: 0,
+            name: "prog".to_string(),
+            section_index: object::SectionIndex(0),
+            section_offset: 0,
+            instructions: vec![],
+            func_info: Default::default(),
+            line_info: LineSecInfo {
+                _sec_name_offset: 0,
+                num_info: 2,
+                line_info: vec![
+                    bpf_line_info {
+                        insn_off: 0,
+                        file_name_off,
+                        line_off: 0,
+                        line_col: (3 << 10) | 0x1,
+                    },
+                    bpf_line_info {
+                        insn_off: 4,
+                        file_name_off,
+                        line_off,
+                        line_col: (7 << 10) | 0xd,
+                    },
+                ],
+            },
+            func_info_rec_size: 0,
+            line_info_rec_size: 0,
+        };
+
+        let mut diagnostic = VerifierDiagnostic::parse(LOG.replace("0: (61)", "5: (61)").as_str());
+        diagnostic.annotate(&function, &btf);
+        assert_eq!(
+            diagnostic.source,
+            Some(SourceLocation {
+                file: Some("src/main.rs".to_string()),
+                line: 7,
+                column: 13,
+                text: Some("let x = unsafe { *ptr };".to_string()),
+            })
+        );
+
+        // Line info without source text keeps the text found in the log.
+        let mut diagnostic = VerifierDiagnostic::parse(LOG);
+        diagnostic.annotate(&function, &btf);
+        assert_eq!(
+            diagnostic.source,
+            Some(SourceLocation {
+                file: Some("src/main.rs".to_string()),
+                line: 3,
+                column: 1,
+                text: Some("int x = *(int *)(ctx->data + 100);".to_string()),
+            })
+        );
+    }
+}
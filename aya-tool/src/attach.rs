@@ -0,0 +1,94 @@
+//! Attaching and detaching programs pinned on bpffs.
+//!
+//! Programs are attached through bpf links which are pinned next to the program, so that the
+//! attachment outlives the tool. Detaching removes the pinned link.
+
+use std::{io, path::Path};
+
+use aya::{
+    pin::PinError,
+    programs::{
+        ProgramError, SchedClassifier, TcAttachType, Xdp, XdpMode,
+        links::{FdLink, LinkError, LinkOrder, PinnedLink},
+        tc::TcAttachOptions,
+    },
+    util::KernelVersion,
+};
+use aya_obj::programs::XdpAttachType;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Program(#[from] ProgramError),
+
+    #[error(transparent)]
+    Link(#[from] LinkError),
+
+    #[error(transparent)]
+    Pin(#[from] PinError),
+
+    #[error("error removing pinned link")]
+    Unpin(#[source] io::Error),
+
+    #[error("attaching TC classifiers requires TCX, introduced in Linux 6.6")]
+    TcxUnsupported,
+}
+
+/// Attaches the XDP program pinned at `program` to `interface` and pins the link at `link`.
+///
+/// Returns the id of the new link.
+pub fn attach_xdp(
+    program: &Path,
+    interface: &str,
+    mode: XdpMode,
+    link: &Path,
+) -> Result<u32, Error> {
+    let mut prog = Xdp::from_pin(program, XdpAttachType::Interface)?;
+    let link_id = prog.attach(interface, mode)?;
+    let fd_link = FdLink::try_from(prog.take_link(link_id)?)?;
+    pin(fd_link, link)
+}
+
+/// Attaches the TC classifier pinned at `program` to `interface` using TCX and pins the link at
+/// `link`.
+///
+/// Netlink attachments aren't links and can't be pinned, so there's no fallback for kernels
+/// older than 6.6: [`Error::TcxUnsupported`] is returned instead.
+///
+/// Returns the id of the new link.
+pub fn attach_tc(
+    program: &Path,
+    interface: &str,
+    attach_type: TcAttachType,
+    link: &Path,
+) -> Result<u32, Error> {
+    if KernelVersion::current().is_ok_and(|version| version < KernelVersion::new(6, 6, 0)) {
+        return Err(Error::TcxUnsupported);
+    }
+    let mut prog = SchedClassifier::from_pin(program)?;
+    let link_id = prog.attach_with_options(
+        interface,
+        attach_type,
+        TcAttachOptions::TcxOrder(LinkOrder::default()),
+    )?;
+    let fd_link = FdLink::try_from(prog.take_link(link_id)?)?;
+    pin(fd_link, link)
+}
+
+/// Detaches the program attached through the link pinned at `link`.
+///
+/// Returns the id of the removed link.
+pub fn detach(link: &Path) -> Result<u32, Error> {
+    let fd_link = PinnedLink::from_pin(link)?.unpin().map_err(Error::Unpin)?;
+    let id = fd_link.info()?.id();
+    // Dropping the last reference to the link detaches the program.
+    drop(fd_link);
+    Ok(id)
+}
+
+fn pin(fd_link: FdLink, path: &Path) -> Result<u32, Error> {
+    let id = fd_link.info()?.id();
+    let _pinned: PinnedLink = fd_link.pin(path)?;
+    Ok(id)
+}
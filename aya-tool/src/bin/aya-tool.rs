@@ -1,50 +1,435 @@
 #![expect(unused_crate_dependencies, reason = "used in lib")]
 
-use std::path::PathBuf;
+use std::{
+    fs,
+    io::{self, Write},
+    path::PathBuf,
+};
 
-use aya_tool::generate::{InputFile, generate};
-use clap::Parser;
+use aya::{
+    maps::MapData,
+    programs::{TcAttachType, XdpMode},
+};
+use aya_obj::lint::{CpuVersion, LintOptions};
+use aya_tool::{
+    attach::{attach_tc, attach_xdp, detach},
+    generate::{InputFile, generate},
+    inspect::{self, LinkSummary, MapDump, MapSummary, PinnedObject, ProgramSummary},
+    lint::{Diagnostic, lint},
+};
+use clap::{Parser, ValueEnum};
+use serde::Serialize;
 
 #[derive(Parser)]
 pub struct Options {
+    /// Print machine readable JSON instead of text
+    #[clap(long, global = true, action)]
+    json: bool,
     #[clap(subcommand)]
     command: Command,
 }
 
 #[derive(Parser)]
 enum Command {
-    /// Generate Rust bindings to Kernel types using bpftool
+    /// Generate Rust bindings to Kernel types
     #[clap(name = "generate", action)]
     Generate {
         #[clap(long, default_value = "/sys/kernel/btf/vmlinux", action)]
         btf: PathBuf,
         #[clap(long, conflicts_with = "btf", action)]
         header: Option<PathBuf>,
+        /// Convert the BTF to C with bpftool instead of natively
+        #[clap(long, conflicts_with = "header", action)]
+        bpftool: bool,
         #[clap(action)]
         names: Vec<String>,
         #[clap(last = true, action)]
         bindgen_args: Vec<String>,
     },
+    /// Generate a typed Rust skeleton for an eBPF object
+    #[clap(name = "skeleton", action)]
+    Skeleton {
+        /// The path of the eBPF object
+        #[clap(long, action)]
+        object: PathBuf,
+        /// The name of the generated struct
+        #[clap(long, action)]
+        name: String,
+    },
+    /// Check an eBPF object for problems the verifier would reject, without loading it
+    #[clap(name = "lint", action)]
+    Lint {
+        /// The path of the eBPF object
+        #[clap(long, action)]
+        object: PathBuf,
+        /// The BPF instruction set version the programs are loaded with
+        #[clap(long, value_enum, default_value_t = Cpu::V3, action)]
+        cpu: Cpu,
+        /// Report all loops, for kernels older than 5.3 without bounded loop support
+        #[clap(long, action)]
+        no_bounded_loops: bool,
+    },
+    /// Inspect loaded programs
+    #[clap(name = "prog", subcommand)]
+    Prog(ProgCommand),
+    /// Inspect loaded maps
+    #[clap(name = "map", subcommand)]
+    Map(MapCommand),
+    /// Inspect loaded links
+    #[clap(name = "link", subcommand)]
+    Link(LinkCommand),
+    /// List the programs, maps and links pinned on bpffs
+    #[clap(name = "pinned", action)]
+    Pinned {
+        #[clap(long, default_value = "/sys/fs/bpf", action)]
+        bpffs: PathBuf,
+    },
+    /// Attach a pinned program and pin the resulting link
+    #[clap(name = "attach", subcommand)]
+    Attach(AttachCommand),
+    /// Detach a program by removing its pinned link
+    #[clap(name = "detach", action)]
+    Detach {
+        /// The path of the pinned link
+        #[clap(long, action)]
+        link: PathBuf,
+    },
 }
 
-fn main() -> Result<(), anyhow::Error> {
-    use std::io::Write as _;
+#[derive(Parser)]
+enum ProgCommand {
+    /// List all programs loaded in the kernel
+    #[clap(name = "list", action)]
+    List,
+}
+
+#[derive(Parser)]
+enum MapCommand {
+    /// List all maps loaded in the kernel
+    #[clap(name = "list", action)]
+    List,
+    /// Dump the contents of a map
+    #[clap(name = "dump", action)]
+    Dump {
+        /// The id of the map
+        #[clap(long, conflicts_with = "pin", required_unless_present = "pin", action)]
+        id: Option<u32>,
+        /// The path of the pinned map
+        #[clap(long, action)]
+        pin: Option<PathBuf>,
+    },
+}
+
+#[derive(Parser)]
+enum LinkCommand {
+    /// List all links loaded in the kernel
+    #[clap(name = "list", action)]
+    List,
+}
+
+#[derive(Parser)]
+enum AttachCommand {
+    /// Attach a pinned XDP program to a network interface
+    #[clap(name = "xdp", action)]
+    Xdp {
+        /// The path of the pinned program
+        #[clap(long, action)]
+        program: PathBuf,
+        /// The network interface to attach to
+        #[clap(long, action)]
+        iface: String,
+        #[clap(long, value_enum, default_value_t = Mode::Default, action)]
+        mode: Mode,
+        /// The path to pin the resulting link at
+        #[clap(long, action)]
+        link: PathBuf,
+    },
+    /// Attach a pinned TC classifier to a network interface
+    ///
+    /// The program is attached with TCX, which requires Linux 6.6 or later. Attachments made
+    /// through netlink on older kernels can't be pinned and aren't supported.
+    #[clap(name = "tc", action)]
+    Tc {
+        /// The path of the pinned program
+        #[clap(long, action)]
+        program: PathBuf,
+        /// The network interface to attach to
+        #[clap(long, action)]
+        iface: String,
+        #[clap(long, value_enum, default_value_t = Direction::Ingress, action)]
+        direction: Direction,
+        /// The path to pin the resulting link at
+        #[clap(long, action)]
+        link: PathBuf,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Mode {
+    Default,
+    Skb,
+    Driver,
+    Hardware,
+}
+
+impl From<Mode> for XdpMode {
+    fn from(mode: Mode) -> Self {
+        match mode {
+            Mode::Default => Self::Default,
+            Mode::Skb => Self::Skb,
+            Mode::Driver => Self::Driver,
+            Mode::Hardware => Self::Hardware,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Direction {
+    Ingress,
+    Egress,
+}
+
+impl From<Direction> for TcAttachType {
+    fn from(direction: Direction) -> Self {
+        match direction {
+            Direction::Ingress => Self::Ingress,
+            Direction::Egress => Self::Egress,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Cpu {
+    V1,
+    V2,
+    V3,
+    V4,
+}
+
+impl From<Cpu> for CpuVersion {
+    fn from(cpu: Cpu) -> Self {
+        match cpu {
+            Cpu::V1 => Self::V1,
+            Cpu::V2 => Self::V2,
+            Cpu::V3 => Self::V3,
+            Cpu::V4 => Self::V4,
+        }
+    }
+}
+
+/// A link created or removed by `attach` or `detach`.
+#[derive(Serialize)]
+struct LinkId {
+    id: u32,
+}
+
+trait Render {
+    fn render(&self, w: &mut dyn Write) -> io::Result<()>;
+}
+
+impl Render for ProgramSummary {
+    fn render(&self, w: &mut dyn Write) -> io::Result<()> {
+        let Self {
+            id,
+            program_type,
+            name,
+            tag,
+            loaded_at,
+            map_ids,
+            btf_id,
+            size_translated,
+            size_jitted,
+        } = self;
+        write!(w, "{id}: {program_type}")?;
+        if let Some(name) = name {
+            write!(w, "  name {name}")?;
+        }
+        writeln!(w, "  tag {tag}")?;
+        if let Some(loaded_at) = loaded_at {
+            write!(w, "\tloaded_at {loaded_at}")?;
+        }
+        if let Some(size_translated) = size_translated {
+            write!(w, "  xlated {size_translated}B")?;
+        }
+        write!(w, "  jited {size_jitted}B")?;
+        if !map_ids.is_empty() {
+            let map_ids = map_ids.iter().map(u32::to_string).collect::<Vec<_>>();
+            write!(w, "  map_ids {}", map_ids.join(","))?;
+        }
+        if let Some(btf_id) = btf_id {
+            write!(w, "  btf_id {btf_id}")?;
+        }
+        writeln!(w)
+    }
+}
 
-    let Options { command } = Parser::parse();
-    let bindings = match command {
+impl Render for MapSummary {
+    fn render(&self, w: &mut dyn Write) -> io::Result<()> {
+        let Self {
+            id,
+            map_type,
+            name,
+            key_size,
+            value_size,
+            max_entries,
+            flags,
+            btf_id,
+        } = self;
+        write!(w, "{id}: {map_type}")?;
+        if let Some(name) = name {
+            write!(w, "  name {name}")?;
+        }
+        writeln!(w, "  flags {flags:#x}")?;
+        write!(
+            w,
+            "\tkey {key_size}B  value {value_size}B  max_entries {max_entries}"
+        )?;
+        if let Some(btf_id) = btf_id {
+            write!(w, "  btf_id {btf_id}")?;
+        }
+        writeln!(w)
+    }
+}
+
+impl Render for LinkSummary {
+    fn render(&self, w: &mut dyn Write) -> io::Result<()> {
+        let Self {
+            id,
+            link_type,
+            program_id,
+        } = self;
+        writeln!(w, "{id}: {link_type}  prog {program_id}")
+    }
+}
+
+impl Render for PinnedObject {
+    fn render(&self, w: &mut dyn Write) -> io::Result<()> {
+        let Self { path, kind, id } = self;
+        writeln!(w, "{}  {kind} {id}", path.display())
+    }
+}
+
+impl Render for MapDump {
+    fn render(&self, w: &mut dyn Write) -> io::Result<()> {
+        let Self {
+            map,
+            key_type,
+            value_type,
+            entries,
+        } = self;
+        map.render(w)?;
+        if let (Some(key_type), Some(value_type)) = (key_type, value_type) {
+            writeln!(w, "\tkey type {key_type}  value type {value_type}")?;
+        }
+        for entry in entries {
+            writeln!(w, "key: {}  value: {}", entry.key, entry.value)?;
+        }
+        writeln!(w, "Found {} elements", entries.len())
+    }
+}
+
+impl Render for Diagnostic {
+    fn render(&self, w: &mut dyn Write) -> io::Result<()> {
+        writeln!(w, "{}", self.rendered)
+    }
+}
+
+impl Render for LinkId {
+    fn render(&self, w: &mut dyn Write) -> io::Result<()> {
+        let Self { id } = self;
+        writeln!(w, "link {id}")
+    }
+}
+
+impl<T: Render> Render for Vec<T> {
+    fn render(&self, w: &mut dyn Write) -> io::Result<()> {
+        self.iter().try_for_each(|item| item.render(w))
+    }
+}
+
+fn print<T: Render + Serialize>(json: bool, value: &T) -> Result<(), anyhow::Error> {
+    let mut stdout = io::stdout().lock();
+    if json {
+        serde_json::to_writer_pretty(&mut stdout, value)?;
+        writeln!(stdout)?;
+    } else {
+        value.render(&mut stdout)?;
+    }
+    Ok(())
+}
+
+fn main() -> Result<(), anyhow::Error> {
+    let Options { json, command } = Parser::parse();
+    match command {
         Command::Generate {
             btf,
             header,
+            bpftool,
             names,
             bindgen_args,
         } => {
-            if let Some(header) = header {
-                generate(InputFile::Header(header), &names, &bindgen_args)
-            } else {
-                generate(InputFile::Btf(btf), &names, &bindgen_args)
+            let input_file = match header {
+                Some(header) => InputFile::Header(header),
+                None if bpftool => InputFile::BpfToolBtf(btf),
+                None => InputFile::Btf(btf),
+            };
+            let bindings = generate(input_file, &names, &bindgen_args)?;
+            io::stdout().write_all(bindings.as_bytes())?;
+        }
+        Command::Skeleton { object, name } => {
+            let data = fs::read(&object)?;
+            let object = aya_obj::Object::parse(&data)?;
+            let skeleton = aya_obj::skeleton::generate(&object, &name)?;
+            io::stdout().write_all(skeleton.as_bytes())?;
+        }
+        Command::Lint {
+            object,
+            cpu,
+            no_bounded_loops,
+        } => {
+            let mut options = LintOptions::default();
+            options.cpu = cpu.into();
+            options.bounded_loops = !no_bounded_loops;
+            let diagnostics = lint(&object, &options)?;
+            print(json, &diagnostics)?;
+            if !diagnostics.is_empty() {
+                anyhow::bail!("{} problems found", diagnostics.len());
             }
         }
-    }?;
-    std::io::stdout().write_all(bindings.as_bytes())?;
+        Command::Prog(ProgCommand::List) => print(json, &inspect::programs()?)?,
+        Command::Map(MapCommand::List) => print(json, &inspect::maps()?)?,
+        Command::Map(MapCommand::Dump { id, pin }) => {
+            let map = match (id, pin) {
+                (Some(id), _) => MapData::from_id(id)?,
+                (None, Some(pin)) => MapData::from_pin(pin)?,
+                (None, None) => anyhow::bail!("either --id or --pin is required"),
+            };
+            print(json, &inspect::dump_map(&map)?)?;
+        }
+        Command::Link(LinkCommand::List) => print(json, &inspect::links()?)?,
+        Command::Pinned { bpffs } => print(json, &inspect::pinned(&bpffs)?)?,
+        Command::Attach(AttachCommand::Xdp {
+            program,
+            iface,
+            mode,
+            link,
+        }) => {
+            let id = attach_xdp(&program, &iface, mode.into(), &link)?;
+            print(json, &LinkId { id })?;
+        }
+        Command::Attach(AttachCommand::Tc {
+            program,
+            iface,
+            direction,
+            link,
+        }) => {
+            let id = attach_tc(&program, &iface, direction.into(), &link)?;
+            print(json, &LinkId { id })?;
+        }
+        Command::Detach { link } => {
+            let id = detach(&link)?;
+            print(json, &LinkId { id })?;
+        }
+    }
     Ok(())
 }
@@ -6,11 +6,16 @@ use std::{
     str,
 };
 
+use aya::Endianness;
+use aya_obj::btf::{Btf, BtfError};
 use tempfile::tempdir;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum Error {
+    #[error("error converting BTF to C")]
+    Btf(#[from] BtfError),
+
     #[error("error executing bpftool")]
     BpfTool(#[source] io::Error),
 
@@ -29,6 +34,8 @@ pub enum Error {
 
 pub enum InputFile {
     Btf(PathBuf),
+    /// BTF converted to C by `bpftool` rather than natively.
+    BpfToolBtf(PathBuf),
     Header(PathBuf),
 }
 
@@ -55,6 +62,7 @@ pub fn generate<T: AsRef<str>>(
 
     let (c_header, name) = match &input_file {
         InputFile::Btf(path) => (c_header_from_btf(path)?, "kernel_types.h"),
+        InputFile::BpfToolBtf(path) => (c_header_from_bpftool(path)?, "kernel_types.h"),
         InputFile::Header(header) => (
             fs::read_to_string(header).map_err(Error::ReadHeaderFile)?,
             header.file_name().unwrap().to_str().unwrap(),
@@ -84,6 +92,11 @@ pub fn generate<T: AsRef<str>>(
 }
 
 fn c_header_from_btf(path: &Path) -> Result<String, Error> {
+    let btf = Btf::parse_file(path, Endianness::default())?;
+    Ok(btf.to_c_header()?)
+}
+
+fn c_header_from_bpftool(path: &Path) -> Result<String, Error> {
     let output = Command::new("bpftool")
         .args(["btf", "dump", "file"])
         .arg(path)
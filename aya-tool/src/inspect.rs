@@ -0,0 +1,358 @@
+//! Inspection of the eBPF programs, maps and links loaded in the kernel.
+
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use aya::{
+    maps::{MapData, MapEntry, MapError, MapInfo, loaded_maps},
+    programs::{
+        ProgramError, ProgramInfo,
+        links::{LinkError, LinkInfo},
+        loaded_links, loaded_programs,
+    },
+};
+use aya_obj::generated::{bpf_link_type, bpf_map_type};
+use serde::Serialize;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Program(#[from] ProgramError),
+
+    #[error(transparent)]
+    Map(#[from] MapError),
+
+    #[error(transparent)]
+    Link(#[from] LinkError),
+
+    #[error("error reading `{path}`")]
+    Read {
+        path: PathBuf,
+        #[source]
+        error: io::Error,
+    },
+}
+
+/// A program loaded in the kernel.
+#[derive(Debug, Serialize)]
+pub struct ProgramSummary {
+    pub id: u32,
+    pub program_type: String,
+    pub name: Option<String>,
+    pub tag: String,
+    /// Load time in seconds since the Unix epoch.
+    pub loaded_at: Option<u64>,
+    pub map_ids: Vec<u32>,
+    pub btf_id: Option<u32>,
+    pub size_translated: Option<u32>,
+    pub size_jitted: u32,
+}
+
+impl ProgramSummary {
+    pub fn new(info: &ProgramInfo) -> Result<Self, Error> {
+        Ok(Self {
+            id: info.id(),
+            program_type: type_name(info.program_type(), "BPF_PROG_TYPE_"),
+            name: info.name_as_str().map(ToOwned::to_owned),
+            tag: format!("{:016x}", info.tag()),
+            loaded_at: info
+                .loaded_at()
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map(|time| time.as_secs()),
+            map_ids: info.map_ids()?.unwrap_or_default(),
+            btf_id: info.btf_id(),
+            size_translated: info.size_translated(),
+            size_jitted: info.size_jitted(),
+        })
+    }
+}
+
+/// A map loaded in the kernel.
+#[derive(Debug, Serialize)]
+pub struct MapSummary {
+    pub id: u32,
+    pub map_type: String,
+    pub name: Option<String>,
+    pub key_size: u32,
+    pub value_size: u32,
+    pub max_entries: u32,
+    pub flags: u32,
+    pub btf_id: Option<u32>,
+}
+
+impl MapSummary {
+    pub fn new(info: &MapInfo) -> Self {
+        Self {
+            id: info.id(),
+            map_type: info
+                .map_type()
+                .ok()
+                .and_then(|map_type| bpf_map_type::try_from(map_type as u32).ok())
+                .map_or_else(
+                    || "unknown".to_owned(),
+                    |map_type| type_name(map_type, "BPF_MAP_TYPE_"),
+                ),
+            name: info.name_as_str().map(ToOwned::to_owned),
+            key_size: info.key_size(),
+            value_size: info.value_size(),
+            max_entries: info.max_entries(),
+            flags: info.map_flags(),
+            btf_id: info.btf_id(),
+        }
+    }
+}
+
+/// A link loaded in the kernel.
+#[derive(Debug, Serialize)]
+pub struct LinkSummary {
+    pub id: u32,
+    pub link_type: String,
+    pub program_id: u32,
+}
+
+impl LinkSummary {
+    pub fn new(info: &LinkInfo) -> Self {
+        Self {
+            id: info.id(),
+            link_type: info
+                .link_type()
+                .ok()
+                .and_then(|link_type| bpf_link_type::try_from(link_type as u32).ok())
+                .map_or_else(
+                    || "unknown".to_owned(),
+                    |link_type| type_name(link_type, "BPF_LINK_TYPE_"),
+                ),
+            program_id: info.program_id(),
+        }
+    }
+}
+
+/// The contents of a map.
+#[derive(Debug, Serialize)]
+pub struct MapDump {
+    pub map: MapSummary,
+    /// The C spelling of the key type, if the map has BTF.
+    pub key_type: Option<String>,
+    /// The C spelling of the value type, if the map has BTF.
+    pub value_type: Option<String>,
+    pub entries: Vec<MapEntry>,
+}
+
+/// The kind of object pinned on bpffs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PinnedKind {
+    Program,
+    Map,
+    Link,
+}
+
+impl fmt::Display for PinnedKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Program => "prog",
+            Self::Map => "map",
+            Self::Link => "link",
+        })
+    }
+}
+
+/// An object pinned on bpffs.
+#[derive(Debug, Serialize)]
+pub struct PinnedObject {
+    pub path: PathBuf,
+    pub kind: PinnedKind,
+    pub id: u32,
+}
+
+pub fn programs() -> Result<Vec<ProgramSummary>, Error> {
+    loaded_programs()
+        .map(|info| ProgramSummary::new(&info?))
+        .collect()
+}
+
+pub fn maps() -> Result<Vec<MapSummary>, Error> {
+    loaded_maps()
+        .map(|info| Ok(MapSummary::new(&info?)))
+        .collect()
+}
+
+pub fn links() -> Result<Vec<LinkSummary>, Error> {
+    loaded_links()
+        .map(|info| Ok(LinkSummary::new(&info?)))
+        .collect()
+}
+
+/// Reads every entry of `map`, decoding keys and values using the map's BTF.
+pub fn dump_map(map: &MapData) -> Result<MapDump, Error> {
+    let info = map.info()?;
+    let btf = info.btf()?;
+    let type_name = |type_id: Option<u32>| {
+        let btf = btf.as_ref()?;
+        btf.type_c_name(type_id?).ok()
+    };
+    Ok(MapDump {
+        map: MapSummary::new(&info),
+        key_type: type_name(info.btf_key_type_id()),
+        value_type: type_name(info.btf_value_type_id()),
+        entries: map.dump()?.collect::<Result<_, _>>()?,
+    })
+}
+
+/// Recursively lists the programs, maps and links pinned under `root`.
+pub fn pinned(root: &Path) -> Result<Vec<PinnedObject>, Error> {
+    let mut objects = Vec::new();
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let read_error = |error| Error::Read {
+            path: dir.clone(),
+            error,
+        };
+        let mut entries = fs::read_dir(&dir)
+            .map_err(read_error)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(read_error)?;
+        entries.sort();
+        for path in entries {
+            if path.is_dir() {
+                dirs.push(path);
+            } else if let Some(object) = pinned_object(&path) {
+                objects.push(object);
+            }
+        }
+    }
+    Ok(objects)
+}
+
+/// Identifies the object pinned at `path`, returning `None` if it isn't a BPF object.
+fn pinned_object(path: &Path) -> Option<PinnedObject> {
+    // Pins can only be opened as the kind of object they refer to, so each kind is tried in turn.
+    let (kind, id) = if let Ok(info) = MapInfo::from_pin(path) {
+        (PinnedKind::Map, info.id())
+    } else if let Ok(info) = ProgramInfo::from_pin(path) {
+        (PinnedKind::Program, info.id())
+    } else if let Ok(info) = LinkInfo::from_pin(path) {
+        (PinnedKind::Link, info.id())
+    } else {
+        return None;
+    };
+    Some(PinnedObject {
+        path: path.to_path_buf(),
+        kind,
+        id,
+    })
+}
+
+/// Converts the kernel name of a type, such as `BPF_MAP_TYPE_PERCPU_HASH`, to the name bpftool
+/// uses, such as `percpu_hash`.
+fn type_name<T: fmt::Debug>(value: T, prefix: &str) -> String {
+    let name = format!("{value:?}");
+    name.strip_prefix(prefix).unwrap_or(&name).to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_matches::assert_matches;
+    use aya::{fake::FakeKernel, maps::MapType};
+    use aya_obj::generated::bpf_prog_type;
+    use serde_json::json;
+
+    use super::*;
+
+    fn array(name: &str) -> MapData {
+        let obj =
+            aya_obj::Map::new_from_params(bpf_map_type::BPF_MAP_TYPE_ARRAY as u32, 4, 8, 2, 0);
+        MapData::create(obj, name, None).unwrap()
+    }
+
+    #[test]
+    fn test_type_name() {
+        assert_eq!(
+            type_name(bpf_prog_type::BPF_PROG_TYPE_SOCKET_FILTER, "BPF_PROG_TYPE_"),
+            "socket_filter"
+        );
+        assert_eq!(
+            type_name(bpf_map_type::BPF_MAP_TYPE_LRU_PERCPU_HASH, "BPF_MAP_TYPE_"),
+            "lru_percpu_hash"
+        );
+        assert_eq!(
+            type_name(bpf_link_type::BPF_LINK_TYPE_UPROBE_MULTI, "BPF_LINK_TYPE_"),
+            "uprobe_multi"
+        );
+        // The aya map types have the same values as the kernel ones.
+        assert_matches!(
+            bpf_map_type::try_from(MapType::PerCpuArray as u32).ok(),
+            Some(bpf_map_type::BPF_MAP_TYPE_PERCPU_ARRAY)
+        );
+    }
+
+    #[test]
+    fn test_pinned() {
+        let kernel = FakeKernel::new();
+        let _guard = kernel.install();
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path();
+
+        // Fake pins aren't visible in the filesystem, so the walk finds them through the files
+        // created at the same paths.
+        let pin = |path: &Path| {
+            fs::write(path, "").unwrap();
+            array("pinned").pin(path).unwrap();
+            MapInfo::from_pin(path).unwrap().id()
+        };
+        fs::create_dir(root.join("dir")).unwrap();
+        let first = pin(&root.join("b"));
+        let second = pin(&root.join("dir/a"));
+        fs::write(root.join("a"), "not a pin").unwrap();
+
+        let objects = pinned(root).unwrap();
+        assert_eq!(
+            serde_json::to_value(&objects).unwrap(),
+            json!([
+                {"path": root.join("b"), "kind": "map", "id": first},
+                {"path": root.join("dir/a"), "kind": "map", "id": second},
+            ])
+        );
+        assert_eq!(
+            objects
+                .iter()
+                .map(|object| object.kind.to_string())
+                .collect::<Vec<_>>(),
+            ["map", "map"]
+        );
+
+        assert_matches!(
+            pinned(&root.join("missing")),
+            Err(Error::Read { path, .. }) if path == root.join("missing")
+        );
+    }
+
+    #[test]
+    fn test_map_json() {
+        let kernel = FakeKernel::new();
+        let _guard = kernel.install();
+
+        let dump = dump_map(&array("counts")).unwrap();
+        assert_eq!(
+            serde_json::to_value(&dump.map).unwrap(),
+            json!({
+                "id": dump.map.id,
+                "map_type": "array",
+                "name": "counts",
+                "key_size": 4,
+                "value_size": 8,
+                "max_entries": 2,
+                "flags": 0,
+                "btf_id": null,
+            })
+        );
+        assert_eq!(dump.key_type, None);
+        assert_eq!(dump.value_type, None);
+        assert_eq!(dump.entries.len(), 2);
+    }
+}
@@ -1,4 +1,7 @@
 #![expect(unused_crate_dependencies, reason = "used in bin")]
 
+pub mod attach;
 pub mod bindgen;
 pub mod generate;
+pub mod inspect;
+pub mod lint;
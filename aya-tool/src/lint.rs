@@ -0,0 +1,191 @@
+//! Static linting of eBPF objects, see [`aya_obj::Object::lint`].
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use aya_obj::{
+    Object, ParseError,
+    lint::{LintDiagnostic, LintOptions},
+    relocation::EbpfRelocationError,
+    verifier::SourceLocation,
+};
+use serde::Serialize;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("error reading `{path}`")]
+    Read {
+        path: PathBuf,
+        #[source]
+        error: io::Error,
+    },
+
+    #[error(transparent)]
+    Parse(#[from] ParseError),
+
+    #[error(transparent)]
+    Relocation(#[from] EbpfRelocationError),
+}
+
+/// A problem found in a program.
+#[derive(Debug, Serialize)]
+pub struct Diagnostic {
+    pub program: String,
+    pub function: Option<String>,
+    pub instruction: usize,
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    pub column: Option<u32>,
+    pub message: String,
+    /// The diagnostic as printed by the tool, including the source line if known.
+    #[serde(skip)]
+    pub rendered: String,
+}
+
+impl Diagnostic {
+    pub fn new(diagnostic: &LintDiagnostic) -> Self {
+        let LintDiagnostic {
+            program,
+            function,
+            instruction,
+            source,
+            kind,
+        } = diagnostic;
+        let (file, line, column) = match source {
+            Some(SourceLocation {
+                file,
+                line,
+                column,
+                text: _,
+            }) => (file.clone(), Some(*line), Some(*column)),
+            None => (None, None, None),
+        };
+        Self {
+            program: program.clone(),
+            function: function.clone(),
+            instruction: *instruction,
+            file,
+            line,
+            column,
+            message: kind.to_string(),
+            rendered: diagnostic.to_string(),
+        }
+    }
+}
+
+/// Lints the programs of the object at `path`.
+pub fn lint(path: &Path, options: &LintOptions) -> Result<Vec<Diagnostic>, Error> {
+    let data = fs::read(path).map_err(|error| Error::Read {
+        path: path.to_owned(),
+        error,
+    })?;
+    let object = Object::parse(&data)?;
+    Ok(object.lint(options)?.iter().map(Diagnostic::new).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_matches::assert_matches;
+    use aya_obj::lint::LintKind;
+    use object::{
+        Architecture, BinaryFormat, Endianness, SectionKind, SymbolFlags, SymbolKind, SymbolScope,
+        write::{Object as ElfObject, Symbol, SymbolSection},
+    };
+    use serde_json::json;
+
+    use super::*;
+
+    /// Returns an object with a socket filter `prog` that loops forever.
+    fn looping_object() -> Vec<u8> {
+        let mut obj = ElfObject::new(BinaryFormat::Elf, Architecture::Bpf, Endianness::Little);
+        let license = obj.add_section(Vec::new(), b"license".to_vec(), SectionKind::Data);
+        obj.append_section_data(license, b"GPL\0", 1);
+        let text = obj.add_section(Vec::new(), b"socket".to_vec(), SectionKind::Text);
+        let instructions = [
+            // r1 += 1
+            [0x07, 0x01, 0, 0, 1, 0, 0, 0],
+            // goto -2
+            [0x05, 0, 0xfe, 0xff, 0, 0, 0, 0],
+            // exit
+            [0x95, 0, 0, 0, 0, 0, 0, 0],
+        ];
+        let offset = obj.append_section_data(text, instructions.as_flattened(), 8);
+        let _symbol = obj.add_symbol(Symbol {
+            name: b"prog".to_vec(),
+            value: offset,
+            size: size_of_val(&instructions) as u64,
+            kind: SymbolKind::Text,
+            scope: SymbolScope::Dynamic,
+            weak: false,
+            section: SymbolSection::Section(text),
+            flags: SymbolFlags::None,
+        });
+        obj.write().unwrap()
+    }
+
+    #[test]
+    fn test_lint() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("prog.o");
+        fs::write(&path, looping_object()).unwrap();
+
+        let diagnostics = lint(&path, &LintOptions::default()).unwrap();
+        let [diagnostic] = diagnostics.as_slice() else {
+            panic!("unexpected diagnostics: {diagnostics:?}");
+        };
+        assert_eq!(diagnostic.rendered, "prog: insn 1: infinite loop");
+        assert_eq!(
+            serde_json::to_value(diagnostic).unwrap(),
+            json!({
+                "program": "prog",
+                "function": null,
+                "instruction": 1,
+                "file": null,
+                "line": null,
+                "column": null,
+                "message": "infinite loop",
+            })
+        );
+
+        assert_matches!(
+            lint(&dir.path().join("missing.o"), &LintOptions::default()),
+            Err(Error::Read { path, error }) if path == dir.path().join("missing.o")
+                && error.kind() == io::ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn test_diagnostic_source() {
+        let diagnostic = Diagnostic::new(&LintDiagnostic {
+            program: "prog".to_string(),
+            function: Some("helper".to_string()),
+            instruction: 3,
+            source: Some(SourceLocation {
+                file: Some("src/main.rs".to_string()),
+                line: 12,
+                column: 5,
+                text: Some("loop {}".to_string()),
+            }),
+            kind: LintKind::InfiniteLoop,
+        });
+        assert_eq!(
+            serde_json::to_value(&diagnostic).unwrap(),
+            json!({
+                "program": "prog",
+                "function": "helper",
+                "instruction": 3,
+                "file": "src/main.rs",
+                "line": 12,
+                "column": 5,
+                "message": "infinite loop",
+            })
+        );
+        assert_eq!(
+            diagnostic.rendered,
+            "prog (helper): insn 3: infinite loop\n  --> src/main.rs:12:5\n   | loop {}"
+        );
+    }
+}
@@ -17,7 +17,7 @@ use log::{debug, warn};
 use thiserror::Error;
 
 use crate::{
-    maps::{Map, MapData, MapError},
+    maps::{Map, MapData, MapError, MapMigration},
     programs::{
         BtfTracePoint, CgroupDevice, CgroupSkb, CgroupSock, CgroupSockAddr, CgroupSockopt,
         CgroupSysctl, Extension, FEntry, FExit, FlowDissector, Iter, KProbe, LircMode2, Lsm,
@@ -33,16 +33,27 @@ use crate::{
         is_probe_read_kernel_supported, is_prog_id_supported, is_prog_name_supported,
         retry_with_verifier_logs,
     },
-    util::{bytes_of, bytes_of_slice, nr_cpus, page_size},
+    util::{bytes_of, bytes_of_slice, ifindex_from_ifname, nr_cpus, page_size},
 };
 
 /// Marker trait for types that can safely be converted to and from byte slices.
 ///
+/// Prefer [`#[derive(Pod)]`](macro@crate::Pod), which also rejects structs with implicit
+/// padding. The check runs when the struct is the key or value of a typed map, such as
+/// [`HashMap`](crate::maps::HashMap), created from a [`Map`](crate::maps::Map) or with `create`.
+/// Other APIs, like [`EbpfLoader::override_global`], don't check it.
+///
 /// # Safety
 ///
 /// This trait is unsafe because it allows for the conversion of types to and
 /// from byte slices.
-pub unsafe trait Pod: Copy + 'static {}
+pub unsafe trait Pod: Copy + 'static {
+    /// Fails to evaluate if the type has implicit padding, set by `#[derive(Pod)]`.
+    ///
+    /// Evaluated by the typed maps for their key and value types.
+    #[doc(hidden)]
+    const ASSERT_NO_PADDING: () = ();
+}
 
 macro_rules! unsafe_impl_pod {
     ($($struct_name:ident),+ $(,)?) => {
@@ -122,6 +133,14 @@ pub struct EbpfLoader<'a> {
     // Map pin path overrides the pin path of the map that matches the provided name before
     // it is created.
     map_pin_path_by_name: HashMap<&'a str, Cow<'a, Path>>,
+    // Migration policies of the pinned maps that match the provided names.
+    map_migration_by_name: HashMap<&'a str, MapMigration<'a>>,
+    // Maps shared with the maps that match the provided names instead of being created.
+    reused_maps: HashMap<&'a str, &'a MapData>,
+    // Maps of a previous instance reused when compatible.
+    exported_maps: Option<&'a ExportedMaps>,
+    // Offloads the maps that match the provided names to the provided network interfaces.
+    map_offload_by_name: HashMap<&'a str, &'a str>,
 
     extensions: HashSet<&'a str>,
     verifier_log_level: VerifierLogLevel,
@@ -190,6 +209,10 @@ impl<'a> EbpfLoader<'a> {
             globals: HashMap::new(),
             max_entries: HashMap::new(),
             map_pin_path_by_name: HashMap::new(),
+            map_migration_by_name: HashMap::new(),
+            reused_maps: HashMap::new(),
+            exported_maps: None,
+            map_offload_by_name: HashMap::new(),
             extensions: HashSet::new(),
             verifier_log_level: VerifierLogLevel::default(),
             allow_unsupported_maps: false,
@@ -413,6 +436,136 @@ impl<'a> EbpfLoader<'a> {
         self
     }
 
+    /// Sets how the pinned map that matches the provided name is handled when its definition
+    /// differs from the object.
+    ///
+    /// By default, pinned maps are used as is. See [`MapMigration`] for the other policies.
+    ///
+    /// Each call to this function with the same name overwrites the policy; last one wins.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use aya::{EbpfLoader, maps::MapMigration};
+    ///
+    /// // The value of the pinned `CONNTRACK` map grew from a u32 to a u64.
+    /// let bpf = EbpfLoader::new()
+    ///     .map_migration(
+    ///         "CONNTRACK",
+    ///         MapMigration::Convert(&|key, value| {
+    ///             let value = u32::from_ne_bytes(value.try_into().ok()?);
+    ///             Some((key.to_vec(), u64::from(value).to_ne_bytes().to_vec()))
+    ///         }),
+    ///     )
+    ///     .load_file("file.o")?;
+    /// # Ok::<(), aya::EbpfError>(())
+    /// ```
+    ///
+    pub fn map_migration(&mut self, name: &'a str, migration: MapMigration<'a>) -> &mut Self {
+        self.map_migration_by_name.insert(name, migration);
+        self
+    }
+
+    /// Reuses an existing map instead of creating the map that matches the provided name.
+    ///
+    /// This allows sharing a map with an already loaded object, for example a map owned by
+    /// another [`Ebpf`] instance. The type, key size, value size, flags and, for maps of maps,
+    /// the inner map definition of `map` must match the definition of the map in the object, or
+    /// loading fails with [`MapError::IncompatibleMap`]. If `max_entries` differs, `map` keeps
+    /// its own and a warning is logged.
+    ///
+    /// Each call to this function with the same name overwrites the map to reuse; last one
+    /// wins.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use aya::{Ebpf, EbpfLoader, maps::Map};
+    ///
+    /// let global = Ebpf::load_file("global.o")?;
+    /// let Some(Map::HashMap(counters)) = global.map("COUNTERS") else {
+    ///     panic!("COUNTERS is not a hash map");
+    /// };
+    /// let tenant = EbpfLoader::new()
+    ///     .reuse_map("COUNTERS", counters)
+    ///     .load_file("tenant.o")?;
+    /// # Ok::<(), aya::EbpfError>(())
+    /// ```
+    ///
+    pub fn reuse_map(&mut self, name: &'a str, map: &'a MapData) -> &mut Self {
+        self.reused_maps.insert(name, map);
+        self
+    }
+
+    /// Reuses the maps exported from another [`Ebpf`] instance where compatible.
+    ///
+    /// This allows loading a new version of an object while keeping the state of its maps, for
+    /// example to upgrade programs without pinning maps. Each map of the object is shared with
+    /// the exported map with the same name if their type, key size, value size, flags and, for
+    /// maps of maps, inner map definitions match. Otherwise a new map is created and the
+    /// mismatch is reported by [`Ebpf::incompatible_maps`]. Reused maps keep their
+    /// `max_entries`, and a warning is logged if it differs from the object.
+    ///
+    /// Global data sections such as `.data`, `.rodata` and `.bss` are never reused, as they are
+    /// initialized from the object. Maps set with [`reuse_map`](Self::reuse_map) take
+    /// precedence.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use aya::{Ebpf, EbpfLoader};
+    ///
+    /// let old = Ebpf::load_file("agent-v1.o")?;
+    /// let maps = old.export_maps()?;
+    /// let new = EbpfLoader::new()
+    ///     .reuse_exported_maps(&maps)
+    ///     .load_file("agent-v2.o")?;
+    /// for error in new.incompatible_maps() {
+    ///     eprintln!("{error}");
+    /// }
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    ///
+    pub const fn reuse_exported_maps(&mut self, maps: &'a ExportedMaps) -> &mut Self {
+        self.exported_maps = Some(maps);
+        self
+    }
+
+    /// Offloads the map that matches the provided name to the network interface `interface`.
+    ///
+    /// Programs offloaded to a device can only use maps offloaded to the same device, and
+    /// programs running on the host can't use offloaded maps. Use this option for the maps used
+    /// by the programs loaded with
+    /// [`Xdp::load_offloaded`](crate::programs::Xdp::load_offloaded). Other maps, including
+    /// global data such as `.rodata` and `.bss` and maps read by user space such as ring buffers
+    /// and perf event arrays, stay on the host.
+    ///
+    /// Only some map types, such as hash maps and arrays, can be offloaded. See
+    /// [`is_offload_supported`](crate::sys::is_offload_supported) to check whether a device
+    /// supports offload. A pinned map is only reused if it is offloaded to `interface`,
+    /// otherwise loading fails with [`MapError::IncompatibleMap`].
+    ///
+    /// Each call to this function with the same name overwrites the interface; last one wins.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use aya::{EbpfLoader, programs::{Xdp, XdpMode}};
+    ///
+    /// let mut bpf = EbpfLoader::new()
+    ///     .offload_map("BLOCKLIST", "eth0")
+    ///     .load_file("file.o")?;
+    /// let program: &mut Xdp = bpf.program_mut("filter").unwrap().try_into()?;
+    /// program.load_offloaded("eth0")?;
+    /// program.attach("eth0", XdpMode::Hardware)?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    ///
+    pub fn offload_map(&mut self, name: &'a str, interface: &'a str) -> &mut Self {
+        self.map_offload_by_name.insert(name, interface);
+        self
+    }
+
     /// Treat the provided program as an [`Extension`]
     ///
     /// When attempting to load the program with the provided `name`
@@ -488,6 +641,28 @@ impl<'a> EbpfLoader<'a> {
     /// # Ok::<(), aya::EbpfError>(())
     /// ```
     pub fn load(&mut self, data: &[u8]) -> Result<Ebpf, EbpfError> {
+        self.load_object(Object::parse(data)?)
+    }
+
+    /// Loads an already parsed object.
+    ///
+    /// This is useful to load objects built from several objects with
+    /// [`Object::link`](aya_obj::Object::link).
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use aya::EbpfLoader;
+    /// use aya_obj::Object;
+    ///
+    /// let objects = ["probes.o", "filters.o"]
+    ///     .into_iter()
+    ///     .map(|path| Ok(Object::parse(&std::fs::read(path)?)?))
+    ///     .collect::<Result<Vec<_>, Box<dyn std::error::Error>>>()?;
+    /// let bpf = EbpfLoader::new().load_object(Object::link(objects)?)?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn load_object(&mut self, mut obj: Object) -> Result<Ebpf, EbpfError> {
         let Self {
             btf,
             default_map_pin_directory,
@@ -497,8 +672,11 @@ impl<'a> EbpfLoader<'a> {
             verifier_log_level,
             allow_unsupported_maps,
             map_pin_path_by_name,
+            map_migration_by_name,
+            reused_maps,
+            exported_maps,
+            map_offload_by_name,
         } = self;
-        let mut obj = Object::parse(data)?;
         obj.patch_map_data(globals.clone())?;
 
         let btf_fd = if let Some(features) = &FEATURES.btf() {
@@ -638,6 +816,7 @@ impl<'a> EbpfLoader<'a> {
         }
 
         let mut maps: HashMap<String, MapData> = HashMap::new();
+        let mut incompatible_maps = Vec::new();
 
         // Regular maps first, so they're available as inner maps below.
         for ((name, mut map_obj), is_map_of_maps) in regular_maps
@@ -665,6 +844,30 @@ impl<'a> EbpfLoader<'a> {
                 map_obj.set_value_size(value_size)
             }
 
+            if let Some(reused) = reused_maps.get(name.as_str()) {
+                let map = reused.reuse(map_obj, &name)?;
+                maps.insert(name, map);
+                continue;
+            }
+            if let Some(exported) = exported_maps.and_then(|maps| maps.get(&name))
+                && !matches!(
+                    map_obj.section_kind(),
+                    EbpfSectionKind::Bss | EbpfSectionKind::Data | EbpfSectionKind::Rodata
+                )
+            {
+                match exported.reuse(map_obj.clone(), &name) {
+                    Ok(map) => {
+                        maps.insert(name, map);
+                        continue;
+                    }
+                    Err(error @ MapError::IncompatibleMap { .. }) => {
+                        warn!("{error}, creating a new map");
+                        incompatible_maps.push(error);
+                    }
+                    Err(error) => return Err(error.into()),
+                }
+            }
+
             let btf_fd = btf_fd.as_deref().map(|fd| fd.as_fd());
 
             // Defer inner map creation to avoid a BPF_MAP_CREATE when the outer map is already pinned.
@@ -677,8 +880,29 @@ impl<'a> EbpfLoader<'a> {
             } else {
                 None
             };
+            let migration = map_migration_by_name
+                .get(name.as_str())
+                .unwrap_or(&MapMigration::Keep);
+            let map_ifindex = map_offload_by_name
+                .get(name.as_str())
+                .map(|interface| {
+                    ifindex_from_ifname(interface).map_err(|_io_error: io::Error| {
+                        MapError::UnknownInterface {
+                            name: (*interface).to_string(),
+                        }
+                    })
+                })
+                .transpose()?;
             let mut map = if let Some(pin_path) = map_pin_path_by_name.get(name.as_str()) {
-                MapData::create_pinned_by_name(pin_path, map_obj, &name, btf_fd, inner_map_obj)?
+                MapData::create_pinned_by_name(
+                    pin_path,
+                    map_obj,
+                    &name,
+                    btf_fd,
+                    inner_map_obj,
+                    migration,
+                    map_ifindex,
+                )?
             } else {
                 match map_obj.pinning() {
                     PinningType::None => {
@@ -690,7 +914,13 @@ impl<'a> EbpfLoader<'a> {
                         } else {
                             None
                         };
-                        MapData::create_with_inner_map_fd(map_obj, &name, btf_fd, inner_map_fd)?
+                        MapData::create_with_inner_map_fd(
+                            map_obj,
+                            &name,
+                            btf_fd,
+                            inner_map_fd,
+                            map_ifindex,
+                        )?
                     }
                     PinningType::ByName => {
                         // pin maps in /sys/fs/bpf by default to align with libbpf
@@ -700,7 +930,15 @@ impl<'a> EbpfLoader<'a> {
                             .unwrap_or_else(|| Path::new("/sys/fs/bpf"));
                         let path = path.join(&name);
 
-                        MapData::create_pinned_by_name(path, map_obj, &name, btf_fd, inner_map_obj)?
+                        MapData::create_pinned_by_name(
+                            path,
+                            map_obj,
+                            &name,
+                            btf_fd,
+                            inner_map_obj,
+                            migration,
+                            map_ifindex,
+                        )?
                     }
                 }
             };
@@ -725,6 +963,8 @@ impl<'a> EbpfLoader<'a> {
         obj.relocate_calls(&text_sections)?;
         obj.sanitize_functions(&FEATURES);
 
+        // Kept around to annotate verifier errors with source line information.
+        let obj_btf = obj.btf.clone().map(Arc::new);
         let programs = obj
             .programs
             .drain()
@@ -736,23 +976,36 @@ impl<'a> EbpfLoader<'a> {
                 let obj = (prog_obj, function_obj);
 
                 let btf_fd = btf_fd.as_ref().map(Arc::clone);
+                let btf = obj_btf.as_ref().map(Arc::clone);
                 let program = if extensions.contains(name.as_str()) {
                     Program::Extension(Extension {
-                        data: ProgramData::new(prog_name, obj, btf_fd, *verifier_log_level),
+                        data: ProgramData::new(prog_name, obj, btf_fd, btf, *verifier_log_level),
                     })
                 } else {
                     match &section {
                         ProgramSection::KProbe => Program::KProbe(KProbe {
-                            data: ProgramData::new(prog_name, obj, btf_fd, *verifier_log_level),
+                            data: ProgramData::new(
+                                prog_name,
+                                obj,
+                                btf_fd,
+                                btf,
+                                *verifier_log_level,
+                            ),
                             kind: ProbeKind::Entry,
                         }),
                         ProgramSection::KRetProbe => Program::KProbe(KProbe {
-                            data: ProgramData::new(prog_name, obj, btf_fd, *verifier_log_level),
+                            data: ProgramData::new(
+                                prog_name,
+                                obj,
+                                btf_fd,
+                                btf,
+                                *verifier_log_level,
+                            ),
                             kind: ProbeKind::Return,
                         }),
                         ProgramSection::UProbe { sleepable, multi } => {
                             let mut data =
-                                ProgramData::new(prog_name, obj, btf_fd, *verifier_log_level);
+                                ProgramData::new(prog_name, obj, btf_fd, btf, *verifier_log_level);
                             if *sleepable {
                                 data.flags = BPF_F_SLEEPABLE;
                             }
@@ -768,7 +1021,7 @@ impl<'a> EbpfLoader<'a> {
                         }
                         ProgramSection::URetProbe { sleepable, multi } => {
                             let mut data =
-                                ProgramData::new(prog_name, obj, btf_fd, *verifier_log_level);
+                                ProgramData::new(prog_name, obj, btf_fd, btf, *verifier_log_level);
                             if *sleepable {
                                 data.flags = BPF_F_SLEEPABLE;
                             }
@@ -783,16 +1036,28 @@ impl<'a> EbpfLoader<'a> {
                             })
                         }
                         ProgramSection::TracePoint => Program::TracePoint(TracePoint {
-                            data: ProgramData::new(prog_name, obj, btf_fd, *verifier_log_level),
+                            data: ProgramData::new(
+                                prog_name,
+                                obj,
+                                btf_fd,
+                                btf,
+                                *verifier_log_level,
+                            ),
                         }),
                         ProgramSection::SocketFilter => Program::SocketFilter(SocketFilter {
-                            data: ProgramData::new(prog_name, obj, btf_fd, *verifier_log_level),
+                            data: ProgramData::new(
+                                prog_name,
+                                obj,
+                                btf_fd,
+                                btf,
+                                *verifier_log_level,
+                            ),
                         }),
                         ProgramSection::Xdp {
                             frags, attach_type, ..
                         } => {
                             let mut data =
-                                ProgramData::new(prog_name, obj, btf_fd, *verifier_log_level);
+                                ProgramData::new(prog_name, obj, btf_fd, btf, *verifier_log_level);
                             if *frags {
                                 data.flags = BPF_F_XDP_HAS_FRAGS;
                             }
@@ -802,67 +1067,145 @@ impl<'a> EbpfLoader<'a> {
                             })
                         }
                         ProgramSection::SkMsg => Program::SkMsg(SkMsg {
-                            data: ProgramData::new(prog_name, obj, btf_fd, *verifier_log_level),
+                            data: ProgramData::new(
+                                prog_name,
+                                obj,
+                                btf_fd,
+                                btf,
+                                *verifier_log_level,
+                            ),
                         }),
                         ProgramSection::CgroupSysctl => Program::CgroupSysctl(CgroupSysctl {
-                            data: ProgramData::new(prog_name, obj, btf_fd, *verifier_log_level),
+                            data: ProgramData::new(
+                                prog_name,
+                                obj,
+                                btf_fd,
+                                btf,
+                                *verifier_log_level,
+                            ),
                         }),
                         ProgramSection::CgroupSockopt { attach_type, .. } => {
                             Program::CgroupSockopt(CgroupSockopt {
-                                data: ProgramData::new(prog_name, obj, btf_fd, *verifier_log_level),
+                                data: ProgramData::new(
+                                    prog_name,
+                                    obj,
+                                    btf_fd,
+                                    btf,
+                                    *verifier_log_level,
+                                ),
                                 attach_type: *attach_type,
                             })
                         }
                         ProgramSection::SkSkbStream { kind } => Program::SkSkb(SkSkb {
-                            data: ProgramData::new(prog_name, obj, btf_fd, *verifier_log_level),
+                            data: ProgramData::new(
+                                prog_name,
+                                obj,
+                                btf_fd,
+                                btf,
+                                *verifier_log_level,
+                            ),
                             kind: *kind,
                         }),
                         ProgramSection::SockOps => Program::SockOps(SockOps {
-                            data: ProgramData::new(prog_name, obj, btf_fd, *verifier_log_level),
+                            data: ProgramData::new(
+                                prog_name,
+                                obj,
+                                btf_fd,
+                                btf,
+                                *verifier_log_level,
+                            ),
                         }),
                         ProgramSection::SchedClassifier => {
                             Program::SchedClassifier(SchedClassifier {
-                                data: ProgramData::new(prog_name, obj, btf_fd, *verifier_log_level),
+                                data: ProgramData::new(
+                                    prog_name,
+                                    obj,
+                                    btf_fd,
+                                    btf,
+                                    *verifier_log_level,
+                                ),
                             })
                         }
                         ProgramSection::CgroupSkb { attach_type } => {
                             Program::CgroupSkb(CgroupSkb {
-                                data: ProgramData::new(prog_name, obj, btf_fd, *verifier_log_level),
+                                data: ProgramData::new(
+                                    prog_name,
+                                    obj,
+                                    btf_fd,
+                                    btf,
+                                    *verifier_log_level,
+                                ),
                                 attach_type: *attach_type,
                             })
                         }
                         ProgramSection::CgroupSockAddr { attach_type, .. } => {
                             Program::CgroupSockAddr(CgroupSockAddr {
-                                data: ProgramData::new(prog_name, obj, btf_fd, *verifier_log_level),
+                                data: ProgramData::new(
+                                    prog_name,
+                                    obj,
+                                    btf_fd,
+                                    btf,
+                                    *verifier_log_level,
+                                ),
                                 attach_type: *attach_type,
                             })
                         }
                         ProgramSection::LircMode2 => Program::LircMode2(LircMode2 {
-                            data: ProgramData::new(prog_name, obj, btf_fd, *verifier_log_level),
+                            data: ProgramData::new(
+                                prog_name,
+                                obj,
+                                btf_fd,
+                                btf,
+                                *verifier_log_level,
+                            ),
                         }),
                         ProgramSection::PerfEvent => Program::PerfEvent(PerfEvent {
-                            data: ProgramData::new(prog_name, obj, btf_fd, *verifier_log_level),
+                            data: ProgramData::new(
+                                prog_name,
+                                obj,
+                                btf_fd,
+                                btf,
+                                *verifier_log_level,
+                            ),
                         }),
                         ProgramSection::RawTracePoint => Program::RawTracePoint(RawTracePoint {
-                            data: ProgramData::new(prog_name, obj, btf_fd, *verifier_log_level),
+                            data: ProgramData::new(
+                                prog_name,
+                                obj,
+                                btf_fd,
+                                btf,
+                                *verifier_log_level,
+                            ),
                         }),
                         ProgramSection::Lsm { sleepable } => {
                             let mut data =
-                                ProgramData::new(prog_name, obj, btf_fd, *verifier_log_level);
+                                ProgramData::new(prog_name, obj, btf_fd, btf, *verifier_log_level);
                             if *sleepable {
                                 data.flags = BPF_F_SLEEPABLE;
                             }
                             Program::Lsm(Lsm { data })
                         }
                         ProgramSection::LsmCgroup => Program::LsmCgroup(LsmCgroup {
-                            data: ProgramData::new(prog_name, obj, btf_fd, *verifier_log_level),
+                            data: ProgramData::new(
+                                prog_name,
+                                obj,
+                                btf_fd,
+                                btf,
+                                *verifier_log_level,
+                            ),
                         }),
                         ProgramSection::BtfTracePoint => Program::BtfTracePoint(BtfTracePoint {
-                            data: ProgramData::new(prog_name, obj, btf_fd, *verifier_log_level),
+                            data: ProgramData::new(
+                                prog_name,
+                                obj,
+                                btf_fd,
+                                btf,
+                                *verifier_log_level,
+                            ),
                         }),
                         ProgramSection::FEntry { sleepable } => {
                             let mut data =
-                                ProgramData::new(prog_name, obj, btf_fd, *verifier_log_level);
+                                ProgramData::new(prog_name, obj, btf_fd, btf, *verifier_log_level);
                             if *sleepable {
                                 data.flags = BPF_F_SLEEPABLE;
                             }
@@ -870,39 +1213,75 @@ impl<'a> EbpfLoader<'a> {
                         }
                         ProgramSection::FExit { sleepable } => {
                             let mut data =
-                                ProgramData::new(prog_name, obj, btf_fd, *verifier_log_level);
+                                ProgramData::new(prog_name, obj, btf_fd, btf, *verifier_log_level);
                             if *sleepable {
                                 data.flags = BPF_F_SLEEPABLE;
                             }
                             Program::FExit(FExit { data })
                         }
                         ProgramSection::FlowDissector => Program::FlowDissector(FlowDissector {
-                            data: ProgramData::new(prog_name, obj, btf_fd, *verifier_log_level),
+                            data: ProgramData::new(
+                                prog_name,
+                                obj,
+                                btf_fd,
+                                btf,
+                                *verifier_log_level,
+                            ),
                         }),
                         ProgramSection::Extension => Program::Extension(Extension {
-                            data: ProgramData::new(prog_name, obj, btf_fd, *verifier_log_level),
+                            data: ProgramData::new(
+                                prog_name,
+                                obj,
+                                btf_fd,
+                                btf,
+                                *verifier_log_level,
+                            ),
                         }),
                         ProgramSection::SkLookup => Program::SkLookup(SkLookup {
-                            data: ProgramData::new(prog_name, obj, btf_fd, *verifier_log_level),
+                            data: ProgramData::new(
+                                prog_name,
+                                obj,
+                                btf_fd,
+                                btf,
+                                *verifier_log_level,
+                            ),
                         }),
                         ProgramSection::SkReuseport { attach_type } => {
                             Program::SkReuseport(SkReuseport {
-                                data: ProgramData::new(prog_name, obj, btf_fd, *verifier_log_level),
+                                data: ProgramData::new(
+                                    prog_name,
+                                    obj,
+                                    btf_fd,
+                                    btf,
+                                    *verifier_log_level,
+                                ),
                                 attach_type: *attach_type,
                             })
                         }
                         ProgramSection::CgroupSock { attach_type, .. } => {
                             Program::CgroupSock(CgroupSock {
-                                data: ProgramData::new(prog_name, obj, btf_fd, *verifier_log_level),
+                                data: ProgramData::new(
+                                    prog_name,
+                                    obj,
+                                    btf_fd,
+                                    btf,
+                                    *verifier_log_level,
+                                ),
                                 attach_type: *attach_type,
                             })
                         }
                         ProgramSection::CgroupDevice => Program::CgroupDevice(CgroupDevice {
-                            data: ProgramData::new(prog_name, obj, btf_fd, *verifier_log_level),
+                            data: ProgramData::new(
+                                prog_name,
+                                obj,
+                                btf_fd,
+                                btf,
+                                *verifier_log_level,
+                            ),
                         }),
                         ProgramSection::Iter { sleepable } => {
                             let mut data =
-                                ProgramData::new(prog_name, obj, btf_fd, *verifier_log_level);
+                                ProgramData::new(prog_name, obj, btf_fd, btf, *verifier_log_level);
                             if *sleepable {
                                 data.flags = BPF_F_SLEEPABLE;
                             }
@@ -918,7 +1297,11 @@ impl<'a> EbpfLoader<'a> {
             .map(|data| parse_map(data, *allow_unsupported_maps))
             .collect::<Result<HashMap<String, Map>, EbpfError>>()?;
 
-        Ok(Ebpf { maps, programs })
+        Ok(Ebpf {
+            maps,
+            programs,
+            incompatible_maps,
+        })
     }
 }
 
@@ -1114,6 +1497,28 @@ impl Default for EbpfLoader<'_> {
 pub struct Ebpf {
     maps: HashMap<String, Map>,
     programs: HashMap<String, Program>,
+    incompatible_maps: Vec<MapError>,
+}
+
+/// The maps of an [`Ebpf`] instance, exported with [`Ebpf::export_maps`].
+///
+/// Exported maps keep the kernel maps alive after the instance is dropped, and can be reused by
+/// a new instance with [`EbpfLoader::reuse_exported_maps`].
+#[derive(Debug)]
+pub struct ExportedMaps {
+    maps: HashMap<String, MapData>,
+}
+
+impl ExportedMaps {
+    /// Returns the exported map with the given name.
+    pub fn get(&self, name: &str) -> Option<&MapData> {
+        self.maps.get(name)
+    }
+
+    /// An iterator over the exported maps and their names.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &MapData)> {
+        self.maps.iter().map(|(name, map)| (name.as_str(), map))
+    }
 }
 
 /// The main entry point into the library, used to work with eBPF programs and maps.
@@ -1275,6 +1680,28 @@ impl Ebpf {
         self.maps.get_disjoint_mut(names)
     }
 
+    /// Exports the maps of this instance.
+    ///
+    /// The exported maps share the kernel maps of this instance, including their contents, and
+    /// can be reused when loading a new version of the object with
+    /// [`EbpfLoader::reuse_exported_maps`]. Maps taken with [`take_map`](Self::take_map) are not
+    /// exported.
+    pub fn export_maps(&self) -> Result<ExportedMaps, MapError> {
+        let maps = self
+            .maps
+            .iter()
+            .map(|(name, map)| Ok((name.clone(), map.map_data().try_clone()?)))
+            .collect::<Result<_, MapError>>()?;
+        Ok(ExportedMaps { maps })
+    }
+
+    /// Returns the maps that couldn't be reused because they are incompatible with the object.
+    ///
+    /// See [`EbpfLoader::reuse_exported_maps`].
+    pub fn incompatible_maps(&self) -> &[MapError] {
+        &self.incompatible_maps
+    }
+
     /// Returns a reference to the program with the given name.
     ///
     /// You can use this to inspect a program and its properties. To load and attach a program, use
@@ -1315,6 +1742,25 @@ impl Ebpf {
         self.programs.get_mut(name)
     }
 
+    /// Takes ownership of the program with the given name.
+    ///
+    /// Use this when borrowing with [`program_mut`](Self::program_mut) is not possible, for
+    /// example to keep typed programs next to the maps they use. The program is removed from
+    /// this `Ebpf`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let mut bpf = aya::Ebpf::load(&[])?;
+    /// use aya::programs::Xdp;
+    ///
+    /// let program: Xdp = bpf.take_program("xdp_pass").unwrap().try_into()?;
+    /// # Ok::<(), aya::EbpfError>(())
+    /// ```
+    pub fn take_program(&mut self, name: &str) -> Option<Program> {
+        self.programs.remove(name)
+    }
+
     /// An iterator over all the programs.
     ///
     /// # Examples
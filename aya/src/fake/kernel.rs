@@ -0,0 +1,966 @@
+//! The objects of a fake kernel and the commands operating on them.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    ffi::{CStr, CString, c_char},
+    fs::{self, File},
+    mem::MaybeUninit,
+    os::{
+        fd::{FromRawFd as _, IntoRawFd as _, OwnedFd, RawFd},
+        unix::fs::{FileExt as _, MetadataExt as _},
+    },
+    ptr, slice,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use aya_obj::generated::{
+    BPF_DW, BPF_F_ALLOW_MULTI, BPF_F_REPLACE, BPF_IMM, BPF_LD, BPF_PSEUDO_MAP_FD,
+    BPF_PSEUDO_MAP_VALUE, bpf_attach_type, bpf_attr, bpf_btf_info, bpf_cmd, bpf_insn,
+    bpf_link_info, bpf_link_type, bpf_map_info, bpf_map_type, bpf_prog_info,
+};
+use libc::{EAGAIN, EBADF, EBUSY, EEXIST, EINVAL, ENOENT, ENOSPC, EOPNOTSUPP, EPERM};
+
+use super::{AttachTarget, maps::MapDef};
+use crate::{
+    fake::maps::MapState,
+    util::{nr_cpus, page_size},
+};
+
+pub(super) struct ProgramState {
+    pub(super) name: String,
+    pub(super) prog_type: u32,
+    pub(super) expected_attach_type: u32,
+    pub(super) license: CString,
+    pub(super) instructions: Vec<bpf_insn>,
+    pub(super) map_ids: Vec<u32>,
+    btf_id: u32,
+    attach_btf_id: u32,
+    load_time: u64,
+}
+
+pub(super) struct LinkState {
+    pub(super) program: u32,
+    pub(super) attach_type: Option<u32>,
+    pub(super) target: AttachTarget,
+    pub(super) detached: bool,
+}
+
+pub(super) enum Kind {
+    Map(MapState),
+    Program(ProgramState),
+    Link(LinkState),
+    Btf(Vec<u8>),
+    PerfEvent { program: Option<u32> },
+    Other,
+}
+
+pub(super) struct Object {
+    pub(super) id: u32,
+    /// The memfd backing the object. Userspace gets duplicates of it, so that fds, `mmap` and
+    /// `poll` behave like they do for real objects.
+    file: File,
+    pub(super) kind: Kind,
+}
+
+/// An attachment made with `BPF_PROG_ATTACH`.
+pub(super) struct Attachment {
+    pub(super) program: u32,
+    pub(super) attach_type: u32,
+    pub(super) target: AttachTarget,
+}
+
+#[derive(Default)]
+pub(super) struct Kernel {
+    /// The device of memfds, shared by all the objects.
+    dev: Option<u64>,
+    /// The objects, by inode.
+    pub(super) objects: HashMap<u64, Object>,
+    ids: BTreeMap<u32, u64>,
+    next_id: u32,
+    pins: HashMap<CString, u64>,
+    pub(super) attachments: Vec<Attachment>,
+}
+
+pub(super) type Result<T, E = i32> = std::result::Result<T, E>;
+
+fn errno(error: &std::io::Error) -> i32 {
+    error.raw_os_error().unwrap_or(EINVAL)
+}
+
+fn fstat(fd: RawFd) -> Result<(u64, u64)> {
+    let mut stat = MaybeUninit::<libc::stat>::uninit();
+    // SAFETY: `stat` is a valid buffer; invalid fds are reported by `fstat`.
+    if unsafe { libc::fstat(fd, stat.as_mut_ptr()) } != 0 {
+        return Err(EBADF);
+    }
+    // SAFETY: `fstat` succeeded.
+    let stat = unsafe { stat.assume_init() };
+    Ok((stat.st_dev, stat.st_ino))
+}
+
+/// Reads `len` bytes at `ptr`.
+///
+/// # Safety
+///
+/// `ptr` must be null or point to `len` readable bytes.
+unsafe fn read(ptr: u64, len: usize) -> Vec<u8> {
+    if ptr == 0 || len == 0 {
+        return Vec::new();
+    }
+    unsafe { slice::from_raw_parts(ptr as *const u8, len) }.to_vec()
+}
+
+/// Writes `data` at `ptr`.
+///
+/// # Safety
+///
+/// `ptr` must be null or point to `data.len()` writable bytes.
+const unsafe fn write(ptr: u64, data: &[u8]) {
+    if ptr != 0 {
+        unsafe { ptr::copy_nonoverlapping(data.as_ptr(), ptr as *mut u8, data.len()) }
+    }
+}
+
+/// Writes the first `len` bytes of `value` at `ptr`.
+///
+/// # Safety
+///
+/// `ptr` must point to `len` writable bytes.
+unsafe fn write_struct<T>(ptr: u64, len: u32, value: &T) -> u32 {
+    let len = (len as usize).min(size_of::<T>());
+    let bytes = unsafe { slice::from_raw_parts(ptr::from_ref(value).cast::<u8>(), len) };
+    unsafe {
+        write(ptr, bytes);
+    }
+    len as u32
+}
+
+fn c_name(name: &[c_char]) -> String {
+    let name: Vec<u8> = name
+        .iter()
+        .take_while(|&&c| c != 0)
+        .map(|&c| c as u8)
+        .collect();
+    String::from_utf8_lossy(&name).into_owned()
+}
+
+fn set_c_name(dst: &mut [c_char], name: &str) {
+    let len = dst.len() - 1;
+    for (dst, src) in dst.iter_mut().zip(name.bytes().take(len)) {
+        *dst = src as c_char;
+    }
+}
+
+/// Returns whether programs of `attach_type` attach to a network interface rather than a file.
+fn attaches_to_interface(attach_type: u32) -> bool {
+    matches!(
+        bpf_attach_type::try_from(attach_type),
+        Ok(bpf_attach_type::BPF_XDP
+            | bpf_attach_type::BPF_TCX_INGRESS
+            | bpf_attach_type::BPF_TCX_EGRESS
+            | bpf_attach_type::BPF_NETKIT_PRIMARY
+            | bpf_attach_type::BPF_NETKIT_PEER)
+    )
+}
+
+fn link_type(link: &LinkState) -> bpf_link_type {
+    let Some(attach_type) = link.attach_type else {
+        return bpf_link_type::BPF_LINK_TYPE_RAW_TRACEPOINT;
+    };
+    match bpf_attach_type::try_from(attach_type) {
+        Ok(bpf_attach_type::BPF_XDP) => bpf_link_type::BPF_LINK_TYPE_XDP,
+        Ok(bpf_attach_type::BPF_TCX_INGRESS | bpf_attach_type::BPF_TCX_EGRESS) => {
+            bpf_link_type::BPF_LINK_TYPE_TCX
+        }
+        Ok(bpf_attach_type::BPF_NETKIT_PRIMARY | bpf_attach_type::BPF_NETKIT_PEER) => {
+            bpf_link_type::BPF_LINK_TYPE_NETKIT
+        }
+        Ok(bpf_attach_type::BPF_PERF_EVENT) => bpf_link_type::BPF_LINK_TYPE_PERF_EVENT,
+        Ok(bpf_attach_type::BPF_TRACE_ITER) => bpf_link_type::BPF_LINK_TYPE_ITER,
+        Ok(bpf_attach_type::BPF_TRACE_KPROBE_MULTI) => bpf_link_type::BPF_LINK_TYPE_KPROBE_MULTI,
+        Ok(bpf_attach_type::BPF_TRACE_UPROBE_MULTI) => bpf_link_type::BPF_LINK_TYPE_UPROBE_MULTI,
+        Ok(bpf_attach_type::BPF_FLOW_DISSECTOR | bpf_attach_type::BPF_SK_LOOKUP) => {
+            bpf_link_type::BPF_LINK_TYPE_NETNS
+        }
+        Ok(
+            bpf_attach_type::BPF_TRACE_RAW_TP
+            | bpf_attach_type::BPF_TRACE_FENTRY
+            | bpf_attach_type::BPF_TRACE_FEXIT
+            | bpf_attach_type::BPF_MODIFY_RETURN
+            | bpf_attach_type::BPF_LSM_MAC
+            | bpf_attach_type::BPF_LSM_CGROUP,
+        ) => bpf_link_type::BPF_LINK_TYPE_TRACING,
+        Ok(_) => bpf_link_type::BPF_LINK_TYPE_CGROUP,
+        Err(_) => bpf_link_type::BPF_LINK_TYPE_UNSPEC,
+    }
+}
+
+impl Kernel {
+    fn create(&mut self, name: &CStr, kind: Kind) -> Result<i64> {
+        self.collect();
+
+        // SAFETY: `name` is a valid C string.
+        let fd = unsafe { libc::memfd_create(name.as_ptr(), libc::MFD_CLOEXEC) };
+        if fd < 0 {
+            return Err(errno(&std::io::Error::last_os_error()));
+        }
+        // SAFETY: `memfd_create` returned a new fd.
+        let file = File::from(unsafe { OwnedFd::from_raw_fd(fd) });
+        let metadata = file.metadata().map_err(|e| errno(&e))?;
+        if let Kind::Map(map) = &kind {
+            if map.is_ringbuf() {
+                // The consumer page, the producer page and the data pages, mapped twice.
+                let len = 2 * page_size() + 2 * map.max_entries as usize;
+                file.set_len(len as u64).map_err(|e| errno(&e))?;
+            }
+        }
+        let fd = file.try_clone().map_err(|e| errno(&e))?;
+
+        self.dev = Some(metadata.dev());
+        self.next_id += 1;
+        let id = self.next_id;
+        let _unused: Option<u64> = self.ids.insert(id, metadata.ino());
+        let _unused: Option<Object> = self
+            .objects
+            .insert(metadata.ino(), Object { id, file, kind });
+        Ok(i64::from(fd.into_raw_fd()))
+    }
+
+    fn dup(&self, key: u64) -> Result<i64> {
+        let Object { file, .. } = &self.objects[&key];
+        let fd = file.try_clone().map_err(|e| errno(&e))?;
+        Ok(i64::from(fd.into_raw_fd()))
+    }
+
+    /// Returns the key of the object referred to by `fd`.
+    fn key(&self, fd: RawFd) -> Result<u64> {
+        let (dev, ino) = fstat(fd)?;
+        if Some(dev) == self.dev && self.objects.contains_key(&ino) {
+            Ok(ino)
+        } else {
+            Err(EINVAL)
+        }
+    }
+
+    pub(super) fn is_object(&self, fd: RawFd) -> bool {
+        self.key(fd).is_ok()
+    }
+
+    pub(super) fn file(&self, fd: RawFd) -> Option<&File> {
+        let key = self.key(fd).ok()?;
+        Some(&self.objects[&key].file)
+    }
+
+    pub(super) fn kind(&self, fd: RawFd) -> Option<&Kind> {
+        let key = self.key(fd).ok()?;
+        Some(&self.objects[&key].kind)
+    }
+
+    fn by_id(&self, id: u32) -> Option<u64> {
+        self.ids.get(&id).copied()
+    }
+
+    pub(super) fn map(&mut self, key: u64) -> Result<&mut MapState> {
+        match self.objects.get_mut(&key) {
+            Some(Object {
+                kind: Kind::Map(map),
+                ..
+            }) => Ok(map),
+            Some(_) => Err(EINVAL),
+            None => Err(ENOENT),
+        }
+    }
+
+    fn map_fd(&mut self, fd: u32) -> Result<(u64, &mut MapState)> {
+        let key = self.key(fd as RawFd)?;
+        self.map(key).map(|map| (key, map))
+    }
+
+    fn program_id(&self, fd: u32) -> Result<u32> {
+        let key = self.key(fd as RawFd)?;
+        match &self.objects[&key] {
+            Object {
+                id,
+                kind: Kind::Program(_),
+                ..
+            } => Ok(*id),
+            _ => Err(EINVAL),
+        }
+    }
+
+    fn target(&self, attach_type: u32, target: u32) -> Result<AttachTarget> {
+        if attaches_to_interface(attach_type) {
+            return Ok(AttachTarget::IfIndex(target));
+        }
+        let (dev, ino) = fstat(target as RawFd)?;
+        if Some(dev) != self.dev {
+            return Ok(AttachTarget::File { dev, ino });
+        }
+        match self.objects.get(&ino) {
+            Some(Object {
+                id,
+                kind: Kind::Map(_),
+                ..
+            }) => Ok(AttachTarget::Map(*id)),
+            Some(Object {
+                id,
+                kind: Kind::Program(_),
+                ..
+            }) => Ok(AttachTarget::Program(*id)),
+            Some(Object {
+                kind: Kind::PerfEvent { .. },
+                ..
+            }) => Ok(AttachTarget::PerfEvent),
+            Some(_) => Err(EINVAL),
+            None => Ok(AttachTarget::File { dev, ino }),
+        }
+    }
+
+    /// Returns the objects the kernel would keep alive: the ones with an fd open in this
+    /// process, the pinned ones, and the ones they reference.
+    pub(super) fn live(&self) -> HashSet<u64> {
+        let mut open = HashMap::<u64, usize>::new();
+        if let Ok(entries) = fs::read_dir("/proc/self/fd") {
+            for entry in entries.flatten() {
+                if let Ok(metadata) = fs::metadata(entry.path()) {
+                    if Some(metadata.dev()) == self.dev {
+                        *open.entry(metadata.ino()).or_default() += 1;
+                    }
+                }
+            }
+        }
+        // One of the fds is the kernel's own.
+        let mut pending: Vec<u64> = self
+            .objects
+            .keys()
+            .copied()
+            .filter(|key| open.get(key).is_some_and(|&count| count > 1))
+            .chain(self.pins.values().copied())
+            .chain(
+                self.attachments
+                    .iter()
+                    .filter_map(|attachment| self.by_id(attachment.program)),
+            )
+            .collect();
+        let mut live = HashSet::new();
+        while let Some(key) = pending.pop() {
+            if !live.insert(key) {
+                continue;
+            }
+            let Some(Object { kind, .. }) = self.objects.get(&key) else {
+                continue;
+            };
+            let ids = match kind {
+                Kind::Map(map) => match bpf_map_type::try_from(map.map_type) {
+                    Ok(
+                        bpf_map_type::BPF_MAP_TYPE_PROG_ARRAY
+                        | bpf_map_type::BPF_MAP_TYPE_ARRAY_OF_MAPS
+                        | bpf_map_type::BPF_MAP_TYPE_HASH_OF_MAPS,
+                    ) => map
+                        .values()
+                        .into_iter()
+                        .filter_map(|value| value.try_into().ok().map(u32::from_ne_bytes))
+                        .chain([map.btf_id])
+                        .collect(),
+                    _ => vec![map.btf_id],
+                },
+                Kind::Program(program) => program
+                    .map_ids
+                    .iter()
+                    .copied()
+                    .chain([program.btf_id])
+                    .collect(),
+                Kind::Link(link) if !link.detached => vec![link.program],
+                Kind::PerfEvent { program } => program.iter().copied().collect(),
+                Kind::Link(_) | Kind::Btf(_) | Kind::Other => Vec::new(),
+            };
+            pending.extend(ids.into_iter().filter_map(|id| self.by_id(id)));
+        }
+        live
+    }
+
+    /// Drops the objects nothing refers to anymore.
+    pub(super) fn collect(&mut self) {
+        let live = self.live();
+        self.objects.retain(|key, _| live.contains(key));
+        self.ids.retain(|_, key| live.contains(key));
+    }
+
+    pub(super) fn bpf(&mut self, cmd: bpf_cmd, attr: &mut bpf_attr) -> Result<i64> {
+        // SAFETY: aya's syscall wrappers initialize the member of `attr` matching `cmd`, and
+        // pass pointers to buffers which outlive the call.
+        unsafe {
+            match cmd {
+                bpf_cmd::BPF_MAP_CREATE => self.map_create(attr),
+                bpf_cmd::BPF_MAP_LOOKUP_ELEM
+                | bpf_cmd::BPF_MAP_UPDATE_ELEM
+                | bpf_cmd::BPF_MAP_DELETE_ELEM
+                | bpf_cmd::BPF_MAP_GET_NEXT_KEY
+                | bpf_cmd::BPF_MAP_LOOKUP_AND_DELETE_ELEM
+                | bpf_cmd::BPF_MAP_FREEZE => self.map_elem(cmd, attr).map(|()| 0),
+                bpf_cmd::BPF_PROG_LOAD => self.prog_load(attr),
+                bpf_cmd::BPF_BTF_LOAD => {
+                    let u = &attr.__bindgen_anon_7;
+                    let btf = read(u.btf, u.btf_size as usize);
+                    if btf.get(..2) != Some(&0xeb9fu16.to_ne_bytes()[..]) {
+                        return Err(EINVAL);
+                    }
+                    self.create(c"btf", Kind::Btf(btf))
+                }
+                bpf_cmd::BPF_OBJ_PIN => {
+                    let u = &attr.__bindgen_anon_4;
+                    let key = self.key(u.bpf_fd as RawFd)?;
+                    let path = CStr::from_ptr(u.pathname as *const c_char).to_owned();
+                    if self.pins.contains_key(&path) {
+                        return Err(EEXIST);
+                    }
+                    let _unused: Option<u64> = self.pins.insert(path, key);
+                    Ok(0)
+                }
+                bpf_cmd::BPF_OBJ_GET => {
+                    let u = &attr.__bindgen_anon_4;
+                    let path = CStr::from_ptr(u.pathname as *const c_char);
+                    let key = *self.pins.get(path).ok_or(ENOENT)?;
+                    self.dup(key)
+                }
+                bpf_cmd::BPF_PROG_ATTACH | bpf_cmd::BPF_PROG_DETACH => {
+                    self.prog_attach(cmd, attr).map(|()| 0)
+                }
+                bpf_cmd::BPF_PROG_QUERY => self.prog_query(attr).map(|()| 0),
+                bpf_cmd::BPF_PROG_GET_NEXT_ID
+                | bpf_cmd::BPF_MAP_GET_NEXT_ID
+                | bpf_cmd::BPF_LINK_GET_NEXT_ID
+                | bpf_cmd::BPF_BTF_GET_NEXT_ID => {
+                    let start = attr.__bindgen_anon_6.__bindgen_anon_1.start_id;
+                    let live = self.live();
+                    let next = self
+                        .ids
+                        .range(start + 1..)
+                        .find(|(_, key)| live.contains(key) && self.has_kind(cmd, **key))
+                        .map(|(id, _)| *id)
+                        .ok_or(ENOENT)?;
+                    attr.__bindgen_anon_6.next_id = next;
+                    Ok(0)
+                }
+                bpf_cmd::BPF_PROG_GET_FD_BY_ID
+                | bpf_cmd::BPF_MAP_GET_FD_BY_ID
+                | bpf_cmd::BPF_LINK_GET_FD_BY_ID
+                | bpf_cmd::BPF_BTF_GET_FD_BY_ID => {
+                    let id = attr.__bindgen_anon_6.__bindgen_anon_1.start_id;
+                    let key = self
+                        .by_id(id)
+                        .filter(|key| self.live().contains(key) && self.has_kind(cmd, *key))
+                        .ok_or(ENOENT)?;
+                    self.dup(key)
+                }
+                bpf_cmd::BPF_OBJ_GET_INFO_BY_FD => self.info(attr).map(|()| 0),
+                bpf_cmd::BPF_RAW_TRACEPOINT_OPEN => {
+                    let u = &attr.raw_tracepoint;
+                    let program = self.program_id(u.prog_fd)?;
+                    let name = (u.name != 0).then(|| {
+                        CStr::from_ptr(u.name as *const c_char)
+                            .to_string_lossy()
+                            .into_owned()
+                    });
+                    let link = LinkState {
+                        program,
+                        attach_type: None,
+                        target: AttachTarget::RawTracePoint(name),
+                        detached: false,
+                    };
+                    self.create(c"bpf_link", Kind::Link(link))
+                }
+                bpf_cmd::BPF_LINK_CREATE => {
+                    let u = &attr.link_create;
+                    let program = self.program_id(u.__bindgen_anon_1.prog_fd)?;
+                    let target = u.__bindgen_anon_2.target_fd;
+                    let target = if target == 0 && !attaches_to_interface(u.attach_type) {
+                        AttachTarget::None
+                    } else {
+                        self.target(u.attach_type, target)?
+                    };
+                    let link = LinkState {
+                        program,
+                        attach_type: Some(u.attach_type),
+                        target,
+                        detached: false,
+                    };
+                    self.create(c"bpf_link", Kind::Link(link))
+                }
+                bpf_cmd::BPF_LINK_UPDATE => {
+                    let u = &attr.link_update;
+                    let program = self.program_id(u.__bindgen_anon_1.new_prog_fd)?;
+                    let old_program = if u.flags & BPF_F_REPLACE != 0 {
+                        Some(self.program_id(u.__bindgen_anon_2.old_prog_fd)?)
+                    } else {
+                        None
+                    };
+                    let key = self.key(u.link_fd as RawFd)?;
+                    let Some(Object {
+                        kind: Kind::Link(link),
+                        ..
+                    }) = self.objects.get_mut(&key)
+                    else {
+                        return Err(EINVAL);
+                    };
+                    if old_program.is_some_and(|old| old != link.program) {
+                        return Err(EPERM);
+                    }
+                    link.program = program;
+                    Ok(0)
+                }
+                bpf_cmd::BPF_LINK_DETACH => {
+                    let key = self.key(attr.link_detach.link_fd as RawFd)?;
+                    let Some(Object {
+                        kind: Kind::Link(link),
+                        ..
+                    }) = self.objects.get_mut(&key)
+                    else {
+                        return Err(EINVAL);
+                    };
+                    link.detached = true;
+                    Ok(0)
+                }
+                bpf_cmd::BPF_ITER_CREATE => self.create(c"bpf_iter", Kind::Other),
+                bpf_cmd::BPF_ENABLE_STATS => self.create(c"bpf_stats", Kind::Other),
+                bpf_cmd::BPF_PROG_TEST_RUN => Err(EOPNOTSUPP),
+                _ => Err(EINVAL),
+            }
+        }
+    }
+
+    fn has_kind(&self, cmd: bpf_cmd, key: u64) -> bool {
+        let kind = &self.objects[&key].kind;
+        match cmd {
+            bpf_cmd::BPF_PROG_GET_NEXT_ID | bpf_cmd::BPF_PROG_GET_FD_BY_ID => {
+                matches!(kind, Kind::Program(_))
+            }
+            bpf_cmd::BPF_MAP_GET_NEXT_ID | bpf_cmd::BPF_MAP_GET_FD_BY_ID => {
+                matches!(kind, Kind::Map(_))
+            }
+            bpf_cmd::BPF_LINK_GET_NEXT_ID | bpf_cmd::BPF_LINK_GET_FD_BY_ID => {
+                matches!(kind, Kind::Link(_))
+            }
+            _ => matches!(kind, Kind::Btf(_)),
+        }
+    }
+
+    unsafe fn map_create(&mut self, attr: &bpf_attr) -> Result<i64> {
+        let u = unsafe { &attr.__bindgen_anon_1 };
+        let btf_id = if u.btf_fd == 0 {
+            0
+        } else {
+            let key = self.key(u.btf_fd as RawFd)?;
+            match &self.objects[&key] {
+                Object {
+                    id,
+                    kind: Kind::Btf(_),
+                    ..
+                } => *id,
+                _ => return Err(EINVAL),
+            }
+        };
+        let nr_cpus = nr_cpus().map_err(|(_, error)| errno(&error))?;
+        let map = MapState::new(
+            MapDef {
+                map_type: u.map_type,
+                name: c_name(&u.map_name),
+                key_size: u.key_size,
+                value_size: u.value_size,
+                max_entries: u.max_entries,
+                map_flags: u.map_flags,
+                map_extra: u.map_extra,
+                btf_id,
+                btf_key_type_id: u.btf_key_type_id,
+                btf_value_type_id: u.btf_value_type_id,
+            },
+            nr_cpus,
+        )?;
+        self.create(c"bpf-map", Kind::Map(map))
+    }
+
+    /// Translates the fds stored in maps of programs and maps of maps to the ids userspace
+    /// reads back.
+    fn map_value(&self, map_type: u32, value: Vec<u8>) -> Result<Vec<u8>> {
+        let expect_map = match bpf_map_type::try_from(map_type) {
+            Ok(bpf_map_type::BPF_MAP_TYPE_PROG_ARRAY) => false,
+            Ok(
+                bpf_map_type::BPF_MAP_TYPE_ARRAY_OF_MAPS | bpf_map_type::BPF_MAP_TYPE_HASH_OF_MAPS,
+            ) => true,
+            _ => return Ok(value),
+        };
+        let fd = u32::from_ne_bytes(value.as_slice().try_into().map_err(|_len| EINVAL)?);
+        let key = self.key(fd as RawFd)?;
+        match &self.objects[&key] {
+            Object {
+                id,
+                kind: Kind::Map(_),
+                ..
+            } if expect_map => Ok(id.to_ne_bytes().to_vec()),
+            Object {
+                id,
+                kind: Kind::Program(_),
+                ..
+            } if !expect_map => Ok(id.to_ne_bytes().to_vec()),
+            _ => Err(EINVAL),
+        }
+    }
+
+    unsafe fn map_elem(&mut self, cmd: bpf_cmd, attr: &bpf_attr) -> Result<()> {
+        let u = unsafe { &attr.__bindgen_anon_2 };
+        let value_ptr = unsafe { u.__bindgen_anon_1.value };
+        let (_, map) = self.map_fd(u.map_fd)?;
+        let (key_size, value_size, map_type) = (map.key_size(), map.value_size(), map.map_type);
+        let key = unsafe { read(u.key, key_size) };
+        let (_, map) = self.map_fd(u.map_fd)?;
+        match cmd {
+            bpf_cmd::BPF_MAP_LOOKUP_ELEM => {
+                let value = if map.is_keyless() {
+                    map.peek(&unsafe { read(value_ptr, value_size) })?
+                } else {
+                    map.lookup(&key)?
+                };
+                unsafe {
+                    write(value_ptr, &value);
+                }
+            }
+            bpf_cmd::BPF_MAP_UPDATE_ELEM => {
+                if map.frozen {
+                    return Err(EPERM);
+                }
+                let value = unsafe { read(value_ptr, value_size) };
+                let value = self.map_value(map_type, value)?;
+                let (_, map) = self.map_fd(u.map_fd)?;
+                if map.is_keyless() {
+                    map.push(&value, u.flags)?;
+                } else {
+                    map.update(&key, &value, u.flags)?;
+                }
+            }
+            bpf_cmd::BPF_MAP_DELETE_ELEM => {
+                if map.frozen {
+                    return Err(EPERM);
+                }
+                map.delete(&key)?;
+            }
+            bpf_cmd::BPF_MAP_GET_NEXT_KEY => {
+                let next = map.next_key((u.key != 0).then_some(key.as_slice()))?;
+                unsafe {
+                    write(u.__bindgen_anon_1.next_key, &next);
+                }
+            }
+            bpf_cmd::BPF_MAP_LOOKUP_AND_DELETE_ELEM => {
+                if map.frozen {
+                    return Err(EPERM);
+                }
+                let value = map.lookup_and_delete(&key)?;
+                unsafe {
+                    write(value_ptr, &value);
+                }
+            }
+            _ => {
+                if map.frozen {
+                    return Err(EBUSY);
+                }
+                map.frozen = true;
+            }
+        }
+        Ok(())
+    }
+
+    unsafe fn prog_load(&mut self, attr: &bpf_attr) -> Result<i64> {
+        let u = unsafe { &attr.__bindgen_anon_3 };
+        if u.insn_cnt == 0 || u.license == 0 {
+            return Err(EINVAL);
+        }
+        let instructions =
+            unsafe { slice::from_raw_parts(u.insns as *const bpf_insn, u.insn_cnt as usize) }
+                .to_vec();
+        let license = unsafe { CStr::from_ptr(u.license as *const c_char) }.to_owned();
+
+        // Like the kernel, resolve the maps referenced by the program.
+        let mut map_ids = Vec::new();
+        for insn in &instructions {
+            if u32::from(insn.code) == BPF_LD | BPF_IMM | BPF_DW
+                && matches!(
+                    u32::from(insn.src_reg()),
+                    BPF_PSEUDO_MAP_FD | BPF_PSEUDO_MAP_VALUE
+                )
+            {
+                let key = self.key(insn.imm)?;
+                let Object { id, kind, .. } = &self.objects[&key];
+                if !matches!(kind, Kind::Map(_)) {
+                    return Err(EINVAL);
+                }
+                if !map_ids.contains(id) {
+                    map_ids.push(*id);
+                }
+            }
+        }
+        let btf_id = if u.prog_btf_fd == 0 {
+            0
+        } else {
+            let key = self.key(u.prog_btf_fd as RawFd)?;
+            self.objects[&key].id
+        };
+        let load_time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_nanos() as u64)
+            .unwrap_or_default();
+        let program = ProgramState {
+            name: c_name(&u.prog_name),
+            prog_type: u.prog_type,
+            expected_attach_type: u.expected_attach_type,
+            license,
+            instructions,
+            map_ids,
+            btf_id,
+            attach_btf_id: u.attach_btf_id,
+            load_time,
+        };
+        self.create(c"bpf-prog", Kind::Program(program))
+    }
+
+    unsafe fn prog_attach(&mut self, cmd: bpf_cmd, attr: &bpf_attr) -> Result<()> {
+        let u = unsafe { &attr.__bindgen_anon_5 };
+        let target = self.target(u.attach_type, unsafe { u.__bindgen_anon_1.target_fd })?;
+        let program = self.program_id(u.attach_bpf_fd);
+        let same = |attachment: &Attachment| {
+            attachment.attach_type == u.attach_type && attachment.target == target
+        };
+        if cmd == bpf_cmd::BPF_PROG_DETACH {
+            let len = self.attachments.len();
+            self.attachments.retain(|attachment| {
+                !same(attachment) || program.is_ok_and(|program| program != attachment.program)
+            });
+            return if self.attachments.len() == len {
+                Err(ENOENT)
+            } else {
+                Ok(())
+            };
+        }
+        let program = program?;
+        if u.attach_flags & BPF_F_ALLOW_MULTI == 0 {
+            self.attachments.retain(|attachment| !same(attachment));
+        }
+        self.attachments.push(Attachment {
+            program,
+            attach_type: u.attach_type,
+            target,
+        });
+        Ok(())
+    }
+
+    unsafe fn prog_query(&mut self, attr: &mut bpf_attr) -> Result<()> {
+        let u = unsafe { &mut attr.query };
+        let target = self.target(u.attach_type, unsafe { u.__bindgen_anon_1.target_fd })?;
+        let live = self.live();
+        let programs: Vec<u32> = self
+            .attachments
+            .iter()
+            .filter(|attachment| {
+                attachment.attach_type == u.attach_type && attachment.target == target
+            })
+            .map(|attachment| attachment.program)
+            .chain(
+                self.objects
+                    .iter()
+                    .filter_map(|(key, object)| match &object.kind {
+                        Kind::Link(link)
+                            if live.contains(key)
+                                && !link.detached
+                                && link.attach_type == Some(u.attach_type)
+                                && link.target == target =>
+                        {
+                            Some(link.program)
+                        }
+                        _ => None,
+                    }),
+            )
+            .collect();
+        let capacity = unsafe { u.__bindgen_anon_2.prog_cnt } as usize;
+        u.__bindgen_anon_2.prog_cnt = programs.len() as u32;
+        u.attach_flags = 0;
+        u.revision = 1;
+        if u.prog_ids != 0 {
+            let len = programs.len().min(capacity);
+            let ids: Vec<u8> = programs[..len]
+                .iter()
+                .flat_map(|id| id.to_ne_bytes())
+                .collect();
+            unsafe {
+                write(u.prog_ids, &ids);
+            }
+            if len < programs.len() {
+                return Err(ENOSPC);
+            }
+        }
+        Ok(())
+    }
+
+    unsafe fn info(&mut self, attr: &mut bpf_attr) -> Result<()> {
+        let u = unsafe { &mut attr.info };
+        let key = self.key(u.bpf_fd as RawFd)?;
+        let Object { id, kind, .. } = &self.objects[&key];
+        let id = *id;
+        u.info_len = match kind {
+            Kind::Map(map) => {
+                let mut info = unsafe { std::mem::zeroed::<bpf_map_info>() };
+                info.type_ = map.map_type;
+                info.id = id;
+                info.key_size = map.key_size;
+                info.value_size = map.value_size;
+                info.max_entries = map.max_entries;
+                info.map_flags = map.map_flags;
+                info.map_extra = map.map_extra;
+                info.btf_id = map.btf_id;
+                info.btf_key_type_id = map.btf_key_type_id;
+                info.btf_value_type_id = map.btf_value_type_id;
+                set_c_name(&mut info.name, &map.name);
+                unsafe { write_struct(u.info, u.info_len, &info) }
+            }
+            Kind::Program(program) => {
+                let mut input = unsafe { std::mem::zeroed::<bpf_prog_info>() };
+                let len = (u.info_len as usize).min(size_of::<bpf_prog_info>());
+                unsafe {
+                    ptr::copy_nonoverlapping(
+                        u.info as *const u8,
+                        ptr::from_mut(&mut input).cast::<u8>(),
+                        len,
+                    );
+                };
+                let mut info = unsafe { std::mem::zeroed::<bpf_prog_info>() };
+                info.type_ = program.prog_type;
+                info.id = id;
+                info.load_time = program.load_time;
+                // SAFETY: getuid can't fail.
+                info.created_by_uid = unsafe { libc::getuid() };
+                info.btf_id = program.btf_id;
+                info.attach_btf_id = program.attach_btf_id;
+                info.verified_insns = program.instructions.len() as u32;
+                info.set_gpl_compatible(u32::from(
+                    program.license.to_bytes().windows(3).any(|w| w == b"GPL"),
+                ));
+                set_c_name(&mut info.name, &program.name);
+
+                let instructions = unsafe {
+                    slice::from_raw_parts(
+                        program.instructions.as_ptr().cast::<u8>(),
+                        size_of_val(program.instructions.as_slice()),
+                    )
+                };
+                info.xlated_prog_len = instructions.len() as u32;
+                if input.xlated_prog_insns != 0 {
+                    let len = instructions.len().min(input.xlated_prog_len as usize);
+                    unsafe {
+                        write(input.xlated_prog_insns, &instructions[..len]);
+                    }
+                    info.xlated_prog_insns = input.xlated_prog_insns;
+                }
+                info.nr_map_ids = program.map_ids.len() as u32;
+                if input.map_ids != 0 {
+                    let len = program.map_ids.len().min(input.nr_map_ids as usize);
+                    let ids: Vec<u8> = program.map_ids[..len]
+                        .iter()
+                        .flat_map(|id| id.to_ne_bytes())
+                        .collect();
+                    unsafe {
+                        write(input.map_ids, &ids);
+                    }
+                    info.map_ids = input.map_ids;
+                }
+                unsafe { write_struct(u.info, u.info_len, &info) }
+            }
+            Kind::Link(link) => {
+                let mut info = unsafe { std::mem::zeroed::<bpf_link_info>() };
+                info.type_ = link_type(link) as u32;
+                info.id = id;
+                info.prog_id = link.program;
+                unsafe { write_struct(u.info, u.info_len, &info) }
+            }
+            Kind::Btf(btf) => {
+                let mut input = unsafe { std::mem::zeroed::<bpf_btf_info>() };
+                let len = (u.info_len as usize).min(size_of::<bpf_btf_info>());
+                unsafe {
+                    ptr::copy_nonoverlapping(
+                        u.info as *const u8,
+                        ptr::from_mut(&mut input).cast::<u8>(),
+                        len,
+                    );
+                };
+                let mut info = input;
+                if input.btf != 0 {
+                    let len = btf.len().min(input.btf_size as usize);
+                    unsafe {
+                        write(input.btf, &btf[..len]);
+                    }
+                }
+                info.btf_size = btf.len() as u32;
+                info.id = id;
+                info.name_len = 0;
+                unsafe { write_struct(u.info, u.info_len, &info) }
+            }
+            Kind::PerfEvent { .. } | Kind::Other => return Err(EINVAL),
+        };
+        Ok(())
+    }
+
+    pub(super) fn perf_event_open(&mut self) -> Result<i64> {
+        self.create(c"[perf_event]", Kind::PerfEvent { program: None })
+    }
+
+    pub(super) fn perf_event_set_bpf(&mut self, fd: RawFd, prog_fd: RawFd) -> Result<()> {
+        let program = self.program_id(prog_fd as u32)?;
+        let key = self.key(fd)?;
+        match &mut self.objects.get_mut(&key).map(|object| &mut object.kind) {
+            Some(Kind::PerfEvent { program: slot }) => {
+                *slot = Some(program);
+                Ok(())
+            }
+            _ => Err(EINVAL),
+        }
+    }
+
+    /// Writes a record to a ring buffer the way `bpf_ringbuf_output` does.
+    pub(super) fn ringbuf_output(&self, key: u64, data: &[u8]) -> Result<()> {
+        const HEADER_SIZE: usize = 8;
+
+        let Object { file, kind, .. } = self.objects.get(&key).ok_or(ENOENT)?;
+        let Kind::Map(map) = kind else {
+            return Err(EINVAL);
+        };
+        if !map.is_ringbuf() {
+            return Err(EINVAL);
+        }
+        let page_size = page_size();
+        let size = map.max_entries as usize;
+        let read_pos = |offset| {
+            let mut pos = [0; size_of::<usize>()];
+            file.read_exact_at(&mut pos, offset as u64)
+                .map(|()| usize::from_ne_bytes(pos))
+                .map_err(|e| errno(&e))
+        };
+        let consumer = read_pos(0)?;
+        let producer = read_pos(page_size)?;
+        let len = (HEADER_SIZE + data.len()).next_multiple_of(8);
+        if producer.wrapping_sub(consumer) + len > size {
+            return Err(EAGAIN);
+        }
+
+        let mut record = Vec::with_capacity(len);
+        record.extend((data.len() as u32).to_ne_bytes());
+        record.extend(0u32.to_ne_bytes());
+        record.extend(data);
+        record.resize(len, 0);
+        // The data pages follow the producer page, and are mapped twice.
+        let offset = producer & (size - 1);
+        let (head, tail) = record.split_at(record.len().min(size - offset));
+        for base in [2 * page_size, 2 * page_size + size] {
+            file.write_all_at(head, (base + offset) as u64)
+                .and_then(|()| file.write_all_at(tail, base as u64))
+                .map_err(|e| errno(&e))?;
+        }
+        file.write_all_at(&(producer + len).to_ne_bytes(), page_size as u64)
+            .map_err(|e| errno(&e))
+    }
+}
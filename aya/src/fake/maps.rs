@@ -0,0 +1,485 @@
+//! Storage of fake maps.
+
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+use aya_obj::generated::{BPF_EXIST, BPF_F_LOCK, BPF_F_NO_PREALLOC, BPF_NOEXIST, bpf_map_type};
+use libc::{E2BIG, EEXIST, EINVAL, ENOENT, EOPNOTSUPP};
+
+use crate::util::page_size;
+
+enum Storage {
+    /// Preallocated arrays, indexed by a `u32`.
+    Array(Vec<u8>),
+    /// Hash maps, and arrays of file descriptors whose slots can be empty.
+    Hash {
+        entries: BTreeMap<Vec<u8>, (Vec<u8>, u64)>,
+        indexed: bool,
+        lru: bool,
+        clock: u64,
+    },
+    /// Longest prefix match tries, keyed by the masked key.
+    LpmTrie(BTreeMap<Vec<u8>, Vec<u8>>),
+    Queue {
+        values: VecDeque<Vec<u8>>,
+        lifo: bool,
+    },
+    BloomFilter(BTreeSet<Vec<u8>>),
+    RingBuf,
+}
+
+/// The state of a fake map.
+///
+/// Keys and values are in the layout of the `bpf(2)` syscall: the values of per-CPU maps hold
+/// one value per possible CPU, each padded to 8 bytes.
+pub(super) struct MapState {
+    pub(super) map_type: u32,
+    pub(super) name: String,
+    pub(super) key_size: u32,
+    pub(super) value_size: u32,
+    pub(super) max_entries: u32,
+    pub(super) map_flags: u32,
+    pub(super) map_extra: u64,
+    pub(super) btf_id: u32,
+    pub(super) btf_key_type_id: u32,
+    pub(super) btf_value_type_id: u32,
+    pub(super) frozen: bool,
+    /// The size of values as seen by userspace.
+    stride: usize,
+    storage: Storage,
+}
+
+pub(super) struct MapDef {
+    pub(super) map_type: u32,
+    pub(super) name: String,
+    pub(super) key_size: u32,
+    pub(super) value_size: u32,
+    pub(super) max_entries: u32,
+    pub(super) map_flags: u32,
+    pub(super) map_extra: u64,
+    pub(super) btf_id: u32,
+    pub(super) btf_key_type_id: u32,
+    pub(super) btf_value_type_id: u32,
+}
+
+impl MapState {
+    /// Validates `def` the way the kernel does and creates an empty map.
+    pub(super) fn new(def: MapDef, nr_cpus: usize) -> Result<Self, i32> {
+        let MapDef {
+            map_type,
+            name,
+            key_size,
+            value_size,
+            max_entries,
+            map_flags,
+            map_extra,
+            btf_id,
+            btf_key_type_id,
+            btf_value_type_id,
+        } = def;
+        let ty = bpf_map_type::try_from(map_type).map_err(|_unknown| EINVAL)?;
+        let percpu = matches!(
+            ty,
+            bpf_map_type::BPF_MAP_TYPE_PERCPU_HASH
+                | bpf_map_type::BPF_MAP_TYPE_LRU_PERCPU_HASH
+                | bpf_map_type::BPF_MAP_TYPE_PERCPU_ARRAY
+                | bpf_map_type::BPF_MAP_TYPE_PERCPU_CGROUP_STORAGE
+        );
+        let stride = if percpu {
+            (value_size as usize).next_multiple_of(8) * nr_cpus
+        } else {
+            value_size as usize
+        };
+        let valid = |valid: bool| if valid { Ok(()) } else { Err(EINVAL) };
+        let hash = |lru| Storage::Hash {
+            entries: BTreeMap::new(),
+            indexed: false,
+            lru,
+            clock: 0,
+        };
+        let storage = match ty {
+            bpf_map_type::BPF_MAP_TYPE_ARRAY | bpf_map_type::BPF_MAP_TYPE_PERCPU_ARRAY => {
+                valid(key_size == 4 && value_size > 0 && max_entries > 0)?;
+                Storage::Array(vec![0; max_entries as usize * stride])
+            }
+            bpf_map_type::BPF_MAP_TYPE_HASH | bpf_map_type::BPF_MAP_TYPE_PERCPU_HASH => {
+                valid(key_size > 0 && value_size > 0 && max_entries > 0)?;
+                hash(false)
+            }
+            bpf_map_type::BPF_MAP_TYPE_LRU_HASH | bpf_map_type::BPF_MAP_TYPE_LRU_PERCPU_HASH => {
+                valid(key_size > 0 && value_size > 0 && max_entries > 0)?;
+                hash(true)
+            }
+            bpf_map_type::BPF_MAP_TYPE_PROG_ARRAY
+            | bpf_map_type::BPF_MAP_TYPE_PERF_EVENT_ARRAY
+            | bpf_map_type::BPF_MAP_TYPE_CGROUP_ARRAY
+            | bpf_map_type::BPF_MAP_TYPE_ARRAY_OF_MAPS
+            | bpf_map_type::BPF_MAP_TYPE_DEVMAP
+            | bpf_map_type::BPF_MAP_TYPE_CPUMAP
+            | bpf_map_type::BPF_MAP_TYPE_XSKMAP
+            | bpf_map_type::BPF_MAP_TYPE_SOCKMAP => {
+                valid(key_size == 4 && value_size > 0 && max_entries > 0)?;
+                Storage::Hash {
+                    entries: BTreeMap::new(),
+                    indexed: true,
+                    lru: false,
+                    clock: 0,
+                }
+            }
+            bpf_map_type::BPF_MAP_TYPE_LPM_TRIE => {
+                valid(
+                    key_size > 4
+                        && value_size > 0
+                        && max_entries > 0
+                        && map_flags & BPF_F_NO_PREALLOC != 0,
+                )?;
+                Storage::LpmTrie(BTreeMap::new())
+            }
+            bpf_map_type::BPF_MAP_TYPE_QUEUE | bpf_map_type::BPF_MAP_TYPE_STACK => {
+                valid(key_size == 0 && value_size > 0 && max_entries > 0)?;
+                Storage::Queue {
+                    values: VecDeque::new(),
+                    lifo: ty == bpf_map_type::BPF_MAP_TYPE_STACK,
+                }
+            }
+            bpf_map_type::BPF_MAP_TYPE_BLOOM_FILTER => {
+                valid(key_size == 0 && value_size > 0 && max_entries > 0)?;
+                Storage::BloomFilter(BTreeSet::new())
+            }
+            bpf_map_type::BPF_MAP_TYPE_RINGBUF => {
+                valid(
+                    key_size == 0
+                        && value_size == 0
+                        && max_entries.is_power_of_two()
+                        && max_entries as usize >= page_size(),
+                )?;
+                Storage::RingBuf
+            }
+            _ => {
+                valid(max_entries > 0)?;
+                hash(false)
+            }
+        };
+        Ok(Self {
+            map_type,
+            name,
+            key_size,
+            value_size,
+            max_entries,
+            map_flags,
+            map_extra,
+            btf_id,
+            btf_key_type_id,
+            btf_value_type_id,
+            frozen: false,
+            stride,
+            storage,
+        })
+    }
+
+    pub(super) const fn key_size(&self) -> usize {
+        self.key_size as usize
+    }
+
+    /// Returns the size of the values exchanged with userspace.
+    pub(super) const fn value_size(&self) -> usize {
+        self.stride
+    }
+
+    pub(super) const fn is_ringbuf(&self) -> bool {
+        matches!(self.storage, Storage::RingBuf)
+    }
+
+    /// Returns whether the map is keyless, with values pushed and popped.
+    pub(super) const fn is_keyless(&self) -> bool {
+        matches!(
+            self.storage,
+            Storage::Queue { .. } | Storage::BloomFilter(_)
+        )
+    }
+
+    fn index(&self, key: &[u8]) -> Result<usize, i32> {
+        let index = u32::from_ne_bytes(key.try_into().map_err(|_len| EINVAL)?);
+        if index < self.max_entries {
+            Ok(index as usize)
+        } else {
+            Err(ENOENT)
+        }
+    }
+
+    pub(super) fn lookup(&mut self, key: &[u8]) -> Result<Vec<u8>, i32> {
+        let stride = self.stride;
+        let index = matches!(self.storage, Storage::Array(_))
+            .then(|| self.index(key))
+            .transpose()?;
+        match &mut self.storage {
+            Storage::Array(values) => {
+                let offset = index.unwrap_or_default() * stride;
+                Ok(values[offset..offset + stride].to_vec())
+            }
+            Storage::Hash {
+                entries,
+                lru,
+                clock,
+                ..
+            } => {
+                let (value, used) = entries.get_mut(key).ok_or(ENOENT)?;
+                if *lru {
+                    *clock += 1;
+                    *used = *clock;
+                }
+                Ok(value.clone())
+            }
+            Storage::LpmTrie(entries) => {
+                let (prefix_len, data) = split_lpm_key(key)?;
+                entries
+                    .iter()
+                    .filter_map(|(entry, value)| {
+                        let (entry_len, entry_data) = split_lpm_key(entry).ok()?;
+                        (entry_len <= prefix_len && prefix_matches(entry_data, data, entry_len))
+                            .then_some((entry_len, value))
+                    })
+                    .max_by_key(|(entry_len, _)| *entry_len)
+                    .map(|(_, value)| value.clone())
+                    .ok_or(ENOENT)
+            }
+            Storage::Queue { .. } | Storage::BloomFilter(_) | Storage::RingBuf => Err(EINVAL),
+        }
+    }
+
+    pub(super) fn update(&mut self, key: &[u8], value: &[u8], flags: u64) -> Result<(), i32> {
+        let flags = flags & !u64::from(BPF_F_LOCK);
+        if flags > u64::from(BPF_EXIST) || value.len() != self.stride {
+            return Err(EINVAL);
+        }
+        let max_entries = self.max_entries as usize;
+        match &mut self.storage {
+            Storage::Array(values) => {
+                if flags == u64::from(BPF_NOEXIST) {
+                    return Err(EEXIST);
+                }
+                let index = u32::from_ne_bytes(key.try_into().map_err(|_len| EINVAL)?) as usize;
+                if index >= max_entries {
+                    return Err(E2BIG);
+                }
+                values[index * value.len()..][..value.len()].copy_from_slice(value);
+                Ok(())
+            }
+            Storage::Hash {
+                entries,
+                indexed,
+                lru,
+                clock,
+            } => {
+                if *indexed {
+                    let index = u32::from_ne_bytes(key.try_into().map_err(|_len| EINVAL)?);
+                    if index as usize >= max_entries {
+                        return Err(E2BIG);
+                    }
+                }
+                *clock += 1;
+                let exists = entries.contains_key(key);
+                match (exists, flags) {
+                    (true, f) if f == u64::from(BPF_NOEXIST) => return Err(EEXIST),
+                    (false, f) if f == u64::from(BPF_EXIST) => return Err(ENOENT),
+                    (false, _) if entries.len() >= max_entries => {
+                        if !*lru {
+                            return Err(E2BIG);
+                        }
+                        // Evict the least recently used entry.
+                        let oldest = entries
+                            .iter()
+                            .min_by_key(|(_, (_, used))| *used)
+                            .map(|(key, _)| key.clone());
+                        if let Some(oldest) = oldest {
+                            let _unused: Option<(Vec<u8>, u64)> = entries.remove(&oldest);
+                        }
+                    }
+                    _ => {}
+                }
+                let _unused: Option<(Vec<u8>, u64)> =
+                    entries.insert(key.to_vec(), (value.to_vec(), *clock));
+                Ok(())
+            }
+            Storage::LpmTrie(entries) => {
+                let key = lpm_key(key)?;
+                let exists = entries.contains_key(&key);
+                match (exists, flags) {
+                    (true, f) if f == u64::from(BPF_NOEXIST) => return Err(EEXIST),
+                    (false, f) if f == u64::from(BPF_EXIST) => return Err(ENOENT),
+                    (false, _) if entries.len() >= max_entries => return Err(ENOSPC),
+                    _ => {}
+                }
+                let _unused: Option<Vec<u8>> = entries.insert(key, value.to_vec());
+                Ok(())
+            }
+            Storage::Queue { .. } | Storage::BloomFilter(_) => self.push(value, flags),
+            Storage::RingBuf => Err(EINVAL),
+        }
+    }
+
+    pub(super) fn delete(&mut self, key: &[u8]) -> Result<(), i32> {
+        match &mut self.storage {
+            Storage::Hash { entries, .. } => entries.remove(key).map(drop).ok_or(ENOENT),
+            Storage::LpmTrie(entries) => entries.remove(&lpm_key(key)?).map(drop).ok_or(ENOENT),
+            Storage::Array(_)
+            | Storage::Queue { .. }
+            | Storage::BloomFilter(_)
+            | Storage::RingBuf => Err(EINVAL),
+        }
+    }
+
+    pub(super) fn lookup_and_delete(&mut self, key: &[u8]) -> Result<Vec<u8>, i32> {
+        match &mut self.storage {
+            Storage::Hash { entries, .. } => entries
+                .remove(key)
+                .map(|(value, _used)| value)
+                .ok_or(ENOENT),
+            Storage::Queue { .. } => self.pop(),
+            Storage::Array(_)
+            | Storage::LpmTrie(_)
+            | Storage::BloomFilter(_)
+            | Storage::RingBuf => Err(EOPNOTSUPP),
+        }
+    }
+
+    /// Returns the key following `key`, or the first key if `key` is `None` or isn't in the map.
+    pub(super) fn next_key(&self, key: Option<&[u8]>) -> Result<Vec<u8>, i32> {
+        fn next<V>(entries: &BTreeMap<Vec<u8>, V>, key: Option<Vec<u8>>) -> Option<Vec<u8>> {
+            match key {
+                Some(key) if entries.contains_key(&key) => entries
+                    .range::<Vec<u8>, _>((
+                        std::ops::Bound::Excluded(&key),
+                        std::ops::Bound::Unbounded,
+                    ))
+                    .next()
+                    .map(|(key, _)| key.clone()),
+                _ => entries.keys().next().cloned(),
+            }
+        }
+
+        let next = match &self.storage {
+            Storage::Array(_) => {
+                let next = match key.map(|key| self.index(key)) {
+                    Some(Ok(index)) => index + 1,
+                    Some(Err(ENOENT)) | None => 0,
+                    Some(Err(errno)) => return Err(errno),
+                };
+                (next < self.max_entries as usize).then(|| (next as u32).to_ne_bytes().to_vec())
+            }
+            Storage::Hash { entries, .. } => next(entries, key.map(<[u8]>::to_vec)),
+            Storage::LpmTrie(entries) => next(entries, key.map(lpm_key).transpose()?),
+            Storage::Queue { .. } | Storage::BloomFilter(_) | Storage::RingBuf => {
+                return Err(EINVAL);
+            }
+        };
+        next.ok_or(ENOENT)
+    }
+
+    pub(super) fn push(&mut self, value: &[u8], flags: u64) -> Result<(), i32> {
+        if value.len() != self.stride {
+            return Err(EINVAL);
+        }
+        let max_entries = self.max_entries as usize;
+        match &mut self.storage {
+            Storage::Queue { values, .. } => {
+                if values.len() >= max_entries {
+                    if flags != u64::from(BPF_EXIST) {
+                        return Err(E2BIG);
+                    }
+                    // BPF_EXIST makes room by dropping the oldest value.
+                    let _unused: Option<Vec<u8>> = values.pop_front();
+                }
+                values.push_back(value.to_vec());
+                Ok(())
+            }
+            Storage::BloomFilter(values) => {
+                let _unused: bool = values.insert(value.to_vec());
+                Ok(())
+            }
+            Storage::Array(_) | Storage::Hash { .. } | Storage::LpmTrie(_) | Storage::RingBuf => {
+                Err(EINVAL)
+            }
+        }
+    }
+
+    /// Returns the next value of a queue or a stack, or checks whether a bloom filter contains
+    /// `value`.
+    pub(super) fn peek(&self, value: &[u8]) -> Result<Vec<u8>, i32> {
+        match &self.storage {
+            Storage::Queue { values, lifo } => if *lifo { values.back() } else { values.front() }
+                .cloned()
+                .ok_or(ENOENT),
+            Storage::BloomFilter(values) => {
+                values.contains(value).then(|| value.to_vec()).ok_or(ENOENT)
+            }
+            Storage::Array(_) | Storage::Hash { .. } | Storage::LpmTrie(_) | Storage::RingBuf => {
+                Err(EINVAL)
+            }
+        }
+    }
+
+    pub(super) fn pop(&mut self) -> Result<Vec<u8>, i32> {
+        match &mut self.storage {
+            Storage::Queue { values, lifo } => if *lifo {
+                values.pop_back()
+            } else {
+                values.pop_front()
+            }
+            .ok_or(ENOENT),
+            Storage::Array(_)
+            | Storage::Hash { .. }
+            | Storage::LpmTrie(_)
+            | Storage::BloomFilter(_)
+            | Storage::RingBuf => Err(EINVAL),
+        }
+    }
+
+    /// Returns the values of the map, used to find the objects referenced by maps of file
+    /// descriptors.
+    pub(super) fn values(&self) -> Vec<&[u8]> {
+        match &self.storage {
+            Storage::Hash { entries, .. } => entries
+                .values()
+                .map(|(value, _used)| value.as_slice())
+                .collect(),
+            Storage::Array(_)
+            | Storage::LpmTrie(_)
+            | Storage::Queue { .. }
+            | Storage::BloomFilter(_)
+            | Storage::RingBuf => Vec::new(),
+        }
+    }
+}
+
+const ENOSPC: i32 = libc::ENOSPC;
+
+fn split_lpm_key(key: &[u8]) -> Result<(u32, &[u8]), i32> {
+    let (prefix_len, data) = key.split_first_chunk::<4>().ok_or(EINVAL)?;
+    Ok((u32::from_ne_bytes(*prefix_len), data))
+}
+
+/// Returns `key` with the bits past its prefix cleared, so that equivalent keys compare equal.
+fn lpm_key(key: &[u8]) -> Result<Vec<u8>, i32> {
+    let (prefix_len, data) = split_lpm_key(key)?;
+    if prefix_len as usize > data.len() * 8 {
+        return Err(EINVAL);
+    }
+    let mut key = key.to_vec();
+    for (i, byte) in key[4..].iter_mut().enumerate() {
+        let bits = (prefix_len as usize).saturating_sub(i * 8).min(8);
+        *byte &= !(0xffu8.checked_shr(bits as u32).unwrap_or_default());
+    }
+    Ok(key)
+}
+
+fn prefix_matches(prefix: &[u8], data: &[u8], prefix_len: u32) -> bool {
+    let prefix_len = prefix_len as usize;
+    let (bytes, bits) = (prefix_len / 8, prefix_len % 8);
+    if prefix[..bytes] != data[..bytes] {
+        return false;
+    }
+    bits == 0 || {
+        let mask = !(0xffu8 >> bits);
+        prefix[bytes] & mask == data[bytes] & mask
+    }
+}
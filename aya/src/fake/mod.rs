@@ -0,0 +1,826 @@
+//! A fake BPF backend to test userspace code without privileges.
+//!
+//! A [`FakeKernel`] installed on a thread handles the `bpf(2)` and `perf_event_open(2)` calls
+//! aya makes from that thread. [`Ebpf`](crate::Ebpf), [`EbpfLoader`](crate::EbpfLoader),
+//! programs and all the [`maps`](crate::maps) types then work as usual, against in-memory
+//! objects:
+//!
+//! - maps honor the semantics of their type: hash maps (including LRU eviction), arrays,
+//!   per-CPU maps, LPM tries, queues, stacks, bloom filters and ring buffers. Other types are
+//!   stored as plain hash maps.
+//! - programs are recorded instead of verified, and can't run. Use [`FakeMap`] to emulate
+//!   what they would do to maps.
+//! - attachments made with links, `BPF_PROG_ATTACH` and perf events are recorded, and can be
+//!   inspected with [`FakeKernel::attachments`].
+//! - pinned objects are only visible to the fake kernel, not in the filesystem.
+//!
+//! Netlink requests, such as netlink-based TC and XDP attachments, and probes created through
+//! tracefs fail with [`io::ErrorKind::Unsupported`] instead of reaching the running kernel.
+//! Socket filters are attached with `setsockopt(2)`, which the running kernel rejects.
+//!
+//! Objects are backed by memfds, so file descriptors, `mmap` and polling behave like they do
+//! for real objects. Like in the kernel, objects are freed once no file descriptor, pin, or
+//! other object refers to them.
+//!
+//! Feature detection runs once per process, on first use: install a fake kernel before
+//! loading anything so that all features are detected as supported.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use aya::{Ebpf, fake::FakeKernel, maps::HashMap};
+//!
+//! let kernel = FakeKernel::new();
+//! let _guard = kernel.install();
+//!
+//! let mut bpf = Ebpf::load_file("ebpf_programs.o")?;
+//! let mut counts: HashMap<_, u32, u64> = HashMap::try_from(bpf.map_mut("COUNTS").unwrap())?;
+//! counts.insert(1, 10, 0)?;
+//!
+//! // Emulate the program incrementing the counter.
+//! let map = kernel.map("COUNTS").unwrap();
+//! map.insert(&1u32.to_ne_bytes(), &11u64.to_ne_bytes())?;
+//! assert_eq!(counts.get(&1, 0)?, 11);
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+mod kernel;
+mod maps;
+
+use std::{
+    cell::RefCell,
+    collections::BTreeSet,
+    ffi::{CString, c_int, c_void},
+    io,
+    marker::PhantomData,
+    os::fd::{AsRawFd as _, BorrowedFd},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use aya_obj::generated::{bpf_attach_type, bpf_insn, bpf_map_type, bpf_prog_type};
+
+use crate::{
+    maps::{MapError, MapType},
+    sys::{ObjectKind, PerfEventIoctlRequest, SysResult, Syscall},
+};
+
+thread_local! {
+    static CURRENT: RefCell<Option<FakeKernel>> = const { RefCell::new(None) };
+}
+
+/// The addresses of the mappings of fake objects.
+///
+/// Mappings can outlive the fake kernel installed on the thread which created them, so they are
+/// tracked process wide.
+static MAPPINGS: Mutex<BTreeSet<usize>> = Mutex::new(BTreeSet::new());
+
+fn mappings() -> MutexGuard<'static, BTreeSet<usize>> {
+    MAPPINGS
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+/// An in-memory implementation of the BPF syscalls.
+///
+/// See the [module documentation](self) for details.
+#[derive(Clone, Default)]
+pub struct FakeKernel {
+    kernel: Arc<Mutex<kernel::Kernel>>,
+}
+
+impl std::fmt::Debug for FakeKernel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FakeKernel").finish_non_exhaustive()
+    }
+}
+
+/// Restores the previously installed kernel of the thread when dropped.
+///
+/// Returned by [`FakeKernel::install`].
+#[must_use = "the fake kernel is uninstalled when the guard is dropped"]
+#[derive(Debug)]
+pub struct FakeKernelGuard {
+    previous: Option<FakeKernel>,
+    // The guard restores the state of the thread that created it.
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Drop for FakeKernelGuard {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        CURRENT.with(|current| *current.borrow_mut() = previous);
+    }
+}
+
+impl FakeKernel {
+    /// Creates a fake kernel without any object.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Makes the calling thread use this kernel until the returned guard is dropped.
+    ///
+    /// The kernel can be installed on several threads at once, for instance to consume a ring
+    /// buffer from another thread.
+    pub fn install(&self) -> FakeKernelGuard {
+        let previous = CURRENT.with(|current| current.borrow_mut().replace(self.clone()));
+        FakeKernelGuard {
+            previous,
+            _not_send: PhantomData,
+        }
+    }
+
+    pub(crate) fn current() -> Option<Self> {
+        CURRENT.with(|current| current.borrow().clone())
+    }
+
+    /// Fails with [`io::ErrorKind::Unsupported`] if a fake kernel is installed on the calling
+    /// thread, for the operations which would otherwise reach the running kernel.
+    pub(crate) fn check_supported(operation: &str) -> io::Result<()> {
+        match Self::current() {
+            Some(Self { .. }) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("{operation} isn't supported by the fake kernel"),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, kernel::Kernel> {
+        // The state stays consistent even if a thread panicked while holding the lock.
+        self.kernel
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    /// Locks the kernel after dropping the objects nothing refers to anymore.
+    fn collected(&self) -> MutexGuard<'_, kernel::Kernel> {
+        let mut kernel = self.lock();
+        kernel.collect();
+        kernel
+    }
+
+    pub(crate) fn syscall(&self, call: Syscall<'_>) -> SysResult {
+        let mut kernel = self.lock();
+        let result = match call {
+            Syscall::Ebpf { cmd, attr } => kernel.bpf(cmd, attr),
+            Syscall::PerfEventOpen { .. } => kernel.perf_event_open(),
+            Syscall::PerfEventIoctl { fd, request } => {
+                if kernel.is_object(fd.as_raw_fd()) {
+                    match request {
+                        PerfEventIoctlRequest::Enable | PerfEventIoctlRequest::Disable => Ok(0),
+                        PerfEventIoctlRequest::SetBpf(prog_fd) => kernel
+                            .perf_event_set_bpf(fd.as_raw_fd(), prog_fd.as_raw_fd())
+                            .map(|()| 0),
+                    }
+                } else {
+                    Err(libc::ENOTTY)
+                }
+            }
+        };
+        result.map_err(|errno| (-1, io::Error::from_raw_os_error(errno)))
+    }
+
+    /// Maps the memfd backing a fake object, growing it as needed.
+    ///
+    /// Returns `None` if `fd` isn't a fake object.
+    pub(crate) unsafe fn mmap(
+        &self,
+        addr: *mut c_void,
+        len: usize,
+        prot: c_int,
+        flags: c_int,
+        fd: BorrowedFd<'_>,
+        offset: libc::off_t,
+    ) -> Option<*mut c_void> {
+        let end = offset as u64 + len as u64;
+        let kernel = self.lock();
+        let file = kernel.file(fd.as_raw_fd())?;
+        let grown = file.metadata().is_ok_and(|metadata| metadata.len() >= end)
+            || file.set_len(end).is_ok();
+        drop(kernel);
+        if !grown {
+            return Some(libc::MAP_FAILED);
+        }
+        let ptr = unsafe { libc::mmap(addr, len, prot, flags, fd.as_raw_fd(), offset) };
+        if ptr != libc::MAP_FAILED {
+            mappings().insert(ptr as usize);
+        }
+        Some(ptr)
+    }
+
+    /// Returns the kind of the fake object `fd` refers to.
+    pub(crate) fn object_kind(&self, fd: BorrowedFd<'_>) -> Option<ObjectKind> {
+        match self.lock().kind(fd.as_raw_fd())? {
+            kernel::Kind::Map(_) => Some(ObjectKind::Map),
+            kernel::Kind::Program(_) => Some(ObjectKind::Program),
+            kernel::Kind::Link(_) => Some(ObjectKind::Link),
+            kernel::Kind::Btf(_) | kernel::Kind::PerfEvent { .. } | kernel::Kind::Other => None,
+        }
+    }
+
+    /// Unmaps a mapping created by [`FakeKernel::mmap`].
+    ///
+    /// Returns `None` if `addr` isn't the address of such a mapping.
+    pub(crate) unsafe fn munmap(addr: *mut c_void, len: usize) -> Option<c_int> {
+        mappings()
+            .remove(&(addr as usize))
+            .then(|| unsafe { libc::munmap(addr, len) })
+    }
+
+    /// Returns the most recently created live map named `name`.
+    ///
+    /// Like in the kernel, map names are truncated to 15 bytes.
+    pub fn map(&self, name: &str) -> Option<FakeMap> {
+        self.maps()
+            .into_iter()
+            .rev()
+            .find(|map| name.starts_with(&map.name) && map.name.len() == name.len().min(15))
+    }
+
+    /// Returns the live maps, in creation order.
+    pub fn maps(&self) -> Vec<FakeMap> {
+        let mut maps: Vec<FakeMap> = self
+            .collected()
+            .objects
+            .iter()
+            .filter_map(|(key, object)| match &object.kind {
+                kernel::Kind::Map(map) => Some(FakeMap {
+                    kernel: self.clone(),
+                    key: *key,
+                    id: object.id,
+                    name: map.name.clone(),
+                    map_type: map.map_type,
+                }),
+                _ => None,
+            })
+            .collect();
+        maps.sort_by_key(|map| map.id);
+        maps
+    }
+
+    /// Returns the live programs, in load order.
+    pub fn programs(&self) -> Vec<FakeProgram> {
+        let mut programs: Vec<FakeProgram> = self
+            .collected()
+            .objects
+            .values()
+            .filter_map(|object| match &object.kind {
+                kernel::Kind::Program(program) => Some(FakeProgram {
+                    id: object.id,
+                    name: program.name.clone(),
+                    program_type: program.prog_type,
+                    expected_attach_type: program.expected_attach_type,
+                    license: program.license.clone(),
+                    instructions: program.instructions.clone(),
+                    map_ids: program.map_ids.clone(),
+                }),
+                _ => None,
+            })
+            .collect();
+        programs.sort_by_key(|program| program.id);
+        programs
+    }
+
+    /// Returns the most recently loaded live program named `name`.
+    ///
+    /// Like in the kernel, program names are truncated to 15 bytes.
+    pub fn program(&self, name: &str) -> Option<FakeProgram> {
+        self.programs().into_iter().rev().find(|program| {
+            name.starts_with(&program.name) && program.name.len() == name.len().min(15)
+        })
+    }
+
+    /// Returns the programs currently attached.
+    pub fn attachments(&self) -> Vec<FakeAttachment> {
+        let kernel = self.collected();
+        let mut attachments: Vec<FakeAttachment> = kernel
+            .attachments
+            .iter()
+            .map(|attachment| FakeAttachment {
+                program_id: attachment.program,
+                attach_type: bpf_attach_type::try_from(attachment.attach_type).ok(),
+                target: attachment.target.clone(),
+                link_id: None,
+            })
+            .collect();
+        let mut links: Vec<FakeAttachment> = kernel
+            .objects
+            .values()
+            .filter_map(|object| match &object.kind {
+                kernel::Kind::Link(link) if !link.detached => Some(FakeAttachment {
+                    program_id: link.program,
+                    attach_type: link
+                        .attach_type
+                        .and_then(|attach_type| bpf_attach_type::try_from(attach_type).ok()),
+                    target: link.target.clone(),
+                    link_id: Some(object.id),
+                }),
+                kernel::Kind::PerfEvent {
+                    program: Some(program),
+                } => Some(FakeAttachment {
+                    program_id: *program,
+                    attach_type: None,
+                    target: AttachTarget::PerfEvent,
+                    link_id: None,
+                }),
+                _ => None,
+            })
+            .collect();
+        drop(kernel);
+        links.sort_by_key(|attachment| attachment.link_id);
+        attachments.extend(links);
+        attachments
+    }
+}
+
+/// A map of a [`FakeKernel`].
+///
+/// Its methods act on the map the way programs do: they ignore whether the map is frozen, and
+/// the values of per-CPU maps are the values of all the possible CPUs, each padded to 8 bytes.
+#[derive(Debug)]
+pub struct FakeMap {
+    kernel: FakeKernel,
+    key: u64,
+    id: u32,
+    name: String,
+    map_type: u32,
+}
+
+impl FakeMap {
+    /// Returns the ID of the map.
+    pub const fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Returns the name of the map, truncated to 15 bytes.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the type of the map.
+    pub fn map_type(&self) -> Result<MapType, MapError> {
+        bpf_map_type::try_from(self.map_type)
+            .unwrap_or(bpf_map_type::__MAX_BPF_MAP_TYPE)
+            .try_into()
+    }
+
+    fn with<T>(
+        &self,
+        f: impl FnOnce(&mut kernel::Kernel, u64) -> Result<T, i32>,
+    ) -> Result<T, io::Error> {
+        f(&mut self.kernel.lock(), self.key).map_err(io::Error::from_raw_os_error)
+    }
+
+    /// Returns the value of `key`, or `None` if there is none.
+    ///
+    /// Like `bpf_map_lookup_elem`, looking up an LPM trie returns the value of the longest
+    /// matching prefix.
+    pub fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.with(|kernel, map| kernel.map(map)?.lookup(key)).ok()
+    }
+
+    /// Sets the value of `key`, like `bpf_map_update_elem` with `BPF_ANY`.
+    pub fn insert(&self, key: &[u8], value: &[u8]) -> Result<(), io::Error> {
+        self.with(|kernel, map| kernel.map(map)?.update(key, value, 0))
+    }
+
+    /// Removes `key`, like `bpf_map_delete_elem`.
+    pub fn remove(&self, key: &[u8]) -> Result<(), io::Error> {
+        self.with(|kernel, map| kernel.map(map)?.delete(key))
+    }
+
+    /// Returns the keys of the map, in iteration order.
+    pub fn keys(&self) -> Vec<Vec<u8>> {
+        let mut keys = Vec::new();
+        let _unused: Result<(), io::Error> = self.with(|kernel, map| {
+            let map = kernel.map(map)?;
+            let mut key = None;
+            while let Ok(next) = map.next_key(key.as_deref()) {
+                keys.push(next.clone());
+                key = Some(next);
+            }
+            Ok(())
+        });
+        keys
+    }
+
+    /// Pushes `value` to a queue, a stack or a bloom filter, like `bpf_map_push_elem`.
+    pub fn push(&self, value: &[u8]) -> Result<(), io::Error> {
+        self.with(|kernel, map| kernel.map(map)?.push(value, 0))
+    }
+
+    /// Pops the next value of a queue or a stack, like `bpf_map_pop_elem`.
+    pub fn pop(&self) -> Option<Vec<u8>> {
+        self.with(|kernel, map| kernel.map(map)?.pop()).ok()
+    }
+
+    /// Writes `data` to a ring buffer, like `bpf_ringbuf_output`.
+    ///
+    /// Fails with `EAGAIN` if the ring buffer is full.
+    pub fn output(&self, data: &[u8]) -> Result<(), io::Error> {
+        self.with(|kernel, map| kernel.ringbuf_output(map, data))
+    }
+}
+
+/// A program loaded into a [`FakeKernel`].
+#[derive(Clone, Debug)]
+pub struct FakeProgram {
+    id: u32,
+    name: String,
+    program_type: u32,
+    expected_attach_type: u32,
+    license: CString,
+    instructions: Vec<bpf_insn>,
+    map_ids: Vec<u32>,
+}
+
+impl FakeProgram {
+    /// Returns the ID of the program.
+    pub const fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Returns the name of the program, truncated to 15 bytes.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the type of the program.
+    pub fn program_type(&self) -> bpf_prog_type {
+        bpf_prog_type::try_from(self.program_type).unwrap_or(bpf_prog_type::__MAX_BPF_PROG_TYPE)
+    }
+
+    /// Returns the expected attach type the program was loaded with.
+    pub fn expected_attach_type(&self) -> Option<bpf_attach_type> {
+        bpf_attach_type::try_from(self.expected_attach_type).ok()
+    }
+
+    /// Returns the license of the program.
+    pub const fn license(&self) -> &CString {
+        &self.license
+    }
+
+    /// Returns the instructions of the program, as loaded.
+    pub fn instructions(&self) -> &[bpf_insn] {
+        &self.instructions
+    }
+
+    /// Returns the IDs of the maps the program refers to.
+    pub fn map_ids(&self) -> &[u32] {
+        &self.map_ids
+    }
+}
+
+/// What a program is attached to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AttachTarget {
+    /// No target, for instance for tracing programs.
+    None,
+    /// A network interface.
+    IfIndex(u32),
+    /// A file, such as a cgroup or a network namespace.
+    File {
+        /// The device of the file.
+        dev: u64,
+        /// The inode of the file.
+        ino: u64,
+    },
+    /// A map, for instance a sockmap.
+    Map(u32),
+    /// A program, for extension programs.
+    Program(u32),
+    /// A perf event, for probes, tracepoints and perf event programs.
+    PerfEvent,
+    /// A raw tracepoint, or the tracepoint of the BTF ID the program was loaded with.
+    RawTracePoint(Option<String>),
+}
+
+/// An attachment of a program of a [`FakeKernel`].
+#[derive(Clone, Debug)]
+pub struct FakeAttachment {
+    program_id: u32,
+    attach_type: Option<bpf_attach_type>,
+    target: AttachTarget,
+    link_id: Option<u32>,
+}
+
+impl FakeAttachment {
+    /// Returns the ID of the attached program.
+    pub const fn program_id(&self) -> u32 {
+        self.program_id
+    }
+
+    /// Returns the attach type, if the attachment has one.
+    pub const fn attach_type(&self) -> Option<bpf_attach_type> {
+        self.attach_type
+    }
+
+    /// Returns what the program is attached to.
+    pub const fn target(&self) -> &AttachTarget {
+        &self.target
+    }
+
+    /// Returns the ID of the link holding the attachment, if any.
+    pub const fn link_id(&self) -> Option<u32> {
+        self.link_id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::fd::AsFd as _;
+
+    use assert_matches::assert_matches;
+    use aya_obj::{
+        EbpfSectionKind,
+        generated::{
+            BPF_DW, BPF_EXIT, BPF_F_NO_PREALLOC, BPF_IMM, BPF_JMP, BPF_LD, BPF_PSEUDO_MAP_FD,
+        },
+        maps::LegacyMap,
+    };
+    use libc::E2BIG;
+
+    use super::*;
+    use crate::{
+        bpf_map_def,
+        maps::{
+            HashMap, Map, MapData, MapInfo, Queue, RingBuf,
+            lpm_trie::{Key, LpmTrie},
+        },
+        programs::{
+            ProgramError, ProgramInfo,
+            links::{LinkError, LinkInfo},
+        },
+        sys::{
+            EbpfLoadProgramAttrs, LinkTarget, SyscallError, bpf_link_create, bpf_load_program,
+            mmap, munmap, netlink_list_links,
+        },
+        util::page_size,
+    };
+
+    fn map(name: &str, map_type: bpf_map_type, key_size: u32, value_size: u32) -> MapData {
+        let max_entries = if map_type == bpf_map_type::BPF_MAP_TYPE_RINGBUF {
+            page_size() as u32
+        } else {
+            2
+        };
+        let map_flags = if map_type == bpf_map_type::BPF_MAP_TYPE_LPM_TRIE {
+            BPF_F_NO_PREALLOC
+        } else {
+            0
+        };
+        let obj = aya_obj::Map::Legacy(LegacyMap {
+            def: bpf_map_def {
+                map_type: map_type as u32,
+                key_size,
+                value_size,
+                max_entries,
+                map_flags,
+                ..Default::default()
+            },
+            inner_def: None,
+            section_index: 0,
+            section_kind: EbpfSectionKind::Maps,
+            data: Vec::new(),
+            symbol_index: None,
+        });
+        MapData::create(obj, name, None).unwrap()
+    }
+
+    #[test]
+    fn test_hash_map() {
+        let kernel = FakeKernel::new();
+        let _guard = kernel.install();
+
+        let data = map("COUNTS", bpf_map_type::BPF_MAP_TYPE_HASH, 4, 8);
+        let mut counts = HashMap::<_, u32, u64>::try_from(Map::HashMap(data)).unwrap();
+        counts.insert(1, 10, 0).unwrap();
+        counts.insert(2, 20, 0).unwrap();
+        assert_matches!(
+            counts.insert(3, 30, 0),
+            Err(MapError::SyscallError(SyscallError { io_error, .. }))
+                if io_error.raw_os_error() == Some(E2BIG)
+        );
+
+        // Updates made on the program side are visible to userspace, and the other way around.
+        let fake = kernel.map("COUNTS").unwrap();
+        assert_matches!(fake.map_type(), Ok(MapType::Hash));
+        assert_eq!(
+            fake.get(&1u32.to_ne_bytes()),
+            Some(10u64.to_ne_bytes().to_vec())
+        );
+        fake.insert(&2u32.to_ne_bytes(), &21u64.to_ne_bytes())
+            .unwrap();
+        assert_eq!(counts.get(&2, 0).unwrap(), 21);
+        counts.remove(&1).unwrap();
+        assert_eq!(fake.keys(), [2u32.to_ne_bytes().to_vec()]);
+        assert_eq!(
+            counts.iter().collect::<Result<Vec<_>, _>>().unwrap(),
+            [(2, 21)]
+        );
+
+        // The map is freed once its last fd is closed.
+        drop(counts);
+        assert_matches!(kernel.map("COUNTS"), None);
+    }
+
+    #[test]
+    fn test_lpm_trie_and_queue() {
+        let kernel = FakeKernel::new();
+        let _guard = kernel.install();
+
+        let data = map("ROUTES", bpf_map_type::BPF_MAP_TYPE_LPM_TRIE, 8, 4);
+        let mut routes = LpmTrie::<_, [u8; 4], u32>::try_from(Map::LpmTrie(data)).unwrap();
+        routes.insert(&Key::new(8, [10, 0, 0, 0]), 8, 0).unwrap();
+        routes.insert(&Key::new(16, [10, 1, 0, 0]), 16, 0).unwrap();
+        assert_eq!(routes.get(&Key::new(32, [10, 1, 2, 3]), 0).unwrap(), 16);
+        assert_eq!(routes.get(&Key::new(32, [10, 2, 2, 3]), 0).unwrap(), 8);
+        assert_matches!(
+            routes.get(&Key::new(32, [11, 1, 2, 3]), 0),
+            Err(MapError::KeyNotFound)
+        );
+        assert_matches!(routes.get(&Key::new(12, [10, 1, 2, 3]), 0), Ok(8));
+
+        let data = map("EVENTS", bpf_map_type::BPF_MAP_TYPE_QUEUE, 0, 4);
+        let mut events = Queue::<_, u32>::try_from(Map::Queue(data)).unwrap();
+        events.push(1, 0).unwrap();
+        kernel
+            .map("EVENTS")
+            .unwrap()
+            .push(&2u32.to_ne_bytes())
+            .unwrap();
+        assert_matches!(events.push(3, 0), Err(MapError::SyscallError(_)));
+        assert_eq!(events.pop(0).unwrap(), 1);
+        assert_eq!(events.pop(0).unwrap(), 2);
+        assert_matches!(events.pop(0), Err(MapError::ElementNotFound));
+    }
+
+    #[test]
+    fn test_ring_buf() {
+        let kernel = FakeKernel::new();
+        let _guard = kernel.install();
+
+        let data = map("RING", bpf_map_type::BPF_MAP_TYPE_RINGBUF, 0, 0);
+        let mut ring = RingBuf::try_from(Map::RingBuf(data)).unwrap();
+        let fake = kernel.map("RING").unwrap();
+        assert!(ring.next().is_none());
+
+        // Write enough records to wrap around the end of the buffer.
+        let record = [0xab; 100];
+        for i in 0..(3 * page_size() / record.len()) {
+            fake.output(&record[..i % record.len()]).unwrap();
+            assert_eq!(&*ring.next().unwrap(), &record[..i % record.len()]);
+            assert!(ring.next().is_none());
+        }
+        while fake.output(&record).is_ok() {}
+        assert_matches!(
+            fake.output(&record),
+            Err(error) if error.raw_os_error() == Some(libc::EAGAIN)
+        );
+        assert!(ring.next().is_some());
+        fake.output(&record).unwrap();
+    }
+
+    #[test]
+    fn test_programs() {
+        let kernel = FakeKernel::new();
+        let _guard = kernel.install();
+
+        let data = map("COUNTS", bpf_map_type::BPF_MAP_TYPE_ARRAY, 4, 4);
+        let insn = |code: u32, src, imm| {
+            let mut insn = unsafe { std::mem::zeroed::<bpf_insn>() };
+            insn.code = code as u8;
+            insn.set_src_reg(src);
+            insn.imm = imm;
+            insn
+        };
+        let instructions = [
+            insn(
+                BPF_LD | BPF_IMM | BPF_DW,
+                BPF_PSEUDO_MAP_FD as u8,
+                data.fd().as_fd().as_raw_fd(),
+            ),
+            insn(0, 0, 0),
+            insn(BPF_JMP | BPF_EXIT, 0, 0),
+        ];
+        let attrs = EbpfLoadProgramAttrs {
+            name: Some(CString::new("pass").unwrap()),
+            ty: bpf_prog_type::BPF_PROG_TYPE_XDP,
+            insns: &instructions,
+            license: c"GPL",
+            kernel_version: 0,
+            expected_attach_type: None,
+            prog_btf_fd: None,
+            attach_btf_obj_fd: None,
+            attach_btf_id: None,
+            attach_prog_fd: None,
+            func_info_rec_size: 0,
+            func_info: Default::default(),
+            line_info_rec_size: 0,
+            line_info: Default::default(),
+            flags: 0,
+            prog_ifindex: None,
+        };
+        let prog_fd = bpf_load_program(&attrs, &mut [], Default::default()).unwrap();
+        let program = kernel.program("pass").unwrap();
+        assert_eq!(program.program_type(), bpf_prog_type::BPF_PROG_TYPE_XDP);
+        assert_eq!(program.instructions().len(), 3);
+        assert_eq!(program.map_ids(), [kernel.map("COUNTS").unwrap().id()]);
+
+        let link = bpf_link_create(
+            prog_fd.as_fd(),
+            LinkTarget::IfIndex(1),
+            bpf_attach_type::BPF_XDP,
+            0,
+            None,
+        )
+        .unwrap();
+        // The link keeps the program, which keeps the map.
+        drop(prog_fd);
+        drop(data);
+        let attachments = kernel.attachments();
+        assert_matches!(
+            attachments.as_slice(),
+            [attachment] if attachment.program_id() == program.id()
+                && attachment.attach_type() == Some(bpf_attach_type::BPF_XDP)
+                && attachment.target() == &AttachTarget::IfIndex(1)
+        );
+        assert!(kernel.map("COUNTS").is_some());
+
+        drop(link);
+        assert!(kernel.attachments().is_empty());
+        assert!(kernel.programs().is_empty());
+        assert!(kernel.maps().is_empty());
+    }
+
+    #[test]
+    fn test_pinned_kinds() {
+        let kernel = FakeKernel::new();
+        let _guard = kernel.install();
+
+        let data = map("PINNED", bpf_map_type::BPF_MAP_TYPE_ARRAY, 4, 4);
+        data.pin("/sys/fs/bpf/pinned").unwrap();
+
+        let id = kernel.map("PINNED").unwrap().id();
+        assert_eq!(MapInfo::from_pin("/sys/fs/bpf/pinned").unwrap().id(), id);
+        assert_eq!(
+            MapData::from_pin("/sys/fs/bpf/pinned")
+                .unwrap()
+                .info()
+                .unwrap()
+                .id(),
+            id
+        );
+        // Opening the pin as another kind of object fails like it does in the kernel.
+        assert_matches!(
+            ProgramInfo::from_pin("/sys/fs/bpf/pinned"),
+            Err(ProgramError::SyscallError(SyscallError { io_error, .. }))
+                if io_error.raw_os_error() == Some(libc::EINVAL)
+        );
+        assert_matches!(
+            LinkInfo::from_pin("/sys/fs/bpf/pinned").map(|info| info.id()),
+            Err(LinkError::SyscallError(SyscallError { io_error, .. }))
+                if io_error.raw_os_error() == Some(libc::EINVAL)
+        );
+    }
+
+    #[test]
+    fn test_mappings_outlive_kernel() {
+        let kernel = FakeKernel::new();
+        let guard = kernel.install();
+
+        let data = map("RING", bpf_map_type::BPF_MAP_TYPE_RINGBUF, 0, 0);
+        let len = page_size();
+        let ptr = unsafe {
+            mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_SHARED,
+                data.fd().as_fd(),
+                0,
+            )
+        };
+        assert_ne!(ptr, libc::MAP_FAILED);
+        drop(guard);
+
+        // The mapping is released even though the kernel isn't installed anymore.
+        assert_eq!(unsafe { munmap(ptr, len) }, 0);
+        assert_eq!(unsafe { libc::madvise(ptr, len, libc::MADV_NORMAL) }, -1);
+        assert_eq!(
+            io::Error::last_os_error().raw_os_error(),
+            Some(libc::ENOMEM)
+        );
+    }
+
+    #[test]
+    fn test_netlink_unsupported() {
+        let _guard = FakeKernel::new().install();
+
+        assert_matches!(
+            netlink_list_links(),
+            Err(err) if err.to_string() == "netlink isn't supported by the fake kernel"
+        );
+    }
+}
@@ -44,6 +44,8 @@
 )]
 
 mod bpf;
+#[cfg(feature = "fake")]
+pub mod fake;
 pub mod maps;
 pub mod pin;
 pub mod programs;
@@ -51,10 +53,12 @@ pub mod sys;
 #[cfg(feature = "test-helpers")]
 pub mod test_helpers;
 pub mod util;
+pub mod xsk;
 
 use std::os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd};
 
-pub use aya_obj::btf::{Btf, BtfError};
+pub use aya_macros::Pod;
+pub use aya_obj::btf::{Btf, BtfError, BtfField, BtfValue};
 pub use bpf::*;
 pub use object::Endianness;
 pub use programs::{
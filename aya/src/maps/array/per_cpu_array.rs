@@ -6,7 +6,7 @@ use std::{
 
 use crate::{
     Pod,
-    maps::{IterableMap, MapData, MapError, PerCpuValues, check_bounds, check_kv_size},
+    maps::{IterableMap, MapData, MapError, PerCpuValues, check_bounds, check_kv_size, lookup_cpu},
     sys::{SyscallError, bpf_map_lookup_elem_per_cpu, bpf_map_update_elem_per_cpu},
 };
 
@@ -91,11 +91,39 @@ impl<T: Borrow<MapData>, V: Pod> PerCpuArray<T, V> {
         value.ok_or(MapError::KeyNotFound)
     }
 
+    /// Returns the value stored at the given index for a single CPU.
+    ///
+    /// On kernels supporting `BPF_F_CPU` (v6.19 or later) only the value of `cpu` is copied,
+    /// older kernels copy the values of all the CPUs.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`MapError::OutOfBounds`] if `index` is out of bounds,
+    /// [`MapError::CpuOutOfBounds`] if `cpu` is not a possible CPU, [`MapError::SyscallError`]
+    /// if `bpf_map_lookup_elem` fails.
+    pub fn get_cpu(&self, index: &u32, cpu: u32, flags: u64) -> Result<V, MapError> {
+        let data = self.inner.borrow();
+        check_bounds(data, *index)?;
+        lookup_cpu(data, index, cpu, flags)
+    }
+
     /// An iterator over the elements of the array. The iterator item type is
     /// `Result<PerCpuValues<V>, MapError>`.
     pub fn iter(&self) -> impl Iterator<Item = Result<PerCpuValues<V>, MapError>> + '_ {
         (0..self.len()).map(move |i| self.get(&i, 0))
     }
+
+    /// Returns the values of all the elements of the array, indexed by position.
+    ///
+    /// Each element is read with a single lookup, so the values of all the CPUs of an element
+    /// are copied at the same time.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`MapError::SyscallError`] if `bpf_map_lookup_elem` fails.
+    pub fn snapshot(&self, flags: u64) -> Result<Vec<PerCpuValues<V>>, MapError> {
+        (0..self.len()).map(|i| self.get(&i, flags)).collect()
+    }
 }
 
 impl<T: BorrowMut<MapData>, V: Pod> PerCpuArray<T, V> {
@@ -0,0 +1,118 @@
+//! Dumping the contents of arbitrary maps.
+
+use std::borrow::Cow;
+
+use aya_obj::btf::BtfValue;
+
+use crate::maps::{MapData, MapError, RawMapKeys, dynamic::MapTypes};
+
+/// A key/value pair returned by [`MapData::dump`] and
+/// [`DynamicMap::iter`](crate::maps::DynamicMap::iter).
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct MapEntry {
+    /// The decoded key.
+    pub key: BtfValue,
+    /// The decoded value.
+    pub value: BtfValue,
+}
+
+/// Iterator returned by [`MapData::dump`] and
+/// [`DynamicMap::iter`](crate::maps::DynamicMap::iter).
+pub struct MapDump<'coll> {
+    map: &'coll MapData,
+    keys: RawMapKeys<'coll>,
+    types: Cow<'coll, MapTypes>,
+}
+
+impl<'coll> MapDump<'coll> {
+    pub(crate) const fn new(map: &'coll MapData, types: Cow<'coll, MapTypes>) -> Self {
+        Self {
+            map,
+            keys: map.raw_keys(),
+            types,
+        }
+    }
+
+    fn entry(&self, key: &[u8]) -> Result<MapEntry, MapError> {
+        let value = self.map.raw_lookup(key, 0)?;
+        Ok(MapEntry {
+            key: self.types.decode_key(key)?,
+            value: self.types.decode_value(&value)?,
+        })
+    }
+}
+
+impl Iterator for MapDump<'_> {
+    type Item = Result<MapEntry, MapError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let key = match self.keys.next()? {
+                Ok(key) => key,
+                Err(e) => return Some(Err(e)),
+            };
+            match self.entry(&key) {
+                Ok(entry) => return Some(Ok(entry)),
+                // The entry was removed while iterating.
+                Err(MapError::KeyNotFound) => {}
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use aya_obj::generated::{bpf_cmd, bpf_map_type};
+    use libc::{EFAULT, ENOENT};
+
+    use super::*;
+    use crate::{
+        maps::test_utils::{new_map, new_obj_map},
+        sys::{Syscall, override_syscall},
+    };
+
+    #[test]
+    fn test_dump_without_btf() {
+        let map = new_map(new_obj_map::<u32>(bpf_map_type::BPF_MAP_TYPE_HASH));
+
+        override_syscall(|call| match call {
+            Syscall::Ebpf {
+                cmd: bpf_cmd::BPF_OBJ_GET_INFO_BY_FD,
+                ..
+            } => Ok(0),
+            Syscall::Ebpf {
+                cmd: bpf_cmd::BPF_MAP_GET_NEXT_KEY,
+                attr,
+            } => {
+                let u = unsafe { &attr.__bindgen_anon_2 };
+                if u.key != 0 {
+                    return Err((-1, io::Error::from_raw_os_error(ENOENT)));
+                }
+                unsafe { *(u.__bindgen_anon_1.next_key as *mut u32) = 7 }
+                Ok(0)
+            }
+            Syscall::Ebpf {
+                cmd: bpf_cmd::BPF_MAP_LOOKUP_ELEM,
+                attr,
+            } => {
+                let u = unsafe { &attr.__bindgen_anon_2 };
+                unsafe { *(u.__bindgen_anon_1.value as *mut u32) = 42 }
+                Ok(0)
+            }
+            _ => Err((-1, io::Error::from_raw_os_error(EFAULT))),
+        });
+
+        let entries = map.dump().unwrap().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(
+            entries,
+            [MapEntry {
+                key: BtfValue::Bytes(7u32.to_ne_bytes().to_vec()),
+                value: BtfValue::Bytes(42u32.to_ne_bytes().to_vec()),
+            }]
+        );
+    }
+}
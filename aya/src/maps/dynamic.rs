@@ -0,0 +1,355 @@
+//! A map whose key and value types are described by BTF at runtime.
+
+use std::borrow::{Borrow, BorrowMut, Cow};
+
+use aya_obj::btf::{Btf, BtfError, BtfValue};
+
+use crate::{
+    maps::{MapData, MapDump, MapEntry, MapError},
+    util::nr_cpus,
+};
+
+/// The BTF key and value types of a map.
+#[derive(Clone, Debug)]
+pub(crate) struct MapTypes {
+    btf: Option<Btf>,
+    key_type_id: Option<u32>,
+    value_type_id: Option<u32>,
+    value_size: usize,
+    per_cpu: bool,
+}
+
+impl MapTypes {
+    pub(crate) fn new(map: &MapData) -> Result<Self, MapError> {
+        let info = map.info()?;
+        Ok(Self {
+            btf: info.btf()?,
+            key_type_id: info.btf_key_type_id(),
+            value_type_id: info.btf_value_type_id(),
+            value_size: map.obj().value_size() as usize,
+            per_cpu: map.is_per_cpu()?,
+        })
+    }
+
+    fn decode(&self, type_id: Option<u32>, data: &[u8]) -> Result<BtfValue, MapError> {
+        match (&self.btf, type_id) {
+            (Some(btf), Some(type_id)) => Ok(btf.decode_value(type_id, data)?),
+            (None, _) | (_, None) => Ok(BtfValue::Bytes(data.to_vec())),
+        }
+    }
+
+    fn encode(&self, type_id: Option<u32>, value: &BtfValue) -> Result<Vec<u8>, MapError> {
+        match (&self.btf, type_id, value) {
+            (Some(btf), Some(type_id), value) => Ok(btf.encode_value(type_id, value)?),
+            (None, _, BtfValue::Bytes(data)) | (_, None, BtfValue::Bytes(data)) => Ok(data.clone()),
+            // Without BTF the only known type is `void`.
+            (None, _, _) | (_, None, _) => Err(BtfError::ValueTypeMismatch { type_id: 0 }.into()),
+        }
+    }
+
+    pub(crate) fn decode_key(&self, data: &[u8]) -> Result<BtfValue, MapError> {
+        self.decode(self.key_type_id, data)
+    }
+
+    pub(crate) fn encode_key(&self, key: &BtfValue) -> Result<Vec<u8>, MapError> {
+        self.encode(self.key_type_id, key)
+    }
+
+    /// Decodes a value as returned by [`MapData::raw_lookup`].
+    ///
+    /// Values of per-CPU maps are decoded as an array with one element per possible CPU.
+    pub(crate) fn decode_value(&self, data: &[u8]) -> Result<BtfValue, MapError> {
+        let Self {
+            value_type_id,
+            value_size,
+            per_cpu,
+            ..
+        } = self;
+        if *per_cpu {
+            Ok(BtfValue::Array(
+                data.chunks_exact(value_size.next_multiple_of(8))
+                    .map(|value| self.decode(*value_type_id, &value[..*value_size]))
+                    .collect::<Result<_, _>>()?,
+            ))
+        } else {
+            self.decode(*value_type_id, data)
+        }
+    }
+
+    /// Encodes a value in the layout expected by [`MapData::raw_insert`], the inverse of
+    /// [`MapTypes::decode_value`].
+    pub(crate) fn encode_value(&self, value: &BtfValue) -> Result<Vec<u8>, MapError> {
+        let Self {
+            value_type_id,
+            value_size,
+            per_cpu,
+            ..
+        } = self;
+        if !*per_cpu {
+            return self.encode(*value_type_id, value);
+        }
+        let BtfValue::Array(values) = value else {
+            return Err(BtfError::ValueTypeMismatch {
+                type_id: value_type_id.unwrap_or_default(),
+            }
+            .into());
+        };
+        let nr_cpus = nr_cpus().map_err(|(_, error)| MapError::IoError(error))?;
+        let stride = value_size.next_multiple_of(8);
+        if values.len() != nr_cpus {
+            return Err(MapError::InvalidValueSize {
+                size: values.len() * stride,
+                expected: nr_cpus * stride,
+            });
+        }
+        let mut data = vec![0; nr_cpus * stride];
+        for (value, data) in values.iter().zip(data.chunks_exact_mut(stride)) {
+            let value = self.encode(*value_type_id, value)?;
+            if value.len() != *value_size {
+                return Err(MapError::InvalidValueSize {
+                    size: value.len(),
+                    expected: *value_size,
+                });
+            }
+            data[..value.len()].copy_from_slice(&value);
+        }
+        Ok(data)
+    }
+}
+
+/// A map whose key and value types are only known at runtime, from the map's BTF.
+///
+/// Keys and values are [`BtfValue`] trees decoded with [`Btf::decode_value`] and encoded with
+/// [`Btf::encode_value`], so struct members can be read and written by name. This is useful for
+/// generic tools that inspect or edit maps of programs they weren't compiled with. Keys and values
+/// of maps without BTF are [`BtfValue::Bytes`], and values of per-CPU maps are arrays with one
+/// element per possible CPU.
+///
+/// Any map holding keys and values can be converted, for example a map obtained with
+/// [`MapData::from_pin`] and [`Map::from_map_data`](crate::maps::Map::from_map_data).
+///
+/// # Minimum kernel version
+///
+/// The minimum kernel version required to use this feature is 4.18, which added BTF.
+///
+/// # Examples
+///
+/// ```no_run
+/// use aya::{
+///     BtfValue,
+///     maps::{DynamicMap, Map, MapData},
+/// };
+///
+/// let map = Map::from_map_data(MapData::from_pin("/sys/fs/bpf/connections")?)?;
+/// let mut map = DynamicMap::try_from(map)?;
+/// let entries = map.iter().collect::<Result<Vec<_>, _>>()?;
+/// for mut entry in entries {
+///     if let Some(count) = entry.value.field_mut("count") {
+///         *count = BtfValue::Uint(0);
+///     }
+///     map.insert(&entry.key, &entry.value, 0)?;
+/// }
+/// # Ok::<(), aya::maps::MapError>(())
+/// ```
+#[derive(Debug)]
+pub struct DynamicMap<T> {
+    pub(crate) inner: T,
+    types: MapTypes,
+}
+
+impl<T: Borrow<MapData>> DynamicMap<T> {
+    pub(crate) fn new(map: T) -> Result<Self, MapError> {
+        let types = MapTypes::new(map.borrow())?;
+        Ok(Self { inner: map, types })
+    }
+
+    /// Returns the BTF the key and value types are described in, if the map has any.
+    pub const fn btf(&self) -> Option<&Btf> {
+        self.types.btf.as_ref()
+    }
+
+    /// Returns the BTF type id of the keys, if the map has one.
+    pub const fn key_type_id(&self) -> Option<u32> {
+        self.types.key_type_id
+    }
+
+    /// Returns the BTF type id of the values, if the map has one.
+    pub const fn value_type_id(&self) -> Option<u32> {
+        self.types.value_type_id
+    }
+
+    /// Returns the value stored at `key`.
+    pub fn get(&self, key: &BtfValue, flags: u64) -> Result<BtfValue, MapError> {
+        let key = self.types.encode_key(key)?;
+        let value = self.inner.borrow().raw_lookup(&key, flags)?;
+        self.types.decode_value(&value)
+    }
+
+    /// An iterator visiting all key-value pairs in arbitrary order. The iterator item type is
+    /// `Result<MapEntry, MapError>`.
+    pub fn iter(&self) -> MapDump<'_> {
+        MapDump::new(self.inner.borrow(), Cow::Borrowed(&self.types))
+    }
+}
+
+impl<'a, T: Borrow<MapData>> IntoIterator for &'a DynamicMap<T> {
+    type Item = Result<MapEntry, MapError>;
+    type IntoIter = MapDump<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<T: BorrowMut<MapData>> DynamicMap<T> {
+    /// Inserts a key-value pair into the map.
+    pub fn insert(&mut self, key: &BtfValue, value: &BtfValue, flags: u64) -> Result<(), MapError> {
+        let key = self.types.encode_key(key)?;
+        let value = self.types.encode_value(value)?;
+        self.inner.borrow_mut().raw_insert(&key, &value, flags)
+    }
+
+    /// Removes a key from the map.
+    pub fn remove(&mut self, key: &BtfValue) -> Result<(), MapError> {
+        let key = self.types.encode_key(key)?;
+        self.inner.borrow_mut().raw_remove(&key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io, slice};
+
+    use assert_matches::assert_matches;
+    use aya_obj::generated::{bpf_cmd, bpf_map_type};
+    use libc::EFAULT;
+
+    use super::*;
+    use crate::{
+        maps::{
+            Map,
+            test_utils::{new_map, new_obj_map},
+        },
+        sys::{Syscall, override_syscall},
+    };
+
+    fn bytes(value: u32) -> BtfValue {
+        BtfValue::Bytes(value.to_ne_bytes().to_vec())
+    }
+
+    #[test]
+    fn test_try_from_wrong_map() {
+        let map = new_map(new_obj_map::<u32>(bpf_map_type::BPF_MAP_TYPE_PROG_ARRAY));
+        let map = Map::ProgramArray(map);
+        assert_matches!(
+            DynamicMap::try_from(&map),
+            Err(MapError::InvalidMapType { .. })
+        );
+    }
+
+    #[test]
+    fn test_without_btf() {
+        let map = new_map(new_obj_map::<u32>(bpf_map_type::BPF_MAP_TYPE_HASH));
+        let mut map = Map::HashMap(map);
+
+        override_syscall(|call| match call {
+            Syscall::Ebpf {
+                cmd: bpf_cmd::BPF_OBJ_GET_INFO_BY_FD,
+                ..
+            } => Ok(0),
+            Syscall::Ebpf {
+                cmd: bpf_cmd::BPF_MAP_LOOKUP_ELEM,
+                attr,
+            } => {
+                let u = unsafe { &attr.__bindgen_anon_2 };
+                let key = unsafe { *(u.key as *const u32) };
+                unsafe { *(u.__bindgen_anon_1.value as *mut u32) = key * 2 }
+                Ok(0)
+            }
+            Syscall::Ebpf {
+                cmd: bpf_cmd::BPF_MAP_UPDATE_ELEM,
+                attr,
+            } => {
+                let u = unsafe { &attr.__bindgen_anon_2 };
+                let key = unsafe { *(u.key as *const u32) };
+                let value = unsafe { *(u.__bindgen_anon_1.value as *const u32) };
+                assert_eq!((key, value), (1, 2));
+                Ok(0)
+            }
+            Syscall::Ebpf {
+                cmd: bpf_cmd::BPF_MAP_DELETE_ELEM,
+                attr,
+            } => {
+                let u = unsafe { &attr.__bindgen_anon_2 };
+                assert_eq!(unsafe { *(u.key as *const u32) }, 1);
+                Ok(0)
+            }
+            _ => Err((-1, io::Error::from_raw_os_error(EFAULT))),
+        });
+
+        let mut map = DynamicMap::try_from(&mut map).unwrap();
+        assert!(map.btf().is_none());
+        assert_eq!(map.key_type_id(), None);
+        assert_eq!(map.get(&bytes(21), 0).unwrap(), bytes(42));
+        map.insert(&bytes(1), &bytes(2), 0).unwrap();
+        map.remove(&bytes(1)).unwrap();
+
+        assert_matches!(
+            map.insert(&BtfValue::Uint(1), &bytes(2), 0),
+            Err(MapError::Btf(BtfError::ValueTypeMismatch { type_id: 0 }))
+        );
+        assert_matches!(
+            map.remove(&BtfValue::Bytes(vec![1])),
+            Err(MapError::InvalidKeySize {
+                size: 1,
+                expected: 4
+            })
+        );
+    }
+
+    #[test]
+    fn test_per_cpu_values() {
+        let map = new_map(new_obj_map::<u32>(bpf_map_type::BPF_MAP_TYPE_PERCPU_ARRAY));
+        let map = Map::PerCpuArray(map);
+
+        override_syscall(|call| match call {
+            Syscall::Ebpf {
+                cmd: bpf_cmd::BPF_OBJ_GET_INFO_BY_FD,
+                ..
+            } => Ok(0),
+            Syscall::Ebpf {
+                cmd: bpf_cmd::BPF_MAP_LOOKUP_ELEM,
+                attr,
+            } => {
+                let u = unsafe { &attr.__bindgen_anon_2 };
+                let nr_cpus = nr_cpus().unwrap();
+                let values = unsafe {
+                    slice::from_raw_parts_mut(u.__bindgen_anon_1.value as *mut u64, nr_cpus)
+                };
+                for (cpu, value) in values.iter_mut().enumerate() {
+                    *value = cpu as u64 + 1;
+                }
+                Ok(0)
+            }
+            _ => Err((-1, io::Error::from_raw_os_error(EFAULT))),
+        });
+
+        let map = DynamicMap::try_from(map).unwrap();
+        let value = map.get(&bytes(0), 0).unwrap();
+        let nr_cpus = nr_cpus().unwrap();
+        assert_eq!(
+            value,
+            BtfValue::Array((1..=nr_cpus as u32).map(bytes).collect())
+        );
+        assert_eq!(
+            map.types.encode_value(&value).unwrap(),
+            (1..=nr_cpus as u64)
+                .flat_map(u64::to_ne_bytes)
+                .collect::<Vec<_>>()
+        );
+        assert_matches!(
+            map.types.encode_value(&BtfValue::Array(Vec::new())),
+            Err(MapError::InvalidValueSize { size: 0, .. })
+        );
+    }
+}
@@ -9,6 +9,7 @@ use crate::{
     Pod,
     maps::{
         IterableMap, MapData, MapError, MapIter, MapKeys, PerCpuValues, check_kv_size, hash_map,
+        lookup_cpu,
     },
     sys::{SyscallError, bpf_map_lookup_elem_per_cpu, bpf_map_update_elem_per_cpu},
 };
@@ -69,12 +70,48 @@ impl<T: Borrow<MapData>, K: Pod, V: Pod> PerCpuHashMap<T, K, V> {
         values.ok_or(MapError::KeyNotFound)
     }
 
+    /// Returns the value associated with the key for a single CPU.
+    ///
+    /// On kernels supporting `BPF_F_CPU` (v6.19 or later) only the value of `cpu` is copied,
+    /// older kernels copy the values of all the CPUs.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`MapError::CpuOutOfBounds`] if `cpu` is not a possible CPU,
+    /// [`MapError::KeyNotFound`] if the key doesn't exist, [`MapError::SyscallError`] if
+    /// `bpf_map_lookup_elem` fails.
+    pub fn get_cpu(&self, key: &K, cpu: u32, flags: u64) -> Result<V, MapError> {
+        lookup_cpu(self.inner.borrow(), key, cpu, flags)
+    }
+
     /// An iterator visiting all key-value pairs in arbitrary order. The
     /// iterator item type is `Result<(K, PerCpuValues<V>), MapError>`.
     pub fn iter(&self) -> MapIter<'_, K, PerCpuValues<V>, Self> {
         MapIter::new(self)
     }
 
+    /// Returns all the key-value pairs in arbitrary order.
+    ///
+    /// Each key is read with a single lookup, so the values of all the CPUs of a key are copied
+    /// at the same time. Keys removed while the map is being read are skipped.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`MapError::SyscallError`] if `bpf_map_get_next_key` or `bpf_map_lookup_elem`
+    /// fail.
+    pub fn snapshot(&self, flags: u64) -> Result<Vec<(K, PerCpuValues<V>)>, MapError> {
+        let mut entries = Vec::new();
+        for key in self.keys() {
+            let key = key?;
+            match self.get(&key, flags) {
+                Ok(values) => entries.push((key, values)),
+                Err(MapError::KeyNotFound) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(entries)
+    }
+
     /// An iterator visiting all keys in arbitrary order. The iterator element
     /// type is `Result<K, MapError>`.
     pub fn keys(&self) -> MapKeys<'_, K> {
@@ -6,13 +6,18 @@ use std::{
     path::Path,
 };
 
-use aya_obj::generated::{bpf_map_info, bpf_map_type};
+use aya_obj::{
+    btf::Btf,
+    generated::{bpf_map_info, bpf_map_type},
+};
+use object::Endianness;
 
 use super::{MapError, MapFd};
 use crate::{
     FEATURES,
     sys::{
-        SyscallError, bpf_get_object, bpf_map_get_fd_by_id, bpf_map_get_info_by_fd, iter_map_ids,
+        ObjectKind, SyscallError, bpf_btf_get_fd_by_id, bpf_get_object_of_kind,
+        bpf_map_get_fd_by_id, bpf_map_get_info_by_fd, btf_obj_get_data_by_fd, iter_map_ids,
     },
     util::bytes_of_bpf_name,
 };
@@ -38,6 +43,13 @@ impl MapInfo {
         Self::new_from_fd(fd.as_fd())
     }
 
+    /// Loads map info from a file descriptor referring to a map.
+    ///
+    /// Uses kernel v4.13 features.
+    pub fn from_fd(fd: BorrowedFd<'_>) -> Result<Self, MapError> {
+        Self::new_from_fd(fd)
+    }
+
     /// The type of map.
     ///
     /// Introduced in kernel v4.13.
@@ -82,6 +94,18 @@ impl MapInfo {
         self.0.map_flags
     }
 
+    /// The index of the network interface the map is offloaded to.
+    ///
+    /// `None` is returned if the map isn't offloaded.
+    ///
+    /// Introduced in kernel v4.16.
+    pub const fn ifindex(&self) -> Option<u32> {
+        match self.0.ifindex {
+            0 => None,
+            ifindex => Some(ifindex),
+        }
+    }
+
     /// The name of the map, limited to 16 bytes.
     ///
     /// Introduced in kernel v4.15.
@@ -99,6 +123,57 @@ impl MapInfo {
         (FEATURES.bpf_name() || !name.is_empty()).then_some(name)
     }
 
+    /// The ID of the BTF object describing this map's key and value types.
+    ///
+    /// `None` is returned if the map was created without BTF.
+    ///
+    /// Introduced in kernel v4.18.
+    pub const fn btf_id(&self) -> Option<u32> {
+        match self.0.btf_id {
+            0 => None,
+            id => Some(id),
+        }
+    }
+
+    /// The BTF type ID of the map's key within the BTF object returned by [`Self::btf`].
+    ///
+    /// `None` is returned if the map was created without BTF or the key type is unknown.
+    ///
+    /// Introduced in kernel v4.18.
+    pub const fn btf_key_type_id(&self) -> Option<u32> {
+        match self.0.btf_key_type_id {
+            0 => None,
+            id => Some(id),
+        }
+    }
+
+    /// The BTF type ID of the map's value within the BTF object returned by [`Self::btf`].
+    ///
+    /// `None` is returned if the map was created without BTF or the value type is unknown.
+    ///
+    /// Introduced in kernel v4.18.
+    pub const fn btf_value_type_id(&self) -> Option<u32> {
+        match self.0.btf_value_type_id {
+            0 => None,
+            id => Some(id),
+        }
+    }
+
+    /// Loads the BTF object describing this map's key and value types from the kernel.
+    ///
+    /// `None` is returned if the map was created without BTF.
+    ///
+    /// Uses kernel v4.18 features.
+    pub fn btf(&self) -> Result<Option<Btf>, MapError> {
+        let Some(btf_id) = self.btf_id() else {
+            return Ok(None);
+        };
+        let fd = bpf_btf_get_fd_by_id(btf_id)?;
+        let data = btf_obj_get_data_by_fd(fd.as_fd())?;
+        let btf = Btf::parse(&data, Endianness::default())?;
+        Ok(Some(btf))
+    }
+
     /// Returns a file descriptor referencing the map.
     ///
     /// The returned file descriptor can be closed at any time and doing so does
@@ -113,15 +188,19 @@ impl MapInfo {
 
     /// Loads a map from a pinned path in bpffs.
     ///
+    /// Fails with `EINVAL` if the object pinned at `path` isn't a map.
+    ///
     /// Uses kernel v4.4 and v4.13 features.
     pub fn from_pin<P: AsRef<Path>>(path: P) -> Result<Self, MapError> {
         use std::os::unix::ffi::OsStrExt as _;
 
         // TODO: avoid this unwrap by adding a new error variant.
         let path_string = CString::new(path.as_ref().as_os_str().as_bytes()).unwrap();
-        let fd = bpf_get_object(&path_string).map_err(|io_error| SyscallError {
-            call: "BPF_OBJ_GET",
-            io_error,
+        let fd = bpf_get_object_of_kind(&path_string, ObjectKind::Map).map_err(|io_error| {
+            SyscallError {
+                call: "BPF_OBJ_GET",
+                io_error,
+            }
         })?;
 
         Self::new_from_fd(fd.as_fd())
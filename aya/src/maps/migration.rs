@@ -0,0 +1,608 @@
+//! Migration of pinned maps whose definition changed.
+
+use std::{
+    fmt::{self, Debug, Formatter},
+    fs,
+    os::fd::{AsFd as _, BorrowedFd},
+    path::Path,
+};
+
+use aya_obj::generated::bpf_map_type;
+
+use crate::{
+    maps::{MapData, MapError, MapInfo},
+    sys::{SyscallError, bpf_map_update_elem_ptr},
+};
+
+/// A callback converting the entries of a pinned map, see [`MapMigration::Convert`].
+pub type ConvertEntry<'a> = dyn Fn(&[u8], &[u8]) -> Option<(Vec<u8>, Vec<u8>)> + 'a;
+
+/// How [`EbpfLoader`](crate::EbpfLoader) handles a pinned map whose definition differs from
+/// the definition in the object.
+///
+/// The type, key size, value size, `max_entries` and flags of the pinned map are compared to the
+/// object. When they differ, all policies but [`Keep`](Self::Keep) and [`Error`](Self::Error)
+/// create a new map and atomically replace the pin with it. Programs loaded before the migration
+/// keep using the old map.
+///
+/// [`Copy`](Self::Copy) and [`Convert`](Self::Convert) take a snapshot of the pinned map: updates
+/// made by programs still using the old map while or after it is copied are lost. Detach these
+/// programs, or otherwise stop them from writing to the map, before loading. They fail with
+/// [`MapError::UnsupportedMigration`] for maps whose keys or values are file descriptors, such as
+/// program arrays, maps of maps and socket maps.
+///
+/// See [`EbpfLoader::map_migration`](crate::EbpfLoader::map_migration).
+#[derive(Default)]
+pub enum MapMigration<'a> {
+    /// Use the pinned map as is.
+    #[default]
+    Keep,
+    /// Fail with [`MapError::IncompatibleMap`].
+    Error,
+    /// Replace the pinned map with an empty map.
+    Replace,
+    /// Replace the pinned map with a map containing its entries.
+    ///
+    /// The key size must be unchanged and the value size can only grow: values are
+    /// zero-extended. Fails if an entry doesn't fit in the new map.
+    Copy,
+    /// Replace the pinned map with a map containing its entries converted by the callback.
+    ///
+    /// The callback is called with the key and value of each entry, as raw bytes, and returns
+    /// the key and value to insert, or `None` to drop the entry. Values of per-CPU maps contain
+    /// one value per possible CPU, each padded to a multiple of 8 bytes.
+    Convert(&'a ConvertEntry<'a>),
+}
+
+impl Debug for MapMigration<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Keep => f.write_str("Keep"),
+            Self::Error => f.write_str("Error"),
+            Self::Replace => f.write_str("Replace"),
+            Self::Copy => f.write_str("Copy"),
+            Self::Convert(_) => f.write_str("Convert(..)"),
+        }
+    }
+}
+
+/// A difference between the definitions of two maps.
+pub(super) struct Mismatch {
+    field: &'static str,
+    actual: u32,
+    expected: u32,
+}
+
+impl Mismatch {
+    pub(super) fn into_error(self, name: &str) -> MapError {
+        let Self {
+            field,
+            actual,
+            expected,
+        } = self;
+        MapError::IncompatibleMap {
+            name: name.into(),
+            field,
+            actual,
+            expected,
+        }
+    }
+}
+
+impl MapData {
+    /// Returns the first difference between the definition of this map and `obj` that prevents
+    /// using this map in place of `obj`.
+    ///
+    /// The type, key size, value size and flags are compared, as well as the definitions of the
+    /// inner maps of maps of maps when both are known. `max_entries` is ignored.
+    pub(super) fn incompatibility(&self, obj: &aya_obj::Map) -> Option<Mismatch> {
+        let Self {
+            obj: existing,
+            fd: _,
+        } = self;
+        let inner = existing.inner().zip(obj.inner());
+        [
+            ("type", existing.map_type(), obj.map_type()),
+            ("key size", existing.key_size(), obj.key_size()),
+            ("value size", existing.value_size(), obj.value_size()),
+            ("flags", existing.map_flags(), obj.map_flags()),
+        ]
+        .into_iter()
+        .chain(inner.iter().flat_map(|(existing, obj)| {
+            [
+                ("inner map type", existing.map_type(), obj.map_type()),
+                ("inner map key size", existing.key_size(), obj.key_size()),
+                (
+                    "inner map value size",
+                    existing.value_size(),
+                    obj.value_size(),
+                ),
+                (
+                    "inner map max_entries",
+                    existing.max_entries(),
+                    obj.max_entries(),
+                ),
+                ("inner map flags", existing.map_flags(), obj.map_flags()),
+            ]
+        }))
+        .find(|(_, actual, expected)| actual != expected)
+        .map(|(field, actual, expected)| Mismatch {
+            field,
+            actual,
+            expected,
+        })
+    }
+
+    /// Returns the first difference between the definition of this map and `obj`.
+    fn mismatch(&self, obj: &aya_obj::Map) -> Option<Mismatch> {
+        self.incompatibility(obj).or_else(|| {
+            let Self {
+                obj: existing,
+                fd: _,
+            } = self;
+            // The loader sets `max_entries` of perf event arrays to the number of CPUs.
+            let perf_event_array =
+                obj.map_type() == bpf_map_type::BPF_MAP_TYPE_PERF_EVENT_ARRAY as u32;
+            (existing.max_entries() != obj.max_entries() && !perf_event_array).then(|| Mismatch {
+                field: "max_entries",
+                actual: existing.max_entries(),
+                expected: obj.max_entries(),
+            })
+        })
+    }
+
+    /// Returns this map, pinned at `path`, if it matches `obj`, or replaces it according to
+    /// `migration`.
+    pub(crate) fn migrate(
+        self,
+        path: &Path,
+        obj: aya_obj::Map,
+        name: &str,
+        btf_fd: Option<BorrowedFd<'_>>,
+        inner_map_obj: Option<aya_obj::Map>,
+        migration: &MapMigration<'_>,
+    ) -> Result<Self, MapError> {
+        let mismatch = match (self.mismatch(&obj), migration) {
+            (Some(mismatch), MapMigration::Error) => return Err(mismatch.into_error(name)),
+            (
+                Some(mismatch),
+                MapMigration::Replace | MapMigration::Copy | MapMigration::Convert(_),
+            ) => mismatch,
+            (Some(_), MapMigration::Keep) | (None, _) => {
+                let Self { obj: _, fd } = self;
+                return Ok(Self { obj, fd });
+            }
+        };
+        if matches!(migration, MapMigration::Copy)
+            && (self.obj.key_size() != obj.key_size() || self.obj.value_size() > obj.value_size())
+        {
+            return Err(mismatch.into_error(name));
+        }
+        if matches!(migration, MapMigration::Copy | MapMigration::Convert(_)) {
+            for map_type in [self.obj.map_type(), obj.map_type()] {
+                let map_type = bpf_map_type::try_from(map_type)?;
+                if holds_fds(map_type) {
+                    return Err(MapError::UnsupportedMigration {
+                        name: name.into(),
+                        map_type,
+                    });
+                }
+            }
+        }
+
+        let inner_map;
+        let inner_map_fd = if let Some(inner) = inner_map_obj {
+            inner_map = Self::create(inner, &format!("{name}.inner"), btf_fd)?;
+            Some(inner_map.fd().as_fd())
+        } else {
+            None
+        };
+        // Create the new map on the device the pinned map is offloaded to, if any.
+        let map_ifindex = MapInfo::new_from_fd(self.fd().as_fd())?.ifindex();
+        let map = Self::create_with_inner_map_fd(obj, name, btf_fd, inner_map_fd, map_ifindex)?;
+
+        match migration {
+            MapMigration::Copy => {
+                for entry in self.raw_entries() {
+                    let (key, value) = entry?;
+                    let value = self.resize_value(&value, map.obj.value_size())?;
+                    map.raw_update(&key, &value)?;
+                }
+            }
+            MapMigration::Convert(convert) => {
+                for entry in self.raw_entries() {
+                    let (key, value) = entry?;
+                    if let Some((key, value)) = convert(&key, &value) {
+                        map.raw_update(&key, &value)?;
+                    }
+                }
+            }
+            MapMigration::Keep | MapMigration::Error | MapMigration::Replace => {}
+        }
+
+        // Pin the new map next to the old one, then atomically replace the old pin.
+        let mut new_path = path.as_os_str().to_owned();
+        new_path.push(".new");
+        map.pin(&new_path).map_err(|error| MapError::PinError {
+            name: Some(name.into()),
+            error,
+        })?;
+        if let Err(error) = fs::rename(&new_path, path) {
+            let _unused: Result<(), _> = fs::remove_file(&new_path);
+            return Err(error.into());
+        }
+        Ok(map)
+    }
+
+    /// Returns an iterator over the keys and values of the map as raw bytes.
+    fn raw_entries(&self) -> impl Iterator<Item = Result<(Vec<u8>, Vec<u8>), MapError>> + '_ {
+        self.raw_keys().map(|key| {
+            let key = key?;
+            let value = self.raw_lookup(&key, 0)?;
+            Ok((key, value))
+        })
+    }
+
+    /// Zero-extends the values in `value`, as read from this map, to `value_size`.
+    fn resize_value(&self, value: &[u8], value_size: u32) -> Result<Vec<u8>, MapError> {
+        let old_size = self.obj.value_size() as usize;
+        let new_size = value_size as usize;
+        if !self.is_per_cpu()? {
+            let mut value = value.to_vec();
+            value.resize(new_size, 0);
+            return Ok(value);
+        }
+        let mut resized = Vec::new();
+        for cpu_value in value.chunks(old_size.next_multiple_of(8)) {
+            resized.extend_from_slice(&cpu_value[..old_size]);
+            resized.resize(resized.len() + new_size.next_multiple_of(8) - old_size, 0);
+        }
+        Ok(resized)
+    }
+
+    /// Sets the value stored at `key`, with the same layout as returned by
+    /// [`raw_lookup`](Self::raw_lookup).
+    fn raw_update(&self, key: &[u8], value: &[u8]) -> Result<(), MapError> {
+        let Self { obj, fd } = self;
+        let expected = obj.key_size() as usize;
+        if key.len() != expected {
+            return Err(MapError::InvalidKeySize {
+                size: key.len(),
+                expected,
+            });
+        }
+        let expected = self.raw_value_size()?;
+        if value.len() != expected {
+            return Err(MapError::InvalidValueSize {
+                size: value.len(),
+                expected,
+            });
+        }
+        bpf_map_update_elem_ptr(fd.as_fd(), key.as_ptr(), value.as_ptr().cast_mut(), 0).map_err(
+            |io_error| SyscallError {
+                call: "bpf_map_update_elem",
+                io_error,
+            },
+        )?;
+        Ok(())
+    }
+}
+
+/// Returns whether the keys or values of maps of type `map_type` are file descriptors on update.
+///
+/// Lookups return program or map ids, socket cookies, or fail, so the entries can't be written
+/// back as they are read.
+const fn holds_fds(map_type: bpf_map_type) -> bool {
+    matches!(
+        map_type,
+        bpf_map_type::BPF_MAP_TYPE_PROG_ARRAY
+            | bpf_map_type::BPF_MAP_TYPE_PERF_EVENT_ARRAY
+            | bpf_map_type::BPF_MAP_TYPE_CGROUP_ARRAY
+            | bpf_map_type::BPF_MAP_TYPE_ARRAY_OF_MAPS
+            | bpf_map_type::BPF_MAP_TYPE_HASH_OF_MAPS
+            | bpf_map_type::BPF_MAP_TYPE_DEVMAP
+            | bpf_map_type::BPF_MAP_TYPE_DEVMAP_HASH
+            | bpf_map_type::BPF_MAP_TYPE_CPUMAP
+            | bpf_map_type::BPF_MAP_TYPE_XSKMAP
+            | bpf_map_type::BPF_MAP_TYPE_SOCKMAP
+            | bpf_map_type::BPF_MAP_TYPE_SOCKHASH
+            | bpf_map_type::BPF_MAP_TYPE_REUSEPORT_SOCKARRAY
+            | bpf_map_type::BPF_MAP_TYPE_SK_STORAGE
+            | bpf_map_type::BPF_MAP_TYPE_INODE_STORAGE
+            | bpf_map_type::BPF_MAP_TYPE_TASK_STORAGE
+            | bpf_map_type::BPF_MAP_TYPE_CGRP_STORAGE
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        ffi::CStr,
+        fs::File,
+        io,
+        os::fd::OwnedFd,
+        slice,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use assert_matches::assert_matches;
+    use aya_obj::generated::bpf_cmd;
+    use libc::{EFAULT, ENOENT};
+
+    use super::*;
+    use crate::{
+        maps::MapFd,
+        sys::{SysResult, Syscall, override_syscall},
+        util::nr_cpus,
+    };
+
+    fn map(obj: aya_obj::Map) -> MapData {
+        let fd = OwnedFd::from(File::open("/dev/null").unwrap());
+        MapData {
+            obj,
+            fd: MapFd::from_fd(fd.into()),
+        }
+    }
+
+    fn obj(map_type: bpf_map_type, value_size: u32, max_entries: u32) -> aya_obj::Map {
+        let mut obj =
+            crate::maps::test_utils::new_obj_map_with_max_entries::<u32>(map_type, max_entries);
+        obj.set_value_size(value_size);
+        obj
+    }
+
+    #[test]
+    fn test_mismatch() {
+        let pinned = map(obj(bpf_map_type::BPF_MAP_TYPE_HASH, 4, 16));
+        assert!(
+            pinned
+                .mismatch(&obj(bpf_map_type::BPF_MAP_TYPE_HASH, 4, 16))
+                .is_none()
+        );
+        let error = pinned
+            .mismatch(&obj(bpf_map_type::BPF_MAP_TYPE_HASH, 8, 32))
+            .unwrap()
+            .into_error("COUNTS");
+        assert_eq!(
+            error.to_string(),
+            "map `COUNTS` can't reuse the given map: its value size is 4, expected 8"
+        );
+
+        // The loader sets the size of perf event arrays.
+        let pinned = map(obj(bpf_map_type::BPF_MAP_TYPE_PERF_EVENT_ARRAY, 4, 4));
+        assert!(
+            pinned
+                .mismatch(&obj(bpf_map_type::BPF_MAP_TYPE_PERF_EVENT_ARRAY, 4, 0))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_resize_value() {
+        let pinned = map(obj(bpf_map_type::BPF_MAP_TYPE_HASH, 4, 16));
+        assert_eq!(
+            pinned.resize_value(&[1, 2, 3, 4], 6).unwrap(),
+            [1, 2, 3, 4, 0, 0]
+        );
+
+        let pinned = map(obj(bpf_map_type::BPF_MAP_TYPE_PERCPU_HASH, 4, 16));
+        let nr_cpus = nr_cpus().unwrap();
+        let value = [1, 2, 3, 4, 0, 0, 0, 0].repeat(nr_cpus);
+        assert_eq!(
+            pinned.resize_value(&value, 12).unwrap(),
+            [1, 2, 3, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0].repeat(nr_cpus)
+        );
+    }
+
+    /// Handles the syscalls of a migration from a map containing `7 => 42` and `8 => 43`, with
+    /// `u32` keys and values, passing the updates of the new map to `update`.
+    fn migration_syscalls(call: Syscall<'_>, update: fn(&[u8], &[u8])) -> SysResult {
+        match call {
+            Syscall::Ebpf {
+                cmd: bpf_cmd::BPF_OBJ_GET_INFO_BY_FD,
+                ..
+            } => Ok(0),
+            Syscall::Ebpf {
+                cmd: bpf_cmd::BPF_MAP_CREATE,
+                ..
+            } => Ok(crate::MockableFd::mock_signed_fd().into()),
+            Syscall::Ebpf {
+                cmd: bpf_cmd::BPF_MAP_GET_NEXT_KEY,
+                attr,
+            } => {
+                let u = unsafe { &attr.__bindgen_anon_2 };
+                let key = (u.key != 0).then(|| unsafe { *(u.key as *const u32) });
+                let next = match key {
+                    None => 7u32,
+                    Some(7) => 8,
+                    Some(_) => return Err((-1, io::Error::from_raw_os_error(ENOENT))),
+                };
+                unsafe { *(u.__bindgen_anon_1.next_key as *mut u32) = next }
+                Ok(0)
+            }
+            Syscall::Ebpf {
+                cmd: bpf_cmd::BPF_MAP_LOOKUP_ELEM,
+                attr,
+            } => {
+                let u = unsafe { &attr.__bindgen_anon_2 };
+                let key = unsafe { *(u.key as *const u32) };
+                unsafe { *(u.__bindgen_anon_1.value as *mut u32) = key + 35 }
+                Ok(0)
+            }
+            Syscall::Ebpf {
+                cmd: bpf_cmd::BPF_MAP_UPDATE_ELEM,
+                attr,
+            } => {
+                let u = unsafe { &attr.__bindgen_anon_2 };
+                let key = unsafe { slice::from_raw_parts(u.key as *const u8, 4) };
+                let value =
+                    unsafe { slice::from_raw_parts(u.__bindgen_anon_1.value as *const u8, 8) };
+                update(key, value);
+                Ok(0)
+            }
+            Syscall::Ebpf {
+                cmd: bpf_cmd::BPF_OBJ_PIN,
+                attr,
+            } => {
+                let u = unsafe { &attr.__bindgen_anon_4 };
+                let path = unsafe { CStr::from_ptr(u.pathname as *const std::ffi::c_char) };
+                File::create(path.to_str().unwrap()).unwrap();
+                Ok(0)
+            }
+            _ => Err((-1, io::Error::from_raw_os_error(EFAULT))),
+        }
+    }
+
+    #[test]
+    fn test_migrate_copy() {
+        static UPDATES: AtomicUsize = AtomicUsize::new(0);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("COUNTS");
+        File::create(&path).unwrap();
+        let pinned = map(obj(bpf_map_type::BPF_MAP_TYPE_HASH, 4, 16));
+
+        override_syscall(|call| {
+            migration_syscalls(call, |key, value| {
+                let key = u32::from_ne_bytes(key.try_into().unwrap());
+                let mut expected = (key + 35).to_ne_bytes().to_vec();
+                expected.resize(8, 0);
+                assert_eq!(value, expected);
+                UPDATES.fetch_add(1, Ordering::Relaxed);
+            })
+        });
+
+        let map = pinned
+            .migrate(
+                &path,
+                obj(bpf_map_type::BPF_MAP_TYPE_HASH, 8, 16),
+                "COUNTS",
+                None,
+                None,
+                &MapMigration::Copy,
+            )
+            .unwrap();
+        assert_eq!(map.obj.value_size(), 8);
+        assert_eq!(UPDATES.load(Ordering::Relaxed), 2);
+        assert!(path.exists());
+        assert!(!dir.path().join("COUNTS.new").exists());
+    }
+
+    #[test]
+    fn test_migrate_copy_shrunk_value() {
+        let pinned = map(obj(bpf_map_type::BPF_MAP_TYPE_HASH, 8, 16));
+
+        override_syscall(|_| Err((-1, io::Error::from_raw_os_error(EFAULT))));
+
+        assert_matches!(
+            pinned.migrate(
+                Path::new("/sys/fs/bpf/COUNTS"),
+                obj(bpf_map_type::BPF_MAP_TYPE_HASH, 4, 16),
+                "COUNTS",
+                None,
+                None,
+                &MapMigration::Copy,
+            ),
+            Err(MapError::IncompatibleMap {
+                field: "value size",
+                actual: 8,
+                expected: 4,
+                ..
+            })
+        );
+    }
+
+    #[test]
+    fn test_migrate_copy_fd_values() {
+        override_syscall(|_| panic!("unexpected syscall"));
+
+        for map_type in [
+            bpf_map_type::BPF_MAP_TYPE_PROG_ARRAY,
+            bpf_map_type::BPF_MAP_TYPE_ARRAY_OF_MAPS,
+            bpf_map_type::BPF_MAP_TYPE_HASH_OF_MAPS,
+            bpf_map_type::BPF_MAP_TYPE_SOCKMAP,
+            bpf_map_type::BPF_MAP_TYPE_SOCKHASH,
+        ] {
+            let convert = |key: &[u8], value: &[u8]| Some((key.to_vec(), value.to_vec()));
+            for migration in [MapMigration::Copy, MapMigration::Convert(&convert)] {
+                let pinned = map(obj(map_type, 4, 16));
+                assert_matches!(
+                    pinned.migrate(
+                        Path::new("/sys/fs/bpf/PROGS"),
+                        obj(map_type, 4, 32),
+                        "PROGS",
+                        None,
+                        None,
+                        &migration,
+                    ),
+                    Err(MapError::UnsupportedMigration { name, map_type: actual })
+                        if name == "PROGS" && actual == map_type
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_migrate_convert() {
+        static UPDATES: AtomicUsize = AtomicUsize::new(0);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("COUNTS");
+        File::create(&path).unwrap();
+        let pinned = map(obj(bpf_map_type::BPF_MAP_TYPE_HASH, 4, 16));
+
+        override_syscall(|call| {
+            migration_syscalls(call, |key, value| {
+                assert_eq!(key, 7u32.to_ne_bytes());
+                assert_eq!(value, 84u64.to_ne_bytes());
+                UPDATES.fetch_add(1, Ordering::Relaxed);
+            })
+        });
+
+        // Drops the entry at 8 and doubles the others.
+        let convert = |key: &[u8], value: &[u8]| {
+            (key != 8u32.to_ne_bytes()).then(|| {
+                let value = u32::from_ne_bytes(value.try_into().unwrap());
+                (key.to_vec(), (u64::from(value) * 2).to_ne_bytes().to_vec())
+            })
+        };
+        pinned
+            .migrate(
+                &path,
+                obj(bpf_map_type::BPF_MAP_TYPE_HASH, 8, 16),
+                "COUNTS",
+                None,
+                None,
+                &MapMigration::Convert(&convert),
+            )
+            .unwrap();
+        assert_eq!(UPDATES.load(Ordering::Relaxed), 1);
+        assert!(!dir.path().join("COUNTS.new").exists());
+    }
+
+    #[test]
+    fn test_migrate_rename_error() {
+        let dir = tempfile::tempdir().unwrap();
+        // A non-empty directory can't be replaced by the new pin.
+        let path = dir.path().join("COUNTS");
+        fs::create_dir(&path).unwrap();
+        File::create(path.join("entry")).unwrap();
+        let pinned = map(obj(bpf_map_type::BPF_MAP_TYPE_HASH, 4, 16));
+
+        override_syscall(|call| migration_syscalls(call, |_, _| panic!("unexpected update")));
+
+        assert_matches!(
+            pinned.migrate(
+                &path,
+                obj(bpf_map_type::BPF_MAP_TYPE_HASH, 4, 32),
+                "COUNTS",
+                None,
+                None,
+                &MapMigration::Replace,
+            ),
+            Err(MapError::IoError(_))
+        );
+        assert!(path.is_dir());
+        assert!(!dir.path().join("COUNTS.new").exists());
+    }
+}
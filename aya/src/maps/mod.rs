@@ -46,9 +46,9 @@
 //!
 //! Many map operations copy data from kernel space to user space and vice
 //! versa. Because of that, all map values must be plain old data and therefore
-//! implement the [Pod] trait.
+//! implement the [Pod] trait, usually with [`#[derive(Pod)]`](macro@crate::Pod).
 use std::{
-    borrow::Borrow,
+    borrow::{Borrow, Cow},
     ffi::CString,
     io,
     marker::PhantomData,
@@ -56,17 +56,24 @@ use std::{
     os::fd::{AsFd, BorrowedFd, OwnedFd},
     path::Path,
     ptr,
+    sync::OnceLock,
 };
 
-use aya_obj::{EbpfSectionKind, InvalidTypeBinding, generated::bpf_map_type, parse_map_info};
+use aya_obj::{
+    EbpfSectionKind, InvalidTypeBinding, btf::BtfError, generated::bpf_map_type, parse_map_info,
+};
+use log::warn;
 use thiserror::Error;
 
 use crate::{
     PinningType, Pod,
     pin::PinError,
     sys::{
-        SyscallError, bpf_create_map, bpf_get_object, bpf_map_freeze, bpf_map_get_fd_by_id,
-        bpf_map_get_next_key, bpf_map_update_elem_ptr, bpf_pin_object,
+        ObjectKind, SyscallError, bpf_create_map, bpf_get_object, bpf_get_object_of_kind,
+        bpf_map_delete_elem_raw, bpf_map_freeze, bpf_map_get_fd_by_id, bpf_map_get_next_key,
+        bpf_map_get_next_key_raw, bpf_map_lookup_elem_cpu, bpf_map_lookup_elem_per_cpu,
+        bpf_map_lookup_elem_raw, bpf_map_update_elem_ptr, bpf_map_update_elem_raw, bpf_pin_object,
+        is_map_lookup_cpu_supported,
     },
     util::nr_cpus,
 };
@@ -75,10 +82,13 @@ pub mod array;
 pub mod bloom_filter;
 pub mod cgroup_storage;
 pub mod cgrp_storage;
+mod dump;
+mod dynamic;
 pub mod hash_map;
 mod info;
 pub mod inode_storage;
 pub mod lpm_trie;
+mod migration;
 pub mod of_maps;
 pub mod perf;
 pub mod queue;
@@ -90,6 +100,7 @@ pub mod stack_trace;
 pub mod xdp;
 
 pub use array::{Array, CgroupArray, PerCpuArray, ProgramArray};
+pub use aya_macros::Aggregate;
 pub use bloom_filter::BloomFilter;
 #[expect(
     deprecated,
@@ -97,10 +108,14 @@ pub use bloom_filter::BloomFilter;
 )]
 pub use cgroup_storage::{CgroupStorage, CgroupStorageKey, PerCpuCgroupStorage};
 pub use cgrp_storage::CgrpStorage;
+pub use dump::{MapDump, MapEntry};
+pub use dynamic::DynamicMap;
+use dynamic::MapTypes;
 pub use hash_map::{HashMap, PerCpuHashMap};
 pub use info::{MapInfo, MapType, loaded_maps};
 pub use inode_storage::InodeStorage;
 pub use lpm_trie::LpmTrie;
+pub use migration::{ConvertEntry, MapMigration};
 pub use of_maps::{ArrayOfMaps, HashOfMaps};
 pub use perf::PerfEventArray;
 pub use queue::Queue;
@@ -196,6 +211,20 @@ pub enum MapError {
         expected: usize,
     },
 
+    /// A map reused with [`EbpfLoader::reuse_map`](crate::EbpfLoader::reuse_map), or pinned,
+    /// doesn't match the definition of the map in the object
+    #[error("map `{name}` can't reuse the given map: its {field} is {actual}, expected {expected}")]
+    IncompatibleMap {
+        /// Map name
+        name: String,
+        /// The mismatched attribute
+        field: &'static str,
+        /// The value of the reused map
+        actual: u32,
+        /// The value in the object
+        expected: u32,
+    },
+
     /// Invalid value stride
     #[error("invalid value size {size}, expected a non-zero multiple of {stride}")]
     InvalidValueStride {
@@ -214,6 +243,15 @@ pub enum MapError {
         max_entries: u32,
     },
 
+    /// CPU is out of bounds
+    #[error("the CPU is {cpu} but the number of possible CPUs is {nr_cpus}")]
+    CpuOutOfBounds {
+        /// CPU accessed
+        cpu: u32,
+        /// Number of possible CPUs
+        nr_cpus: u32,
+    },
+
     /// Key not found
     #[error("key not found")]
     KeyNotFound,
@@ -259,6 +297,17 @@ pub enum MapError {
         map_type: bpf_map_type,
     },
 
+    /// The entries of a map can't be migrated with [`MapMigration::Copy`] or
+    /// [`MapMigration::Convert`] because its keys or values are file descriptors, which the
+    /// kernel reads back as ids or cookies
+    #[error("the entries of map `{name}` ({map_type:?}) can't be copied to a new map")]
+    UnsupportedMigration {
+        /// Map name
+        name: String,
+        /// The map type
+        map_type: bpf_map_type,
+    },
+
     /// Unsupported map flags
     #[error("unsupported map flags {flags:#x}: {reason}")]
     UnsupportedMapFlags {
@@ -267,6 +316,17 @@ pub enum MapError {
         /// The reason
         reason: &'static str,
     },
+
+    /// An error occurred while working with BTF.
+    #[error(transparent)]
+    Btf(#[from] BtfError),
+
+    /// The network interface a map is offloaded to does not exist.
+    #[error("unknown network interface {name}")]
+    UnknownInterface {
+        /// interface name
+        name: String,
+    },
 }
 
 impl From<InvalidTypeBinding<u32>> for MapError {
@@ -403,6 +463,42 @@ impl Map {
         }
     }
 
+    /// Returns the underlying [`MapData`].
+    pub(crate) const fn map_data(&self) -> &MapData {
+        match self {
+            Self::Array(map) => map,
+            Self::ArrayOfMaps(map) => map,
+            Self::BloomFilter(map) => map,
+            Self::CgroupArray(map) => map,
+            Self::CgroupStorage(map) => map,
+            Self::CgrpStorage(map) => map,
+            Self::CpuMap(map) => map,
+            Self::DevMap(map) => map,
+            Self::DevMapHash(map) => map,
+            Self::HashMap(map) => map,
+            Self::HashOfMaps(map) => map,
+            Self::InodeStorage(map) => map,
+            Self::LpmTrie(map) => map,
+            Self::LruHashMap(map) => map,
+            Self::PerCpuArray(map) => map,
+            Self::PerCpuCgroupStorage(map) => map,
+            Self::PerCpuHashMap(map) => map,
+            Self::PerCpuLruHashMap(map) => map,
+            Self::PerfEventArray(map) => map,
+            Self::ProgramArray(map) => map,
+            Self::Queue(map) => map,
+            Self::RingBuf(map) => map,
+            Self::ReusePortSockArray(map) => map,
+            Self::SockHash(map) => map,
+            Self::SockMap(map) => map,
+            Self::SkStorage(map) => map,
+            Self::Stack(map) => map,
+            Self::StackTraceMap(map) => map,
+            Self::Unsupported(map) => map,
+            Self::XskMap(map) => map,
+        }
+    }
+
     /// Pins the map to a BPF filesystem.
     ///
     /// When a map is pinned it will remain loaded until the corresponding file
@@ -527,6 +623,7 @@ macro_rules! impl_map_pin {
 
 impl_map_pin!(() {
     CgroupArray,
+    DynamicMap,
     ProgramArray,
     ReusePortSockArray,
     SockMap,
@@ -617,6 +714,7 @@ impl_try_from_map!(() {
     CpuMap,
     DevMap,
     DevMapHash,
+    DynamicMap from Array|HashMap|LruHashMap|LpmTrie|PerCpuArray|PerCpuHashMap|PerCpuLruHashMap,
     PerfEventArray,
     ProgramArray,
     ReusePortSockArray,
@@ -777,7 +875,10 @@ pub(crate) const fn check_bounds(map: &MapData, index: u32) -> Result<(), MapErr
     }
 }
 
-pub(crate) const fn check_kv_size<K, V>(map: &MapData) -> Result<(), MapError> {
+/// Checks the key and value sizes of a typed map, and that `K` and `V` have no implicit padding.
+pub(crate) const fn check_kv_size<K: Pod, V: Pod>(map: &MapData) -> Result<(), MapError> {
+    let () = K::ASSERT_NO_PADDING;
+    let () = V::ASSERT_NO_PADDING;
     let size = size_of::<K>();
     let expected = map.obj.key_size() as usize;
     if size != expected {
@@ -791,7 +892,9 @@ pub(crate) const fn check_kv_size<K, V>(map: &MapData) -> Result<(), MapError> {
     Ok(())
 }
 
-pub(crate) const fn check_v_size<V>(map: &MapData) -> Result<(), MapError> {
+/// Checks the value size of a typed map, and that `V` has no implicit padding.
+pub(crate) const fn check_v_size<V: Pod>(map: &MapData) -> Result<(), MapError> {
+    let () = V::ASSERT_NO_PADDING;
     let size = size_of::<V>();
     let expected = map.obj.value_size() as usize;
     if size != expected {
@@ -816,15 +919,17 @@ impl MapData {
         name: &str,
         btf_fd: Option<BorrowedFd<'_>>,
     ) -> Result<Self, MapError> {
-        Self::create_with_inner_map_fd(obj, name, btf_fd, None)
+        Self::create_with_inner_map_fd(obj, name, btf_fd, None, None)
     }
 
-    /// Creates a new map with the provided `name` and optional `inner_map_fd` for map-of-maps types.
+    /// Creates a new map with the provided `name` and optional `inner_map_fd` for map-of-maps types,
+    /// offloaded to the network interface `map_ifindex` if set.
     pub(crate) fn create_with_inner_map_fd(
         mut obj: aya_obj::Map,
         name: &str,
         btf_fd: Option<BorrowedFd<'_>>,
         inner_map_fd: Option<BorrowedFd<'_>>,
+        map_ifindex: Option<u32>,
     ) -> Result<Self, MapError> {
         let c_name = CString::new(name)
             .map_err(|std::ffi::NulError { .. }| MapError::InvalidName { name: name.into() })?;
@@ -847,12 +952,12 @@ impl MapData {
             }
         }
 
-        let fd = bpf_create_map(&c_name, &obj, btf_fd, inner_map_fd).map_err(|io_error| {
-            MapError::CreateError {
+        let fd = bpf_create_map(&c_name, &obj, btf_fd, inner_map_fd, map_ifindex).map_err(
+            |io_error| MapError::CreateError {
                 name: name.into(),
                 io_error,
-            }
-        })?;
+            },
+        )?;
         Ok(Self {
             obj,
             fd: MapFd::from_fd(fd),
@@ -865,6 +970,8 @@ impl MapData {
         name: &str,
         btf_fd: Option<BorrowedFd<'_>>,
         inner_map_obj: Option<aya_obj::Map>,
+        migration: &MapMigration<'_>,
+        map_ifindex: Option<u32>,
     ) -> Result<Self, MapError> {
         use std::os::unix::ffi::OsStrExt as _;
 
@@ -883,10 +990,25 @@ impl MapData {
             }
         };
         if let Ok(fd) = bpf_get_object(&path_string) {
-            Ok(Self {
-                obj,
-                fd: MapFd::from_fd(fd),
-            })
+            // Offloaded programs can only use maps offloaded to the same device.
+            if let Some(expected) = map_ifindex {
+                let actual = MapInfo::new_from_fd(fd.as_fd())?.ifindex();
+                if actual != map_ifindex {
+                    return Err(MapError::IncompatibleMap {
+                        name: name.into(),
+                        field: "ifindex",
+                        actual: actual.unwrap_or(0),
+                        expected,
+                    });
+                }
+            }
+            if matches!(migration, MapMigration::Keep) {
+                return Ok(Self {
+                    obj,
+                    fd: MapFd::from_fd(fd),
+                });
+            }
+            Self::from_fd_inner(fd)?.migrate(path, obj, name, btf_fd, inner_map_obj, migration)
         } else {
             let inner_map;
             let inner_map_fd = if let Some(inner) = inner_map_obj {
@@ -895,7 +1017,7 @@ impl MapData {
             } else {
                 None
             };
-            let map = Self::create_with_inner_map_fd(obj, name, btf_fd, inner_map_fd)?;
+            let map = Self::create_with_inner_map_fd(obj, name, btf_fd, inner_map_fd, map_ifindex)?;
             map.pin(path).map_err(|error| MapError::PinError {
                 name: Some(name.into()),
                 error,
@@ -904,6 +1026,37 @@ impl MapData {
         }
     }
 
+    pub(crate) fn try_clone(&self) -> Result<Self, MapError> {
+        let Self { obj, fd } = self;
+        Ok(Self {
+            obj: obj.clone(),
+            fd: fd.try_clone()?,
+        })
+    }
+
+    /// Shares this map with the map `name` of another object, defined by `obj`.
+    ///
+    /// The definition of `obj` must be compatible with this map, see
+    /// [`incompatibility`](Self::incompatibility). The `max_entries` of this map is kept.
+    pub(crate) fn reuse(&self, mut obj: aya_obj::Map, name: &str) -> Result<Self, MapError> {
+        if let Some(mismatch) = self.incompatibility(&obj) {
+            return Err(mismatch.into_error(name));
+        }
+        let Self { obj: existing, fd } = self;
+        if existing.max_entries() != obj.max_entries() {
+            warn!(
+                "map `{name}` reuses a map with max_entries {}, expected {}",
+                existing.max_entries(),
+                obj.max_entries()
+            );
+            obj.set_max_entries(existing.max_entries());
+        }
+        Ok(Self {
+            obj,
+            fd: fd.try_clone()?,
+        })
+    }
+
     pub(crate) fn finalize(&mut self) -> Result<(), MapError> {
         let Self { obj, fd } = self;
         if !obj.data().is_empty() {
@@ -926,6 +1079,8 @@ impl MapData {
     }
 
     /// Loads a map from a pinned path in bpffs.
+    ///
+    /// Fails with `EINVAL` if the object pinned at `path` isn't a map.
     pub fn from_pin<P: AsRef<Path>>(path: P) -> Result<Self, MapError> {
         use std::os::unix::ffi::OsStrExt as _;
 
@@ -939,9 +1094,11 @@ impl MapData {
                 },
             })?;
 
-        let fd = bpf_get_object(&path_string).map_err(|io_error| SyscallError {
-            call: "BPF_OBJ_GET",
-            io_error,
+        let fd = bpf_get_object_of_kind(&path_string, ObjectKind::Map).map_err(|io_error| {
+            SyscallError {
+                call: "BPF_OBJ_GET",
+                io_error,
+            }
         })?;
 
         Self::from_fd_inner(fd)
@@ -1028,6 +1185,194 @@ impl MapData {
     pub fn info(&self) -> Result<MapInfo, MapError> {
         MapInfo::new_from_fd(self.fd.as_fd())
     }
+
+    /// Returns an iterator over the keys of the map as raw bytes.
+    ///
+    /// This is useful for inspecting maps whose key type is not known at compile time, for
+    /// example maps obtained with [`MapData::from_id`] or [`MapData::from_pin`].
+    pub const fn raw_keys(&self) -> RawMapKeys<'_> {
+        RawMapKeys::new(self)
+    }
+
+    /// Returns the value stored at `key` as raw bytes.
+    ///
+    /// For per-CPU maps the returned buffer contains one value per possible CPU, each padded
+    /// to a multiple of 8 bytes, as laid out by the kernel.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`MapError::InvalidKeySize`] if `key` is not exactly `key_size` bytes long,
+    /// [`MapError::KeyNotFound`] if the key doesn't exist in the map or
+    /// [`MapError::SyscallError`] if `bpf_map_lookup_elem` fails.
+    pub fn raw_lookup(&self, key: &[u8], flags: u64) -> Result<Vec<u8>, MapError> {
+        let Self { obj: _, fd } = self;
+        self.check_raw_key_size(key)?;
+        let mut value = vec![0u8; self.raw_value_size()?];
+        bpf_map_lookup_elem_raw(fd.as_fd(), key, &mut value, flags)
+            .map_err(|io_error| SyscallError {
+                call: "bpf_map_lookup_elem",
+                io_error,
+            })?
+            .ok_or(MapError::KeyNotFound)?;
+        Ok(value)
+    }
+
+    /// Inserts or updates the value stored at `key` from raw bytes.
+    ///
+    /// For per-CPU maps `value` must contain one value per possible CPU, laid out as returned by
+    /// [`MapData::raw_lookup`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`MapError::InvalidKeySize`] or [`MapError::InvalidValueSize`] if `key` or
+    /// `value` don't have the expected size, or [`MapError::SyscallError`] if
+    /// `bpf_map_update_elem` fails.
+    pub fn raw_insert(&mut self, key: &[u8], value: &[u8], flags: u64) -> Result<(), MapError> {
+        self.check_raw_key_size(key)?;
+        let expected = self.raw_value_size()?;
+        if value.len() != expected {
+            return Err(MapError::InvalidValueSize {
+                size: value.len(),
+                expected,
+            });
+        }
+        let Self { obj: _, fd } = self;
+        bpf_map_update_elem_raw(fd.as_fd(), key, value, flags).map_err(|io_error| {
+            SyscallError {
+                call: "bpf_map_update_elem",
+                io_error,
+            }
+        })?;
+        Ok(())
+    }
+
+    /// Removes the value stored at `key`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`MapError::InvalidKeySize`] if `key` is not exactly `key_size` bytes long or
+    /// [`MapError::SyscallError`] if `bpf_map_delete_elem` fails.
+    pub fn raw_remove(&mut self, key: &[u8]) -> Result<(), MapError> {
+        self.check_raw_key_size(key)?;
+        let Self { obj: _, fd } = self;
+        bpf_map_delete_elem_raw(fd.as_fd(), key).map_err(|io_error| SyscallError {
+            call: "bpf_map_delete_elem",
+            io_error,
+        })?;
+        Ok(())
+    }
+
+    /// Returns an iterator over the entries of the map, decoded using the map's BTF.
+    ///
+    /// Keys and values are decoded as the BTF key and value types the map was created with, see
+    /// [`Btf::decode_value`](crate::Btf::decode_value). Maps without BTF yield
+    /// [`BtfValue::Bytes`](crate::BtfValue::Bytes). Values of per-CPU maps are arrays with
+    /// one element per possible CPU.
+    ///
+    /// With the `serde` feature enabled, the yielded [`MapEntry`] values can be serialized.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use aya::maps::MapData;
+    /// let map = MapData::from_pin("/sys/fs/bpf/my_map")?;
+    /// for entry in map.dump()? {
+    ///     let entry = entry?;
+    ///     println!("{} => {}", entry.key, entry.value);
+    /// }
+    /// # Ok::<(), aya::maps::MapError>(())
+    /// ```
+    pub fn dump(&self) -> Result<MapDump<'_>, MapError> {
+        Ok(MapDump::new(self, Cow::Owned(MapTypes::new(self)?)))
+    }
+
+    const fn check_raw_key_size(&self, key: &[u8]) -> Result<(), MapError> {
+        let Self { obj, fd: _ } = self;
+        let expected = obj.key_size() as usize;
+        if key.len() != expected {
+            return Err(MapError::InvalidKeySize {
+                size: key.len(),
+                expected,
+            });
+        }
+        Ok(())
+    }
+
+    /// Returns whether the kernel stores one value per possible CPU for each key.
+    fn is_per_cpu(&self) -> Result<bool, MapError> {
+        let Self { obj, fd: _ } = self;
+        let map_type = bpf_map_type::try_from(obj.map_type())?;
+        Ok(matches!(
+            map_type,
+            bpf_map_type::BPF_MAP_TYPE_PERCPU_HASH
+                | bpf_map_type::BPF_MAP_TYPE_PERCPU_ARRAY
+                | bpf_map_type::BPF_MAP_TYPE_LRU_PERCPU_HASH
+                | bpf_map_type::BPF_MAP_TYPE_PERCPU_CGROUP_STORAGE
+        ))
+    }
+
+    /// Returns the size in bytes of the buffer the kernel fills in on lookup.
+    fn raw_value_size(&self) -> Result<usize, MapError> {
+        let Self { obj, fd: _ } = self;
+        let value_size = obj.value_size() as usize;
+        Ok(if self.is_per_cpu()? {
+            let nr_cpus = nr_cpus().map_err(|(_, error)| MapError::IoError(error))?;
+            value_size.next_multiple_of(8) * nr_cpus
+        } else {
+            value_size
+        })
+    }
+}
+
+/// Iterator returned by [`MapData::raw_keys`].
+pub struct RawMapKeys<'coll> {
+    map: &'coll MapData,
+    err: bool,
+    key: Option<Vec<u8>>,
+}
+
+impl<'coll> RawMapKeys<'coll> {
+    const fn new(map: &'coll MapData) -> Self {
+        Self {
+            map,
+            err: false,
+            key: None,
+        }
+    }
+}
+
+impl Iterator for RawMapKeys<'_> {
+    type Item = Result<Vec<u8>, MapError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.err {
+            return None;
+        }
+
+        let fd = self.map.fd().as_fd();
+        let mut next_key = vec![0u8; self.map.obj().key_size() as usize];
+        let key =
+            bpf_map_get_next_key_raw(fd, self.key.as_deref(), &mut next_key).map_err(|io_error| {
+                SyscallError {
+                    call: "bpf_map_get_next_key",
+                    io_error,
+                }
+            });
+        match key {
+            Err(err) => {
+                self.err = true;
+                Some(Err(err.into()))
+            }
+            Ok(None) => {
+                self.key = None;
+                None
+            }
+            Ok(Some(())) => {
+                self.key = Some(next_key.clone());
+                Some(Ok(next_key))
+            }
+        }
+    }
 }
 
 /// An iterable map
@@ -1216,6 +1561,159 @@ impl<T: Pod> Deref for PerCpuValues<T> {
     }
 }
 
+impl<T: Aggregate> PerCpuValues<T> {
+    /// Returns the sum of the values of all the CPUs.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let bpf = aya::Ebpf::load(&[])?;
+    /// use aya::maps::PerCpuArray;
+    ///
+    /// let array = PerCpuArray::<_, u64>::try_from(bpf.map("COUNTERS").unwrap())?;
+    /// let total = array.get(&0, 0)?.sum();
+    /// # Ok::<(), aya::EbpfError>(())
+    /// ```
+    pub fn sum(&self) -> T {
+        self.fold(T::sum_with)
+    }
+
+    /// Returns the minimum of the values of all the CPUs.
+    pub fn min(&self) -> T {
+        self.fold(T::min_with)
+    }
+
+    /// Returns the maximum of the values of all the CPUs.
+    pub fn max(&self) -> T {
+        self.fold(T::max_with)
+    }
+
+    fn fold(&self, f: impl Fn(&mut T, &T)) -> T {
+        // There is always at least one possible CPU.
+        let (first, rest) = self.values.split_first().unwrap();
+        rest.iter().fold(*first, |mut acc, value| {
+            f(&mut acc, value);
+            acc
+        })
+    }
+}
+
+/// A per-CPU value which can be aggregated across CPUs.
+///
+/// Implemented for integers, where sums wrap around on overflow, and for arrays of aggregatable
+/// values. Structs can derive it to aggregate field by field.
+///
+/// # Examples
+///
+/// ```
+/// use aya::{Pod, maps::Aggregate};
+///
+/// #[derive(Clone, Copy, Debug, PartialEq, Eq, Pod, Aggregate)]
+/// #[repr(C)]
+/// struct Counters {
+///     packets: u64,
+///     bytes: u64,
+/// }
+///
+/// let mut total = Counters {
+///     packets: 1,
+///     bytes: 100,
+/// };
+/// total.sum_with(&Counters {
+///     packets: 2,
+///     bytes: 50,
+/// });
+/// assert_eq!(
+///     total,
+///     Counters {
+///         packets: 3,
+///         bytes: 150,
+///     }
+/// );
+/// ```
+pub trait Aggregate: Pod {
+    /// Adds `other` to `self`.
+    fn sum_with(&mut self, other: &Self);
+
+    /// Replaces `self` with `other` if `other` is smaller.
+    fn min_with(&mut self, other: &Self);
+
+    /// Replaces `self` with `other` if `other` is greater.
+    fn max_with(&mut self, other: &Self);
+}
+
+macro_rules! impl_aggregate {
+    ($($t:ty),+ $(,)?) => {
+        $(
+            impl Aggregate for $t {
+                fn sum_with(&mut self, other: &Self) {
+                    *self = self.wrapping_add(*other);
+                }
+
+                fn min_with(&mut self, other: &Self) {
+                    *self = Ord::min(*self, *other);
+                }
+
+                fn max_with(&mut self, other: &Self) {
+                    *self = Ord::max(*self, *other);
+                }
+            }
+        )+
+    }
+}
+
+impl_aggregate!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128);
+
+impl<T: Aggregate, const N: usize> Aggregate for [T; N] {
+    fn sum_with(&mut self, other: &Self) {
+        self.iter_mut()
+            .zip(other)
+            .for_each(|(value, other)| value.sum_with(other));
+    }
+
+    fn min_with(&mut self, other: &Self) {
+        self.iter_mut()
+            .zip(other)
+            .for_each(|(value, other)| value.min_with(other));
+    }
+
+    fn max_with(&mut self, other: &Self) {
+        self.iter_mut()
+            .zip(other)
+            .for_each(|(value, other)| value.max_with(other));
+    }
+}
+
+/// Looks up the value of a single CPU of a per-CPU map.
+///
+/// Uses `BPF_F_CPU` when the kernel supports it, and otherwise looks up the values of all the
+/// CPUs.
+pub(crate) fn lookup_cpu<K: Pod, V: Pod>(
+    map: &MapData,
+    key: &K,
+    cpu: u32,
+    flags: u64,
+) -> Result<V, MapError> {
+    static CPU_LOOKUP_SUPPORTED: OnceLock<bool> = OnceLock::new();
+
+    let nr_cpus = nr_cpus().map_err(|(_, error)| MapError::IoError(error))? as u32;
+    if cpu >= nr_cpus {
+        return Err(MapError::CpuOutOfBounds { cpu, nr_cpus });
+    }
+    let fd = map.fd().as_fd();
+    let value = if *CPU_LOOKUP_SUPPORTED.get_or_init(is_map_lookup_cpu_supported) {
+        bpf_map_lookup_elem_cpu(fd, key, cpu, flags)
+    } else {
+        bpf_map_lookup_elem_per_cpu(fd, key, flags)
+            .map(|values| values.map(|values: PerCpuValues<V>| values[cpu as usize]))
+    }
+    .map_err(|io_error| SyscallError {
+        call: "bpf_map_lookup_elem",
+        io_error,
+    })?;
+    value.ok_or(MapError::KeyNotFound)
+}
+
 #[cfg(test)]
 mod test_utils {
     use aya_obj::{
@@ -1284,11 +1782,17 @@ mod tests {
     use std::{ffi::c_char, os::fd::AsRawFd as _};
 
     use assert_matches::assert_matches;
-    use aya_obj::generated::{bpf_cmd, bpf_map_info};
+    use aya_obj::{
+        generated::{BPF_F_NO_PREALLOC, bpf_cmd, bpf_map_info},
+        maps::LegacyMap,
+    };
     use libc::EFAULT;
 
     use super::*;
-    use crate::sys::{Syscall, override_syscall};
+    use crate::{
+        bpf_map_def,
+        sys::{Syscall, override_syscall},
+    };
 
     fn new_obj_map() -> aya_obj::Map {
         test_utils::new_obj_map::<u32>(bpf_map_type::BPF_MAP_TYPE_HASH)
@@ -1348,6 +1852,139 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_reuse() {
+        let map = MapData {
+            obj: test_utils::new_obj_map_with_max_entries::<u32>(
+                bpf_map_type::BPF_MAP_TYPE_HASH,
+                16,
+            ),
+            fd: MapFd::from_fd(OwnedFd::from(std::fs::File::open("/dev/null").unwrap()).into()),
+        };
+
+        let reused = map.reuse(new_obj_map(), "foo").unwrap();
+        assert_ne!(reused.fd.as_fd().as_raw_fd(), map.fd.as_fd().as_raw_fd());
+        assert_eq!(reused.obj.max_entries(), 16);
+
+        assert_matches!(
+            map.reuse(test_utils::new_obj_map::<u64>(bpf_map_type::BPF_MAP_TYPE_HASH), "foo"),
+            Err(MapError::IncompatibleMap {
+                name,
+                field: "key size",
+                actual: 4,
+                expected: 8,
+            }) if name == "foo"
+        );
+        assert_matches!(
+            map.reuse(
+                test_utils::new_obj_map::<u32>(bpf_map_type::BPF_MAP_TYPE_ARRAY),
+                "foo"
+            ),
+            Err(MapError::IncompatibleMap { field: "type", .. })
+        );
+        assert_matches!(
+            map.reuse(
+                aya_obj::Map::new_from_params(
+                    bpf_map_type::BPF_MAP_TYPE_HASH as u32,
+                    4,
+                    4,
+                    16,
+                    BPF_F_NO_PREALLOC,
+                ),
+                "foo"
+            ),
+            Err(MapError::IncompatibleMap {
+                field: "flags",
+                actual: 0,
+                expected: 1,
+                ..
+            })
+        );
+    }
+
+    #[test]
+    fn test_reuse_map_of_maps() {
+        let outer = |inner_value_size| {
+            aya_obj::Map::Legacy(LegacyMap {
+                def: bpf_map_def {
+                    map_type: bpf_map_type::BPF_MAP_TYPE_HASH_OF_MAPS as u32,
+                    key_size: 4,
+                    value_size: 4,
+                    max_entries: 16,
+                    ..Default::default()
+                },
+                inner_def: Some(bpf_map_def {
+                    map_type: bpf_map_type::BPF_MAP_TYPE_ARRAY as u32,
+                    key_size: 4,
+                    value_size: inner_value_size,
+                    max_entries: 1,
+                    ..Default::default()
+                }),
+                section_index: 0,
+                section_kind: EbpfSectionKind::Maps,
+                data: Vec::new(),
+                symbol_index: None,
+            })
+        };
+        let map = MapData {
+            obj: outer(4),
+            fd: MapFd::from_fd(OwnedFd::from(std::fs::File::open("/dev/null").unwrap()).into()),
+        };
+
+        assert_matches!(map.reuse(outer(4), "foo"), Ok(_));
+        assert_matches!(
+            map.reuse(outer(8), "foo"),
+            Err(MapError::IncompatibleMap {
+                field: "inner map value size",
+                actual: 4,
+                expected: 8,
+                ..
+            })
+        );
+    }
+
+    #[test]
+    fn test_create_pinned_offloaded() {
+        override_syscall(|call| match call {
+            Syscall::Ebpf {
+                cmd: bpf_cmd::BPF_OBJ_GET,
+                ..
+            } => Ok(crate::MockableFd::mock_signed_fd().into()),
+            Syscall::Ebpf {
+                cmd: bpf_cmd::BPF_OBJ_GET_INFO_BY_FD,
+                attr,
+            } => {
+                let info = unsafe { &mut *(attr.info.info as *mut bpf_map_info) };
+                info.ifindex = 3;
+                Ok(0)
+            }
+            _ => Err((-1, io::Error::from_raw_os_error(EFAULT))),
+        });
+
+        let create = |map_ifindex| {
+            MapData::create_pinned_by_name(
+                "/sys/fs/bpf/foo",
+                new_obj_map(),
+                "foo",
+                None,
+                None,
+                &MapMigration::Keep,
+                Some(map_ifindex),
+            )
+        };
+        assert_matches!(create(3), Ok(_));
+        // The pinned map is offloaded to another device.
+        assert_matches!(
+            create(4),
+            Err(MapError::IncompatibleMap {
+                field: "ifindex",
+                actual: 3,
+                expected: 4,
+                ..
+            })
+        );
+    }
+
     #[test]
     fn test_create_perf_event_array() {
         override_syscall(|call| match call {
@@ -1551,4 +2188,124 @@ mod tests {
 
         assert_matches!(map.pin("/sys/fs/bpf/socket_map"), Ok(()));
     }
+
+    #[test]
+    fn test_raw_keys_and_lookup() {
+        let map = test_utils::new_map(new_obj_map());
+
+        override_syscall(|call| match call {
+            Syscall::Ebpf {
+                cmd: bpf_cmd::BPF_MAP_GET_NEXT_KEY,
+                attr,
+            } => {
+                let u = unsafe { &attr.__bindgen_anon_2 };
+                let key = (u.key != 0).then(|| unsafe { *(u.key as *const u32) });
+                let next = match key {
+                    None => 10u32,
+                    Some(10) => 20,
+                    Some(_) => return Err((-1, io::Error::from_raw_os_error(libc::ENOENT))),
+                };
+                unsafe { *(u.__bindgen_anon_1.next_key as *mut u32) = next }
+                Ok(0)
+            }
+            Syscall::Ebpf {
+                cmd: bpf_cmd::BPF_MAP_LOOKUP_ELEM,
+                attr,
+            } => {
+                let u = unsafe { &attr.__bindgen_anon_2 };
+                let key = unsafe { *(u.key as *const u32) };
+                if key != 20 {
+                    return Err((-1, io::Error::from_raw_os_error(libc::ENOENT)));
+                }
+                unsafe { *(u.__bindgen_anon_1.value as *mut u32) = 200 }
+                Ok(0)
+            }
+            _ => Err((-1, io::Error::from_raw_os_error(EFAULT))),
+        });
+
+        let keys = map.raw_keys().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(keys, [10u32.to_ne_bytes(), 20u32.to_ne_bytes()]);
+        assert_eq!(
+            map.raw_lookup(&20u32.to_ne_bytes(), 0).unwrap(),
+            200u32.to_ne_bytes()
+        );
+        assert_matches!(
+            map.raw_lookup(&10u32.to_ne_bytes(), 0),
+            Err(MapError::KeyNotFound)
+        );
+        assert_matches!(
+            map.raw_lookup(&[0u8; 2], 0),
+            Err(MapError::InvalidKeySize {
+                size: 2,
+                expected: 4
+            })
+        );
+    }
+
+    #[test]
+    fn test_per_cpu_values_aggregate() {
+        let nr_cpus = nr_cpus().unwrap();
+        // Values go from 1 to 255, and start over on machines with more CPUs.
+        let value = |cpu: usize| (cpu % usize::from(u8::MAX)) as u8 + 1;
+        let values = PerCpuValues::try_from(
+            (0..nr_cpus)
+                .map(|cpu| [value(cpu), u8::MAX])
+                .collect::<Vec<_>>(),
+        )
+        .unwrap();
+
+        let expected_sum = (0..nr_cpus).fold(0u8, |sum, cpu| sum.wrapping_add(value(cpu)));
+        let expected_max = u8::MAX.wrapping_mul(nr_cpus as u8);
+        assert_eq!(values.sum(), [expected_sum, expected_max]);
+        assert_eq!(values.min(), [1, u8::MAX]);
+        assert_eq!(
+            values.max(),
+            [nr_cpus.min(usize::from(u8::MAX)) as u8, u8::MAX]
+        );
+    }
+
+    #[test]
+    fn test_lookup_cpu() {
+        const BPF_F_CPU: u64 = 8;
+
+        let map = test_utils::new_map(test_utils::new_obj_map::<u32>(
+            bpf_map_type::BPF_MAP_TYPE_PERCPU_HASH,
+        ));
+
+        // Answer both the single CPU and the all CPUs lookups, as whether `BPF_F_CPU` is
+        // supported is probed once per process.
+        override_syscall(|call| match call {
+            Syscall::Ebpf {
+                cmd: bpf_cmd::BPF_MAP_CREATE,
+                ..
+            } => Ok(crate::MockableFd::mock_signed_fd().into()),
+            Syscall::Ebpf {
+                cmd: bpf_cmd::BPF_MAP_LOOKUP_ELEM,
+                attr,
+            } => {
+                let u = unsafe { &attr.__bindgen_anon_2 };
+                let value = unsafe { u.__bindgen_anon_1.value } as *mut u64;
+                if u.flags & BPF_F_CPU != 0 {
+                    let cpu = u.flags >> 32;
+                    unsafe { value.cast::<u32>().write_unaligned(cpu as u32 * 10) }
+                } else {
+                    for cpu in 0..nr_cpus().unwrap() {
+                        unsafe { value.add(cpu).write_unaligned(cpu as u64 * 10) }
+                    }
+                }
+                Ok(0)
+            }
+            _ => Err((-1, io::Error::from_raw_os_error(EFAULT))),
+        });
+
+        let last_cpu = nr_cpus().unwrap() as u32 - 1;
+        assert_eq!(
+            lookup_cpu::<u32, u32>(&map, &1, last_cpu, 0).unwrap(),
+            last_cpu * 10
+        );
+        assert_matches!(
+            lookup_cpu::<u32, u32>(&map, &1, last_cpu + 1, 0),
+            Err(MapError::CpuOutOfBounds { cpu, nr_cpus }) if cpu == nr_cpus
+        );
+    }
 }
@@ -2,6 +2,7 @@
 mod reuseport_sock_array;
 mod sock_hash;
 mod sock_map;
+mod sock_pairs;
 
 use std::{
     io,
@@ -11,6 +12,9 @@ use std::{
 pub use reuseport_sock_array::ReusePortSockArray;
 pub use sock_hash::SockHash;
 pub use sock_map::SockMap;
+pub use sock_pairs::{
+    Ipv4SockKey, SockPairId, SockPairKey, SockPairStats, SockPairs, SockPairsError, SockStats,
+};
 
 /// A socket map file descriptor.
 #[repr(transparent)]
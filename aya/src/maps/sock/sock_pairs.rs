@@ -0,0 +1,489 @@
+//! Pairs of TCP sockets redirecting data to each other through a [`SockHash`].
+
+use std::{
+    borrow::BorrowMut,
+    collections::{HashMap, hash_map::Entry},
+    io, mem,
+    net::{SocketAddr, TcpStream},
+    os::fd::{AsFd as _, AsRawFd as _, BorrowedFd},
+};
+
+use libc::{
+    FIONREAD, IPPROTO_TCP, POLLERR, POLLHUP, POLLRDHUP, TCP_INFO, getsockopt, ioctl, poll, pollfd,
+    tcp_info,
+};
+use thiserror::Error;
+
+use crate::{
+    Pod,
+    maps::{MapData, MapError, sock::SockHash},
+    sys::SyscallError,
+};
+
+/// Errors from [`SockPairs`].
+#[derive(Debug, Error)]
+pub enum SockPairsError {
+    /// A map operation failed.
+    #[error(transparent)]
+    MapError(#[from] MapError),
+
+    /// The addresses of a socket can't be retrieved.
+    #[error("failed to get the addresses of the socket")]
+    Addresses(#[source] io::Error),
+
+    /// The key type doesn't support the addresses of a socket.
+    #[error("the key type doesn't support the socket addresses {local} -> {remote}")]
+    UnsupportedAddresses {
+        /// The local address of the socket.
+        local: SocketAddr,
+        /// The remote address of the socket.
+        remote: SocketAddr,
+    },
+
+    /// A syscall failed.
+    #[error(transparent)]
+    SyscallError(#[from] SyscallError),
+
+    /// The pair isn't in [`SockPairs`].
+    #[error("unknown socket pair {id:?}")]
+    UnknownPair {
+        /// The identifier of the pair.
+        id: SockPairId,
+    },
+}
+
+/// A [`SockHash`] key computed from the addresses of a socket.
+///
+/// The eBPF programs attached to the map compute the same key from their context, such as
+/// `sk_msg_md` for [`SkMsg`](crate::programs::SkMsg) programs, to look up the peer of the
+/// socket they run on.
+pub trait SockPairKey: Pod {
+    /// Returns the key of the socket with the local address `local` connected to `remote`, or
+    /// `None` if the addresses are not supported.
+    fn from_addrs(local: SocketAddr, remote: SocketAddr) -> Option<Self>;
+}
+
+/// A key made of the IPv4 addresses and ports of a socket, in host byte order.
+///
+/// The eBPF side defines the same `#[repr(C)]` struct and fills it from `sk_msg_md` or
+/// `__sk_buff` with:
+///
+/// ```ignore
+/// let key = Ipv4SockKey {
+///     remote_ip4: u32::from_be(md.remote_ip4),
+///     local_ip4: u32::from_be(md.local_ip4),
+///     remote_port: u32::from_be(md.remote_port),
+///     local_port: md.local_port,
+/// };
+/// ```
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct Ipv4SockKey {
+    /// The remote IPv4 address.
+    pub remote_ip4: u32,
+    /// The local IPv4 address.
+    pub local_ip4: u32,
+    /// The remote port.
+    pub remote_port: u32,
+    /// The local port.
+    pub local_port: u32,
+}
+
+unsafe impl Pod for Ipv4SockKey {}
+
+impl SockPairKey for Ipv4SockKey {
+    fn from_addrs(local: SocketAddr, remote: SocketAddr) -> Option<Self> {
+        match (local, remote) {
+            (SocketAddr::V4(local), SocketAddr::V4(remote)) => Some(Self {
+                remote_ip4: (*remote.ip()).into(),
+                local_ip4: (*local.ip()).into(),
+                remote_port: remote.port().into(),
+                local_port: local.port().into(),
+            }),
+            (SocketAddr::V6(local), SocketAddr::V6(remote)) => {
+                // Dual-stack sockets used for IPv4 connections.
+                Self::from_addrs(
+                    SocketAddr::new(local.ip().to_ipv4_mapped()?.into(), local.port()),
+                    SocketAddr::new(remote.ip().to_ipv4_mapped()?.into(), remote.port()),
+                )
+            }
+            (SocketAddr::V4(_), SocketAddr::V6(_)) | (SocketAddr::V6(_), SocketAddr::V4(_)) => None,
+        }
+    }
+}
+
+/// The identifier of a pair of sockets in [`SockPairs`].
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct SockPairId(u64);
+
+/// The TCP counters of a socket of a pair, see [`SockPairs::stats`].
+///
+/// The counters are read from the kernel's TCP state with `TCP_INFO` and `FIONREAD`, so they
+/// count the bytes that went through the socket rather than the messages redirected by the
+/// eBPF programs. Programs that need to count redirects must do so themselves, for example in a
+/// map.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct SockStats {
+    /// The number of bytes received in order from the peer, `tcpi_bytes_received`.
+    ///
+    /// Bytes redirected by `sk_skb` programs are included, since they are counted before being
+    /// redirected. Always 0 before Linux 4.1.
+    pub tcp_bytes_received: u64,
+    /// The number of bytes sent to the peer including retransmissions, `tcpi_bytes_sent`.
+    ///
+    /// Bytes redirected to the socket by `sk_msg` and `sk_skb` programs are counted here, not
+    /// on the socket they were written to. Always 0 before Linux 4.19.
+    pub tcp_bytes_sent: u64,
+    /// The number of bytes in the receive queue waiting to be read by the application.
+    pub receive_queue_bytes: u64,
+}
+
+/// The TCP counters of a pair of sockets, see [`SockPairs::stats`].
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct SockPairStats {
+    /// The counters of the accepted socket.
+    pub accepted: SockStats,
+    /// The counters of the connected socket.
+    pub connected: SockStats,
+}
+
+struct SockPair<K> {
+    accepted: TcpStream,
+    connected: TcpStream,
+    accepted_key: K,
+    connected_key: K,
+}
+
+/// Pairs of TCP sockets redirecting data to each other through a [`SockHash`].
+///
+/// This implements the user space side of the socket map proxy pattern: an application accepts
+/// a connection, connects to an upstream server, and an [`SkMsg`](crate::programs::SkMsg) or
+/// [`SkSkb`](crate::programs::SkSkb) program attached to the map splices the two sockets
+/// together in the kernel.
+///
+/// Each socket is inserted in the map under the [`SockPairKey`] of the _other_ socket of its
+/// pair, so the eBPF program redirects data by looking the map up with the key of the socket
+/// it runs on, for instance with `SockHash::redirect_msg`.
+///
+/// The sockets are owned by [`SockPairs`]. Pairs are removed from the map and closed with
+/// [`SockPairs::remove`], with [`SockPairs::remove_closed`] once either connection is shut
+/// down, and when [`SockPairs`] is dropped.
+///
+/// # Minimum kernel version
+///
+/// The minimum kernel version required to use this feature is 4.18.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[derive(Debug, thiserror::Error)]
+/// # enum Error {
+/// #     #[error(transparent)]
+/// #     IO(#[from] std::io::Error),
+/// #     #[error(transparent)]
+/// #     Map(#[from] aya::maps::MapError),
+/// #     #[error(transparent)]
+/// #     SockPairs(#[from] aya::maps::sock::SockPairsError),
+/// #     #[error(transparent)]
+/// #     Program(#[from] aya::programs::ProgramError),
+/// #     #[error(transparent)]
+/// #     Ebpf(#[from] aya::EbpfError)
+/// # }
+/// # let mut bpf = aya::Ebpf::load(&[])?;
+/// use std::net::{TcpListener, TcpStream};
+///
+/// use aya::maps::{SockHash, sock::{Ipv4SockKey, SockPairs}};
+/// use aya::programs::SkMsg;
+///
+/// let map: SockHash<_, Ipv4SockKey> = bpf.take_map("SOCK_PAIRS").unwrap().try_into()?;
+/// let prog: &mut SkMsg = bpf.program_mut("redirect_to_peer").unwrap().try_into()?;
+/// prog.load()?;
+/// prog.attach(map.fd())?;
+///
+/// let mut pairs = SockPairs::new(map);
+/// let listener = TcpListener::bind("127.0.0.1:8080")?;
+/// for client in listener.incoming() {
+///     let upstream = TcpStream::connect("127.0.0.1:9090")?;
+///     pairs.insert(client?, upstream)?;
+///     for id in pairs.remove_closed()? {
+///         println!("pair {id:?} closed");
+///     }
+/// }
+/// # Ok::<(), Error>(())
+/// ```
+pub struct SockPairs<T: BorrowMut<MapData>, K: SockPairKey> {
+    map: SockHash<T, K>,
+    pairs: HashMap<SockPairId, SockPair<K>>,
+    next_id: u64,
+}
+
+impl<T: BorrowMut<MapData>, K: SockPairKey> SockPairs<T, K> {
+    /// Creates an empty set of pairs inserting the sockets in `map`.
+    pub fn new(map: SockHash<T, K>) -> Self {
+        Self {
+            map,
+            pairs: HashMap::new(),
+            next_id: 0,
+        }
+    }
+
+    /// Returns the map the sockets are inserted in.
+    pub const fn map(&self) -> &SockHash<T, K> {
+        &self.map
+    }
+
+    /// Inserts a pair made of an `accepted` socket and a `connected` one in the map.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`SockPairsError::UnsupportedAddresses`] if the key type doesn't support the
+    /// addresses of either socket. On error, nothing is left in the map and the sockets are
+    /// closed.
+    pub fn insert(
+        &mut self,
+        accepted: TcpStream,
+        connected: TcpStream,
+    ) -> Result<SockPairId, SockPairsError> {
+        let accepted_key = key_of(&accepted)?;
+        let connected_key = key_of(&connected)?;
+
+        let Self {
+            map,
+            pairs,
+            next_id,
+        } = self;
+        // Each socket is stored under the key of its peer.
+        map.insert(accepted_key, connected.as_fd(), 0)?;
+        if let Err(err) = map.insert(connected_key, accepted.as_fd(), 0) {
+            let _unused: Result<(), MapError> = map.remove(&accepted_key);
+            return Err(err.into());
+        }
+
+        let id = SockPairId(*next_id);
+        *next_id += 1;
+        pairs.insert(
+            id,
+            SockPair {
+                accepted,
+                connected,
+                accepted_key,
+                connected_key,
+            },
+        );
+        Ok(id)
+    }
+
+    /// Returns the keys the sockets of the pair `id` are identified with, in the order
+    /// `(accepted, connected)`.
+    pub fn keys(&self, id: SockPairId) -> Option<(K, K)> {
+        self.pairs.get(&id).map(
+            |SockPair {
+                 accepted_key,
+                 connected_key,
+                 ..
+             }| (*accepted_key, *connected_key),
+        )
+    }
+
+    /// An iterator over the identifiers of the pairs.
+    pub fn ids(&self) -> impl Iterator<Item = SockPairId> + '_ {
+        self.pairs.keys().copied()
+    }
+
+    /// Returns the TCP counters of the sockets of the pair `id`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`SockPairsError::UnknownPair`] if there is no pair `id`.
+    pub fn stats(&self, id: SockPairId) -> Result<SockPairStats, SockPairsError> {
+        let SockPair {
+            accepted,
+            connected,
+            ..
+        } = self
+            .pairs
+            .get(&id)
+            .ok_or(SockPairsError::UnknownPair { id })?;
+        Ok(SockPairStats {
+            accepted: sock_stats(accepted.as_fd())?,
+            connected: sock_stats(connected.as_fd())?,
+        })
+    }
+
+    /// Removes the pair `id` from the map, and returns its sockets in the order
+    /// `(accepted, connected)`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`SockPairsError::UnknownPair`] if there is no pair `id`. If the sockets can't be
+    /// removed from the map, the pair is kept and the removal can be retried.
+    pub fn remove(&mut self, id: SockPairId) -> Result<(TcpStream, TcpStream), SockPairsError> {
+        let Self { map, pairs, .. } = self;
+        let Entry::Occupied(entry) = pairs.entry(id) else {
+            return Err(SockPairsError::UnknownPair { id });
+        };
+        remove_pair(map, entry.get())?;
+        let SockPair {
+            accepted,
+            connected,
+            ..
+        } = entry.remove();
+        Ok((accepted, connected))
+    }
+
+    /// Removes and closes the pairs with a socket shut down by its remote end, or in error, and
+    /// returns their identifiers.
+    ///
+    /// # Errors
+    ///
+    /// If the sockets of a pair can't be removed from the map, all the pairs are kept and the
+    /// removal can be retried with the next call.
+    pub fn remove_closed(&mut self) -> Result<Vec<SockPairId>, SockPairsError> {
+        let Self { map, pairs, .. } = self;
+        let mut closed = Vec::new();
+        for (
+            id,
+            SockPair {
+                accepted,
+                connected,
+                ..
+            },
+        ) in pairs.iter()
+        {
+            if is_closed(accepted.as_fd())? || is_closed(connected.as_fd())? {
+                closed.push(*id);
+            }
+        }
+        closed.sort_unstable();
+        // Only close the sockets once they are all out of the map. Sockets which are already
+        // out of the map are skipped by the next call.
+        for id in &closed {
+            remove_pair(map, &pairs[id])?;
+        }
+        for id in &closed {
+            let _unused: Option<SockPair<K>> = pairs.remove(id);
+        }
+        Ok(closed)
+    }
+}
+
+impl<T: BorrowMut<MapData>, K: SockPairKey> Drop for SockPairs<T, K> {
+    fn drop(&mut self) {
+        let Self { map, pairs, .. } = self;
+        for (_, pair) in pairs.drain() {
+            let _unused: Result<(), SockPairsError> = remove_pair(map, &pair);
+        }
+    }
+}
+
+fn key_of<K: SockPairKey>(socket: &TcpStream) -> Result<K, SockPairsError> {
+    let local = socket.local_addr().map_err(SockPairsError::Addresses)?;
+    let remote = socket.peer_addr().map_err(SockPairsError::Addresses)?;
+    K::from_addrs(local, remote).ok_or(SockPairsError::UnsupportedAddresses { local, remote })
+}
+
+fn remove_pair<T: BorrowMut<MapData>, K: SockPairKey>(
+    map: &mut SockHash<T, K>,
+    SockPair {
+        accepted_key,
+        connected_key,
+        ..
+    }: &SockPair<K>,
+) -> Result<(), SockPairsError> {
+    // The kernel removes closed sockets from the map on its own.
+    for key in [accepted_key, connected_key] {
+        match map.remove(key) {
+            Ok(()) => {}
+            Err(MapError::SyscallError(SyscallError { io_error, .. }))
+                if io_error.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Ok(())
+}
+
+fn sock_stats(fd: BorrowedFd<'_>) -> Result<SockStats, SyscallError> {
+    // Safety: tcp_info is POD so this is safe. Older kernels fill a prefix of the struct.
+    let mut info = unsafe { mem::zeroed::<tcp_info>() };
+    let mut len = size_of::<tcp_info>() as u32;
+    // Safety: libc wrapper
+    if unsafe {
+        getsockopt(
+            fd.as_raw_fd(),
+            IPPROTO_TCP,
+            TCP_INFO,
+            (&raw mut info).cast(),
+            &raw mut len,
+        )
+    } < 0
+    {
+        return Err(SyscallError {
+            call: "getsockopt",
+            io_error: io::Error::last_os_error(),
+        });
+    }
+
+    let mut receive_queue_bytes: libc::c_int = 0;
+    // Safety: libc wrapper
+    if unsafe { ioctl(fd.as_raw_fd(), FIONREAD, &raw mut receive_queue_bytes) } < 0 {
+        return Err(SyscallError {
+            call: "ioctl",
+            io_error: io::Error::last_os_error(),
+        });
+    }
+
+    Ok(SockStats {
+        tcp_bytes_received: info.tcpi_bytes_received,
+        tcp_bytes_sent: info.tcpi_bytes_sent,
+        receive_queue_bytes: receive_queue_bytes as u64,
+    })
+}
+
+fn is_closed(fd: BorrowedFd<'_>) -> Result<bool, SyscallError> {
+    let mut pollfd = pollfd {
+        fd: fd.as_raw_fd(),
+        events: POLLRDHUP,
+        revents: 0,
+    };
+    // Safety: libc wrapper
+    if unsafe { poll(&raw mut pollfd, 1, 0) } < 0 {
+        return Err(SyscallError {
+            call: "poll",
+            io_error: io::Error::last_os_error(),
+        });
+    }
+    Ok(pollfd.revents & (POLLRDHUP | POLLHUP | POLLERR) != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr, SocketAddrV4, SocketAddrV6};
+
+    use super::*;
+
+    #[test]
+    fn test_ipv4_sock_key() {
+        let (local_ip, remote_ip) = (Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2));
+        let key = Ipv4SockKey {
+            remote_ip4: 0x0a000002,
+            local_ip4: 0x0a000001,
+            remote_port: 40000,
+            local_port: 8080,
+        };
+        let local = SocketAddr::V4(SocketAddrV4::new(local_ip, 8080));
+        let remote = SocketAddr::V4(SocketAddrV4::new(remote_ip, 40000));
+        assert_eq!(Ipv4SockKey::from_addrs(local, remote), Some(key));
+
+        // IPv4-mapped IPv6 addresses of dual-stack sockets.
+        let local = SocketAddr::V6(SocketAddrV6::new(local_ip.to_ipv6_mapped(), 8080, 0, 0));
+        let remote = SocketAddr::V6(SocketAddrV6::new(remote_ip.to_ipv6_mapped(), 40000, 0, 0));
+        assert_eq!(Ipv4SockKey::from_addrs(local, remote), Some(key));
+
+        let v6 = SocketAddr::V6(SocketAddrV6::new(Ipv6Addr::LOCALHOST, 8080, 0, 0));
+        assert_eq!(Ipv4SockKey::from_addrs(v6, v6), None);
+        assert_eq!(
+            Ipv4SockKey::from_addrs(SocketAddr::V4(SocketAddrV4::new(local_ip, 8080)), v6),
+            None
+        );
+    }
+}
@@ -38,7 +38,7 @@ pub struct Stack<T, V: Pod> {
 impl<T: Borrow<MapData>, V: Pod> Stack<T, V> {
     pub(crate) fn new(map: T) -> Result<Self, MapError> {
         let data = map.borrow();
-        check_kv_size::<(), V>(data)?;
+        check_kv_size::<[u8; 0], V>(data)?;
 
         Ok(Self {
             inner: map,
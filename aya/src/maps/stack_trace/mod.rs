@@ -14,6 +14,12 @@ use crate::{
     sys::{SyscallError, bpf_map_lookup_elem_ptr},
 };
 
+mod symbolize;
+
+pub use symbolize::{
+    KernelSymbols, SourceLocation, Symbol, SymbolizeError, SymbolizedFrame, Symbolizer,
+};
+
 /// A hash map of kernel or user space stack traces.
 ///
 /// Stack trace maps can be used to store stack traces captured by eBPF programs, which can be
@@ -51,7 +57,7 @@ use crate::{
 ///
 /// // here we resolve symbol names using kernel symbols. If this was a user space stack (for
 /// // example captured from a uprobe), you'd have to load the symbols using some other mechanism
-/// // (eg loading the target binary debuginfo)
+/// // (eg loading the target binary debuginfo). See `Symbolizer` for a helper doing both.
 /// for frame in stack_trace.frames() {
 ///     if let Some(sym) = ksyms.range(..=frame.ip).next_back().map(|(_, s)| s) {
 ///         println!(
@@ -0,0 +1,880 @@
+//! Symbolization of kernel and user space stack traces.
+//!
+//! See [`Symbolizer`] for documentation and examples.
+
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    ffi::{OsStr, OsString},
+    fmt::Write as _,
+    fs::{self, File},
+    io::{self, BufRead, BufReader},
+    os::unix::ffi::OsStrExt as _,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use gimli::{EndianArcSlice, RunTimeEndian};
+use log::debug;
+use object::{Object as _, ObjectSection as _, ObjectSegment as _, ObjectSymbol as _, SymbolKind};
+use thiserror::Error;
+
+use crate::{
+    maps::stack_trace::StackTrace,
+    programs::uprobe::{ProcMap, ProcMapEntry, ProcMapError, construct_debuglink_path},
+    util::MMap,
+};
+
+/// Errors occurring while symbolizing a stack trace.
+#[derive(Debug, Error)]
+pub enum SymbolizeError {
+    /// Reading `/proc/kallsyms` failed.
+    #[error("failed to read /proc/kallsyms")]
+    Kallsyms(#[source] io::Error),
+
+    /// Reading `/proc/<pid>/maps` failed.
+    #[error("failed to read memory maps of process {pid}")]
+    ProcMap {
+        /// The process ID.
+        pid: u32,
+        /// The original error.
+        #[source]
+        source: ProcMapError,
+    },
+}
+
+/// A resolved symbol.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    /// The (possibly mangled) name of the symbol.
+    pub name: String,
+    /// The offset of the address from the start of the symbol.
+    pub offset: u64,
+}
+
+/// A source location resolved from DWARF line information.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLocation {
+    /// The path of the source file, as recorded by the compiler.
+    pub file: String,
+    /// The line number, if known.
+    pub line: Option<u32>,
+    /// The column number, if known.
+    pub column: Option<u32>,
+}
+
+impl std::fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Self { file, line, column } = self;
+        write!(f, "{file}")?;
+        if let Some(line) = line {
+            write!(f, ":{line}")?;
+            if let Some(column) = column {
+                write!(f, ":{column}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A symbolized stack frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SymbolizedFrame {
+    /// The instruction pointer of this frame.
+    pub ip: u64,
+    /// The module the frame belongs to.
+    ///
+    /// For kernel frames this is the name of the kernel module, or `None` for frames in the core
+    /// kernel image. For user space frames this is the path of the mapped object file, or `None`
+    /// if the address isn't backed by a file.
+    pub module: Option<String>,
+    /// The symbol the instruction pointer resolves to, if any.
+    pub symbol: Option<Symbol>,
+    /// The source location the instruction pointer resolves to.
+    ///
+    /// Only user space frames in objects with DWARF line information, or with a separate debug
+    /// file that has it, are resolved to a source location.
+    pub location: Option<SourceLocation>,
+}
+
+impl std::fmt::Display for SymbolizedFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Self {
+            ip,
+            module,
+            symbol,
+            location,
+        } = self;
+        write!(f, "{ip:#x}")?;
+        if let Some(Symbol { name, offset }) = symbol {
+            write!(f, " {name}+{offset:#x}")?;
+        }
+        if let Some(module) = module {
+            write!(f, " [{module}]")?;
+        }
+        if let Some(location) = location {
+            write!(f, " at {location}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct KernelSymbol {
+    address: u64,
+    name: String,
+    module: Option<String>,
+}
+
+/// Kernel symbols loaded from `/proc/kallsyms`.
+///
+/// Unlike [`crate::util::kernel_symbols`], this keeps track of the kernel module each symbol
+/// belongs to and resolves addresses to a symbol and an offset.
+#[derive(Debug)]
+pub struct KernelSymbols {
+    // Sorted by address.
+    symbols: Vec<KernelSymbol>,
+}
+
+impl KernelSymbols {
+    /// Loads kernel symbols from `/proc/kallsyms`.
+    ///
+    /// When `kptr_restrict` hides kernel addresses from the caller all addresses read as zero and
+    /// nothing will resolve.
+    pub fn load() -> Result<Self, io::Error> {
+        let reader = BufReader::new(File::open("/proc/kallsyms")?);
+        Self::parse(reader)
+    }
+
+    fn parse(reader: impl BufRead) -> Result<Self, io::Error> {
+        let mut symbols = reader
+            .lines()
+            .filter_map(|line| {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(error) => return Some(Err(error)),
+                };
+                (|| {
+                    let mut parts = line.split_ascii_whitespace();
+                    let address = parts.next()?;
+                    let _kind = parts.next()?;
+                    let name = parts.next()?;
+                    let module = match parts.next() {
+                        Some(module) => Some(module.strip_prefix('[')?.strip_suffix(']')?),
+                        None => None,
+                    };
+                    if parts.next().is_some() {
+                        return None;
+                    }
+                    // TODO(https://github.com/rust-lang/rust-clippy/issues/14112): Remove this
+                    // allowance when the lint behaves more sensibly.
+                    #[expect(clippy::manual_ok_err, reason = "type ascription")]
+                    let address = match u64::from_str_radix(address, 16) {
+                        Ok(address) => Some(address),
+                        Err(std::num::ParseIntError { .. }) => None,
+                    }?;
+                    Some(KernelSymbol {
+                        address,
+                        name: name.to_owned(),
+                        module: module.map(str::to_owned),
+                    })
+                })()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, line.clone()))
+                .map(|symbol| (symbol.address != 0).then_some(symbol))
+                .transpose()
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        symbols.sort_by_key(|KernelSymbol { address, .. }| *address);
+        Ok(Self { symbols })
+    }
+
+    /// Resolves `address` to the closest preceding kernel symbol.
+    ///
+    /// Returns the name of the kernel module containing the symbol, if any, and the symbol.
+    pub fn resolve(&self, address: u64) -> Option<(Option<&str>, Symbol)> {
+        let Self { symbols } = self;
+        let index = symbols.partition_point(|symbol| symbol.address <= address);
+        let KernelSymbol {
+            address: start,
+            name,
+            module,
+        } = symbols.get(index.checked_sub(1)?)?;
+        Some((
+            module.as_deref(),
+            Symbol {
+                name: name.clone(),
+                offset: address - start,
+            },
+        ))
+    }
+}
+
+#[derive(Debug)]
+struct ElfSymbol {
+    address: u64,
+    size: u64,
+    name: String,
+}
+
+type DwarfReader = EndianArcSlice<RunTimeEndian>;
+
+// Resolves addresses to source locations using the DWARF line information of an object.
+struct Lines(Mutex<addr2line::Context<DwarfReader>>);
+
+impl std::fmt::Debug for Lines {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Lines").finish_non_exhaustive()
+    }
+}
+
+impl Lines {
+    fn load(path: &Path, obj: &object::File<'_>) -> Option<Self> {
+        obj.section_by_name(".debug_info")?;
+        let endian = if obj.is_little_endian() {
+            RunTimeEndian::Little
+        } else {
+            RunTimeEndian::Big
+        };
+        // The sections are copied so that the context doesn't borrow the mapped object.
+        let Ok(dwarf) = gimli::Dwarf::load(|id| {
+            let data = obj.section_by_name(id.name()).map_or_else(
+                || Cow::Borrowed(&[][..]),
+                |section| {
+                    section.uncompressed_data().unwrap_or_else(|error| {
+                        debug!(
+                            "failed to read {} of {}: {error}",
+                            id.name(),
+                            path.display()
+                        );
+                        Cow::Borrowed(&[][..])
+                    })
+                },
+            );
+            Ok::<_, std::convert::Infallible>(EndianArcSlice::new(Arc::from(&*data), endian))
+        });
+        let context = addr2line::Context::from_dwarf(dwarf)
+            .map_err(|error| debug!("failed to parse DWARF of {}: {error}", path.display()))
+            .ok()?;
+        Some(Self(Mutex::new(context)))
+    }
+
+    fn resolve(&self, address: u64) -> Option<SourceLocation> {
+        let Self(context) = self;
+        let (file, line, column) = context
+            .lock()
+            .unwrap()
+            .find_location(address)
+            .map_err(|error| debug!("failed to find the location of {address:#x}: {error}"))
+            .ok()?
+            .map(|addr2line::Location { file, line, column }| {
+                (file.map(str::to_owned), line, column)
+            })?;
+        Some(SourceLocation {
+            file: file?,
+            line,
+            column,
+        })
+    }
+}
+
+// The symbols and line information of a single ELF object, along with the information needed to
+// translate file offsets into virtual addresses.
+#[derive(Debug)]
+struct ElfSymbols {
+    // (file offset, file size, virtual address) of each loadable segment.
+    segments: Vec<(u64, u64, u64)>,
+    // Sorted by address.
+    symbols: Vec<ElfSymbol>,
+    lines: Option<Lines>,
+}
+
+impl ElfSymbols {
+    fn new(root: &Path, path: &Path, obj: &object::File<'_>) -> Self {
+        let segments = obj
+            .segments()
+            .map(|segment| {
+                let (offset, size) = segment.file_range();
+                (offset, size, segment.address())
+            })
+            .collect();
+
+        let mut symbols = Vec::new();
+        Self::collect_symbols(obj, &mut symbols);
+        let stripped = obj.symbols().next().is_none();
+        let mut lines = Lines::load(path, obj);
+        if stripped || lines.is_none() {
+            // Look for a separate debug file. Its symbols and line information use the same
+            // virtual addresses as the original object.
+            if let Some(debug_path) = find_debug_file(root, path, obj) {
+                match MMap::map_copy_read_only(&debug_path) {
+                    Ok(data) => match object::File::parse(data.as_ref()) {
+                        Ok(debug_obj) => {
+                            if stripped {
+                                Self::collect_symbols(&debug_obj, &mut symbols);
+                            }
+                            if lines.is_none() {
+                                lines = Lines::load(&debug_path, &debug_obj);
+                            }
+                        }
+                        Err(error) => {
+                            debug!("failed to parse {}: {error}", debug_path.display());
+                        }
+                    },
+                    Err(error) => debug!("failed to map {}: {error}", debug_path.display()),
+                }
+            }
+        }
+        symbols.sort_by_key(|ElfSymbol { address, .. }| *address);
+        symbols.dedup_by_key(|ElfSymbol { address, .. }| *address);
+
+        Self {
+            segments,
+            symbols,
+            lines,
+        }
+    }
+
+    fn collect_symbols(obj: &object::File<'_>, symbols: &mut Vec<ElfSymbol>) {
+        symbols.extend(
+            obj.symbols()
+                .chain(obj.dynamic_symbols())
+                .filter(|sym| sym.kind() == SymbolKind::Text && !sym.is_undefined())
+                .filter_map(|sym| {
+                    let name = sym.name().ok()?;
+                    (!name.is_empty()).then(|| ElfSymbol {
+                        address: sym.address(),
+                        size: sym.size(),
+                        name: name.to_owned(),
+                    })
+                }),
+        );
+    }
+
+    fn resolve(&self, file_offset: u64) -> (Option<Symbol>, Option<SourceLocation>) {
+        let Self {
+            segments,
+            symbols: _,
+            lines,
+        } = self;
+        // Relocatable objects have no segments; their symbols are relative to the file.
+        let address = if segments.is_empty() {
+            Some(file_offset)
+        } else {
+            segments
+                .iter()
+                .find(|(offset, size, _address)| (*offset..*offset + *size).contains(&file_offset))
+                .map(|(offset, _size, address)| file_offset - offset + address)
+        };
+        let Some(address) = address else {
+            return (None, None);
+        };
+        (
+            self.symbol(address),
+            lines.as_ref().and_then(|lines| lines.resolve(address)),
+        )
+    }
+
+    fn symbol(&self, address: u64) -> Option<Symbol> {
+        let Self {
+            segments: _,
+            symbols,
+            lines: _,
+        } = self;
+        let index = symbols.partition_point(|symbol| symbol.address <= address);
+        let ElfSymbol {
+            address: start,
+            size,
+            name,
+        } = symbols.get(index.checked_sub(1)?)?;
+        let offset = address - start;
+        (*size == 0 || offset < *size).then(|| Symbol {
+            name: name.clone(),
+            offset,
+        })
+    }
+}
+
+// Debug files are looked up in the file system of the process, `root`, which `path` is already
+// relative to.
+fn find_debug_file(root: &Path, path: &Path, obj: &object::File<'_>) -> Option<PathBuf> {
+    if let Ok(Some(build_id)) = obj.build_id() {
+        if let Some((first, rest)) = build_id.split_first() {
+            let mut name = format!("{first:02x}/");
+            for byte in rest {
+                write!(name, "{byte:02x}").unwrap();
+            }
+            name.push_str(".debug");
+            let debug_path = root.join("usr/lib/debug/.build-id").join(name);
+            if debug_path.exists() {
+                return Some(debug_path);
+            }
+        }
+    }
+    let (filename, _crc) = obj.gnu_debuglink().ok()??;
+    let debuglink = Path::new(OsStr::from_bytes(filename));
+    let debug_path = if debuglink.is_absolute() {
+        path_in_root(root, debuglink)
+    } else {
+        construct_debuglink_path(filename, path).into_owned()
+    };
+    debug_path.exists().then_some(debug_path)
+}
+
+fn path_in_root(root: &Path, path: &Path) -> PathBuf {
+    root.join(path.strip_prefix("/").unwrap_or(path))
+}
+
+#[derive(Debug, Hash, PartialEq, Eq)]
+enum ObjectKey {
+    BuildId(Vec<u8>),
+    File {
+        path: PathBuf,
+        dev: OsString,
+        inode: u64,
+    },
+}
+
+/// Resolves the instruction pointers of a [`StackTrace`] to symbols.
+///
+/// Kernel frames are resolved using `/proc/kallsyms`, including the kernel module each symbol
+/// belongs to. User space frames are resolved by looking up the mapping containing each address
+/// in `/proc/<pid>/maps` and reading the symbol tables of the mapped ELF object, falling back to
+/// separate debug files (found by build ID or `.gnu_debuglink`) for stripped objects. Parsed
+/// objects are cached by build ID so that symbolizing traces from many processes running the same
+/// binaries only reads each object once.
+///
+/// Symbol names are returned as found in the symbol table, i.e. possibly mangled. User space
+/// frames are also resolved to source locations using the DWARF line information of the object
+/// or of its debug file. Inlined frames aren't expanded: the location is the innermost one.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[derive(thiserror::Error, Debug)]
+/// # enum Error {
+/// #     #[error(transparent)]
+/// #     Map(#[from] aya::maps::MapError),
+/// #     #[error(transparent)]
+/// #     Symbolize(#[from] aya::maps::stack_trace::SymbolizeError),
+/// #     #[error(transparent)]
+/// #     Ebpf(#[from] aya::EbpfError)
+/// # }
+/// # let bpf = aya::Ebpf::load(&[])?;
+/// use aya::maps::{StackTraceMap, stack_trace::Symbolizer};
+///
+/// let stack_traces = StackTraceMap::try_from(bpf.map("STACK_TRACES").unwrap())?;
+/// let mut symbolizer = Symbolizer::new();
+///
+/// // NOTE: you typically send stack_ids and pids from eBPF to user space using other maps
+/// let (kernel_stack_id, user_stack_id, pid) = (1234, 5678, 42);
+///
+/// let kernel_stack = stack_traces.get(&kernel_stack_id, 0)?;
+/// for frame in symbolizer.symbolize_kernel(&kernel_stack)? {
+///     println!("{frame}");
+/// }
+///
+/// let user_stack = stack_traces.get(&user_stack_id, 0)?;
+/// for frame in symbolizer.symbolize_user(pid, &user_stack)? {
+///     println!("{frame}");
+/// }
+/// # Ok::<(), Error>(())
+/// ```
+#[derive(Debug, Default)]
+pub struct Symbolizer {
+    kernel: Option<KernelSymbols>,
+    objects: HashMap<ObjectKey, Arc<ElfSymbols>>,
+}
+
+impl Symbolizer {
+    /// Creates a new symbolizer with empty caches.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the kernel symbols, loading them from `/proc/kallsyms` on first use.
+    pub fn kernel_symbols(&mut self) -> Result<&KernelSymbols, SymbolizeError> {
+        let Self { kernel, objects: _ } = self;
+        if kernel.is_none() {
+            *kernel = Some(KernelSymbols::load().map_err(SymbolizeError::Kallsyms)?);
+        }
+        Ok(kernel.as_ref().unwrap())
+    }
+
+    /// Drops cached kernel symbols so that they are reloaded on next use.
+    ///
+    /// Call this after kernel modules have been loaded or unloaded.
+    pub fn reload_kernel_symbols(&mut self) {
+        self.kernel = None;
+    }
+
+    /// Symbolizes a stack trace captured in kernel space.
+    pub fn symbolize_kernel(
+        &mut self,
+        trace: &StackTrace,
+    ) -> Result<Vec<SymbolizedFrame>, SymbolizeError> {
+        let ksyms = self.kernel_symbols()?;
+        Ok(trace
+            .frames()
+            .iter()
+            .map(|frame| {
+                let ip = frame.ip;
+                let (module, symbol) = match ksyms.resolve(ip) {
+                    Some((module, symbol)) => (module.map(str::to_owned), Some(symbol)),
+                    None => (None, None),
+                };
+                SymbolizedFrame {
+                    ip,
+                    module,
+                    symbol,
+                    location: None,
+                }
+            })
+            .collect())
+    }
+
+    /// Symbolizes a stack trace captured in user space in the process with the given `pid`.
+    ///
+    /// The process must still be running, since its memory maps are read from
+    /// `/proc/<pid>/maps`. Objects are read through `/proc/<pid>/root` so that processes running
+    /// in other mount namespaces are symbolized correctly.
+    pub fn symbolize_user(
+        &mut self,
+        pid: u32,
+        trace: &StackTrace,
+    ) -> Result<Vec<SymbolizedFrame>, SymbolizeError> {
+        let data =
+            fs::read(format!("/proc/{pid}/maps")).map_err(|error| SymbolizeError::ProcMap {
+                pid,
+                source: ProcMapError::ReadFile(error),
+            })?;
+        let proc_map = ProcMap { pid, data };
+        let entries = proc_map
+            .libs()
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|source| SymbolizeError::ProcMap { pid, source })?;
+
+        Ok(trace
+            .frames()
+            .iter()
+            .map(|frame| self.symbolize_user_ip(pid, &entries, frame.ip))
+            .collect())
+    }
+
+    fn symbolize_user_ip(
+        &mut self,
+        pid: u32,
+        entries: &[ProcMapEntry<'_>],
+        ip: u64,
+    ) -> SymbolizedFrame {
+        let Some(ProcMapEntry {
+            address,
+            offset,
+            dev,
+            inode,
+            path,
+            ..
+        }) = entries
+            .iter()
+            .find(|entry| (entry.address..entry.address_end).contains(&ip))
+        else {
+            return SymbolizedFrame {
+                ip,
+                module: None,
+                symbol: None,
+                location: None,
+            };
+        };
+        // Anonymous mappings and pseudo-paths such as `[vdso]` or `[stack]`.
+        let Some(path) = path.filter(|path| path.as_bytes().first() == Some(&b'/')) else {
+            return SymbolizedFrame {
+                ip,
+                module: path.map(|path| path.to_string_lossy().into_owned()),
+                symbol: None,
+                location: None,
+            };
+        };
+        let (symbol, location) = self
+            .object(pid, Path::new(path), dev, *inode)
+            .map_or((None, None), |object| object.resolve(ip - address + offset));
+        SymbolizedFrame {
+            ip,
+            module: Some(path.to_string_lossy().into_owned()),
+            symbol,
+            location,
+        }
+    }
+
+    fn object(
+        &mut self,
+        pid: u32,
+        path: &Path,
+        dev: &OsStr,
+        inode: u64,
+    ) -> Option<Arc<ElfSymbols>> {
+        let Self { kernel: _, objects } = self;
+
+        // Inode numbers are only unique within a device.
+        let file_key = ObjectKey::File {
+            path: path.to_owned(),
+            dev: dev.to_owned(),
+            inode,
+        };
+        if let Some(object) = objects.get(&file_key) {
+            return Some(Arc::clone(object));
+        }
+
+        let root = PathBuf::from(format!("/proc/{pid}/root"));
+        let root_path = path_in_root(&root, path);
+        let data = MMap::map_copy_read_only(&root_path)
+            .map_err(|error| debug!("failed to map {}: {error}", root_path.display()))
+            .ok()?;
+        let obj = object::File::parse(data.as_ref())
+            .map_err(|error| debug!("failed to parse {}: {error}", root_path.display()))
+            .ok()?;
+
+        let object = match obj.build_id() {
+            Ok(Some(build_id)) => {
+                let build_id_key = ObjectKey::BuildId(build_id.to_vec());
+                Arc::clone(
+                    objects
+                        .entry(build_id_key)
+                        .or_insert_with(|| Arc::new(ElfSymbols::new(&root, &root_path, &obj))),
+                )
+            }
+            Ok(None) | Err(object::Error { .. }) => {
+                Arc::new(ElfSymbols::new(&root, &root_path, &obj))
+            }
+        };
+        objects.insert(file_key, Arc::clone(&object));
+        Some(object)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{maps::stack_trace::StackFrame, sys::TEST_MMAP_RET};
+
+    #[test]
+    fn test_parse_kernel_symbols_with_modules() {
+        let data = "0000000000000000 A fixed_percpu_data\n\
+                    ffffffff81000000 T _stext\n\
+                    ffffffff81001000 T do_one_initcall\n\
+                    ffffffffc0a01000 t ext4_fill_super\t[ext4]\n\
+                    ffffffffc0a02000 t bpf_prog_6deef7357e7b4530_foo\t[bpf]\n"
+            .as_bytes();
+        let ksyms = KernelSymbols::parse(BufReader::new(data)).unwrap();
+
+        assert_eq!(ksyms.resolve(0x1000), None);
+        assert_eq!(
+            ksyms.resolve(0xffffffff81001010),
+            Some((
+                None,
+                Symbol {
+                    name: "do_one_initcall".to_owned(),
+                    offset: 0x10,
+                }
+            ))
+        );
+        assert_eq!(
+            ksyms.resolve(0xffffffffc0a01234),
+            Some((
+                Some("ext4"),
+                Symbol {
+                    name: "ext4_fill_super".to_owned(),
+                    offset: 0x234,
+                }
+            ))
+        );
+        assert_eq!(
+            ksyms.resolve(0xffffffffc0a02000),
+            Some((
+                Some("bpf"),
+                Symbol {
+                    name: "bpf_prog_6deef7357e7b4530_foo".to_owned(),
+                    offset: 0,
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn test_parse_kernel_symbols_invalid() {
+        let data = "ffffffff81000000 T _stext [unterminated\n".as_bytes();
+        assert_eq!(
+            KernelSymbols::parse(BufReader::new(data))
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn test_symbolized_frame_display() {
+        let frame = SymbolizedFrame {
+            ip: 0xffffffffc0a01234,
+            module: Some("ext4".to_owned()),
+            symbol: Some(Symbol {
+                name: "ext4_fill_super".to_owned(),
+                offset: 0x234,
+            }),
+            location: None,
+        };
+        assert_eq!(
+            frame.to_string(),
+            "0xffffffffc0a01234 ext4_fill_super+0x234 [ext4]"
+        );
+
+        let frame = SymbolizedFrame {
+            ip: 0x1234,
+            module: Some("/usr/bin/foo".to_owned()),
+            symbol: Some(Symbol {
+                name: "main".to_owned(),
+                offset: 0x10,
+            }),
+            location: Some(SourceLocation {
+                file: "src/main.rs".to_owned(),
+                line: Some(12),
+                column: Some(5),
+            }),
+        };
+        assert_eq!(
+            frame.to_string(),
+            "0x1234 main+0x10 [/usr/bin/foo] at src/main.rs:12:5"
+        );
+    }
+
+    fn object_with_debug_info(debuglink: &[u8]) -> Vec<u8> {
+        use object::{
+            Architecture, BinaryFormat, Endianness, SectionKind,
+            write::{Object, StandardSegment},
+        };
+
+        let mut obj = Object::new(
+            BinaryFormat::Elf,
+            Architecture::X86_64,
+            Endianness::default(),
+        );
+        let mut note = Vec::new();
+        for word in [4u32, 4, object::elf::NT_GNU_BUILD_ID] {
+            note.extend_from_slice(&word.to_ne_bytes());
+        }
+        note.extend_from_slice(b"GNU\0");
+        note.extend_from_slice(&[0xab, 0xcd, 0xef, 0x01]);
+        let section = obj.add_section(
+            obj.segment_name(StandardSegment::Data).to_vec(),
+            b".note.gnu.build-id".to_vec(),
+            SectionKind::Note,
+        );
+        obj.append_section_data(section, &note, 4);
+
+        let mut link = debuglink.to_vec();
+        link.push(0);
+        link.resize(link.len().next_multiple_of(4), 0);
+        link.extend_from_slice(&0u32.to_ne_bytes());
+        let section = obj.add_section(Vec::new(), b".gnu_debuglink".to_vec(), SectionKind::Other);
+        obj.append_section_data(section, &link, 4);
+        obj.write().unwrap()
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore = "`open` not available when isolation is enabled")]
+    fn test_find_debug_file_in_root() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path();
+        let path = root.join("usr/bin/foo");
+        let data = object_with_debug_info(b"/usr/lib/debug/foo.debug");
+        let obj = object::File::parse(data.as_slice()).unwrap();
+        assert_eq!(obj.build_id().unwrap(), Some(&[0xab, 0xcd, 0xef, 0x01][..]));
+
+        // Nothing exists in the root, debug files of the host aren't used.
+        assert_eq!(find_debug_file(root, &path, &obj), None);
+
+        let debuglink_path = root.join("usr/lib/debug/foo.debug");
+        fs::create_dir_all(debuglink_path.parent().unwrap()).unwrap();
+        fs::write(&debuglink_path, b"").unwrap();
+        assert_eq!(find_debug_file(root, &path, &obj), Some(debuglink_path));
+
+        // The build ID takes precedence over the debug link.
+        let build_id_path = root.join("usr/lib/debug/.build-id/ab/cdef01.debug");
+        fs::create_dir_all(build_id_path.parent().unwrap()).unwrap();
+        fs::write(&build_id_path, b"").unwrap();
+        assert_eq!(find_debug_file(root, &path, &obj), Some(build_id_path));
+
+        // Relative debug links are resolved next to the object.
+        let data = object_with_debug_info(b"foo.debug");
+        let obj = object::File::parse(data.as_slice()).unwrap();
+        fs::remove_dir_all(root.join("usr/lib/debug/.build-id")).unwrap();
+        assert_eq!(find_debug_file(root, &path, &obj), None);
+        let relative_path = root.join("usr/bin/foo.debug");
+        fs::create_dir_all(relative_path.parent().unwrap()).unwrap();
+        fs::write(&relative_path, b"").unwrap();
+        assert_eq!(find_debug_file(root, &path, &obj), Some(relative_path));
+    }
+
+    #[inline(never)]
+    fn symbolize_me() -> u64 {
+        std::hint::black_box(42)
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore = "`open` not available when isolation is enabled")]
+    fn test_symbolize_user_self() {
+        #[expect(
+            clippy::fn_to_numeric_cast_any,
+            reason = "we need the address of the function"
+        )]
+        let ip = symbolize_me as *const () as u64;
+        let trace = StackTrace {
+            id: 0,
+            frames: vec![StackFrame { ip }],
+        };
+        // `mmap` is mocked in tests; hand out a copy of the test binary instead.
+        let mut exe = fs::read(std::env::current_exe().unwrap()).unwrap();
+        TEST_MMAP_RET.with(|ret| *ret.borrow_mut() = exe.as_mut_ptr().cast());
+
+        let mut symbolizer = Symbolizer::new();
+        let frames = symbolizer
+            .symbolize_user(std::process::id(), &trace)
+            .unwrap();
+        let [
+            SymbolizedFrame {
+                ip: frame_ip,
+                module: Some(_),
+                symbol: Some(Symbol { name, offset }),
+                location,
+            },
+        ] = frames.as_slice()
+        else {
+            panic!("unexpected frames: {frames:?}");
+        };
+        assert_eq!(*frame_ip, ip);
+        assert!(name.contains("symbolize_me"), "{name}");
+        assert_eq!(*offset, 0);
+        // Test binaries built without debug information have no line information.
+        let has_debug_info = object::File::parse(exe.as_slice())
+            .unwrap()
+            .section_by_name(".debug_info")
+            .is_some();
+        if has_debug_info {
+            let Some(SourceLocation {
+                file,
+                line: Some(_),
+                column: _,
+            }) = location
+            else {
+                panic!("unexpected location: {location:?}");
+            };
+            assert!(file.ends_with("symbolize.rs"), "{file}");
+        }
+
+        // The second lookup is served from the cache.
+        assert!(!symbolizer.objects.is_empty());
+        assert_eq!(
+            symbolizer
+                .symbolize_user(std::process::id(), &trace)
+                .unwrap(),
+            frames
+        );
+    }
+}
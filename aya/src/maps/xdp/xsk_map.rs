@@ -5,7 +5,10 @@ use std::{
     os::fd::{AsRawFd, RawFd},
 };
 
-use crate::maps::{MapData, MapError, check_bounds, check_kv_size, hash_map};
+use crate::{
+    maps::{MapData, MapError, check_bounds, check_kv_size, hash_map},
+    xsk::XskSocket,
+};
 
 /// An array of `AF_XDP` sockets.
 ///
@@ -69,6 +72,18 @@ impl<T: BorrowMut<MapData>> XskMap<T> {
         hash_map::insert(data, &index, &socket_fd.as_raw_fd(), flags)
     }
 
+    /// Sets `socket` at the index of the queue it's bound to.
+    ///
+    /// XDP programs usually redirect packets to the index `rx_queue_index` of their context.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`MapError::OutOfBounds`] if the queue is out of bounds, [`MapError::SyscallError`]
+    /// if `bpf_map_update_elem` fails.
+    pub fn set_socket(&mut self, socket: &XskSocket, flags: u64) -> Result<(), MapError> {
+        self.set(socket.queue_id(), socket.as_raw_fd(), flags)
+    }
+
     /// Un-sets the `AF_XDP` socket at a given index.
     ///
     /// # Errors
@@ -175,19 +175,7 @@ fn get_btf_info(
     // the bpf fd of the BTF object
     let btf_fd = sys::bpf_btf_get_fd_by_id(info.btf_id)?;
 
-    // we need to read the btf bytes into a buffer but we don't know the size ahead of time.
-    // assume 4kb. if this is too small we can resize based on the size obtained in the response.
-    let mut buf = vec![0u8; 4096];
-    loop {
-        let info = sys::btf_obj_get_info_by_fd(btf_fd.as_fd(), &mut buf)?;
-        let btf_size = info.btf_size as usize;
-        if btf_size > buf.len() {
-            buf.resize(btf_size, 0u8);
-            continue;
-        }
-        buf.truncate(btf_size);
-        break;
-    }
+    let buf = sys::btf_obj_get_data_by_fd(btf_fd.as_fd())?;
 
     let btf = Btf::parse(&buf, Endianness::default()).map_err(ProgramError::Btf)?;
 
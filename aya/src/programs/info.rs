@@ -17,7 +17,8 @@ use super::{
 use crate::{
     FEATURES,
     sys::{
-        SyscallError, bpf_get_object, bpf_prog_get_fd_by_id, bpf_prog_get_info_by_fd,
+        ObjectKind, SyscallError, bpf_get_object_of_kind, bpf_prog_get_fd_by_id,
+        bpf_prog_get_info_by_fd,
         feature_probe::{is_prog_info_license_supported, is_prog_info_map_ids_supported},
         iter_prog_ids,
     },
@@ -218,17 +219,28 @@ impl ProgramInfo {
         Ok(ProgramFd(fd))
     }
 
+    /// Loads program info from a file descriptor referring to a program.
+    ///
+    /// Uses kernel v4.13 features.
+    pub fn from_fd(fd: BorrowedFd<'_>) -> Result<Self, ProgramError> {
+        Self::new_from_fd(fd)
+    }
+
     /// Loads a program from a pinned path in bpffs.
     ///
+    /// Fails with `EINVAL` if the object pinned at `path` isn't a program.
+    ///
     /// Uses kernel v4.4 and v4.13 features.
     pub fn from_pin<P: AsRef<Path>>(path: P) -> Result<Self, ProgramError> {
         use std::os::unix::ffi::OsStrExt as _;
 
         // TODO: avoid this unwrap by adding a new error variant.
         let path_string = CString::new(path.as_ref().as_os_str().as_bytes()).unwrap();
-        let fd = bpf_get_object(&path_string).map_err(|io_error| SyscallError {
-            call: "BPF_OBJ_GET",
-            io_error,
+        let fd = bpf_get_object_of_kind(&path_string, ObjectKind::Program).map_err(|io_error| {
+            SyscallError {
+                call: "BPF_OBJ_GET",
+                io_error,
+            }
         })?;
 
         Self::new_from_fd(fd.as_fd())
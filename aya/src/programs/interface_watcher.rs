@@ -0,0 +1,521 @@
+//! Automatic attachment of programs to network interfaces as they appear.
+use std::{
+    collections::{HashMap, HashSet},
+    os::fd::{AsFd, BorrowedFd},
+};
+
+use libc::{EEXIST, ENOBUFS};
+use thiserror::Error;
+
+use crate::{
+    programs::{
+        NetNs, NetNsError, ProgramError, SchedClassifier, TcAttachType, TcError, Xdp, XdpMode,
+        tc::{Qdisc, SchedClassifierLinkId, TcAttachOptions, default_attach_options},
+        xdp::XdpLinkId,
+    },
+    sys::{
+        LinkMessage, NetlinkError, NetlinkSocket, netlink_list_links, netlink_qdisc_add,
+        netlink_watch_links,
+    },
+};
+
+/// Errors from [`InterfaceWatcher`].
+#[derive(Debug, Error)]
+pub enum InterfaceWatcherError {
+    /// A netlink error occurred.
+    #[error(transparent)]
+    NetlinkError(#[from] NetlinkError),
+
+    /// The network namespace can't be entered.
+    #[error(transparent)]
+    NetNsError(#[from] NetNsError),
+}
+
+/// A pattern matching network interface names.
+///
+/// `*` matches any sequence of characters and `?` matches any single character; the other
+/// characters match themselves. For instance `veth*` matches `veth0` and `vethab12`, and `eth0`
+/// only matches `eth0`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct InterfacePattern(String);
+
+impl InterfacePattern {
+    /// Creates a pattern from its textual representation.
+    pub fn new(pattern: impl Into<String>) -> Self {
+        Self(pattern.into())
+    }
+
+    /// Returns whether the interface `name` matches the pattern.
+    pub fn matches(&self, name: &str) -> bool {
+        let Self(pattern) = self;
+        let pattern = pattern.as_bytes();
+        let name = name.as_bytes();
+
+        // Greedy matching which backtracks to the last `*` on a mismatch.
+        let (mut p, mut n) = (0, 0);
+        let mut backtrack = None;
+        while n < name.len() {
+            match pattern.get(p) {
+                Some(b'*') => {
+                    backtrack = Some((p, n));
+                    p += 1;
+                }
+                Some(&c) if c == b'?' || c == name[n] => {
+                    p += 1;
+                    n += 1;
+                }
+                _ => match backtrack {
+                    Some((star, start)) => {
+                        p = star + 1;
+                        n = start + 1;
+                        backtrack = Some((star, start + 1));
+                    }
+                    None => return false,
+                },
+            }
+        }
+        pattern[p..].iter().all(|&c| c == b'*')
+    }
+}
+
+impl From<&str> for InterfacePattern {
+    fn from(pattern: &str) -> Self {
+        Self::new(pattern)
+    }
+}
+
+impl From<String> for InterfacePattern {
+    fn from(pattern: String) -> Self {
+        Self::new(pattern)
+    }
+}
+
+/// The identifier of a program watched by an [`InterfaceWatcher`].
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct WatchId(usize);
+
+/// An event reported by [`InterfaceWatcher`].
+#[derive(Debug)]
+pub enum InterfaceEvent {
+    /// The program was attached to a matching interface.
+    Attached {
+        /// The watched program.
+        watch: WatchId,
+        /// The name of the interface.
+        interface: String,
+        /// The index of the interface.
+        if_index: u32,
+    },
+
+    /// The interface the program was attached to was removed, or renamed to a name which
+    /// doesn't match anymore.
+    Detached {
+        /// The watched program.
+        watch: WatchId,
+        /// The name of the interface.
+        interface: String,
+        /// The index of the interface.
+        if_index: u32,
+    },
+
+    /// The program couldn't be attached to a matching interface.
+    ///
+    /// Attaching is retried on the next notification about the interface, for instance when it
+    /// is brought up.
+    AttachFailed {
+        /// The watched program.
+        watch: WatchId,
+        /// The name of the interface.
+        interface: String,
+        /// The index of the interface.
+        if_index: u32,
+        /// The original error.
+        error: ProgramError,
+    },
+
+    /// The program couldn't be detached from an interface renamed to a name which doesn't
+    /// match anymore.
+    DetachFailed {
+        /// The watched program.
+        watch: WatchId,
+        /// The name of the interface.
+        interface: String,
+        /// The index of the interface.
+        if_index: u32,
+        /// The original error.
+        error: ProgramError,
+    },
+}
+
+enum WatchedProgram {
+    Xdp {
+        program: Xdp,
+        mode: XdpMode,
+        links: HashMap<u32, XdpLinkId>,
+    },
+    SchedClassifier {
+        program: SchedClassifier,
+        attach_type: TcAttachType,
+        links: HashMap<u32, SchedClassifierLinkId>,
+    },
+}
+
+impl WatchedProgram {
+    fn if_indices(&self) -> Vec<u32> {
+        match self {
+            Self::Xdp { links, .. } => links.keys().copied().collect(),
+            Self::SchedClassifier { links, .. } => links.keys().copied().collect(),
+        }
+    }
+
+    fn is_attached(&self, if_index: u32) -> bool {
+        match self {
+            Self::Xdp { links, .. } => links.contains_key(&if_index),
+            Self::SchedClassifier { links, .. } => links.contains_key(&if_index),
+        }
+    }
+
+    fn attach(&mut self, if_index: u32, netns: Option<&NetNs>) -> Result<(), ProgramError> {
+        match self {
+            Self::Xdp {
+                program,
+                mode,
+                links,
+            } => {
+                let link_id = program.do_attach(if_index, *mode, netns)?;
+                links.insert(if_index, link_id);
+            }
+            Self::SchedClassifier {
+                program,
+                attach_type,
+                links,
+            } => {
+                let options = default_attach_options(*attach_type);
+                // Netlink filters need a qdisc, which new interfaces don't have.
+                if matches!(options, TcAttachOptions::Netlink(_))
+                    && matches!(attach_type, TcAttachType::Ingress | TcAttachType::Egress)
+                {
+                    match NetNs::run(netns, || unsafe {
+                        netlink_qdisc_add(if_index as i32, Qdisc::Clsact)
+                    })
+                    .map_err(TcError::NetNsError)?
+                    {
+                        Ok(()) => {}
+                        Err(err) if err.raw_os_error() == Some(EEXIST) => {}
+                        Err(err) => return Err(TcError::NetlinkError(err).into()),
+                    }
+                }
+                let link_id = program.do_attach(if_index, *attach_type, options, true, netns)?;
+                links.insert(if_index, link_id);
+            }
+        }
+        Ok(())
+    }
+
+    fn detach(&mut self, if_index: u32) -> Result<(), ProgramError> {
+        match self {
+            Self::Xdp { program, links, .. } => match links.remove(&if_index) {
+                Some(link_id) => program.detach(link_id),
+                None => Ok(()),
+            },
+            Self::SchedClassifier { program, links, .. } => match links.remove(&if_index) {
+                Some(link_id) => program.detach(link_id),
+                None => Ok(()),
+            },
+        }
+    }
+
+    /// Releases the link to a removed interface. The kernel already detached the program.
+    fn forget(&mut self, if_index: u32) {
+        match self {
+            Self::Xdp { program, links, .. } => {
+                if let Some(link_id) = links.remove(&if_index) {
+                    let _unused: Result<_, ProgramError> = program.take_link(link_id);
+                }
+            }
+            Self::SchedClassifier { program, links, .. } => {
+                if let Some(link_id) = links.remove(&if_index) {
+                    let _unused: Result<_, ProgramError> = program.take_link(link_id);
+                }
+            }
+        }
+    }
+}
+
+struct Watch {
+    pattern: InterfacePattern,
+    program: WatchedProgram,
+}
+
+/// Attaches [`Xdp`] and [`SchedClassifier`] programs to network interfaces as they appear.
+///
+/// Links to an interface go away when the interface is deleted, for instance when a container
+/// restarts and recreates its `veth` pair. The watcher subscribes to the `RTM_NEWLINK` and
+/// `RTM_DELLINK` rtnetlink notifications, and attaches each watched program to the interfaces
+/// whose name matches its [`InterfacePattern`], including the ones recreated later on.
+///
+/// The watcher takes ownership of the programs, which must be loaded. [`SchedClassifier`]
+/// programs are attached with the default options of [`SchedClassifier::attach`]; when netlink
+/// is used, a `clsact` qdisc is added to the interfaces which don't have one.
+///
+/// [`InterfaceWatcher::scan`] attaches the programs to the existing interfaces, then
+/// [`InterfaceWatcher::wait`] blocks until the next notification and handles it. The watcher
+/// implements [`AsFd`], so it can be registered with `poll`, `epoll` or an async runtime to only
+/// call [`InterfaceWatcher::wait`] once it is readable.
+///
+/// # Examples
+///
+/// ```no_run
+/// # let mut bpf = aya::Ebpf::load(&[])?;
+/// use aya::programs::{
+///     InterfaceEvent, InterfaceWatcher, SchedClassifier, TcAttachType, Xdp, XdpMode,
+/// };
+///
+/// let mut xdp: Xdp = bpf.take_program("xdp").unwrap().try_into()?;
+/// xdp.load()?;
+/// let mut classifier: SchedClassifier = bpf.take_program("classifier").unwrap().try_into()?;
+/// classifier.load()?;
+///
+/// let mut watcher = InterfaceWatcher::new()?;
+/// watcher.watch_xdp("veth*", xdp, XdpMode::Skb);
+/// watcher.watch_sched_classifier("veth*", classifier, TcAttachType::Ingress);
+///
+/// let mut events = watcher.scan()?;
+/// loop {
+///     for event in events {
+///         if let InterfaceEvent::AttachFailed { interface, error, .. } = event {
+///             eprintln!("failed to attach to {interface}: {error}");
+///         }
+///     }
+///     events = watcher.wait()?;
+/// }
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub struct InterfaceWatcher {
+    sock: NetlinkSocket,
+    netns: Option<NetNs>,
+    watches: Vec<Watch>,
+    /// The names of the interfaces, as last reported by the kernel.
+    names: HashMap<u32, String>,
+}
+
+impl InterfaceWatcher {
+    /// Creates a watcher for the interfaces of the network namespace of the calling thread.
+    pub fn new() -> Result<Self, InterfaceWatcherError> {
+        Self::with_netns(None)
+    }
+
+    /// Creates a watcher for the interfaces of the network namespace `netns`.
+    ///
+    /// The programs are attached from a helper thread which enters `netns`, see
+    /// [`Xdp::attach_in_netns`].
+    pub fn new_in_netns(netns: &NetNs) -> Result<Self, InterfaceWatcherError> {
+        Self::with_netns(Some(netns))
+    }
+
+    fn with_netns(netns: Option<&NetNs>) -> Result<Self, InterfaceWatcherError> {
+        // A netlink socket stays bound to the network namespace it was created in.
+        let sock = NetNs::run(netns, netlink_watch_links)??;
+        Ok(Self {
+            sock,
+            netns: netns.cloned(),
+            watches: Vec::new(),
+            names: HashMap::new(),
+        })
+    }
+
+    /// Attaches `program` in `mode` to the interfaces matching `pattern`.
+    pub fn watch_xdp(
+        &mut self,
+        pattern: impl Into<InterfacePattern>,
+        program: Xdp,
+        mode: XdpMode,
+    ) -> WatchId {
+        self.watch(
+            pattern.into(),
+            WatchedProgram::Xdp {
+                program,
+                mode,
+                links: HashMap::new(),
+            },
+        )
+    }
+
+    /// Attaches `program` as `attach_type` to the interfaces matching `pattern`.
+    pub fn watch_sched_classifier(
+        &mut self,
+        pattern: impl Into<InterfacePattern>,
+        program: SchedClassifier,
+        attach_type: TcAttachType,
+    ) -> WatchId {
+        self.watch(
+            pattern.into(),
+            WatchedProgram::SchedClassifier {
+                program,
+                attach_type,
+                links: HashMap::new(),
+            },
+        )
+    }
+
+    fn watch(&mut self, pattern: InterfacePattern, program: WatchedProgram) -> WatchId {
+        let Self { watches, .. } = self;
+        watches.push(Watch { pattern, program });
+        WatchId(watches.len() - 1)
+    }
+
+    /// Returns the indices of the interfaces the program `watch` is attached to.
+    pub fn interfaces(&self, watch: WatchId) -> Vec<u32> {
+        let WatchId(index) = watch;
+        let mut if_indices = self
+            .watches
+            .get(index)
+            .map(|watch| watch.program.if_indices())
+            .unwrap_or_default();
+        if_indices.sort_unstable();
+        if_indices
+    }
+
+    /// Attaches the watched programs to the existing interfaces matching their pattern.
+    ///
+    /// Call this after adding the watches; interfaces created later are handled by
+    /// [`InterfaceWatcher::wait`].
+    pub fn scan(&mut self) -> Result<Vec<InterfaceEvent>, InterfaceWatcherError> {
+        let links = NetNs::run(self.netns.as_ref(), netlink_list_links)??;
+        Ok(self.handle(links))
+    }
+
+    /// Blocks until the next interface notification, and attaches or detaches the watched
+    /// programs accordingly.
+    ///
+    /// When notifications arrive faster than they are handled, the kernel drops the ones which
+    /// don't fit in the socket buffer and the next read fails with `ENOBUFS`. The watcher then
+    /// resynchronizes with the current interfaces like [`InterfaceWatcher::scan`]: the programs
+    /// are attached to the matching interfaces they missed, and [`InterfaceEvent::Detached`] is
+    /// reported for the interfaces removed in the meantime.
+    pub fn wait(&mut self) -> Result<Vec<InterfaceEvent>, InterfaceWatcherError> {
+        match self.sock.recv_links() {
+            Ok(links) => Ok(self.handle(links)),
+            Err(err) if err.raw_os_error() == Some(ENOBUFS) => self.resync(),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn resync(&mut self) -> Result<Vec<InterfaceEvent>, InterfaceWatcherError> {
+        let links = NetNs::run(self.netns.as_ref(), netlink_list_links)??;
+        let present: HashSet<_> = links.iter().map(|link| link.if_index).collect();
+        let mut removed: Vec<_> = self
+            .watches
+            .iter()
+            .flat_map(|Watch { program, .. }| program.if_indices())
+            .filter(|if_index| !present.contains(if_index))
+            .collect();
+        removed.sort_unstable();
+        removed.dedup();
+        let removed = removed.into_iter().map(|if_index| LinkMessage {
+            if_index,
+            name: self.names.get(&if_index).cloned().unwrap_or_default(),
+            removed: true,
+        });
+        let links = removed.chain(links).collect();
+        Ok(self.handle(links))
+    }
+
+    fn handle(&mut self, links: Vec<LinkMessage>) -> Vec<InterfaceEvent> {
+        let Self {
+            sock: _,
+            netns,
+            watches,
+            names,
+        } = self;
+        let mut events = Vec::new();
+        for LinkMessage {
+            if_index,
+            name: interface,
+            removed,
+        } in links
+        {
+            if removed {
+                names.remove(&if_index);
+            } else {
+                names.insert(if_index, interface.clone());
+            }
+            for (index, Watch { pattern, program }) in watches.iter_mut().enumerate() {
+                let watch = WatchId(index);
+                let attached = program.is_attached(if_index);
+                if removed {
+                    if attached {
+                        program.forget(if_index);
+                        events.push(InterfaceEvent::Detached {
+                            watch,
+                            interface: interface.clone(),
+                            if_index,
+                        });
+                    }
+                    continue;
+                }
+                let event = match (pattern.matches(&interface), attached) {
+                    (true, false) => match program.attach(if_index, netns.as_ref()) {
+                        Ok(()) => InterfaceEvent::Attached {
+                            watch,
+                            interface: interface.clone(),
+                            if_index,
+                        },
+                        Err(error) => InterfaceEvent::AttachFailed {
+                            watch,
+                            interface: interface.clone(),
+                            if_index,
+                            error,
+                        },
+                    },
+                    (false, true) => match program.detach(if_index) {
+                        Ok(()) => InterfaceEvent::Detached {
+                            watch,
+                            interface: interface.clone(),
+                            if_index,
+                        },
+                        Err(error) => InterfaceEvent::DetachFailed {
+                            watch,
+                            interface: interface.clone(),
+                            if_index,
+                            error,
+                        },
+                    },
+                    (true, true) | (false, false) => continue,
+                };
+                events.push(event);
+            }
+        }
+        events
+    }
+}
+
+impl AsFd for InterfaceWatcher {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.sock.as_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case("eth0", "eth0", true)]
+    #[case("eth0", "eth1", false)]
+    #[case("eth0", "eth01", false)]
+    #[case("veth*", "veth", true)]
+    #[case("veth*", "vethab12", true)]
+    #[case("veth*", "eth0", false)]
+    #[case("*", "lo", true)]
+    #[case("eth?", "eth1", true)]
+    #[case("eth?", "eth", false)]
+    #[case("*-in*", "pod-ingress", true)]
+    #[case("*a*b", "xaxbab", true)]
+    #[case("*a*b", "xaxba", false)]
+    fn test_pattern_matches(#[case] pattern: &str, #[case] name: &str, #[case] matches: bool) {
+        assert_eq!(InterfacePattern::new(pattern).matches(name), matches);
+    }
+}
@@ -20,8 +20,8 @@ use crate::{
     pin::PinError,
     programs::{MultiProgLink, MultiProgram, ProgramError, ProgramFd, ProgramId},
     sys::{
-        SyscallError, bpf_get_object, bpf_link_get_info_by_fd, bpf_pin_object, bpf_prog_attach,
-        bpf_prog_detach,
+        ObjectKind, SyscallError, bpf_get_object_of_kind, bpf_link_get_info_by_fd, bpf_pin_object,
+        bpf_prog_attach, bpf_prog_detach,
     },
 };
 
@@ -121,6 +121,23 @@ impl LinkInfo {
         Ok(Self(info))
     }
 
+    /// Loads link info from a file descriptor referring to a link.
+    ///
+    /// Uses kernel v5.8 features.
+    pub fn from_fd(fd: BorrowedFd<'_>) -> Result<Self, LinkError> {
+        Self::new_from_fd(fd)
+    }
+
+    /// Loads link info from a pinned path in bpffs.
+    ///
+    /// Fails with `EINVAL` if the object pinned at `path` isn't a link.
+    ///
+    /// Uses kernel v5.8 features.
+    pub fn from_pin<P: AsRef<Path>>(path: P) -> Result<Self, LinkError> {
+        let link = PinnedLink::from_pin(path)?;
+        Self::new_from_fd(link.inner.fd.as_fd())
+    }
+
     /// Returns the link ID.
     pub const fn id(&self) -> u32 {
         self.0.id
@@ -345,12 +362,14 @@ impl PinnedLink {
     }
 
     /// Creates a [`crate::programs::links::PinnedLink`] from a valid path on bpffs.
+    ///
+    /// Fails with `EINVAL` if the object pinned at `path` isn't a link.
     pub fn from_pin<P: AsRef<Path>>(path: P) -> Result<Self, LinkError> {
         use std::os::unix::ffi::OsStrExt as _;
 
         // TODO: avoid this unwrap by adding a new error variant.
         let path_string = CString::new(path.as_ref().as_os_str().as_bytes()).unwrap();
-        let fd = bpf_get_object(&path_string).map_err(|io_error| {
+        let fd = bpf_get_object_of_kind(&path_string, ObjectKind::Link).map_err(|io_error| {
             LinkError::SyscallError(SyscallError {
                 call: "BPF_OBJ_GET",
                 io_error,
@@ -52,12 +52,14 @@ pub mod extension;
 pub mod fentry;
 pub mod fexit;
 pub mod flow_dissector;
+pub mod interface_watcher;
 pub mod iter;
 pub mod kprobe;
 pub mod links;
 pub mod lirc_mode2;
 pub mod lsm;
 pub mod lsm_cgroup;
+pub mod netns;
 pub mod perf_attach;
 pub mod perf_event;
 pub mod raw_trace_point;
@@ -84,9 +86,10 @@ use std::{
 
 use aya_obj::{
     VerifierLog,
-    btf::BtfError,
+    btf::{Btf, BtfError},
     generated::{BPF_F_TEST_XDP_LIVE_FRAMES, bpf_attach_type, bpf_prog_info, bpf_prog_type},
     programs::XdpAttachType,
+    verifier::VerifierDiagnostic,
 };
 use info::impl_info;
 pub use info::{LsmAttachType, ProgramInfo, ProgramType, loaded_programs};
@@ -106,12 +109,16 @@ pub use crate::programs::{
     fentry::FEntry,
     fexit::FExit,
     flow_dissector::FlowDissector,
+    interface_watcher::{
+        InterfaceEvent, InterfacePattern, InterfaceWatcher, InterfaceWatcherError, WatchId,
+    },
     iter::Iter,
     kprobe::{KProbe, KProbeError},
     links::{CgroupAttachMode, Link, LinkOrder},
     lirc_mode2::LircMode2,
     lsm::Lsm,
     lsm_cgroup::LsmCgroup,
+    netns::{NetNs, NetNsError},
     perf_event::PerfEvent,
     probe::ProbeKind,
     raw_trace_point::RawTracePoint,
@@ -139,10 +146,11 @@ use crate::{
         perf_attach::{PerfLinkIdInner, PerfLinkInner, perf_attach, perf_attach_debugfs},
     },
     sys::{
-        EbpfLoadProgramAttrs, NetlinkError, ProgQueryTarget, SyscallError, bpf_btf_get_fd_by_id,
-        bpf_get_object, bpf_link_get_fd_by_id, bpf_load_program, bpf_pin_object,
-        bpf_prog_get_fd_by_id, bpf_prog_query, bpf_prog_test_run, bpf_prog_test_run_raw_tp,
-        bpf_prog_test_run_tracing, iter_link_ids, retry_with_verifier_logs,
+        EbpfLoadProgramAttrs, NetlinkError, ObjectKind, ProgQueryTarget, SyscallError,
+        bpf_btf_get_fd_by_id, bpf_get_object_of_kind, bpf_link_get_fd_by_id, bpf_load_program,
+        bpf_pin_object, bpf_prog_get_fd_by_id, bpf_prog_query, bpf_prog_test_run,
+        bpf_prog_test_run_raw_tp, bpf_prog_test_run_tracing, iter_link_ids,
+        retry_with_verifier_logs,
     },
     util::KernelVersion,
 };
@@ -167,13 +175,18 @@ pub enum ProgramError {
     NotAttached,
 
     /// Loading the program failed.
-    #[error("the BPF_PROG_LOAD syscall returned {io_error}. Verifier output: {verifier_log}")]
+    #[error(
+        "the BPF_PROG_LOAD syscall returned {io_error}: {diagnostic}\nVerifier output: {verifier_log}"
+    )]
     LoadError {
         /// The [`io::Error`] returned by the `BPF_PROG_LOAD` syscall.
         #[source]
         io_error: io::Error,
         /// The error log produced by the kernel verifier.
         verifier_log: VerifierLog,
+        /// The summary of the verifier log, annotated with the source location of the rejected
+        /// instruction when the object carries `.BTF.ext` line information.
+        diagnostic: Box<VerifierDiagnostic>,
     },
 
     /// A syscall failed.
@@ -249,6 +262,10 @@ pub enum ProgramError {
     /// An error occurred while working with Netlink.
     #[error(transparent)]
     NetlinkError(#[from] NetlinkError),
+
+    /// An error occurred while working with a network namespace.
+    #[error(transparent)]
+    NetNsError(#[from] NetNsError),
 }
 
 /// A [`Program`] file descriptor.
@@ -535,6 +552,46 @@ impl Program {
             Self::Iter(p) => p.info(),
         }
     }
+
+    /// Extracts a [`VerifierDiagnostic`] from a verifier log produced when loading this program.
+    ///
+    /// [`ProgramError::LoadError`] already carries the diagnostic of the failed load.
+    ///
+    /// The rejected instruction is annotated with its source location when the object carries
+    /// `.BTF.ext` line information.
+    pub fn diagnose(&self, verifier_log: &VerifierLog) -> VerifierDiagnostic {
+        match self {
+            Self::KProbe(p) => p.data.diagnose(verifier_log),
+            Self::UProbe(p) => p.data.diagnose(verifier_log),
+            Self::TracePoint(p) => p.data.diagnose(verifier_log),
+            Self::SocketFilter(p) => p.data.diagnose(verifier_log),
+            Self::ReusePortSocketFilter(p) => p.data.diagnose(verifier_log),
+            Self::Xdp(p) => p.data.diagnose(verifier_log),
+            Self::SkMsg(p) => p.data.diagnose(verifier_log),
+            Self::SkSkb(p) => p.data.diagnose(verifier_log),
+            Self::SockOps(p) => p.data.diagnose(verifier_log),
+            Self::SchedClassifier(p) => p.data.diagnose(verifier_log),
+            Self::CgroupSkb(p) => p.data.diagnose(verifier_log),
+            Self::CgroupSysctl(p) => p.data.diagnose(verifier_log),
+            Self::CgroupSockopt(p) => p.data.diagnose(verifier_log),
+            Self::LircMode2(p) => p.data.diagnose(verifier_log),
+            Self::PerfEvent(p) => p.data.diagnose(verifier_log),
+            Self::RawTracePoint(p) => p.data.diagnose(verifier_log),
+            Self::Lsm(p) => p.data.diagnose(verifier_log),
+            Self::LsmCgroup(p) => p.data.diagnose(verifier_log),
+            Self::BtfTracePoint(p) => p.data.diagnose(verifier_log),
+            Self::FEntry(p) => p.data.diagnose(verifier_log),
+            Self::FExit(p) => p.data.diagnose(verifier_log),
+            Self::FlowDissector(p) => p.data.diagnose(verifier_log),
+            Self::Extension(p) => p.data.diagnose(verifier_log),
+            Self::CgroupSockAddr(p) => p.data.diagnose(verifier_log),
+            Self::SkLookup(p) => p.data.diagnose(verifier_log),
+            Self::SkReuseport(p) => p.data.diagnose(verifier_log),
+            Self::CgroupSock(p) => p.data.diagnose(verifier_log),
+            Self::CgroupDevice(p) => p.data.diagnose(verifier_log),
+            Self::Iter(p) => p.data.diagnose(verifier_log),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -547,9 +604,11 @@ pub(crate) struct ProgramData<T: Link> {
     pub(crate) attach_btf_id: Option<u32>,
     pub(crate) attach_prog_fd: Option<ProgramFd>,
     pub(crate) btf_fd: Option<Arc<crate::MockableFd>>,
+    pub(crate) btf: Option<Arc<Btf>>,
     pub(crate) verifier_log_level: VerifierLogLevel,
     pub(crate) path: Option<PathBuf>,
     pub(crate) flags: u32,
+    pub(crate) ifindex: Option<u32>,
 }
 
 impl<T: Link> ProgramData<T> {
@@ -557,6 +616,7 @@ impl<T: Link> ProgramData<T> {
         name: Option<Cow<'static, str>>,
         obj: (aya_obj::Program, aya_obj::Function),
         btf_fd: Option<Arc<crate::MockableFd>>,
+        btf: Option<Arc<Btf>>,
         verifier_log_level: VerifierLogLevel,
     ) -> Self {
         Self {
@@ -568,12 +628,19 @@ impl<T: Link> ProgramData<T> {
             attach_btf_id: None,
             attach_prog_fd: None,
             btf_fd,
+            btf,
             verifier_log_level,
             path: None,
             flags: 0,
+            ifindex: None,
         }
     }
 
+    fn diagnose(&self, verifier_log: &VerifierLog) -> VerifierDiagnostic {
+        let Self { obj, btf, .. } = self;
+        diagnose(obj.as_ref(), btf.as_deref(), verifier_log)
+    }
+
     pub(crate) fn from_bpf_prog_info(
         name: Option<Cow<'static, str>>,
         fd: crate::MockableFd,
@@ -595,9 +662,11 @@ impl<T: Link> ProgramData<T> {
             attach_btf_id,
             attach_prog_fd: None,
             btf_fd: None,
+            btf: None,
             verifier_log_level,
             path: Some(path.to_path_buf()),
             flags: 0,
+            ifindex: None,
         })
     }
 
@@ -609,9 +678,11 @@ impl<T: Link> ProgramData<T> {
 
         // TODO: avoid this unwrap by adding a new error variant.
         let path_string = CString::new(path.as_ref().as_os_str().as_bytes()).unwrap();
-        let fd = bpf_get_object(&path_string).map_err(|io_error| SyscallError {
-            call: "bpf_obj_get",
-            io_error,
+        let fd = bpf_get_object_of_kind(&path_string, ObjectKind::Program).map_err(|io_error| {
+            SyscallError {
+                call: "bpf_obj_get",
+                io_error,
+            }
         })?;
 
         let info = ProgramInfo::new_from_fd(fd.as_fd())?;
@@ -707,9 +778,11 @@ fn load_program<T: Link>(
         attach_btf_id,
         attach_prog_fd,
         btf_fd,
+        btf,
         verifier_log_level,
         path: _,
         flags,
+        ifindex,
     } = data;
     if fd.is_some() {
         return Err(ProgramError::AlreadyLoaded);
@@ -765,6 +838,7 @@ fn load_program<T: Link>(
         line_info_rec_size: *line_info_rec_size,
         line_info: line_info.clone(),
         flags: *flags,
+        prog_ifindex: *ifindex,
     };
 
     let (ret, verifier_log) = retry_with_verifier_logs(10, |logger| {
@@ -776,13 +850,29 @@ fn load_program<T: Link>(
             *fd = Some(ProgramFd(prog_fd));
             Ok(())
         }
-        Err(io_error) => Err(ProgramError::LoadError {
-            io_error,
-            verifier_log,
-        }),
+        Err(io_error) => {
+            let diagnostic = Box::new(diagnose(Some(obj), btf.as_deref(), &verifier_log));
+            Err(ProgramError::LoadError {
+                io_error,
+                verifier_log,
+                diagnostic,
+            })
+        }
     }
 }
 
+fn diagnose(
+    obj: Option<&(aya_obj::Program, aya_obj::Function)>,
+    btf: Option<&Btf>,
+    verifier_log: &VerifierLog,
+) -> VerifierDiagnostic {
+    let mut diagnostic = verifier_log.diagnose();
+    if let (Some((_, function)), Some(btf)) = (obj, btf) {
+        diagnostic.annotate(function, btf);
+    }
+    diagnostic
+}
+
 pub(crate) fn query(
     target: ProgQueryTarget<'_>,
     attach_type: bpf_attach_type,
@@ -1483,6 +1573,17 @@ macro_rules! impl_try_from_program {
                     }
                 }
             }
+
+            impl TryFrom<Program> for $ty {
+                type Error = ProgramError;
+
+                fn try_from(program: Program) -> Result<$ty, ProgramError> {
+                    match program {
+                        Program::$ty(p) => Ok(p),
+                        _ => Err(ProgramError::UnexpectedProgramType),
+                    }
+                }
+            }
         )+
     }
 }
@@ -1526,6 +1627,24 @@ macro_rules! impl_try_from_socket_filter_program {
                 }
             }
         }
+
+        impl TryFrom<Program> for $ty {
+            type Error = ProgramError;
+
+            fn try_from(program: Program) -> Result<$ty, ProgramError> {
+                match program {
+                    Program::$variant(p) => Ok(p),
+                    Program::$other_variant(other) => {
+                        let other = std::mem::ManuallyDrop::new(other);
+                        // SAFETY: Both variants are `repr(transparent)`
+                        // wrappers around the same `ProgramData<FdLink>` field,
+                        // and `other` is never dropped so the data is moved once.
+                        Ok(unsafe { std::ptr::from_ref(&*other).cast::<$ty>().read() })
+                    }
+                    _ => Err(ProgramError::UnexpectedProgramType),
+                }
+            }
+        }
     };
 }
 
@@ -0,0 +1,232 @@
+//! Network namespaces.
+use std::{
+    fs::{self, File},
+    io,
+    mem::MaybeUninit,
+    os::{
+        fd::{AsFd, AsRawFd as _, BorrowedFd, OwnedFd},
+        unix::fs::MetadataExt as _,
+    },
+    panic,
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread,
+};
+
+use libc::{CLONE_NEWNET, EINVAL, ENOTTY, NS_GET_NSTYPE};
+use thiserror::Error;
+
+use crate::sys::SyscallError;
+
+/// Errors from operations on network namespaces.
+#[derive(Debug, Error)]
+pub enum NetNsError {
+    /// The network namespace file can't be opened.
+    #[error("failed to open network namespace `{path}`")]
+    Open {
+        /// The path of the file.
+        path: PathBuf,
+        /// The original [`io::Error`].
+        #[source]
+        io_error: io::Error,
+    },
+
+    /// The file descriptor doesn't refer to a network namespace.
+    #[error("the file descriptor is not a network namespace")]
+    NotNetNs,
+
+    /// A syscall failed.
+    #[error(transparent)]
+    SyscallError(#[from] SyscallError),
+}
+
+/// A network namespace.
+///
+/// Network interfaces are looked up by name or index in the network namespace of the calling
+/// thread. Pass a [`NetNs`] to the `_in_netns` attach variants, such as
+/// [`Xdp::attach_in_netns`](crate::programs::Xdp::attach_in_netns), to attach programs to the
+/// interfaces of another namespace, such as the one of a container. The interface lookup and
+/// the netlink requests then happen on a helper thread which enters the namespace, leaving the
+/// namespace of the calling thread untouched.
+///
+/// Cloning a [`NetNs`] is cheap: the clones share the same file descriptor.
+///
+/// # Examples
+///
+/// ```no_run
+/// # let mut bpf = aya::Ebpf::load(&[])?;
+/// use aya::programs::{NetNs, Xdp, XdpMode};
+///
+/// let netns = NetNs::from_path("/var/run/netns/pod")?;
+/// let program: &mut Xdp = bpf.program_mut("xdp").unwrap().try_into()?;
+/// program.load()?;
+/// program.attach_in_netns("eth0", XdpMode::Default, &netns)?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Clone, Debug)]
+pub struct NetNs {
+    fd: Arc<OwnedFd>,
+}
+
+impl NetNs {
+    /// Opens the network namespace at `path`, such as `/var/run/netns/<name>` or
+    /// `/proc/<pid>/ns/net`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`NetNsError::Open`] if the file can't be opened and [`NetNsError::NotNetNs`] if
+    /// it isn't a network namespace.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, NetNsError> {
+        let path = path.as_ref();
+        let file = File::open(path).map_err(|io_error| NetNsError::Open {
+            path: path.to_owned(),
+            io_error,
+        })?;
+        Self::from_fd(file.into())
+    }
+
+    /// Creates a [`NetNs`] from a file descriptor referring to a network namespace.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`NetNsError::NotNetNs`] if `fd` isn't a network namespace. Kernels older than
+    /// 4.11 can't report the type of a namespace, so there any namespace is accepted.
+    pub fn from_fd(fd: OwnedFd) -> Result<Self, NetNsError> {
+        check_netns(fd.as_fd())?;
+        Ok(Self { fd: Arc::new(fd) })
+    }
+
+    /// Returns the network namespace of the calling thread.
+    pub fn current() -> Result<Self, NetNsError> {
+        Self::from_path("/proc/thread-self/ns/net")
+    }
+
+    /// Runs `f` on a helper thread which entered the network namespace `netns`, or on the
+    /// calling thread if `netns` is `None`.
+    pub(crate) fn run<R: Send>(
+        netns: Option<&Self>,
+        f: impl FnOnce() -> R + Send,
+    ) -> Result<R, NetNsError> {
+        let Some(netns) = netns else {
+            return Ok(f());
+        };
+        let fd = netns.as_fd();
+        thread::scope(|scope| {
+            let thread = scope.spawn(move || {
+                // SAFETY: libc wrapper. `setns` only affects the helper thread.
+                if unsafe { libc::setns(fd.as_raw_fd(), CLONE_NEWNET) } < 0 {
+                    return Err(SyscallError {
+                        call: "setns",
+                        io_error: io::Error::last_os_error(),
+                    }
+                    .into());
+                }
+                Ok(f())
+            });
+            thread
+                .join()
+                .unwrap_or_else(|payload| panic::resume_unwind(payload))
+        })
+    }
+}
+
+impl AsFd for NetNs {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+/// Returns an error if `fd` isn't a network namespace.
+///
+/// The type of the namespace is queried with `NS_GET_NSTYPE`, which requires Linux 4.11. Older
+/// kernels can only tell whether `fd` is a namespace at all, see [`check_nsfs`].
+pub(crate) fn check_netns(fd: BorrowedFd<'_>) -> Result<(), NetNsError> {
+    // SAFETY: libc wrapper
+    let ret = unsafe { libc::ioctl(fd.as_raw_fd(), NS_GET_NSTYPE) };
+    if ret < 0 {
+        let io_error = io::Error::last_os_error();
+        // Files other than namespaces don't support the ioctl, and neither do namespaces on
+        // kernels predating it.
+        return match io_error.raw_os_error() {
+            Some(ENOTTY | EINVAL) => check_nsfs(fd),
+            _ => Err(SyscallError {
+                call: "ioctl",
+                io_error,
+            }
+            .into()),
+        };
+    }
+    if ret != CLONE_NEWNET {
+        return Err(NetNsError::NotNetNs);
+    }
+    Ok(())
+}
+
+/// Returns an error if `fd` isn't a namespace.
+///
+/// Namespace files all live on the same internal filesystem, so `fd` is a namespace if it has
+/// the same device as the network namespace of the calling process. The type of the namespace
+/// can't be verified.
+fn check_nsfs(fd: BorrowedFd<'_>) -> Result<(), NetNsError> {
+    const CURRENT: &str = "/proc/self/ns/net";
+
+    let mut stat = MaybeUninit::<libc::stat>::uninit();
+    // SAFETY: `stat` is a valid buffer.
+    if unsafe { libc::fstat(fd.as_raw_fd(), stat.as_mut_ptr()) } != 0 {
+        return Err(SyscallError {
+            call: "fstat",
+            io_error: io::Error::last_os_error(),
+        }
+        .into());
+    }
+    // SAFETY: `fstat` succeeded.
+    let stat = unsafe { stat.assume_init() };
+    let current = fs::metadata(CURRENT).map_err(|io_error| NetNsError::Open {
+        path: CURRENT.into(),
+        io_error,
+    })?;
+    if stat.st_dev != current.dev() {
+        return Err(NetNsError::NotNetNs);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_matches::assert_matches;
+
+    use super::*;
+
+    #[test]
+    fn test_current() {
+        let netns = NetNs::current().unwrap();
+        let clone = netns.clone();
+        assert_eq!(netns.as_fd().as_raw_fd(), clone.as_fd().as_raw_fd());
+        assert_matches!(NetNs::run(None, || 42), Ok(42));
+    }
+
+    #[test]
+    fn test_not_netns() {
+        assert_matches!(
+            NetNs::from_path("/proc/thread-self/ns/uts"),
+            Err(NetNsError::NotNetNs)
+        );
+        let file = tempfile::tempfile().unwrap();
+        assert_matches!(NetNs::from_fd(file.into()), Err(NetNsError::NotNetNs));
+        assert_matches!(
+            NetNs::from_path("/nonexistent"),
+            Err(NetNsError::Open { io_error, .. }) if io_error.kind() == io::ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn test_check_nsfs() {
+        // Without `NS_GET_NSTYPE` any namespace passes, but other files don't.
+        let netns = File::open("/proc/thread-self/ns/net").unwrap();
+        assert_matches!(check_nsfs(netns.as_fd()), Ok(()));
+        let uts = File::open("/proc/thread-self/ns/uts").unwrap();
+        assert_matches!(check_nsfs(uts.as_fd()), Ok(()));
+        let file = tempfile::tempfile().unwrap();
+        assert_matches!(check_nsfs(file.as_fd()), Err(NetNsError::NotNetNs));
+    }
+}
@@ -305,6 +305,9 @@ fn create_as_trace_point<P: Probe>(
     offset: u64,
     pid: Option<u32>,
 ) -> Result<(crate::MockableFd, ProbeEvent), ProgramError> {
+    #[cfg(feature = "fake")]
+    crate::fake::FakeKernel::check_supported("creating probes through tracefs")?;
+
     let tracefs = find_tracefs_path()?;
 
     let event = create_probe_event::<P>(tracefs, kind, name, offset)
@@ -7,7 +7,7 @@ use super::links::FdLink;
 use crate::{
     programs::{
         FdLinkId, ProgramData, ProgramError, ProgramType, define_link_wrapper,
-        load_program_with_attach_type,
+        load_program_with_attach_type, netns::check_netns,
     },
     sys::{LinkTarget, SyscallError, bpf_link_create},
 };
@@ -36,6 +36,8 @@ use crate::{
 /// #     #[error(transparent)]
 /// #     Program(#[from] aya::programs::ProgramError),
 /// #     #[error(transparent)]
+/// #     NetNs(#[from] aya::programs::NetNsError),
+/// #     #[error(transparent)]
 /// #     Ebpf(#[from] aya::EbpfError)
 /// # }
 /// # let mut bpf = aya::Ebpf::load(&[])?;
@@ -46,6 +48,10 @@ use crate::{
 /// let program: &mut SkLookup = bpf.program_mut("sk_lookup").unwrap().try_into()?;
 /// program.load()?;
 /// program.attach(file)?;
+///
+/// // Or, with a network namespace that's checked when opened.
+/// let netns = aya::programs::NetNs::from_path("/var/run/netns/other")?;
+/// program.attach(&netns)?;
 /// # Ok::<(), Error>(())
 /// ```
 #[derive(Debug)]
@@ -66,11 +72,20 @@ impl SkLookup {
 
     /// Attaches the program to the given network namespace.
     ///
+    /// `netns` can be any file descriptor referring to a network namespace, such as a
+    /// [`NetNs`](crate::programs::NetNs), an opened `/proc/<pid>/ns/net` or a bind mount under
+    /// `/var/run/netns`, and needn't be the namespace of the calling thread.
+    ///
     /// The returned value can be used to detach, see [`SkLookup::detach`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ProgramError::NetNsError`] if `netns` isn't a network namespace.
     pub fn attach<T: AsFd>(&mut self, netns: T) -> Result<SkLookupLinkId, ProgramError> {
         let prog_fd = self.fd()?;
         let prog_fd = prog_fd.as_fd();
         let netns_fd = netns.as_fd();
+        check_netns(netns_fd)?;
 
         let link_fd = bpf_link_create(prog_fd, LinkTarget::Fd(netns_fd), BPF_SK_LOOKUP, 0, None)
             .map_err(|io_error| SyscallError {
@@ -1,8 +1,13 @@
 //! Network traffic control programs.
-use std::{ffi::CString, io, os::fd::AsFd as _, path::Path};
+use std::{
+    ffi::{CStr, CString},
+    io,
+    os::fd::{AsFd as _, AsRawFd as _, RawFd},
+    path::Path,
+};
 
 use aya_obj::generated::{
-    TC_H_CLSACT, TC_H_MIN_EGRESS, TC_H_MIN_INGRESS,
+    TC_H_CLSACT, TC_H_INGRESS, TC_H_MIN_EGRESS, TC_H_MIN_INGRESS, TC_H_UNSPEC,
     bpf_attach_type::{self, BPF_TCX_EGRESS, BPF_TCX_INGRESS},
     bpf_link_type,
     bpf_prog_type::BPF_PROG_TYPE_SCHED_CLS,
@@ -13,14 +18,14 @@ use super::{FdLink, ProgramInfo};
 use crate::{
     VerifierLogLevel,
     programs::{
-        Link, LinkError, LinkOrder, ProgramData, ProgramError, ProgramType, define_link_wrapper,
-        id_as_key, impl_try_from_fdlink, impl_try_into_fdlink, load_program_without_attach_type,
-        query,
+        Link, LinkError, LinkOrder, NetNs, NetNsError, ProgramData, ProgramError, ProgramType,
+        define_link_wrapper, id_as_key, impl_try_from_fdlink, impl_try_into_fdlink,
+        load_program_without_attach_type, query,
     },
     sys::{
-        BpfLinkCreateArgs, LinkTarget, NetlinkError, NetlinkSocket, ProgQueryTarget, SyscallError,
-        bpf_link_create, bpf_link_update, bpf_prog_get_fd_by_id, netlink_find_filter_with_name,
-        netlink_qdisc_add_clsact, netlink_qdisc_attach, netlink_qdisc_detach,
+        BpfLinkCreateArgs, LinkTarget, NetlinkError, ProgQueryTarget, SyscallError,
+        bpf_link_create, bpf_link_update, bpf_prog_get_fd_by_id, netlink_list_filters,
+        netlink_qdisc_add, netlink_qdisc_attach, netlink_qdisc_detach,
     },
     util::{KernelVersion, ifindex_from_ifname, tc_handler_make},
 };
@@ -105,6 +110,9 @@ pub enum TcError {
     /// operation not supported for programs loaded via tcx.
     #[error("operation not supported for programs loaded via tcx")]
     InvalidLinkOperation,
+    /// a network namespace error occurred.
+    #[error(transparent)]
+    NetNsError(#[from] NetNsError),
 }
 
 impl TcAttachType {
@@ -125,6 +133,16 @@ impl TcAttachType {
     }
 }
 
+/// Returns the options of [`SchedClassifier::attach`]: TCX on kernels >= 6.6.0 unless
+/// attaching to a custom parent, netlink otherwise.
+pub(crate) fn default_attach_options(attach_type: TcAttachType) -> TcAttachOptions {
+    if !matches!(attach_type, TcAttachType::Custom(_)) && KernelVersion::at_least(6, 6, 0) {
+        TcAttachOptions::TcxOrder(LinkOrder::default())
+    } else {
+        TcAttachOptions::Netlink(NlOptions::default())
+    }
+}
+
 /// Options for a [`SchedClassifier`] attach operation.
 ///
 /// The options vary based on what is supported by the current kernel. Kernels
@@ -181,7 +199,17 @@ impl From<u32> for TcHandle {
 }
 
 /// Options for [`SchedClassifier`] attach via netlink.
-#[derive(Debug, Default, Hash, Eq, PartialEq)]
+///
+/// Options can be set field by field or with the setters below, starting from
+/// [`NlOptions::default`]:
+///
+/// ```no_run
+/// # use aya::programs::tc::{NlOptions, TcHandle};
+/// let options = NlOptions::default()
+///     .priority(10)
+///     .classid(Some(TcHandle::new(1, 1)));
+/// ```
+#[derive(Debug, Hash, Eq, PartialEq)]
 pub struct NlOptions {
     /// Priority assigned to tc program with lower number = higher priority.
     /// If set to default (0), the system chooses the next highest priority or 49152 if no filters exist yet
@@ -203,6 +231,170 @@ pub struct NlOptions {
     /// [`3a461da1d03e`]: https://github.com/torvalds/linux/commit/3a461da1d03e7a857edfa6a002040d07e118c639
     #[doc(alias = "TCA_BPF_CLASSID")]
     pub classid: Option<TcHandle>,
+    /// The filter chain the program is attached to.
+    ///
+    /// Defaults to chain 0, the chain packets are classified with unless a `goto_chain`
+    /// action sends them to another one. Other chains require Linux 4.13.
+    #[doc(alias = "TCA_CHAIN")]
+    pub chain: u32,
+    /// Whether the program runs in direct-action mode.
+    ///
+    /// In direct-action mode, the default, the return value of the program is a TC action such
+    /// as `TC_ACT_OK` or `TC_ACT_SHOT`. Otherwise the program acts as a classifier: it returns
+    /// `0` for no match, `-1` to select [`classid`](Self::classid), or the class id to select.
+    #[doc(alias = "TCA_BPF_FLAG_ACT_DIRECT")]
+    pub direct_action: bool,
+}
+
+impl NlOptions {
+    /// Sets [`priority`](Self::priority).
+    #[must_use]
+    pub const fn priority(mut self, priority: u16) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Sets [`handle`](Self::handle).
+    #[must_use]
+    pub const fn handle(mut self, handle: TcHandle) -> Self {
+        self.handle = handle;
+        self
+    }
+
+    /// Sets [`classid`](Self::classid).
+    #[must_use]
+    pub const fn classid(mut self, classid: Option<TcHandle>) -> Self {
+        self.classid = classid;
+        self
+    }
+
+    /// Sets [`chain`](Self::chain).
+    #[must_use]
+    pub const fn chain(mut self, chain: u32) -> Self {
+        self.chain = chain;
+        self
+    }
+
+    /// Sets [`direct_action`](Self::direct_action).
+    #[must_use]
+    pub const fn direct_action(mut self, direct_action: bool) -> Self {
+        self.direct_action = direct_action;
+        self
+    }
+}
+
+impl Default for NlOptions {
+    fn default() -> Self {
+        Self {
+            priority: 0,
+            handle: TcHandle::AUTO_ASSIGN,
+            classid: None,
+            chain: 0,
+            direct_action: true,
+        }
+    }
+}
+
+/// A queueing discipline to add to a network interface with [`qdisc_add`].
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
+pub enum Qdisc {
+    /// The `clsact` qdisc, to attach filters with [`TcAttachType::Ingress`] and
+    /// [`TcAttachType::Egress`].
+    Clsact,
+    /// The `ingress` qdisc, to attach ingress filters with the parent `ffff:`, see
+    /// [`Qdisc::filter_parent`].
+    Ingress,
+    /// The `htb` (Hierarchy Token Bucket) classful qdisc at the root of the interface.
+    ///
+    /// Its classes must be created separately, for instance with `tc class add`.
+    Htb {
+        /// The major number of the qdisc handle.
+        major: u16,
+        /// The minor number of the class unclassified traffic is sent to.
+        default_class: u16,
+    },
+}
+
+impl Qdisc {
+    /// Returns the attach type of the filters of the qdisc.
+    ///
+    /// For [`Qdisc::Clsact`], which has both ingress and egress filters, this returns
+    /// [`TcAttachType::Ingress`].
+    pub const fn filter_parent(self) -> TcAttachType {
+        match self {
+            Self::Clsact => TcAttachType::Ingress,
+            Self::Ingress => TcAttachType::Custom(tc_handler_make(TC_H_INGRESS, TC_H_UNSPEC)),
+            Self::Htb { major, .. } => TcAttachType::Custom((major as u32) << 16),
+        }
+    }
+}
+
+/// A BPF filter attached with netlink, as returned by [`SchedClassifier::query_netlink`].
+#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+pub struct TcFilter {
+    pub(crate) if_index: u32,
+    pub(crate) attach_type: TcAttachType,
+    pub(crate) priority: u16,
+    pub(crate) handle: TcHandle,
+    pub(crate) chain: u32,
+    pub(crate) name: Option<CString>,
+    pub(crate) program_id: Option<u32>,
+    pub(crate) classid: Option<TcHandle>,
+    pub(crate) direct_action: bool,
+}
+
+impl TcFilter {
+    /// Returns the attach type the filter was queried with.
+    pub const fn attach_type(&self) -> TcAttachType {
+        self.attach_type
+    }
+
+    /// Returns the priority of the filter.
+    pub const fn priority(&self) -> u16 {
+        self.priority
+    }
+
+    /// Returns the handle of the filter.
+    pub const fn handle(&self) -> TcHandle {
+        self.handle
+    }
+
+    /// Returns the chain of the filter.
+    pub const fn chain(&self) -> u32 {
+        self.chain
+    }
+
+    /// Returns the name of the filter, usually the ELF section and name of the program.
+    pub fn name(&self) -> Option<&CStr> {
+        self.name.as_deref()
+    }
+
+    /// Returns the id of the program, or [`None`] on kernels older than 4.13.
+    #[doc(alias = "TCA_BPF_ID")]
+    pub const fn program_id(&self) -> Option<u32> {
+        self.program_id
+    }
+
+    /// Returns information about the program, or [`None`] on kernels older than 4.13.
+    pub fn program_info(&self) -> Result<Option<ProgramInfo>, ProgramError> {
+        self.program_id
+            .map(|prog_id| {
+                let prog_fd = bpf_prog_get_fd_by_id(prog_id)?;
+                ProgramInfo::new_from_fd(prog_fd.as_fd())
+            })
+            .transpose()
+    }
+
+    /// Returns the `classid` bound to the filter. See [`NlOptions::classid`].
+    pub const fn classid(&self) -> Option<TcHandle> {
+        self.classid
+    }
+
+    /// Returns whether the program runs in direct-action mode. See
+    /// [`NlOptions::direct_action`].
+    pub const fn direct_action(&self) -> bool {
+        self.direct_action
+    }
 }
 
 impl SchedClassifier {
@@ -237,19 +429,31 @@ impl SchedClassifier {
         interface: &str,
         attach_type: TcAttachType,
     ) -> Result<SchedClassifierLinkId, ProgramError> {
-        if !matches!(attach_type, TcAttachType::Custom(_)) && KernelVersion::at_least(6, 6, 0) {
-            self.attach_with_options(
-                interface,
-                attach_type,
-                TcAttachOptions::TcxOrder(LinkOrder::default()),
-            )
-        } else {
-            self.attach_with_options(
-                interface,
-                attach_type,
-                TcAttachOptions::Netlink(NlOptions::default()),
-            )
-        }
+        self.attach_with_options(interface, attach_type, default_attach_options(attach_type))
+    }
+
+    /// Attaches the program to the given `interface` of the network namespace `netns`.
+    ///
+    /// Like [`SchedClassifier::attach`], but the interface is looked up, and the program
+    /// attached, from a helper thread which enters `netns`. Links attached via netlink are also
+    /// detached from `netns`.
+    ///
+    /// # Errors
+    ///
+    /// See [`SchedClassifier::attach`]. [`TcError::NetNsError`] is returned if `netns` can't be
+    /// entered. A `clsact` qdisc can be added to the interface with [`qdisc_add_in_netns`].
+    pub fn attach_in_netns(
+        &mut self,
+        interface: &str,
+        attach_type: TcAttachType,
+        netns: &NetNs,
+    ) -> Result<SchedClassifierLinkId, ProgramError> {
+        self.attach_with_options_in_netns(
+            interface,
+            attach_type,
+            default_attach_options(attach_type),
+            netns,
+        )
     }
 
     /// Attaches the program to the given `interface` with options defined in [`TcAttachOptions`].
@@ -312,12 +516,32 @@ impl SchedClassifier {
         options: TcAttachOptions,
     ) -> Result<SchedClassifierLinkId, ProgramError> {
         let if_index = ifindex_from_ifname(interface).map_err(TcError::IoError)?;
-        self.do_attach(if_index, attach_type, options, true)
+        self.do_attach(if_index, attach_type, options, true, None)
+    }
+
+    /// Attaches the program to the given `interface` of the network namespace `netns` with
+    /// options defined in [`TcAttachOptions`].
+    ///
+    /// See [`SchedClassifier::attach_with_options`] and [`SchedClassifier::attach_in_netns`].
+    pub fn attach_with_options_in_netns(
+        &mut self,
+        interface: &str,
+        attach_type: TcAttachType,
+        options: TcAttachOptions,
+        netns: &NetNs,
+    ) -> Result<SchedClassifierLinkId, ProgramError> {
+        let if_index = NetNs::run(Some(netns), || ifindex_from_ifname(interface))
+            .map_err(TcError::NetNsError)?
+            .map_err(TcError::IoError)?;
+        self.do_attach(if_index, attach_type, options, true, Some(netns))
     }
 
     /// Atomically replaces the program referenced by the provided link.
     ///
-    /// Ownership of the link will transfer to this program.
+    /// Ownership of the link will transfer to this program. Filters attached via netlink,
+    /// including ones found with [`SchedClassifier::query_netlink`] and turned into links with
+    /// [`SchedClassifierLink::from_filter`], are replaced with `NLM_F_REPLACE`, keeping their
+    /// priority, handle, chain, `classid` and mode.
     pub fn attach_to_link(
         &mut self,
         link: SchedClassifierLink,
@@ -346,6 +570,9 @@ impl SchedClassifier {
                 priority,
                 handle,
                 classid,
+                chain,
+                direct_action,
+                netns,
             }) => self.do_attach(
                 if_index,
                 attach_type,
@@ -353,18 +580,22 @@ impl SchedClassifier {
                     priority,
                     handle,
                     classid,
+                    chain,
+                    direct_action,
                 }),
                 false,
+                netns.as_ref(),
             ),
         }
     }
 
-    fn do_attach(
+    pub(crate) fn do_attach(
         &mut self,
         if_index: u32,
         attach_type: TcAttachType,
         options: TcAttachOptions,
         create: bool,
+        netns: Option<&NetNs>,
     ) -> Result<SchedClassifierLinkId, ProgramError> {
         let prog_fd = self.fd()?;
         let prog_fd = prog_fd.as_fd();
@@ -374,18 +605,17 @@ impl SchedClassifier {
                 let name = self.data.name.as_deref().unwrap_or_default();
                 // TODO: avoid this unwrap by adding a new error variant.
                 let name = CString::new(name).unwrap();
-                let (priority, handle) = unsafe {
+                let (priority, handle) = NetNs::run(netns, || unsafe {
                     netlink_qdisc_attach(
                         if_index as i32,
                         &attach_type,
                         prog_fd,
                         &name,
-                        options.priority,
-                        options.handle,
-                        options.classid,
+                        &options,
                         create,
                     )
-                }
+                })
+                .map_err(TcError::NetNsError)?
                 .map_err(TcError::NetlinkError)?;
 
                 self.data
@@ -396,16 +626,24 @@ impl SchedClassifier {
                         priority,
                         handle,
                         classid: options.classid,
+                        chain: options.chain,
+                        direct_action: options.direct_action,
+                        netns: netns.cloned(),
                     })))
             }
             TcAttachOptions::TcxOrder(options) => {
-                let link_fd = bpf_link_create(
-                    prog_fd,
-                    LinkTarget::IfIndex(if_index),
-                    attach_type.tcx_attach_type()?,
-                    options.flags.bits(),
-                    Some(BpfLinkCreateArgs::Tcx(&options.link_ref)),
-                )
+                let tcx_attach_type = attach_type.tcx_attach_type()?;
+                // The kernel looks the interface index up in the network namespace of the caller.
+                let link_fd = NetNs::run(netns, || {
+                    bpf_link_create(
+                        prog_fd,
+                        LinkTarget::IfIndex(if_index),
+                        tcx_attach_type,
+                        options.flags.bits(),
+                        Some(BpfLinkCreateArgs::Tcx(&options.link_ref)),
+                    )
+                })
+                .map_err(TcError::NetNsError)?
                 .map_err(|io_error| SyscallError {
                     call: "bpf_mprog_attach",
                     io_error,
@@ -469,10 +707,42 @@ impl SchedClassifier {
 
         Ok((revision, prog_infos))
     }
+
+    /// Queries a given interface for the BPF filters attached via netlink.
+    ///
+    /// The filters of all the chains of `attach_type` are returned, whichever loader attached
+    /// them. Non-BPF filters are skipped.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use aya::programs::tc::{SchedClassifier, SchedClassifierLink, TcAttachType};
+    /// # let mut bpf = aya::Ebpf::load(&[])?;
+    /// let prog: &mut SchedClassifier = bpf.program_mut("redirect_ingress").unwrap().try_into()?;
+    /// prog.load()?;
+    ///
+    /// // Atomically replace the program of the filters named after ours.
+    /// for filter in SchedClassifier::query_netlink("eth0", TcAttachType::Ingress)? {
+    ///     if filter.name() == Some(c"redirect_ingress") {
+    ///         prog.attach_to_link(SchedClassifierLink::from_filter(&filter))?;
+    ///     }
+    /// }
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    #[doc(alias = "RTM_GETTFILTER")]
+    pub fn query_netlink(
+        interface: &str,
+        attach_type: TcAttachType,
+    ) -> Result<Vec<TcFilter>, ProgramError> {
+        let if_index = ifindex_from_ifname(interface).map_err(TcError::IoError)?;
+        let filters =
+            netlink_list_filters(if_index as i32, attach_type).map_err(TcError::NetlinkError)?;
+        Ok(filters)
+    }
 }
 
 #[derive(Debug, Hash, Eq, PartialEq)]
-pub(crate) struct NlLinkId(u32, TcAttachType, u16, TcHandle);
+pub(crate) struct NlLinkId(u32, TcAttachType, u16, TcHandle, u32, Option<RawFd>);
 
 #[derive(Debug)]
 pub(crate) struct NlLink {
@@ -481,24 +751,36 @@ pub(crate) struct NlLink {
     priority: u16,
     handle: TcHandle,
     classid: Option<TcHandle>,
+    chain: u32,
+    direct_action: bool,
+    // The network namespace of the interface, if not the one of the calling thread.
+    netns: Option<NetNs>,
 }
 
 impl Link for NlLink {
     type Id = NlLinkId;
 
     fn id(&self) -> Self::Id {
-        NlLinkId(self.if_index, self.attach_type, self.priority, self.handle)
+        NlLinkId(
+            self.if_index,
+            self.attach_type,
+            self.priority,
+            self.handle,
+            self.chain,
+            self.netns.as_ref().map(|netns| netns.as_fd().as_raw_fd()),
+        )
     }
 
     fn detach(self) -> Result<(), ProgramError> {
-        unsafe {
+        NetNs::run(self.netns.as_ref(), || unsafe {
             netlink_qdisc_detach(
                 self.if_index as i32,
                 self.attach_type,
                 self.priority,
                 self.handle,
+                self.chain,
             )
-        }
+        })?
         .map_err(ProgramError::NetlinkError)?;
         Ok(())
     }
@@ -612,9 +894,44 @@ impl SchedClassifierLink {
             priority,
             handle,
             classid,
+            chain: 0,
+            direct_action: true,
+            netns: None,
         }))))
     }
 
+    /// Constructs a [`SchedClassifierLink`] from a filter returned by
+    /// [`SchedClassifier::query_netlink`].
+    ///
+    /// Pass the link to [`SchedClassifier::attach_to_link`] to atomically replace the program
+    /// of the filter.
+    ///
+    /// Note: If you create a link for a program that you do not own, detaching it may have
+    /// unintended consequences.
+    pub const fn from_filter(filter: &TcFilter) -> Self {
+        let TcFilter {
+            if_index,
+            attach_type,
+            priority,
+            handle,
+            chain,
+            name: _,
+            program_id: _,
+            classid,
+            direct_action,
+        } = *filter;
+        Self(Some(TcLinkInner::NlLink(NlLink {
+            if_index,
+            attach_type,
+            priority,
+            handle,
+            classid,
+            chain,
+            direct_action,
+            netns: None,
+        })))
+    }
+
     /// Returns the attach type.
     pub fn attach_type(&self) -> Result<TcAttachType, ProgramError> {
         if let TcLinkInner::NlLink(n) = self.inner() {
@@ -651,6 +968,25 @@ impl SchedClassifierLink {
             Err(TcError::InvalidLinkOperation.into())
         }
     }
+
+    /// Returns the chain of this filter. See [`NlOptions::chain`].
+    pub fn chain(&self) -> Result<u32, ProgramError> {
+        if let TcLinkInner::NlLink(n) = self.inner() {
+            Ok(n.chain)
+        } else {
+            Err(TcError::InvalidLinkOperation.into())
+        }
+    }
+
+    /// Returns whether the program runs in direct-action mode. See
+    /// [`NlOptions::direct_action`].
+    pub fn direct_action(&self) -> Result<bool, ProgramError> {
+        if let TcLinkInner::NlLink(n) = self.inner() {
+            Ok(n.direct_action)
+        } else {
+            Err(TcError::InvalidLinkOperation.into())
+        }
+    }
 }
 
 /// Add the `clasct` qdisc to the given interface.
@@ -658,12 +994,50 @@ impl SchedClassifierLink {
 /// The `clsact` qdisc must be added to an interface before [`SchedClassifier`]
 /// programs can be attached.
 pub fn qdisc_add_clsact(if_name: &str) -> Result<(), TcError> {
+    qdisc_add(if_name, Qdisc::Clsact)
+}
+
+/// Add the given qdisc to the given interface.
+///
+/// Use [`Qdisc::filter_parent`] to attach [`SchedClassifier`] programs to the qdisc.
+///
+/// # Example
+///
+/// ```no_run
+/// # use aya::programs::tc::{self, NlOptions, Qdisc, SchedClassifier, TcAttachOptions};
+/// # let mut bpf = aya::Ebpf::load(&[])?;
+/// let htb = Qdisc::Htb {
+///     major: 1,
+///     default_class: 10,
+/// };
+/// tc::qdisc_add("eth0", htb)?;
+///
+/// // Classify the packets in classes of `1:`, without direct-action.
+/// let prog: &mut SchedClassifier = bpf.program_mut("classify").unwrap().try_into()?;
+/// prog.load()?;
+/// prog.attach_with_options(
+///     "eth0",
+///     htb.filter_parent(),
+///     TcAttachOptions::Netlink(NlOptions::default().direct_action(false)),
+/// )?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn qdisc_add(if_name: &str, qdisc: Qdisc) -> Result<(), TcError> {
     let if_index = ifindex_from_ifname(if_name)?;
-    unsafe { netlink_qdisc_add_clsact(if_index as i32).map_err(TcError::NetlinkError) }
+    unsafe { netlink_qdisc_add(if_index as i32, qdisc).map_err(TcError::NetlinkError) }
+}
+
+/// Add the given qdisc to the given interface of the network namespace `netns`.
+///
+/// See [`qdisc_add`] and [`SchedClassifier::attach_in_netns`].
+pub fn qdisc_add_in_netns(if_name: &str, qdisc: Qdisc, netns: &NetNs) -> Result<(), TcError> {
+    NetNs::run(Some(netns), || qdisc_add(if_name, qdisc))?
 }
 
 /// Detaches the programs with the given name.
 ///
+/// The filters of all the chains of `attach_type` are considered.
+///
 /// # Errors
 ///
 /// Returns [`io::ErrorKind::NotFound`] to indicate that no programs with the
@@ -677,19 +1051,25 @@ pub fn qdisc_detach_program(
     let cstr = CString::new(name).map_err(TcError::NulError)?;
     let if_index = ifindex_from_ifname(if_name)? as i32;
 
-    let sock = NetlinkSocket::open().map_err(NetlinkError::from)?;
-    let filter_info = netlink_find_filter_with_name(&sock, if_index, attach_type, &cstr)?;
-    // Check for errors before detaching any programs.
-    let filter_info: Vec<_> = filter_info.collect::<Result<_, _>>()?;
-    if filter_info.is_empty() {
+    let filters: Vec<_> = netlink_list_filters(if_index, attach_type)?
+        .into_iter()
+        .filter(|filter| filter.name() == Some(cstr.as_c_str()))
+        .collect();
+    if filters.is_empty() {
         return Err(TcError::IoError(io::Error::new(
             io::ErrorKind::NotFound,
             name.to_owned(),
         )));
     }
 
-    for (prio, handle) in filter_info {
-        unsafe { netlink_qdisc_detach(if_index, attach_type, prio, handle)? }
+    for TcFilter {
+        priority,
+        handle,
+        chain,
+        ..
+    } in filters
+    {
+        unsafe { netlink_qdisc_detach(if_index, attach_type, priority, handle, chain)? }
     }
 
     Ok(())
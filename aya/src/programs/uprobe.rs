@@ -1024,20 +1024,15 @@ pub enum ProcMapError {
 /// This contains information about a mapped portion of memory
 /// for the process, ranging from address to `address_end`.
 #[cfg_attr(test, derive(Debug, PartialEq))]
-struct ProcMapEntry<'a> {
-    #[cfg_attr(not(test), expect(dead_code, reason = "parsed but not exposed"))]
-    address: u64,
-    #[cfg_attr(not(test), expect(dead_code, reason = "parsed but not exposed"))]
-    address_end: u64,
+pub(crate) struct ProcMapEntry<'a> {
+    pub(crate) address: u64,
+    pub(crate) address_end: u64,
     #[cfg_attr(not(test), expect(dead_code, reason = "parsed but not exposed"))]
     perms: &'a OsStr,
-    #[cfg_attr(not(test), expect(dead_code, reason = "parsed but not exposed"))]
-    offset: u64,
-    #[cfg_attr(not(test), expect(dead_code, reason = "parsed but not exposed"))]
-    dev: &'a OsStr,
-    #[cfg_attr(not(test), expect(dead_code, reason = "parsed but not exposed"))]
-    inode: u32,
-    path: Option<&'a OsStr>,
+    pub(crate) offset: u64,
+    pub(crate) dev: &'a OsStr,
+    pub(crate) inode: u64,
+    pub(crate) path: Option<&'a OsStr>,
 }
 
 /// Split a byte slice on ASCII whitespace up to `n` times.
@@ -1144,9 +1139,9 @@ impl<'a> ProcMapEntry<'a> {
 /// This is read from /proc/`pid`/maps.
 ///
 /// The information here may be used to resolve addresses to paths.
-struct ProcMap<T> {
-    pid: u32,
-    data: T,
+pub(crate) struct ProcMap<T> {
+    pub(crate) pid: u32,
+    pub(crate) data: T,
 }
 
 impl ProcMap<Vec<u8>> {
@@ -1159,7 +1154,7 @@ impl ProcMap<Vec<u8>> {
 }
 
 impl<T: AsRef<[u8]>> ProcMap<T> {
-    fn libs(&self) -> impl Iterator<Item = Result<ProcMapEntry<'_>, ProcMapError>> {
+    pub(crate) fn libs(&self) -> impl Iterator<Item = Result<ProcMapEntry<'_>, ProcMapError>> {
         let Self { pid: _, data } = self;
 
         // /proc/<pid>/maps ends with '\n', so split() yields a trailing empty slice without this.
@@ -1352,7 +1347,7 @@ enum ResolveSymbolError {
     BuildIdMismatch(String),
 }
 
-fn construct_debuglink_path<'a>(filename: &'a [u8], main_path: &Path) -> Cow<'a, Path> {
+pub(crate) fn construct_debuglink_path<'a>(filename: &'a [u8], main_path: &Path) -> Cow<'a, Path> {
     let filename_str = OsStr::from_bytes(filename);
     let debuglink_path = Path::new(filename_str);
 
@@ -1844,7 +1839,7 @@ mod tests {
         perms: &'static str,
         offset: u64,
         dev: &'static str,
-        inode: u32,
+        inode: u64,
         path: Option<&'static str>,
     }
 
@@ -3,14 +3,15 @@
 use std::{
     ffi::CString,
     hash::Hash,
+    io,
     os::fd::{AsFd as _, AsRawFd as _, BorrowedFd, RawFd},
     path::Path,
 };
 
 use aya_obj::{
     generated::{
-        XDP_FLAGS_DRV_MODE, XDP_FLAGS_HW_MODE, XDP_FLAGS_SKB_MODE, bpf_link_type,
-        bpf_prog_type::BPF_PROG_TYPE_XDP,
+        BPF_F_XDP_DEV_BOUND_ONLY, XDP_FLAGS_DRV_MODE, XDP_FLAGS_HW_MODE, XDP_FLAGS_SKB_MODE,
+        bpf_link_type, bpf_prog_type::BPF_PROG_TYPE_XDP,
     },
     programs::XdpAttachType,
 };
@@ -19,14 +20,14 @@ use thiserror::Error;
 use crate::{
     VerifierLogLevel,
     programs::{
-        FdLink, Link, ProgramData, ProgramError, ProgramType, define_link_wrapper, id_as_key,
-        impl_try_from_fdlink, impl_try_into_fdlink, load_program_with_attach_type,
+        FdLink, Link, NetNs, ProgramData, ProgramError, ProgramType, define_link_wrapper,
+        id_as_key, impl_try_from_fdlink, impl_try_into_fdlink, load_program_with_attach_type,
     },
     sys::{
         LinkTarget, NetlinkError, SyscallError, bpf_link_create, bpf_link_update,
         netlink_set_xdp_fd,
     },
-    util::KernelVersion,
+    util::{KernelVersion, ifindex_from_ifname},
 };
 
 /// An error that occurred while working with an XDP program.
@@ -97,6 +98,58 @@ impl Xdp {
     /// Loads the program inside the kernel.
     pub fn load(&mut self) -> Result<(), ProgramError> {
         let Self { data, attach_type } = self;
+        data.ifindex = None;
+        data.flags &= !BPF_F_XDP_DEV_BOUND_ONLY;
+        load_program_with_attach_type(BPF_PROG_TYPE_XDP, *attach_type, data)
+    }
+
+    /// Loads the program inside the kernel, bound to the given `interface`.
+    ///
+    /// Device-bound programs can call the XDP metadata kfuncs implemented by the driver of
+    /// `interface`, such as `bpf_xdp_metadata_rx_hash`, but can only be attached to
+    /// `interface`.
+    ///
+    /// # Minimum kernel version
+    ///
+    /// The minimum kernel version required to use this feature is 6.3.
+    ///
+    /// # Errors
+    ///
+    /// If the given `interface` does not exist
+    /// [`ProgramError::UnknownInterface`] is returned.
+    pub fn load_dev_bound(&mut self, interface: &str) -> Result<(), ProgramError> {
+        self.load_for_interface(interface, BPF_F_XDP_DEV_BOUND_ONLY)
+    }
+
+    /// Loads the program inside the kernel, offloaded to the given `interface`.
+    ///
+    /// Offloaded programs are executed by the network device and must be attached to
+    /// `interface` with [`XdpMode::Hardware`]. The maps they use must be offloaded to the same
+    /// device, see [`EbpfLoader::offload_map`](crate::EbpfLoader::offload_map). Use
+    /// [`is_offload_supported`](crate::sys::is_offload_supported) to check whether the device
+    /// supports offload.
+    ///
+    /// # Minimum kernel version
+    ///
+    /// The minimum kernel version required to use this feature is 4.15.
+    ///
+    /// # Errors
+    ///
+    /// If the given `interface` does not exist
+    /// [`ProgramError::UnknownInterface`] is returned.
+    pub fn load_offloaded(&mut self, interface: &str) -> Result<(), ProgramError> {
+        self.load_for_interface(interface, 0)
+    }
+
+    fn load_for_interface(&mut self, interface: &str, flags: u32) -> Result<(), ProgramError> {
+        let if_index = ifindex_from_ifname(interface).map_err(|_io_error: io::Error| {
+            ProgramError::UnknownInterface {
+                name: interface.to_string(),
+            }
+        })?;
+        let Self { data, attach_type } = self;
+        data.ifindex = Some(if_index);
+        data.flags = (data.flags & !BPF_F_XDP_DEV_BOUND_ONLY) | flags;
         load_program_with_attach_type(BPF_PROG_TYPE_XDP, *attach_type, data)
     }
 
@@ -123,6 +176,32 @@ impl Xdp {
         self.attach_to_if_index(if_index, mode)
     }
 
+    /// Attaches the program to the given `interface` of the network namespace `netns`.
+    ///
+    /// The interface is looked up, and the program attached, from a helper thread which enters
+    /// `netns`. Links attached via netlink are also detached from `netns`.
+    ///
+    /// The returned value can be used to detach, see [`Xdp::detach`].
+    ///
+    /// # Errors
+    ///
+    /// If the given `interface` does not exist in `netns`
+    /// [`ProgramError::UnknownInterface`] is returned. If `netns` can't be
+    /// entered, [`ProgramError::NetNsError`] is returned.
+    pub fn attach_in_netns(
+        &mut self,
+        interface: &str,
+        mode: XdpMode,
+        netns: &NetNs,
+    ) -> Result<XdpLinkId, ProgramError> {
+        let if_index = NetNs::run(Some(netns), || ifindex_from_ifname(interface))?.map_err(
+            |_io_error: io::Error| ProgramError::UnknownInterface {
+                name: interface.to_string(),
+            },
+        )?;
+        self.do_attach(if_index, mode, Some(netns))
+    }
+
     /// Attaches the program to the given interface index.
     ///
     /// The returned value can be used to detach, see [`Xdp::detach`].
@@ -135,18 +214,31 @@ impl Xdp {
         &mut self,
         if_index: u32,
         mode: XdpMode,
+    ) -> Result<XdpLinkId, ProgramError> {
+        self.do_attach(if_index, mode, None)
+    }
+
+    pub(crate) fn do_attach(
+        &mut self,
+        if_index: u32,
+        mode: XdpMode,
+        netns: Option<&NetNs>,
     ) -> Result<XdpLinkId, ProgramError> {
         let Self { data, attach_type } = self;
         let prog_fd = data.fd()?;
         let prog_fd = prog_fd.as_fd();
         let flags = mode.flags();
-        let link = match bpf_link_create(
-            prog_fd,
-            LinkTarget::IfIndex(if_index),
-            *attach_type,
-            flags,
-            None,
-        ) {
+        let attach_type = *attach_type;
+        // The kernel looks the interface index up in the network namespace of the caller.
+        let link = match NetNs::run(netns, || {
+            bpf_link_create(
+                prog_fd,
+                LinkTarget::IfIndex(if_index),
+                attach_type,
+                flags,
+                None,
+            )
+        })? {
             Ok(link_fd) => XdpLinkInner::Fd(FdLink::new(link_fd)),
             Err(io_error) => {
                 if io_error.raw_os_error() != Some(libc::EINVAL) {
@@ -159,14 +251,17 @@ impl Xdp {
                 // Fall back to netlink-based attachment.
 
                 let if_index = if_index as i32;
-                unsafe { netlink_set_xdp_fd(if_index, Some(prog_fd), None, mode) }
-                    .map_err(XdpError::NetlinkError)?;
+                NetNs::run(netns, || unsafe {
+                    netlink_set_xdp_fd(if_index, Some(prog_fd), None, mode)
+                })?
+                .map_err(XdpError::NetlinkError)?;
 
                 let prog_fd = prog_fd.as_raw_fd();
                 XdpLinkInner::NlLink(NlLink {
                     if_index,
                     prog_fd,
                     mode,
+                    netns: netns.cloned(),
                 })
             }
         };
@@ -211,6 +306,7 @@ impl Xdp {
                 if_index,
                 prog_fd: old_prog_fd,
                 mode,
+                netns,
             }) => {
                 // SAFETY: TODO(https://github.com/aya-rs/aya/issues/612): make this safe by not holding `RawFd`s.
                 let old_prog_fd = unsafe { BorrowedFd::borrow_raw(old_prog_fd) };
@@ -223,8 +319,10 @@ impl Xdp {
                     // were added in Linux 5.7. On older kernels this request
                     // is expected to fail in the kernel instead of degrading to
                     // an unconditional replacement.
-                    netlink_set_xdp_fd(if_index, Some(prog_fd), Some(old_prog_fd), mode)
-                        .map_err(XdpError::NetlinkError)?;
+                    NetNs::run(netns.as_ref(), || {
+                        netlink_set_xdp_fd(if_index, Some(prog_fd), Some(old_prog_fd), mode)
+                    })?
+                    .map_err(XdpError::NetlinkError)?;
                 }
 
                 let prog_fd = prog_fd.as_raw_fd();
@@ -234,6 +332,7 @@ impl Xdp {
                         if_index,
                         prog_fd,
                         mode,
+                        netns,
                     })))
             }
         }
@@ -245,10 +344,12 @@ pub(crate) struct NlLink {
     if_index: i32,
     prog_fd: RawFd,
     mode: XdpMode,
+    // The network namespace of the interface, if not the one of the calling thread.
+    netns: Option<NetNs>,
 }
 
 #[derive(Debug, Hash, Eq, PartialEq)]
-pub(crate) struct NlLinkId(i32, RawFd);
+pub(crate) struct NlLinkId(i32, RawFd, Option<RawFd>);
 
 impl Link for NlLink {
     type Id = NlLinkId;
@@ -258,8 +359,13 @@ impl Link for NlLink {
             if_index,
             prog_fd,
             mode: _,
+            netns,
         } = self;
-        NlLinkId(*if_index, *prog_fd)
+        NlLinkId(
+            *if_index,
+            *prog_fd,
+            netns.as_ref().map(|netns| netns.as_fd().as_raw_fd()),
+        )
     }
 
     fn detach(self) -> Result<(), ProgramError> {
@@ -267,6 +373,7 @@ impl Link for NlLink {
             if_index,
             prog_fd,
             mode,
+            netns,
         } = self;
         // IFLA_XDP_EXPECTED_FD and XDP_FLAGS_REPLACE were added in Linux 5.7;
         // see https://github.com/torvalds/linux/commit/92234c8f. Use them
@@ -278,8 +385,9 @@ impl Link for NlLink {
             // SAFETY: TODO(https://github.com/aya-rs/aya/issues/612): make this safe by not holding `RawFd`s.
             unsafe { BorrowedFd::borrow_raw(prog_fd) }
         });
-        let _unused: Result<(), NetlinkError> =
-            unsafe { netlink_set_xdp_fd(if_index, None, prog_fd, mode) };
+        let _unused: Result<Result<(), NetlinkError>, _> = NetNs::run(netns.as_ref(), || unsafe {
+            netlink_set_xdp_fd(if_index, None, prog_fd, mode)
+        });
         Ok(())
     }
 }
@@ -25,8 +25,8 @@ use aya_obj::{
     maps::{LegacyMap, bpf_map_def},
 };
 use libc::{
-    EBADF, ENOENT, ENOMEM, ENOSPC, EPERM, RLIM_INFINITY, RLIMIT_MEMLOCK, getrlimit, rlim_t, rlimit,
-    setrlimit,
+    EBADF, EINVAL, ENOENT, ENOMEM, ENOSPC, EPERM, RLIM_INFINITY, RLIMIT_MEMLOCK, getrlimit, rlim_t,
+    rlimit, setrlimit,
 };
 use log::warn;
 
@@ -57,6 +57,7 @@ pub(crate) fn bpf_create_map(
     def: &aya_obj::Map,
     btf_fd: Option<BorrowedFd<'_>>,
     inner_map_fd: Option<BorrowedFd<'_>>,
+    map_ifindex: Option<u32>,
 ) -> io::Result<crate::MockableFd> {
     let mut attr = unsafe { mem::zeroed::<bpf_attr>() };
 
@@ -73,6 +74,11 @@ pub(crate) fn bpf_create_map(
         u.inner_map_fd = inner_fd.as_raw_fd() as u32;
     }
 
+    // For maps offloaded to a network device.
+    if let Some(v) = map_ifindex {
+        u.map_ifindex = v;
+    }
+
     if let aya_obj::Map::Btf(m) = def {
         // Mimic https://github.com/libbpf/libbpf/issues/355
         // Currently a bunch of (usually pretty specialized) BPF maps do not support
@@ -143,6 +149,48 @@ pub(crate) fn bpf_get_object(path: &CStr) -> io::Result<crate::MockableFd> {
     unsafe { fd_sys_bpf(bpf_cmd::BPF_OBJ_GET, &mut attr) }
 }
 
+/// The kind of object a BPF file descriptor refers to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ObjectKind {
+    Map,
+    Program,
+    Link,
+}
+
+/// Returns the kind of object `fd` refers to, or `None` if it isn't a map, program or link.
+pub(crate) fn bpf_object_kind(fd: BorrowedFd<'_>) -> io::Result<Option<ObjectKind>> {
+    #[cfg(feature = "fake")]
+    if let Some(kernel) = crate::fake::FakeKernel::current() {
+        return Ok(kernel.object_kind(fd));
+    }
+    // BPF_OBJ_GET_INFO_BY_FD fills in the info of whatever kind the object is, so the kind is
+    // taken from the fdinfo fields specific to each kind.
+    let fdinfo = std::fs::read_to_string(format!("/proc/self/fdinfo/{}", fd.as_raw_fd()))?;
+    Ok(fdinfo.lines().find_map(|line| {
+        let (key, _) = line.split_once(':')?;
+        match key {
+            "map_type" => Some(ObjectKind::Map),
+            "prog_type" => Some(ObjectKind::Program),
+            "link_type" => Some(ObjectKind::Link),
+            _ => None,
+        }
+    }))
+}
+
+/// Opens the object pinned at `path`, failing with `EINVAL` like the kernel does for file
+/// descriptors of the wrong kind if it isn't a `kind`.
+pub(crate) fn bpf_get_object_of_kind(
+    path: &CStr,
+    kind: ObjectKind,
+) -> io::Result<crate::MockableFd> {
+    let fd = bpf_get_object(path)?;
+    if bpf_object_kind(fd.as_fd())? == Some(kind) {
+        Ok(fd)
+    } else {
+        Err(io::Error::from_raw_os_error(EINVAL))
+    }
+}
+
 pub(crate) struct EbpfLoadProgramAttrs<'a> {
     pub(crate) name: Option<CString>,
     pub(crate) ty: bpf_prog_type,
@@ -159,6 +207,7 @@ pub(crate) struct EbpfLoadProgramAttrs<'a> {
     pub(crate) line_info_rec_size: usize,
     pub(crate) line_info: LineSecInfo,
     pub(crate) flags: u32,
+    pub(crate) prog_ifindex: Option<u32>,
 }
 
 pub(crate) fn bpf_load_program(
@@ -178,6 +227,9 @@ pub(crate) fn bpf_load_program(
     }
 
     u.prog_flags = aya_attr.flags;
+    if let Some(v) = aya_attr.prog_ifindex {
+        u.prog_ifindex = v;
+    }
     u.prog_type = aya_attr.ty as u32;
     if let Some(v) = aya_attr.expected_attach_type {
         u.expected_attach_type = v as u32;
@@ -276,6 +328,21 @@ pub(crate) fn bpf_map_lookup_elem_per_cpu<K: Pod, V: Pod>(
     }
 }
 
+/// Selects a single CPU in per-CPU map operations, the CPU being in the upper 32 bits of the
+/// flags.
+///
+/// Introduced in kernel v6.19, not yet in the generated bindings.
+const BPF_F_CPU: u64 = 8;
+
+pub(crate) fn bpf_map_lookup_elem_cpu<K: Pod, V: Pod>(
+    fd: BorrowedFd<'_>,
+    key: &K,
+    cpu: u32,
+    flags: u64,
+) -> io::Result<Option<V>> {
+    bpf_map_lookup_elem(fd, key, flags | BPF_F_CPU | (u64::from(cpu) << 32))
+}
+
 pub(crate) fn bpf_map_lookup_elem_ptr<K: Pod, V>(
     fd: BorrowedFd<'_>,
     key: Option<&K>,
@@ -410,6 +477,75 @@ pub(crate) fn bpf_map_get_next_key<K: Pod>(
     }
 }
 
+pub(crate) fn bpf_map_lookup_elem_raw(
+    fd: BorrowedFd<'_>,
+    key: &[u8],
+    value: &mut [u8],
+    flags: u64,
+) -> io::Result<Option<()>> {
+    let mut attr = unsafe { mem::zeroed::<bpf_attr>() };
+
+    let u = unsafe { &mut attr.__bindgen_anon_2 };
+    u.map_fd = fd.as_raw_fd() as u32;
+    u.key = key.as_ptr() as u64;
+    u.__bindgen_anon_1.value = value.as_mut_ptr() as u64;
+    u.flags = flags;
+
+    match unit_sys_bpf(bpf_cmd::BPF_MAP_LOOKUP_ELEM, &mut attr) {
+        Ok(()) => Ok(Some(())),
+        Err(io_error) if io_error.raw_os_error() == Some(ENOENT) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+pub(crate) fn bpf_map_update_elem_raw(
+    fd: BorrowedFd<'_>,
+    key: &[u8],
+    value: &[u8],
+    flags: u64,
+) -> io::Result<()> {
+    let mut attr = unsafe { mem::zeroed::<bpf_attr>() };
+
+    let u = unsafe { &mut attr.__bindgen_anon_2 };
+    u.map_fd = fd.as_raw_fd() as u32;
+    u.key = key.as_ptr() as u64;
+    u.__bindgen_anon_1.value = value.as_ptr() as u64;
+    u.flags = flags;
+
+    unit_sys_bpf(bpf_cmd::BPF_MAP_UPDATE_ELEM, &mut attr)
+}
+
+pub(crate) fn bpf_map_delete_elem_raw(fd: BorrowedFd<'_>, key: &[u8]) -> io::Result<()> {
+    let mut attr = unsafe { mem::zeroed::<bpf_attr>() };
+
+    let u = unsafe { &mut attr.__bindgen_anon_2 };
+    u.map_fd = fd.as_raw_fd() as u32;
+    u.key = key.as_ptr() as u64;
+
+    unit_sys_bpf(bpf_cmd::BPF_MAP_DELETE_ELEM, &mut attr)
+}
+
+pub(crate) fn bpf_map_get_next_key_raw(
+    fd: BorrowedFd<'_>,
+    key: Option<&[u8]>,
+    next_key: &mut [u8],
+) -> io::Result<Option<()>> {
+    let mut attr = unsafe { mem::zeroed::<bpf_attr>() };
+
+    let u = unsafe { &mut attr.__bindgen_anon_2 };
+    u.map_fd = fd.as_raw_fd() as u32;
+    if let Some(key) = key {
+        u.key = key.as_ptr() as u64;
+    }
+    u.__bindgen_anon_1.next_key = next_key.as_mut_ptr() as u64;
+
+    match unit_sys_bpf(bpf_cmd::BPF_MAP_GET_NEXT_KEY, &mut attr) {
+        Ok(()) => Ok(Some(())),
+        Err(io_error) if io_error.raw_os_error() == Some(ENOENT) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
 // since kernel 5.2
 pub(crate) fn bpf_map_freeze(fd: BorrowedFd<'_>) -> io::Result<()> {
     let mut attr = unsafe { mem::zeroed::<bpf_attr>() };
@@ -881,6 +1017,23 @@ pub(crate) fn btf_obj_get_info_by_fd(
     })
 }
 
+/// Reads the raw BTF blob of the BTF object referenced by `fd`.
+pub(crate) fn btf_obj_get_data_by_fd(fd: BorrowedFd<'_>) -> Result<Vec<u8>, SyscallError> {
+    // we need to read the btf bytes into a buffer but we don't know the size ahead of time.
+    // assume 4kb. if this is too small we can resize based on the size obtained in the response.
+    let mut buf = vec![0u8; 4096];
+    loop {
+        let info = btf_obj_get_info_by_fd(fd, &mut buf)?;
+        let btf_size = info.btf_size as usize;
+        if btf_size > buf.len() {
+            buf.resize(btf_size, 0u8);
+            continue;
+        }
+        buf.truncate(btf_size);
+        return Ok(buf);
+    }
+}
+
 pub(crate) fn bpf_raw_tracepoint_open(
     name: Option<&CStr>,
     prog_fd: BorrowedFd<'_>,
@@ -1239,6 +1392,22 @@ pub(crate) fn is_prog_id_supported(map_type: bpf_map_type) -> bool {
     bpf_map_create(&mut attr).is_ok()
 }
 
+/// Tests whether per-CPU map lookups can select a single CPU with `BPF_F_CPU`.
+pub(crate) fn is_map_lookup_cpu_supported() -> bool {
+    let mut attr = unsafe { mem::zeroed::<bpf_attr>() };
+    let u = unsafe { &mut attr.__bindgen_anon_1 };
+
+    u.map_type = bpf_map_type::BPF_MAP_TYPE_PERCPU_ARRAY as u32;
+    u.key_size = 4;
+    u.value_size = 4;
+    u.max_entries = 1;
+
+    let Ok(fd) = bpf_map_create(&mut attr) else {
+        return false;
+    };
+    bpf_map_lookup_elem_cpu::<u32, u32>(fd.as_fd(), &0, 0, 0).is_ok()
+}
+
 pub(crate) fn is_btf_supported() -> bool {
     let mut btf = Btf::new();
     let name_offset = btf.add_string("int");
@@ -1530,7 +1699,6 @@ mod tests {
     use aya_obj::{
         generated::bpf_map_type::BPF_MAP_TYPE_BLOOM_FILTER, maps::PinningType, obj::parse_map_info,
     };
-    use libc::EINVAL;
     use rstest::rstest;
 
     use super::*;
@@ -1659,7 +1827,7 @@ bpf_map_type::BPF_MAP_TYPE_DEVMAP_HASH`"]
 
         let name = CString::new("FILTER").unwrap();
         let btf_fd = unsafe { BorrowedFd::borrow_raw(BTF_FD) };
-        bpf_create_map(&name, &map, Some(btf_fd), None).unwrap();
+        bpf_create_map(&name, &map, Some(btf_fd), None, None).unwrap();
     }
 
     #[rstest]
@@ -1705,6 +1873,33 @@ bpf_map_type::BPF_MAP_TYPE_DEVMAP_HASH`"]
 
         let name = CString::new("TEST").unwrap();
         let btf_fd = unsafe { BorrowedFd::borrow_raw(BTF_FD) };
-        bpf_create_map(&name, &map, Some(btf_fd), None).unwrap();
+        bpf_create_map(&name, &map, Some(btf_fd), None, None).unwrap();
+    }
+
+    #[test]
+    fn test_create_offloaded_map() {
+        const IF_INDEX: u32 = 7;
+
+        override_syscall(|call| match call {
+            Syscall::Ebpf {
+                cmd: bpf_cmd::BPF_MAP_CREATE,
+                attr,
+            } => {
+                let u = unsafe { attr.__bindgen_anon_1 };
+                assert_eq!(u.map_ifindex, IF_INDEX);
+                Ok(crate::MockableFd::mock_signed_fd().into())
+            }
+            _ => Err((-1, io::Error::from_raw_os_error(EINVAL))),
+        });
+
+        let mut info = unsafe { mem::zeroed::<bpf_map_info>() };
+        info.type_ = bpf_map_type::BPF_MAP_TYPE_HASH as u32;
+        info.key_size = 4;
+        info.value_size = 4;
+        info.max_entries = 64;
+        let map = parse_map_info(info, PinningType::None);
+
+        let name = CString::new("TEST").unwrap();
+        bpf_create_map(&name, &map, None, None, Some(IF_INDEX)).unwrap();
     }
 }
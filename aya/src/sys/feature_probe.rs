@@ -14,7 +14,7 @@ use aya_obj::{
         bpf_func_id, bpf_map_type, bpf_prog_info,
     },
 };
-use libc::{E2BIG, EBADF, EINVAL};
+use libc::{E2BIG, EBADF, EINVAL, EOPNOTSUPP};
 
 use super::{
     SyscallError, bpf_map_create, bpf_prog_load, bpf_raw_tracepoint_open, new_insn, unit_sys_bpf,
@@ -305,6 +305,56 @@ pub fn is_program_supported(program_type: ProgramType) -> Result<bool, ProgramEr
     })
 }
 
+/// Whether programs of the [`ProgramType`] can be offloaded to the network interface
+/// `if_index`.
+///
+/// This probes by loading a minimal program for the device, as
+/// [`Xdp::load_offloaded`](crate::programs::Xdp::load_offloaded) does. Only
+/// [`ProgramType::Xdp`] and [`ProgramType::SchedClassifier`] programs can be offloaded, and only
+/// by a few drivers.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use aya::{programs::ProgramType, sys::is_offload_supported};
+/// #
+/// # let if_index = 2;
+/// match is_offload_supported(ProgramType::Xdp, if_index) {
+///     Ok(true) => println!("XDP offload supported"),
+///     Ok(false) => println!("XDP offload not supported"),
+///     Err(err) => println!("unexpected error while probing: {:?}", err),
+/// }
+/// ```
+///
+/// # Errors
+///
+/// Returns [`ProgramError::SyscallError`] if probing fails with an unexpected error.
+pub fn is_offload_supported(
+    program_type: ProgramType,
+    if_index: u32,
+) -> Result<bool, ProgramError> {
+    with_trivial_prog(program_type, |attr| {
+        // SAFETY: union access
+        let u = unsafe { &mut attr.__bindgen_anon_3 };
+        u.prog_ifindex = if_index;
+
+        match bpf_prog_load(attr) {
+            Ok(_fd) => Ok(true),
+            Err(io_error) => match io_error.raw_os_error() {
+                // `EINVAL` is returned when the program type or the device doesn't support
+                // offload, or when the device doesn't exist. Drivers may return `EOPNOTSUPP`.
+                // `E2BIG` means that the kernel predates `prog_ifindex`.
+                Some(EINVAL | EOPNOTSUPP | E2BIG) => Ok(false),
+                _ => Err(SyscallError {
+                    call: "bpf_prog_load",
+                    io_error,
+                }
+                .into()),
+            },
+        }
+    })
+}
+
 /// Whether the host kernel supports the [`MapType`].
 ///
 /// # Examples
@@ -18,7 +18,9 @@ use aya_obj::generated::{bpf_attr, bpf_cmd, bpf_stats_type, perf_event_attr};
 pub(crate) use bpf::*;
 #[cfg(test)]
 pub(crate) use fake::*;
-pub use feature_probe::{BpfHelper, is_helper_supported, is_map_supported, is_program_supported};
+pub use feature_probe::{
+    BpfHelper, is_helper_supported, is_map_supported, is_offload_supported, is_program_supported,
+};
 pub use netlink::NetlinkError;
 #[doc(hidden)]
 pub use netlink::netlink_set_link_up;
@@ -28,7 +30,10 @@ use thiserror::Error;
 
 pub(crate) type SysResult = Result<i64, (i64, io::Error)>;
 
-#[cfg_attr(test, expect(dead_code, reason = "test stubs cut above this"))]
+#[cfg_attr(
+    all(test, not(feature = "fake")),
+    expect(dead_code, reason = "test stubs cut above this")
+)]
 #[derive(Debug)]
 pub(crate) enum PerfEventIoctlRequest<'a> {
     Enable,
@@ -98,6 +103,11 @@ impl std::fmt::Debug for Syscall<'_> {
 }
 
 fn syscall(call: Syscall<'_>) -> SysResult {
+    #[cfg(feature = "fake")]
+    if let Some(kernel) = crate::fake::FakeKernel::current() {
+        return kernel.syscall(call);
+    }
+
     #[cfg(test)]
     {
         TEST_SYSCALL.with(|test_impl| unsafe { test_impl.borrow()(call) })
@@ -155,7 +165,7 @@ fn syscall(call: Syscall<'_>) -> SysResult {
 }
 
 #[cfg_attr(
-    test,
+    all(test, not(feature = "fake")),
     expect(unused_variables, reason = "TODO: we should validate all arguments")
 )]
 pub(crate) unsafe fn mmap(
@@ -166,6 +176,13 @@ pub(crate) unsafe fn mmap(
     fd: BorrowedFd<'_>,
     offset: libc::off_t,
 ) -> *mut c_void {
+    #[cfg(feature = "fake")]
+    if let Some(ret) = crate::fake::FakeKernel::current()
+        .and_then(|kernel| unsafe { kernel.mmap(addr, len, prot, flags, fd, offset) })
+    {
+        return ret;
+    }
+
     #[cfg(test)]
     {
         TEST_MMAP_RET.with(|ret| *ret.borrow())
@@ -180,11 +197,17 @@ pub(crate) unsafe fn mmap(
 }
 
 #[cfg_attr(
-    test,
+    all(test, not(feature = "fake")),
     expect(clippy::missing_const_for_fn, reason = "only const in cfg(test)"),
     expect(unused_variables, reason = "TODO: we should validate all arguments")
 )]
 pub(crate) unsafe fn munmap(addr: *mut c_void, len: usize) -> c_int {
+    // Mappings of fake objects are real.
+    #[cfg(feature = "fake")]
+    if let Some(ret) = unsafe { crate::fake::FakeKernel::munmap(addr, len) } {
+        return ret;
+    }
+
     #[cfg(test)]
     {
         0
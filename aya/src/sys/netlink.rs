@@ -1,28 +1,34 @@
 use std::{
+    array::TryFromSliceError,
     ffi::{CStr, CString, FromBytesWithNulError},
     io, iter, mem,
-    os::fd::{AsRawFd as _, BorrowedFd, FromRawFd as _},
+    os::fd::{AsFd, AsRawFd as _, BorrowedFd, FromRawFd as _},
     ptr, slice,
 };
 
 use aya_obj::generated::{
     IFLA_XDP_EXPECTED_FD, IFLA_XDP_FD, IFLA_XDP_FLAGS, NLMSG_ALIGNTO, TC_H_CLSACT, TC_H_INGRESS,
-    TC_H_MAJ_MASK, TC_H_UNSPEC, TCA_BPF_CLASSID, TCA_BPF_FD, TCA_BPF_FLAG_ACT_DIRECT,
-    TCA_BPF_FLAGS, TCA_BPF_NAME, TCA_KIND, TCA_OPTIONS, XDP_FLAGS_REPLACE,
+    TC_H_MAJ_MASK, TC_H_ROOT, TC_H_UNSPEC, TCA_BPF_CLASSID, TCA_BPF_FD, TCA_BPF_FLAG_ACT_DIRECT,
+    TCA_BPF_FLAGS, TCA_BPF_ID, TCA_BPF_NAME, TCA_CHAIN, TCA_KIND, TCA_OPTIONS, XDP_FLAGS_REPLACE,
     XDP_FLAGS_UPDATE_IF_NOEXIST, ifinfomsg, nlmsgerr_attrs::NLMSGERR_ATTR_MSG, tcmsg,
 };
 use libc::{
-    AF_NETLINK, AF_UNSPEC, ETH_P_ALL, IFF_UP, IFLA_XDP, NETLINK_CAP_ACK, NETLINK_EXT_ACK,
-    NETLINK_ROUTE, NLA_ALIGNTO, NLA_F_NESTED, NLA_TYPE_MASK, NLM_F_ACK, NLM_F_CREATE, NLM_F_DUMP,
-    NLM_F_ECHO, NLM_F_EXCL, NLM_F_MULTI, NLM_F_REQUEST, NLMSG_DONE, NLMSG_ERROR, RTM_DELTFILTER,
-    RTM_GETTFILTER, RTM_NEWQDISC, RTM_NEWTFILTER, RTM_SETLINK, SOCK_RAW, SOL_NETLINK, getsockname,
-    nlattr, nlmsgerr, nlmsghdr, recv, send, setsockopt, sockaddr_nl, socket,
+    AF_NETLINK, AF_UNSPEC, ETH_P_ALL, IFF_UP, IFLA_IFNAME, IFLA_XDP, NETLINK_ADD_MEMBERSHIP,
+    NETLINK_CAP_ACK, NETLINK_EXT_ACK, NETLINK_ROUTE, NLA_ALIGNTO, NLA_F_NESTED, NLA_TYPE_MASK,
+    NLM_F_ACK, NLM_F_CREATE, NLM_F_DUMP, NLM_F_ECHO, NLM_F_EXCL, NLM_F_MULTI, NLM_F_REPLACE,
+    NLM_F_REQUEST, NLMSG_DONE, NLMSG_ERROR, RTM_DELLINK, RTM_DELTFILTER, RTM_GETLINK,
+    RTM_GETTFILTER, RTM_NEWLINK, RTM_NEWQDISC, RTM_NEWTFILTER, RTM_SETLINK, RTNLGRP_LINK, SOCK_RAW,
+    SOL_NETLINK, bind, getsockname, nlattr, nlmsgerr, nlmsghdr, recv, send, setsockopt,
+    sockaddr_nl, socket,
 };
 use thiserror::Error;
 
 use crate::{
     Pod,
-    programs::{TcAttachType, TcHandle, XdpMode},
+    programs::{
+        TcAttachType, TcHandle, XdpMode,
+        tc::{NlOptions, Qdisc, TcFilter},
+    },
     util::{bytes_of, tc_handler_make},
 };
 
@@ -45,12 +51,34 @@ const NLA_HDR_ALIGN_LEN: usize = nla_align!(NLA_HDR_LEN);
 /// <https://github.com/torvalds/linux/blob/v6.19/net/sched/cls_bpf.c#L28>
 const CLS_BPF_NAME_LEN: usize = 256;
 
+/// `TCA_HTB_INIT` from the Linux kernel, not in the generated bindings.
+/// <https://github.com/torvalds/linux/blob/v6.19/include/uapi/linux/pkt_sched.h#L421>
+const TCA_HTB_INIT: u16 = 2;
+
+/// `TC_HTB_PROTOVER` from the Linux kernel.
+const TC_HTB_PROTOVER: u32 = 3;
+
+/// `struct tc_htb_glob` from the Linux kernel.
+#[derive(Copy, Clone)]
+#[repr(C)]
+struct TcHtbGlob {
+    version: u32,
+    rate2quantum: u32,
+    defcls: u32,
+    debug: u32,
+    direct_pkts: u32,
+}
+
+unsafe impl Pod for TcHtbGlob {}
+
 // Size of the attribute buffer needed by write_tc_attach_attrs:
-// TCA_KIND + nested TCA_OPTIONS containing TCA_BPF_CLASSID, TCA_BPF_FD,
-// TCA_BPF_NAME, TCA_BPF_FLAGS.
+// TCA_KIND + TCA_CHAIN + nested TCA_OPTIONS containing TCA_BPF_CLASSID,
+// TCA_BPF_FD, TCA_BPF_NAME, TCA_BPF_FLAGS.
 const fn tc_request_attrs_size() -> usize {
     // TCA_KIND
     NLA_HDR_ALIGN_LEN + nla_align!(c"bpf".to_bytes_with_nul().len())
+    // TCA_CHAIN
+    + NLA_HDR_ALIGN_LEN + nla_align!(size_of::<u32>())
     // TCA_OPTIONS header
     + NLA_HDR_ALIGN_LEN
     // TCA_BPF_CLASSID
@@ -63,7 +91,7 @@ const fn tc_request_attrs_size() -> usize {
     + NLA_HDR_ALIGN_LEN + nla_align!(size_of::<u32>())
 }
 
-const _: () = assert!(tc_request_attrs_size() == 296);
+const _: () = assert!(tc_request_attrs_size() == 304);
 
 /// A private error type for internal use in this module.
 #[derive(Error, Debug)]
@@ -166,7 +194,7 @@ pub(crate) unsafe fn netlink_set_xdp_fd(
     Ok(())
 }
 
-pub(crate) unsafe fn netlink_qdisc_add_clsact(if_index: i32) -> Result<(), NetlinkError> {
+pub(crate) unsafe fn netlink_qdisc_add(if_index: i32, qdisc: Qdisc) -> Result<(), NetlinkError> {
     let sock = NetlinkSocket::open()?;
 
     let mut req = unsafe { mem::zeroed::<TcRequest>() };
@@ -179,17 +207,27 @@ pub(crate) unsafe fn netlink_qdisc_add_clsact(if_index: i32) -> Result<(), Netli
         nlmsg_pid: 0,
         nlmsg_seq: 1,
     };
+    let (kind, handle, parent) = match qdisc {
+        Qdisc::Clsact => (
+            c"clsact",
+            tc_handler_make(TC_H_CLSACT, TC_H_UNSPEC),
+            tc_handler_make(TC_H_CLSACT, TC_H_INGRESS),
+        ),
+        Qdisc::Ingress => (
+            c"ingress",
+            tc_handler_make(TC_H_INGRESS, TC_H_UNSPEC),
+            TC_H_INGRESS,
+        ),
+        Qdisc::Htb { major, .. } => (c"htb", u32::from(TcHandle::new(major, 0)), TC_H_ROOT),
+    };
     req.tc_info.tcm_family = AF_UNSPEC as u8;
     req.tc_info.tcm_ifindex = if_index;
-    req.tc_info.tcm_handle = tc_handler_make(TC_H_CLSACT, TC_H_UNSPEC);
-    req.tc_info.tcm_parent = tc_handler_make(TC_H_CLSACT, TC_H_INGRESS);
+    req.tc_info.tcm_handle = handle;
+    req.tc_info.tcm_parent = parent;
     req.tc_info.tcm_info = 0;
 
-    // add the TCA_KIND attribute
-    let attrs_buf = unsafe { request_attributes(&mut req, nlmsg_len) };
-    let (_, attr_len) = write_attr_bytes(attrs_buf, TCA_KIND as u16, c"clsact".to_bytes_with_nul())
+    write_qdisc_attrs(&mut req, nlmsg_len, kind, qdisc)
         .map_err(|e| NetlinkError(NetlinkErrorInternal::IoError(e)))?;
-    req.header.nlmsg_len += nla_align!(attr_len) as u32;
 
     sock.send(&bytes_of(&req)[..req.header.nlmsg_len as usize])?;
     for msg in sock.recv() {
@@ -199,13 +237,52 @@ pub(crate) unsafe fn netlink_qdisc_add_clsact(if_index: i32) -> Result<(), Netli
     Ok(())
 }
 
+fn write_qdisc_attrs(
+    req: &mut TcRequest,
+    nlmsg_len: usize,
+    kind: &CStr,
+    qdisc: Qdisc,
+) -> io::Result<()> {
+    let attrs_buf = unsafe { request_attributes(req, nlmsg_len) };
+
+    // add the TCA_KIND attribute
+    let (attrs_buf, kind_len) =
+        write_attr_bytes(attrs_buf, TCA_KIND as u16, kind.to_bytes_with_nul())?;
+    let options_len = match qdisc {
+        Qdisc::Clsact | Qdisc::Ingress => 0,
+        Qdisc::Htb { default_class, .. } => {
+            // The defaults of tc(8).
+            let glob = TcHtbGlob {
+                version: TC_HTB_PROTOVER,
+                rate2quantum: 10,
+                defcls: u32::from(default_class),
+                debug: 0,
+                direct_pkts: 0,
+            };
+            let mut options = NestedAttrs::new(attrs_buf, TCA_OPTIONS as u16);
+            options.write_attr(TCA_HTB_INIT, glob)?;
+            options.finish()?
+        }
+    };
+
+    req.header.nlmsg_len += nla_align!(kind_len + options_len) as u32;
+    Ok(())
+}
+
 fn write_tc_attach_attrs(
     req: &mut TcRequest,
     nlmsg_len: usize,
     prog_fd: i32,
     prog_name: &[u8],
-    classid: Option<TcHandle>,
+    options: &NlOptions,
 ) -> io::Result<()> {
+    let NlOptions {
+        priority: _,
+        handle: _,
+        classid,
+        chain,
+        direct_action,
+    } = *options;
     if prog_name.len() > CLS_BPF_NAME_LEN {
         return Err(io::Error::new(
             io::ErrorKind::InvalidInput,
@@ -216,6 +293,13 @@ fn write_tc_attach_attrs(
 
     let (attrs_buf, kind_len) =
         write_attr_bytes(attrs_buf, TCA_KIND as u16, c"bpf".to_bytes_with_nul())?;
+    // Like tc(8), only send TCA_CHAIN for chains other than the default one so that kernels
+    // without chains keep working.
+    let (attrs_buf, chain_len) = if chain == 0 {
+        (attrs_buf, 0)
+    } else {
+        write_attr(attrs_buf, TCA_CHAIN as u16, chain)?
+    };
 
     let mut options = NestedAttrs::new(attrs_buf, TCA_OPTIONS as u16);
     if let Some(classid) = classid {
@@ -223,22 +307,24 @@ fn write_tc_attach_attrs(
     }
     options.write_attr(TCA_BPF_FD as u16, prog_fd)?;
     options.write_attr_bytes(TCA_BPF_NAME as u16, prog_name)?;
-    options.write_attr(TCA_BPF_FLAGS as u16, TCA_BPF_FLAG_ACT_DIRECT)?;
+    let flags = if direct_action {
+        TCA_BPF_FLAG_ACT_DIRECT
+    } else {
+        0
+    };
+    options.write_attr(TCA_BPF_FLAGS as u16, flags)?;
     let options_len = options.finish()?;
 
-    req.header.nlmsg_len += nla_align!(kind_len + options_len) as u32;
+    req.header.nlmsg_len += nla_align!(kind_len + chain_len + options_len) as u32;
     Ok(())
 }
 
-#[expect(clippy::too_many_arguments, reason = "internal netlink helper")]
 pub(crate) unsafe fn netlink_qdisc_attach(
     if_index: i32,
     attach_type: &TcAttachType,
     prog_fd: BorrowedFd<'_>,
     prog_name: &CStr,
-    priority: u16,
-    handle: TcHandle,
-    classid: Option<TcHandle>,
+    options: &NlOptions,
     create: bool,
 ) -> Result<(u16, TcHandle), NetlinkError> {
     let sock = NetlinkSocket::open()?;
@@ -250,14 +336,14 @@ pub(crate) unsafe fn netlink_qdisc_attach(
     // set NLM_F_EXCL so that attaching fails if there's already a program attached to the given
     // handle.
     //
-    // When create=false we're replacing an existing attachment so we must not set either flags.
+    // When create=false we're atomically replacing the program of an existing filter, so we set
+    // NLM_F_REPLACE but not NLM_F_CREATE: the request fails if the filter doesn't exist.
     //
     // See https://github.com/torvalds/linux/blob/3a87498/net/sched/cls_api.c#L2304
     let request_flags = if create {
         NLM_F_CREATE | NLM_F_EXCL
     } else {
-        // NLM_F_REPLACE exists, but seems unused by cls_bpf
-        0
+        NLM_F_REPLACE
     };
     req.header = nlmsghdr {
         nlmsg_len: nlmsg_len as u32,
@@ -267,11 +353,11 @@ pub(crate) unsafe fn netlink_qdisc_attach(
         nlmsg_seq: 1,
     };
     req.tc_info.tcm_family = AF_UNSPEC as u8;
-    req.tc_info.tcm_handle = handle.into();
+    req.tc_info.tcm_handle = options.handle.into();
     req.tc_info.tcm_ifindex = if_index;
     req.tc_info.tcm_parent = attach_type.tc_parent();
     req.tc_info.tcm_info = tc_handler_make(
-        u32::from(priority) << 16,
+        u32::from(options.priority) << 16,
         u32::from(htons(ETH_P_ALL as u16)),
     );
 
@@ -280,7 +366,7 @@ pub(crate) unsafe fn netlink_qdisc_attach(
         nlmsg_len,
         prog_fd.as_raw_fd(),
         prog_name.to_bytes_with_nul(),
-        classid,
+        options,
     )
     .map_err(|e| NetlinkError(NetlinkErrorInternal::IoError(e)))?;
     sock.send(&bytes_of(&req)[..req.header.nlmsg_len as usize])?;
@@ -317,13 +403,15 @@ pub(crate) unsafe fn netlink_qdisc_detach(
     attach_type: TcAttachType,
     priority: u16,
     handle: TcHandle,
+    chain: u32,
 ) -> Result<(), NetlinkError> {
     let sock = NetlinkSocket::open()?;
 
     let mut req = unsafe { mem::zeroed::<TcRequest>() };
 
+    let nlmsg_len = size_of::<nlmsghdr>() + size_of::<tcmsg>();
     req.header = nlmsghdr {
-        nlmsg_len: (size_of::<nlmsghdr>() + size_of::<tcmsg>()) as u32,
+        nlmsg_len: nlmsg_len as u32,
         nlmsg_flags: (NLM_F_REQUEST | NLM_F_ACK) as u16,
         nlmsg_type: RTM_DELTFILTER,
         nlmsg_pid: 0,
@@ -339,6 +427,13 @@ pub(crate) unsafe fn netlink_qdisc_detach(
     req.tc_info.tcm_parent = attach_type.tc_parent();
     req.tc_info.tcm_ifindex = if_index;
 
+    if chain != 0 {
+        let attrs_buf = unsafe { request_attributes(&mut req, nlmsg_len) };
+        let (_, attr_len) = write_attr(attrs_buf, TCA_CHAIN as u16, chain)
+            .map_err(|e| NetlinkError(NetlinkErrorInternal::IoError(e)))?;
+        req.header.nlmsg_len += nla_align!(attr_len) as u32;
+    }
+
     sock.send(&bytes_of(&req)[..req.header.nlmsg_len as usize])?;
 
     for msg in sock.recv() {
@@ -348,12 +443,12 @@ pub(crate) unsafe fn netlink_qdisc_detach(
     Ok(())
 }
 
-pub(crate) fn netlink_find_filter_with_name(
-    sock: &NetlinkSocket,
+pub(crate) fn netlink_list_filters(
     if_index: i32,
     attach_type: TcAttachType,
-    name: &CStr,
-) -> Result<impl Iterator<Item = Result<(u16, TcHandle), NetlinkError>>, NetlinkError> {
+) -> Result<Vec<TcFilter>, NetlinkError> {
+    let sock = NetlinkSocket::open()?;
+
     let mut req = unsafe { mem::zeroed::<TcRequest>() };
 
     let nlmsg_len = size_of::<nlmsghdr>() + size_of::<tcmsg>();
@@ -369,58 +464,297 @@ pub(crate) fn netlink_find_filter_with_name(
     req.tc_info.tcm_ifindex = if_index;
     req.tc_info.tcm_parent = attach_type.tc_parent();
 
+    // Without TCA_CHAIN, the kernel dumps the filters of all the chains.
     sock.send(&bytes_of(&req)[..req.header.nlmsg_len as usize])?;
-    let mut resp = sock.recv();
-
-    Ok(iter::from_fn(move || {
-        loop {
-            let msg = resp.next()?;
-            if let Some(result) = (|| {
-                let msg = msg?;
-                if msg.header.nlmsg_type != RTM_NEWTFILTER {
-                    return Ok(None);
-                }
 
-                let (tc_msg_buf, attrs_buf) = msg
-                    .data
-                    .split_at_checked(size_of::<tcmsg>())
-                    .ok_or_else(|| {
-                        NetlinkError(NetlinkErrorInternal::IoError(io::Error::other(
-                            "RTM_NEWTFILTER payload smaller than tcmsg",
-                        )))
-                    })?;
-                let tc_msg: tcmsg = unsafe { ptr::read_unaligned(tc_msg_buf.as_ptr().cast()) };
-                let priority = (tc_msg.tcm_info >> 16) as u16;
-
-                let mut filter = None;
-                for opt in NlAttrsIterator::new(attrs_buf) {
-                    let opt =
-                        opt.map_err(|e| NetlinkError(NetlinkErrorInternal::NlAttrError(e)))?;
-                    if opt.header.nla_type & NLA_TYPE_MASK as u16 != TCA_OPTIONS as u16 {
-                        continue;
-                    }
-                    for opt in NlAttrsIterator::new(opt.data) {
-                        let opt =
-                            opt.map_err(|e| NetlinkError(NetlinkErrorInternal::NlAttrError(e)))?;
-                        if opt.header.nla_type & NLA_TYPE_MASK as u16 != TCA_BPF_NAME as u16 {
-                            continue;
-                        }
-                        let f_name = CStr::from_bytes_with_nul(opt.data)
-                            .map_err(NlAttrError::CStrFromBytesWithNul)
-                            .map_err(|e| NetlinkError(NetlinkErrorInternal::NlAttrError(e)))?;
-                        if f_name != name {
-                            continue;
-                        }
-                        filter = Some((priority, tc_msg.tcm_handle.into()));
-                    }
-                }
-                Ok(filter)
-            })()
-            .transpose()
-            {
-                break Some(result);
+    // always parse the entire response to ensure we don't miss any replies
+    let mut filters = Vec::new();
+    for msg in sock.recv() {
+        let msg = msg?;
+        if msg.header.nlmsg_type != RTM_NEWTFILTER {
+            continue;
+        }
+        if let Some(filter) = parse_tc_filter(if_index as u32, attach_type, &msg.data)
+            .map_err(|e| NetlinkError(NetlinkErrorInternal::NlAttrError(e)))?
+        {
+            filters.push(filter);
+        }
+    }
+    Ok(filters)
+}
+
+/// Parses the payload of a `RTM_NEWTFILTER` message.
+///
+/// Returns `None` if the filter isn't a `cls_bpf` one, or if the message describes a filter
+/// priority rather than a filter.
+fn parse_tc_filter(
+    if_index: u32,
+    attach_type: TcAttachType,
+    data: &[u8],
+) -> Result<Option<TcFilter>, NlAttrError> {
+    let (tc_msg_buf, attrs_buf) =
+        data.split_at_checked(size_of::<tcmsg>())
+            .ok_or(NlAttrError::BufferLength {
+                size: data.len(),
+                expected: size_of::<tcmsg>(),
+            })?;
+    let tc_msg: tcmsg = unsafe { ptr::read_unaligned(tc_msg_buf.as_ptr().cast()) };
+
+    let mut is_bpf = false;
+    let mut chain = 0;
+    let mut options = None;
+    for attr in NlAttrsIterator::new(attrs_buf) {
+        let attr = attr?;
+        match u32::from(attr.header.nla_type & NLA_TYPE_MASK as u16) {
+            TCA_KIND => is_bpf = CStr::from_bytes_with_nul(attr.data)? == c"bpf",
+            TCA_CHAIN => chain = read_u32(attr.data)?,
+            TCA_OPTIONS => options = Some(attr.data),
+            _ => {}
+        }
+    }
+    // The kernel reports each filter priority with an empty filter before its filters.
+    let Some(options) = options.filter(|_| is_bpf && tc_msg.tcm_handle != 0) else {
+        return Ok(None);
+    };
+
+    let mut filter = TcFilter {
+        if_index,
+        attach_type,
+        priority: (tc_msg.tcm_info >> 16) as u16,
+        handle: tc_msg.tcm_handle.into(),
+        chain,
+        name: None,
+        program_id: None,
+        classid: None,
+        direct_action: false,
+    };
+    for attr in NlAttrsIterator::new(options) {
+        let attr = attr?;
+        match u32::from(attr.header.nla_type & NLA_TYPE_MASK as u16) {
+            TCA_BPF_NAME => filter.name = Some(CStr::from_bytes_with_nul(attr.data)?.to_owned()),
+            TCA_BPF_ID => filter.program_id = Some(read_u32(attr.data)?),
+            TCA_BPF_CLASSID => filter.classid = Some(read_u32(attr.data)?.into()),
+            TCA_BPF_FLAGS => {
+                filter.direct_action = read_u32(attr.data)? & TCA_BPF_FLAG_ACT_DIRECT != 0;
             }
+            _ => {}
+        }
+    }
+    Ok(Some(filter))
+}
+
+fn read_u32(data: &[u8]) -> Result<u32, NlAttrError> {
+    let data = data
+        .try_into()
+        .map_err(|_error: TryFromSliceError| NlAttrError::BufferLength {
+            size: data.len(),
+            expected: size_of::<u32>(),
+        })?;
+    Ok(u32::from_ne_bytes(data))
+}
+
+/// A network interface, as reported by `RTM_NEWLINK` and `RTM_DELLINK` messages.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct LinkMessage {
+    pub(crate) if_index: u32,
+    pub(crate) name: String,
+    /// Whether the interface was removed.
+    pub(crate) removed: bool,
+}
+
+/// Returns the network interfaces of the network namespace of the calling thread.
+pub(crate) fn netlink_list_links() -> Result<Vec<LinkMessage>, NetlinkError> {
+    let sock = NetlinkSocket::open()?;
+
+    // Safety: Request is POD so this is safe
+    let mut req = unsafe { mem::zeroed::<Request>() };
+
+    let nlmsg_len = size_of::<nlmsghdr>() + size_of::<ifinfomsg>();
+    req.header = nlmsghdr {
+        nlmsg_len: nlmsg_len as u32,
+        nlmsg_flags: (NLM_F_REQUEST | NLM_F_DUMP) as u16,
+        nlmsg_type: RTM_GETLINK,
+        nlmsg_pid: 0,
+        nlmsg_seq: 1,
+    };
+    req.if_info.ifi_family = AF_UNSPEC as u8;
+
+    sock.send(&bytes_of(&req)[..req.header.nlmsg_len as usize])?;
+    sock.recv_links()
+}
+
+/// Opens a netlink socket receiving the `RTM_NEWLINK` and `RTM_DELLINK` notifications of the
+/// network namespace of the calling thread, see [`NetlinkSocket::recv_links`].
+pub(crate) fn netlink_watch_links() -> Result<NetlinkSocket, NetlinkError> {
+    let sock = NetlinkSocket::open()?;
+    sock.add_membership(RTNLGRP_LINK)?;
+    Ok(sock)
+}
+
+/// Creates a network interface of the given `kind`, such as `dummy`.
+#[cfg(feature = "test-helpers")]
+pub(crate) unsafe fn netlink_add_link(name: &CStr, kind: &CStr) -> Result<(), NetlinkError> {
+    let sock = NetlinkSocket::open()?;
+
+    // Safety: Request is POD so this is safe
+    let mut req = unsafe { mem::zeroed::<Request>() };
+
+    let nlmsg_len = size_of::<nlmsghdr>() + size_of::<ifinfomsg>();
+    req.header = nlmsghdr {
+        nlmsg_len: nlmsg_len as u32,
+        nlmsg_flags: (NLM_F_REQUEST | NLM_F_ACK | NLM_F_EXCL | NLM_F_CREATE) as u16,
+        nlmsg_type: RTM_NEWLINK,
+        nlmsg_pid: 0,
+        nlmsg_seq: 1,
+    };
+    req.if_info.ifi_family = AF_UNSPEC as u8;
+
+    let attrs_buf = unsafe { request_attributes(&mut req, nlmsg_len) };
+    let (attrs_buf, name_len) = write_attr_bytes(attrs_buf, IFLA_IFNAME, name.to_bytes_with_nul())
+        .map_err(|e| NetlinkError(NetlinkErrorInternal::IoError(e)))?;
+    let mut link_info = NestedAttrs::new(attrs_buf, libc::IFLA_LINKINFO);
+    link_info
+        .write_attr_bytes(libc::IFLA_INFO_KIND, kind.to_bytes_with_nul())
+        .map_err(|e| NetlinkError(NetlinkErrorInternal::IoError(e)))?;
+    let link_info_len = link_info
+        .finish()
+        .map_err(|e| NetlinkError(NetlinkErrorInternal::IoError(e)))?;
+    req.header.nlmsg_len += (name_len + nla_align!(link_info_len)) as u32;
+
+    sock.send(&bytes_of(&req)[..req.header.nlmsg_len as usize])?;
+    for msg in sock.recv() {
+        msg?;
+    }
+
+    Ok(())
+}
+
+/// Creates a pair of `veth` network interfaces named `name` and `peer`.
+#[cfg(feature = "test-helpers")]
+pub(crate) unsafe fn netlink_add_veth(name: &CStr, peer: &CStr) -> Result<(), NetlinkError> {
+    // From linux/veth.h, not exported by libc.
+    const VETH_INFO_PEER: u16 = 1;
+
+    #[derive(Copy, Clone)]
+    #[repr(C)]
+    struct VethRequest {
+        header: nlmsghdr,
+        if_info: ifinfomsg,
+        attrs: [u8; 128],
+    }
+
+    unsafe impl Pod for VethRequest {}
+
+    let io_error = |e: io::Error| NetlinkError(NetlinkErrorInternal::IoError(e));
+
+    // The peer is described by an `ifinfomsg` followed by its own attributes, nested in
+    // IFLA_LINKINFO/IFLA_INFO_DATA/VETH_INFO_PEER.
+    let mut peer_buf = [0u8; 64];
+    let (rest, info_len) =
+        write_bytes(&mut peer_buf, &[0; size_of::<ifinfomsg>()]).map_err(io_error)?;
+    let (_, peer_name_len) =
+        write_attr_bytes(rest, IFLA_IFNAME, peer.to_bytes_with_nul()).map_err(io_error)?;
+    let peer_len = info_len + peer_name_len;
+
+    let mut info_data_buf = [0u8; 64];
+    let (_, info_data_len) =
+        write_attr_bytes(&mut info_data_buf, VETH_INFO_PEER, &peer_buf[..peer_len])
+            .map_err(io_error)?;
+
+    let sock = NetlinkSocket::open()?;
+
+    // Safety: VethRequest is POD so this is safe
+    let mut req = unsafe { mem::zeroed::<VethRequest>() };
+
+    let nlmsg_len = size_of::<nlmsghdr>() + size_of::<ifinfomsg>();
+    req.header = nlmsghdr {
+        nlmsg_len: nlmsg_len as u32,
+        nlmsg_flags: (NLM_F_REQUEST | NLM_F_ACK | NLM_F_EXCL | NLM_F_CREATE) as u16,
+        nlmsg_type: RTM_NEWLINK,
+        nlmsg_pid: 0,
+        nlmsg_seq: 1,
+    };
+    req.if_info.ifi_family = AF_UNSPEC as u8;
+
+    let attrs_buf = unsafe { request_attributes(&mut req, nlmsg_len) };
+    let (attrs_buf, name_len) =
+        write_attr_bytes(attrs_buf, IFLA_IFNAME, name.to_bytes_with_nul()).map_err(io_error)?;
+    let mut link_info = NestedAttrs::new(attrs_buf, libc::IFLA_LINKINFO);
+    link_info
+        .write_attr_bytes(libc::IFLA_INFO_KIND, c"veth".to_bytes_with_nul())
+        .map_err(io_error)?;
+    link_info
+        .write_attr_bytes(libc::IFLA_INFO_DATA, &info_data_buf[..info_data_len])
+        .map_err(io_error)?;
+    let link_info_len = link_info.finish().map_err(io_error)?;
+    req.header.nlmsg_len += (name_len + nla_align!(link_info_len)) as u32;
+
+    sock.send(&bytes_of(&req)[..req.header.nlmsg_len as usize])?;
+    for msg in sock.recv() {
+        msg?;
+    }
+
+    Ok(())
+}
+
+/// Deletes the network interface with the given index.
+#[cfg(feature = "test-helpers")]
+pub(crate) unsafe fn netlink_delete_link(if_index: i32) -> Result<(), NetlinkError> {
+    let sock = NetlinkSocket::open()?;
+
+    // Safety: Request is POD so this is safe
+    let mut req = unsafe { mem::zeroed::<Request>() };
+
+    let nlmsg_len = size_of::<nlmsghdr>() + size_of::<ifinfomsg>();
+    req.header = nlmsghdr {
+        nlmsg_len: nlmsg_len as u32,
+        nlmsg_flags: (NLM_F_REQUEST | NLM_F_ACK) as u16,
+        nlmsg_type: RTM_DELLINK,
+        nlmsg_pid: 0,
+        nlmsg_seq: 1,
+    };
+    req.if_info.ifi_family = AF_UNSPEC as u8;
+    req.if_info.ifi_index = if_index;
+
+    sock.send(&bytes_of(&req)[..req.header.nlmsg_len as usize])?;
+    for msg in sock.recv() {
+        msg?;
+    }
+
+    Ok(())
+}
+
+/// Parses a `RTM_NEWLINK` or `RTM_DELLINK` message, returns `None` for other messages.
+fn parse_link_message(msg: &NetlinkMessage) -> Result<Option<LinkMessage>, NlAttrError> {
+    let removed = match msg.header.nlmsg_type {
+        RTM_NEWLINK => false,
+        RTM_DELLINK => true,
+        _ => return Ok(None),
+    };
+    let (if_info_buf, attrs_buf) =
+        msg.data
+            .split_at_checked(size_of::<ifinfomsg>())
+            .ok_or(NlAttrError::BufferLength {
+                size: msg.data.len(),
+                expected: size_of::<ifinfomsg>(),
+            })?;
+    let if_info: ifinfomsg = unsafe { ptr::read_unaligned(if_info_buf.as_ptr().cast()) };
+
+    let mut name = None;
+    for attr in NlAttrsIterator::new(attrs_buf) {
+        let attr = attr?;
+        if attr.header.nla_type & NLA_TYPE_MASK as u16 == IFLA_IFNAME {
+            name = Some(
+                CStr::from_bytes_with_nul(attr.data)?
+                    .to_string_lossy()
+                    .into_owned(),
+            );
         }
+    }
+    Ok(name.map(|name| LinkMessage {
+        if_index: if_info.ifi_index as u32,
+        name,
+        removed,
     }))
 }
 
@@ -480,6 +814,9 @@ pub(crate) struct NetlinkSocket {
 
 impl NetlinkSocket {
     pub(crate) fn open() -> Result<Self, NetlinkErrorInternal> {
+        #[cfg(feature = "fake")]
+        crate::fake::FakeKernel::check_supported("netlink")?;
+
         // Safety: libc wrapper
         let sock = unsafe { socket(AF_NETLINK, SOCK_RAW, NETLINK_ROUTE) };
         if sock < 0 {
@@ -538,6 +875,61 @@ impl NetlinkSocket {
         })
     }
 
+    /// Subscribes the socket to the multicast group `group`, such as `RTNLGRP_LINK`.
+    fn add_membership(&self, group: u32) -> Result<(), NetlinkError> {
+        // Multicast messages are only delivered to bound sockets, let the kernel pick the port
+        // id.
+        // Safety: sockaddr_nl is POD so this is safe
+        let mut addr = unsafe { mem::zeroed::<sockaddr_nl>() };
+        addr.nl_family = AF_NETLINK as u16;
+        // Safety: libc wrapper
+        if unsafe {
+            bind(
+                self.sock.as_raw_fd(),
+                ptr::from_ref(&addr).cast(),
+                size_of_val(&addr) as u32,
+            )
+        } < 0
+        {
+            return Err(NetlinkError(NetlinkErrorInternal::IoError(
+                io::Error::last_os_error(),
+            )));
+        }
+
+        // Safety: libc wrapper
+        if unsafe {
+            setsockopt(
+                self.sock.as_raw_fd(),
+                SOL_NETLINK,
+                NETLINK_ADD_MEMBERSHIP,
+                ptr::from_ref(&group).cast(),
+                size_of_val(&group) as u32,
+            )
+        } < 0
+        {
+            return Err(NetlinkError(NetlinkErrorInternal::IoError(
+                io::Error::last_os_error(),
+            )));
+        }
+        Ok(())
+    }
+
+    /// Receives the next `RTM_NEWLINK` and `RTM_DELLINK` messages, blocking until one arrives.
+    ///
+    /// Returns the reply to a dump request, or the next notification of the subscribed
+    /// multicast groups.
+    pub(crate) fn recv_links(&self) -> Result<Vec<LinkMessage>, NetlinkError> {
+        let mut links = Vec::new();
+        for msg in self.recv() {
+            if let Some(link) = parse_link_message(&msg?)
+                .map_err(|e| NetlinkError(NetlinkErrorInternal::NlAttrError(e)))?
+            {
+                links.push(link);
+            }
+        }
+        Ok(links)
+    }
+
     fn send(&self, msg: &[u8]) -> Result<(), NetlinkErrorInternal> {
         if unsafe { send(self.sock.as_raw_fd(), msg.as_ptr().cast(), msg.len(), 0) } < 0 {
             return Err(NetlinkErrorInternal::IoError(io::Error::last_os_error()));
@@ -611,6 +1003,12 @@ impl NetlinkSocket {
     }
 }
 
+impl AsFd for NetlinkSocket {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.sock.as_fd()
+    }
+}
+
 struct NetlinkMessage {
     header: nlmsghdr,
     data: Vec<u8>,
@@ -971,29 +1369,34 @@ mod tests {
         assert_eq!(name.to_str().unwrap(), "foo");
     }
 
-    fn tc_request(name: &[u8], classid: Option<TcHandle>) -> io::Result<TcRequest> {
+    fn tc_request(name: &[u8], options: &NlOptions) -> io::Result<TcRequest> {
         let mut req = unsafe { mem::zeroed::<TcRequest>() };
         let nlmsg_len = size_of::<nlmsghdr>() + size_of::<tcmsg>();
         req.header.nlmsg_len = nlmsg_len as u32;
 
-        write_tc_attach_attrs(&mut req, nlmsg_len, 0, name, classid)?;
+        write_tc_attach_attrs(&mut req, nlmsg_len, 0, name, options)?;
         Ok(req)
     }
 
-    fn classid_in_request(req: &TcRequest) -> Option<TcHandle> {
+    fn request_attrs(req: &TcRequest) -> &[u8] {
         let attrs_len = req.header.nlmsg_len as usize - size_of::<nlmsghdr>() - size_of::<tcmsg>();
-        let attrs = &req.attrs[..attrs_len];
+        &req.attrs[..attrs_len]
+    }
 
-        let options = NlAttrsIterator::new(attrs)
+    fn find_attr(attrs: &[u8], attr_type: u32) -> Option<NlAttr<'_>> {
+        NlAttrsIterator::new(attrs)
             .filter_map(Result::ok)
-            .find(|a| a.header.nla_type & NLA_TYPE_MASK as u16 == TCA_OPTIONS as u16)?;
+            .find(|a| a.header.nla_type & NLA_TYPE_MASK as u16 == attr_type as u16)
+    }
 
-        let classid = NlAttrsIterator::new(options.data)
-            .filter_map(Result::ok)
-            .find(|a| a.header.nla_type & NLA_TYPE_MASK as u16 == TCA_BPF_CLASSID as u16)?;
+    fn option_in_request(req: &TcRequest, attr_type: u32) -> Option<u32> {
+        let options = find_attr(request_attrs(req), TCA_OPTIONS)?;
+        let attr = find_attr(options.data, attr_type)?;
+        read_u32(attr.data).ok()
+    }
 
-        let raw = u32::from_ne_bytes(classid.data.try_into().ok()?);
-        Some(raw.into())
+    fn classid_in_request(req: &TcRequest) -> Option<TcHandle> {
+        option_in_request(req, TCA_BPF_CLASSID).map(Into::into)
     }
 
     /// Verify that [`TcRequest`] fits a `CLS_BPF_NAME_LEN`-byte program name.
@@ -1002,14 +1405,19 @@ mod tests {
     /// long names failed with "no space left".
     #[test]
     fn tc_request_fits_max_length_name() {
-        tc_request(&[b'a'; CLS_BPF_NAME_LEN], None).unwrap();
+        let options = NlOptions {
+            chain: u32::MAX,
+            classid: Some(TcHandle::new(1, 1)),
+            ..Default::default()
+        };
+        tc_request(&[b'a'; CLS_BPF_NAME_LEN], &options).unwrap();
     }
 
     /// Verify that a name exceeding `CLS_BPF_NAME_LEN` is rejected before the
     /// netlink request is built.
     #[test]
     fn tc_request_rejects_oversized_name() {
-        let Err(err) = tc_request(&[b'a'; CLS_BPF_NAME_LEN + 1], None) else {
+        let Err(err) = tc_request(&[b'a'; CLS_BPF_NAME_LEN + 1], &NlOptions::default()) else {
             panic!("expected oversized name to be rejected");
         };
         assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
@@ -1023,7 +1431,195 @@ mod tests {
     #[case::set(Some(TcHandle::new(1, 1)))]
     #[case::unset(None)]
     fn tc_request_classid_serialization(#[case] classid: Option<TcHandle>) {
-        let req = tc_request(b"foo\0", classid).unwrap();
+        let options = NlOptions {
+            classid,
+            ..Default::default()
+        };
+        let req = tc_request(b"foo\0", &options).unwrap();
         assert_eq!(classid_in_request(&req), classid);
     }
+
+    /// Verify that `TCA_CHAIN` is only sent for chains other than the default
+    /// one, like tc(8) does.
+    #[rstest]
+    #[case::default(0, None)]
+    #[case::other(42, Some(42))]
+    fn tc_request_chain_serialization(#[case] chain: u32, #[case] expected: Option<u32>) {
+        let options = NlOptions {
+            chain,
+            ..Default::default()
+        };
+        let req = tc_request(b"foo\0", &options).unwrap();
+        let chain = find_attr(request_attrs(&req), TCA_CHAIN).map(|a| read_u32(a.data).unwrap());
+        assert_eq!(chain, expected);
+    }
+
+    #[rstest]
+    #[case::direct_action(true, TCA_BPF_FLAG_ACT_DIRECT)]
+    #[case::classifier(false, 0)]
+    fn tc_request_flags_serialization(#[case] direct_action: bool, #[case] flags: u32) {
+        let options = NlOptions {
+            direct_action,
+            ..Default::default()
+        };
+        let req = tc_request(b"foo\0", &options).unwrap();
+        assert_eq!(option_in_request(&req, TCA_BPF_FLAGS), Some(flags));
+    }
+
+    #[test]
+    fn test_qdisc_attrs_htb() {
+        let mut req = unsafe { mem::zeroed::<TcRequest>() };
+        let nlmsg_len = size_of::<nlmsghdr>() + size_of::<tcmsg>();
+        req.header.nlmsg_len = nlmsg_len as u32;
+        let htb = Qdisc::Htb {
+            major: 1,
+            default_class: 10,
+        };
+        write_qdisc_attrs(&mut req, nlmsg_len, c"htb", htb).unwrap();
+
+        let attrs = request_attrs(&req);
+        let kind = find_attr(attrs, TCA_KIND).unwrap();
+        assert_eq!(kind.data, c"htb".to_bytes_with_nul());
+        let options = find_attr(attrs, TCA_OPTIONS).unwrap();
+        let init = find_attr(options.data, u32::from(TCA_HTB_INIT)).unwrap();
+        assert_eq!(init.data.len(), size_of::<TcHtbGlob>());
+        let glob: TcHtbGlob = unsafe { ptr::read_unaligned(init.data.as_ptr().cast()) };
+        assert_eq!(glob.version, TC_HTB_PROTOVER);
+        assert_eq!(glob.defcls, 10);
+    }
+
+    fn filter_message(kind: &CStr, handle: u32, options: bool) -> Vec<u8> {
+        let tc_msg = tcmsg {
+            tcm_handle: handle,
+            tcm_info: tc_handler_make(50 << 16, u32::from(htons(ETH_P_ALL as u16))),
+            ..unsafe { mem::zeroed() }
+        };
+        let mut buf = unsafe {
+            slice::from_raw_parts(ptr::from_ref(&tc_msg).cast::<u8>(), size_of::<tcmsg>())
+        }
+        .to_vec();
+        let mut attrs = [0; 128];
+        let (rest, kind_len) =
+            write_attr_bytes(&mut attrs, TCA_KIND as u16, kind.to_bytes_with_nul()).unwrap();
+        let (rest, chain_len) = write_attr(rest, TCA_CHAIN as u16, 7u32).unwrap();
+        let options_len = if options {
+            let mut options = NestedAttrs::new(rest, TCA_OPTIONS as u16);
+            options
+                .write_attr(TCA_BPF_CLASSID as u16, 0x10002u32)
+                .unwrap();
+            options
+                .write_attr_bytes(TCA_BPF_NAME as u16, c"foo".to_bytes_with_nul())
+                .unwrap();
+            options.write_attr(TCA_BPF_ID as u16, 42u32).unwrap();
+            options
+                .write_attr(TCA_BPF_FLAGS as u16, TCA_BPF_FLAG_ACT_DIRECT)
+                .unwrap();
+            options.finish().unwrap()
+        } else {
+            0
+        };
+        buf.extend_from_slice(&attrs[..kind_len + chain_len + options_len]);
+        buf
+    }
+
+    #[test]
+    fn test_parse_tc_filter() {
+        let data = filter_message(c"bpf", 1, true);
+        let filter = parse_tc_filter(3, TcAttachType::Egress, &data)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            filter,
+            TcFilter {
+                if_index: 3,
+                attach_type: TcAttachType::Egress,
+                priority: 50,
+                handle: TcHandle::new(0, 1),
+                chain: 7,
+                name: Some(c"foo".to_owned()),
+                program_id: Some(42),
+                classid: Some(TcHandle::new(1, 2)),
+                direct_action: true,
+            }
+        );
+    }
+
+    #[rstest]
+    #[case::other_kind(c"u32", 1, true)]
+    #[case::priority(c"bpf", 0, false)]
+    fn test_parse_tc_filter_skipped(
+        #[case] kind: &CStr,
+        #[case] handle: u32,
+        #[case] options: bool,
+    ) {
+        let data = filter_message(kind, handle, options);
+        assert_eq!(
+            parse_tc_filter(3, TcAttachType::Ingress, &data).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_parse_tc_filter_truncated() {
+        assert_eq!(
+            parse_tc_filter(3, TcAttachType::Ingress, &[0; 4]),
+            Err(NlAttrError::BufferLength {
+                size: 4,
+                expected: size_of::<tcmsg>(),
+            })
+        );
+    }
+
+    fn link_message(nlmsg_type: u16, name: Option<&CStr>) -> NetlinkMessage {
+        let if_info = ifinfomsg {
+            ifi_index: 4,
+            ..unsafe { mem::zeroed() }
+        };
+        let mut data = unsafe {
+            slice::from_raw_parts(ptr::from_ref(&if_info).cast::<u8>(), size_of::<ifinfomsg>())
+        }
+        .to_vec();
+        let mut attrs = [0; 64];
+        let (rest, mtu_len) = write_attr(&mut attrs, libc::IFLA_MTU, 1500u32).unwrap();
+        let name_len = match name {
+            Some(name) => {
+                write_attr_bytes(rest, IFLA_IFNAME, name.to_bytes_with_nul())
+                    .unwrap()
+                    .1
+            }
+            None => 0,
+        };
+        data.extend_from_slice(&attrs[..mtu_len + name_len]);
+        NetlinkMessage {
+            header: nlmsghdr {
+                nlmsg_type,
+                ..unsafe { mem::zeroed() }
+            },
+            data,
+            error: None,
+        }
+    }
+
+    #[rstest]
+    #[case::new(RTM_NEWLINK, false)]
+    #[case::del(RTM_DELLINK, true)]
+    fn test_parse_link_message(#[case] nlmsg_type: u16, #[case] removed: bool) {
+        let msg = link_message(nlmsg_type, Some(c"veth0"));
+        assert_eq!(
+            parse_link_message(&msg).unwrap(),
+            Some(LinkMessage {
+                if_index: 4,
+                name: "veth0".to_owned(),
+                removed,
+            })
+        );
+    }
+
+    #[rstest]
+    #[case::other_type(RTM_NEWQDISC, Some(c"veth0"))]
+    #[case::no_name(RTM_NEWLINK, None)]
+    fn test_parse_link_message_skipped(#[case] nlmsg_type: u16, #[case] name: Option<&CStr>) {
+        let msg = link_message(nlmsg_type, name);
+        assert_eq!(parse_link_message(&msg).unwrap(), None);
+    }
 }
@@ -2,6 +2,7 @@
 
 use std::{
     borrow::Cow,
+    ffi::CStr,
     fs,
     io::{self, BufRead as _, BufReader, Write as _},
     marker::PhantomData,
@@ -13,7 +14,10 @@ use std::{
 
 use libc::if_nametoindex;
 
-use crate::{netlink_set_link_up, sys::NetlinkError};
+use crate::{
+    netlink_set_link_up,
+    sys::{NetlinkError, netlink_add_link, netlink_add_veth, netlink_delete_link},
+};
 
 /// The cgroup-relative name of the file to which a PID is written to assign
 /// that process to the cgroup.
@@ -479,6 +483,50 @@ impl Drop for NetNsGuard {
     }
 }
 
+/// Creates a `dummy` network interface named `name` and returns its index.
+///
+/// The interface is created in the network namespace of the calling thread, usually the one of
+/// a [`NetNsGuard`], and goes away with it.
+pub fn add_dummy_link(name: &CStr) -> AyaTestResult<u32> {
+    unsafe {
+        netlink_add_link(name, c"dummy")?;
+    }
+    if_index(name)
+}
+
+fn if_index(name: &CStr) -> AyaTestResult<u32> {
+    // Safety: libc wrapper
+    let idx = unsafe { if_nametoindex(name.as_ptr()) };
+    if idx == 0 {
+        return Err(AyaTestError::Io {
+            op: "lookup interface index",
+            path: PathBuf::from(name.to_string_lossy().into_owned()),
+            source: io::Error::last_os_error(),
+        });
+    }
+    Ok(idx)
+}
+
+/// Creates a pair of `veth` network interfaces named `name` and `peer` and returns their
+/// indices.
+///
+/// Packets sent on one end are received on the other. Like [`add_dummy_link`], the pair lives
+/// in the network namespace of the calling thread.
+pub fn add_veth_pair(name: &CStr, peer: &CStr) -> AyaTestResult<(u32, u32)> {
+    unsafe {
+        netlink_add_veth(name, peer)?;
+    }
+    Ok((if_index(name)?, if_index(peer)?))
+}
+
+/// Deletes the network interface with the given index.
+pub fn delete_link(if_index: u32) -> AyaTestResult<()> {
+    unsafe {
+        netlink_delete_link(if_index as i32)?;
+    }
+    Ok(())
+}
+
 /// Asserts a condition based on the running kernel version.
 ///
 /// If `KernelVersion::current >= $version`, evaluates to `assert!($cond)`.
@@ -0,0 +1,194 @@
+//! `AF_XDP` sockets.
+//!
+//! `AF_XDP` sockets receive packets redirected by XDP programs through an
+//! [`XskMap`](crate::maps::XskMap), and transmit packets directly on a network
+//! interface queue, bypassing most of the kernel network stack.
+//!
+//! Packets are stored in a [`Umem`], a memory region divided in frames and shared with the
+//! kernel. Frames are exchanged with the kernel through four rings:
+//!
+//! - the [`FillQueue`] gives the kernel frames to receive packets in,
+//! - the [`RxQueue`] returns the frames containing received packets,
+//! - the [`TxQueue`] gives the kernel frames containing packets to transmit,
+//! - the [`CompletionQueue`] returns the frames of transmitted packets.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! # let mut bpf = aya::Ebpf::load(&[])?;
+//! use aya::{
+//!     maps::XskMap,
+//!     xsk::{Umem, UmemConfig, XdpDesc, XskConfig, XskSocket},
+//! };
+//!
+//! let mut umem = Umem::new(UmemConfig::default())?;
+//! let mut xsk = XskSocket::bind(&umem, "eth0", 0, &XskConfig::default())?;
+//! let mut xskmap = XskMap::try_from(bpf.map_mut("SOCKETS").unwrap())?;
+//! xskmap.set_socket(&xsk.socket, 0)?;
+//!
+//! // Give all the frames to the kernel to receive packets in.
+//! let frames: Vec<u64> = umem.frames().collect();
+//! let fill = xsk.fill.as_mut().unwrap();
+//! fill.produce(&frames);
+//!
+//! let mut descs = [XdpDesc::default(); 64];
+//! loop {
+//!     let received = xsk.rx.consume(&mut descs);
+//!     for desc in &descs[..received] {
+//!         // SAFETY: the frame was returned by the RX queue and is only given back to the
+//!         // kernel once `packet` is dropped.
+//!         let packet = unsafe { umem.data(desc) }.unwrap();
+//!         println!("received {} bytes", packet.len());
+//!     }
+//!     // Recycle the frames.
+//!     let addrs: Vec<u64> = descs[..received]
+//!         .iter()
+//!         .map(|desc| umem.frame_addr(desc.addr))
+//!         .collect();
+//!     fill.produce(&addrs);
+//!     if fill.needs_wakeup() {
+//!         xsk.socket.wakeup()?;
+//!     }
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! # Minimum kernel version
+//!
+//! The minimum kernel version required to use this feature is 4.18. Shared UMEMs and
+//! `need_wakeup` require 5.4.
+
+mod ring;
+mod socket;
+mod umem;
+
+use std::{
+    io, mem,
+    os::fd::{AsRawFd as _, BorrowedFd, FromRawFd as _, OwnedFd},
+    ptr,
+};
+
+use libc::{AF_XDP, SOCK_CLOEXEC, SOCK_RAW, SOL_XDP, socklen_t};
+pub use ring::{CompletionQueue, FillQueue, RxQueue, TxQueue};
+pub use socket::{Xsk, XskBindMode, XskConfig, XskSocket, XskStatistics};
+use thiserror::Error;
+pub use umem::{Umem, UmemConfig};
+
+use crate::sys::SyscallError;
+
+/// A descriptor of a packet in a [`Umem`], exchanged on the [`RxQueue`] and [`TxQueue`].
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct XdpDesc {
+    /// The address of the packet in the [`Umem`], including the headroom.
+    pub addr: u64,
+    /// The length of the packet.
+    pub len: u32,
+    /// Options, such as `XDP_PKT_CONTD` for multi-buffer packets.
+    pub options: u32,
+}
+
+/// Errors from operations on `AF_XDP` sockets.
+#[derive(Debug, Error)]
+pub enum XskError {
+    /// A configuration value is invalid.
+    #[error("invalid {name} {value}: {reason}")]
+    InvalidConfig {
+        /// The name of the configuration value.
+        name: &'static str,
+        /// The value.
+        value: u32,
+        /// Why the value is invalid.
+        reason: &'static str,
+    },
+
+    /// The network interface doesn't exist.
+    #[error("unknown network interface {name}")]
+    UnknownInterface {
+        /// The interface name.
+        name: String,
+        /// The original [`io::Error`].
+        #[source]
+        io_error: io::Error,
+    },
+
+    /// A syscall failed.
+    #[error(transparent)]
+    SyscallError(#[from] SyscallError),
+}
+
+/// Returns an error if `value` isn't a non-zero power of two.
+const fn check_power_of_two(name: &'static str, value: u32) -> Result<(), XskError> {
+    if value.is_power_of_two() {
+        Ok(())
+    } else {
+        Err(XskError::InvalidConfig {
+            name,
+            value,
+            reason: "must be a power of two",
+        })
+    }
+}
+
+/// Creates an unbound `AF_XDP` socket.
+fn xdp_socket() -> Result<OwnedFd, SyscallError> {
+    // SAFETY: libc wrapper
+    let fd = unsafe { libc::socket(AF_XDP, SOCK_RAW | SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(SyscallError {
+            call: "socket",
+            io_error: io::Error::last_os_error(),
+        });
+    }
+    // SAFETY: `socket` returns a file descriptor.
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+/// Sets the `SOL_XDP` option `name` of the socket `fd` to `value`.
+fn setsockopt<T>(fd: BorrowedFd<'_>, name: i32, value: &T) -> Result<(), SyscallError> {
+    // SAFETY: `value` is valid for `size_of::<T>()` bytes.
+    let ret = unsafe {
+        libc::setsockopt(
+            fd.as_raw_fd(),
+            SOL_XDP,
+            name,
+            ptr::from_ref(value).cast(),
+            size_of::<T>() as socklen_t,
+        )
+    };
+    if ret < 0 {
+        return Err(SyscallError {
+            call: "setsockopt",
+            io_error: io::Error::last_os_error(),
+        });
+    }
+    Ok(())
+}
+
+/// Reads the `SOL_XDP` option `name` of the socket `fd`.
+///
+/// # Safety
+///
+/// All bit patterns must be valid values of `T`.
+unsafe fn getsockopt<T>(fd: BorrowedFd<'_>, name: i32) -> Result<T, SyscallError> {
+    // SAFETY: guaranteed by the caller.
+    let mut value: T = unsafe { mem::zeroed() };
+    let mut len = size_of::<T>() as socklen_t;
+    // SAFETY: `value` is valid for `len` bytes.
+    let ret = unsafe {
+        libc::getsockopt(
+            fd.as_raw_fd(),
+            SOL_XDP,
+            name,
+            ptr::from_mut(&mut value).cast(),
+            &raw mut len,
+        )
+    };
+    if ret < 0 {
+        return Err(SyscallError {
+            call: "getsockopt",
+            io_error: io::Error::last_os_error(),
+        });
+    }
+    Ok(value)
+}
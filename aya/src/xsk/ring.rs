@@ -0,0 +1,348 @@
+//! Rings shared with the kernel.
+
+use std::{
+    fmt::{self, Debug, Formatter},
+    os::fd::BorrowedFd,
+    ptr::NonNull,
+    sync::{
+        Arc,
+        atomic::{AtomicU32, Ordering},
+    },
+};
+
+use libc::{MAP_POPULATE, MAP_SHARED, PROT_READ, PROT_WRITE, XDP_RING_NEED_WAKEUP, off_t};
+
+use crate::{
+    sys::SyscallError,
+    util::MMap,
+    xsk::{XdpDesc, umem::UmemInner},
+};
+
+/// A single producer, single consumer ring shared with the kernel.
+///
+/// The producer and consumer indices grow forever and wrap around; an entry is stored at
+/// `index & (size - 1)`. Local copies of the indices avoid reading the shared ones, and
+/// so bouncing their cache line, on every operation.
+pub(crate) struct Ring<T> {
+    // Keeps the ring mapped. `None` in tests.
+    _mmap: Option<MMap>,
+    producer: NonNull<AtomicU32>,
+    consumer: NonNull<AtomicU32>,
+    flags: NonNull<AtomicU32>,
+    entries: NonNull<T>,
+    size: u32,
+    cached_producer: u32,
+    cached_consumer: u32,
+}
+
+// Needed because NonNull<T> is !Send. The ring is only accessed through `&mut self`.
+unsafe impl<T: Send> Send for Ring<T> {}
+
+impl<T: Copy> Ring<T> {
+    /// Maps the ring of `size` entries of the socket `fd` at `offset`.
+    pub(crate) fn map(
+        fd: BorrowedFd<'_>,
+        offset: off_t,
+        offsets: &libc::xdp_ring_offset,
+        size: u32,
+    ) -> Result<Self, SyscallError> {
+        let len = offsets.desc as usize + size as usize * size_of::<T>();
+        let mmap = MMap::new(
+            fd,
+            len,
+            PROT_READ | PROT_WRITE,
+            MAP_SHARED | MAP_POPULATE,
+            offset,
+        )?;
+        let base = mmap.ptr().cast();
+        // SAFETY: the kernel mapped a ring of `size` entries laid out as described by `offsets`.
+        Ok(unsafe { Self::from_raw(Some(mmap), base, offsets, size) })
+    }
+
+    /// Creates a ring of `size` entries at `base`, laid out as described by `offsets`.
+    ///
+    /// # Safety
+    ///
+    /// `base` must point to a ring valid for as long as `mmap` lives, or forever if `None`.
+    unsafe fn from_raw(
+        mmap: Option<MMap>,
+        base: NonNull<u8>,
+        offsets: &libc::xdp_ring_offset,
+        size: u32,
+    ) -> Self {
+        // SAFETY: the offsets are within the ring.
+        let at = |offset: u64| unsafe { base.add(offset as usize) };
+        let producer = at(offsets.producer).cast::<AtomicU32>();
+        let consumer = at(offsets.consumer).cast::<AtomicU32>();
+        // SAFETY: the indices are valid and aligned.
+        let (cached_producer, cached_consumer) = unsafe {
+            (
+                producer.as_ref().load(Ordering::Relaxed),
+                consumer.as_ref().load(Ordering::Relaxed),
+            )
+        };
+        Self {
+            _mmap: mmap,
+            producer,
+            consumer,
+            flags: at(offsets.flags).cast(),
+            entries: at(offsets.desc).cast(),
+            size,
+            cached_producer,
+            cached_consumer,
+        }
+    }
+
+    const fn producer(&self) -> &AtomicU32 {
+        // SAFETY: the index is valid for as long as the ring.
+        unsafe { self.producer.as_ref() }
+    }
+
+    const fn consumer(&self) -> &AtomicU32 {
+        // SAFETY: the index is valid for as long as the ring.
+        unsafe { self.consumer.as_ref() }
+    }
+
+    const fn entry(&self, index: u32) -> *mut T {
+        // SAFETY: the masked index is within the ring.
+        unsafe {
+            self.entries
+                .as_ptr()
+                .add((index & (self.size - 1)) as usize)
+        }
+    }
+
+    /// Writes as many `entries` as there is room for in the ring and returns how many were
+    /// written.
+    pub(crate) fn produce(&mut self, entries: &[T]) -> usize {
+        let free =
+            |ring: &Self| ring.size - ring.cached_producer.wrapping_sub(ring.cached_consumer);
+        if (free(self) as usize) < entries.len() {
+            self.cached_consumer = self.consumer().load(Ordering::Acquire);
+        }
+        let count = entries.len().min(free(self) as usize);
+        for entry in &entries[..count] {
+            // SAFETY: the entry was released by the consumer.
+            unsafe {
+                self.entry(self.cached_producer).write(*entry);
+            }
+            self.cached_producer = self.cached_producer.wrapping_add(1);
+        }
+        if count > 0 {
+            self.producer()
+                .store(self.cached_producer, Ordering::Release);
+        }
+        count
+    }
+
+    /// Reads as many entries as available in the ring into `entries` and returns how many
+    /// were read.
+    pub(crate) fn consume(&mut self, entries: &mut [T]) -> usize {
+        let available = |ring: &Self| ring.cached_producer.wrapping_sub(ring.cached_consumer);
+        if (available(self) as usize) < entries.len() {
+            self.cached_producer = self.producer().load(Ordering::Acquire);
+        }
+        let count = entries.len().min(available(self) as usize);
+        for entry in &mut entries[..count] {
+            // SAFETY: the entry was submitted by the producer.
+            *entry = unsafe { self.entry(self.cached_consumer).read() };
+            self.cached_consumer = self.cached_consumer.wrapping_add(1);
+        }
+        if count > 0 {
+            self.consumer()
+                .store(self.cached_consumer, Ordering::Release);
+        }
+        count
+    }
+
+    /// Returns true if the kernel must be woken up to process the ring.
+    pub(crate) fn needs_wakeup(&self) -> bool {
+        // SAFETY: the flags are valid for as long as the ring.
+        let flags = unsafe { self.flags.as_ref() };
+        flags.load(Ordering::Relaxed) & XDP_RING_NEED_WAKEUP != 0
+    }
+}
+
+macro_rules! queue {
+    ($(#[$attr:meta])* $name:ident, $entry:ty) => {
+        $(#[$attr])*
+        pub struct $name {
+            ring: Ring<$entry>,
+            // The UMEM must outlive the ring: the mapping keeps the socket, and so the
+            // UMEM registration, alive.
+            _umem: Arc<UmemInner>,
+        }
+
+        impl $name {
+            pub(crate) const fn new(ring: Ring<$entry>, umem: Arc<UmemInner>) -> Self {
+                Self { ring, _umem: umem }
+            }
+
+            /// Returns the number of entries of the ring.
+            pub const fn size(&self) -> u32 {
+                self.ring.size
+            }
+
+            /// Returns true if the kernel must be woken up with
+            /// [`XskSocket::wakeup`](crate::xsk::XskSocket::wakeup) to process the ring.
+            ///
+            /// Always false unless the socket was bound with
+            /// [`XskConfig::need_wakeup`](crate::xsk::XskConfig::need_wakeup).
+            pub fn needs_wakeup(&self) -> bool {
+                self.ring.needs_wakeup()
+            }
+        }
+
+        impl Debug for $name {
+            fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+                f.debug_struct(stringify!($name))
+                    .field("size", &self.ring.size)
+                    .finish_non_exhaustive()
+            }
+        }
+    };
+}
+
+queue!(
+    /// The ring giving the kernel frames to receive packets in.
+    FillQueue,
+    u64
+);
+
+queue!(
+    /// The ring returning the frames of transmitted packets.
+    CompletionQueue,
+    u64
+);
+
+queue!(
+    /// The ring returning received packets.
+    RxQueue,
+    XdpDesc
+);
+
+queue!(
+    /// The ring giving the kernel packets to transmit.
+    TxQueue,
+    XdpDesc
+);
+
+impl FillQueue {
+    /// Gives the frames at `addrs` to the kernel to receive packets in, and returns how many
+    /// were given.
+    ///
+    /// The frames must not be used until they are returned by the [`RxQueue`].
+    pub fn produce(&mut self, addrs: &[u64]) -> usize {
+        self.ring.produce(addrs)
+    }
+}
+
+impl CompletionQueue {
+    /// Reads the addresses of the frames of transmitted packets into `addrs`, and returns how
+    /// many were read.
+    pub fn consume(&mut self, addrs: &mut [u64]) -> usize {
+        self.ring.consume(addrs)
+    }
+}
+
+impl RxQueue {
+    /// Reads the descriptors of received packets into `descs`, and returns how many were read.
+    ///
+    /// Once processed, the frames should be given back with [`FillQueue::produce`].
+    pub fn consume(&mut self, descs: &mut [XdpDesc]) -> usize {
+        self.ring.consume(descs)
+    }
+}
+
+impl TxQueue {
+    /// Gives the packets described by `descs` to the kernel to transmit, and returns how many
+    /// were given.
+    ///
+    /// The frames must not be used until they are returned by the [`CompletionQueue`]. The
+    /// kernel may need to be woken up with
+    /// [`XskSocket::wakeup`](crate::xsk::XskSocket::wakeup) to transmit them.
+    pub fn produce(&mut self, descs: &[XdpDesc]) -> usize {
+        self.ring.produce(descs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem::offset_of;
+
+    use super::*;
+
+    #[repr(C)]
+    struct TestRing {
+        producer: AtomicU32,
+        consumer: AtomicU32,
+        flags: AtomicU32,
+        entries: [u64; 4],
+    }
+
+    fn ring(test_ring: &mut TestRing) -> Ring<u64> {
+        let offsets = libc::xdp_ring_offset {
+            producer: offset_of!(TestRing, producer) as u64,
+            consumer: offset_of!(TestRing, consumer) as u64,
+            desc: offset_of!(TestRing, entries) as u64,
+            flags: offset_of!(TestRing, flags) as u64,
+        };
+        let base = NonNull::from(test_ring).cast();
+        unsafe { Ring::from_raw(None, base, &offsets, 4) }
+    }
+
+    #[test]
+    fn test_produce() {
+        let mut test_ring = TestRing {
+            producer: AtomicU32::new(u32::MAX - 1),
+            consumer: AtomicU32::new(u32::MAX - 1),
+            flags: AtomicU32::new(0),
+            entries: [0; 4],
+        };
+        let mut producer = ring(&mut test_ring);
+        assert_eq!(producer.produce(&[1, 2, 3]), 3);
+        assert_eq!(producer.produce(&[4, 5]), 1);
+        assert_eq!(producer.produce(&[6]), 0);
+        assert!(!producer.needs_wakeup());
+        drop(producer);
+        assert_eq!(test_ring.producer.load(Ordering::Relaxed), 2);
+        assert_eq!(test_ring.entries, [3, 4, 1, 2]);
+
+        // The kernel consumes two entries.
+        test_ring.consumer.store(0, Ordering::Relaxed);
+        test_ring
+            .flags
+            .store(XDP_RING_NEED_WAKEUP, Ordering::Relaxed);
+        let mut producer = ring(&mut test_ring);
+        assert_eq!(producer.produce(&[5, 6, 7]), 2);
+        assert!(producer.needs_wakeup());
+        drop(producer);
+        assert_eq!(test_ring.producer.load(Ordering::Relaxed), 4);
+        assert_eq!(test_ring.entries, [3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn test_consume() {
+        let mut test_ring = TestRing {
+            producer: AtomicU32::new(u32::MAX),
+            consumer: AtomicU32::new(u32::MAX - 1),
+            flags: AtomicU32::new(0),
+            entries: [1, 2, 3, 4],
+        };
+        let mut consumer = ring(&mut test_ring);
+        let mut entries = [0; 4];
+        assert_eq!(consumer.consume(&mut entries), 1);
+        assert_eq!(entries, [3, 0, 0, 0]);
+        drop(consumer);
+
+        // The kernel produces three entries.
+        test_ring.producer.store(2, Ordering::Relaxed);
+        let mut consumer = ring(&mut test_ring);
+        assert_eq!(consumer.consume(&mut entries[..2]), 2);
+        assert_eq!(consumer.consume(&mut entries[2..]), 1);
+        assert_eq!(entries, [4, 1, 2, 0]);
+        assert_eq!(consumer.consume(&mut entries), 0);
+        drop(consumer);
+        assert_eq!(test_ring.consumer.load(Ordering::Relaxed), 2);
+    }
+}
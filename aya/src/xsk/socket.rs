@@ -0,0 +1,390 @@
+//! `AF_XDP` sockets bound to a network interface queue.
+
+use std::{
+    fmt::{self, Debug, Formatter},
+    io,
+    os::fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd, RawFd},
+    ptr,
+    sync::Arc,
+};
+
+use libc::{
+    AF_XDP, EAGAIN, EBUSY, ENETDOWN, ENOBUFS, MSG_DONTWAIT, XDP_COPY, XDP_MMAP_OFFSETS,
+    XDP_PGOFF_RX_RING, XDP_PGOFF_TX_RING, XDP_RX_RING, XDP_SHARED_UMEM, XDP_STATISTICS,
+    XDP_TX_RING, XDP_UMEM_COMPLETION_RING, XDP_UMEM_FILL_RING, XDP_UMEM_PGOFF_COMPLETION_RING,
+    XDP_UMEM_PGOFF_FILL_RING, XDP_USE_NEED_WAKEUP, XDP_ZEROCOPY, off_t, sockaddr, sockaddr_xdp,
+    socklen_t,
+};
+
+use crate::{
+    sys::SyscallError,
+    util::ifindex_from_ifname,
+    xsk::{
+        CompletionQueue, FillQueue, RxQueue, TxQueue, Umem, XskError, check_power_of_two,
+        getsockopt, ring::Ring, setsockopt, umem::UmemInner, xdp_socket,
+    },
+};
+
+/// How an [`XskSocket`] exchanges packets with the driver.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum XskBindMode {
+    /// Use zero-copy mode if the driver supports it, copy mode otherwise.
+    #[default]
+    Auto,
+    /// Copy packets between the driver buffers and the [`Umem`].
+    Copy,
+    /// Let the driver use the [`Umem`] directly. Fails if the driver doesn't support it.
+    ZeroCopy,
+}
+
+impl XskBindMode {
+    const fn flags(self) -> u16 {
+        match self {
+            Self::Auto => 0,
+            Self::Copy => XDP_COPY,
+            Self::ZeroCopy => XDP_ZEROCOPY,
+        }
+    }
+}
+
+/// The configuration of an [`XskSocket`].
+#[derive(Clone, Copy, Debug)]
+pub struct XskConfig {
+    /// The number of entries of the RX ring, a power of two.
+    pub rx_size: u32,
+    /// The number of entries of the TX ring, a power of two.
+    pub tx_size: u32,
+    /// How the socket exchanges packets with the driver.
+    pub mode: XskBindMode,
+    /// Whether the kernel must be explicitly woken up with [`XskSocket::wakeup`] when
+    /// [`needs_wakeup`](crate::xsk::FillQueue::needs_wakeup) returns true, rather than busy
+    /// polling the rings.
+    pub need_wakeup: bool,
+}
+
+impl Default for XskConfig {
+    fn default() -> Self {
+        Self {
+            rx_size: 2048,
+            tx_size: 2048,
+            mode: XskBindMode::Auto,
+            need_wakeup: true,
+        }
+    }
+}
+
+/// Statistics of an [`XskSocket`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct XskStatistics {
+    /// Packets dropped for reasons other than an invalid descriptor.
+    pub rx_dropped: u64,
+    /// Packets dropped because of an invalid descriptor.
+    pub rx_invalid_descs: u64,
+    /// Packets not transmitted because of an invalid descriptor.
+    pub tx_invalid_descs: u64,
+    /// Packets dropped because the RX ring was full.
+    pub rx_ring_full: u64,
+    /// Times the fill ring was empty when receiving a packet.
+    pub rx_fill_ring_empty_descs: u64,
+    /// Times the TX ring was empty when the kernel tried to transmit.
+    pub tx_ring_empty_descs: u64,
+}
+
+/// A bound [`XskSocket`] and its rings.
+#[derive(Debug)]
+pub struct Xsk {
+    /// The socket.
+    pub socket: XskSocket,
+    /// The ring returning received packets.
+    pub rx: RxQueue,
+    /// The ring giving the kernel packets to transmit.
+    pub tx: TxQueue,
+    /// The fill ring of the interface queue, if this is the first socket of the [`Umem`] bound
+    /// to it. Otherwise, the fill ring of the first socket is shared.
+    pub fill: Option<FillQueue>,
+    /// The completion ring of the interface queue, see [`fill`](Self::fill).
+    pub completion: Option<CompletionQueue>,
+}
+
+/// An `AF_XDP` socket bound to a network interface queue.
+///
+/// Packets redirected to the socket by an XDP program through an
+/// [`XskMap`](crate::maps::XskMap) are received on its [`RxQueue`].
+pub struct XskSocket {
+    // `None` for the first socket of the UMEM, which is the socket the UMEM is registered on.
+    fd: Option<OwnedFd>,
+    umem: Arc<UmemInner>,
+    ifindex: u32,
+    queue_id: u32,
+}
+
+impl XskSocket {
+    /// Creates a socket using `umem` and binds it to the queue `queue_id` of the network
+    /// interface `interface`.
+    ///
+    /// The first socket bound to a given interface queue gets the fill and completion rings of
+    /// the queue, which are shared with the later ones. Later sockets share the bind mode and
+    /// the `need_wakeup` setting of the first socket of the [`Umem`], so `config.mode` and
+    /// `config.need_wakeup` must match the ones the first socket was bound with.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`XskError::InvalidConfig`] if `config` is invalid or doesn't match the first
+    /// socket of the [`Umem`],
+    /// [`XskError::UnknownInterface`] if `interface` doesn't exist and
+    /// [`XskError::SyscallError`] if the socket can't be created or bound.
+    pub fn bind(
+        umem: &Umem,
+        interface: &str,
+        queue_id: u32,
+        config: &XskConfig,
+    ) -> Result<Xsk, XskError> {
+        let XskConfig {
+            rx_size,
+            tx_size,
+            mode,
+            need_wakeup,
+        } = *config;
+        check_power_of_two("RX size", rx_size)?;
+        check_power_of_two("TX size", tx_size)?;
+        let ifindex =
+            ifindex_from_ifname(interface).map_err(|io_error| XskError::UnknownInterface {
+                name: interface.to_owned(),
+                io_error,
+            })?;
+
+        let umem = umem.inner();
+        let mut bindings = umem
+            .bindings
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let shared = if let Some((owner_mode, owner_need_wakeup)) = bindings.owner {
+            if mode != owner_mode {
+                return Err(XskError::InvalidConfig {
+                    name: "bind mode",
+                    value: mode.flags().into(),
+                    reason: "must match the first socket of the UMEM",
+                });
+            }
+            if need_wakeup != owner_need_wakeup {
+                return Err(XskError::InvalidConfig {
+                    name: "need_wakeup",
+                    value: need_wakeup.into(),
+                    reason: "must match the first socket of the UMEM",
+                });
+            }
+            true
+        } else {
+            false
+        };
+        let socket = Self {
+            fd: shared.then(xdp_socket).transpose()?,
+            umem: Arc::clone(umem),
+            ifindex,
+            queue_id,
+        };
+        let fd = socket.as_fd();
+        let own_rings = !bindings.queues.contains(&(ifindex, queue_id));
+
+        let config = &umem.config;
+        // Rings set up on the socket the UMEM is registered on by a failed bind are reused.
+        let owner_rings = &mut bindings.owner_rings;
+        if own_rings && (shared || !owner_rings.umem) {
+            setsockopt(fd, XDP_UMEM_FILL_RING, &config.fill_size)?;
+            setsockopt(fd, XDP_UMEM_COMPLETION_RING, &config.completion_size)?;
+            owner_rings.umem |= !shared;
+        }
+        match owner_rings.rx_tx {
+            Some((owner_rx_size, owner_tx_size)) if !shared => {
+                if rx_size != owner_rx_size {
+                    return Err(XskError::InvalidConfig {
+                        name: "RX size",
+                        value: rx_size,
+                        reason: "must match the RX ring set up by a failed bind",
+                    });
+                }
+                if tx_size != owner_tx_size {
+                    return Err(XskError::InvalidConfig {
+                        name: "TX size",
+                        value: tx_size,
+                        reason: "must match the TX ring set up by a failed bind",
+                    });
+                }
+            }
+            Some(_) | None => {
+                setsockopt(fd, XDP_RX_RING, &rx_size)?;
+                setsockopt(fd, XDP_TX_RING, &tx_size)?;
+                if !shared {
+                    owner_rings.rx_tx = Some((rx_size, tx_size));
+                }
+            }
+        }
+        // SAFETY: `xdp_mmap_offsets` only contains integers.
+        let offsets: libc::xdp_mmap_offsets = unsafe { getsockopt(fd, XDP_MMAP_OFFSETS)? };
+
+        let rx = Ring::map(fd, XDP_PGOFF_RX_RING, &offsets.rx, rx_size)?;
+        let tx = Ring::map(fd, XDP_PGOFF_TX_RING, &offsets.tx, tx_size)?;
+        let (fill, completion) = if own_rings {
+            let fill = Ring::map(
+                fd,
+                XDP_UMEM_PGOFF_FILL_RING as off_t,
+                &offsets.fr,
+                config.fill_size,
+            )?;
+            let completion = Ring::map(
+                fd,
+                XDP_UMEM_PGOFF_COMPLETION_RING as off_t,
+                &offsets.cr,
+                config.completion_size,
+            )?;
+            (
+                Some(FillQueue::new(fill, Arc::clone(umem))),
+                Some(CompletionQueue::new(completion, Arc::clone(umem))),
+            )
+        } else {
+            (None, None)
+        };
+
+        // The kernel rejects the mode flags along with `XDP_SHARED_UMEM`.
+        let (flags, shared_umem_fd) = if shared {
+            (XDP_SHARED_UMEM, umem.fd().as_raw_fd() as u32)
+        } else {
+            let need_wakeup = if need_wakeup { XDP_USE_NEED_WAKEUP } else { 0 };
+            (mode.flags() | need_wakeup, 0)
+        };
+        let addr = sockaddr_xdp {
+            sxdp_family: AF_XDP as u16,
+            sxdp_flags: flags,
+            sxdp_ifindex: ifindex,
+            sxdp_queue_id: queue_id,
+            sxdp_shared_umem_fd: shared_umem_fd,
+        };
+        // SAFETY: libc wrapper
+        let ret = unsafe {
+            libc::bind(
+                fd.as_raw_fd(),
+                ptr::from_ref(&addr).cast::<sockaddr>(),
+                size_of_val(&addr) as socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(SyscallError {
+                call: "bind",
+                io_error: io::Error::last_os_error(),
+            }
+            .into());
+        }
+
+        if !shared {
+            bindings.owner = Some((mode, need_wakeup));
+        }
+        if own_rings {
+            bindings.queues.push((ifindex, queue_id));
+        }
+        drop(bindings);
+
+        let rx = RxQueue::new(rx, Arc::clone(umem));
+        let tx = TxQueue::new(tx, Arc::clone(umem));
+        Ok(Xsk {
+            socket,
+            rx,
+            tx,
+            fill,
+            completion,
+        })
+    }
+
+    /// Returns the index of the network interface the socket is bound to.
+    pub const fn ifindex(&self) -> u32 {
+        self.ifindex
+    }
+
+    /// Returns the queue the socket is bound to.
+    pub const fn queue_id(&self) -> u32 {
+        self.queue_id
+    }
+
+    /// Wakes up the kernel to process the rings of the socket.
+    ///
+    /// Needed when [`needs_wakeup`](crate::xsk::TxQueue::needs_wakeup) returns true on the
+    /// [`TxQueue`] or [`FillQueue`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`XskError::SyscallError`] if `sendto` fails, except if the kernel is merely busy.
+    pub fn wakeup(&self) -> Result<(), XskError> {
+        // SAFETY: libc wrapper
+        let ret = unsafe {
+            libc::sendto(
+                self.as_raw_fd(),
+                ptr::null(),
+                0,
+                MSG_DONTWAIT,
+                ptr::null(),
+                0,
+            )
+        };
+        if ret < 0 {
+            let io_error = io::Error::last_os_error();
+            if !matches!(
+                io_error.raw_os_error(),
+                Some(EAGAIN | EBUSY | ENOBUFS | ENETDOWN)
+            ) {
+                return Err(SyscallError {
+                    call: "sendto",
+                    io_error,
+                }
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the statistics of the socket.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`XskError::SyscallError`] if `getsockopt` fails.
+    pub fn statistics(&self) -> Result<XskStatistics, XskError> {
+        // SAFETY: `xdp_statistics` only contains integers.
+        let libc::xdp_statistics {
+            rx_dropped,
+            rx_invalid_descs,
+            tx_invalid_descs,
+            rx_ring_full,
+            rx_fill_ring_empty_descs,
+            tx_ring_empty_descs,
+        } = unsafe { getsockopt(self.as_fd(), XDP_STATISTICS)? };
+        Ok(XskStatistics {
+            rx_dropped,
+            rx_invalid_descs,
+            tx_invalid_descs,
+            rx_ring_full,
+            rx_fill_ring_empty_descs,
+            tx_ring_empty_descs,
+        })
+    }
+}
+
+impl AsFd for XskSocket {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        let Self { fd, umem, .. } = self;
+        fd.as_ref().unwrap_or_else(|| umem.fd()).as_fd()
+    }
+}
+
+impl AsRawFd for XskSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.as_fd().as_raw_fd()
+    }
+}
+
+impl Debug for XskSocket {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("XskSocket")
+            .field("fd", &self.as_fd())
+            .field("ifindex", &self.ifindex)
+            .field("queue_id", &self.queue_id)
+            .finish_non_exhaustive()
+    }
+}
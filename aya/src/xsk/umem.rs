@@ -0,0 +1,281 @@
+//! Memory shared with the kernel to store packets.
+
+use std::{
+    alloc::{self, Layout},
+    fmt::{self, Debug, Formatter},
+    os::fd::{AsFd as _, OwnedFd},
+    ptr::NonNull,
+    slice,
+    sync::{Arc, Mutex},
+};
+
+use libc::XDP_UMEM_REG;
+
+use crate::{
+    util::page_size,
+    xsk::{XdpDesc, XskBindMode, XskError, check_power_of_two, setsockopt, xdp_socket},
+};
+
+/// The configuration of a [`Umem`].
+#[derive(Clone, Copy, Debug)]
+pub struct UmemConfig {
+    /// The number of frames.
+    pub frame_count: u32,
+    /// The size of each frame, a power of two between 2048 and the page size.
+    pub frame_size: u32,
+    /// The number of bytes reserved at the start of each frame, before received packets.
+    pub frame_headroom: u32,
+    /// The number of entries of the fill rings, a power of two.
+    pub fill_size: u32,
+    /// The number of entries of the completion rings, a power of two.
+    pub completion_size: u32,
+}
+
+impl Default for UmemConfig {
+    fn default() -> Self {
+        Self {
+            frame_count: 4096,
+            frame_size: 4096,
+            frame_headroom: 0,
+            fill_size: 2048,
+            completion_size: 2048,
+        }
+    }
+}
+
+/// The sockets bound to a [`Umem`].
+#[derive(Default)]
+pub(crate) struct Bindings {
+    /// The bind mode and `need_wakeup` setting of the socket the UMEM is registered on, once
+    /// bound.
+    pub(crate) owner: Option<(XskBindMode, bool)>,
+    /// The rings set up on the socket the UMEM is registered on.
+    ///
+    /// The kernel doesn't allow setting up a ring twice, so they're kept across failed binds.
+    pub(crate) owner_rings: OwnerRings,
+    /// The interfaces and queues which have a fill and a completion ring.
+    pub(crate) queues: Vec<(u32, u32)>,
+}
+
+/// The rings set up on the socket a [`Umem`] is registered on.
+#[derive(Default)]
+pub(crate) struct OwnerRings {
+    /// Whether the fill and completion rings are set up.
+    pub(crate) umem: bool,
+    /// The sizes of the RX and TX rings, if set up.
+    pub(crate) rx_tx: Option<(u32, u32)>,
+}
+
+pub(crate) struct UmemInner {
+    memory: NonNull<u8>,
+    layout: Layout,
+    // The socket the UMEM is registered on. `None` once dropped.
+    fd: Option<OwnedFd>,
+    pub(crate) config: UmemConfig,
+    pub(crate) bindings: Mutex<Bindings>,
+}
+
+// Needed because NonNull<T> is !Send and !Sync. The memory is only accessed through `Umem`.
+unsafe impl Send for UmemInner {}
+unsafe impl Sync for UmemInner {}
+
+impl UmemInner {
+    pub(crate) const fn fd(&self) -> &OwnedFd {
+        let Self { fd, .. } = self;
+        // The socket is only dropped in `drop`.
+        fd.as_ref().expect("UMEM socket dropped")
+    }
+}
+
+impl Drop for UmemInner {
+    fn drop(&mut self) {
+        let Self {
+            memory,
+            layout,
+            fd,
+            config: _,
+            bindings: _,
+        } = self;
+        // Close the socket first so that the kernel stops using the memory.
+        drop(fd.take());
+        // SAFETY: the memory was allocated with this layout.
+        unsafe {
+            alloc::dealloc(memory.as_ptr(), *layout);
+        }
+    }
+}
+
+/// Memory shared with the kernel to store packets, divided in frames.
+///
+/// Frames are identified by their address, the offset of their first byte in the UMEM. The
+/// UMEM can be shared by several [`XskSocket`](crate::xsk::XskSocket)s, and is freed once it and
+/// all the sockets using it are dropped.
+pub struct Umem {
+    inner: Arc<UmemInner>,
+}
+
+impl Umem {
+    /// Allocates a UMEM and registers it with the kernel.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`XskError::InvalidConfig`] if `config` is invalid, [`XskError::SyscallError`] if
+    /// the UMEM can't be registered.
+    pub fn new(config: UmemConfig) -> Result<Self, XskError> {
+        let UmemConfig {
+            frame_count,
+            frame_size,
+            frame_headroom,
+            fill_size,
+            completion_size,
+        } = config;
+        check_power_of_two("frame size", frame_size)?;
+        check_power_of_two("fill size", fill_size)?;
+        check_power_of_two("completion size", completion_size)?;
+        let page_size = page_size();
+        if frame_size < 2048 || frame_size as usize > page_size {
+            return Err(XskError::InvalidConfig {
+                name: "frame size",
+                value: frame_size,
+                reason: "must be between 2048 and the page size",
+            });
+        }
+        if frame_headroom >= frame_size {
+            return Err(XskError::InvalidConfig {
+                name: "frame headroom",
+                value: frame_headroom,
+                reason: "must be smaller than the frame size",
+            });
+        }
+        let len = frame_count as usize * frame_size as usize;
+        let layout = Layout::from_size_align(len, page_size)
+            .ok()
+            .filter(|_| len > 0)
+            .ok_or(XskError::InvalidConfig {
+                name: "frame count",
+                value: frame_count,
+                reason: "must be non-zero and fit in memory",
+            })?;
+
+        let fd = xdp_socket()?;
+        // SAFETY: the layout has a non-zero size.
+        let memory = unsafe { alloc::alloc_zeroed(layout) };
+        let Some(memory) = NonNull::new(memory) else {
+            alloc::handle_alloc_error(layout)
+        };
+        let inner = UmemInner {
+            memory,
+            layout,
+            fd: Some(fd),
+            config,
+            bindings: Mutex::default(),
+        };
+
+        let reg = libc::xdp_umem_reg {
+            addr: memory.as_ptr() as u64,
+            len: len as u64,
+            chunk_size: frame_size,
+            headroom: frame_headroom,
+            flags: 0,
+            tx_metadata_len: 0,
+        };
+        setsockopt(inner.fd().as_fd(), XDP_UMEM_REG, &reg)?;
+
+        Ok(Self {
+            inner: Arc::new(inner),
+        })
+    }
+
+    pub(crate) const fn inner(&self) -> &Arc<UmemInner> {
+        &self.inner
+    }
+
+    /// Returns the configuration of the UMEM.
+    pub fn config(&self) -> &UmemConfig {
+        &self.inner.config
+    }
+
+    /// Returns the addresses of all the frames.
+    pub fn frames(&self) -> impl Iterator<Item = u64> + use<> {
+        let UmemConfig {
+            frame_count,
+            frame_size,
+            ..
+        } = self.inner.config;
+        (0..u64::from(frame_count)).map(move |frame| frame * u64::from(frame_size))
+    }
+
+    /// Returns the address of the frame containing `addr`.
+    ///
+    /// Use it to give the frame of a received packet back to the [`FillQueue`](super::FillQueue).
+    pub fn frame_addr(&self, addr: u64) -> u64 {
+        addr & !(u64::from(self.inner.config.frame_size) - 1)
+    }
+
+    /// Returns the `len` bytes at `addr`, or `None` if they're not within a frame.
+    ///
+    /// # Safety
+    ///
+    /// The frame must be owned by user space for as long as the returned slice lives: it must
+    /// have been returned by the [`RxQueue`](super::RxQueue) or the
+    /// [`CompletionQueue`](super::CompletionQueue), or never been given to the kernel, and must
+    /// not be given to the [`FillQueue`](super::FillQueue) or the [`TxQueue`](super::TxQueue)
+    /// until the slice is dropped. Otherwise the kernel may write to it concurrently.
+    pub unsafe fn frame(&self, addr: u64, len: usize) -> Option<&[u8]> {
+        let offset = self.check_range(addr, len)?;
+        // SAFETY: the range is within the UMEM and the caller guarantees the kernel doesn't
+        // write to it while the slice lives.
+        Some(unsafe { slice::from_raw_parts(self.inner.memory.as_ptr().add(offset), len) })
+    }
+
+    /// Returns the `len` bytes at `addr` mutably, or `None` if they're not within a frame.
+    ///
+    /// # Safety
+    ///
+    /// The frame must be owned by user space as described in [`Umem::frame`]. In addition, no
+    /// other slice of the frame may exist while the returned slice lives, including slices
+    /// obtained through other sockets sharing the UMEM.
+    pub unsafe fn frame_mut(&mut self, addr: u64, len: usize) -> Option<&mut [u8]> {
+        let offset = self.check_range(addr, len)?;
+        // SAFETY: the range is within the UMEM and the caller guarantees exclusive access to it
+        // while the slice lives.
+        Some(unsafe { slice::from_raw_parts_mut(self.inner.memory.as_ptr().add(offset), len) })
+    }
+
+    /// Returns the packet described by `desc`, or `None` if it's not within a frame.
+    ///
+    /// # Safety
+    ///
+    /// See [`Umem::frame`].
+    pub unsafe fn data(&self, desc: &XdpDesc) -> Option<&[u8]> {
+        // SAFETY: guaranteed by the caller.
+        unsafe { self.frame(desc.addr, desc.len as usize) }
+    }
+
+    /// Returns the packet described by `desc` mutably, or `None` if it's not within a frame.
+    ///
+    /// # Safety
+    ///
+    /// See [`Umem::frame_mut`].
+    pub unsafe fn data_mut(&mut self, desc: &XdpDesc) -> Option<&mut [u8]> {
+        // SAFETY: guaranteed by the caller.
+        unsafe { self.frame_mut(desc.addr, desc.len as usize) }
+    }
+
+    /// Returns the offset of `addr` in the UMEM if the `len` bytes at `addr` are within a frame.
+    fn check_range(&self, addr: u64, len: usize) -> Option<usize> {
+        let frame_size = u64::from(self.inner.config.frame_size);
+        let end = addr.checked_add(len as u64)?;
+        (end <= self.inner.layout.size() as u64
+            && (len == 0 || addr / frame_size == (end - 1) / frame_size))
+            .then_some(addr as usize)
+    }
+}
+
+impl Debug for Umem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Umem")
+            .field("config", &self.inner.config)
+            .finish_non_exhaustive()
+    }
+}
@@ -1,7 +1,60 @@
 use core::ffi::c_void;
 
+use aya_ebpf_cty::c_int;
+
 use crate::{EbpfContext, bindings::xdp_md};
 
+// XDP metadata kfuncs, implemented by the network driver. They're only available to programs
+// loaded for a specific device; other programs get `-EOPNOTSUPP`.
+unsafe extern "C" {
+    fn bpf_xdp_metadata_rx_timestamp(ctx: *const xdp_md, timestamp: *mut u64) -> c_int;
+    fn bpf_xdp_metadata_rx_hash(ctx: *const xdp_md, hash: *mut u32, rss_type: *mut u32) -> c_int;
+    fn bpf_xdp_metadata_rx_vlan_tag(
+        ctx: *const xdp_md,
+        vlan_proto: *mut u16,
+        vlan_tci: *mut u16,
+    ) -> c_int;
+}
+
+/// The hash of a received packet, computed by the network device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RxHash {
+    /// The hash.
+    pub hash: u32,
+    /// The headers the hash was computed on, a combination of the `RSS_*` constants.
+    pub rss_type: u32,
+}
+
+impl RxHash {
+    /// The hash covers IPv4 addresses.
+    pub const RSS_L3_IPV4: u32 = 1 << 0;
+    /// The hash covers IPv6 addresses.
+    pub const RSS_L3_IPV6: u32 = 1 << 1;
+    /// The hash covers IPv6 extension headers.
+    pub const RSS_L3_DYNHDR: u32 = 1 << 2;
+    /// The hash covers L4 ports.
+    pub const RSS_L4: u32 = 1 << 3;
+    /// The packet is TCP.
+    pub const RSS_L4_TCP: u32 = 1 << 4;
+    /// The packet is UDP.
+    pub const RSS_L4_UDP: u32 = 1 << 5;
+    /// The packet is SCTP.
+    pub const RSS_L4_SCTP: u32 = 1 << 6;
+    /// The packet uses IP security.
+    pub const RSS_L4_IPSEC: u32 = 1 << 7;
+    /// The packet is ICMP.
+    pub const RSS_L4_ICMP: u32 = 1 << 8;
+}
+
+/// The VLAN tag of a received packet, stripped by the network device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RxVlanTag {
+    /// The VLAN protocol, such as `ETH_P_8021Q`, in host byte order.
+    pub proto: u16,
+    /// The tag control information: priority, drop eligible indicator and VLAN ID.
+    pub tci: u16,
+}
+
 pub struct XdpContext {
     pub ctx: *mut xdp_md,
 }
@@ -44,6 +97,66 @@ impl XdpContext {
     pub fn rx_queue_index(&self) -> u32 {
         unsafe { (*self.ctx).rx_queue_index }
     }
+
+    /// Returns the time the packet was received at, in nanoseconds, as reported by the
+    /// network device.
+    ///
+    /// The program must be loaded for the device, see `Xdp::load_dev_bound` in aya. Requires
+    /// Linux 6.3.
+    ///
+    /// # Errors
+    ///
+    /// Returns a negative error code, such as `-EOPNOTSUPP` if the driver doesn't support it or
+    /// `-ENODATA` if the timestamp isn't available for this packet.
+    #[inline]
+    pub fn rx_timestamp(&self) -> Result<u64, c_int> {
+        let mut timestamp = 0;
+        match unsafe { bpf_xdp_metadata_rx_timestamp(self.ctx, &raw mut timestamp) } {
+            0 => Ok(timestamp),
+            ret => Err(ret),
+        }
+    }
+
+    /// Returns the hash of the packet computed by the network device, such as its RSS hash.
+    ///
+    /// The program must be loaded for the device, see `Xdp::load_dev_bound` in aya. Requires
+    /// Linux 6.3.
+    ///
+    /// # Errors
+    ///
+    /// Returns a negative error code, such as `-EOPNOTSUPP` if the driver doesn't support it or
+    /// `-ENODATA` if the hash isn't available for this packet.
+    #[inline]
+    pub fn rx_hash(&self) -> Result<RxHash, c_int> {
+        let mut hash = 0;
+        let mut rss_type = 0;
+        match unsafe { bpf_xdp_metadata_rx_hash(self.ctx, &raw mut hash, &raw mut rss_type) } {
+            0 => Ok(RxHash { hash, rss_type }),
+            ret => Err(ret),
+        }
+    }
+
+    /// Returns the VLAN tag of the packet, stripped by the network device.
+    ///
+    /// The program must be loaded for the device, see `Xdp::load_dev_bound` in aya. Requires
+    /// Linux 6.8.
+    ///
+    /// # Errors
+    ///
+    /// Returns a negative error code, such as `-EOPNOTSUPP` if the driver doesn't support it or
+    /// `-ENODATA` if the packet had no tag.
+    #[inline]
+    pub fn rx_vlan_tag(&self) -> Result<RxVlanTag, c_int> {
+        let mut proto = 0;
+        let mut tci = 0;
+        match unsafe { bpf_xdp_metadata_rx_vlan_tag(self.ctx, &raw mut proto, &raw mut tci) } {
+            0 => Ok(RxVlanTag {
+                proto: u16::from_be(proto),
+                tci,
+            }),
+            ret => Err(ret),
+        }
+    }
 }
 
 impl EbpfContext for XdpContext {
@@ -51,6 +51,7 @@ pub mod fexit {
     pub const TEST_COUNT: u32 = 10;
 
     #[derive(Clone, Copy, Default)]
+    #[cfg_attr(feature = "user", derive(aya::Pod))]
     #[repr(C)]
     pub struct TestResult {
         /// Distinguishes a recorded result from a zero-initialised slot. Use a
@@ -59,9 +60,6 @@ pub mod fexit {
         pub ran: u32,
         pub error: i32,
     }
-
-    #[cfg(feature = "user")]
-    unsafe impl aya::Pod for TestResult {}
 }
 
 pub mod bpf_probe_read {
@@ -116,36 +114,30 @@ pub mod log {
 pub mod raw_tracepoint {
     #[repr(C)]
     #[derive(Clone, Copy)]
+    #[cfg_attr(feature = "user", derive(aya::Pod))]
     pub struct SysEnterEvent {
         pub regs_addr: u64,
         pub syscall_id: i64,
     }
 
-    #[cfg(feature = "user")]
-    unsafe impl aya::Pod for SysEnterEvent {}
-
     #[repr(C)]
     #[derive(Clone, Copy)]
+    #[cfg_attr(feature = "user", derive(aya::Pod))]
     pub struct TaskRenameEvent {
         pub task_addr: u64,
         pub comm_addr: u64,
     }
-
-    #[cfg(feature = "user")]
-    unsafe impl aya::Pod for TaskRenameEvent {}
 }
 
 pub mod ring_buf {
     // This structure's definition is duplicated in the probe.
     #[repr(C)]
     #[derive(Clone, Copy, Debug, Eq, PartialEq, Default)]
+    #[cfg_attr(feature = "user", derive(aya::Pod))]
     pub struct Registers {
         pub dropped: u64,
         pub rejected: u64,
     }
-
-    #[cfg(feature = "user")]
-    unsafe impl aya::Pod for Registers {}
 }
 
 pub mod strncmp {
@@ -188,14 +180,12 @@ pub mod btf_map_of_maps {
     pub const INNER_MAX_ENTRIES: u32 = 10;
 
     #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+    #[cfg_attr(feature = "user", derive(aya::Pod))]
     #[repr(C)]
     pub struct TestResult {
         pub value: u32,
         pub ran: u32,
     }
-
-    #[cfg(feature = "user")]
-    unsafe impl aya::Pod for TestResult {}
 }
 
 pub mod sk_storage {
@@ -286,3 +276,15 @@ pub mod test_run {
     pub const IF_INDEX: u32 = 1;
     pub const XDP_MODIFY_LEN: usize = 16;
 }
+
+pub mod xdp_metadata {
+    /// Slot holding the return code of `rx_timestamp`.
+    pub const TIMESTAMP_INDEX: u32 = 0;
+    /// Slot holding the return code of `rx_hash`.
+    pub const HASH_INDEX: u32 = 1;
+    /// Slot holding the return code of `rx_vlan_tag`.
+    pub const VLAN_TAG_INDEX: u32 = 2;
+    /// Slot counting the packets seen by the program.
+    pub const PACKETS_INDEX: u32 = 3;
+    pub const RESULTS_LEN: u32 = 4;
+}
@@ -5,9 +5,9 @@
 use aya_ebpf::{
     bindings::sk_action::{SK_DROP, SK_PASS},
     btf_maps::{Array, SockHash as BtfSockHash},
-    macros::{btf_map, map, sk_lookup},
+    macros::{btf_map, map, sk_lookup, sk_msg},
     maps::SockHash as LegacySockHash,
-    programs::SkLookupContext,
+    programs::{SkLookupContext, SkMsgContext},
 };
 #[cfg(not(test))]
 extern crate ebpf_panic;
@@ -38,3 +38,28 @@ macro_rules! define_sk_lookup {
 
 define_sk_lookup!(SOCKETS_LEGACY, sk_lookup_legacy);
 define_sk_lookup!(SOCKETS_BTF, sk_lookup_btf);
+
+/// Mirrors `aya::maps::sock::Ipv4SockKey`.
+#[repr(C)]
+struct Ipv4SockKey {
+    remote_ip4: u32,
+    local_ip4: u32,
+    remote_port: u32,
+    local_port: u32,
+}
+
+#[map(name = "SOCK_PAIRS")]
+static SOCK_PAIRS: LegacySockHash<Ipv4SockKey> = LegacySockHash::with_max_entries(16, 0);
+
+#[sk_msg]
+fn redirect_to_peer(ctx: SkMsgContext) -> u32 {
+    let md = unsafe { &*ctx.msg };
+    let key = Ipv4SockKey {
+        remote_ip4: u32::from_be(md.remote_ip4),
+        local_ip4: u32::from_be(md.local_ip4),
+        remote_port: u32::from_be(md.remote_port),
+        local_port: md.local_port,
+    };
+    // The peer of the socket is stored under the key of the socket.
+    SOCK_PAIRS.redirect_msg(&ctx, key, 0) as u32
+}
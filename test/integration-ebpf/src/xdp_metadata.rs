@@ -0,0 +1,42 @@
+#![no_std]
+#![no_main]
+#![expect(unused_crate_dependencies, reason = "used in other bins")]
+
+use aya_ebpf::{
+    bindings::xdp_action,
+    macros::{map, xdp},
+    maps::Array,
+    programs::XdpContext,
+};
+use integration_common::xdp_metadata::{
+    HASH_INDEX, PACKETS_INDEX, RESULTS_LEN, TIMESTAMP_INDEX, VLAN_TAG_INDEX,
+};
+#[cfg(not(test))]
+extern crate ebpf_panic;
+
+#[map]
+static RESULTS: Array<i32> = Array::with_max_entries(RESULTS_LEN, 0);
+
+fn set(index: u32, value: i32) {
+    if let Some(ptr) = RESULTS.get_ptr_mut(index) {
+        unsafe { *ptr = value }
+    }
+}
+
+fn code<T>(result: Result<T, i32>) -> i32 {
+    match result {
+        Ok(_) => 0,
+        Err(ret) => ret,
+    }
+}
+
+#[xdp]
+fn xdp_metadata(ctx: XdpContext) -> u32 {
+    set(TIMESTAMP_INDEX, code(ctx.rx_timestamp()));
+    set(HASH_INDEX, code(ctx.rx_hash()));
+    set(VLAN_TAG_INDEX, code(ctx.rx_vlan_tag()));
+    if let Some(ptr) = RESULTS.get_ptr_mut(PACKETS_INDEX) {
+        unsafe { *ptr += 1 }
+    }
+    xdp_action::XDP_PASS
+}
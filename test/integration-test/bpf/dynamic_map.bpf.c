@@ -0,0 +1,37 @@
+// clang-format off
+#include <vmlinux.h>
+#include <bpf/bpf_helpers.h>
+// clang-format on
+
+struct connection_key {
+  __u32 pid;
+  __u16 port;
+  __u16 family;
+};
+
+struct connection {
+  char comm[16];
+  __u64 count;
+  union {
+    __u32 ipv4;
+    __u8 ipv6[16];
+  };
+  __u32 flags : 4;
+  __u32 state : 28;
+};
+
+struct {
+  __uint(type, BPF_MAP_TYPE_HASH);
+  __type(key, struct connection_key);
+  __type(value, struct connection);
+  __uint(max_entries, 16);
+} connections SEC(".maps");
+
+struct {
+  __uint(type, BPF_MAP_TYPE_PERCPU_HASH);
+  __type(key, __u32);
+  __type(value, __u64);
+  __uint(max_entries, 16);
+} per_cpu_counts SEC(".maps");
+
+char _license[] SEC("license") = "Dual MIT/GPL";
@@ -0,0 +1,47 @@
+// clang-format off
+#include <vmlinux.h>
+#include <bpf/bpf_helpers.h>
+// clang-format on
+
+// The programs, maps and types are named after Rust keywords, and the `ebpf` map after the
+// field of the skeleton holding the object, so that the generated skeleton has to rename them.
+
+struct self {
+  __u32 type;
+  __u8 super;
+  __u64 crate;
+  union {
+    __u32 ipv4;
+    __u8 ipv6[16];
+  };
+  __u32 flags : 4;
+  __u32 state : 28;
+};
+
+struct {
+  __uint(type, BPF_MAP_TYPE_HASH);
+  __type(key, __u32);
+  __type(value, struct self);
+  __uint(max_entries, 1);
+} ebpf SEC(".maps");
+
+struct {
+  __uint(type, BPF_MAP_TYPE_ARRAY);
+  __type(key, __u32);
+  __type(value, __u64);
+  __uint(max_entries, 1);
+} type SEC(".maps");
+
+volatile const __u64 increment = 1;
+
+SEC("xdp")
+int crate(struct xdp_md *ctx) {
+  __u32 key = 0;
+  __u64 *value = bpf_map_lookup_elem(&type, &key);
+  if (value) {
+    __sync_fetch_and_add(value, increment);
+  }
+  return XDP_PASS;
+}
+
+char _license[] SEC("license") = "GPL";
@@ -30,6 +30,7 @@ use xtask::{AYA_BUILD_INTEGRATION_BPF, LIBBPF_DIR, exec, install_libbpf_headers_
 /// runtime because the stubs are inadequate for actually running the tests.
 fn main() -> Result<()> {
     println!("cargo:rerun-if-env-changed={AYA_BUILD_INTEGRATION_BPF}");
+    println!("cargo::rustc-check-cfg=cfg(skeleton)");
 
     // TODO(https://github.com/rust-lang/cargo/issues/4001): generalize this and move it to
     // aya-build if we can determine that we're in a check build.
@@ -81,6 +82,8 @@ fn main() -> Result<()> {
         ("iter.bpf.c", true),
         ("main.bpf.c", false),
         ("multimap-btf.bpf.c", false),
+        ("dynamic_map.bpf.c", false),
+        ("skeleton.bpf.c", false),
         ("ringbuf-btf.bpf.c", true),
         ("enum_signed_32_checked_variants_reloc.bpf.c", true),
         ("enum_signed_32_reloc.bpf.c", true),
@@ -228,6 +231,14 @@ fn main() -> Result<()> {
             }
         }
 
+        // The skeleton can't be generated from the stubs, so its test is only built here.
+        aya_build::write_skeleton(
+            &out_dir.join("skeleton.bpf.o"),
+            "skeleton",
+            &out_dir.join("skeleton.rs"),
+        )?;
+        println!("cargo:rustc-cfg=skeleton");
+
         let Package {
             name,
             manifest_path,
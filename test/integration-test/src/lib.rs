@@ -11,6 +11,8 @@ bpf_file!(
     ITER_TASK => "iter.bpf.o",
     MAIN => "main.bpf.o",
     MULTIMAP_BTF => "multimap-btf.bpf.o",
+    DYNAMIC_MAP => "dynamic_map.bpf.o",
+    SKELETON => "skeleton.bpf.o",
     RINGBUF_BTF => "ringbuf-btf.bpf.o",
     KSYMS => "ksyms.bpf.o",
     KSYMS_STRONG => "ksyms_strong.bpf.o",
@@ -88,6 +90,7 @@ bpf_file!(
     STACK_TRACE_LSM => "stack_trace_lsm",
     BTF_MAP_OF_MAPS => "btf_map_of_maps",
     XSK_MAP => "xsk_map",
+    XDP_METADATA => "xdp_metadata",
     INODE_STORAGE => "inode_storage",
     CGRP_STORAGE => "cgrp_storage",
     UPROBE_MULTI => "uprobe_multi",
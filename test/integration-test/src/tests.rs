@@ -31,16 +31,19 @@ mod btf_relocations;
 mod cgroup_array;
 mod cgroup_storage;
 mod cgrp_storage;
+mod dynamic_map;
 mod elf;
 mod feature_probe;
 mod fexit;
 mod hash_map;
 mod info;
 mod inode_storage;
+mod interface_watcher;
 mod iter;
 mod kprobe;
 mod ksyms;
 mod linear_data_structures;
+mod linker;
 mod load;
 mod log;
 mod lpm_trie;
@@ -60,7 +63,10 @@ mod ring_buf;
 mod sk_lookup;
 mod sk_reuseport;
 mod sk_storage;
+#[cfg(skeleton)]
+mod skeleton;
 mod smoke;
+mod sock_pairs;
 mod socket_filter;
 mod stack_trace;
 mod stack_trace_lsm;
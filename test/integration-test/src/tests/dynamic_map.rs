@@ -0,0 +1,93 @@
+use assert_matches::assert_matches;
+use aya::{
+    BtfError, BtfField, BtfValue, Ebpf,
+    maps::{DynamicMap, MapError},
+    util::nr_cpus,
+};
+
+fn field(name: &str, value: BtfValue) -> BtfField {
+    BtfField {
+        name: name.into(),
+        value,
+    }
+}
+
+fn connection_key(pid: u32) -> BtfValue {
+    BtfValue::Struct(vec![field("pid", BtfValue::Uint(pid.into()))])
+}
+
+#[test_log::test]
+fn dynamic_map_struct_values() {
+    let mut bpf = Ebpf::load(crate::DYNAMIC_MAP).unwrap();
+    let mut connections = DynamicMap::try_from(bpf.map_mut("connections").unwrap()).unwrap();
+    assert!(connections.btf().is_some());
+
+    // Members that aren't given are zeroed.
+    let value = BtfValue::Struct(vec![
+        field("comm", BtfValue::String("aya".into())),
+        field("count", BtfValue::Uint(1)),
+        field("ipv4", BtfValue::Uint(0x7f00_0001)),
+        field("state", BtfValue::Uint(3)),
+    ]);
+    connections.insert(&connection_key(1), &value, 0).unwrap();
+
+    let mut value = connections.get(&connection_key(1), 0).unwrap();
+    assert_eq!(value.field("comm"), Some(&BtfValue::String("aya".into())));
+    assert_eq!(value.field("count"), Some(&BtfValue::Uint(1)));
+    assert_eq!(value.field("ipv4"), Some(&BtfValue::Uint(0x7f00_0001)));
+    assert_eq!(value.field("flags"), Some(&BtfValue::Uint(0)));
+    assert_eq!(value.field("state"), Some(&BtfValue::Uint(3)));
+
+    *value.field_mut("count").unwrap() = BtfValue::Uint(2);
+    connections.insert(&connection_key(1), &value, 0).unwrap();
+
+    let entries = connections.iter().collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(
+        entries[0].key.to_string(),
+        "{.pid = 1, .port = 0, .family = 0}"
+    );
+    assert_eq!(entries[0].value.field("count"), Some(&BtfValue::Uint(2)));
+
+    assert_matches!(
+        connections.insert(
+            &connection_key(1),
+            &BtfValue::Struct(vec![field("nope", BtfValue::Uint(0))]),
+            0,
+        ),
+        Err(MapError::Btf(BtfError::UnknownField { name, .. })) if name == "nope"
+    );
+    assert_matches!(
+        connections.insert(
+            &connection_key(1),
+            &BtfValue::Struct(vec![field("state", BtfValue::Uint(1 << 28))]),
+            0,
+        ),
+        Err(MapError::Btf(BtfError::ValueOutOfRange { .. }))
+    );
+
+    connections.remove(&connection_key(1)).unwrap();
+    assert_matches!(
+        connections.get(&connection_key(1), 0),
+        Err(MapError::KeyNotFound)
+    );
+}
+
+#[test_log::test]
+fn dynamic_map_per_cpu_values() {
+    let mut bpf = Ebpf::load(crate::DYNAMIC_MAP).unwrap();
+    let mut counts = DynamicMap::try_from(bpf.map_mut("per_cpu_counts").unwrap()).unwrap();
+
+    let values = BtfValue::Array(
+        (0..nr_cpus().unwrap() as u128)
+            .map(BtfValue::Uint)
+            .collect(),
+    );
+    counts.insert(&BtfValue::Uint(7), &values, 0).unwrap();
+    assert_eq!(counts.get(&BtfValue::Uint(7), 0).unwrap(), values);
+
+    assert_matches!(
+        counts.insert(&BtfValue::Uint(7), &BtfValue::Array(Vec::new()), 0),
+        Err(MapError::InvalidValueSize { size: 0, .. })
+    );
+}
@@ -0,0 +1,153 @@
+use std::{
+    ffi::CString,
+    os::fd::{AsFd as _, AsRawFd as _},
+};
+
+use assert_matches::assert_matches;
+use aya::{
+    Ebpf,
+    programs::{
+        InterfaceEvent, InterfaceWatcher, SchedClassifier, TcAttachType, WatchId, Xdp, XdpMode,
+    },
+    test_helpers::{NetNsGuard, add_dummy_link, delete_link},
+    util::KernelVersion,
+};
+
+use crate::TCX;
+
+/// Waits until the watcher reports events.
+///
+/// Attaching programs also triggers link notifications, which don't produce events.
+fn next_events(watcher: &mut InterfaceWatcher) -> Vec<InterfaceEvent> {
+    loop {
+        let events = watcher.wait().unwrap();
+        if !events.is_empty() {
+            return events;
+        }
+    }
+}
+
+fn assert_attached(events: &[InterfaceEvent], expected_watch: WatchId, expected_if_index: u32) {
+    assert_matches!(
+        events,
+        [InterfaceEvent::Attached { watch, if_index, .. }]
+            if *watch == expected_watch && *if_index == expected_if_index
+    );
+}
+
+fn assert_detached(events: &[InterfaceEvent], expected_watch: WatchId, expected_if_index: u32) {
+    assert_matches!(
+        events,
+        [InterfaceEvent::Detached { watch, if_index, .. }]
+            if *watch == expected_watch && *if_index == expected_if_index
+    );
+}
+
+#[test_log::test]
+fn xdp_reattach_recreated_interface() {
+    let _netns = NetNsGuard::new().unwrap();
+
+    let mut bpf = Ebpf::load(crate::PASS).unwrap();
+    let mut xdp: Xdp = bpf.take_program("pass").unwrap().try_into().unwrap();
+    xdp.load().unwrap();
+
+    let first = add_dummy_link(c"aya-dummy0").unwrap();
+
+    let mut watcher = InterfaceWatcher::new().unwrap();
+    let watch = watcher.watch_xdp("aya-dummy*", xdp, XdpMode::Skb);
+
+    // Only the existing interface matching the pattern, not `lo`.
+    assert_attached(&watcher.scan().unwrap(), watch, first);
+    assert_eq!(watcher.interfaces(watch), [first]);
+
+    delete_link(first).unwrap();
+    assert_detached(&next_events(&mut watcher), watch, first);
+    assert!(watcher.interfaces(watch).is_empty());
+
+    let second = add_dummy_link(c"aya-dummy0").unwrap();
+    assert_attached(&next_events(&mut watcher), watch, second);
+    assert_eq!(watcher.interfaces(watch), [second]);
+}
+
+#[test_log::test]
+fn xdp_resync_after_overflow() {
+    let _netns = NetNsGuard::new().unwrap();
+
+    let mut bpf = Ebpf::load(crate::PASS).unwrap();
+    let mut xdp: Xdp = bpf.take_program("pass").unwrap().try_into().unwrap();
+    xdp.load().unwrap();
+
+    let first = add_dummy_link(c"aya-dummy0").unwrap();
+
+    let mut watcher = InterfaceWatcher::new().unwrap();
+    let watch = watcher.watch_xdp("aya-dummy*", xdp, XdpMode::Skb);
+    assert_attached(&watcher.scan().unwrap(), watch, first);
+
+    // Shrink the socket buffer to its minimum so that the notifications below overflow it.
+    let size: libc::c_int = 0;
+    let ret = unsafe {
+        libc::setsockopt(
+            watcher.as_fd().as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_RCVBUF,
+            (&raw const size).cast(),
+            size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    assert_eq!(ret, 0, "setsockopt: {}", std::io::Error::last_os_error());
+
+    delete_link(first).unwrap();
+    let mut created: Vec<_> = (1..=16)
+        .map(|i| add_dummy_link(&CString::new(format!("aya-dummy{i}")).unwrap()).unwrap())
+        .collect();
+    created.sort_unstable();
+
+    // The lost notifications are recovered from the current interfaces.
+    let events = watcher.wait().unwrap();
+    assert_matches!(
+        events.as_slice(),
+        [InterfaceEvent::Detached { watch: detached, interface, if_index }, ..]
+            if *detached == watch && interface == "aya-dummy0" && *if_index == first
+    );
+    let mut attached: Vec<_> = events[1..]
+        .iter()
+        .map(|event| {
+            assert_matches!(
+                event,
+                InterfaceEvent::Attached { watch: attached, if_index, .. }
+                    if *attached == watch => *if_index
+            )
+        })
+        .collect();
+    attached.sort_unstable();
+    assert_eq!(attached, created);
+    assert_eq!(watcher.interfaces(watch), created);
+}
+
+#[test_log::test]
+fn sched_classifier_reattach_recreated_interface() {
+    if !KernelVersion::at_least(6, 6, 0) {
+        eprintln!("skipping test - TCX not supported");
+        return;
+    }
+
+    let _netns = NetNsGuard::new().unwrap();
+
+    let mut bpf = Ebpf::load(TCX).unwrap();
+    let mut prog: SchedClassifier = bpf.take_program("tcx_next").unwrap().try_into().unwrap();
+    prog.load().unwrap();
+
+    let mut watcher = InterfaceWatcher::new().unwrap();
+    let watch = watcher.watch_sched_classifier("aya-dummy?", prog, TcAttachType::Ingress);
+    assert_matches!(watcher.scan().unwrap().as_slice(), []);
+
+    let first = add_dummy_link(c"aya-dummy0").unwrap();
+    assert_attached(&next_events(&mut watcher), watch, first);
+
+    delete_link(first).unwrap();
+    assert_detached(&next_events(&mut watcher), watch, first);
+
+    let second = add_dummy_link(c"aya-dummy1").unwrap();
+    assert_attached(&next_events(&mut watcher), watch, second);
+    assert_eq!(watcher.interfaces(watch), [second]);
+}
@@ -0,0 +1,24 @@
+use aya::{
+    EbpfLoader,
+    programs::{SocketFilter, TracePoint},
+};
+use aya_obj::Object;
+
+#[test_log::test]
+fn link_objects() {
+    let objects = [crate::SIMPLE_PROG, crate::TWO_PROGS].map(|data| Object::parse(data).unwrap());
+    let object = Object::link(objects).unwrap();
+    assert_eq!(object.programs.len(), 3);
+
+    // The kernel checks the merged BTF and the function and line info rebased on it.
+    let mut bpf = EbpfLoader::new().load_object(object).unwrap();
+
+    let prog: &mut SocketFilter = bpf.program_mut("simple_prog").unwrap().try_into().unwrap();
+    prog.load().unwrap();
+
+    for name in ["test_tracepoint_one", "test_tracepoint_two"] {
+        let prog: &mut TracePoint = bpf.program_mut(name).unwrap().try_into().unwrap();
+        prog.load().unwrap();
+        prog.attach("sched", "sched_switch").unwrap();
+    }
+}
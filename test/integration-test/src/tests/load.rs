@@ -4,8 +4,8 @@ use std::{
 
 use assert_matches::assert_matches;
 use aya::{
-    Ebpf,
-    maps::{Array, RingBuf},
+    Ebpf, EbpfError, EbpfLoader,
+    maps::{Array, MapError, RingBuf},
     pin::PinError,
     programs::{
         FlowDissector, KProbe, LinkOrder, ProbeKind, Program, ProgramError, SchedClassifier,
@@ -107,6 +107,50 @@ fn multiple_btf_maps() {
     remove_file(map_pin).unwrap();
 }
 
+#[test_log::test]
+fn reuse_exported_maps() {
+    let mut bpf = Ebpf::load(crate::MULTIMAP_BTF).unwrap();
+    let mut map_1: Array<_, u64> = bpf.map_mut("map_1").unwrap().try_into().unwrap();
+    map_1.set(0, 7, 0).unwrap();
+    let maps = bpf.export_maps().unwrap();
+    drop(bpf);
+
+    let mut bpf = EbpfLoader::new()
+        .reuse_exported_maps(&maps)
+        .load(crate::MULTIMAP_BTF)
+        .unwrap();
+    assert_matches!(bpf.incompatible_maps(), []);
+    let map_1: Array<_, u64> = bpf.take_map("map_1").unwrap().try_into().unwrap();
+    assert_eq!(map_1.get(&0, 0).unwrap(), 7);
+    drop(bpf);
+
+    // A reused map keeps its own max_entries.
+    let mut bpf = EbpfLoader::new()
+        .map_max_entries("map_1", 4)
+        .reuse_exported_maps(&maps)
+        .load(crate::MULTIMAP_BTF)
+        .unwrap();
+    assert_matches!(bpf.incompatible_maps(), []);
+    let map_1: Array<_, u64> = bpf.take_map("map_1").unwrap().try_into().unwrap();
+    assert_eq!(map_1.len(), 1);
+    assert_eq!(map_1.get(&0, 0).unwrap(), 7);
+
+    // A map reused explicitly must match the definition in the object.
+    let ring_buf = Ebpf::load(crate::RING_BUF).unwrap();
+    let ring_buf = ring_buf.export_maps().unwrap();
+    assert_matches!(
+        EbpfLoader::new()
+            .reuse_map("map_1", ring_buf.get("RING_BUF").unwrap())
+            .load(crate::MULTIMAP_BTF),
+        Err(EbpfError::MapError(MapError::IncompatibleMap {
+            field: "type",
+            ..
+        }))
+    );
+
+    remove_file("/sys/fs/bpf/map_pin_by_name").unwrap();
+}
+
 #[test_log::test]
 fn pin_lifecycle_multiple_btf_maps() {
     let mut bpf = Ebpf::load(crate::MULTIMAP_BTF).unwrap();
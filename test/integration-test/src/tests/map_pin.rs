@@ -1,8 +1,9 @@
 use std::path::Path;
 
+use assert_matches::assert_matches;
 use aya::{
-    Ebpf,
-    maps::{HashMap, Map, MapData, MapType},
+    Ebpf, EbpfError, EbpfLoader,
+    maps::{HashMap, Map, MapData, MapError, MapMigration, MapType},
     programs::{ProgramType, SocketFilter},
     sys::{is_map_supported, is_program_supported},
 };
@@ -58,3 +59,51 @@ fn pin_and_reopen_hashmap() {
     assert_eq!(hash_from_bpf.get(&0, 0).unwrap(), 2);
     assert_eq!(hash_from_pin.get(&0, 0).unwrap(), 2);
 }
+
+#[test_log::test]
+fn migrate_pinned_map() {
+    let mut rng = rand::rng();
+    let pin_path = Path::new("/sys/fs/bpf/")
+        .join(format!("test_migrate_pinned_map_{:x}", rng.random::<u64>()));
+
+    // Pin an array where the object expects a hash map.
+    let bpf = Ebpf::load(crate::MULTIMAP_BTF).unwrap();
+    bpf.map("map_1").unwrap().pin(&pin_path).unwrap();
+    defer! {
+        std::fs::remove_file(&pin_path).unwrap();
+    }
+    std::fs::remove_file("/sys/fs/bpf/map_pin_by_name").unwrap();
+
+    assert_matches!(
+        EbpfLoader::new()
+            .map_pin_path("BAR", &pin_path)
+            .map_migration("BAR", MapMigration::Error)
+            .load(crate::MAP_TEST),
+        Err(EbpfError::MapError(MapError::IncompatibleMap {
+            field: "type",
+            ..
+        }))
+    );
+    assert_matches!(
+        MapData::from_pin(&pin_path)
+            .unwrap()
+            .info()
+            .unwrap()
+            .map_type(),
+        Ok(MapType::Array)
+    );
+
+    let bpf = EbpfLoader::new()
+        .map_pin_path("BAR", &pin_path)
+        .map_migration("BAR", MapMigration::Replace)
+        .load(crate::MAP_TEST)
+        .unwrap();
+    assert!(!Path::new(&format!("{}.new", pin_path.display())).exists());
+    let pinned = MapData::from_pin(&pin_path).unwrap();
+    assert_matches!(pinned.info().unwrap().map_type(), Ok(MapType::Hash));
+    let mut bar: HashMap<_, u32, u8> =
+        HashMap::try_from(Map::from_map_data(pinned).unwrap()).unwrap();
+    bar.insert(0, 1, 0).unwrap();
+    let bar: HashMap<_, u32, u8> = HashMap::try_from(bpf.map("BAR").unwrap()).unwrap();
+    assert_eq!(bar.get(&0, 0).unwrap(), 1);
+}
@@ -1,6 +1,6 @@
 use aya::{
-    EbpfLoader,
-    maps::{Array, MapType, PerCpuArray, PerCpuValues},
+    EbpfLoader, Pod,
+    maps::{Aggregate, Array, MapType, PerCpuArray, PerCpuValues},
     programs::{UProbe, uprobe::UProbeScope},
     sys::is_map_supported,
     util::nr_cpus,
@@ -77,7 +77,8 @@ fn per_cpu_array_basic(
             .unwrap_or_else(|err| panic!("attach {prog_name}: {err}"));
     }
 
-    let mut array: PerCpuArray<_, u32> = bpf.take_map(array_map).unwrap().try_into().unwrap();
+    let mut map = bpf.take_map(array_map).unwrap();
+    let mut array = PerCpuArray::<_, u32>::try_from(&mut map).unwrap();
     let result = Array::<_, u32>::try_from(bpf.map(result_map).unwrap()).unwrap();
 
     const INDEX: u32 = 0;
@@ -113,4 +114,30 @@ fn per_cpu_array_basic(
         }
     }
     assert_eq!(new_count, 1, "set() should mutate exactly one per-CPU slot");
+
+    // Single CPU lookups and aggregates agree with the full lookup.
+    for (cpu, slot) in after.iter().enumerate() {
+        assert_eq!(array.get_cpu(&INDEX, cpu as u32, 0).unwrap(), *slot);
+    }
+    assert_eq!(
+        after.sum(),
+        VALUE.wrapping_mul(cpu_count as u32 - 1) + NEW_VALUE
+    );
+    assert_eq!(after.min(), NEW_VALUE);
+    let snapshot = array.snapshot(0).unwrap();
+    assert_eq!(snapshot.len(), 1);
+    assert_eq!(**snapshot[0], **after);
+
+    // Structs deriving `Aggregate` combine the per-CPU values field by field.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Pod, Aggregate)]
+    #[repr(C)]
+    struct Slot {
+        value: u32,
+    }
+
+    let array = PerCpuArray::<_, Slot>::try_from(&map).unwrap();
+    let slots = array.get(&INDEX, 0).unwrap();
+    assert_eq!(slots.sum(), Slot { value: after.sum() });
+    assert_eq!(slots.min(), Slot { value: after.min() });
+    assert_eq!(slots.max(), Slot { value: after.max() });
 }
@@ -8,7 +8,7 @@ use std::{
 use aya::{
     Ebpf, EbpfLoader,
     maps::{Array, MapType, SockHash, SockMap},
-    programs::{ProgramType, SkLookup},
+    programs::{NetNs, NetNsError, ProgramError, ProgramType, SkLookup},
     sys::{is_map_supported, is_program_supported},
     test_helpers::NetNsGuard,
 };
@@ -161,3 +161,33 @@ fn redirect_sk_lookup_miss_propagates_enoent(
         .expect("LAST_ERRNO is not an Array");
     assert_eq!(last_errno.get(&0, 0).unwrap(), -ENOENT);
 }
+
+/// Verify that [`SkLookup::attach`] accepts network namespaces other than the
+/// current one and rejects file descriptors which aren't network namespaces.
+#[test_log::test]
+fn attach_sk_lookup_netns() {
+    if !is_program_supported(ProgramType::SkLookup).unwrap() {
+        eprintln!("skipping test - sk_lookup not supported");
+        return;
+    }
+
+    let host = NetNs::current().unwrap();
+    let _netns = NetNsGuard::new().unwrap();
+
+    let mut bpf = EbpfLoader::new().load(crate::SOCK_HASH).unwrap();
+    let prog: &mut SkLookup = bpf
+        .program_mut("sk_lookup_legacy")
+        .unwrap()
+        .try_into()
+        .unwrap();
+    prog.load().unwrap();
+
+    let link_id = prog.attach(&host).unwrap();
+    prog.detach(link_id).unwrap();
+
+    let file = std::fs::File::open("/proc/self/exe").unwrap();
+    assert!(matches!(
+        prog.attach(&file),
+        Err(ProgramError::NetNsError(NetNsError::NotNetNs))
+    ));
+}
@@ -0,0 +1,49 @@
+use std::mem;
+
+use aya::{TestRunOptions, programs::TestRun as _, util::KernelVersion};
+
+/// The skeleton generated by `build.rs` from `skeleton.bpf.c`.
+mod skel {
+    #![expect(dead_code, reason = "the tests don't use all of the skeleton")]
+
+    include!(concat!(env!("OUT_DIR"), "/skeleton.rs"));
+}
+
+/// Verify that the generated skeleton compiles, renaming the programs, maps, types and fields
+/// named after Rust keywords, and loads the object it was generated from.
+#[test_log::test]
+fn skeleton() {
+    // BPF_PROG_TEST_RUN supports XDP programs since Linux 4.12.
+    if KernelVersion::current().unwrap() < KernelVersion::new(4, 12, 0) {
+        eprintln!("skipping test, XDP test runs require Linux 4.12");
+        return;
+    }
+
+    let mut skel = skel::SkeletonBuilder::new()
+        .set_increment(&2)
+        .load(crate::SKELETON)
+        .unwrap();
+
+    let value = skel::Self_ {
+        r#type: 1,
+        super_: 2,
+        crate_: 3,
+        // Zeroes the anonymous union, the bitfields and the padding.
+        ..unsafe { mem::zeroed() }
+    };
+    skel.ebpf_2.insert(0, value, 0).unwrap();
+    let value = skel.ebpf_2.get(&0, 0).unwrap();
+    assert_eq!((value.r#type, value.super_, value.crate_), (1, 2, 3));
+
+    skel.crate_.load().unwrap();
+    let data = [0u8; 64];
+    let result = skel
+        .crate_
+        .test_run(TestRunOptions {
+            data_in: Some(&data),
+            ..TestRunOptions::default()
+        })
+        .unwrap();
+    assert_eq!(result.return_value, 2, "Expected XDP_PASS(2)");
+    assert_eq!(skel.r#type.get(&0, 0).unwrap(), 2);
+}
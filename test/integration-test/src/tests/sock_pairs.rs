@@ -0,0 +1,131 @@
+use std::{
+    io::{Read as _, Write as _},
+    net::{TcpListener, TcpStream},
+    thread,
+    time::{Duration, Instant},
+};
+
+use assert_matches::assert_matches;
+use aya::{
+    Ebpf,
+    maps::{
+        SockHash,
+        sock::{Ipv4SockKey, SockPairs, SockPairsError},
+    },
+    programs::SkMsg,
+    test_helpers::NetNsGuard,
+    util::KernelVersion,
+};
+
+/// Returns a connected socket and the socket accepted by `listener`.
+fn connect(listener: &TcpListener) -> (TcpStream, TcpStream) {
+    let connected = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (accepted, _) = listener.accept().unwrap();
+    for socket in [&connected, &accepted] {
+        socket
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+    }
+    (connected, accepted)
+}
+
+#[test_log::test]
+fn sk_msg_redirect_to_peer() {
+    let _netns = NetNsGuard::new().unwrap();
+
+    let mut bpf = Ebpf::load(crate::SOCK_HASH).unwrap();
+    let map: SockHash<_, Ipv4SockKey> = bpf.take_map("SOCK_PAIRS").unwrap().try_into().unwrap();
+    let prog: &mut SkMsg = bpf
+        .program_mut("redirect_to_peer")
+        .unwrap()
+        .try_into()
+        .unwrap();
+    prog.load().unwrap();
+    prog.attach(map.fd()).unwrap();
+    let mut pairs = SockPairs::new(map);
+
+    // client -> proxy -> upstream server, all over the loopback interface.
+    let proxy = TcpListener::bind("127.0.0.1:0").unwrap();
+    let (mut client, accepted) = connect(&proxy);
+    let upstream = TcpListener::bind("127.0.0.1:0").unwrap();
+    let (connected, mut server) = connect(&upstream);
+
+    // Keep handles to write from the proxy side, the sockets are owned by `pairs`.
+    let mut accepted_writer = accepted.try_clone().unwrap();
+    let mut connected_writer = connected.try_clone().unwrap();
+    let id = pairs.insert(accepted, connected).unwrap();
+    assert_eq!(pairs.map().keys().count(), 2);
+
+    // Data sent on a socket is redirected to its peer.
+    accepted_writer.write_all(b"ping").unwrap();
+    let mut buf = [0; 4];
+    server.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"ping");
+
+    connected_writer.write_all(b"pong").unwrap();
+    client.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"pong");
+
+    let stats = pairs.stats(id).unwrap();
+    assert_eq!(stats.accepted.receive_queue_bytes, 0);
+    assert_eq!(stats.connected.receive_queue_bytes, 0);
+    if KernelVersion::at_least(4, 19, 0) {
+        assert!(stats.accepted.tcp_bytes_sent >= 4, "{stats:?}");
+        assert!(stats.connected.tcp_bytes_sent >= 4, "{stats:?}");
+    }
+
+    // Closing the client closes the pair.
+    assert!(pairs.remove_closed().unwrap().is_empty());
+    drop(client);
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let closed = pairs.remove_closed().unwrap();
+        if !closed.is_empty() {
+            assert_eq!(closed, [id]);
+            break;
+        }
+        assert!(Instant::now() < deadline, "the pair wasn't closed");
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(pairs.ids().count(), 0);
+    assert_eq!(pairs.map().keys().count(), 0);
+}
+
+#[test_log::test]
+fn remove_returns_sockets() {
+    let _netns = NetNsGuard::new().unwrap();
+
+    let mut bpf = Ebpf::load(crate::SOCK_HASH).unwrap();
+    let map: SockHash<_, Ipv4SockKey> = bpf.take_map("SOCK_PAIRS").unwrap().try_into().unwrap();
+    let mut pairs = SockPairs::new(map);
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let (connected, accepted) = connect(&listener);
+    let (accepted_port, connected_port) = (
+        accepted.local_addr().unwrap().port(),
+        connected.local_addr().unwrap().port(),
+    );
+    let id = pairs.insert(accepted, connected).unwrap();
+
+    let (accepted_key, connected_key) = pairs.keys(id).unwrap();
+    assert_eq!(accepted_key.local_port, u32::from(accepted_port));
+    assert_eq!(connected_key.local_port, u32::from(connected_port));
+    let keys: Vec<_> = pairs.map().keys().map(Result::unwrap).collect();
+    assert_eq!(keys.len(), 2);
+    assert!(keys.contains(&accepted_key), "{keys:?}");
+    assert!(keys.contains(&connected_key), "{keys:?}");
+
+    let (accepted, connected) = pairs.remove(id).unwrap();
+    assert_eq!(accepted.local_addr().unwrap().port(), accepted_port);
+    assert_eq!(connected.local_addr().unwrap().port(), connected_port);
+    assert_eq!(pairs.map().keys().count(), 0);
+
+    assert_matches!(
+        pairs.remove(id),
+        Err(SockPairsError::UnknownPair { id: unknown }) if unknown == id
+    );
+    assert_matches!(
+        pairs.stats(id),
+        Err(SockPairsError::UnknownPair { id: unknown }) if unknown == id
+    );
+}
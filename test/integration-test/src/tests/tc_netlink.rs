@@ -1,8 +1,13 @@
+use std::os::fd::AsFd as _;
+
 use aya::{
     Ebpf,
     programs::{
-        SchedClassifier, TcAttachType,
-        tc::{NlOptions, TcAttachOptions, TcHandle, qdisc_add_clsact},
+        NetNs, SchedClassifier, TcAttachType,
+        tc::{
+            NlOptions, Qdisc, SchedClassifierLink, TcAttachOptions, TcHandle, qdisc_add,
+            qdisc_add_clsact, qdisc_add_in_netns,
+        },
     },
     test_helpers::NetNsGuard,
     util::KernelVersion,
@@ -58,10 +63,7 @@ fn netlink_attach_to_link_preserves_classid() {
         .attach_with_options(
             "lo",
             TcAttachType::Ingress,
-            TcAttachOptions::Netlink(NlOptions {
-                classid: Some(classid),
-                ..Default::default()
-            }),
+            TcAttachOptions::Netlink(NlOptions::default().classid(Some(classid))),
         )
         .unwrap();
 
@@ -122,13 +124,190 @@ fn netlink_attach_preserves_explicit_handle() {
         .attach_with_options(
             "lo",
             TcAttachType::Ingress,
-            TcAttachOptions::Netlink(NlOptions {
-                handle,
-                ..Default::default()
-            }),
+            TcAttachOptions::Netlink(NlOptions::default().handle(handle)),
         )
         .unwrap();
 
     let link = prog.take_link(link_id).unwrap();
     assert_eq!(link.handle().unwrap(), handle);
 }
+
+/// Verify that [`SchedClassifier::query_netlink`] reports the filters of all
+/// chains with their program ids, and that a listed filter can be replaced
+/// atomically with [`SchedClassifier::attach_to_link`].
+#[test_log::test]
+fn netlink_query_and_replace_filters() {
+    if !cls_bpf_autoloads() {
+        return;
+    }
+
+    let _netns = NetNsGuard::new().unwrap();
+
+    qdisc_add_clsact("lo").unwrap();
+
+    let mut bpf = Ebpf::load(TCX).unwrap();
+    let prog: &mut SchedClassifier = bpf.program_mut("tcx_next").unwrap().try_into().unwrap();
+    prog.load().unwrap();
+    let prog_id = prog.info().unwrap().id();
+
+    let mut links = Vec::new();
+    for (chain, direct_action) in [(0, true), (3, false)] {
+        let link_id = prog
+            .attach_with_options(
+                "lo",
+                TcAttachType::Ingress,
+                TcAttachOptions::Netlink(
+                    NlOptions::default()
+                        .priority(10)
+                        .chain(chain)
+                        .direct_action(direct_action)
+                        .classid(Some(TcHandle::new(1, 1))),
+                ),
+            )
+            .unwrap();
+        let link = prog.take_link(link_id).unwrap();
+        assert_eq!(link.chain().unwrap(), chain);
+        assert_eq!(link.direct_action().unwrap(), direct_action);
+        // Keep the filters attached, the links detach them on drop.
+        links.push(link);
+    }
+
+    let mut filters = SchedClassifier::query_netlink("lo", TcAttachType::Ingress).unwrap();
+    filters.sort_by_key(|filter| filter.chain());
+    let summary: Vec<_> = filters
+        .iter()
+        .map(|filter| {
+            (
+                filter.chain(),
+                filter.priority(),
+                filter.direct_action(),
+                filter.classid(),
+                filter.program_id(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            (0, 10, true, Some(TcHandle::new(1, 1)), Some(prog_id)),
+            (3, 10, false, Some(TcHandle::new(1, 1)), Some(prog_id)),
+        ]
+    );
+    assert!(
+        SchedClassifier::query_netlink("lo", TcAttachType::Egress)
+            .unwrap()
+            .is_empty()
+    );
+
+    let mut new_bpf = Ebpf::load(TCX).unwrap();
+    let new_prog: &mut SchedClassifier =
+        new_bpf.program_mut("tcx_next").unwrap().try_into().unwrap();
+    new_prog.load().unwrap();
+    let new_prog_id = new_prog.info().unwrap().id();
+
+    let filter = &filters[1];
+    let link_id = new_prog
+        .attach_to_link(SchedClassifierLink::from_filter(filter))
+        .unwrap();
+
+    let replaced: Vec<_> = SchedClassifier::query_netlink("lo", TcAttachType::Ingress)
+        .unwrap()
+        .into_iter()
+        .filter(|f| f.chain() == 3)
+        .collect();
+    assert_eq!(replaced.len(), 1);
+    assert_eq!(replaced[0].handle(), filter.handle());
+    assert!(!replaced[0].direct_action());
+    assert_eq!(replaced[0].program_id(), Some(new_prog_id));
+
+    new_prog.detach(link_id).unwrap();
+    let remaining = SchedClassifier::query_netlink("lo", TcAttachType::Ingress).unwrap();
+    assert_eq!(remaining.len(), 1);
+    assert_eq!(remaining[0].chain(), 0);
+}
+
+/// Verify that programs can be attached to the filters of the `ingress` qdisc.
+#[test_log::test]
+fn netlink_attach_ingress_qdisc() {
+    if !cls_bpf_autoloads() {
+        return;
+    }
+
+    let _netns = NetNsGuard::new().unwrap();
+
+    qdisc_add("lo", Qdisc::Ingress).unwrap();
+
+    let mut bpf = Ebpf::load(TCX).unwrap();
+    let prog: &mut SchedClassifier = bpf.program_mut("tcx_next").unwrap().try_into().unwrap();
+    prog.load().unwrap();
+
+    let attach_type = Qdisc::Ingress.filter_parent();
+    let link_id = prog
+        .attach_with_options(
+            "lo",
+            attach_type,
+            TcAttachOptions::Netlink(NlOptions::default()),
+        )
+        .unwrap();
+
+    let filters = SchedClassifier::query_netlink("lo", attach_type).unwrap();
+    assert_eq!(filters.len(), 1);
+
+    prog.detach(link_id).unwrap();
+    assert!(
+        SchedClassifier::query_netlink("lo", attach_type)
+            .unwrap()
+            .is_empty()
+    );
+}
+
+/// Verify that netlink filters are attached to, and detached from, the
+/// interfaces of the given network namespace rather than the current one.
+#[test_log::test]
+fn netlink_attach_in_netns() {
+    if !cls_bpf_autoloads() {
+        return;
+    }
+
+    let outer = NetNsGuard::new().unwrap();
+    let netns = NetNs::from_fd(outer.as_fd().try_clone_to_owned().unwrap()).unwrap();
+
+    let inner = NetNsGuard::new().unwrap();
+    qdisc_add_in_netns("lo", Qdisc::Clsact, &netns).unwrap();
+
+    let mut bpf = Ebpf::load(TCX).unwrap();
+    let prog: &mut SchedClassifier = bpf.program_mut("tcx_next").unwrap().try_into().unwrap();
+    prog.load().unwrap();
+
+    let link_id = prog
+        .attach_with_options_in_netns(
+            "lo",
+            TcAttachType::Ingress,
+            TcAttachOptions::Netlink(NlOptions::default()),
+            &netns,
+        )
+        .unwrap();
+    // The filter isn't attached to the inner namespace.
+    assert!(
+        SchedClassifier::query_netlink("lo", TcAttachType::Ingress)
+            .unwrap()
+            .is_empty()
+    );
+    drop(inner);
+
+    assert_eq!(
+        SchedClassifier::query_netlink("lo", TcAttachType::Ingress)
+            .unwrap()
+            .len(),
+        1
+    );
+    // Detach from the inner namespace.
+    let inner = NetNsGuard::new().unwrap();
+    prog.detach(link_id).unwrap();
+    drop(inner);
+    assert!(
+        SchedClassifier::query_netlink("lo", TcAttachType::Ingress)
+            .unwrap()
+            .is_empty()
+    );
+}
@@ -1,12 +1,29 @@
-use std::{ffi::CString, net::UdpSocket, num::NonZeroU32, time::Duration};
+use std::{
+    ffi::CString,
+    mem,
+    net::UdpSocket,
+    num::NonZeroU32,
+    os::fd::{AsFd as _, AsRawFd as _, FromRawFd as _, OwnedFd},
+    thread,
+    time::Duration,
+};
 
 use assert_matches::assert_matches;
 use aya::{
     Ebpf,
     maps::{Array, CpuMap, DevMap, DevMapHash, XskMap},
-    programs::{ProgramError, Xdp, XdpError, XdpMode, xdp::XdpLinkId},
-    test_helpers::NetNsGuard,
+    netlink_set_link_up,
+    programs::{NetNs, ProgramError, ProgramType, Xdp, XdpError, XdpMode, xdp::XdpLinkId},
+    sys::is_offload_supported,
+    test_helpers::{NetNsGuard, add_veth_pair},
     util::KernelVersion,
+    xsk::{
+        CompletionQueue, Umem as XskUmem, UmemConfig as XskUmemConfig, XdpDesc, Xsk, XskBindMode,
+        XskConfig, XskError, XskSocket,
+    },
+};
+use integration_common::xdp_metadata::{
+    HASH_INDEX, PACKETS_INDEX, TIMESTAMP_INDEX, VLAN_TAG_INDEX,
 };
 use object::{Object as _, ObjectSection as _, ObjectSymbol as _, SymbolSection};
 use rstest::rstest;
@@ -114,6 +131,404 @@ fn af_xdp(#[case] socks_name: &str, #[case] prog_name: &str) {
     assert_eq!(rx.available(), 2);
 }
 
+#[rstest]
+#[case::legacy("SOCKS", "redirect_sock")]
+#[case::btf("SOCKS_BTF", "redirect_sock_btf")]
+#[test_attr(test_log::test)]
+fn af_xdp_xsk(#[case] socks_name: &str, #[case] prog_name: &str) {
+    let _netns = NetNsGuard::new().unwrap();
+
+    let mut bpf = Ebpf::load(crate::XSK_MAP).unwrap();
+    let mut socks: XskMap<_> = bpf.take_map(socks_name).unwrap().try_into().unwrap();
+
+    let xdp: &mut Xdp = bpf.program_mut(prog_name).unwrap().try_into().unwrap();
+    xdp.load().unwrap();
+    xdp.attach("lo", XdpMode::default()).unwrap();
+
+    let umem = match XskUmem::new(XskUmemConfig {
+        frame_count: 2,
+        ..Default::default()
+    }) {
+        Ok(umem) => umem,
+        Err(XskError::SyscallError(err))
+            if err.io_error.raw_os_error() == Some(libc::EAFNOSUPPORT) =>
+        {
+            eprintln!("skipping test - AF_XDP sockets not available: {err}");
+            return;
+        }
+        Err(err) => panic!("failed to create UMEM: {err}"),
+    };
+    let config = XskConfig {
+        rx_size: 32,
+        ..Default::default()
+    };
+    let mut xsk = XskSocket::bind(&umem, "lo", 0, &config).unwrap();
+    socks.set_socket(&xsk.socket, 0).unwrap();
+
+    // Produce two frames to be filled by the kernel.
+    let frames: Vec<u64> = umem.frames().collect();
+    assert_eq!(xsk.fill.as_mut().unwrap().produce(&frames), 2);
+
+    let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let port = sock.local_addr().unwrap().port();
+    sock.send_to(b"hello AF_XDP", "127.0.0.1:1777").unwrap();
+
+    let mut descs = [XdpDesc::default(); 2];
+    assert_eq!(xsk.rx.consume(&mut descs), 1);
+    // SAFETY: the frame was returned by the RX queue and isn't given back to the kernel.
+    let buf = unsafe { umem.data(&descs[0]) }.unwrap();
+
+    let (eth, buf) = buf.split_at(14);
+    assert_eq!(eth[12..14], [0x08, 0x00]); // IP
+    let (ip, buf) = buf.split_at(20);
+    assert_eq!(ip[9], 17); // UDP
+    let (udp, payload) = buf.split_at(8);
+    #[expect(
+        clippy::big_endian_bytes,
+        reason = "packet headers are encoded in network byte order"
+    )]
+    let (src_be, dst_be) = (port.to_be_bytes(), 1777u16.to_be_bytes());
+    assert_eq!(udp[..2], src_be); // Source
+    assert_eq!(udp[2..4], dst_be); // Dest
+    assert_eq!(payload, b"hello AF_XDP");
+
+    // Removes socket from map, no more packets will be redirected.
+    socks.unset(0).unwrap();
+    sock.send_to(b"hello AF_XDP", "127.0.0.1:1777").unwrap();
+    assert_eq!(xsk.rx.consume(&mut descs), 0);
+    // Adds socket to map again, packets will be redirected again.
+    socks.set_socket(&xsk.socket, 0).unwrap();
+    sock.send_to(b"hello AF_XDP", "127.0.0.1:1777").unwrap();
+    assert_eq!(xsk.rx.consume(&mut descs), 1);
+
+    assert_eq!(xsk.socket.statistics().unwrap().rx_dropped, 0);
+}
+
+/// A broadcast Ethernet frame with a local experimental EtherType.
+fn xsk_frame() -> [u8; 60] {
+    let mut frame = [0u8; 60];
+    frame[..6].fill(0xff); // Destination
+    frame[6..12].copy_from_slice(&[0x02, 0, 0, 0, 0, 1]); // Source
+    frame[12..14].copy_from_slice(&[0x88, 0xb5]);
+    frame
+}
+
+/// Sets up a veth pair whose `veth1` end redirects the packets it receives to the `AF_XDP`
+/// socket in `SOCKS`, and returns the loaded object, the map and a UMEM of 4 frames.
+///
+/// Returns `None` if `AF_XDP` sockets aren't available.
+fn xsk_veth_pair() -> Option<(Ebpf, XskMap<aya::maps::MapData>, XskUmem)> {
+    let (veth0, veth1) = add_veth_pair(c"veth0", c"veth1").unwrap();
+
+    let mut bpf = Ebpf::load(crate::XSK_MAP).unwrap();
+    let socks: XskMap<_> = bpf.take_map("SOCKS").unwrap().try_into().unwrap();
+    let xdp: &mut Xdp = bpf
+        .program_mut("redirect_sock")
+        .unwrap()
+        .try_into()
+        .unwrap();
+    xdp.load().unwrap();
+    xdp.attach("veth1", XdpMode::Skb).unwrap();
+
+    unsafe {
+        netlink_set_link_up(veth0 as i32).unwrap();
+        netlink_set_link_up(veth1 as i32).unwrap();
+    }
+
+    let umem = match XskUmem::new(XskUmemConfig {
+        frame_count: 4,
+        ..Default::default()
+    }) {
+        Ok(umem) => umem,
+        Err(XskError::SyscallError(err))
+            if err.io_error.raw_os_error() == Some(libc::EAFNOSUPPORT) =>
+        {
+            eprintln!("skipping test - AF_XDP sockets not available: {err}");
+            return None;
+        }
+        Err(err) => panic!("failed to create UMEM: {err}"),
+    };
+    Some((bpf, socks, umem))
+}
+
+/// Transmits [`xsk_frame`] from the frame at `addr` on `tx`, and waits for the frame to be
+/// returned by `completion`.
+fn xsk_transmit(umem: &mut XskUmem, tx: &mut Xsk, completion: &mut CompletionQueue, addr: u64) {
+    let frame = xsk_frame();
+    // SAFETY: the frame isn't owned by the kernel until it's given to the TX queue.
+    unsafe { umem.frame_mut(addr, frame.len()) }
+        .unwrap()
+        .copy_from_slice(&frame);
+    let desc = XdpDesc {
+        addr,
+        len: frame.len() as u32,
+        options: 0,
+    };
+    assert_eq!(tx.tx.produce(&[desc]), 1);
+    // Copy mode transmits from `sendto`, so the TX queue always needs a wakeup.
+    assert!(tx.tx.needs_wakeup());
+    tx.socket.wakeup().unwrap();
+
+    let mut completed = [0u64; 1];
+    for _ in 0..100 {
+        if completion.consume(&mut completed) == 1 {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(completed, [addr]);
+}
+
+/// Waits for a packet on `rx` and returns its descriptor.
+fn xsk_receive(umem: &XskUmem, rx: &mut Xsk) -> XdpDesc {
+    let mut descs = [XdpDesc::default(); 1];
+    for _ in 0..100 {
+        if rx.rx.consume(&mut descs) == 1 {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    let [desc] = descs;
+    // SAFETY: the frame was returned by the RX queue and isn't given back to the kernel.
+    assert_eq!(unsafe { umem.data(&desc) }, Some(xsk_frame().as_slice()));
+    desc
+}
+
+#[test_log::test]
+fn af_xdp_veth_round_trip() {
+    let kernel_version = KernelVersion::current().unwrap();
+    if kernel_version < KernelVersion::new(5, 4, 0) {
+        eprintln!("skipping test on kernel {kernel_version:?}, need_wakeup was added in 5.4");
+        return;
+    }
+
+    let _netns = NetNsGuard::new().unwrap();
+    let Some((_bpf, mut socks, mut umem)) = xsk_veth_pair() else {
+        return;
+    };
+    let rx_umem = XskUmem::new(XskUmemConfig {
+        frame_count: 2,
+        ..Default::default()
+    })
+    .unwrap();
+    let config = XskConfig {
+        rx_size: 32,
+        tx_size: 32,
+        mode: XskBindMode::Copy,
+        need_wakeup: true,
+    };
+    let mut tx = XskSocket::bind(&umem, "veth0", 0, &config).unwrap();
+    let mut rx = XskSocket::bind(&rx_umem, "veth1", 0, &config).unwrap();
+    socks.set_socket(&rx.socket, 0).unwrap();
+
+    let rx_frames: Vec<u64> = rx_umem.frames().collect();
+    assert_eq!(rx.fill.as_mut().unwrap().produce(&rx_frames), 2);
+
+    // Packets sent on veth0 are received on veth1 and redirected to the RX socket.
+    let mut completion = tx.completion.take().unwrap();
+    xsk_transmit(&mut umem, &mut tx, &mut completion, 0);
+    let desc = xsk_receive(&rx_umem, &mut rx);
+    assert!(rx_frames.contains(&rx_umem.frame_addr(desc.addr)));
+
+    // The completed frame can be reused.
+    xsk_transmit(&mut umem, &mut tx, &mut completion, 0);
+    xsk_receive(&rx_umem, &mut rx);
+
+    assert_eq!(tx.socket.statistics().unwrap().tx_invalid_descs, 0);
+    assert_eq!(rx.socket.statistics().unwrap().rx_dropped, 0);
+}
+
+#[test_log::test]
+fn af_xdp_shared_umem() {
+    let kernel_version = KernelVersion::current().unwrap();
+    if kernel_version < KernelVersion::new(5, 10, 0) {
+        eprintln!(
+            "skipping test on kernel {kernel_version:?}, sharing a UMEM between queues was added in 5.10"
+        );
+        return;
+    }
+
+    let _netns = NetNsGuard::new().unwrap();
+    let Some((_bpf, mut socks, mut umem)) = xsk_veth_pair() else {
+        return;
+    };
+    let config = XskConfig {
+        rx_size: 32,
+        tx_size: 32,
+        mode: XskBindMode::Copy,
+        need_wakeup: true,
+    };
+    // The first two sockets share the fill and completion rings of the veth1 queue.
+    let mut first = XskSocket::bind(&umem, "veth1", 0, &config).unwrap();
+    let mut second = XskSocket::bind(&umem, "veth1", 0, &config).unwrap();
+    assert!(second.fill.is_none());
+    assert!(second.completion.is_none());
+    // The third socket gets its own rings for the veth0 queue.
+    let mut tx = XskSocket::bind(&umem, "veth0", 0, &config).unwrap();
+    let mut completion = tx.completion.take().unwrap();
+
+    let frames: Vec<u64> = umem.frames().collect();
+    let (rx_frames, tx_frames) = frames.split_at(2);
+    assert_eq!(first.fill.as_mut().unwrap().produce(rx_frames), 2);
+
+    socks.set_socket(&first.socket, 0).unwrap();
+    xsk_transmit(&mut umem, &mut tx, &mut completion, tx_frames[0]);
+    let desc = xsk_receive(&umem, &mut first);
+    assert!(rx_frames.contains(&umem.frame_addr(desc.addr)));
+
+    // The second socket receives in the frames given to the shared fill ring.
+    socks.set_socket(&second.socket, 0).unwrap();
+    xsk_transmit(&mut umem, &mut tx, &mut completion, tx_frames[1]);
+    let second_desc = xsk_receive(&umem, &mut second);
+    assert!(rx_frames.contains(&umem.frame_addr(second_desc.addr)));
+    assert_ne!(
+        umem.frame_addr(desc.addr),
+        umem.frame_addr(second_desc.addr)
+    );
+
+    // A socket bound with another configuration can't share the UMEM.
+    assert_matches!(
+        XskSocket::bind(
+            &umem,
+            "veth1",
+            0,
+            &XskConfig {
+                need_wakeup: false,
+                ..config
+            }
+        ),
+        Err(XskError::InvalidConfig {
+            name: "need_wakeup",
+            ..
+        })
+    );
+}
+
+#[test_log::test]
+fn attach_in_netns() {
+    let outer = NetNsGuard::new().unwrap();
+    let netns = NetNs::from_fd(outer.as_fd().try_clone_to_owned().unwrap()).unwrap();
+    let _inner = NetNsGuard::new().unwrap();
+
+    let mut bpf = Ebpf::load(crate::PASS).unwrap();
+    let xdp: &mut Xdp = bpf.program_mut("pass").unwrap().try_into().unwrap();
+    xdp.load().unwrap();
+
+    // The loopback interface of the outer namespace.
+    xdp.attach_in_netns("lo", XdpMode::Skb, &netns).unwrap();
+    // Only one program can be attached to an interface in a given mode.
+    assert_matches!(xdp.attach_in_netns("lo", XdpMode::Skb, &netns), Err(_));
+    // The loopback interface of the inner namespace is unaffected.
+    xdp.attach("lo", XdpMode::Skb).unwrap();
+
+    assert_matches!(
+        xdp.attach_in_netns("nonexistent", XdpMode::Skb, &netns),
+        Err(ProgramError::UnknownInterface { .. })
+    );
+}
+
+#[test_log::test]
+fn offload_unsupported() {
+    let _netns = NetNsGuard::new().unwrap();
+
+    // The loopback interface, index 1 in a new namespace, doesn't support offload.
+    assert_matches!(is_offload_supported(ProgramType::Xdp, 1), Ok(false));
+    // Only XDP and TC classifier programs can be offloaded.
+    assert_matches!(
+        is_offload_supported(ProgramType::SocketFilter, 1),
+        Ok(false)
+    );
+
+    let mut bpf = Ebpf::load(crate::PASS).unwrap();
+    let xdp: &mut Xdp = bpf.program_mut("pass").unwrap().try_into().unwrap();
+    assert_matches!(
+        xdp.load_offloaded("lo"),
+        Err(ProgramError::LoadError { .. })
+    );
+    assert_matches!(
+        xdp.load_offloaded("nonexistent"),
+        Err(ProgramError::UnknownInterface { .. })
+    );
+}
+
+#[test_log::test]
+fn metadata_kfuncs() {
+    // The program uses all the metadata kfuncs, so it can't be loaded before the last one was
+    // added.
+    let kernel_version = KernelVersion::current().unwrap();
+    if kernel_version < KernelVersion::new(6, 8, 0) {
+        eprintln!(
+            "skipping test on kernel {kernel_version:?}, bpf_xdp_metadata_rx_vlan_tag was added in 6.8"
+        );
+        return;
+    }
+
+    let _netns = NetNsGuard::new().unwrap();
+    let (veth0, veth1) = add_veth_pair(c"veth0", c"veth1").unwrap();
+
+    let mut bpf = Ebpf::load(crate::XDP_METADATA).unwrap();
+    let results: Array<_, i32> = bpf.take_map("RESULTS").unwrap().try_into().unwrap();
+    let xdp: &mut Xdp = bpf.program_mut("xdp_metadata").unwrap().try_into().unwrap();
+    assert_matches!(
+        xdp.load_dev_bound("nonexistent"),
+        Err(ProgramError::UnknownInterface { .. })
+    );
+    xdp.load_dev_bound("veth0").unwrap();
+    // Device-bound programs can only be attached to their device.
+    assert_matches!(xdp.attach("lo", XdpMode::Skb), Err(_));
+    xdp.attach("veth0", XdpMode::Driver).unwrap();
+
+    unsafe {
+        netlink_set_link_up(veth0 as i32).unwrap();
+        netlink_set_link_up(veth1 as i32).unwrap();
+    }
+
+    // Send a broadcast frame from the peer, received by veth0.
+    let sock = unsafe { libc::socket(libc::AF_PACKET, libc::SOCK_RAW, 0) };
+    assert!(sock >= 0, "socket: {}", std::io::Error::last_os_error());
+    let sock = unsafe { OwnedFd::from_raw_fd(sock) };
+    let mut addr = unsafe { mem::zeroed::<libc::sockaddr_ll>() };
+    addr.sll_family = libc::AF_PACKET as u16;
+    addr.sll_ifindex = veth1 as i32;
+    addr.sll_halen = 6;
+    addr.sll_addr[..6].fill(0xff);
+    let mut frame = [0u8; 60];
+    frame[..6].fill(0xff); // Destination
+    frame[6..12].copy_from_slice(&[0x02, 0, 0, 0, 0, 1]); // Source
+    frame[12..14].copy_from_slice(&[0x88, 0xb5]); // Local experimental EtherType
+    let sent = unsafe {
+        libc::sendto(
+            sock.as_raw_fd(),
+            frame.as_ptr().cast(),
+            frame.len(),
+            0,
+            (&raw const addr).cast(),
+            size_of::<libc::sockaddr_ll>() as libc::socklen_t,
+        )
+    };
+    assert_eq!(
+        sent,
+        frame.len() as isize,
+        "sendto: {}",
+        std::io::Error::last_os_error()
+    );
+
+    // veth runs XDP programs from its NAPI poll, after `sendto` returns.
+    for _ in 0..100 {
+        if results.get(&PACKETS_INDEX, 0).unwrap() > 0 {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_ne!(results.get(&PACKETS_INDEX, 0).unwrap(), 0);
+
+    // veth reports the metadata of the skb the packet was sent with: no hardware timestamp, a
+    // software hash and no VLAN tag.
+    assert_eq!(results.get(&TIMESTAMP_INDEX, 0).unwrap(), 0);
+    assert_eq!(results.get(&HASH_INDEX, 0).unwrap(), 0);
+    assert_eq!(results.get(&VLAN_TAG_INDEX, 0).unwrap(), -libc::ENODATA);
+}
+
 #[test_log::test]
 fn prog_sections() {
     let obj_file = object::File::parse(crate::XDP_SEC).unwrap();